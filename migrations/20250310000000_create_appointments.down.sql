@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS appointments;
+
+ALTER TABLE messages
+DROP COLUMN IF EXISTS message_type;
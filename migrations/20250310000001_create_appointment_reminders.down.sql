@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_appointments_status_starts_at;
+DROP TABLE IF EXISTS appointment_reminders;
@@ -0,0 +1,12 @@
+-- One row per (appointment, lead time) so every reminder is sent at most once,
+-- even when the worker restarts between scans
+CREATE TABLE appointment_reminders (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    appointment_id UUID NOT NULL REFERENCES appointments(id) ON DELETE CASCADE,
+    lead_time_minutes INTEGER NOT NULL,
+    channel VARCHAR(10),
+    sent_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (appointment_id, lead_time_minutes)
+);
+
+CREATE INDEX idx_appointments_status_starts_at ON appointments(status, starts_at);
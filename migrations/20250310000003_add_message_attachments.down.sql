@@ -0,0 +1,2 @@
+ALTER TABLE messages DROP COLUMN IF EXISTS attachment_image_id;
+ALTER TABLE images DROP COLUMN IF EXISTS size_bytes;
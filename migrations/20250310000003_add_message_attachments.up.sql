@@ -0,0 +1,5 @@
+-- Record upload sizes so storage can be totalled per conversation
+ALTER TABLE images ADD COLUMN size_bytes BIGINT;
+
+-- Messages can carry one uploaded image as an attachment
+ALTER TABLE messages ADD COLUMN attachment_image_id UUID REFERENCES images(id) ON DELETE SET NULL;
@@ -0,0 +1,14 @@
+-- Security-relevant events for support and compliance. User ids are not foreign
+-- keys so entries outlive the accounts they describe.
+CREATE TABLE audit_log (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    action VARCHAR(64) NOT NULL,
+    actor_id UUID,
+    user_id UUID,
+    details JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_audit_log_created_at ON audit_log(created_at);
+CREATE INDEX idx_audit_log_action_created_at ON audit_log(action, created_at);
+CREATE INDEX idx_audit_log_user_id_created_at ON audit_log(user_id, created_at);
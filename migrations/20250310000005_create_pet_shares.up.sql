@@ -0,0 +1,15 @@
+-- Lets a pet's owner give other accounts (e.g. a spouse) access to the pet and
+-- its conversations. Shares start pending until the invitee accepts.
+CREATE TABLE pet_shares (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    pet_id UUID NOT NULL REFERENCES pets(id) ON DELETE CASCADE,
+    owner_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    shared_with_user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    permissions VARCHAR(10) NOT NULL CHECK (permissions IN ('read', 'read_write')),
+    status VARCHAR(10) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'accepted')),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    accepted_at TIMESTAMP WITH TIME ZONE,
+    UNIQUE (pet_id, shared_with_user_id)
+);
+
+CREATE INDEX idx_pet_shares_shared_with_user_id ON pet_shares(shared_with_user_id);
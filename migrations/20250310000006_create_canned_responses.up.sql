@@ -0,0 +1,17 @@
+-- Reusable message templates (after-hours, post-op instructions, ...) owned by a provider
+CREATE TABLE canned_responses (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    provider_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    title VARCHAR(100) NOT NULL,
+    body TEXT NOT NULL,
+    category VARCHAR(50),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TRIGGER update_canned_responses_updated_at
+    BEFORE UPDATE ON canned_responses
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
+CREATE INDEX idx_canned_responses_provider_id ON canned_responses(provider_id);
@@ -0,0 +1,3 @@
+-- The provider who owns the case in a multi-provider conversation
+ALTER TABLE conversations
+ADD COLUMN assigned_provider UUID REFERENCES users(id) ON DELETE SET NULL;
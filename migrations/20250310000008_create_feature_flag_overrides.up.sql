@@ -0,0 +1,9 @@
+-- Per-user exceptions to the environment's feature flag settings, e.g. to let a
+-- pilot cohort try a feature before it's switched on for everyone
+CREATE TABLE feature_flag_overrides (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    flag VARCHAR(50) NOT NULL,
+    enabled BOOLEAN NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, flag)
+);
@@ -0,0 +1,13 @@
+-- Direct notifications (conversation invitations, appointment proposals) for users
+-- who had no open WebSocket session, delivered the next time they connect
+CREATE TABLE pending_events (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    event VARCHAR(50) NOT NULL,
+    params JSONB NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    delivered_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX idx_pending_events_undelivered ON pending_events (user_id, created_at)
+    WHERE delivered_at IS NULL;
@@ -0,0 +1,17 @@
+-- Images accepted while storage was unavailable. The worker retries the upload and,
+-- once it succeeds, moves the row into images under the same id.
+CREATE TABLE pending_uploads (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    filename TEXT,
+    content_type TEXT,
+    image_type TEXT NOT NULL,
+    object_name TEXT NOT NULL,
+    data BYTEA NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_pending_uploads_next_attempt_at ON pending_uploads (next_attempt_at);
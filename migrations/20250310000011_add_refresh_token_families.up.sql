@@ -0,0 +1,5 @@
+-- A family is one device's login. Every refresh token issued for that login shares
+-- its family_id, so the device can be signed out as a whole.
+ALTER TABLE refresh_tokens ADD COLUMN family_id UUID NOT NULL DEFAULT gen_random_uuid();
+
+CREATE INDEX idx_refresh_tokens_family_id ON refresh_tokens(user_id, family_id);
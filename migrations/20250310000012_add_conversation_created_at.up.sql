@@ -0,0 +1,12 @@
+ALTER TABLE conversations ADD COLUMN created_at TIMESTAMP WITH TIME ZONE;
+
+-- Existing conversations started no later than their first message
+UPDATE conversations c
+SET created_at = COALESCE(
+    (SELECT MIN(m.timestamp) FROM messages m WHERE m.conversation_id = c.id),
+    c.last_updated_timestamp
+);
+
+ALTER TABLE conversations
+    ALTER COLUMN created_at SET DEFAULT CURRENT_TIMESTAMP,
+    ALTER COLUMN created_at SET NOT NULL;
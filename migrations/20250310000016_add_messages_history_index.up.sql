@@ -0,0 +1,2 @@
+-- Serves history pages newest first, including `before` cursor pages
+CREATE INDEX idx_messages_conversation_history ON messages(conversation_id, timestamp DESC, id DESC);
@@ -0,0 +1,8 @@
+-- Kept up to date as messages are sent and deleted so history requests don't
+-- count every message; ConversationService::reconcile_message_counts repairs drift.
+ALTER TABLE conversations ADD COLUMN message_count BIGINT NOT NULL DEFAULT 0;
+
+UPDATE conversations c
+SET message_count = counts.message_count
+FROM (SELECT conversation_id, COUNT(*) AS message_count FROM messages GROUP BY conversation_id) counts
+WHERE counts.conversation_id = c.id;
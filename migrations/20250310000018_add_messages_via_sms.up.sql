@@ -0,0 +1,2 @@
+-- Set on messages that arrived as SMS replies through the Twilio webhook
+ALTER TABLE messages ADD COLUMN via_sms BOOLEAN NOT NULL DEFAULT FALSE;
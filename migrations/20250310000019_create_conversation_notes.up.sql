@@ -0,0 +1,9 @@
+-- One shared, provider-edited note per conversation (e.g. a treatment plan).
+-- `version` starts at 1 and is bumped on every edit for optimistic locking.
+CREATE TABLE conversation_notes (
+    conversation_id UUID PRIMARY KEY REFERENCES conversations(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    updated_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    version INTEGER NOT NULL DEFAULT 1,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
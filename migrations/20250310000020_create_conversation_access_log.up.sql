@@ -0,0 +1,16 @@
+-- Who read which part of a conversation's history, for compliance. Written in
+-- the background after each history fetch and pruned after the retention period.
+-- Like the audit log, accessors aren't foreign keys so entries outlive accounts.
+CREATE TABLE conversation_access_log (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    conversation_id UUID NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+    accessor_id UUID NOT NULL,
+    source VARCHAR(16) NOT NULL, -- 'rest' or 'websocket'
+    message_count INTEGER NOT NULL,
+    oldest_message_at TIMESTAMP WITH TIME ZONE,
+    newest_message_at TIMESTAMP WITH TIME ZONE,
+    accessed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_conversation_access_log_conversation_id_accessed_at ON conversation_access_log(conversation_id, accessed_at);
+CREATE INDEX idx_conversation_access_log_accessed_at ON conversation_access_log(accessed_at);
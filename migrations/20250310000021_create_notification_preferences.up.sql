@@ -0,0 +1,10 @@
+-- Whether and how a user is notified outside the app. The row with a null
+-- conversation_id is their default; a conversation's own row overrides it.
+CREATE TABLE notification_preferences (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    conversation_id UUID REFERENCES conversations(id) ON DELETE CASCADE,
+    muted BOOLEAN NOT NULL DEFAULT FALSE,
+    level VARCHAR(16) NOT NULL DEFAULT 'all', -- 'all' or 'push_only'
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE NULLS NOT DISTINCT (user_id, conversation_id)
+);
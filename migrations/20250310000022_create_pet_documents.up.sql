@@ -0,0 +1,15 @@
+-- Papers kept for a pet, such as insurance policies and adoption records. The
+-- file itself is an upload in images.
+CREATE TABLE pet_documents (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    pet_id UUID NOT NULL REFERENCES pets(id) ON DELETE CASCADE,
+    image_id UUID NOT NULL REFERENCES images(id) ON DELETE CASCADE,
+    uploaded_by UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    category VARCHAR(32) NOT NULL, -- 'insurance', 'adoption', 'vaccination', 'medical' or 'other'
+    title VARCHAR(200) NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_pet_documents_pet_id ON pet_documents(pet_id, created_at DESC);
+CREATE INDEX idx_pet_documents_expires_at ON pet_documents(expires_at) WHERE expires_at IS NOT NULL;
@@ -0,0 +1,25 @@
+-- Postgres can't inflate the cold content itself, so this only reverts once
+-- no messages are cold.
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM messages_cold) THEN
+        RAISE EXCEPTION 'messages_cold still holds message content';
+    END IF;
+END
+$$;
+
+DROP TRIGGER rewarm_edited_message ON messages;
+DROP FUNCTION rewarm_edited_message();
+
+CREATE OR REPLACE FUNCTION update_messages_updated_at_column()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'UPDATE' THEN
+        NEW.updated_at = CURRENT_TIMESTAMP;
+    END IF;
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+ALTER TABLE messages ALTER COLUMN content SET NOT NULL;
+DROP TABLE messages_cold;
@@ -0,0 +1,36 @@
+-- Old messages' content, compressed. A message moved here has a null
+-- content column; editing it writes the content back and drops its cold row.
+CREATE TABLE messages_cold (
+    message_id UUID PRIMARY KEY REFERENCES messages(id) ON DELETE CASCADE,
+    content_compressed BYTEA NOT NULL,
+    content_bytes INTEGER NOT NULL, -- uncompressed size, for conversation summaries
+    compressed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+ALTER TABLE messages ALTER COLUMN content DROP NOT NULL;
+
+-- Moving content out to cold storage isn't a change to the message
+CREATE OR REPLACE FUNCTION update_messages_updated_at_column()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'UPDATE' AND NOT (OLD.content IS NOT NULL AND NEW.content IS NULL) THEN
+        NEW.updated_at = CURRENT_TIMESTAMP;
+    END IF;
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+CREATE OR REPLACE FUNCTION rewarm_edited_message()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.content IS NOT NULL THEN
+        DELETE FROM messages_cold WHERE message_id = NEW.id;
+    END IF;
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+CREATE TRIGGER rewarm_edited_message
+    AFTER UPDATE OF content ON messages
+    FOR EACH ROW
+    EXECUTE FUNCTION rewarm_edited_message();
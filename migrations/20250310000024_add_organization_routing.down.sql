@@ -0,0 +1,2 @@
+ALTER TABLE organization_members DROP COLUMN IF EXISTS last_routed_at;
+ALTER TABLE organizations DROP COLUMN IF EXISTS routing_mode;
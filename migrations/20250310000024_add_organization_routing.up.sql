@@ -0,0 +1,7 @@
+-- How conversations started with an organization but no named providers are
+-- routed: to every member ('broadcast'), or to one member at a time, picked by
+-- load ('round_robin').
+ALTER TABLE organizations ADD COLUMN routing_mode VARCHAR(20) NOT NULL DEFAULT 'broadcast';
+
+-- When each member was last picked, so members with the same load take turns
+ALTER TABLE organization_members ADD COLUMN last_routed_at TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,11 @@
+CREATE OR REPLACE FUNCTION update_messages_updated_at_column()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'UPDATE' AND NOT (OLD.content IS NOT NULL AND NEW.content IS NULL) THEN
+        NEW.updated_at = CURRENT_TIMESTAMP;
+    END IF;
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+ALTER TABLE messages DROP COLUMN edited_at;
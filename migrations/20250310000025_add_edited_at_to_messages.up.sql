@@ -0,0 +1,22 @@
+-- When the message's content was last changed by an edit; null if never
+ALTER TABLE messages ADD COLUMN edited_at TIMESTAMP WITH TIME ZONE;
+
+-- A new message was last updated when it was sent, so backfilled history
+-- keeps its own times. Afterwards only real changes move updated_at: not
+-- no-op updates, and not moving content out to cold storage.
+CREATE OR REPLACE FUNCTION update_messages_updated_at_column()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        NEW.updated_at = NEW.timestamp;
+    ELSIF OLD.content IS NOT NULL AND NEW.content IS NULL THEN
+        NEW.updated_at = OLD.updated_at;
+    ELSIF NEW IS DISTINCT FROM OLD THEN
+        NEW.updated_at = CURRENT_TIMESTAMP;
+        IF NEW.content IS DISTINCT FROM OLD.content THEN
+            NEW.edited_at = CURRENT_TIMESTAMP;
+        END IF;
+    END IF;
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
@@ -0,0 +1,3 @@
+-- Walks a conversation's attachments newest first without scanning its text messages
+CREATE INDEX idx_messages_conversation_attachments ON messages(conversation_id, timestamp DESC, id DESC)
+    WHERE attachment_image_id IS NOT NULL;
@@ -0,0 +1,3 @@
+-- Hashed tokens can't be turned back into plaintext; those devices sign in again
+DELETE FROM refresh_tokens WHERE hashed;
+ALTER TABLE refresh_tokens DROP COLUMN hashed;
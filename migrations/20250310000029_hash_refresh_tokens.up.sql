@@ -0,0 +1,5 @@
+-- Refresh tokens are stored as a peppered SHA-256 hash rather than in plaintext.
+-- The pepper is only known to the server, so rows written before this stay in
+-- plaintext with hashed = false until the server hashes them at startup or on
+-- their next use.
+ALTER TABLE refresh_tokens ADD COLUMN hashed BOOLEAN NOT NULL DEFAULT FALSE;
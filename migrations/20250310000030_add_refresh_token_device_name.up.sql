@@ -0,0 +1,2 @@
+-- A name the user's app gives the device at login, e.g. "Sam's iPhone"
+ALTER TABLE refresh_tokens ADD COLUMN device_name TEXT;
@@ -0,0 +1,11 @@
+-- Signed requests already accepted, by a hash of their signature, so the same
+-- payload can't be replayed while its timestamp is still fresh. Rows are only
+-- needed until the timestamp would be refused anyway.
+CREATE TABLE signed_request_nonces (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    nonce VARCHAR(64) NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (user_id, nonce)
+);
+
+CREATE INDEX idx_signed_request_nonces_expires_at ON signed_request_nonces (expires_at);
@@ -0,0 +1,2 @@
+-- Set when an admin suspends the account; suspended users can't log in.
+ALTER TABLE users ADD COLUMN suspended_at TIMESTAMPTZ;
@@ -0,0 +1,4 @@
+-- Set when someone registers asking to be a provider. They stay a client until
+-- an admin approves the request.
+ALTER TABLE users ADD COLUMN provider_status VARCHAR(20)
+    CHECK (provider_status IN ('pending', 'approved', 'rejected'));
@@ -0,0 +1,7 @@
+ALTER TABLE users DROP CONSTRAINT check_valid_phone;
+
+UPDATE users SET phone_number = substr(phone_number, 3)
+WHERE phone_number ~ '^\+1[0-9]{10}$';
+
+ALTER TABLE users ADD CONSTRAINT check_valid_phone
+CHECK (phone_number ~ '^\+?[0-9]{10,14}$') NOT VALID;
@@ -0,0 +1,10 @@
+-- Phone numbers were stored as 10 US digits; they're now E.164 (+15551234567).
+-- Test numbers (000123...) stay bare digits.
+ALTER TABLE users DROP CONSTRAINT check_valid_phone;
+
+UPDATE users SET phone_number = '+1' || phone_number
+WHERE phone_number ~ '^[0-9]{10}$' AND phone_number NOT LIKE '000123%';
+
+-- Not validated, so any stray legacy formats don't block the migration
+ALTER TABLE users ADD CONSTRAINT check_valid_phone
+CHECK (phone_number ~ '^\+[1-9][0-9]{7,14}$' OR phone_number ~ '^000123[0-9]{4,9}$') NOT VALID;
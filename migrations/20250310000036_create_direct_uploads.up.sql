@@ -0,0 +1,14 @@
+-- Images a client was given a signed URL to upload straight to storage. The row
+-- moves into images under the same id once the client confirms the upload.
+CREATE TABLE direct_uploads (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    filename TEXT,
+    content_type TEXT NOT NULL,
+    image_type TEXT NOT NULL,
+    object_name TEXT NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_direct_uploads_user_id ON direct_uploads(user_id);
@@ -0,0 +1,2 @@
+-- Snoozes notifications until a time, after which the preference is unmuted again
+ALTER TABLE notification_preferences ADD COLUMN muted_until TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,3 @@
+-- When the sender retracted the message; it stays in the history, blanked, so
+-- counts and pagination don't shift
+ALTER TABLE messages ADD COLUMN deleted_at TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,172 @@
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest};
+use std::fmt;
+use std::future::{ready, Ready};
+use uuid::Uuid;
+
+use crate::errors::ApiError;
+use crate::utils::{verify_and_decode_token, Claims, KeyRing, TokenError};
+
+/// The caller of an endpoint that needs a signed-in user. Taking it as a
+/// handler parameter refuses requests without a valid bearer token with a 401
+/// before the handler runs. Requests authenticated with an API key get the
+/// `service` scope claims the API key middleware left for them.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub user_id: Uuid,
+    pub scope: String,
+    /// The rest of the token, for the few handlers that need its family or id.
+    pub claims: Claims,
+}
+
+/// Why a request couldn't be authenticated.
+#[derive(Debug)]
+pub enum AuthError {
+    MissingHeader,
+    MalformedHeader,
+    Token(TokenError),
+    InvalidUserId,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::MissingHeader => write!(f, "Missing Authorization header"),
+            AuthError::MalformedHeader => write!(f, "Authorization header must be 'Bearer <token>'"),
+            AuthError::Token(e) => write!(f, "{}", e),
+            AuthError::InvalidUserId => write!(f, "Invalid user ID in token"),
+        }
+    }
+}
+
+impl From<AuthError> for ApiError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            // Our own misconfiguration, not the caller's fault
+            AuthError::Token(TokenError::Keys(message)) => ApiError::Internal(message),
+            _ => ApiError::Unauthorized(e.to_string()),
+        }
+    }
+}
+
+impl AuthenticatedUser {
+    /// The user a request's `Authorization: Bearer` token, or API key, was issued to.
+    pub fn authenticate(req: &HttpRequest) -> Result<Self, AuthError> {
+        // Set by the API key middleware for service accounts
+        if let Some(claims) = req.extensions().get::<Claims>() {
+            return Self::from_claims(claims.clone());
+        }
+
+        let header = req.headers().get("Authorization").ok_or(AuthError::MissingHeader)?;
+        let token = header.to_str().ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .ok_or(AuthError::MalformedHeader)?;
+        Self::from_token(req, token)
+    }
+
+    /// The user an access token was issued to, for tokens that don't come in a
+    /// header. The request is only used for the app's `KeyRing`.
+    pub fn from_token(req: &HttpRequest, token: &str) -> Result<Self, AuthError> {
+        let keys = req.app_data::<web::Data<KeyRing>>()
+            .ok_or_else(|| AuthError::Token(TokenError::Keys("Token keys are not configured".to_string())))?;
+        Self::from_claims(verify_and_decode_token(keys, token).map_err(AuthError::Token)?)
+    }
+
+    fn from_claims(claims: Claims) -> Result<Self, AuthError> {
+        let user_id = Uuid::parse_str(claims.get_sub()).map_err(|_| AuthError::InvalidUserId)?;
+        Ok(AuthenticatedUser { user_id, scope: claims.get_scope().to_string(), claims })
+    }
+
+    /// The user's id if they are an admin, otherwise a 403.
+    pub fn require_admin(&self) -> Result<Uuid, ApiError> {
+        self.require_scope("admin", "Admin access required")
+    }
+
+    /// The user's id if they are a provider, otherwise a 403.
+    pub fn require_provider(&self) -> Result<Uuid, ApiError> {
+        self.require_scope("provider", "Provider access required")
+    }
+
+    fn require_scope(&self, scope: &str, message: &str) -> Result<Uuid, ApiError> {
+        if self.scope != scope {
+            return Err(ApiError::Forbidden(message.to_string()));
+        }
+        Ok(self.user_id)
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, ApiError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Self::authenticate(req).map_err(ApiError::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{generate_signed_encrypted_token, test_keys};
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+    use actix_web::ResponseError;
+    use chrono::Utc;
+
+    fn authenticate(request: TestRequest) -> Result<AuthenticatedUser, ApiError> {
+        let (req, mut payload) = request.app_data(test_keys::key_ring()).to_http_parts();
+        futures::executor::block_on(AuthenticatedUser::from_request(&req, &mut payload))
+    }
+
+    fn refusal(request: TestRequest) -> (StatusCode, String) {
+        let error = authenticate(request).unwrap_err();
+        (error.status_code(), error.to_string())
+    }
+
+    fn bearer(token: &str) -> TestRequest {
+        TestRequest::default().insert_header(("Authorization", format!("Bearer {}", token)))
+    }
+
+    #[test]
+    fn bearer_tokens_name_the_user_and_scope() {
+        let user_id = Uuid::new_v4();
+        let (token, _) = generate_signed_encrypted_token(&test_keys::key_ring(), user_id, "provider", None).unwrap();
+
+        let user = authenticate(bearer(&token)).unwrap();
+        assert_eq!((user.user_id, user.scope.as_str()), (user_id, "provider"));
+        assert_eq!(user.require_provider().unwrap(), user_id);
+        assert_eq!(user.require_admin().unwrap_err().status_code(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn each_way_of_failing_gets_its_own_401() {
+        let mut claims = Claims::for_service_account(Uuid::new_v4());
+        claims.exp = (Utc::now().timestamp() - 3600) as usize;
+        let expired = test_keys::token_for(&claims);
+
+        let unauthorized = |message: &str| (StatusCode::UNAUTHORIZED, message.to_string());
+        assert_eq!(refusal(TestRequest::default()), unauthorized("Missing Authorization header"));
+        assert_eq!(
+            refusal(TestRequest::default().insert_header(("Authorization", "Token abc"))),
+            unauthorized("Authorization header must be 'Bearer <token>'")
+        );
+        assert_eq!(refusal(bearer(&expired)), unauthorized("Token has expired"));
+        assert_eq!(refusal(bearer("bm90IGEgdG9rZW4")), unauthorized("Token could not be decrypted; sign in again"));
+
+        claims.exp = (Utc::now().timestamp() + 3600) as usize;
+        claims.sub = "not-a-uuid".to_string();
+        assert_eq!(refusal(bearer(&test_keys::token_for(&claims))), unauthorized("Invalid user ID in token"));
+    }
+
+    #[test]
+    fn api_key_claims_are_used_without_a_header() {
+        let provider_id = Uuid::new_v4();
+        let (req, mut payload) = TestRequest::default().to_http_parts();
+        req.extensions_mut().insert(Claims::for_service_account(provider_id));
+
+        let user = futures::executor::block_on(AuthenticatedUser::from_request(&req, &mut payload)).unwrap();
+        assert_eq!((user.user_id, user.scope.as_str()), (provider_id, "service"));
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+#[derive(Default)]
+struct FailureState {
+    consecutive_failures: u32,
+    locked_until: Option<Instant>,
+}
+
+/// Counts consecutive wrong verification codes per user at login, shared by
+/// all workers. Once the count reaches the threshold the user can't log in,
+/// even with the right code, until the lockout expires, so codes can't be
+/// guessed while they are valid.
+pub struct CodeFailureTracker {
+    threshold: u32,
+    lockout: Duration,
+    users: Mutex<HashMap<Uuid, FailureState>>,
+}
+
+impl CodeFailureTracker {
+    pub fn new(threshold: u32, lockout: Duration) -> Self {
+        CodeFailureTracker {
+            threshold,
+            lockout,
+            users: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Time left on the user's lockout, if they are locked out at `now`.
+    pub fn lockout_remaining(&self, user_id: Uuid, now: Instant) -> Option<Duration> {
+        let mut users = self.users.lock().unwrap();
+        let locked_until = users.get(&user_id)?.locked_until?;
+        if locked_until > now {
+            return Some(locked_until - now);
+        }
+        // Expired: the user starts over with a clean count
+        users.remove(&user_id);
+        None
+    }
+
+    /// Records a wrong code. Returns the time left on the lockout if this
+    /// failure started one.
+    pub fn record_failure(&self, user_id: Uuid, now: Instant) -> Option<Duration> {
+        let mut users = self.users.lock().unwrap();
+        let state = users.entry(user_id).or_default();
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= self.threshold && state.locked_until.is_none() {
+            state.locked_until = Some(now + self.lockout);
+            return Some(self.lockout);
+        }
+        None
+    }
+
+    pub fn record_success(&self, user_id: Uuid) {
+        self.users.lock().unwrap().remove(&user_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locks_out_after_threshold_consecutive_failures_until_it_expires() {
+        let tracker = CodeFailureTracker::new(3, Duration::from_secs(60));
+        let user_id = Uuid::new_v4();
+        let now = Instant::now();
+
+        assert_eq!(tracker.record_failure(user_id, now), None);
+        assert_eq!(tracker.record_failure(user_id, now), None);
+        assert!(tracker.lockout_remaining(user_id, now).is_none());
+        assert_eq!(tracker.record_failure(user_id, now), Some(Duration::from_secs(60)));
+        assert_eq!(tracker.lockout_remaining(user_id, now + Duration::from_secs(20)), Some(Duration::from_secs(40)));
+        assert!(tracker.lockout_remaining(Uuid::new_v4(), now).is_none());
+
+        assert!(tracker.lockout_remaining(user_id, now + Duration::from_secs(60)).is_none());
+        assert_eq!(tracker.record_failure(user_id, now + Duration::from_secs(61)), None);
+    }
+
+    #[test]
+    fn success_resets_the_count() {
+        let tracker = CodeFailureTracker::new(3, Duration::from_secs(60));
+        let user_id = Uuid::new_v4();
+        let now = Instant::now();
+
+        tracker.record_failure(user_id, now);
+        tracker.record_failure(user_id, now);
+        tracker.record_success(user_id);
+        tracker.record_failure(user_id, now);
+        assert_eq!(tracker.record_failure(user_id, now), None);
+    }
+}
@@ -0,0 +1,100 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use crate::config::Config;
+
+/// Caps how many requests in a route group run at once, shared by all workers.
+pub struct ConcurrencyLimiter {
+    capacity: usize,
+    semaphore: Arc<Semaphore>,
+    rejected: AtomicU64,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(capacity: usize) -> Self {
+        ConcurrencyLimiter {
+            capacity,
+            semaphore: Arc::new(Semaphore::new(capacity)),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Waits up to `wait` for a permit, which is held until it is dropped.
+    /// `None` when the group stayed full the whole time.
+    pub async fn acquire(&self, wait: Duration) -> Option<OwnedSemaphorePermit> {
+        match actix_web::rt::time::timeout(wait, self.semaphore.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => Some(permit),
+            // The semaphore is never closed, so only the timeout gets here
+            _ => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                None
+            },
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn in_use(&self) -> usize {
+        self.capacity - self.semaphore.available_permits()
+    }
+
+    /// Requests turned away since startup.
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+/// A limiter for each group of heavy endpoints, so a burst on one can't
+/// starve the workers (or the database pool) for everything else.
+pub struct ConcurrencyLimits {
+    /// `POST /upload-image`, which buffers the file and waits on the scanner
+    /// and Cloud Storage.
+    pub uploads: ConcurrencyLimiter,
+    /// `GET /profiles`, whose full view joins every pet of every requested user.
+    pub profile_reads: ConcurrencyLimiter,
+    /// How long a request waits for a permit before being refused.
+    pub wait: Duration,
+}
+
+impl ConcurrencyLimits {
+    pub fn from_config(config: &Config) -> Self {
+        ConcurrencyLimits {
+            uploads: ConcurrencyLimiter::new(config.upload_concurrency),
+            profile_reads: ConcurrencyLimiter::new(config.profile_read_concurrency),
+            wait: Duration::from_millis(config.concurrency_wait_ms),
+        }
+    }
+
+    /// Each group by the name it is reported under in `/metrics`.
+    pub fn groups(&self) -> [(&'static str, &ConcurrencyLimiter); 2] {
+        [("uploads", &self.uploads), ("profile_reads", &self.profile_reads)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn permits_are_returned_when_dropped() {
+        let limiter = ConcurrencyLimiter::new(2);
+        let wait = Duration::from_millis(20);
+
+        let first = limiter.acquire(wait).await.unwrap();
+        let _second = limiter.acquire(wait).await.unwrap();
+        assert_eq!(limiter.in_use(), 2);
+        assert!(limiter.acquire(wait).await.is_none());
+        assert_eq!(limiter.rejected(), 1);
+
+        // A request already waiting gets the permit as soon as one is released
+        let (third, ()) = futures::join!(limiter.acquire(Duration::from_secs(5)), async move { drop(first) });
+        assert!(third.is_some());
+        assert_eq!(limiter.in_use(), 2);
+        drop(third);
+        assert_eq!(limiter.in_use(), 1);
+        assert_eq!(limiter.capacity(), 2);
+    }
+}
@@ -0,0 +1,337 @@
+use chrono::Duration;
+use std::collections::BTreeMap;
+use std::env;
+use std::str::FromStr;
+use crate::moderation::ModerationAction;
+
+/// Runtime settings read once from the environment at startup.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// How long before a confirmed appointment each reminder is sent.
+    pub reminder_lead_times: Vec<Duration>,
+    /// How often the worker scans for due reminders.
+    pub reminder_scan_interval_secs: u64,
+    /// How long a recorded Idempotency-Key response can be replayed.
+    pub idempotency_key_ttl: Duration,
+    /// How long a request holds its Idempotency-Key before a retry may take it
+    /// over, in case it never finishes (say the client hung up mid-request).
+    pub idempotency_lease: Duration,
+    /// Maximum number of recent events kept per conversation for reconnect replay.
+    pub ws_replay_buffer_events: usize,
+    /// How long a conversation event stays replayable after it was broadcast.
+    pub ws_replay_buffer_ttl: Duration,
+    /// Most events one reconnect replays per conversation; past this only the
+    /// newest are sent and the client is told to refetch the history.
+    pub ws_replay_max_events: usize,
+    /// Most broadcasts held for a user whose last session closed; oldest dropped first.
+    pub ws_disconnect_buffer_events: usize,
+    /// How long after their last session closes a user's broadcasts are held.
+    pub ws_disconnect_buffer_ttl: Duration,
+    /// Undelivered notifications kept per offline user; older ones are dropped.
+    pub pending_events_max_per_user: usize,
+    /// How long an undelivered notification waits for the user to connect.
+    pub pending_events_ttl: Duration,
+    /// Accept uploads while image storage is failing and retry them in the worker.
+    pub upload_fallback: bool,
+    /// Total bytes of images each user may store.
+    pub image_storage_quota_bytes: u64,
+    /// How many seconds clients may cache an image served from `/images/{id}/content`.
+    pub image_cache_max_age_secs: u64,
+    /// How many seconds a signed URL from `/upload-url` can be used for.
+    pub direct_upload_url_ttl_secs: u64,
+    /// Image uploads handled at once across all workers.
+    pub upload_concurrency: usize,
+    /// Full profile reads handled at once across all workers.
+    pub profile_read_concurrency: usize,
+    /// How long a request waits for its route group to have room before a 503.
+    pub concurrency_wait_ms: u64,
+    /// clamd address that uploads are scanned with; unset disables scanning.
+    pub clamav_address: Option<String>,
+    /// Requests per minute allowed for a new service account's API key.
+    pub service_account_rate_limit_per_minute: u32,
+    /// Serve `POST /check-phone`; off for deployments that mustn't reveal which
+    /// numbers are registered.
+    pub check_phone_enabled: bool,
+    /// Phone registration checks allowed per client IP per hour.
+    pub check_phone_limit_per_ip: u32,
+    /// Phone registration checks allowed per phone number per hour.
+    pub check_phone_limit_per_number: u32,
+    /// Return an existing conversation instead of creating an identical one.
+    pub dedupe_conversations: bool,
+    /// Public URL Twilio posts inbound SMS to. Signatures cover the URL, so set
+    /// this when a proxy changes the scheme or host; by default the URL the
+    /// request arrived on is used.
+    pub twilio_webhook_url: Option<String>,
+    /// Twilio Verify service that sends and checks verification codes.
+    pub twilio_verify_service_sid: Option<String>,
+    /// Twilio Messaging Service outbound texts are sent through.
+    pub twilio_messaging_service_sid: Option<String>,
+    /// Number or alphanumeric sender ID outbound texts come from; with a
+    /// Messaging Service, picks the sender within it.
+    pub twilio_from_number: Option<String>,
+    /// Conversations a client may start per hour; 0 disables the limit.
+    pub conversation_creation_limit_per_hour: u32,
+    /// Deepest offset (in messages) a history page may start at; older
+    /// messages have to be paged with a `before` cursor.
+    pub max_history_offset: i32,
+    /// How long conversation history reads stay in the access log.
+    pub access_log_retention: Duration,
+    /// Whether the worker compresses old messages' content into cold storage.
+    pub cold_storage_enabled: bool,
+    /// How old a message must be before its content is moved to cold storage.
+    pub cold_storage_after: Duration,
+    /// Messages moved to cold storage per run of the job.
+    pub cold_storage_batch_size: i64,
+    /// Consecutive signature failures before a user's signed requests are refused.
+    pub signature_failure_threshold: u32,
+    /// Signature failures from one client address, for any users, within a
+    /// lockout period before the address's signed requests are refused.
+    pub signature_failure_threshold_per_ip: u32,
+    /// How long a signature failure lockout lasts.
+    pub signature_lockout_secs: u64,
+    /// Text the user when their account is locked out.
+    pub signature_lockout_sms: bool,
+    /// Consecutive wrong verification codes before a user can't log in.
+    pub code_failure_threshold: u32,
+    /// How long a verification code lockout lasts.
+    pub code_lockout_secs: u64,
+    /// Secret mixed into refresh token hashes, so a copy of the database alone
+    /// can't be used to check guesses. Changing it signs everyone out.
+    pub refresh_token_pepper: Option<String>,
+    /// How long a refresh token lasts from login or its last use.
+    pub refresh_token_ttl: Duration,
+    /// How long expired refresh tokens are kept before the worker deletes them.
+    pub refresh_token_cleanup_grace: Duration,
+    /// Close WebSocket sessions that send no application messages for this many
+    /// seconds. Protocol-level pings don't count; 0 disables.
+    pub ws_idle_timeout_secs: u64,
+    /// Mark a session away after this many seconds without application
+    /// messages; 0 disables.
+    pub ws_away_after_secs: u64,
+    /// WebSocket messages a session may send per minute before it is sent a
+    /// `backpressure` event and further messages are dropped; 0 disables.
+    pub ws_message_limit_per_minute: u32,
+    /// Feature flag name -> whether it's on for this environment. Users can be
+    /// switched individually through `feature_flag_overrides`.
+    pub feature_flags: BTreeMap<String, bool>,
+    /// Whether messages that trip a moderation rule are rejected or redacted.
+    pub moderation_action: ModerationAction,
+    /// Regex of blocked message content, e.g. `(?i)\b(word1|word2)\b`.
+    pub moderation_blocklist: Option<String>,
+    /// Treat Luhn-valid payment card numbers in messages as a moderation match.
+    pub moderation_detect_card_numbers: bool,
+    /// Connect to the database, Cloud Storage, Twilio and check the JWT keys
+    /// right after binding instead of on first use.
+    pub warm_up: bool,
+    /// Dependencies (by readiness name) whose warm-up failure stops the server;
+    /// others are only reported as degraded.
+    pub warm_up_required: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            reminder_lead_times: vec![Duration::hours(24), Duration::hours(1)],
+            reminder_scan_interval_secs: 60,
+            idempotency_key_ttl: Duration::hours(24),
+            idempotency_lease: Duration::minutes(2),
+            ws_replay_buffer_events: 100,
+            ws_replay_buffer_ttl: Duration::minutes(5),
+            ws_replay_max_events: 50,
+            ws_disconnect_buffer_events: 50,
+            ws_disconnect_buffer_ttl: Duration::seconds(30),
+            pending_events_max_per_user: 100,
+            pending_events_ttl: Duration::days(7),
+            upload_fallback: false,
+            image_storage_quota_bytes: 500 * 1024 * 1024,
+            image_cache_max_age_secs: 24 * 60 * 60,
+            direct_upload_url_ttl_secs: 15 * 60,
+            upload_concurrency: 8,
+            profile_read_concurrency: 32,
+            concurrency_wait_ms: 500,
+            clamav_address: None,
+            service_account_rate_limit_per_minute: 60,
+            check_phone_enabled: true,
+            check_phone_limit_per_ip: 10,
+            check_phone_limit_per_number: 5,
+            dedupe_conversations: true,
+            twilio_webhook_url: None,
+            twilio_verify_service_sid: None,
+            twilio_messaging_service_sid: None,
+            twilio_from_number: None,
+            conversation_creation_limit_per_hour: 10,
+            max_history_offset: 1000,
+            access_log_retention: Duration::days(365),
+            cold_storage_enabled: false,
+            cold_storage_after: Duration::days(365),
+            cold_storage_batch_size: 500,
+            signature_failure_threshold: 5,
+            signature_failure_threshold_per_ip: 20,
+            signature_lockout_secs: 15 * 60,
+            signature_lockout_sms: false,
+            code_failure_threshold: 5,
+            code_lockout_secs: 15 * 60,
+            refresh_token_pepper: None,
+            refresh_token_ttl: Duration::days(30),
+            refresh_token_cleanup_grace: Duration::days(7),
+            ws_idle_timeout_secs: 10 * 60,
+            ws_away_after_secs: 5 * 60,
+            ws_message_limit_per_minute: 120,
+            feature_flags: crate::services::feature_flags::DEFAULT_FLAGS
+                .iter()
+                .map(|(flag, enabled)| (flag.to_string(), *enabled))
+                .collect(),
+            moderation_action: ModerationAction::Reject,
+            moderation_blocklist: None,
+            moderation_detect_card_numbers: true,
+            warm_up: false,
+            warm_up_required: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let defaults = Config::default();
+
+        let reminder_lead_times = env::var("REMINDER_LEAD_TIMES_MINUTES")
+            .ok()
+            .map(|value| {
+                value
+                    .split(',')
+                    .filter_map(|minutes| minutes.trim().parse::<i64>().ok())
+                    .filter(|minutes| *minutes > 0)
+                    .map(Duration::minutes)
+                    .collect::<Vec<_>>()
+            })
+            .filter(|lead_times| !lead_times.is_empty())
+            .unwrap_or(defaults.reminder_lead_times);
+
+        // FEATURE_FLAGS="canned_responses=false,new_inbox" (a bare name turns the flag on)
+        let mut feature_flags = defaults.feature_flags;
+        let warm_up_required = env::var("WARM_UP_REQUIRED")
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or(defaults.warm_up_required);
+        if let Ok(value) = env::var("FEATURE_FLAGS") {
+            for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+                match entry.split_once('=') {
+                    Some((flag, enabled)) => {
+                        if let Ok(enabled) = enabled.trim().parse() {
+                            feature_flags.insert(flag.trim().to_string(), enabled);
+                        }
+                    },
+                    None => {
+                        feature_flags.insert(entry.to_string(), true);
+                    }
+                }
+            }
+        }
+
+        Config {
+            reminder_lead_times,
+            reminder_scan_interval_secs: env_or("REMINDER_SCAN_INTERVAL_SECS", defaults.reminder_scan_interval_secs),
+            idempotency_key_ttl: Duration::seconds(env_or("IDEMPOTENCY_KEY_TTL_SECS", defaults.idempotency_key_ttl.num_seconds())),
+            idempotency_lease: Duration::seconds(env_or("IDEMPOTENCY_LEASE_SECS", defaults.idempotency_lease.num_seconds())),
+            ws_replay_buffer_events: env_or("WS_REPLAY_BUFFER_EVENTS", defaults.ws_replay_buffer_events),
+            ws_replay_buffer_ttl: Duration::seconds(env_or("WS_REPLAY_BUFFER_SECS", defaults.ws_replay_buffer_ttl.num_seconds())),
+            ws_replay_max_events: env_or("WS_REPLAY_MAX_EVENTS", defaults.ws_replay_max_events),
+            ws_disconnect_buffer_events: env_or("WS_DISCONNECT_BUFFER_EVENTS", defaults.ws_disconnect_buffer_events),
+            ws_disconnect_buffer_ttl: Duration::seconds(env_or("WS_DISCONNECT_BUFFER_SECS", defaults.ws_disconnect_buffer_ttl.num_seconds())),
+            pending_events_max_per_user: env_or("PENDING_EVENTS_MAX_PER_USER", defaults.pending_events_max_per_user),
+            pending_events_ttl: Duration::seconds(env_or("PENDING_EVENTS_TTL_SECS", defaults.pending_events_ttl.num_seconds())),
+            upload_fallback: env_or("UPLOAD_FALLBACK", defaults.upload_fallback),
+            image_storage_quota_bytes: env_or("IMAGE_STORAGE_QUOTA_BYTES", defaults.image_storage_quota_bytes),
+            image_cache_max_age_secs: env_or("IMAGE_CACHE_MAX_AGE_SECS", defaults.image_cache_max_age_secs),
+            direct_upload_url_ttl_secs: env_or("DIRECT_UPLOAD_URL_TTL_SECS", defaults.direct_upload_url_ttl_secs),
+            upload_concurrency: env_or("UPLOAD_CONCURRENCY", defaults.upload_concurrency),
+            profile_read_concurrency: env_or("PROFILE_READ_CONCURRENCY", defaults.profile_read_concurrency),
+            concurrency_wait_ms: env_or("CONCURRENCY_WAIT_MS", defaults.concurrency_wait_ms),
+            clamav_address: env::var("CLAMAV_ADDRESS").ok().filter(|address| !address.trim().is_empty()),
+            service_account_rate_limit_per_minute: env_or("SERVICE_ACCOUNT_RATE_LIMIT_PER_MINUTE", defaults.service_account_rate_limit_per_minute),
+            check_phone_enabled: env_or("CHECK_PHONE_ENABLED", defaults.check_phone_enabled),
+            check_phone_limit_per_ip: env_or("CHECK_PHONE_LIMIT_PER_IP", defaults.check_phone_limit_per_ip),
+            check_phone_limit_per_number: env_or("CHECK_PHONE_LIMIT_PER_NUMBER", defaults.check_phone_limit_per_number),
+            dedupe_conversations: env_or("DEDUPE_CONVERSATIONS", defaults.dedupe_conversations),
+            twilio_webhook_url: env::var("TWILIO_WEBHOOK_URL").ok().filter(|url| !url.trim().is_empty()),
+            // TWILIO_SERVICE_SID is the name older deployments use
+            twilio_verify_service_sid: non_empty_env("TWILIO_VERIFY_SERVICE_SID").or_else(|| non_empty_env("TWILIO_SERVICE_SID")),
+            twilio_messaging_service_sid: non_empty_env("TWILIO_MESSAGING_SERVICE_SID"),
+            twilio_from_number: non_empty_env("TWILIO_FROM_NUMBER"),
+            conversation_creation_limit_per_hour: env_or("CONVERSATION_CREATION_LIMIT_PER_HOUR", defaults.conversation_creation_limit_per_hour),
+            max_history_offset: env_or("MAX_HISTORY_OFFSET", defaults.max_history_offset),
+            access_log_retention: Duration::days(env_or("ACCESS_LOG_RETENTION_DAYS", defaults.access_log_retention.num_days())),
+            cold_storage_enabled: env_or("COLD_STORAGE_ENABLED", defaults.cold_storage_enabled),
+            cold_storage_after: Duration::days(env_or("COLD_STORAGE_AFTER_DAYS", defaults.cold_storage_after.num_days())),
+            cold_storage_batch_size: env_or("COLD_STORAGE_BATCH_SIZE", defaults.cold_storage_batch_size),
+            signature_failure_threshold: env_or("SIGNATURE_FAILURE_THRESHOLD", defaults.signature_failure_threshold),
+            signature_failure_threshold_per_ip: env_or("SIGNATURE_FAILURE_THRESHOLD_PER_IP", defaults.signature_failure_threshold_per_ip),
+            signature_lockout_secs: env_or("SIGNATURE_LOCKOUT_SECS", defaults.signature_lockout_secs),
+            signature_lockout_sms: env_or("SIGNATURE_LOCKOUT_SMS", defaults.signature_lockout_sms),
+            code_failure_threshold: env_or("CODE_FAILURE_THRESHOLD", defaults.code_failure_threshold),
+            code_lockout_secs: env_or("CODE_LOCKOUT_SECS", defaults.code_lockout_secs),
+            refresh_token_pepper: non_empty_env("REFRESH_TOKEN_PEPPER"),
+            refresh_token_ttl: Duration::days(env_or("REFRESH_TOKEN_TTL_DAYS", defaults.refresh_token_ttl.num_days())),
+            refresh_token_cleanup_grace: Duration::days(env_or("REFRESH_TOKEN_CLEANUP_GRACE_DAYS", defaults.refresh_token_cleanup_grace.num_days())),
+            ws_idle_timeout_secs: env_or("WS_IDLE_TIMEOUT_SECS", defaults.ws_idle_timeout_secs),
+            ws_away_after_secs: env_or("WS_AWAY_AFTER_SECS", defaults.ws_away_after_secs),
+            ws_message_limit_per_minute: env_or("WS_MESSAGE_LIMIT_PER_MINUTE", defaults.ws_message_limit_per_minute),
+            feature_flags,
+            moderation_action: env_or("MODERATION_ACTION", defaults.moderation_action),
+            moderation_blocklist: env::var("MODERATION_BLOCKLIST").ok().filter(|pattern| !pattern.trim().is_empty()),
+            moderation_detect_card_numbers: env_or("MODERATION_DETECT_CARD_NUMBERS", defaults.moderation_detect_card_numbers),
+            warm_up: env_or("WARM_UP", defaults.warm_up),
+            warm_up_required,
+        }
+    }
+
+    /// Checks the Twilio sender settings look like what Twilio expects, so a
+    /// typo stops startup rather than every verification text.
+    pub fn validate_twilio_sender(&self) -> Result<(), String> {
+        let is_sid = |sid: &str, prefix: &str| {
+            sid.len() == 34 && sid.starts_with(prefix) && sid[2..].chars().all(|c| c.is_ascii_hexdigit())
+        };
+        if let Some(sid) = &self.twilio_verify_service_sid {
+            if !is_sid(sid, "VA") {
+                return Err(format!("TWILIO_VERIFY_SERVICE_SID must be a Verify service SID (VA followed by 32 hex digits), got {:?}", sid));
+            }
+        }
+        if let Some(sid) = &self.twilio_messaging_service_sid {
+            if !is_sid(sid, "MG") {
+                return Err(format!("TWILIO_MESSAGING_SERVICE_SID must be a Messaging Service SID (MG followed by 32 hex digits), got {:?}", sid));
+            }
+        }
+        if let Some(from) = &self.twilio_from_number {
+            // An E.164 number, or an alphanumeric sender ID of up to 11 characters
+            let is_number = from.strip_prefix('+').is_some_and(|digits| {
+                (8..=15).contains(&digits.len()) && digits.chars().all(|c| c.is_ascii_digit())
+            });
+            let is_sender_id = (1..=11).contains(&from.len())
+                && from.chars().all(|c| c.is_ascii_alphanumeric() || c == ' ')
+                && from.chars().any(|c| c.is_ascii_alphabetic());
+            if !is_number && !is_sender_id {
+                return Err(format!("TWILIO_FROM_NUMBER must be an E.164 number like +15551234567 or a sender ID of up to 11 letters and digits, got {:?}", from));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn non_empty_env(key: &str) -> Option<String> {
+    env::var(key).ok().map(|value| value.trim().to_string()).filter(|value| !value.is_empty())
+}
+
+/// Reads and parses an environment variable, falling back to `default` when it is
+/// unset or unparseable.
+pub fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(default)
+}
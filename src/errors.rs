@@ -0,0 +1,371 @@
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpResponse, ResponseError};
+use serde_json::{json, Map, Value};
+use std::fmt;
+
+use crate::services::access_log::AccessLogError;
+use crate::services::appointments::AppointmentError;
+use crate::services::canned_responses::CannedResponseError;
+use crate::services::conversations::{is_connection_error, AssignmentError, HistoryError};
+use crate::services::direct_uploads::DirectUploadError;
+use crate::services::drafts::DraftError;
+use crate::services::notes::NoteError;
+use crate::services::notification_preferences::NotificationPreferenceError;
+use crate::services::organizations::OrganizationError;
+use crate::services::pet_documents::PetDocumentError;
+use crate::services::pet_shares::PetShareError;
+use crate::services::provider_pets::ProviderPetError;
+use crate::services::service_accounts::ServiceAccountError;
+use crate::services::user_admin::UserAdminError;
+
+/// An error a handler returns with `?`. Every variant renders as
+/// `{"error": {"code": .., "message": ..}}`; database, upstream and internal
+/// failures are logged with their details and reach the client as a generic
+/// message.
+#[derive(Debug)]
+pub enum ApiError {
+    /// 400: the request is malformed or breaks a rule the client can fix.
+    Validation(String),
+    /// 401: the caller's credentials are missing, invalid or expired.
+    Unauthorized(String),
+    /// 403: the caller is known but may not do this.
+    Forbidden(String),
+    /// 404
+    NotFound(String),
+    /// 409: the request clashes with the current state.
+    Conflict(String),
+    /// A client error with its own code for clients to branch on, and any
+    /// fields they need to recover, e.g. `cursor_required`.
+    Rejected {
+        status: StatusCode,
+        code: &'static str,
+        message: String,
+        details: Option<Value>,
+    },
+    /// 429 with a `Retry-After` header.
+    RateLimited {
+        code: &'static str,
+        message: String,
+        retry_after: u64,
+    },
+    /// 503 with a `Retry-After` header, for load the client should back off from.
+    Unavailable {
+        message: &'static str,
+        retry_after: u64,
+    },
+    /// A failed query. Losing the connection is a 503 the client can retry.
+    Database(sqlx::Error),
+    /// 502: a service we depend on (SMS, storage) failed.
+    External(String),
+    /// 500: anything else that went wrong on our side.
+    Internal(String),
+}
+
+impl ApiError {
+    /// A client error with a code of its own.
+    pub fn rejected(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::Rejected { status, code, message: message.into(), details: None }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::Validation(_) => "invalid_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Rejected { code, .. } | ApiError::RateLimited { code, .. } => code,
+            ApiError::Database(e) if is_connection_error(e) => "service_unavailable",
+            ApiError::Unavailable { .. } => "service_unavailable",
+            ApiError::Database(_) | ApiError::Internal(_) => "internal_error",
+            ApiError::External(_) => "upstream_error",
+        }
+    }
+
+    // What the client is told, which for server-side failures is never the cause
+    fn public_message(&self) -> String {
+        match self {
+            ApiError::Validation(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::Conflict(message)
+            | ApiError::Rejected { message, .. }
+            | ApiError::RateLimited { message, .. } => message.clone(),
+            ApiError::Unavailable { message, .. } => message.to_string(),
+            ApiError::Database(e) if is_connection_error(e) => {
+                "The service is temporarily unavailable. Try again shortly.".to_string()
+            },
+            ApiError::External(_) => "A service we depend on failed. Try again shortly.".to_string(),
+            ApiError::Database(_) | ApiError::Internal(_) => "Something went wrong on our side".to_string(),
+        }
+    }
+
+    fn retry_after(&self) -> Option<u64> {
+        match self {
+            ApiError::RateLimited { retry_after, .. } | ApiError::Unavailable { retry_after, .. } => Some(*retry_after),
+            ApiError::Database(e) if is_connection_error(e) => Some(5),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Database(e) => write!(f, "Database error: {}", e),
+            ApiError::External(detail) | ApiError::Internal(detail) => write!(f, "{}", detail),
+            _ => write!(f, "{}", self.public_message()),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Validation(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Rejected { status, .. } => *status,
+            ApiError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Database(e) if is_connection_error(e) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Unavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::External(_) => StatusCode::BAD_GATEWAY,
+            ApiError::Database(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        if status.is_server_error() {
+            elogln!("{}", self);
+        }
+
+        let mut error = Map::new();
+        error.insert("code".to_string(), json!(self.code()));
+        error.insert("message".to_string(), json!(self.public_message()));
+        if let ApiError::Rejected { details: Some(Value::Object(details)), .. } = self {
+            error.extend(details.clone());
+        }
+        let mut response = HttpResponse::build(status);
+        if let Some(retry_after) = self.retry_after() {
+            error.insert("retry_after".to_string(), json!(retry_after));
+            response.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+        }
+        response.json(json!({ "error": error }))
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> Self {
+        ApiError::Database(e)
+    }
+}
+
+impl From<AccessLogError> for ApiError {
+    fn from(e: AccessLogError) -> Self {
+        match e {
+            AccessLogError::NotFound => ApiError::NotFound(e.to_string()),
+            AccessLogError::Invalid(_) => ApiError::Validation(e.to_string()),
+            AccessLogError::Database(e) => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<AppointmentError> for ApiError {
+    fn from(e: AppointmentError) -> Self {
+        match e {
+            AppointmentError::NotFound => ApiError::NotFound(e.to_string()),
+            AppointmentError::Forbidden(_) => ApiError::Forbidden(e.to_string()),
+            AppointmentError::Invalid(_) => ApiError::Validation(e.to_string()),
+            AppointmentError::Database(e) => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<AssignmentError> for ApiError {
+    fn from(e: AssignmentError) -> Self {
+        match e {
+            AssignmentError::NotFound => ApiError::NotFound(e.to_string()),
+            AssignmentError::Invalid(_) => ApiError::Validation(e.to_string()),
+            AssignmentError::Database(e) => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<CannedResponseError> for ApiError {
+    fn from(e: CannedResponseError) -> Self {
+        match e {
+            CannedResponseError::NotFound => ApiError::NotFound(e.to_string()),
+            CannedResponseError::Forbidden => ApiError::Forbidden(e.to_string()),
+            CannedResponseError::Invalid(_) => ApiError::Validation(e.to_string()),
+            CannedResponseError::Database(e) => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<DirectUploadError> for ApiError {
+    fn from(e: DirectUploadError) -> Self {
+        match e {
+            DirectUploadError::NotFound => ApiError::NotFound(e.to_string()),
+            DirectUploadError::Invalid(_) => ApiError::Validation(e.to_string()),
+            DirectUploadError::Database(e) => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<DraftError> for ApiError {
+    fn from(e: DraftError) -> Self {
+        match e {
+            DraftError::NotFound => ApiError::NotFound(e.to_string()),
+            DraftError::Forbidden => ApiError::Forbidden(e.to_string()),
+            DraftError::Invalid(_) => ApiError::Validation(e.to_string()),
+            DraftError::Database(e) => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<HistoryError> for ApiError {
+    fn from(e: HistoryError) -> Self {
+        match e {
+            HistoryError::Invalid(_) => ApiError::Validation(e.to_string()),
+            HistoryError::CursorRequired => ApiError::rejected(StatusCode::BAD_REQUEST, "cursor_required", e.to_string()),
+            HistoryError::Database(e) => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<NoteError> for ApiError {
+    fn from(e: NoteError) -> Self {
+        match e {
+            NoteError::NotFound => ApiError::NotFound(e.to_string()),
+            NoteError::Forbidden => ApiError::Forbidden(e.to_string()),
+            NoteError::Invalid(_) => ApiError::Validation(e.to_string()),
+            NoteError::Conflict(ref note) => ApiError::Rejected {
+                status: StatusCode::CONFLICT,
+                code: "note_version_conflict",
+                message: e.to_string(),
+                details: Some(json!({ "note": note })),
+            },
+            NoteError::Database(e) => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<NotificationPreferenceError> for ApiError {
+    fn from(e: NotificationPreferenceError) -> Self {
+        match e {
+            NotificationPreferenceError::NotFound => ApiError::NotFound(e.to_string()),
+            NotificationPreferenceError::Invalid(_) => ApiError::Validation(e.to_string()),
+            NotificationPreferenceError::Database(e) => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<OrganizationError> for ApiError {
+    fn from(e: OrganizationError) -> Self {
+        match e {
+            OrganizationError::NotFound(_) => ApiError::NotFound(e.to_string()),
+            OrganizationError::Invalid(_) => ApiError::Validation(e.to_string()),
+            OrganizationError::Database(e) => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<PetDocumentError> for ApiError {
+    fn from(e: PetDocumentError) -> Self {
+        match e {
+            PetDocumentError::NotFound(_) => ApiError::NotFound(e.to_string()),
+            PetDocumentError::Forbidden => ApiError::Forbidden(e.to_string()),
+            PetDocumentError::Invalid(_) => ApiError::Validation(e.to_string()),
+            PetDocumentError::Database(e) => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<PetShareError> for ApiError {
+    fn from(e: PetShareError) -> Self {
+        match e {
+            PetShareError::NotFound(_) => ApiError::NotFound(e.to_string()),
+            PetShareError::Invalid(_) => ApiError::Validation(e.to_string()),
+            PetShareError::Database(e) => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<ProviderPetError> for ApiError {
+    fn from(e: ProviderPetError) -> Self {
+        match e {
+            ProviderPetError::Invalid(_) => ApiError::Validation(e.to_string()),
+            ProviderPetError::Database(e) => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<ServiceAccountError> for ApiError {
+    fn from(e: ServiceAccountError) -> Self {
+        match e {
+            ServiceAccountError::NotFound(_) => ApiError::NotFound(e.to_string()),
+            ServiceAccountError::Invalid(_) => ApiError::Validation(e.to_string()),
+            ServiceAccountError::Database(e) => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<UserAdminError> for ApiError {
+    fn from(e: UserAdminError) -> Self {
+        match e {
+            UserAdminError::NotFound => ApiError::NotFound(e.to_string()),
+            UserAdminError::Invalid(_) => ApiError::Validation(e.to_string()),
+            UserAdminError::Database(e) => ApiError::Database(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+
+    async fn body(error: ApiError) -> (StatusCode, Option<String>, Value) {
+        let response = error.error_response();
+        let retry_after = response.headers().get(header::RETRY_AFTER).map(|value| value.to_str().unwrap().to_string());
+        let status = response.status();
+        let bytes = to_bytes(response.into_body()).await.unwrap();
+        (status, retry_after, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn client_errors_carry_their_code_and_message() {
+        let (status, _, json) = body(ApiError::NotFound("Pet not found".to_string())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(json, json!({ "error": { "code": "not_found", "message": "Pet not found" } }));
+
+        let (status, retry_after, json) = body(ApiError::RateLimited {
+            code: "verification_code_lockout",
+            message: "Slow down".to_string(),
+            retry_after: 30,
+        }).await;
+        assert_eq!((status, retry_after.as_deref()), (StatusCode::TOO_MANY_REQUESTS, Some("30")));
+        assert_eq!(json["error"]["code"], "verification_code_lockout");
+        assert_eq!(json["error"]["retry_after"], 30);
+    }
+
+    #[actix_web::test]
+    async fn server_errors_hide_their_cause() {
+        let (status, _, json) = body(ApiError::Database(sqlx::Error::RowNotFound)).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(json["error"]["code"], "internal_error");
+        assert!(!json.to_string().contains("no rows"));
+
+        let (status, retry_after, json) = body(ApiError::Database(sqlx::Error::PoolTimedOut)).await;
+        assert_eq!((status, retry_after.as_deref()), (StatusCode::SERVICE_UNAVAILABLE, Some("5")));
+        assert_eq!(json["error"]["code"], "service_unavailable");
+
+        let (status, _, json) = body(ApiError::External("Twilio said no: account 42 suspended".to_string())).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert!(!json.to_string().contains("Twilio"));
+    }
+}
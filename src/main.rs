@@ -1,5 +1,12 @@
 use actix::prelude::*; // Import Actix prelude for common traits and functionalities
-use actix_web::{post, web, App, HttpRequest, HttpResponse, HttpServer, Responder, get, delete};
+use actix_web::{post, web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer, ResponseError, get, delete, put};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, StatusCode};
+use actix_web::middleware::{from_fn, Next};
+use std::future::Future;
+use std::time::Instant;
+use std::sync::Arc;
 use serde_json::json;
 use sqlx::postgres::PgPoolOptions;
 use chrono::{Utc, DateTime};
@@ -7,32 +14,92 @@ use uuid::Uuid;
 use actix_multipart::Multipart;
 use futures::{StreamExt, TryStreamExt};
 use std::path::Path;
-use std::collections::HashMap;
-use google_cloud_storage::client::{Client as GcsClient, ClientConfig};
+use std::collections::{BTreeMap, HashMap};
 use sqlx::FromRow;
 use serde::Serialize;
 use serde::Deserialize;
-use mime;
-use google_cloud_storage::http::objects::upload::{UploadObjectRequest, UploadType, Media};
-use std::borrow::Cow;
 use std::fs;
 use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
 
+#[macro_use]
+mod request_id;
 mod utils;
+mod errors;
+mod auth;
 mod models;
 mod services;
 mod websockets; // Import the websockets module
+mod config;
+mod notifications;
+mod worker;
+mod signature_failures;
+mod code_failures;
+mod moderation;
+mod storage;
+mod scanning;
+mod metrics;
+mod rate_limits;
+mod readiness;
+mod concurrency;
+mod ws_schema;
+mod pagination;
+#[cfg(test)]
+mod test_utils;
 
 use crate::utils::{
-    is_timestamp_valid, send_verification_request, check_verification_code,
-    verify_signature, generate_refresh_token, generate_signed_encrypted_token,
-    verify_and_decode_token, extract_user_id_from_token
+    is_timestamp_valid, normalize_phone_number, is_test_phone_number, DEFAULT_COUNTRY_CODE, send_verification_request, check_verification_code,
+    verify_signature, generate_signed_encrypted_token,
+    inspect_token, verify_and_decode_token, verify_twilio_signature, Claims, KeyRing
 };
 use crate::models::{
-    SignedData, RegisterData, RequestVerificationCodeData, LoginData,
-    RefreshData, LogoutData, RefreshToken, UpdateProfileData, ProfilesQuery, DeleteUserData,
-    Pet, GetImagesQuery, UploadImageQuery, UpdatePetData, DeletePetData
+    SignedData, RegisterData, CheckPhoneData, RequestVerificationCodeData, LoginData,
+    RefreshData, LogoutData, RevokeTokenData, UpdateProfileData, ProfilesQuery, DeleteUserData,
+    Pet, PetData, PetUpdateResult, GetImagesQuery, ImageContentQuery, UploadImageQuery, UploadUrlData, UploadCompleteData, UpdatePetData, DeletePetData,
+    Appointment, ProposeAppointmentData, AuditLogQuery, SharePetData,
+    CannedResponseData, CannedResponsesQuery, AssignProviderData, FeatureFlagOverrideData,
+    ParticipantsQuery, ParticipantSummary, DecodeTokenData, CreateServiceAccountData, CreateOrganizationData, OrganizationRoutingData, ConversationHistoryQuery, ConversationAttachmentsQuery, ConfirmDeletionData, ProviderPetsQuery, AdminUsersQuery, SetUserScopeData, ReviewProviderRequestData,
+    ConversationHistoryResponse, UpdateNoteData, SaveDraftData, AccessLogQuery,
+    NotificationPreferenceData, PetDocumentData, PetDocumentsQuery, DisplayNamesData
 };
+use crate::services::access_log::{self, AccessLogService};
+use crate::services::appointments::AppointmentService;
+use crate::services::idempotency::{IdempotencyService, Reservation, StoredResponse};
+use crate::services::conversations::{
+    ConversationService, SummaryCache, SendMessageError, MAX_PARTICIPANT_CONVERSATIONS,
+    MAX_DISPLAY_PROFILES, is_connection_error, unique_violation
+};
+use crate::services::audit::AuditService;
+use crate::services::canned_responses::CannedResponseService;
+use crate::services::feature_flags::{self, FeatureFlagService};
+use crate::services::direct_uploads::{DirectUpload, DirectUploadService};
+use crate::services::drafts::DraftService;
+use crate::services::notes::NoteService;
+use crate::services::notification_preferences::NotificationPreferenceService;
+use crate::services::organizations::OrganizationService;
+use crate::services::images::ImageService;
+use crate::services::pending_uploads::{PendingUploadService, NewPendingUpload};
+use crate::services::pet_documents::PetDocumentService;
+use crate::services::pet_shares::PetShareService;
+use crate::services::provider_pets::ProviderPetService;
+use crate::services::request_nonces::RequestNonceService;
+use crate::services::revoked_tokens::RevokedTokenService;
+use crate::services::service_accounts::ServiceAccountService;
+use crate::services::sessions::{self, SessionService};
+use crate::services::storage_quota::StorageQuotaService;
+use crate::services::user_admin::UserAdminService;
+use crate::signature_failures::SignatureFailureTracker;
+use crate::code_failures::CodeFailureTracker;
+use crate::notifications::{Notifier, TwilioNotifier};
+use crate::config::Config;
+use crate::errors::ApiError;
+use crate::auth::AuthenticatedUser;
+use crate::moderation::{MessageModerator, RegexModerator};
+use crate::storage::{ImageStorage, GcsStorage};
+use crate::scanning::{Scanner, ScanVerdict, scanner_from_config};
+use crate::metrics::DeliveryMetrics;
+use crate::concurrency::ConcurrencyLimits;
+use crate::rate_limits::{ApiKeyRateLimiter, ConversationRateLimiter, PhoneCheckRateLimiter, SessionMessageLimiter};
+use crate::readiness::Readiness;
 use crate::websockets::websocket_route; // Import the WebSocket route handler
 
 #[derive(FromRow, Debug, Serialize, Deserialize)]
@@ -69,16 +136,286 @@ struct UserWithPet {
 
 
 
+// Runs `handler` at most once per `Idempotency-Key` header value, replaying the
+// recorded response for retries. Requests without the header run normally.
+async fn with_idempotency(
+    req: &HttpRequest,
+    pool: &sqlx::PgPool,
+    config: &Config,
+    user_id: Uuid,
+    handler: impl Future<Output = Result<HttpResponse, ApiError>>,
+) -> Result<HttpResponse, ApiError> {
+    let key = match req.headers().get("Idempotency-Key") {
+        None => return handler.await,
+        Some(value) => match value.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= 255 => key.to_string(),
+            _ => return Err(ApiError::Validation("Invalid Idempotency-Key header".to_string())),
+        },
+    };
+    let endpoint = format!("{} {}", req.method(), req.path());
+
+    match IdempotencyService::reserve(pool, user_id, &key, &endpoint, config.idempotency_lease).await? {
+        Reservation::Acquired => {},
+        Reservation::Completed(stored) => {
+            let mut response = HttpResponse::build(StatusCode::from_u16(stored.status_code).unwrap_or(StatusCode::OK));
+            if let Some(content_type) = stored.content_type {
+                response.content_type(content_type);
+            }
+            return Ok(response
+                .insert_header(("Idempotency-Replayed", "true"))
+                .body(stored.body));
+        },
+        Reservation::InProgress => return Err(ApiError::Conflict(
+            "A request with this Idempotency-Key is still being processed".to_string()
+        )),
+        Reservation::Mismatch => return Err(ApiError::rejected(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "idempotency_key_reused",
+            "Idempotency-Key was already used for a different endpoint",
+        )),
+    }
+
+    // Client errors are recorded and replayed like any other response
+    let response = handler.await.unwrap_or_else(|e| e.error_response());
+    let status = response.status();
+
+    // Server errors are not recorded so the client can retry with the same key
+    if status.is_server_error() {
+        if let Err(e) = IdempotencyService::release(pool, user_id, &key).await {
+            logln!("Failed to release idempotency key: {}", e);
+        }
+        return Ok(response);
+    }
+
+    let content_type = response.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+    let body = match actix_web::body::to_bytes(response.into_body()).await {
+        Ok(body) => body.to_vec(),
+        Err(_) => {
+            let _ = IdempotencyService::release(pool, user_id, &key).await;
+            return Err(ApiError::Internal("Failed to read response body".to_string()));
+        }
+    };
+
+    let stored = StoredResponse { status_code: status.as_u16(), content_type, body };
+    if let Err(e) = IdempotencyService::complete(pool, user_id, &key, &stored, config.idempotency_key_ttl).await {
+        logln!("Failed to record idempotent response: {}", e);
+    }
+
+    let mut response = HttpResponse::build(status);
+    if let Some(content_type) = stored.content_type {
+        response.content_type(content_type);
+    }
+    Ok(response.body(stored.body))
+}
+
+// Tags each request with a correlation id, the caller's `X-Request-Id` when it
+// is usable or a fresh one otherwise. Everything logged while handling the
+// request carries the id, and the response echoes it back.
+async fn assign_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let id = request_id::accept_or_generate(
+        req.headers().get(request_id::HEADER).and_then(|value| value.to_str().ok()),
+    );
+    let mut response = request_id::scope(id.clone(), next.call(req)).await?.map_into_boxed_body();
+    if let Ok(value) = header::HeaderValue::from_str(&id) {
+        response.headers_mut().insert(header::HeaderName::from_static(request_id::HEADER), value);
+    }
+    Ok(response)
+}
+
+// The read-only routes a service account's API key can call, as (method, route pattern)
+const SERVICE_ACCOUNT_ROUTES: &[(&str, &str)] = &[
+    ("GET", "/profiles"),
+    ("GET", "/conversations/participants"),
+    ("GET", "/conversations/{id}/summary"),
+];
+
+// Authenticates `Authorization: Api-Key <key>` requests as the key's service
+// account, leaving `service` scope claims for `AuthenticatedUser`. Other
+// requests pass through untouched.
+async fn authenticate_api_key(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let api_key = match req.headers().get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Api-Key "))
+    {
+        Some(api_key) => api_key.trim().to_string(),
+        None => return next.call(req).await.map(ServiceResponse::map_into_boxed_body),
+    };
+    let pool = req.app_data::<web::Data<sqlx::PgPool>>().expect("pool is registered").clone();
+    let limiter = req.app_data::<web::Data<ApiKeyRateLimiter>>().expect("rate limiter is registered").clone();
+
+    let account = match ServiceAccountService::authenticate(&pool, &api_key).await {
+        Ok(Some(account)) => account,
+        Ok(None) => return Ok(req.into_response(
+            ApiError::Unauthorized("Invalid API key".to_string()).error_response()
+        )),
+        Err(e) => return Ok(req.into_response(ApiError::Database(e).error_response())),
+    };
+
+    let allowed = req.match_pattern()
+        .is_some_and(|pattern| SERVICE_ACCOUNT_ROUTES.contains(&(req.method().as_str(), pattern.as_str())));
+    if !allowed {
+        return Ok(req.into_response(
+            ApiError::Forbidden("Service accounts can only read conversations and profiles".to_string()).error_response()
+        ));
+    }
+
+    if let Err(retry_after) = limiter.check(account.id, account.rate_limit_per_minute as u32, Instant::now()) {
+        return Ok(req.into_response(ApiError::RateLimited {
+            code: "api_key_rate_limited",
+            message: "API key rate limit exceeded".to_string(),
+            retry_after: retry_after.as_secs().max(1),
+        }.error_response()));
+    }
+
+    req.extensions_mut().insert(Claims::for_service_account(account.provider_id));
+    next.call(req).await.map(ServiceResponse::map_into_boxed_body)
+}
+
+// Refuses bearer tokens that have been revoked with a 401. Tokens that don't
+// decode are left for the handlers to refuse as before.
+async fn reject_revoked_tokens(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let keys = req.app_data::<web::Data<KeyRing>>();
+    let jti = req.headers().get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .zip(keys)
+        .and_then(|(token, keys)| verify_and_decode_token(keys, token.trim()).ok())
+        .and_then(|claims| claims.get_jti());
+    let Some(jti) = jti else {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    };
+    let pool = req.app_data::<web::Data<sqlx::PgPool>>().expect("pool is registered").clone();
+
+    match RevokedTokenService::is_token_revoked(&pool, jti).await {
+        Ok(false) => next.call(req).await.map(ServiceResponse::map_into_boxed_body),
+        Ok(true) => Ok(req.into_response(
+            ApiError::Unauthorized("Token has been revoked".to_string()).error_response()
+        )),
+        Err(e) => Ok(req.into_response(ApiError::Database(e).error_response())),
+    }
+}
+
+// A 429 for a user locked out of login by wrong verification codes
+fn code_lockout_error(remaining: std::time::Duration) -> ApiError {
+    ApiError::RateLimited {
+        code: "verification_code_lockout",
+        message: "Too many wrong verification codes. Try again later.".to_string(),
+        retry_after: remaining.as_secs().max(1),
+    }
+}
+
+// A 503 for a request whose route group stayed full, so clients back off
+// briefly instead of piling more work onto a busy server.
+fn busy_error() -> ApiError {
+    ApiError::Unavailable { message: "The server is busy. Try again shortly.", retry_after: 1 }
+}
+
+// Refuses the request with a 404 unless `flag` is on for the user, so gated
+// endpoints look absent to everyone else.
+async fn require_feature(pool: &sqlx::PgPool, config: &Config, user_id: Uuid, flag: &str) -> Result<(), ApiError> {
+    if FeatureFlagService::is_enabled(pool, config, user_id, flag).await {
+        Ok(())
+    } else {
+        Err(ApiError::NotFound("This feature is not enabled".to_string()))
+    }
+}
+
+// Verifies a signed request from an existing user. Consecutive failures lock the
+// user out of signed requests for a while, and many failures from one address
+// lock the address out. Returns the error to send when the request must be
+// refused.
+async fn check_signature<T: Serialize>(
+    req: &HttpRequest,
+    pool: &sqlx::PgPool,
+    tracker: &SignatureFailureTracker,
+    config: &Config,
+    user_id: Uuid,
+    signed_data: &SignedData<T>,
+    public_key: &str,
+) -> Result<(), ApiError> {
+    let ip = req.peer_addr().map_or(std::net::Ipv4Addr::UNSPECIFIED.into(), |addr| addr.ip());
+    let locked_out = tracker.address_lockout_remaining(ip, Instant::now())
+        .or_else(|| tracker.lockout_remaining(user_id, Instant::now()));
+    if let Some(remaining) = locked_out {
+        return Err(ApiError::RateLimited {
+            code: "signature_failure_lockout",
+            message: "Too many failed signature verifications. Try again later.".to_string(),
+            retry_after: remaining.as_secs().max(1),
+        });
+    }
+
+    let e = match verify_signature(&signed_data.data, &signed_data.signature, public_key) {
+        Ok(()) => {
+            tracker.record_success(user_id);
+            // Only genuine signatures are remembered, so garbage can't fill the table
+            return match RequestNonceService::claim(pool, user_id, &signed_data.signature).await? {
+                true => Ok(()),
+                false => Err(ApiError::rejected(
+                    StatusCode::CONFLICT,
+                    "request_replayed",
+                    "This signed request has already been used",
+                )),
+            };
+        },
+        Err(e) => e,
+    };
+    logln!("Signature verification failed for user {} from {}: {}", user_id, ip, e);
+
+    if let Some(failures) = tracker.record_address_failure(ip, Instant::now()) {
+        logln!("Address {} locked out after {} signature failures", ip, failures);
+        AuditService::record(pool, "signature_failure_address_lockout", None, None, json!({
+            "ip": ip.to_string(),
+            "failures": failures,
+            "lockout_secs": config.signature_lockout_secs
+        })).await;
+    }
+
+    if let Some(failures) = tracker.record_failure(user_id, Instant::now()) {
+        logln!("User {} locked out after {} signature failures", user_id, failures);
+        AuditService::record(pool, "signature_failure_lockout", None, Some(user_id), json!({
+            "consecutive_failures": failures,
+            "lockout_secs": config.signature_lockout_secs
+        })).await;
+
+        if config.signature_lockout_sms {
+            if let Ok(Some(user)) = sqlx::query!("SELECT phone_number FROM users WHERE id = $1", user_id)
+                .fetch_optional(pool)
+                .await
+            {
+                let body = "VetText: we blocked several failed sign-in attempts on your account. If this wasn't you, contact support.";
+                if let Err(e) = TwilioNotifier::from_config(config).send_sms(&user.phone_number, body).await {
+                    logln!("Failed to send lockout SMS to user {}: {}", user_id, e);
+                }
+            }
+        }
+    }
+
+    Err(ApiError::Validation("Invalid signature".to_string()))
+}
+
 #[post("/register")]
 async fn register(
     signed_data: web::Json<SignedData<RegisterData>>,
-    pool: web::Data<sqlx::PgPool>
-) -> impl Responder {
-    println!("Register endpoint hit!");
+    pool: web::Data<sqlx::PgPool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    logln!("Register endpoint hit!");
 
     // Check timestamp
     if !is_timestamp_valid(&signed_data.data.timestamp) {
-        return HttpResponse::BadRequest().body("Invalid timestamp");
+        return Err(ApiError::Validation("Invalid timestamp".to_string()));
     }
 
     // Verify signature
@@ -87,237 +424,412 @@ async fn register(
         &signed_data.signature,
         &signed_data.data.public_key
     ) {
-        println!("Signature verification failed: {}", e);
-        return HttpResponse::BadRequest().body("Invalid signature");
+        logln!("Signature verification failed: {}", e);
+        return Err(ApiError::Validation("Invalid signature".to_string()));
     }
 
+    // Stored, and texted, in E.164
+    let country_code = signed_data.data.country_code.as_deref().unwrap_or(DEFAULT_COUNTRY_CODE);
+    let phone_number = normalize_phone_number(&signed_data.data.phone_number, country_code).map_err(|e| {
+        ApiError::rejected(StatusCode::BAD_REQUEST, "invalid_phone_number", format!("Invalid phone number: {}", e))
+    })?;
+
+    // Providers start out as clients until an admin approves them
+    let provider_status = match signed_data.data.requested_scope.as_deref() {
+        None | Some("client") => None,
+        Some("provider") => Some("pending"),
+        Some(_) => return Err(ApiError::Validation(
+            "requested_scope must be \"client\" or \"provider\"".to_string()
+        )),
+    };
+
     // Insert new user into the database
     let record = match sqlx::query!(
-        "INSERT INTO users (phone_number, public_key, scope) VALUES ($1, $2, $3) RETURNING id",
-        &signed_data.data.phone_number,
+        "INSERT INTO users (phone_number, public_key, scope, provider_status) VALUES ($1, $2, $3, $4) RETURNING id",
+        &phone_number,
         &signed_data.data.public_key,
-        "client"
+        "client",
+        provider_status
     )
     .fetch_one(&**pool)
     .await {
         Ok(record) => record,
-        Err(e) => {
-            if e.to_string().contains("users_phone_number_key") {
-                return HttpResponse::BadRequest().json(json!({
-                    "message": "Phone number already registered"
-                }));
-            }
-            return HttpResponse::InternalServerError().body(format!("Failed to insert user: {}", e));
-        }
+        // The phone number is the only unique column set here
+        Err(e) if unique_violation(&e).is_some() => {
+            return Err(ApiError::rejected(StatusCode::CONFLICT, "phone_number_taken", "Phone number already registered"));
+        },
+        Err(e) => return Err(e.into()),
     };
 
-    println!("Generated user_id: {:?}", record.id);
+    logln!("Generated user_id: {:?}", record.id);
+    AuditService::record(&pool, "register", Some(record.id), Some(record.id), json!({
+        "provider_status": provider_status
+    })).await;
 
-    // If phone number starts with "000123" then it is a test phone number
-    if signed_data.data.phone_number.starts_with("000123") {
-        return HttpResponse::Ok().json(json!({
+    if is_test_phone_number(&phone_number) {
+        return Ok(HttpResponse::Ok().json(json!({
             "message": "Test registration data received and verified. Test verification code is 123456.",
-            "user_id": record.id
-        }));
+            "user_id": record.id,
+            "provider_status": provider_status
+        })));
     }
 
     // Send Twilio verification code for real phone numbers
-    match send_verification_request(&signed_data.data.phone_number).await {
-        Ok(_) => HttpResponse::Ok().json(json!({
-            "message": "Registration data received and verified. Verification code sent.",
-            "user_id": record.id
-        })),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Failed to send verification: {}", e)),
+    send_verification_request(&config, &phone_number).await
+        .map_err(|e| ApiError::External(format!("Failed to send verification: {}", e)))?;
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Registration data received and verified. Verification code sent.",
+        "user_id": record.id,
+        "provider_status": provider_status
+    })))
+}
+
+// Every /check-phone response takes at least this long, so timing doesn't
+// reveal whether the number was found, refused or rate limited
+const CHECK_PHONE_RESPONSE_TIME: std::time::Duration = std::time::Duration::from_millis(300);
+
+/// Tells the app whether to show log in or sign up for a phone number. Heavily
+/// rate limited, and 404 when `CHECK_PHONE_ENABLED` is off.
+#[post("/check-phone")]
+async fn check_phone(
+    req: HttpRequest,
+    data: Option<web::Json<CheckPhoneData>>,
+    pool: web::Data<sqlx::PgPool>,
+    config: web::Data<Config>,
+    limiter: web::Data<PhoneCheckRateLimiter>,
+) -> Result<HttpResponse, ApiError> {
+    // Indistinguishable from an unknown route, malformed body or not
+    if !config.check_phone_enabled {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let started_at = Instant::now();
+    let response = phone_registration_status(&req, data, &pool, &config, &limiter).await;
+    actix_web::rt::time::sleep_until((started_at + CHECK_PHONE_RESPONSE_TIME).into()).await;
+    response
+}
+
+async fn phone_registration_status(
+    req: &HttpRequest,
+    data: Option<web::Json<CheckPhoneData>>,
+    pool: &sqlx::PgPool,
+    config: &Config,
+    limiter: &PhoneCheckRateLimiter,
+) -> Result<HttpResponse, ApiError> {
+    let Some(phone_number) = data.and_then(|data| {
+        normalize_phone_number(&data.phone_number, data.country_code.as_deref().unwrap_or(DEFAULT_COUNTRY_CODE)).ok()
+    }) else {
+        return Err(ApiError::Validation("Invalid phone number".to_string()));
+    };
+
+    let ip = req.peer_addr().map_or(std::net::Ipv4Addr::UNSPECIFIED.into(), |addr| addr.ip());
+    if let Err(retry_after) = limiter.check(
+        ip,
+        &phone_number,
+        config.check_phone_limit_per_ip,
+        config.check_phone_limit_per_number,
+        Instant::now(),
+    ) {
+        return Err(ApiError::RateLimited {
+            code: "phone_check_rate_limited",
+            message: "Too many phone number checks. Try again later.".to_string(),
+            retry_after: retry_after.as_secs().max(1),
+        });
+    }
+
+    let record = sqlx::query!("SELECT EXISTS (SELECT 1 FROM users WHERE phone_number = $1) AS \"registered!\"", phone_number)
+        .fetch_one(pool)
+        .await?;
+    Ok(HttpResponse::Ok().json(json!({ "registered": record.registered })))
+}
+
+// Tells Twilio there's nothing to send back; replies go out through the Notifier
+const EMPTY_TWIML: &str = r#"<?xml version="1.0" encoding="UTF-8"?><Response></Response>"#;
+
+// A short code such as `#3F9A1C` in a reply names the conversation it's for
+fn sms_short_code_pattern() -> &'static regex::Regex {
+    static PATTERN: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    PATTERN.get_or_init(|| regex::Regex::new(r"\s*#([0-9A-Fa-f]{6})\b").unwrap())
+}
+
+fn twiml_response() -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().content_type("text/xml").body(EMPTY_TWIML))
+}
+
+async fn reply_by_sms(notifier: &dyn Notifier, phone_number: &str, body: &str) {
+    if let Err(e) = notifier.send_sms(phone_number, body).await {
+        logln!("Failed to send SMS reply to {}: {}", phone_number, e);
+    }
+}
+
+/// Inbound SMS from Twilio. A text from a registered number is posted, flagged
+/// `via_sms`, to the conversation named by a short code in it or else to the
+/// sender's most recently active conversation. Anything that can't be posted
+/// gets an SMS explaining why and nothing is stored.
+#[post("/webhooks/twilio/inbound-sms")]
+#[allow(clippy::too_many_arguments)]
+async fn twilio_inbound_sms(
+    req: HttpRequest,
+    form: web::Form<BTreeMap<String, String>>,
+    pool: web::Data<sqlx::PgPool>,
+    config: web::Data<Config>,
+    moderator: web::Data<dyn MessageModerator>,
+    notifier: web::Data<dyn Notifier>,
+    ws_server: web::Data<Addr<websockets::WsServer>>,
+) -> Result<HttpResponse, ApiError> {
+    let Ok(auth_token) = std::env::var("TWILIO_AUTH_TOKEN") else {
+        return Err(ApiError::rejected(StatusCode::SERVICE_UNAVAILABLE, "not_configured", "Inbound SMS is not configured"));
+    };
+    let url = config.twilio_webhook_url.clone().unwrap_or_else(|| {
+        let info = req.connection_info();
+        format!("{}://{}{}", info.scheme(), info.host(), req.uri())
+    });
+    let params = form.into_inner();
+    let signature = req.headers().get("X-Twilio-Signature").and_then(|value| value.to_str().ok()).unwrap_or("");
+    if !verify_twilio_signature(&auth_token, &url, &params, signature) {
+        return Err(ApiError::Forbidden("Invalid signature".to_string()));
+    }
+
+    let (Some(from), Some(body)) = (params.get("From"), params.get("Body")) else {
+        return Err(ApiError::Validation("From and Body are required".to_string()));
+    };
+    // Twilio sends E.164 already; this just matches it to how numbers are stored
+    let from = normalize_phone_number(from, DEFAULT_COUNTRY_CODE).unwrap_or_else(|_| from.clone());
+    let from = from.as_str();
+    let user = sqlx::query!("SELECT id FROM users WHERE phone_number = $1", from)
+        .fetch_optional(&**pool)
+        .await?;
+    let user_id = match user {
+        Some(user) => user.id,
+        None => {
+            reply_by_sms(&**notifier, from, "Sorry, we couldn't find a VetText account for this number. Please open the VetText app to message your vet.").await;
+            return twiml_response();
+        },
+    };
+
+    let short_code = sms_short_code_pattern().captures(body).map(|captures| captures[1].to_string());
+    let content = sms_short_code_pattern().replace(body, "").trim().to_string();
+    if ConversationService::validate_message_content(&content).is_err() {
+        reply_by_sms(&**notifier, from, "Sorry, we couldn't deliver that message. Please open the VetText app to reply.").await;
+        return twiml_response();
+    }
+
+    let conversation_id = match ConversationService::find_sms_reply_conversation(&pool, user_id, short_code.as_deref()).await? {
+        Some(conversation_id) => conversation_id,
+        None => {
+            reply_by_sms(&**notifier, from, "You don't have any VetText conversations yet. Please open the VetText app to start one.").await;
+            return twiml_response();
+        },
+    };
+
+    match ConversationService::send_message(&pool, &**moderator, user_id, conversation_id, content, Utc::now(), None, true).await {
+        Ok(message) => {
+            ws_server.do_send(websockets::BroadcastToConversation {
+                conversation_id,
+                message: models::WsMessage {
+                    sender_id: Uuid::nil(),
+                    event: "message_sent".to_string(),
+                    params: json!(message),
+                },
+                timing: None,
+            });
+            twiml_response()
+        },
+        Err(SendMessageError::Blocked(_)) => {
+            reply_by_sms(&**notifier, from, "Sorry, we couldn't deliver that message. Please open the VetText app to reply.").await;
+            twiml_response()
+        },
+        Err(SendMessageError::Database(e)) => Err(e.into()),
     }
 }
 
 #[post("/request-verification-code")]
 async fn request_verification_code(
+    req: HttpRequest,
     signed_data: web::Json<SignedData<RequestVerificationCodeData>>,
-    pool: web::Data<sqlx::PgPool>
-) -> impl Responder {
-    println!("Request verification code endpoint hit!");
+    pool: web::Data<sqlx::PgPool>,
+    tracker: web::Data<SignatureFailureTracker>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    logln!("Request verification code endpoint hit!");
 
     // Check timestamp
     if !is_timestamp_valid(&signed_data.data.timestamp) {
-        return HttpResponse::BadRequest().body("Invalid timestamp");
+        return Err(ApiError::Validation("Invalid timestamp".to_string()));
     }
 
-    // Look up the user's public key and phone number by phone number
+    let country_code = signed_data.data.country_code.as_deref().unwrap_or(DEFAULT_COUNTRY_CODE);
+    let phone_number = match normalize_phone_number(&signed_data.data.phone_number, country_code) {
+        Ok(phone_number) => phone_number,
+        Err(e) => return Err(ApiError::rejected(StatusCode::BAD_REQUEST, "invalid_phone_number", format!("Invalid phone number: {}", e))),
+    };
+
+    // Look up the user's public key by phone number
     let user_data = match sqlx::query!(
         "SELECT id, public_key FROM users WHERE phone_number = $1",
-        &signed_data.data.phone_number
+        &phone_number
     )
     .fetch_optional(&**pool)
-    .await {
-        Ok(Some(record)) => record,
-        Ok(None) => return HttpResponse::NotFound().body(format!("User not found for phone number: {}", signed_data.data.phone_number)),
-        Err(e) => return HttpResponse::InternalServerError().body(format!("Database error: {}", e)),
+    .await? {
+        Some(record) => record,
+        None => return Err(ApiError::NotFound(format!("User not found for phone number: {}", signed_data.data.phone_number))),
     };
 
     // Verify signature using the retrieved public key
-    if let Err(e) = verify_signature(
-        &signed_data.data,
-        &signed_data.signature,
-        &user_data.public_key
-    ) {
-        println!("Signature verification failed: {}", e);
-        return HttpResponse::BadRequest().body("Invalid signature");
-    }
+    check_signature(&req, &pool, &tracker, &config, user_data.id, &signed_data, &user_data.public_key).await?;
 
-    // If phone number starts with "000123" then it is a test phone number
-    if signed_data.data.phone_number.starts_with("000123") {
-        return HttpResponse::Ok().json(json!({
+    if is_test_phone_number(&phone_number) {
+        return Ok(HttpResponse::Ok().json(json!({
             "message": "Test registration data received and verified. Test verification code is 123456.",
             "user_id": user_data.id
-        }));
+        })));
     }
 
     // Send Twilio verification code for real phone numbers
-    match send_verification_request(&signed_data.data.phone_number).await {
-        Ok(_) => HttpResponse::Ok().json(json!({
-            "message": "Verification code sent",
-            "user_id": user_data.id
-        })),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Failed to send verification: {}", e)),
-    }
+    send_verification_request(&config, &phone_number).await
+        .map_err(|e| ApiError::External(format!("Failed to send verification: {}", e)))?;
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Verification code sent",
+        "user_id": user_data.id
+    })))
 }
 
 #[post("/login")]
 async fn login(
+    req: HttpRequest,
     signed_data: web::Json<SignedData<LoginData>>,
-    pool: web::Data<sqlx::PgPool>
-) -> impl Responder {
-    println!("Login endpoint hit!");
+    pool: web::Data<sqlx::PgPool>,
+    tracker: web::Data<SignatureFailureTracker>,
+    code_failures: web::Data<CodeFailureTracker>,
+    config: web::Data<Config>,
+    keys: web::Data<KeyRing>,
+) -> Result<HttpResponse, ApiError> {
+    logln!("Login endpoint hit!");
 
     // Check timestamp
     if !is_timestamp_valid(&signed_data.data.timestamp) {
-        return HttpResponse::BadRequest().body("Invalid timestamp");
+        return Err(ApiError::Validation("Invalid timestamp".to_string()));
+    }
+
+    let device_name = signed_data.data.device_name.as_deref().map(str::trim).filter(|name| !name.is_empty());
+    if device_name.is_some_and(|name| name.chars().count() > sessions::MAX_DEVICE_NAME_LENGTH) {
+        return Err(ApiError::Validation("device_name must be at most 100 characters".to_string()));
     }
+    let device_name = device_name.map(str::to_string);
 
     // Look up the user's public key and verified status by user_id
     let user_data = match sqlx::query!(
-        "SELECT public_key, verified, phone_number, scope FROM users WHERE id = $1",
+        "SELECT public_key, verified, phone_number, scope, suspended_at FROM users WHERE id = $1",
         &signed_data.data.user_id
     )
     .fetch_optional(&**pool)
-    .await {
-        Ok(Some(record)) => record,
-        Ok(None) => return HttpResponse::NotFound().body(format!("User not found for id: {}", signed_data.data.user_id)),
-        Err(e) => return HttpResponse::InternalServerError().body(format!("Database error: {}", e)),
+    .await? {
+        Some(record) => record,
+        None => return Err(ApiError::NotFound(format!("User not found for id: {}", signed_data.data.user_id))),
     };
 
     // Verify signature using the retrieved public key
-    if let Err(e) = verify_signature(
-        &signed_data.data,
-        &signed_data.signature,
-        &user_data.public_key
-    ) {
-        println!("Signature verification failed: {}", e);
-        return HttpResponse::BadRequest().body("Invalid signature");
+    check_signature(&req, &pool, &tracker, &config, signed_data.data.user_id, &signed_data, &user_data.public_key).await?;
+
+    if user_data.suspended_at.is_some() {
+        return Err(ApiError::rejected(StatusCode::FORBIDDEN, "account_suspended", "This account has been suspended"));
     }
 
-    // If phone number starts with "000123" then it is a test phone number
-    if user_data.phone_number.starts_with("000123") {
-        if signed_data.data.verification_code != "123456" {
-            return HttpResponse::BadRequest().json(json!({
-                "message": "Invalid verification code"
-            }));
-        }
+    let user_id = signed_data.data.user_id;
+    if let Some(remaining) = code_failures.lockout_remaining(user_id, Instant::now()) {
+        return Err(code_lockout_error(remaining));
+    }
+
+    let is_valid = if is_test_phone_number(&user_data.phone_number) {
+        signed_data.data.verification_code == "123456"
     } else {
         // Check Twilio verification code for real phone numbers
-        let is_valid = match check_verification_code(&user_data.phone_number, &signed_data.data.verification_code).await {
-            Ok(is_valid) => is_valid,
-            Err(e) => return HttpResponse::InternalServerError().body(format!("Failed to check verification: {}", e)),
-        };
-
-        if !is_valid {
-            return HttpResponse::BadRequest().json(json!({
-                "message": "Invalid verification code"
-            }));
+        check_verification_code(&config, &user_data.phone_number, &signed_data.data.verification_code).await
+            .map_err(|e| ApiError::External(format!("Failed to check verification: {}", e)))?
+    };
+    if !is_valid {
+        if let Some(lockout) = code_failures.record_failure(user_id, Instant::now()) {
+            logln!("User {} locked out after {} wrong verification codes", user_id, config.code_failure_threshold);
+            AuditService::record(&pool, "verification_code_lockout", None, Some(user_id), json!({
+                "consecutive_failures": config.code_failure_threshold,
+                "lockout_secs": config.code_lockout_secs
+            })).await;
+            return Err(code_lockout_error(lockout));
         }
+        return Err(ApiError::Validation("Invalid verification code".to_string()));
     }
+    code_failures.record_success(user_id);
 
     // Update user to verified=true if not already verified
     if !user_data.verified {
-        if let Err(e) = sqlx::query!(
+        sqlx::query!(
             "UPDATE users SET verified = true WHERE id = $1",
             &signed_data.data.user_id
         )
         .execute(&**pool)
-        .await {
-            return HttpResponse::InternalServerError().body(format!("Failed to update user: {}", e));
-        }
+        .await?;
     }
 
-    // Delete existing non-invalidated refresh tokens
-    if let Err(e) = sqlx::query!(
-        "DELETE FROM refresh_tokens WHERE user_id = $1 AND is_revoked = false",
-        &signed_data.data.user_id
-    )
-    .execute(&**pool)
-    .await {
-        println!("Failed to delete existing tokens: {}", e);
-    }
-
-    // Generate new refresh token
-    let refresh_token = generate_refresh_token();
-
-    // Save refresh token to database
-    // TODO: add user_agent
-    if let Err(e) = sqlx::query!(
-        "INSERT INTO refresh_tokens (token, user_id) VALUES ($1, $2)",
-        refresh_token,
-        &signed_data.data.user_id
-    )
-    .execute(&**pool)
-    .await {
-        return HttpResponse::InternalServerError().body(format!("Failed to save refresh token: {}", e));
-    }
+    // Each login starts a new refresh token family; the user's other devices stay signed in
+    let user_agent = req.headers().get(header::USER_AGENT).and_then(|value| value.to_str().ok());
+    let (refresh_token, family_id) = SessionService::start_family(
+        &pool,
+        config.refresh_token_pepper.as_deref(),
+        signed_data.data.user_id,
+        user_agent,
+        device_name.as_deref(),
+        Utc::now() + config.refresh_token_ttl
+    ).await?;
 
     // Generate access token
-    let (access_token, expiration) = match generate_signed_encrypted_token(signed_data.data.user_id, &user_data.scope) {
-        Ok((token, exp)) => (token, exp),
-        Err(e) => return HttpResponse::InternalServerError().body(format!("Failed to generate access token: {}", e)),
-    };
+    let (access_token, expiration) = generate_signed_encrypted_token(&keys, signed_data.data.user_id, &user_data.scope, Some(family_id))
+        .map_err(|e| ApiError::Internal(format!("Failed to generate access token: {}", e)))?;
+
+    AuditService::record(&pool, "login", Some(signed_data.data.user_id), Some(signed_data.data.user_id), json!({})).await;
 
-    HttpResponse::Ok().json(json!({
+    Ok(HttpResponse::Ok().json(json!({
         "message": "Login successful",
         "user_id": &signed_data.data.user_id,
         "access_token": access_token,
         "refresh_token": refresh_token,
-        "expires_at": expiration
-    }))
+        "expires_at": expiration as i64 * 1000
+    })))
 }
 
 #[post("/refresh")]
 async fn refresh(
+    req: HttpRequest,
     signed_data: web::Json<SignedData<RefreshData>>,
-    pool: web::Data<sqlx::PgPool>
-) -> impl Responder {
-    println!("Refresh endpoint hit!");
+    pool: web::Data<sqlx::PgPool>,
+    tracker: web::Data<SignatureFailureTracker>,
+    config: web::Data<Config>,
+    keys: web::Data<KeyRing>,
+) -> Result<HttpResponse, ApiError> {
+    logln!("Refresh endpoint hit!");
 
     // Check timestamp
     if !is_timestamp_valid(&signed_data.data.timestamp) {
-        return HttpResponse::BadRequest().body("Invalid timestamp");
+        return Err(ApiError::Validation("Invalid timestamp".to_string()));
     }
 
     // Look up the refresh token
-    let refresh_token_record = match sqlx::query_as!(
-        RefreshToken,
-        "SELECT * FROM refresh_tokens WHERE token = $1 AND user_id = $2",
-        &signed_data.data.refresh_token,
-        &signed_data.data.user_id
-    )
-    .fetch_optional(&**pool)
-    .await {
-        Ok(Some(token)) => token,
-        Ok(None) => return HttpResponse::Unauthorized().body("Refresh token not found"),
-        Err(e) => return HttpResponse::InternalServerError().body(format!("Database error: {}", e)),
+    let refresh_token_record = match SessionService::find_token(
+        &pool,
+        config.refresh_token_pepper.as_deref(),
+        signed_data.data.user_id,
+        &signed_data.data.refresh_token
+    ).await? {
+        Some(token) => token,
+        None => return Err(ApiError::Unauthorized("Refresh token not found".to_string())),
     };
 
     if refresh_token_record.is_revoked {
-        return HttpResponse::Unauthorized().body("Invalid refresh token");
+        return Err(ApiError::Unauthorized("Invalid refresh token".to_string()));
+    }
+
+    // Tokens issued before expiry was recorded have none until their next use
+    if refresh_token_record.expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+        return Err(ApiError::Unauthorized("Refresh token expired".to_string()));
     }
 
     // Look up the user's info by user_id
@@ -326,57 +838,42 @@ async fn refresh(
         refresh_token_record.user_id
     )
     .fetch_optional(&**pool)
-    .await {
-        Ok(Some(record)) => record,
-        Ok(None) => return HttpResponse::NotFound().body(format!("User not found for id: {}", refresh_token_record.user_id)),
-        Err(e) => return HttpResponse::InternalServerError().body(format!("Database error: {}", e)),
+    .await? {
+        Some(record) => record,
+        None => return Err(ApiError::NotFound(format!("User not found for id: {}", refresh_token_record.user_id))),
     };
 
     // Verify signature
-    if let Err(e) = verify_signature(
-        &signed_data.data,
-        &signed_data.signature,
-        &user_data.public_key
-    ) {
-        println!("Signature verification failed: {}", e);
-        return HttpResponse::BadRequest().body("Invalid signature");
-    }
+    check_signature(&req, &pool, &tracker, &config, refresh_token_record.user_id, &signed_data, &user_data.public_key).await?;
 
-    // Update last_used_at
+    // Update last_used_at; each use keeps the token alive for another TTL
     let now = Utc::now();
-    if let Err(e) = sqlx::query!(
-        "UPDATE refresh_tokens SET last_used_at = $1 WHERE token = $2",
-        now,
-        &signed_data.data.refresh_token
-    )
-    .execute(&**pool)
-    .await {
-        return HttpResponse::InternalServerError().body(format!("Failed to update refresh token: {}", e));
-    }
+    SessionService::mark_used(&pool, &refresh_token_record.token, now, now + config.refresh_token_ttl).await?;
 
     // Generate new access token
-    let (access_token, expiration) = match generate_signed_encrypted_token(refresh_token_record.user_id, &user_data.scope) {
-        Ok((token, exp)) => (token, exp),
-        Err(e) => return HttpResponse::InternalServerError().body(format!("Failed to generate access token: {}", e)),
-    };
+    let (access_token, expiration) = generate_signed_encrypted_token(&keys, refresh_token_record.user_id, &user_data.scope, Some(refresh_token_record.family_id))
+        .map_err(|e| ApiError::Internal(format!("Failed to generate access token: {}", e)))?;
 
-    HttpResponse::Ok().json(json!({
+    Ok(HttpResponse::Ok().json(json!({
         "message": "Token refreshed successfully",
         "access_token": access_token,
-        "expires_at": expiration
-    }))
+        "expires_at": expiration as i64 * 1000
+    })))
 }
 
 #[post("/logout")]
 async fn logout(
+    req: HttpRequest,
     signed_data: web::Json<SignedData<LogoutData>>,
-    pool: web::Data<sqlx::PgPool>
-) -> impl Responder {
-    println!("Logout endpoint hit!");
+    pool: web::Data<sqlx::PgPool>,
+    tracker: web::Data<SignatureFailureTracker>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    logln!("Logout endpoint hit!");
 
     // Check timestamp
     if !is_timestamp_valid(&signed_data.data.timestamp) {
-        return HttpResponse::BadRequest().body("Invalid timestamp");
+        return Err(ApiError::Validation("Invalid timestamp".to_string()));
     }
 
     // Look up the user's public key by user_id
@@ -385,68 +882,129 @@ async fn logout(
         &signed_data.data.user_id
     )
     .fetch_optional(&**pool)
-    .await {
-        Ok(Some(record)) => record.public_key,
-        Ok(None) => return HttpResponse::NotFound().body(format!("User not found for id: {}", &signed_data.data.user_id)),
-        Err(e) => return HttpResponse::InternalServerError().body(format!("Database error: {}", e)),
+    .await? {
+        Some(record) => record.public_key,
+        None => return Err(ApiError::NotFound(format!("User not found for id: {}", &signed_data.data.user_id))),
     };
 
     // Verify signature
-    if let Err(e) = verify_signature(
-        &signed_data.data,
-        &signed_data.signature,
-        &public_key
-    ) {
-        println!("Signature verification failed: {}", e);
-        return HttpResponse::BadRequest().body("Invalid signature");
-    }
+    check_signature(&req, &pool, &tracker, &config, signed_data.data.user_id, &signed_data, &public_key).await?;
 
     // Delete the refresh token
-    match sqlx::query!(
-        "DELETE FROM refresh_tokens WHERE token = $1 AND user_id = $2",
-        &signed_data.data.refresh_token,
+    let deleted = SessionService::delete_token(
+        &pool,
+        config.refresh_token_pepper.as_deref(),
+        signed_data.data.user_id,
+        &signed_data.data.refresh_token
+    ).await?;
+    if deleted {
+        AuditService::record(&pool, "logout", Some(signed_data.data.user_id), Some(signed_data.data.user_id), json!({})).await;
+        Ok(HttpResponse::Ok().json(json!({
+            "message": "Logged out successfully"
+        })))
+    } else {
+        Err(ApiError::NotFound("Refresh token not found for this user".to_string()))
+    }
+}
+
+/// Who the token belongs to, read from the token rather than the users table
+/// so clients can check their session cheaply.
+#[get("/whoami")]
+async fn whoami(user: AuthenticatedUser) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(json!({
+        "user_id": user.user_id,
+        "scope": user.scope,
+        "expires_at": user.claims.exp as i64 * 1000
+    })))
+}
+
+/// Revokes the access token the request is made with, so it stops working
+/// before it expires. Signed like logout, since the token alone may have leaked.
+#[post("/revoke-token")]
+async fn revoke_token(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    signed_data: web::Json<SignedData<RevokeTokenData>>,
+    pool: web::Data<sqlx::PgPool>,
+    tracker: web::Data<SignatureFailureTracker>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    if !is_timestamp_valid(&signed_data.data.timestamp) {
+        return Err(ApiError::Validation("Invalid timestamp".to_string()));
+    }
+
+    if user.user_id != signed_data.data.user_id {
+        return Err(ApiError::Forbidden("The token belongs to a different user".to_string()));
+    }
+
+    let public_key = match sqlx::query!(
+        "SELECT public_key FROM users WHERE id = $1",
         &signed_data.data.user_id
     )
-    .execute(&**pool)
-    .await {
-        Ok(result) => {
-            if result.rows_affected() > 0 {
-                HttpResponse::Ok().json(json!({
-                    "message": "Logged out successfully"
-                }))
-            } else {
-                HttpResponse::NotFound().json(json!({
-                    "message": "Refresh token not found for this user"
-                }))
-            }
+    .fetch_optional(&**pool)
+    .await? {
+        Some(record) => record.public_key,
+        None => return Err(ApiError::NotFound(format!("User not found for id: {}", &signed_data.data.user_id))),
+    };
+    check_signature(&req, &pool, &tracker, &config, signed_data.data.user_id, &signed_data, &public_key).await?;
+
+    // Tokens issued before they carried an id can't be singled out
+    let Some(jti) = user.claims.get_jti() else {
+        return Err(ApiError::Validation("This token can't be revoked; sign in again to get one that can".to_string()));
+    };
+    let expires_at = DateTime::from_timestamp(user.claims.exp as i64, 0).unwrap_or_else(Utc::now);
+
+    RevokedTokenService::revoke(&pool, jti, signed_data.data.user_id, expires_at).await?;
+    AuditService::record(&pool, "token_revoked", Some(signed_data.data.user_id), Some(signed_data.data.user_id), json!({
+        "jti": jti
+    })).await;
+    Ok(HttpResponse::Ok().json(json!({ "message": "Token revoked" })))
+}
+
+#[get("/sessions")]
+async fn get_sessions(
+    user: AuthenticatedUser,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let sessions = SessionService::list(&pool, user.user_id, user.claims.get_family()).await?;
+    Ok(HttpResponse::Ok().json(json!({ "sessions": sessions })))
+}
+
+#[post("/sessions/{family_id}/revoke")]
+async fn revoke_session(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    pool: web::Data<sqlx::PgPool>,
+    ws_server: web::Data<Addr<websockets::WsServer>>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let family_id = path.into_inner();
+    match SessionService::revoke_family(&pool, user_id, family_id).await? {
+        true => {
+            let closed = ws_server
+                .send(websockets::DisconnectFamily { user_id, family_id })
+                .await
+                .unwrap_or(0);
+            AuditService::record(&pool, "session_revoked", Some(user_id), Some(user_id), json!({ "family_id": family_id })).await;
+            Ok(HttpResponse::Ok().json(json!({
+                "message": "Session revoked",
+                "disconnected_sockets": closed
+            })))
         },
-        Err(e) => HttpResponse::InternalServerError().body(format!("Failed to delete refresh token: {}", e)),
+        false => Err(ApiError::NotFound("Session not found".to_string())),
     }
 }
 
 #[get("/profiles")]
 async fn get_profiles(
-    req: HttpRequest,
+    user: AuthenticatedUser,
     query: web::Query<ProfilesQuery>,
     pool: web::Data<sqlx::PgPool>,
-) -> impl Responder {
-    // Extract and verify the token from the Authorization header
-    let token = match req.headers().get("Authorization") {
-        Some(value) => {
-            let parts: Vec<&str> = value.to_str().unwrap_or("").split_whitespace().collect();
-            if parts.len() == 2 && parts[0] == "Bearer" {
-                parts[1]
-            } else {
-                return HttpResponse::Unauthorized().body("Invalid Authorization header");
-            }
-        }
-        None => return HttpResponse::Unauthorized().body("Missing Authorization header"),
-    };
-
-    // Verify and decode the token
-    let claims = match verify_and_decode_token(token) {
-        Ok(claims) => claims,
-        Err(_) => return HttpResponse::Unauthorized().body("Invalid token"),
+    limits: web::Data<ConcurrencyLimits>,
+) -> Result<HttpResponse, ApiError> {
+    let Some(_permit) = limits.profile_reads.acquire(limits.wait).await else {
+        return Err(busy_error());
     };
 
     // Parse the user_ids from the query string
@@ -455,8 +1013,22 @@ async fn get_profiles(
         .filter_map(|id| Uuid::parse_str(id).ok())
         .collect();
 
-    // Execute the query based on the authenticated user's scope
-    let rows = if claims.get_scope() == "provider" {
+    match query.fields.as_deref() {
+        None => {},
+        Some("basic") => {
+            let viewer = match user.scope.as_str() {
+                "provider" | "service" => None,
+                _ => Some(user.user_id),
+            };
+            let profiles = fetch_basic_profiles(&pool, &user_ids, viewer).await?;
+            return Ok(HttpResponse::Ok().json(profiles));
+        },
+        Some(_) => return Err(ApiError::Validation("fields must be \"basic\" or left out".to_string())),
+    }
+
+    // Execute the query based on the authenticated user's scope. Service accounts
+    // read as the provider they belong to.
+    let rows = if matches!(user.scope.as_str(), "provider" | "service") {
         sqlx::query_as!(
             UserWithPet,
             r#"
@@ -499,85 +1071,182 @@ async fn get_profiles(
             WHERE (u.id = ANY($1) AND (u.scope = 'provider' OR u.id = $2))
             "#,
             &user_ids,
-            Uuid::parse_str(claims.get_sub()).unwrap()
+            user.user_id
         )
         .fetch_all(&**pool)
         .await
     };
 
-    match rows {
-        Ok(rows) => {
-            // Group rows by user and create UserProfile objects
-            let mut user_profiles: HashMap<Uuid, crate::models::UserProfile> = HashMap::new();
-            
-            for row in rows {
-                let user_id = row.id.unwrap();
-                
-                // Get or create user profile
-                let user_profile = user_profiles.entry(user_id).or_insert_with(|| crate::models::UserProfile {
-                    id: user_id,
-                    phone_number: row.phone_number.unwrap(),
-                    public_key: row.public_key.unwrap(),
-                    scope: row.scope.unwrap(),
-                    first_name: row.first_name,
-                    last_name: row.last_name,
-                    email: row.email,
-                    address: row.address,
-                    profile_image_url: row.profile_image_url,
-                    verified: row.verified.unwrap(),
-                    created_at: row.created_at.unwrap(),
-                    updated_at: row.updated_at.unwrap(),
-                    pets: Vec::new(),
-                });
-                
-                // Add pet if it exists
-                if let Some(pet_id) = row.pet_id {
-                    let pet = crate::models::Pet {
-                        id: pet_id,
-                        user_id: row.pet_user_id.unwrap(),
-                        name: row.pet_name.unwrap(),
-                        breed: row.pet_breed.unwrap(),
-                        sex: row.pet_sex.unwrap(),
-                        birthday: row.pet_birthday,
-                        pet_image_url: row.pet_image_url,
-                        color: row.pet_color,
-                        species: row.pet_species.unwrap_or_else(|| "dog".to_string()),
-                        spayed_neutered: row.pet_spayed_neutered.unwrap_or(false),
-                        weight: row.pet_weight.unwrap_or(0),
-                    };
-                    user_profile.pets.push(pet);
-                }
-            }
-            
-            // Convert HashMap values to Vec and return
-            let profiles: Vec<crate::models::UserProfile> = user_profiles.into_values().collect();
-            HttpResponse::Ok().json(profiles)
-        },
-        Err(e) => HttpResponse::InternalServerError().body(format!("Database error: {}", e)),
+    // Providers show the clinics they answer for
+    let mut memberships = OrganizationService::memberships(&pool, &user_ids).await?;
+
+    let rows = rows?;
+    // Group rows by user and create UserProfile objects
+    let mut user_profiles: HashMap<Uuid, crate::models::UserProfile> = HashMap::new();
+    
+    for row in rows {
+        let user_id = row.id.unwrap();
+        
+        // Get or create user profile
+        let user_profile = user_profiles.entry(user_id).or_insert_with(|| crate::models::UserProfile {
+            id: user_id,
+            phone_number: row.phone_number.unwrap(),
+            public_key: row.public_key.unwrap(),
+            scope: row.scope.unwrap(),
+            first_name: row.first_name,
+            last_name: row.last_name,
+            email: row.email,
+            address: row.address,
+            profile_image_url: row.profile_image_url,
+            verified: row.verified.unwrap(),
+            created_at: row.created_at.unwrap(),
+            updated_at: row.updated_at.unwrap(),
+            pets: Vec::new(),
+            organizations: memberships.remove(&user_id).unwrap_or_default(),
+        });
+        
+        // Add pet if it exists
+        if let Some(pet_id) = row.pet_id {
+            let pet = crate::models::Pet {
+                id: pet_id,
+                user_id: row.pet_user_id.unwrap(),
+                name: row.pet_name.unwrap(),
+                breed: row.pet_breed.unwrap(),
+                sex: row.pet_sex.unwrap(),
+                birthday: row.pet_birthday,
+                pet_image_url: row.pet_image_url,
+                color: row.pet_color,
+                species: row.pet_species.unwrap_or_else(|| "dog".to_string()),
+                spayed_neutered: row.pet_spayed_neutered.unwrap_or(false),
+                weight: row.pet_weight.unwrap_or(0),
+            };
+            user_profile.pets.push(pet);
+        }
     }
+    
+    // Convert HashMap values to Vec and return
+    let profiles: Vec<crate::models::UserProfile> = user_profiles.into_values().collect();
+    Ok(HttpResponse::Ok().json(profiles))
+}
+
+// Just enough to show a user in a list: no contact details, keys or pets. Like
+// the full profiles, a client (`viewer`) only sees providers and themselves.
+async fn fetch_basic_profiles(pool: &sqlx::PgPool, user_ids: &[Uuid], viewer: Option<Uuid>) -> Result<Vec<ParticipantSummary>, sqlx::Error> {
+    sqlx::query_as!(
+        ParticipantSummary,
+        "
+        SELECT id, scope, first_name, last_name, profile_image_url
+        FROM users
+        WHERE id = ANY($1) AND ($2::UUID IS NULL OR scope = 'provider' OR id = $2)
+        ",
+        user_ids,
+        viewer
+    )
+    .fetch_all(pool)
+    .await
 }
 
 #[post("/profile")]
 async fn update_profile(
     req: HttpRequest,
+    user: AuthenticatedUser,
     data: web::Json<UpdateProfileData>,
     pool: web::Data<sqlx::PgPool>,
-) -> impl Responder {
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ApiError> {
     // Extract the user_id from the token
-    let user_id = match extract_user_id_from_token(&req) {
-        Ok(id) => id,
-        Err(e) => return HttpResponse::Unauthorized().body(e.to_string()),
-    };
+    let user_id = user.user_id;
 
-    // Start a transaction
-    let mut tx = match pool.begin().await {
-        Ok(tx) => tx,
-        Err(e) => return HttpResponse::InternalServerError().body(format!("Failed to start transaction: {}", e)),
-    };
+    with_idempotency(&req, &pool, &config, user_id, apply_profile_update(user_id, data.into_inner(), &pool)).await
+}
 
-    // Update user profile fields
-    if let Err(e) = sqlx::query!(
-        "UPDATE users SET 
+// Checks a pet from the profile update against the table's limits, so a bad
+// entry fails on its own with a reason rather than as a database error.
+fn pet_data_error(pet_data: &PetData) -> Option<&'static str> {
+    if pet_data.id.is_none() && pet_data.birthday.is_none() {
+        return Some("New pets need a birthday");
+    }
+    let too_long = |value: &Option<String>, max: usize| value.as_ref().is_some_and(|value| value.chars().count() > max);
+    if too_long(&pet_data.name, 50) || too_long(&pet_data.breed, 50) {
+        return Some("Name and breed must be at most 50 characters");
+    }
+    if too_long(&pet_data.sex, 10) {
+        return Some("Sex must be at most 10 characters");
+    }
+    None
+}
+
+// Updates the pet if `pet_data` has an id, otherwise creates one. None if the
+// id isn't one of the user's pets.
+async fn upsert_pet(conn: &mut sqlx::PgConnection, user_id: Uuid, pet_data: &PetData) -> Result<Option<Pet>, sqlx::Error> {
+    if let Some(pet_id) = pet_data.id {
+        sqlx::query_as!(
+            Pet,
+            r#"
+            UPDATE pets
+            SET 
+                name = COALESCE($1, name),
+                breed = COALESCE($2, breed),
+                sex = COALESCE($3, sex),
+                birthday = COALESCE($4, birthday),
+                pet_image_url = COALESCE($5, pet_image_url),
+                color = COALESCE($6, color),
+                species = COALESCE($7, species),
+                spayed_neutered = COALESCE($8, spayed_neutered),
+                weight = COALESCE($9, weight),
+                updated_at = CURRENT_TIMESTAMP
+            WHERE id = $10 AND user_id = $11
+            RETURNING id, user_id, name, breed, sex, birthday, pet_image_url, color, species, spayed_neutered, weight
+            "#,
+            pet_data.name,
+            pet_data.breed,
+            pet_data.sex,
+            pet_data.birthday,
+            pet_data.pet_image_url,
+            pet_data.color,
+            pet_data.species,
+            pet_data.spayed_neutered,
+            pet_data.weight,
+            pet_id,
+            user_id
+        )
+        .fetch_optional(conn)
+        .await
+    } else {
+        sqlx::query_as!(
+            Pet,
+            r#"
+            INSERT INTO pets (user_id, name, breed, sex, birthday, pet_image_url, color, species, spayed_neutered, weight)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            RETURNING id, user_id, name, breed, sex, birthday, pet_image_url, color, species, spayed_neutered, weight
+            "#,
+            user_id,
+            pet_data.name.clone().unwrap_or_else(|| "".to_string()),
+            pet_data.breed.clone().unwrap_or_else(|| "".to_string()),
+            pet_data.sex.clone().unwrap_or_else(|| "".to_string()),
+            pet_data.birthday,
+            pet_data.pet_image_url,
+            pet_data.color,
+            pet_data.species,
+            pet_data.spayed_neutered,
+            pet_data.weight
+        )
+        .fetch_one(conn)
+        .await
+        .map(Some)
+    }
+}
+
+// The user's own fields are saved even if some pets fail. Each pet is saved
+// under its own savepoint, so one that fails is reported in `results` without
+// undoing the others.
+async fn apply_profile_update(user_id: Uuid, data: UpdateProfileData, pool: &sqlx::PgPool) -> Result<HttpResponse, ApiError> {
+    // Start a transaction
+    let mut tx = pool.begin().await?;
+
+    // Update user profile fields
+    sqlx::query!(
+        "UPDATE users SET 
             first_name = COALESCE($1, first_name), 
             last_name = COALESCE($2, last_name), 
             email = COALESCE($3, email), 
@@ -593,118 +1262,70 @@ async fn update_profile(
         user_id
     )
     .execute(&mut *tx)
-    .await {
-        let _ = tx.rollback().await;
-        return HttpResponse::InternalServerError().body(format!("Failed to update user: {}", e));
-    }
+    .await?;
 
     // Handle pets
-    let mut updated_pets = Vec::new();
-    
-    for pet_data in &data.pets {
-        let pet_result = if let Some(pet_id) = pet_data.id {
-            // Update existing pet  
-            let updated_pet = match sqlx::query_as!(
-                Pet,
-                r#"
-                UPDATE pets
-                SET 
-                    name = COALESCE($1, name),
-                    breed = COALESCE($2, breed),
-                    sex = COALESCE($3, sex),
-                    birthday = COALESCE($4, birthday),
-                    pet_image_url = COALESCE($5, pet_image_url),
-                    color = COALESCE($6, color),
-                    species = COALESCE($7, species),
-                    spayed_neutered = COALESCE($8, spayed_neutered),
-                    weight = COALESCE($9, weight),
-                    updated_at = CURRENT_TIMESTAMP
-                WHERE id = $10 AND user_id = $11
-                RETURNING id, user_id, name, breed, sex, birthday, pet_image_url, color, species, spayed_neutered, weight
-                "#,
-                pet_data.name,
-                pet_data.breed,
-                pet_data.sex,
-                pet_data.birthday,
-                pet_data.pet_image_url,
-                pet_data.color,
-                pet_data.species,
-                pet_data.spayed_neutered,
-                pet_data.weight,
-                pet_id,
-                user_id
-            )
-            .fetch_optional(&mut *tx)
-            .await {
-                Ok(pet) => pet,
-                Err(e) => {
-                    let _ = tx.rollback().await;
-                    return HttpResponse::InternalServerError().body(format!("Failed to update pet: {}", e));
-                }
-            };
-            
-            // Convert Option<Pet> to Result<Pet, Error>
-            match updated_pet {
-                Some(pet) => Ok(pet),
-                None => Err(sqlx::Error::RowNotFound)
-            }
-        } else {
-            // Create new pet
-            sqlx::query_as!(
-                Pet,
-                r#"
-                INSERT INTO pets (user_id, name, breed, sex, birthday, pet_image_url, color, species, spayed_neutered, weight)
-                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
-                RETURNING id, user_id, name, breed, sex, birthday, pet_image_url, color, species, spayed_neutered, weight
-                "#,
-                user_id,
-                pet_data.name.clone().unwrap_or_else(|| "".to_string()),
-                pet_data.breed.clone().unwrap_or_else(|| "".to_string()),
-                pet_data.sex.clone().unwrap_or_else(|| "".to_string()),
-                pet_data.birthday,
-                pet_data.pet_image_url,
-                pet_data.color,
-                pet_data.species,
-                pet_data.spayed_neutered,
-                pet_data.weight
-            )
-            .fetch_one(&mut *tx)
-            .await
-        };
+    let mut results = Vec::new();
+    for (index, pet_data) in data.pets.iter().enumerate() {
+        let failed = |error| PetUpdateResult { index, id: pet_data.id, status: "failed", pet: None, error: Some(error) };
+        if let Some(error) = pet_data_error(pet_data) {
+            results.push(failed(error));
+            continue;
+        }
 
-        match pet_result {
-            Ok(pet) => {
-                updated_pets.push(pet);
-            }
+        let mut savepoint = sqlx::Connection::begin(&mut *tx).await?;
+        match upsert_pet(&mut savepoint, user_id, pet_data).await {
+            Ok(Some(pet)) => {
+                savepoint.commit().await?;
+                let status = if pet_data.id.is_some() { "updated" } else { "created" };
+                results.push(PetUpdateResult { index, id: Some(pet.id), status, pet: Some(pet), error: None });
+            },
+            Ok(None) => {
+                let _ = savepoint.rollback().await;
+                results.push(failed("Pet not found"));
+            },
+            Err(e) if is_connection_error(&e) => return Err(e.into()),
             Err(e) => {
-                let _ = tx.rollback().await;
-                return HttpResponse::InternalServerError().body(format!("Failed to update pet: {}", e));
-            }
+                logln!("Failed to save pet {} for user {}: {}", index, user_id, e);
+                let _ = savepoint.rollback().await;
+                results.push(failed("Pet could not be saved"));
+            },
         }
     }
 
     // Commit the transaction
-    if let Err(e) = tx.commit().await {
-        return HttpResponse::InternalServerError().body(format!("Failed to commit transaction: {}", e));
+    tx.commit().await?;
+
+    let updated_pets: Vec<Pet> = results.iter().filter_map(|result| result.pet.clone()).collect();
+    if updated_pets.len() < results.len() {
+        return Ok(HttpResponse::build(StatusCode::MULTI_STATUS).json(json!({
+            "message": "Profile updated, but some pets could not be saved",
+            "pets": updated_pets,
+            "results": results
+        })));
     }
 
     // Return success response with updated pets
-    HttpResponse::Ok().json(json!({
+    Ok(HttpResponse::Ok().json(json!({
         "message": "Profile updated successfully",
-        "pets": updated_pets
-    }))
+        "pets": updated_pets,
+        "results": results
+    })))
 }
 
 #[post("/delete-account")]
 async fn delete_account(
+    req: HttpRequest,
     signed_data: web::Json<SignedData<DeleteUserData>>,
-    pool: web::Data<sqlx::PgPool>
-) -> impl Responder {
-    println!("Delete account endpoint hit!");
+    pool: web::Data<sqlx::PgPool>,
+    tracker: web::Data<SignatureFailureTracker>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    logln!("Delete account endpoint hit!");
 
     // Check timestamp
     if !is_timestamp_valid(&signed_data.data.timestamp) {
-        return HttpResponse::BadRequest().body("Invalid timestamp");
+        return Err(ApiError::Validation("Invalid timestamp".to_string()));
     }
 
     // Look up the user's public key by user_id
@@ -713,99 +1334,99 @@ async fn delete_account(
         &signed_data.data.user_id
     )
     .fetch_optional(&**pool)
-    .await {
-        Ok(Some(record)) => record,
-        Ok(None) => return HttpResponse::NotFound().body(format!("User not found for id: {}", &signed_data.data.user_id)),
-        Err(e) => return HttpResponse::InternalServerError().body(format!("Database error: {}", e)),
+    .await? {
+        Some(record) => record,
+        None => return Err(ApiError::NotFound(format!("User not found for id: {}", &signed_data.data.user_id))),
     };
 
     // Verify signature
-    if let Err(e) = verify_signature(
-        &signed_data.data,
-        &signed_data.signature,
-        &user_data.public_key
-    ) {
-        println!("Signature verification failed: {}", e);
-        return HttpResponse::BadRequest().body("Invalid signature");
-    }
+    check_signature(&req, &pool, &tracker, &config, signed_data.data.user_id, &signed_data, &user_data.public_key).await?;
 
     // Start a transaction to ensure all deletions succeed or fail together
-    let mut tx = match pool.begin().await {
-        Ok(tx) => tx,
-        Err(e) => return HttpResponse::InternalServerError().body(format!("Failed to start transaction: {}", e)),
-    };
+    let mut tx = pool.begin().await?;
 
     // Delete refresh tokens
-    if let Err(e) = sqlx::query!(
+    sqlx::query!(
         "DELETE FROM refresh_tokens WHERE user_id = $1",
         &signed_data.data.user_id
     )
     .execute(&mut *tx)
-    .await {
-        let _ = tx.rollback().await;
-        return HttpResponse::InternalServerError().body(format!("Failed to delete refresh tokens: {}", e));
-    }
+    .await?;
 
     // Delete pets
-    if let Err(e) = sqlx::query!(
+    sqlx::query!(
         "DELETE FROM pets WHERE user_id = $1",
         &signed_data.data.user_id
     )
     .execute(&mut *tx)
-    .await {
-        let _ = tx.rollback().await;
-        return HttpResponse::InternalServerError().body(format!("Failed to delete pets: {}", e));
-    }
+    .await?;
+
+    // Their messages go with them, so take them out of the conversations' counts
+    ConversationService::discount_messages_from(&mut tx, signed_data.data.user_id).await?;
 
     // Finally, delete the user
-    if let Err(e) = sqlx::query!(
+    sqlx::query!(
         "DELETE FROM users WHERE id = $1",
         &signed_data.data.user_id
     )
     .execute(&mut *tx)
-    .await {
-        let _ = tx.rollback().await;
-        return HttpResponse::InternalServerError().body(format!("Failed to delete user: {}", e));
-    }
+    .await?;
 
     // Commit the transaction
-    if let Err(e) = tx.commit().await {
-        return HttpResponse::InternalServerError().body(format!("Failed to commit transaction: {}", e));
-    }
+    tx.commit().await?;
+
+    AuditService::record(&pool, "delete_account", Some(signed_data.data.user_id), Some(signed_data.data.user_id), json!({})).await;
 
-    HttpResponse::Ok().json(json!({
+    Ok(HttpResponse::Ok().json(json!({
         "message": "Account and all personal data successfully deleted. Conversation history has been preserved."
-    }))
+    })))
 }
 
 #[post("/upload-image")]
+#[allow(clippy::too_many_arguments)]
 async fn upload_image(
     req: HttpRequest,
-    mut payload: Multipart,
+    user: AuthenticatedUser,
+    payload: Multipart,
     query: web::Query<UploadImageQuery>,
-    pool: web::Data<sqlx::PgPool>
-) -> impl Responder {
-    println!("Upload image endpoint hit!");
+    pool: web::Data<sqlx::PgPool>,
+    config: web::Data<Config>,
+    scanner: web::Data<dyn Scanner>,
+    storage: web::Data<dyn ImageStorage>,
+    limits: web::Data<ConcurrencyLimits>,
+) -> Result<HttpResponse, ApiError> {
+    logln!("Upload image endpoint hit!");
 
-    // Extract the user_id from the token
-    let user_id = match extract_user_id_from_token(&req) {
-        Ok(id) => id,
-        Err(e) => {
-            println!("❌ Failed to extract user_id from token: {}", e);
-            return HttpResponse::Unauthorized().body(e.to_string());
-        }
+    let user_id = user.user_id;
+
+    // Held until the upload is stored, since the body is buffered in memory until then
+    let Some(_permit) = limits.uploads.acquire(limits.wait).await else {
+        return Err(busy_error());
     };
 
+    let upload = store_uploaded_image(user_id, payload, query.into_inner(), &pool, &config, scanner.get_ref(), storage.get_ref());
+    with_idempotency(&req, &pool, &config, user_id, upload).await
+}
+
+async fn store_uploaded_image(
+    user_id: Uuid,
+    mut payload: Multipart,
+    query: UploadImageQuery,
+    pool: &sqlx::PgPool,
+    config: &Config,
+    scanner: &dyn Scanner,
+    storage: &dyn ImageStorage,
+) -> Result<HttpResponse, ApiError> {
     // Validate image type
     let image_type = match &query.image_type {
-        Some(image_type) if ["profile", "pet"].contains(&image_type.to_lowercase().as_str()) => image_type.to_lowercase(),
+        Some(image_type) if ["profile", "pet", "attachment", "document"].contains(&image_type.to_lowercase().as_str()) => image_type.to_lowercase(),
         Some(invalid_type) => {
-            println!("❌ Invalid image_type provided: {}", invalid_type);
-            return HttpResponse::BadRequest().body("Invalid image_type. Must be 'profile' or 'pet'");
+            logln!("❌ Invalid image_type provided: {}", invalid_type);
+            return Err(ApiError::Validation("Invalid image_type. Must be 'profile', 'pet', 'attachment' or 'document'".to_string()));
         },
         None => {
-            println!("❌ Missing image_type parameter");
-            return HttpResponse::BadRequest().body("Missing image_type parameter");
+            logln!("❌ Missing image_type parameter");
+            return Err(ApiError::Validation("Missing image_type parameter".to_string()));
         }
     };
 
@@ -821,7 +1442,7 @@ async fn upload_image(
         let content_disposition = match field.content_disposition() {
             Some(cd) => cd,
             None => {
-                eprintln!("⚠️ Field without content disposition, skipping...");
+                elogln!("⚠️ Field without content disposition, skipping...");
                 continue;
             }
         };
@@ -834,14 +1455,15 @@ async fn upload_image(
                     
                     // Get the content type
                     if let Some(ct) = field.content_type() {
-                        if ct.type_() == mime::IMAGE {
+                        // Documents may also be PDFs
+                        if ct.type_() == mime::IMAGE || (image_type == "document" && *ct == mime::APPLICATION_PDF) {
                             content_type = Some(ct.to_string());
                         } else {
-                            eprintln!("❌ Content type is not an image: {}", ct);
-                            return HttpResponse::BadRequest().body("File must be an image");
+                            elogln!("❌ Content type is not an image: {}", ct);
+                            return Err(ApiError::Validation("File must be an image".to_string()));
                         }
                     } else {
-                        eprintln!("⚠️ No content type found in field, will infer from extension");
+                        elogln!("⚠️ No content type found in field, will infer from extension");
                     }
                     
                     // Read the file data
@@ -850,37 +1472,67 @@ async fn upload_image(
                         match chunk {
                             Ok(bytes) => data.extend_from_slice(&bytes),
                             Err(e) => {
-                                eprintln!("❌ Error reading file chunk: {}", e);
-                                return HttpResponse::InternalServerError().body(format!("Error reading file: {}", e));
+                                elogln!("❌ Error reading file chunk: {}", e);
+                                return Err(ApiError::Validation("Could not read the uploaded file".to_string()));
                             }
                         }
                     }
                     
                     image_data = Some(data);
                 } else {
-                    eprintln!("❌ No filename found in content disposition");
-                    return HttpResponse::BadRequest().body("No filename provided");
+                    elogln!("❌ No filename found in content disposition");
+                    return Err(ApiError::Validation("No filename provided".to_string()));
                 }
             } else {
-                eprintln!("⚠️ Skipping non-file field: {}", name);
+                elogln!("⚠️ Skipping non-file field: {}", name);
             }
         } else {
-            eprintln!("⚠️ Field without name, skipping...");
+            elogln!("⚠️ Field without name, skipping...");
         }
     }
 
     // Check if we have the image data
     let image_bytes = match image_data {
         Some(data) => {
-            println!("✅ Image data received: {} bytes", data.len());
+            logln!("✅ Image data received: {} bytes", data.len());
             
             data
         },
         None => {
-            eprintln!("❌ No image file provided in multipart data");
-            return HttpResponse::BadRequest().body("No image file provided");
+            elogln!("❌ No image file provided in multipart data");
+            return Err(ApiError::Validation("No image file provided".to_string()));
         }
     };
+
+    // Reject uploads that would take the user over their storage quota
+    let quota = StorageQuotaService::quota_for_user(pool, user_id, config.image_storage_quota_bytes).await?;
+    if image_bytes.len() as i64 > quota.remaining_bytes {
+        logln!("❌ Upload of {} bytes exceeds the remaining quota of user {}", image_bytes.len(), user_id);
+        return Err(ApiError::Rejected {
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            code: "storage_quota_exceeded",
+            message: "Storage quota exceeded".to_string(),
+            details: Some(json!({ "quota": quota })),
+        });
+    }
+
+    // Nothing is stored, or queued for storage, until it has been scanned
+    match scanner.scan(&image_bytes).await {
+        Ok(ScanVerdict::Clean) => {},
+        Ok(ScanVerdict::Infected(signature)) => {
+            logln!("❌ Upload from user {} rejected: {} detected", user_id, signature);
+            AuditService::record(pool, "upload_infected", Some(user_id), Some(user_id), json!({
+                "signature": signature,
+                "filename": filename,
+                "size_bytes": image_bytes.len()
+            })).await;
+            return Err(ApiError::rejected(StatusCode::UNPROCESSABLE_ENTITY, "upload_infected", "File rejected by malware scan"));
+        },
+        Err(e) => {
+            logln!("❌ Failed to scan upload from user {}: {}", user_id, e);
+            return Err(ApiError::Unavailable { message: "File could not be scanned. Try again later.", retry_after: 5 });
+        }
+    }
     
     // Get file extension for content type detection
     let file_ext = match filename.as_ref().and_then(|name| {
@@ -888,38 +1540,18 @@ async fn upload_image(
     }) {
         Some(ext) => ext,
         None => {
-            eprintln!("⚠️ No file extension found, defaulting to jpg");
+            elogln!("⚠️ No file extension found, defaulting to jpg");
             "jpg".to_string()
         }
     };
 
-    // Create the GCS client using proper authentication
-    let client_config = match ClientConfig::default().with_auth().await {
-        Ok(config) => config,
-        Err(e) => {
-            println!("❌ Error setting up GCS authentication: {}", e);
-            return HttpResponse::InternalServerError().body(format!("Failed to initialize GCS client: {}", e));
-        }
-    };
-
-    let client = GcsClient::new(client_config);
-    
-    // Get bucket name from env
-    let bucket_name = match std::env::var("GCS_BUCKET_NAME") {
-        Ok(name) => name,
-        Err(_) => {
-            println!("❌ GCS_BUCKET_NAME not set in environment");
-            return HttpResponse::InternalServerError().body("GCS_BUCKET_NAME not set in environment");
-        }
-    };
-    
     // Generate a unique object name
     let object_name = format!("{}/{}.{}", image_type, Uuid::new_v4(), file_ext);
 
     // Determine the content type
     let content_type_str = match &content_type {
         Some(ct) => {
-            println!("✅ Using content type from field: {}", ct);
+            logln!("✅ Using content type from field: {}", ct);
             ct.clone()
         },
         None => {
@@ -927,101 +1559,192 @@ async fn upload_image(
                 "jpg" | "jpeg" => "image/jpeg".to_string(),
                 "png" => "image/png".to_string(),
                 "gif" => "image/gif".to_string(),
+                "pdf" if image_type == "document" => "application/pdf".to_string(),
                 _ => "application/octet-stream".to_string(),
             };
-            println!("✅ Inferred content type: {}", inferred_type);
+            logln!("✅ Inferred content type: {}", inferred_type);
             inferred_type
         }
     };
 
-    // Update the upload call to use the correct API
-    let upload_request = UploadObjectRequest {
-        bucket: bucket_name.clone(),
-        ..Default::default()
-    };
-    // Media with object name and content type
-    let media = Media {
-        name: Cow::Owned(object_name.clone()),
-        content_type: Cow::Owned(content_type_str.clone()),
-        content_length: Some(image_bytes.len() as u64),
-    };
-    let upload_type = UploadType::Simple(media);
-    let upload_result = client
-        .upload_object(&upload_request, image_bytes.clone(), &upload_type)
-        .await;
-    match &upload_result {
-        Ok(_) => (),
-        Err(e) => {
-            println!("❌ Upload failed: {:?}", e);
-            
-            let error_string = format!("{:?}", e);
-            if error_string.contains("status code: 403") {
-                println!("❌ This is a permissions error (403 Forbidden)");
-            } else if error_string.contains("status code: 404") {
-                println!("❌ This is a not found error (404 Not Found) - check bucket name");
-            }
-            
-            // Check bucket name case sensitivity
-            println!("❌ Using bucket name: '{}' (check case sensitivity)", bucket_name);
-            println!("❌ Object path: '{}'", object_name);
-        }
-    }
-    let image_url = match upload_result {
-        Ok(_) => {
-            // Generate a public URL for the uploaded image
-            let url = format!(
-                "https://storage.googleapis.com/{}/{}",
-                bucket_name,
-                object_name
-            );
-            println!("Image uploaded to: {}", url);
+    let image_url = match storage.upload(&object_name, &content_type_str, image_bytes.clone()).await {
+        Ok(url) => {
+            logln!("Image uploaded to: {}", url);
             url
         },
-        Err(e) => {
-            eprintln!("❌ Failed to upload image to GCS: {}", e);
-            return HttpResponse::InternalServerError().body(format!("Failed to upload image to GCS: {}", e));
-        }
+        Err(e) if config.upload_fallback => {
+            // Keep the image and let the worker finish the upload
+            elogln!("❌ {}; queueing image {} for retry", e, image_id);
+            let pending = NewPendingUpload {
+                image_id,
+                user_id,
+                filename,
+                content_type: Some(content_type_str),
+                image_type,
+                object_name,
+                data: image_bytes,
+            };
+            PendingUploadService::enqueue(pool, pending).await?;
+            return Ok(HttpResponse::Accepted().json(json!({
+                "message": "Image upload pending",
+                "image_id": image_id,
+                "status": "pending"
+            })));
+        },
+        Err(e) => return Err(ApiError::External(e.to_string())),
     };
     let result = sqlx::query!(
-        "INSERT INTO images (id, user_id, filename, content_type, image_type, image_url) 
-         VALUES ($1, $2, $3, $4, $5, $6)
+        "INSERT INTO images (id, user_id, filename, content_type, image_type, image_url, size_bytes) 
+         VALUES ($1, $2, $3, $4, $5, $6, $7)
          RETURNING id",
         image_id,
         user_id,
         filename,
         content_type,
         image_type,
-        image_url
+        image_url,
+        image_bytes.len() as i64
     )
-    .fetch_one(&**pool)
+    .fetch_one(pool)
     .await;
-    match result {
-        Ok(_) => {
-            HttpResponse::Ok().json(json!({
-                "message": "Image uploaded successfully",
-                "image_id": image_id,
-                "image_url": image_url
-            }))
+    if let Err(e) = result {
+        logln!("❌ Failed to store image metadata in database: {}", e);
+        logln!("=== IMAGE UPLOAD FAILED ===");
+        return Err(e.into());
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Image uploaded successfully",
+        "image_id": image_id,
+        "image_url": image_url
+    })))
+}
+
+
+// Issues a signed URL the client PUTs the file to, so the bytes go straight to
+// storage instead of through this server. The image exists once the client
+// calls /upload-complete.
+#[post("/upload-url")]
+async fn create_upload_url(
+    user: AuthenticatedUser,
+    data: web::Json<UploadUrlData>,
+    pool: web::Data<sqlx::PgPool>,
+    config: web::Data<Config>,
+    storage: web::Data<dyn ImageStorage>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+    let data = data.into_inner();
+    let ttl_secs = config.direct_upload_url_ttl_secs;
+
+    let upload = DirectUploadService::create(
+        &pool, user_id, &data.image_type, &data.content_type, data.filename, chrono::Duration::seconds(ttl_secs as i64)
+    ).await?;
+    match storage.signed_upload_url(&upload.object_name, &upload.content_type, std::time::Duration::from_secs(ttl_secs)).await {
+        Ok(upload_url) => Ok(HttpResponse::Ok().json(json!({
+            "image_id": upload.id,
+            "upload_url": upload_url,
+            "object_name": upload.object_name,
+            "method": "PUT",
+            "headers": { "Content-Type": upload.content_type },
+            "expires_at": upload.expires_at.timestamp_millis()
+        }))),
+        Err(e) => {
+            elogln!("❌ {}", e);
+            let _ = DirectUploadService::discard(&pool, upload.id).await;
+            Err(ApiError::Unavailable { message: "Could not issue an upload URL. Try again later.", retry_after: 5 })
+        }
+    }
+}
+
+// Removes an uploaded object that won't become an image, and its pending row
+async fn reject_direct_upload(pool: &sqlx::PgPool, storage: &dyn ImageStorage, upload: &DirectUpload, image_url: &str) {
+    if let Err(e) = storage.delete(image_url).await {
+        elogln!("❌ Failed to delete rejected upload {}: {}", upload.object_name, e);
+    }
+    if let Err(e) = DirectUploadService::discard(pool, upload.id).await {
+        elogln!("❌ Failed to discard rejected upload {}: {}", upload.id, e);
+    }
+}
+
+// Turns a file the client uploaded through a signed URL into an image, once it
+// passes the same quota and malware checks as /upload-image.
+#[post("/upload-complete")]
+#[allow(clippy::too_many_arguments)]
+async fn complete_upload(
+    user: AuthenticatedUser,
+    data: web::Json<UploadCompleteData>,
+    pool: web::Data<sqlx::PgPool>,
+    config: web::Data<Config>,
+    scanner: web::Data<dyn Scanner>,
+    storage: web::Data<dyn ImageStorage>,
+    limits: web::Data<ConcurrencyLimits>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+    let upload = DirectUploadService::find(&pool, user_id, data.image_id).await?;
+
+    let object = match storage.find(&upload.object_name).await {
+        Ok(Some(object)) => object,
+        Ok(None) => return Err(ApiError::Conflict("Nothing has been uploaded to the URL yet".to_string())),
+        Err(e) => {
+            elogln!("❌ {}", e);
+            return Err(ApiError::Unavailable { message: "Could not check the upload. Try again later.", retry_after: 5 });
+        }
+    };
+
+    let quota = StorageQuotaService::quota_for_user(&pool, user_id, config.image_storage_quota_bytes).await?;
+    if object.size_bytes as i64 > quota.remaining_bytes {
+        logln!("❌ Upload of {} bytes exceeds the remaining quota of user {}", object.size_bytes, user_id);
+        reject_direct_upload(&pool, storage.get_ref(), &upload, &object.url).await;
+        return Err(ApiError::Rejected {
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            code: "storage_quota_exceeded",
+            message: "Storage quota exceeded".to_string(),
+            details: Some(json!({ "quota": quota })),
+        });
+    }
+
+    // Held while the file is in memory for scanning
+    let Some(_permit) = limits.uploads.acquire(limits.wait).await else {
+        return Err(busy_error());
+    };
+    let verdict = match storage.download(&object.url).await {
+        Ok(bytes) => scanner.scan(&bytes).await,
+        Err(e) => Err(e),
+    };
+    match verdict {
+        Ok(ScanVerdict::Clean) => {},
+        Ok(ScanVerdict::Infected(signature)) => {
+            logln!("❌ Upload from user {} rejected: {} detected", user_id, signature);
+            reject_direct_upload(&pool, storage.get_ref(), &upload, &object.url).await;
+            AuditService::record(&pool, "upload_infected", Some(user_id), Some(user_id), json!({
+                "signature": signature,
+                "filename": upload.filename,
+                "size_bytes": object.size_bytes
+            })).await;
+            return Err(ApiError::rejected(StatusCode::UNPROCESSABLE_ENTITY, "upload_infected", "File rejected by malware scan"));
         },
         Err(e) => {
-            println!("❌ Failed to store image metadata in database: {}", e);
-            println!("=== IMAGE UPLOAD FAILED ===");
-            HttpResponse::InternalServerError().body(format!("Failed to store image metadata: {}", e))
+            logln!("❌ Failed to scan upload from user {}: {}", user_id, e);
+            return Err(ApiError::Unavailable { message: "File could not be scanned. Try again later.", retry_after: 5 });
         }
     }
+
+    DirectUploadService::complete(&pool, &upload, &object.url, object.size_bytes as i64).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Image uploaded successfully",
+        "image_id": upload.id,
+        "image_url": object.url
+    })))
 }
 
 #[get("/images")]
 async fn get_images(
-    req: HttpRequest,
+    user: AuthenticatedUser,
     query: web::Query<GetImagesQuery>,
     pool: web::Data<sqlx::PgPool>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     // Extract the user_id from the token
-    let user_id = match extract_user_id_from_token(&req) {
-        Ok(id) => id,
-        Err(e) => return HttpResponse::Unauthorized().body(e.to_string()),
-    };
+    let user_id = user.user_id;
 
     // Build the query based on whether image_type filter is provided
     let images = if let Some(image_type) = &query.image_type {
@@ -1049,44 +1772,128 @@ async fn get_images(
         .await
     };
 
-    match images {
-        Ok(images) => HttpResponse::Ok().json(images),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Failed to fetch images: {}", e)),
+    let images = images?;
+    Ok(HttpResponse::Ok().json(images))
+}
+
+#[get("/images/quota")]
+async fn get_image_quota(
+    user: AuthenticatedUser,
+    pool: web::Data<sqlx::PgPool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let quota = StorageQuotaService::quota_for_user(&pool, user_id, config.image_storage_quota_bytes).await?;
+    Ok(HttpResponse::Ok().json(quota))
+}
+
+#[get("/images/{id}/status")]
+async fn get_image_status(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    match PendingUploadService::status(&pool, user_id, path.into_inner()).await? {
+        Some(status) => Ok(HttpResponse::Ok().json(status)),
+        None => Err(ApiError::NotFound("Image not found".to_string())),
+    }
+}
+
+// Changes whenever the image is replaced, without reading it from storage
+fn image_etag(image: &models::Image) -> String {
+    format!("\"{}-{}\"", image.id.simple(), image.updated_at.timestamp_millis())
+}
+
+// Whether an If-None-Match header lists the tag (weakly, as GET comparisons are)
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.split(',').map(str::trim).any(|tag| {
+        tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag
+    })
+}
+
+#[get("/images/{id}/content")]
+async fn get_image_content(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    query: web::Query<ImageContentQuery>,
+    pool: web::Data<sqlx::PgPool>,
+    storage: web::Data<dyn ImageStorage>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let image = match ImageService::find_viewable(&pool, user_id, path.into_inner()).await? {
+        Some(image) => image,
+        None => return Err(ApiError::NotFound("Image not found".to_string())),
+    };
+
+    let etag = image_etag(&image);
+    let cache_control = format!("private, max-age={}", config.image_cache_max_age_secs);
+    let not_modified = req.headers().get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| etag_matches(value, &etag));
+    if not_modified {
+        return Ok(HttpResponse::NotModified()
+            .insert_header((header::ETAG, etag))
+            .insert_header((header::CACHE_CONTROL, cache_control))
+            .finish());
     }
+
+    let data = storage.download(&image.image_url).await
+        .map_err(|e| ApiError::External(format!("Failed to download image: {}", e)))?;
+
+    let disposition = header::ContentDisposition {
+        disposition: if query.download { header::DispositionType::Attachment } else { header::DispositionType::Inline },
+        parameters: vec![header::DispositionParam::Filename(
+            image.filename.clone().unwrap_or_else(|| image.id.to_string())
+        )],
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(image.content_type.as_deref().unwrap_or("application/octet-stream"))
+        .insert_header((header::ETAG, etag))
+        .insert_header((header::CACHE_CONTROL, cache_control))
+        .insert_header(disposition)
+        .body(data))
 }
 
 #[post("/pet")]
 async fn update_pet(
     req: HttpRequest,
+    user: AuthenticatedUser,
     data: web::Json<UpdatePetData>,
     pool: web::Data<sqlx::PgPool>,
-) -> impl Responder {
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ApiError> {
     // Extract the user_id from the token
-    let user_id = match extract_user_id_from_token(&req) {
-        Ok(id) => id,
-        Err(e) => return HttpResponse::Unauthorized().body(e.to_string()),
-    };
+    let user_id = user.user_id;
+
+    with_idempotency(&req, &pool, &config, user_id, save_pet(user_id, data.into_inner(), &pool)).await
+}
 
+async fn save_pet(user_id: Uuid, data: UpdatePetData, pool: &sqlx::PgPool) -> Result<HttpResponse, ApiError> {
     // Check if we're updating or creating a pet
     if let Some(pet_id) = data.id {
         // UPDATING: Verify the pet belongs to the user
-        let pet_exists = match sqlx::query!(
+        let pet_exists = sqlx::query!(
             "SELECT COUNT(*) as count FROM pets WHERE id = $1 AND user_id = $2",
             pet_id,
             user_id
         )
-        .fetch_one(&**pool)
-        .await {
-            Ok(result) => result.count.unwrap_or(0) > 0,
-            Err(e) => return HttpResponse::InternalServerError().body(format!("Database error: {}", e)),
-        };
+        .fetch_one(pool)
+        .await?
+        .count
+        .unwrap_or(0) > 0;
 
         if !pet_exists {
-            return HttpResponse::NotFound().body("Pet not found or does not belong to you");
+            return Err(ApiError::NotFound("Pet not found or does not belong to you".to_string()));
         }
 
         // Update the pet
-        match sqlx::query_as!(
+        let updated_pet = sqlx::query_as!(
             Pet,
             r#"
             UPDATE pets
@@ -1116,23 +1923,21 @@ async fn update_pet(
             pet_id,
             user_id
         )
-        .fetch_one(&**pool)
-        .await {
-            Ok(updated_pet) => HttpResponse::Ok().json(json!({
-                "message": "Pet updated successfully",
-                "pet": updated_pet
-            })),
-            Err(e) => HttpResponse::InternalServerError().body(format!("Failed to update pet: {}", e)),
-        }
+        .fetch_one(pool)
+        .await?;
+        Ok(HttpResponse::Ok().json(json!({
+            "message": "Pet updated successfully",
+            "pet": updated_pet
+        })))
     } else {
         // CREATING: Validate required fields for new pet
         if data.name.is_none() || data.breed.is_none() || data.sex.is_none() || data.birthday.is_none() || 
            data.species.is_none() || data.spayed_neutered.is_none() || data.weight.is_none() {
-            return HttpResponse::BadRequest().body("Name, breed, sex, birthday, species, spayed_neutered, and weight are required when creating a new pet");
+            return Err(ApiError::Validation("Name, breed, sex, birthday, species, spayed_neutered, and weight are required when creating a new pet".to_string()));
         }
 
         // Create a new pet
-        match sqlx::query_as!(
+        let new_pet = sqlx::query_as!(
             Pet,
             r#"
             INSERT INTO pets (user_id, name, breed, sex, birthday, pet_image_url, color, species, spayed_neutered, weight)
@@ -1150,28 +1955,23 @@ async fn update_pet(
             data.spayed_neutered.unwrap(),
             data.weight.unwrap()
         )
-        .fetch_one(&**pool)
-        .await {
-            Ok(new_pet) => HttpResponse::Created().json(json!({
-                "message": "Pet created successfully",
-                "pet": new_pet
-            })),
-            Err(e) => HttpResponse::InternalServerError().body(format!("Failed to create pet: {}", e)),
-        }
+        .fetch_one(pool)
+        .await?;
+        Ok(HttpResponse::Created().json(json!({
+            "message": "Pet created successfully",
+            "pet": new_pet
+        })))
     }
 }
 
 #[delete("/pet")]
 async fn delete_pet(
-    req: HttpRequest,
+    user: AuthenticatedUser,
     data: web::Json<DeletePetData>,
     pool: web::Data<sqlx::PgPool>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     // Extract the user_id from the token
-    let user_id = match extract_user_id_from_token(&req) {
-        Ok(id) => id,
-        Err(e) => return HttpResponse::Unauthorized().body(e.to_string()),
-    };
+    let user_id = user.user_id;
 
     // First verify the pet belongs to the user
     let _pet = match sqlx::query!(
@@ -1180,90 +1980,2246 @@ async fn delete_pet(
         user_id
     )
     .fetch_optional(&**pool)
-    .await {
-        Ok(Some(pet)) => pet,
-        Ok(None) => return HttpResponse::NotFound().body("Pet not found or does not belong to you"),
-        Err(e) => return HttpResponse::InternalServerError().body(format!("Database error: {}", e)),
+    .await? {
+        Some(pet) => pet,
+        None => return Err(ApiError::NotFound("Pet not found or does not belong to you".to_string())),
     };
 
     // Delete the pet
-    match sqlx::query!(
+    sqlx::query!(
         "DELETE FROM pets WHERE id = $1 AND user_id = $2",
         data.id,
         user_id
     )
     .execute(&**pool)
-    .await {
-        Ok(_) => HttpResponse::Ok().json(json!({
-            "message": "Pet deleted successfully",
-            "pet_id": data.id
-        })),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Failed to delete pet: {}", e)),
+    .await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Pet deleted successfully",
+        "pet_id": data.id
+    })))
+}
+
+#[get("/pets/{id}")]
+async fn get_pet(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    // Owners and users with an accepted share can view the pet
+    let pet_id = path.into_inner();
+    match PetShareService::get_pet_access(&pool, user_id, pet_id).await? {
+        Some(_) => {},
+        None => return Err(ApiError::NotFound("Pet not found".to_string())),
+    }
+
+    match sqlx::query_as!(
+        Pet,
+        "
+        SELECT id, user_id, name, breed, sex, birthday, pet_image_url, color, species, spayed_neutered, weight
+        FROM pets
+        WHERE id = $1
+        ",
+        pet_id
+    )
+    .fetch_optional(&**pool)
+    .await? {
+        Some(pet) => Ok(HttpResponse::Ok().json(pet)),
+        None => Err(ApiError::NotFound("Pet not found".to_string())),
     }
 }
 
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    dotenv::dotenv().ok();
+#[post("/pets/{id}/shares")]
+async fn share_pet(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    data: web::Json<SharePetData>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
 
-    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
-        .await
-        .expect("Failed to create pool");
+    let share = PetShareService::invite(&pool, user_id, path.into_inner(), &data.phone_number, &data.permissions).await?;
+    AuditService::record(&pool, "pet_share_invited", Some(user_id), Some(share.shared_with_user_id), json!({
+        "share_id": share.id,
+        "pet_id": share.pet_id,
+        "permissions": share.permissions
+    })).await;
+    Ok(HttpResponse::Created().json(json!({
+        "message": "Pet shared",
+        "share": share
+    })))
+}
 
-    // Start the WebSocket server actor
-    let ws_server = websockets::WsServer::new().start();
+#[get("/pet-shares")]
+async fn get_pet_shares(
+    user: AuthenticatedUser,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
 
-    // Get certificate and key file paths from environment variables
-    let cert_path = std::env::var("SSL_CERT_PATH").unwrap_or_else(|_| "cert.pem".to_string());
-    let key_path = std::env::var("SSL_KEY_PATH").unwrap_or_else(|_| "key.pem".to_string());
+    let shares = PetShareService::get_shares_for_user(&pool, user_id).await?;
+    Ok(HttpResponse::Ok().json(shares))
+}
 
-    // Verify certificate files exist
-    if !fs::metadata(&cert_path).is_ok() {
-        eprintln!("SSL certificate file not found: {}", cert_path);
-        eprintln!("Set SSL_CERT_PATH environment variable or place cert.pem in the current directory");
-        std::process::exit(1);
-    }
+#[post("/pet-shares/{id}/accept")]
+async fn accept_pet_share(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
 
-    if !fs::metadata(&key_path).is_ok() {
-        eprintln!("SSL private key file not found: {}", key_path);
-        eprintln!("Set SSL_KEY_PATH environment variable or place key.pem in the current directory");
-        std::process::exit(1);
-    }
+    let share = PetShareService::accept(&pool, user_id, path.into_inner()).await?;
+    AuditService::record(&pool, "pet_share_accepted", Some(user_id), Some(share.owner_id), json!({
+        "share_id": share.id,
+        "pet_id": share.pet_id
+    })).await;
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Share accepted",
+        "share": share
+    })))
+}
 
-    // Configure OpenSSL
-    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())
-        .expect("Failed to create SSL acceptor");
-    builder.set_private_key_file(&key_path, SslFiletype::PEM)
-        .expect("Failed to set private key file");
-    builder.set_certificate_chain_file(&cert_path)
-        .expect("Failed to set certificate chain file");
+#[delete("/pet-shares/{id}")]
+async fn revoke_pet_share(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
 
-    println!("Starting HTTPS server on port 443...");
+    let share = PetShareService::revoke(&pool, user_id, path.into_inner()).await?;
+    AuditService::record(&pool, "pet_share_revoked", Some(user_id), Some(share.shared_with_user_id), json!({
+        "share_id": share.id,
+        "pet_id": share.pet_id
+    })).await;
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Share revoked",
+        "share_id": share.id
+    })))
+}
 
-    HttpServer::new(move || {
-        App::new()
-            .app_data(web::Data::new(pool.clone()))
-            .app_data(web::Data::new(ws_server.clone()))
-            .service(register)
-            .service(request_verification_code)
-            .service(login)
-            .service(refresh)
-            .service(logout)
-            .service(get_profiles)
-            .service(update_profile)
-            .service(delete_account)
-            .service(upload_image)
-            .service(get_images)
-            .service(update_pet)
-            .service(delete_pet)
-            .service(websocket_route)
-    })
-    .bind_openssl(("0.0.0.0", 443), builder)?
-    .run()
-    .await
+
+#[post("/pets/{id}/documents")]
+async fn create_pet_document(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    data: web::Json<PetDocumentData>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let document = PetDocumentService::create(&pool, user_id, path.into_inner(), &data).await?;
+    Ok(HttpResponse::Created().json(document))
+}
+
+#[get("/pets/{id}/documents")]
+async fn get_pet_documents(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    query: web::Query<PetDocumentsQuery>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let documents = PetDocumentService::list(&pool, user_id, path.into_inner(), &query).await?;
+    Ok(HttpResponse::Ok().json(json!({ "documents": documents })))
 }
 
+#[delete("/pets/{id}/documents/{document_id}")]
+async fn delete_pet_document(
+    user: AuthenticatedUser,
+    path: web::Path<(Uuid, Uuid)>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let (pet_id, document_id) = path.into_inner();
+    PetDocumentService::delete(&pool, user_id, pet_id, document_id).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Document deleted",
+        "document_id": document_id
+    })))
+}
+
+
+
+// Notify everyone subscribed to the conversation about an appointment change. The
+// system message is included so clients can append it to the open chat.
+fn broadcast_appointment_update(
+    ws_server: &Addr<websockets::WsServer>,
+    event: &str,
+    appointment: &Appointment,
+    message: &models::Message,
+) {
+    ws_server.do_send(websockets::BroadcastToConversation {
+        conversation_id: appointment.conversation_id,
+        message: models::WsMessage {
+            sender_id: Uuid::nil(),
+            event: event.to_string(),
+            params: json!({
+                "appointment": appointment,
+                "message": message
+            }),
+        },
+        timing: None,
+    });
+}
+
+#[post("/appointments")]
+async fn propose_appointment(
+    user: AuthenticatedUser,
+    data: web::Json<ProposeAppointmentData>,
+    pool: web::Data<sqlx::PgPool>,
+    config: web::Data<Config>,
+    ws_server: web::Data<Addr<websockets::WsServer>>,
+) -> Result<HttpResponse, ApiError> {
+    if user.scope != "provider" {
+        return Err(ApiError::Forbidden("Only providers can propose appointments".to_string()));
+    }
+    let provider_id = user.user_id;
+
+    let data = data.into_inner();
+    let (appointment, message) = AppointmentService::propose(&pool, provider_id, data.conversation_id, data.starts_at, data.duration_minutes, data.notes).await?;
+    broadcast_appointment_update(&ws_server, "appointment_proposed", &appointment, &message);
+    // An offline client isn't subscribed, so hold the proposal until they connect
+    websockets::queue_if_offline(&ws_server, &pool, &config, appointment.client_id, models::WsMessage {
+        sender_id: Uuid::nil(),
+        event: "appointment_proposed".to_string(),
+        params: json!({
+            "appointment": appointment,
+            "message": message
+        }),
+    }).await;
+    Ok(HttpResponse::Created().json(json!({
+        "message": "Appointment proposed",
+        "appointment": appointment
+    })))
+}
+
+async fn transition_appointment(
+    user_id: Uuid,
+    appointment_id: Uuid,
+    new_status: &str,
+    pool: &sqlx::PgPool,
+    ws_server: &Addr<websockets::WsServer>,
+) -> Result<HttpResponse, ApiError> {
+    let (appointment, message) = AppointmentService::transition(pool, user_id, appointment_id, new_status).await?;
+    broadcast_appointment_update(ws_server, "appointment_updated", &appointment, &message);
+    Ok(HttpResponse::Ok().json(json!({
+        "message": format!("Appointment {}", new_status),
+        "appointment": appointment
+    })))
+}
+
+#[post("/appointments/{id}/confirm")]
+async fn confirm_appointment(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    pool: web::Data<sqlx::PgPool>,
+    ws_server: web::Data<Addr<websockets::WsServer>>,
+) -> Result<HttpResponse, ApiError> {
+    transition_appointment(user.user_id, path.into_inner(), "confirmed", &pool, &ws_server).await
+}
+
+#[post("/appointments/{id}/decline")]
+async fn decline_appointment(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    pool: web::Data<sqlx::PgPool>,
+    ws_server: web::Data<Addr<websockets::WsServer>>,
+) -> Result<HttpResponse, ApiError> {
+    transition_appointment(user.user_id, path.into_inner(), "declined", &pool, &ws_server).await
+}
+
+#[post("/appointments/{id}/cancel")]
+async fn cancel_appointment(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    pool: web::Data<sqlx::PgPool>,
+    ws_server: web::Data<Addr<websockets::WsServer>>,
+) -> Result<HttpResponse, ApiError> {
+    transition_appointment(user.user_id, path.into_inner(), "cancelled", &pool, &ws_server).await
+}
+
+#[get("/appointments/upcoming")]
+async fn get_upcoming_appointments(
+    user: AuthenticatedUser,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let appointments = if user.scope == "provider" {
+        AppointmentService::get_upcoming_for_provider(&pool, user_id).await
+    } else {
+        AppointmentService::get_upcoming_for_client(&pool, user_id).await
+    };
+
+    let appointments = appointments?;
+    Ok(HttpResponse::Ok().json(appointments))
+}
+
+#[get("/conversations/participants")]
+async fn get_conversation_participants(
+    user: AuthenticatedUser,
+    query: web::Query<ParticipantsQuery>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let mut conversation_ids = Vec::new();
+    for id in query.conversation_ids.split(',').map(str::trim).filter(|id| !id.is_empty()) {
+        match Uuid::parse_str(id) {
+            Ok(id) if !conversation_ids.contains(&id) => conversation_ids.push(id),
+            Ok(_) => {},
+            Err(_) => return Err(ApiError::Validation(format!("Invalid conversation id: {}", id))),
+        }
+    }
+    if conversation_ids.len() > MAX_PARTICIPANT_CONVERSATIONS {
+        return Err(ApiError::Validation(format!("At most {} conversations can be requested at once", MAX_PARTICIPANT_CONVERSATIONS)));
+    }
+
+    let participants = ConversationService::get_participants(&pool, user_id, &conversation_ids).await?;
+    Ok(HttpResponse::Ok().json(participants))
+}
+
+#[post("/users/display")]
+async fn get_display_names(
+    user: AuthenticatedUser,
+    data: web::Json<DisplayNamesData>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let mut user_ids = data.into_inner().ids;
+    user_ids.sort();
+    user_ids.dedup();
+    if user_ids.len() > MAX_DISPLAY_PROFILES {
+        return Err(ApiError::Validation(format!("At most {} users can be requested at once", MAX_DISPLAY_PROFILES)));
+    }
+
+    let profiles = ConversationService::get_display_profiles(&pool, user_id, &user_ids).await?;
+    Ok(HttpResponse::Ok().json(profiles))
+}
+
+#[get("/conversations/{id}/messages")]
+async fn get_conversation_messages(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    query: web::Query<ConversationHistoryQuery>,
+    pool: web::Data<sqlx::PgPool>,
+    ws_server: web::Data<Addr<websockets::WsServer>>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+    pagination::check(query.page.map(i64::from), query.limit.map(i64::from))?;
+
+    let conversation_id = path.into_inner();
+    match ConversationService::get_access(&pool, conversation_id, user_id).await? {
+        Some(_) => {},
+        None => return Err(ApiError::NotFound("Conversation not found".to_string())),
+    }
+
+    let page = query.page.unwrap_or(1);
+    let limit = query.limit.unwrap_or(20);
+    let history = ConversationService::get_conversation_messages(
+        &pool, conversation_id, page, limit, query.before, config.max_history_offset
+    ).await;
+    let (messages, total_count, has_more) = history?;
+    let read_state = ConversationService::get_read_state(&pool, conversation_id, user_id).await?;
+    AccessLogService::record_in_background(&pool, conversation_id, user_id, access_log::SOURCE_REST, &messages);
+    let state = match query.include_state {
+        true => websockets::conversation_state(&ws_server, &pool, conversation_id, user_id).await,
+        false => None,
+    };
+
+    Ok(HttpResponse::Ok().json(ConversationHistoryResponse {
+        messages,
+        total_count,
+        has_more,
+        last_read_message_id: read_state.as_ref().and_then(|read| read.last_read_message_id),
+        last_read_at: read_state.map(|read| read.last_read_at),
+        state,
+    }))
+}
+
+#[get("/conversations/{id}/summary")]
+async fn get_conversation_summary(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    pool: web::Data<sqlx::PgPool>,
+    cache: web::Data<SummaryCache>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let conversation_id = path.into_inner();
+    match ConversationService::get_access(&pool, conversation_id, user_id).await? {
+        Some(_) => {},
+        None => return Err(ApiError::NotFound("Conversation not found".to_string())),
+    }
+    let conversation = match ConversationService::get_conversation_by_id(&pool, conversation_id).await? {
+        Some(conversation) => conversation,
+        None => return Err(ApiError::NotFound("Conversation not found".to_string())),
+    };
+
+    let mut summary = match cache.get(conversation.id) {
+        Some(summary) => summary,
+        None => {
+            let summary = ConversationService::get_conversation_summary(&pool, &conversation).await?;
+            cache.insert(summary.clone());
+            summary
+        },
+    };
+    summary.note = Some(NoteService::get(&pool, conversation.id).await?);
+
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+#[get("/conversations/{id}/attachments")]
+async fn get_conversation_attachments(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    query: web::Query<ConversationAttachmentsQuery>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+    pagination::check(None, query.limit)?;
+
+    let conversation_id = path.into_inner();
+    match ConversationService::get_access(&pool, conversation_id, user_id).await? {
+        Some(_) => {},
+        None => return Err(ApiError::NotFound("Conversation not found".to_string())),
+    }
+
+    let limit = query.limit.unwrap_or(50);
+    let (attachments, has_more) = ConversationService::get_attachments(&pool, conversation_id, limit, query.before).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "attachments": attachments,
+        "has_more": has_more
+    })))
+}
+
+#[get("/conversations/{id}/access-log")]
+async fn get_conversation_access_log(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    query: web::Query<AccessLogQuery>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+    pagination::check(query.page, query.limit)?;
+
+    let (entries, total_count, has_more) = AccessLogService::list_for_user(&pool, path.into_inner(), user_id, &query).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "entries": entries,
+        "total_count": total_count,
+        "has_more": has_more
+    })))
+}
+
+/// The pets of every client the provider has a conversation with, for the
+/// provider dashboard.
+#[get("/provider/pets")]
+async fn get_provider_pets(
+    user: AuthenticatedUser,
+    query: web::Query<ProviderPetsQuery>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let provider_id = user.require_provider()?;
+    pagination::check(query.page, query.limit)?;
+
+    let (pets, total_count, has_more) = ProviderPetService::list(&pool, provider_id, &query).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "pets": pets,
+        "total_count": total_count,
+        "has_more": has_more
+    })))
+}
+
+
+#[get("/notification-preferences")]
+async fn get_notification_preferences(
+    user: AuthenticatedUser,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let (default, conversations) = NotificationPreferenceService::list(&pool, user_id).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "default": default,
+        "conversations": conversations
+    })))
+}
+
+#[put("/notification-preferences")]
+async fn update_notification_preferences(
+    user: AuthenticatedUser,
+    data: web::Json<NotificationPreferenceData>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let preference = NotificationPreferenceService::set(&pool, user_id, None, &data).await?;
+    Ok(HttpResponse::Ok().json(preference))
+}
+
+#[put("/conversations/{id}/notification-preferences")]
+async fn update_conversation_notification_preferences(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    data: web::Json<NotificationPreferenceData>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let preference = NotificationPreferenceService::set(&pool, user_id, Some(path.into_inner()), &data).await?;
+    Ok(HttpResponse::Ok().json(preference))
+}
+
+#[delete("/conversations/{id}/notification-preferences")]
+async fn clear_conversation_notification_preferences(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    match NotificationPreferenceService::clear(&pool, user_id, path.into_inner()).await? {
+        true => Ok(HttpResponse::Ok().json(json!({ "message": "Notification preference removed" }))),
+        false => Err(ApiError::NotFound("No notification preference for this conversation".to_string())),
+    }
+}
+
+
+#[get("/conversations/{id}/note")]
+async fn get_conversation_note(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let note = NoteService::get_for_user(&pool, path.into_inner(), user_id).await?;
+    Ok(HttpResponse::Ok().json(note))
+}
+
+#[put("/conversations/{id}/note")]
+async fn update_conversation_note(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    data: web::Json<UpdateNoteData>,
+    pool: web::Data<sqlx::PgPool>,
+    ws_server: web::Data<Addr<websockets::WsServer>>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let note = NoteService::update(&pool, path.into_inner(), user_id, &data.content, data.version).await?;
+    ws_server.do_send(websockets::BroadcastToConversation {
+        conversation_id: note.conversation_id,
+        message: models::WsMessage {
+            sender_id: Uuid::nil(),
+            event: "note_updated".to_string(),
+            params: json!(note),
+        },
+        timing: None,
+    });
+    Ok(HttpResponse::Ok().json(note))
+}
+
+
+#[get("/conversations/{id}/draft")]
+async fn get_conversation_draft(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let draft = DraftService::get_for_user(&pool, path.into_inner(), user_id).await?;
+    Ok(HttpResponse::Ok().json(draft))
+}
+
+#[put("/conversations/{id}/draft")]
+async fn save_conversation_draft(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    data: web::Json<SaveDraftData>,
+    pool: web::Data<sqlx::PgPool>,
+    ws_server: web::Data<Addr<websockets::WsServer>>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let draft = DraftService::save(&pool, path.into_inner(), user_id, &data.content).await?;
+    ws_server.do_send(websockets::SendToUser {
+        user_id,
+        message: models::WsMessage {
+            sender_id: Uuid::nil(),
+            event: "draft_updated".to_string(),
+            params: json!(draft),
+        },
+    });
+    Ok(HttpResponse::Ok().json(draft))
+}
+
+#[put("/conversations/{id}/assigned-provider")]
+async fn assign_provider(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    data: web::Json<AssignProviderData>,
+    pool: web::Data<sqlx::PgPool>,
+    ws_server: web::Data<Addr<websockets::WsServer>>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let conversation = ConversationService::assign_provider(&pool, user_id, path.into_inner(), data.provider_id).await?;
+    ws_server.do_send(websockets::BroadcastToConversation {
+        conversation_id: conversation.id,
+        message: models::WsMessage {
+            sender_id: Uuid::nil(),
+            event: "assignment_changed".to_string(),
+            params: json!({
+                "conversation_id": conversation.id,
+                "assigned_provider": conversation.assigned_provider,
+                "assigned_by": user_id
+            }),
+        },
+        timing: None,
+    });
+    Ok(HttpResponse::Ok().json(conversation))
+}
+
+/// Lets a member of the conversation's organization take over a conversation
+/// routed or assigned to someone else.
+#[post("/conversations/{id}/take-over")]
+async fn take_over_conversation(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    pool: web::Data<sqlx::PgPool>,
+    ws_server: web::Data<Addr<websockets::WsServer>>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let (conversation, previous) = ConversationService::take_over(&pool, user_id, path.into_inner()).await?;
+    ws_server.do_send(websockets::SubscribeToConversation { user_id, conversation_id: conversation.id });
+    ws_server.do_send(websockets::BroadcastToConversation {
+        conversation_id: conversation.id,
+        message: models::WsMessage {
+            sender_id: Uuid::nil(),
+            event: "assignment_changed".to_string(),
+            params: json!({
+                "conversation_id": conversation.id,
+                "assigned_provider": conversation.assigned_provider,
+                "assigned_by": user_id
+            }),
+        },
+        timing: None,
+    });
+    let taker = websockets::display_name(&pool, user_id).await;
+    let content = match previous {
+        Some(previous) => format!("{} took over from {}", taker, websockets::display_name(&pool, previous).await),
+        None => format!("{} took over", taker),
+    };
+    websockets::post_system_message(&ws_server, &pool, user_id, conversation.id, content).await;
+    Ok(HttpResponse::Ok().json(conversation))
+}
+
+// Scraped by monitoring; holds only aggregate timings, so it isn't authenticated
+#[get("/metrics")]
+async fn get_metrics(metrics: web::Data<DeliveryMetrics>, limits: web::Data<ConcurrencyLimits>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok()
+        .content_type("application/openmetrics-text; version=1.0.0; charset=utf-8")
+        .body(metrics.render(&limits)))
+}
+
+/// Each external dependency's warm-up result. 503 until every required one is
+/// ready; optional ones that failed only make the status "degraded".
+#[get("/readiness")]
+async fn get_readiness(readiness: web::Data<Readiness>) -> Result<HttpResponse, ApiError> {
+    let status = readiness.status();
+    let body = json!({
+        "status": status,
+        "dependencies": readiness.dependencies()
+    });
+    match status {
+        "ready" | "degraded" => Ok(HttpResponse::Ok().json(body)),
+        _ => Ok(HttpResponse::ServiceUnavailable().json(body)),
+    }
+}
+
+#[get("/config")]
+async fn get_client_config(
+    user: AuthenticatedUser,
+    pool: web::Data<sqlx::PgPool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let features = FeatureFlagService::enabled_for_user(&pool, &config, user_id).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "features": features
+    })))
+}
+
+
+#[get("/canned-responses")]
+async fn get_canned_responses(
+    user: AuthenticatedUser,
+    query: web::Query<CannedResponsesQuery>,
+    pool: web::Data<sqlx::PgPool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    let provider_id = user.require_provider()?;
+    require_feature(&pool, &config, provider_id, feature_flags::CANNED_RESPONSES).await?;
+
+    let canned_responses = CannedResponseService::list(&pool, provider_id, query.category.as_deref()).await?;
+    Ok(HttpResponse::Ok().json(canned_responses))
+}
+
+#[post("/canned-responses")]
+async fn create_canned_response(
+    user: AuthenticatedUser,
+    data: web::Json<CannedResponseData>,
+    pool: web::Data<sqlx::PgPool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    let provider_id = user.require_provider()?;
+    require_feature(&pool, &config, provider_id, feature_flags::CANNED_RESPONSES).await?;
+
+    let canned_response = CannedResponseService::create(&pool, provider_id, &data).await?;
+    Ok(HttpResponse::Created().json(canned_response))
+}
+
+#[put("/canned-responses/{id}")]
+async fn update_canned_response(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    data: web::Json<CannedResponseData>,
+    pool: web::Data<sqlx::PgPool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    let provider_id = user.require_provider()?;
+    require_feature(&pool, &config, provider_id, feature_flags::CANNED_RESPONSES).await?;
+
+    let canned_response = CannedResponseService::update(&pool, provider_id, path.into_inner(), &data).await?;
+    Ok(HttpResponse::Ok().json(canned_response))
+}
+
+#[delete("/canned-responses/{id}")]
+async fn delete_canned_response(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    pool: web::Data<sqlx::PgPool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    let provider_id = user.require_provider()?;
+    require_feature(&pool, &config, provider_id, feature_flags::CANNED_RESPONSES).await?;
+
+    let id = path.into_inner();
+    CannedResponseService::delete(&pool, provider_id, id).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Canned response deleted",
+        "id": id
+    })))
+}
+
+#[get("/admin/audit")]
+async fn get_audit_log(
+    user: AuthenticatedUser,
+    query: web::Query<AuditLogQuery>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    user.require_admin()?;
+    pagination::check(query.page, query.limit)?;
+
+    match AuditService::search(&pool, &query).await {
+        Ok((entries, total_count, has_more)) => Ok(HttpResponse::Ok().json(json!({
+            "entries": entries,
+            "total_count": total_count,
+            "has_more": has_more
+        }))),
+        Err(sqlx::Error::Protocol(message)) => Err(ApiError::Validation(message)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[get("/admin/connections")]
+async fn get_connections(
+    user: AuthenticatedUser,
+    ws_server: web::Data<Addr<websockets::WsServer>>,
+) -> Result<HttpResponse, ApiError> {
+    user.require_admin()?;
+
+    let connections = ws_server.send(websockets::ListConnections).await
+        .map_err(|e| ApiError::Internal(format!("Failed to query connections: {}", e)))?;
+    Ok(HttpResponse::Ok().json(connections))
+}
+
+#[get("/admin/connections/{user_id}")]
+async fn get_connection(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    ws_server: web::Data<Addr<websockets::WsServer>>,
+) -> Result<HttpResponse, ApiError> {
+    user.require_admin()?;
+
+    match ws_server.send(websockets::GetConnection { user_id: path.into_inner() }).await
+        .map_err(|e| ApiError::Internal(format!("Failed to query connection: {}", e)))?
+    {
+        Some(details) => Ok(HttpResponse::Ok().json(details)),
+        None => Err(ApiError::NotFound("User is not connected".to_string())),
+    }
+}
+
+#[post("/admin/decode-token")]
+async fn decode_token(
+    user: AuthenticatedUser,
+    data: web::Json<DecodeTokenData>,
+    pool: web::Data<sqlx::PgPool>,
+    keys: web::Data<KeyRing>,
+) -> Result<HttpResponse, ApiError> {
+    let admin_id = user.require_admin()?;
+
+    // Diagnostics only: the decoded token never authenticates anything
+    let claims = match inspect_token(&keys, data.token.trim()) {
+        Ok(claims) => claims,
+        Err(e) => {
+            AuditService::record(&pool, "token_decoded", Some(admin_id), None, json!({
+                "valid": false
+            })).await;
+            return Err(ApiError::Validation(format!("Token could not be decoded: {}", e)));
+        }
+    };
+
+    AuditService::record(&pool, "token_decoded", Some(admin_id), Uuid::parse_str(&claims.sub).ok(), json!({
+        "valid": true,
+        "scope": claims.scope
+    })).await;
+
+    // `claims` is the token verbatim, so its times are in seconds like any JWT
+    let expired = (claims.exp as i64) <= Utc::now().timestamp();
+    Ok(HttpResponse::Ok().json(json!({
+        "claims": claims,
+        "issued_at": claims.iat as i64 * 1000,
+        "expires_at": claims.exp as i64 * 1000,
+        "expired": expired
+    })))
+}
+
+#[put("/admin/feature-flags/{user_id}")]
+async fn set_feature_flag_override(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    data: web::Json<FeatureFlagOverrideData>,
+    pool: web::Data<sqlx::PgPool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    let admin_id = user.require_admin()?;
+
+    let user_id = path.into_inner();
+    let flag = data.flag.trim();
+    if flag.is_empty() || flag.len() > 50 {
+        return Err(ApiError::Validation("Flag name must be between 1 and 50 characters".to_string()));
+    }
+
+    FeatureFlagService::set_override(&pool, user_id, flag, data.enabled).await?;
+    AuditService::record(&pool, "feature_flag_override", Some(admin_id), Some(user_id), json!({
+        "flag": flag,
+        "enabled": data.enabled
+    })).await;
+
+    let flags = FeatureFlagService::flags_for_user(&pool, &config, user_id).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "user_id": user_id,
+        "features": flags
+    })))
+}
+
+
+#[get("/admin/users")]
+async fn get_admin_users(
+    user: AuthenticatedUser,
+    query: web::Query<AdminUsersQuery>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    user.require_admin()?;
+    pagination::check(query.page, query.limit)?;
+
+    let (users, total_count, has_more) = UserAdminService::list(&pool, &query).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "users": users,
+        "total_count": total_count,
+        "has_more": has_more
+    })))
+}
+
+#[post("/admin/users/{id}/scope")]
+async fn set_user_scope(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    data: web::Json<SetUserScopeData>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let admin_id = user.require_admin()?;
+
+    let user_id = path.into_inner();
+    let previous = UserAdminService::set_scope(&pool, user_id, &data.scope).await?;
+    AuditService::record(&pool, "user_scope_changed", Some(admin_id), Some(user_id), json!({
+        "from": previous,
+        "to": data.scope
+    })).await;
+    Ok(HttpResponse::Ok().json(json!({
+        "user_id": user_id,
+        "scope": data.scope
+    })))
+}
+
+#[post("/admin/users/{id}/provider-request")]
+async fn review_provider_request(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    data: web::Json<ReviewProviderRequestData>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let admin_id = user.require_admin()?;
+
+    let user_id = path.into_inner();
+    let status = UserAdminService::review_provider_request(&pool, user_id, data.approve).await?;
+    let action = if data.approve { "provider_request_approved" } else { "provider_request_rejected" };
+    AuditService::record(&pool, action, Some(admin_id), Some(user_id), json!({})).await;
+    Ok(HttpResponse::Ok().json(json!({
+        "user_id": user_id,
+        "provider_status": status,
+        "scope": if data.approve { "provider" } else { "client" }
+    })))
+}
+
+#[post("/admin/users/{id}/suspend")]
+async fn suspend_user(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    pool: web::Data<sqlx::PgPool>,
+    ws_server: web::Data<Addr<websockets::WsServer>>,
+) -> Result<HttpResponse, ApiError> {
+    let admin_id = user.require_admin()?;
+
+    let user_id = path.into_inner();
+    if user_id == admin_id {
+        return Err(ApiError::Validation("Admins can't suspend themselves".to_string()));
+    }
+    let families = UserAdminService::suspend(&pool, user_id).await?;
+    let closed = ws_server
+        .send(websockets::DisconnectUser { user_id, reason: "account suspended" })
+        .await
+        .unwrap_or(0);
+    AuditService::record(&pool, "user_suspended", Some(admin_id), Some(user_id), json!({
+        "revoked_sessions": families.len()
+    })).await;
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "User suspended",
+        "revoked_sessions": families.len(),
+        "disconnected_sockets": closed
+    })))
+}
+
+
+#[post("/admin/service-accounts")]
+async fn create_service_account(
+    user: AuthenticatedUser,
+    data: web::Json<CreateServiceAccountData>,
+    pool: web::Data<sqlx::PgPool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    let admin_id = user.require_admin()?;
+
+    let rate_limit = data.rate_limit_per_minute.unwrap_or(config.service_account_rate_limit_per_minute as i32);
+    let (account, api_key) = ServiceAccountService::create(&pool, data.provider_id, &data.name, rate_limit).await?;
+    AuditService::record(&pool, "service_account_created", Some(admin_id), Some(account.provider_id), json!({
+        "service_account_id": account.id,
+        "name": account.name
+    })).await;
+    Ok(HttpResponse::Created().json(json!({
+        "service_account": account,
+        "api_key": api_key
+    })))
+}
+
+#[post("/admin/service-accounts/{id}/rotate")]
+async fn rotate_service_account_key(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let admin_id = user.require_admin()?;
+
+    let (account, api_key) = ServiceAccountService::rotate(&pool, path.into_inner()).await?;
+    AuditService::record(&pool, "service_account_key_rotated", Some(admin_id), Some(account.provider_id), json!({
+        "service_account_id": account.id
+    })).await;
+    Ok(HttpResponse::Ok().json(json!({
+        "service_account": account,
+        "api_key": api_key
+    })))
+}
+
+#[post("/admin/service-accounts/{id}/revoke")]
+async fn revoke_service_account(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let admin_id = user.require_admin()?;
+
+    let account = ServiceAccountService::revoke(&pool, path.into_inner()).await?;
+    AuditService::record(&pool, "service_account_revoked", Some(admin_id), Some(account.provider_id), json!({
+        "service_account_id": account.id
+    })).await;
+    Ok(HttpResponse::Ok().json(json!({
+        "service_account": account
+    })))
+}
+
+
+#[post("/admin/organizations")]
+async fn create_organization(
+    user: AuthenticatedUser,
+    data: web::Json<CreateOrganizationData>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let admin_id = user.require_admin()?;
+
+    let organization = OrganizationService::create(&pool, &data.name).await?;
+    AuditService::record(&pool, "organization_created", Some(admin_id), None, json!({
+        "organization_id": organization.id,
+        "name": organization.name
+    })).await;
+    Ok(HttpResponse::Created().json(organization))
+}
+
+/// Permanently deletes a conversation, its messages and their attachments,
+/// for legal deletion requests. Can't be undone.
+#[delete("/admin/conversations/{id}")]
+async fn delete_conversation_permanently(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    data: web::Json<ConfirmDeletionData>,
+    pool: web::Data<sqlx::PgPool>,
+    storage: web::Data<dyn ImageStorage>,
+    ws_server: web::Data<Addr<websockets::WsServer>>,
+) -> Result<HttpResponse, ApiError> {
+    let admin_id = user.require_admin()?;
+    let conversation_id = path.into_inner();
+    if data.confirm_conversation_id != conversation_id {
+        return Err(ApiError::rejected(StatusCode::BAD_REQUEST, "confirmation_mismatch", "confirm_conversation_id must match the conversation being deleted"));
+    }
+
+    match ConversationService::delete_permanently(&pool, storage.get_ref(), conversation_id).await? {
+        Some(deleted) => {
+            AuditService::record(&pool, "conversation_deleted", Some(admin_id), None, json!({
+                "conversation_id": conversation_id,
+                "message_count": deleted.message_count,
+                "attachment_count": deleted.attachment_count,
+                "storage_failures": deleted.storage_failures
+            })).await;
+            ws_server.do_send(websockets::BroadcastToConversation {
+                conversation_id,
+                message: models::WsMessage {
+                    sender_id: Uuid::nil(),
+                    event: "conversation_deleted".to_string(),
+                    params: json!({ "conversation_id": conversation_id }),
+                },
+                timing: None,
+            });
+            Ok(HttpResponse::Ok().json(json!({
+                "conversation_id": conversation_id,
+                "message_count": deleted.message_count,
+                "attachment_count": deleted.attachment_count,
+                "storage_failures": deleted.storage_failures
+            })))
+        },
+        None => Err(ApiError::NotFound("Conversation not found".to_string())),
+    }
+}
+
+/// Sets how the organization's new conversations reach its members: all of
+/// them ("broadcast") or one at a time ("round_robin").
+#[put("/admin/organizations/{id}/routing")]
+async fn set_organization_routing(
+    user: AuthenticatedUser,
+    path: web::Path<Uuid>,
+    data: web::Json<OrganizationRoutingData>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let admin_id = user.require_admin()?;
+
+    let organization = OrganizationService::set_routing_mode(&pool, path.into_inner(), &data.routing_mode).await?;
+    AuditService::record(&pool, "organization_routing_changed", Some(admin_id), None, json!({
+        "organization_id": organization.id,
+        "routing_mode": organization.routing_mode
+    })).await;
+    Ok(HttpResponse::Ok().json(organization))
+}
+
+/// Adds a provider to an organization. They are subscribed to its existing
+/// conversations straight away and receive new ones from then on.
+#[put("/admin/organizations/{id}/members/{user_id}")]
+async fn add_organization_member(
+    user: AuthenticatedUser,
+    path: web::Path<(Uuid, Uuid)>,
+    pool: web::Data<sqlx::PgPool>,
+    ws_server: web::Data<Addr<websockets::WsServer>>,
+) -> Result<HttpResponse, ApiError> {
+    let admin_id = user.require_admin()?;
+    let (organization_id, user_id) = path.into_inner();
+
+    let added = OrganizationService::add_member(&pool, organization_id, user_id).await?;
+    if added {
+        AuditService::record(&pool, "organization_member_added", Some(admin_id), Some(user_id), json!({
+            "organization_id": organization_id
+        })).await;
+    }
+    match OrganizationService::routed_conversation_ids(&pool, organization_id, user_id).await {
+        Ok(conversation_ids) => for conversation_id in conversation_ids {
+            ws_server.do_send(websockets::SubscribeToConversation { user_id, conversation_id });
+        },
+        Err(e) => logln!("Failed to subscribe {} to organization {}: {}", user_id, organization_id, e),
+    }
+    Ok(HttpResponse::Ok().json(json!({ "message": "Member added" })))
+}
+
+/// Removes a provider from an organization. They stop receiving its
+/// conversations, but the messages they sent stay attributed to them.
+#[delete("/admin/organizations/{id}/members/{user_id}")]
+async fn remove_organization_member(
+    user: AuthenticatedUser,
+    path: web::Path<(Uuid, Uuid)>,
+    pool: web::Data<sqlx::PgPool>,
+    ws_server: web::Data<Addr<websockets::WsServer>>,
+) -> Result<HttpResponse, ApiError> {
+    let admin_id = user.require_admin()?;
+    let (organization_id, user_id) = path.into_inner();
+
+    match OrganizationService::remove_member(&pool, organization_id, user_id).await? {
+        true => {
+            AuditService::record(&pool, "organization_member_removed", Some(admin_id), Some(user_id), json!({
+                "organization_id": organization_id
+            })).await;
+            match OrganizationService::routed_conversation_ids(&pool, organization_id, user_id).await {
+                Ok(conversation_ids) => for conversation_id in conversation_ids {
+                    ws_server.do_send(websockets::UnsubscribeFromConversation { user_id, conversation_id });
+                },
+                Err(e) => logln!("Failed to unsubscribe {} from organization {}: {}", user_id, organization_id, e),
+            }
+            Ok(HttpResponse::Ok().json(json!({ "message": "Member removed" })))
+        },
+        false => Err(ApiError::NotFound("Membership not found".to_string())),
+    }
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    dotenv::dotenv().ok();
+
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    // Connections are checked before use so one dropped by a database restart or
+    // failover is replaced instead of failing the request
+    let pool = PgPoolOptions::new()
+        .max_connections(5)
+        .test_before_acquire(true)
+        .connect(&database_url)
+        .await
+        .expect("Failed to create pool");
+
+    let config = config::Config::from_env();
+    if let Err(e) = config.validate_twilio_sender() {
+        elogln!("{}", e);
+        std::process::exit(1);
+    }
+
+    // `vt-rust worker` runs the background jobs instead of the API server
+    if std::env::args().nth(1).as_deref() == Some("worker") {
+        worker::run(pool, config).await;
+        return Ok(());
+    }
+
+    // Read once here rather than on every request; see KeyRing::from_env for rotating them
+    let key_ring = match KeyRing::from_env() {
+        Ok(key_ring) => web::Data::new(key_ring),
+        Err(e) => {
+            elogln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Without a pepper refresh tokens are hashed bare, and adding one later signs everyone out
+    if config.refresh_token_pepper.is_none() {
+        elogln!("⚠️ REFRESH_TOKEN_PEPPER is not set; refresh tokens are stored without a pepper");
+    }
+
+    // Refresh tokens issued before they were hashed; the pepper is only known here
+    match SessionService::hash_legacy_tokens(&pool, config.refresh_token_pepper.as_deref()).await {
+        Ok(0) => {},
+        Ok(hashed) => logln!("Hashed {} refresh tokens stored in plaintext", hashed),
+        Err(e) => elogln!("Failed to hash refresh tokens stored in plaintext: {}", e),
+    }
+
+    // Start the WebSocket server actor
+    let ws_server = websockets::WsServer::new(&config).start();
+
+    // Get certificate and key file paths from environment variables
+    let cert_path = std::env::var("SSL_CERT_PATH").unwrap_or_else(|_| "cert.pem".to_string());
+    let key_path = std::env::var("SSL_KEY_PATH").unwrap_or_else(|_| "key.pem".to_string());
+
+    // Verify certificate files exist
+    if fs::metadata(&cert_path).is_err() {
+        elogln!("SSL certificate file not found: {}", cert_path);
+        elogln!("Set SSL_CERT_PATH environment variable or place cert.pem in the current directory");
+        std::process::exit(1);
+    }
+
+    if fs::metadata(&key_path).is_err() {
+        elogln!("SSL private key file not found: {}", key_path);
+        elogln!("Set SSL_KEY_PATH environment variable or place key.pem in the current directory");
+        std::process::exit(1);
+    }
+
+    // Configure OpenSSL
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())
+        .expect("Failed to create SSL acceptor");
+    builder.set_private_key_file(&key_path, SslFiletype::PEM)
+        .expect("Failed to set private key file");
+    builder.set_certificate_chain_file(&cert_path)
+        .expect("Failed to set certificate chain file");
+
+    // Shared across workers so lockouts apply no matter which worker handles a request
+    let signature_tracker = web::Data::new(SignatureFailureTracker::new(
+        config.signature_failure_threshold,
+        config.signature_failure_threshold_per_ip,
+        std::time::Duration::from_secs(config.signature_lockout_secs),
+    ));
+
+    // Shared for the same reason, so codes can't be guessed on several workers at once
+    let code_failures = web::Data::new(CodeFailureTracker::new(
+        config.code_failure_threshold,
+        std::time::Duration::from_secs(config.code_lockout_secs),
+    ));
+
+    // Shared across workers so every request sees the same cached summaries
+    let summary_cache = web::Data::new(SummaryCache::new(std::time::Duration::from_secs(30)));
+    let moderator: web::Data<dyn MessageModerator> =
+        web::Data::from(Arc::new(RegexModerator::from_config(&config)) as Arc<dyn MessageModerator>);
+    let notifier: web::Data<dyn Notifier> = web::Data::from(Arc::new(TwilioNotifier::from_config(&config)) as Arc<dyn Notifier>);
+    let delivery_metrics = web::Data::new(DeliveryMetrics::default());
+    let conversation_limiter = web::Data::new(ConversationRateLimiter::default());
+    let message_limiter = web::Data::new(SessionMessageLimiter::default());
+    let scanner: web::Data<dyn Scanner> = web::Data::from(scanner_from_config(&config));
+    let image_storage: web::Data<dyn ImageStorage> = web::Data::from(Arc::new(GcsStorage) as Arc<dyn ImageStorage>);
+    // Shared across workers so each limit holds for the whole server
+    let concurrency_limits = web::Data::new(ConcurrencyLimits::from_config(&config));
+    let api_key_limiter = web::Data::new(ApiKeyRateLimiter::default());
+    let phone_check_limiter = web::Data::new(PhoneCheckRateLimiter::default());
+
+    let readiness = web::Data::new(Readiness::default());
+    let warm_up_dependencies: Vec<Box<dyn readiness::Dependency>> = vec![
+        Box::new(readiness::Database(pool.clone())),
+        Box::new(readiness::CloudStorage),
+        Box::new(readiness::Twilio(config.clone())),
+        Box::new(readiness::JwtKeys(key_ring.clone())),
+    ];
+    let (warm_up, warm_up_required) = (config.warm_up, config.warm_up_required.clone());
+    let app_readiness = readiness.clone();
+
+    logln!("Starting HTTPS server on port 443...");
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(ws_server.clone()))
+            .app_data(web::Data::new(config.clone()))
+            .app_data(key_ring.clone())
+            .app_data(summary_cache.clone())
+            .app_data(signature_tracker.clone())
+            .app_data(code_failures.clone())
+            .app_data(moderator.clone())
+            .app_data(notifier.clone())
+            .app_data(scanner.clone())
+            .app_data(image_storage.clone())
+            .app_data(concurrency_limits.clone())
+            .app_data(delivery_metrics.clone())
+            .app_data(conversation_limiter.clone())
+            .app_data(message_limiter.clone())
+            .app_data(api_key_limiter.clone())
+            .app_data(phone_check_limiter.clone())
+            .app_data(app_readiness.clone())
+            .app_data(web::QueryConfig::default().error_handler(pagination::query_error))
+            .wrap(from_fn(authenticate_api_key))
+            .wrap(from_fn(reject_revoked_tokens))
+            .wrap(from_fn(assign_request_id))
+            .service(get_metrics)
+            .service(get_readiness)
+            .service(register)
+            .service(check_phone)
+            .service(twilio_inbound_sms)
+            .service(request_verification_code)
+            .service(login)
+            .service(refresh)
+            .service(logout)
+            .service(revoke_token)
+            .service(whoami)
+            .service(get_sessions)
+            .service(revoke_session)
+            .service(get_profiles)
+            .service(update_profile)
+            .service(delete_account)
+            .service(upload_image)
+            .service(create_upload_url)
+            .service(complete_upload)
+            .service(get_images)
+            .service(get_image_quota)
+            .service(get_image_status)
+            .service(get_image_content)
+            .service(update_pet)
+            .service(delete_pet)
+            .service(get_pet)
+            .service(share_pet)
+            .service(get_pet_shares)
+            .service(accept_pet_share)
+            .service(revoke_pet_share)
+            .service(create_pet_document)
+            .service(get_pet_documents)
+            .service(delete_pet_document)
+            .service(get_upcoming_appointments)
+            .service(propose_appointment)
+            .service(confirm_appointment)
+            .service(decline_appointment)
+            .service(cancel_appointment)
+            .service(get_conversation_participants)
+            .service(get_display_names)
+            .service(get_conversation_messages)
+            .service(get_conversation_summary)
+            .service(get_conversation_attachments)
+            .service(get_conversation_note)
+            .service(get_conversation_draft)
+            .service(get_conversation_access_log)
+            .service(get_provider_pets)
+            .service(get_notification_preferences)
+            .service(update_notification_preferences)
+            .service(update_conversation_notification_preferences)
+            .service(clear_conversation_notification_preferences)
+            .service(update_conversation_note)
+            .service(save_conversation_draft)
+            .service(assign_provider)
+            .service(take_over_conversation)
+            .service(get_canned_responses)
+            .service(create_canned_response)
+            .service(update_canned_response)
+            .service(delete_canned_response)
+            .service(get_audit_log)
+            .service(get_admin_users)
+            .service(set_user_scope)
+            .service(review_provider_request)
+            .service(suspend_user)
+            .service(get_connections)
+            .service(get_connection)
+            .service(set_feature_flag_override)
+            .service(create_service_account)
+            .service(rotate_service_account_key)
+            .service(revoke_service_account)
+            .service(create_organization)
+            .service(set_organization_routing)
+            .service(delete_conversation_permanently)
+            .service(add_organization_member)
+            .service(remove_organization_member)
+            .service(decode_token)
+            .service(get_client_config)
+            .service(websocket_route)
+    })
+    .bind_openssl(("0.0.0.0", 443), builder)?
+    .run();
+
+    // Already bound, so `/readiness` can report progress while dependencies warm up
+    if warm_up {
+        let handle = server.handle();
+        let readiness = readiness.clone();
+        actix_web::rt::spawn(async move {
+            let failed = readiness::warm_up(&readiness, &warm_up_dependencies, &warm_up_required).await;
+            if !failed.is_empty() {
+                elogln!("Required dependencies failed to warm up ({}); shutting down", failed.join(", "));
+                handle.stop(true).await;
+            }
+        });
+    }
+
+    server.await?;
+    if readiness.status() == "unavailable" {
+        return Err(std::io::Error::other("Required dependencies failed to warm up"));
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{insert_pet, insert_user, test_phone_number, test_pool};
+    use actix_web::test;
+
+    fn check_phone_request(phone_number: &str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/check-phone")
+            .peer_addr("203.0.113.7:50000".parse().unwrap())
+            .set_json(json!({ "phone_number": phone_number }))
+    }
+
+    #[actix_web::test]
+    async fn check_phone_reports_registration_until_rate_limited() {
+        let pool = test_pool().await;
+        let config = Config {
+            check_phone_limit_per_ip: 10,
+            check_phone_limit_per_number: 2,
+            ..Config::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(PhoneCheckRateLimiter::default()))
+                .service(check_phone)
+        ).await;
+        let phone_number = test_phone_number();
+        let user_id = sqlx::query!(
+            "INSERT INTO users (phone_number, public_key, scope) VALUES ($1, 'key', 'client') RETURNING id",
+            phone_number
+        )
+        .fetch_one(&pool).await.unwrap().id;
+
+        let started_at = Instant::now();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, check_phone_request(&phone_number).to_request()).await;
+        assert_eq!(body["registered"], true);
+        assert!(started_at.elapsed() >= CHECK_PHONE_RESPONSE_TIME);
+
+        // Formatting doesn't matter, but it is the same number as far as the limit goes
+        let formatted = format!("{}-{}-{}", &phone_number[..4], &phone_number[4..8], &phone_number[8..]);
+        let body: serde_json::Value = test::call_and_read_body_json(&app, check_phone_request(&formatted).to_request()).await;
+        assert_eq!(body["registered"], true);
+
+        let response = test::call_service(&app, check_phone_request(&phone_number).to_request()).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+
+        let body: serde_json::Value = test::call_and_read_body_json(&app, check_phone_request("000123999999999").to_request()).await;
+        assert_eq!(body["registered"], false);
+
+        sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn check_phone_is_not_found_when_disabled() {
+        let config = Config {
+            check_phone_enabled: false,
+            ..Config::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(test_pool().await))
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(PhoneCheckRateLimiter::default()))
+                .service(check_phone)
+        ).await;
+
+        let response = test::call_service(&app, check_phone_request("5551234567").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    const TEST_WEBHOOK_URL: &str = "https://api.example.com/webhooks/twilio/inbound-sms";
+    const TEST_TWILIO_AUTH_TOKEN: &str = "test-auth-token";
+
+    #[derive(Default)]
+    struct RecordingNotifier {
+        sms: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Notifier for RecordingNotifier {
+        async fn send_push(&self, _user_id: Uuid, _title: &str, _body: &str) -> anyhow::Result<bool> {
+            Ok(false)
+        }
+
+        async fn send_sms(&self, phone_number: &str, _body: &str) -> anyhow::Result<()> {
+            self.sms.lock().unwrap().push(phone_number.to_string());
+            Ok(())
+        }
+    }
+
+    fn inbound_sms_request(from: &str, body: &str) -> test::TestRequest {
+        let params = BTreeMap::from([
+            ("From".to_string(), from.to_string()),
+            ("Body".to_string(), body.to_string()),
+            ("MessageSid".to_string(), "SM00000000000000000000000000000000".to_string()),
+        ]);
+        let signature = utils::twilio_signature(TEST_TWILIO_AUTH_TOKEN, TEST_WEBHOOK_URL, &params).unwrap();
+        test::TestRequest::post()
+            .uri("/webhooks/twilio/inbound-sms")
+            .insert_header(("X-Twilio-Signature", signature))
+            .set_form(&params)
+    }
+
+    #[actix_web::test]
+    async fn inbound_sms_is_posted_to_the_senders_conversation() {
+        std::env::set_var("TWILIO_AUTH_TOKEN", TEST_TWILIO_AUTH_TOKEN);
+        let pool = test_pool().await;
+        let config = Config {
+            twilio_webhook_url: Some(TEST_WEBHOOK_URL.to_string()),
+            ..Config::default()
+        };
+        let notifier = Arc::new(RecordingNotifier::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(websockets::WsServer::new(&config).start()))
+                .app_data(web::Data::from(Arc::new(RegexModerator::from_config(&config)) as Arc<dyn MessageModerator>))
+                .app_data(web::Data::from(notifier.clone() as Arc<dyn Notifier>))
+                .app_data(web::Data::new(config))
+                .service(twilio_inbound_sms)
+        ).await;
+
+        let phone_number = test_phone_number();
+        let client_id = sqlx::query!(
+            "INSERT INTO users (phone_number, public_key, scope) VALUES ($1, 'key', 'client') RETURNING id",
+            phone_number
+        )
+        .fetch_one(&pool).await.unwrap().id;
+        let vet_id = insert_user(&pool, "provider").await;
+        let pet_id = insert_pet(&pool, client_id).await;
+        let (older, _) = ConversationService::create_conversation(&pool, vec![vet_id], None, client_id, pet_id, false).await.unwrap();
+        let (latest, _) = ConversationService::create_conversation(&pool, vec![vet_id], None, client_id, pet_id, false).await.unwrap();
+        let latest_messages = |conversation_id: Uuid| {
+            let pool = pool.clone();
+            async move {
+                sqlx::query!(
+                    r#"SELECT content AS "content!", via_sms FROM messages WHERE conversation_id = $1 ORDER BY timestamp DESC, id DESC LIMIT 1"#,
+                    conversation_id
+                )
+                .fetch_optional(&pool).await.unwrap()
+            }
+        };
+
+        // Without a short code the reply lands in the most recently active conversation
+        let response = test::call_service(&app, inbound_sms_request(&phone_number, "She ate breakfast").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let message = latest_messages(latest.id).await.unwrap();
+        assert_eq!(message.content, "She ate breakfast");
+        assert!(message.via_sms);
+
+        // A short code from the nudge picks the conversation, and is left out of the message
+        let short_code = older.id.simple().to_string()[..6].to_uppercase();
+        let body = format!("Re #{} thanks, the swelling is down", short_code);
+        let response = test::call_service(&app, inbound_sms_request(&phone_number, &body).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(latest_messages(older.id).await.unwrap().content, "Re thanks, the swelling is down");
+        assert!(notifier.sms.lock().unwrap().is_empty());
+
+        // A forged signature is refused
+        let request = inbound_sms_request(&phone_number, "Hello")
+            .insert_header(("X-Twilio-Signature", "bm90IGEgc2lnbmF0dXJl"))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::FORBIDDEN);
+
+        sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &[client_id, vet_id]).execute(&pool).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn inbound_sms_from_an_unknown_number_only_gets_a_reply() {
+        std::env::set_var("TWILIO_AUTH_TOKEN", TEST_TWILIO_AUTH_TOKEN);
+        let pool = test_pool().await;
+        let config = Config {
+            twilio_webhook_url: Some(TEST_WEBHOOK_URL.to_string()),
+            ..Config::default()
+        };
+        let notifier = Arc::new(RecordingNotifier::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(websockets::WsServer::new(&config).start()))
+                .app_data(web::Data::from(Arc::new(RegexModerator::from_config(&config)) as Arc<dyn MessageModerator>))
+                .app_data(web::Data::from(notifier.clone() as Arc<dyn Notifier>))
+                .app_data(web::Data::new(config))
+                .service(twilio_inbound_sms)
+        ).await;
+        let body = format!("Is anyone there? {}", Uuid::new_v4());
+
+        let response = test::call_service(&app, inbound_sms_request("000123999999", &body).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(*notifier.sms.lock().unwrap(), vec!["000123999999".to_string()]);
+
+        let stored = sqlx::query!("SELECT COUNT(*) AS count FROM messages WHERE content = $1", body)
+            .fetch_one(&pool).await.unwrap();
+        assert_eq!(stored.count, Some(0));
+    }
+
+    #[actix_web::test]
+    async fn profile_update_reports_each_pet() {
+        let pool = test_pool().await;
+        let user_id = insert_user(&pool, "client").await;
+        let data: UpdateProfileData = serde_json::from_value(json!({
+            "first_name": "Robin",
+            "pets": [
+                {
+                    "name": "Millie", "breed": "Mutt", "sex": "F", "birthday": 1546300800000i64,
+                    "species": "dog", "spayed_neutered": true, "weight": 30
+                },
+                { "name": "Biscuit", "breed": "Beagle", "sex": "M", "birthday": null },
+                { "id": Uuid::new_v4(), "name": "Ghost", "birthday": null }
+            ]
+        }))
+        .unwrap();
+
+        let response = apply_profile_update(user_id, data, &pool).await.unwrap();
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+        let body: serde_json::Value = serde_json::from_slice(&actix_web::body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results[0]["status"], "created");
+        assert_eq!(results[0]["pet"]["name"], "Millie");
+        assert_eq!(results[1]["status"], "failed");
+        assert_eq!(results[1]["error"], "New pets need a birthday");
+        assert_eq!(results[2]["status"], "failed");
+        assert_eq!(results[2]["error"], "Pet not found");
+        assert_eq!(body["pets"].as_array().unwrap().len(), 1);
+
+        // The user's fields and the valid pet were saved regardless
+        let user = sqlx::query!("SELECT first_name FROM users WHERE id = $1", user_id).fetch_one(&pool).await.unwrap();
+        assert_eq!(user.first_name.as_deref(), Some("Robin"));
+        let pets = sqlx::query!("SELECT name FROM pets WHERE user_id = $1", user_id).fetch_all(&pool).await.unwrap();
+        assert_eq!(pets.iter().map(|pet| pet.name.as_str()).collect::<Vec<_>>(), vec!["Millie"]);
+
+        sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn basic_profiles_leave_out_private_fields() {
+        let pool = test_pool().await;
+        let mut ids = Vec::new();
+        for scope in ["client", "client", "provider"] {
+            ids.push(sqlx::query!(
+                "
+                INSERT INTO users (phone_number, public_key, scope, first_name, email, address)
+                VALUES ($1, 'key', $2, 'Sam', 'sam@example.com', '1 Main St') RETURNING id
+                ",
+                test_phone_number(),
+                scope
+            )
+            .fetch_one(&pool).await.unwrap().id);
+        }
+
+        let profiles = fetch_basic_profiles(&pool, &ids, None).await.unwrap();
+        assert_eq!(profiles.len(), 3);
+        for profile in serde_json::to_value(&profiles).unwrap().as_array().unwrap() {
+            let mut keys: Vec<&str> = profile.as_object().unwrap().keys().map(String::as_str).collect();
+            keys.sort();
+            assert_eq!(keys, vec!["first_name", "id", "last_name", "profile_image_url", "scope"]);
+            assert_eq!(profile["first_name"], "Sam");
+        }
+
+        // A client sees only themselves and providers, as in the full view
+        let profiles = fetch_basic_profiles(&pool, &ids, Some(ids[0])).await.unwrap();
+        let mut visible: Vec<Uuid> = profiles.iter().map(|profile| profile.id).collect();
+        visible.sort();
+        let mut expected = vec![ids[0], ids[2]];
+        expected.sort();
+        assert_eq!(visible, expected);
+
+        sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &ids).execute(&pool).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn messages_serialize_times_as_milliseconds() {
+        let pool = test_pool().await;
+        let mut ids = Vec::new();
+        for scope in ["client", "provider"] {
+            ids.push(insert_user(&pool, scope).await);
+        }
+        let pet_id = sqlx::query!(
+            "INSERT INTO pets (user_id, name, breed, sex, birthday) VALUES ($1, 'Millie', 'Mutt', 'F', NOW()) RETURNING id",
+            ids[0]
+        )
+        .fetch_one(&pool).await.unwrap().id;
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![ids[1]], None, ids[0], pet_id, false).await.unwrap();
+        let moderator = RegexModerator::new(None, false, moderation::ModerationAction::Reject);
+        let sent_at = Utc::now();
+        let message = ConversationService::send_message(
+            &pool, &moderator, ids[1], conversation.id, "Hello".to_string(), sent_at, None, false
+        ).await.unwrap();
+
+        // `message_sent` carries the message as stored, like the history does
+        let event = json!(message);
+        assert_eq!(event["timestamp"].as_i64(), Some(sent_at.timestamp_millis()));
+        assert!(event["updated_at"].is_i64());
+
+        let (messages, total_count, has_more) = ConversationService::get_conversation_messages(
+            &pool, conversation.id, 1, 20, None, 1000
+        ).await.unwrap();
+        let history = json!(ConversationHistoryResponse { messages, total_count, has_more, last_read_message_id: None, last_read_at: None, state: None });
+        assert_eq!(history["messages"][0]["timestamp"], event["timestamp"]);
+        assert_eq!(history["messages"][0]["updated_at"], event["updated_at"]);
+
+        sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &ids).execute(&pool).await.unwrap();
+    }
+
+    fn register_request(signing_key: &ed25519_dalek::SigningKey, phone_number: &str) -> test::TestRequest {
+        register_request_as(signing_key, phone_number, None)
+    }
+
+    fn register_request_as(signing_key: &ed25519_dalek::SigningKey, phone_number: &str, requested_scope: Option<&str>) -> test::TestRequest {
+        use base64::Engine;
+        use ed25519_dalek::Signer;
+        let mut data = json!({
+            "phone_number": phone_number,
+            "public_key": base64::engine::general_purpose::STANDARD.encode(signing_key.verifying_key().as_bytes()),
+            "timestamp": Utc::now().to_rfc3339()
+        });
+        if let Some(scope) = requested_scope {
+            data["requested_scope"] = json!(scope);
+        }
+        let signature = signing_key.sign(utils::to_canonical_json(&data).as_bytes());
+        test::TestRequest::post().uri("/register").set_json(json!({
+            "data": data,
+            "signature": base64::engine::general_purpose::STANDARD.encode(signature.to_bytes())
+        }))
+    }
+
+    fn login_request(signing_key: &ed25519_dalek::SigningKey, user_id: Uuid, verification_code: &str) -> test::TestRequest {
+        use base64::Engine;
+        use ed25519_dalek::Signer;
+        let data = json!({
+            "verification_code": verification_code,
+            "user_id": user_id,
+            "timestamp": Utc::now().to_rfc3339()
+        });
+        let signature = signing_key.sign(utils::to_canonical_json(&data).as_bytes());
+        test::TestRequest::post().uri("/login").set_json(json!({
+            "data": data,
+            "signature": base64::engine::general_purpose::STANDARD.encode(signature.to_bytes())
+        }))
+    }
+
+    #[actix_web::test]
+    async fn wrong_verification_codes_lock_the_user_out_of_login() {
+        use base64::Engine;
+        let pool = test_pool().await;
+        let config = Config::default();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(SignatureFailureTracker::new(5, 20, std::time::Duration::from_secs(900))))
+                .app_data(web::Data::new(CodeFailureTracker::new(
+                    config.code_failure_threshold,
+                    std::time::Duration::from_secs(config.code_lockout_secs),
+                )))
+                .app_data(web::Data::new(config))
+                .app_data(utils::test_keys::key_ring())
+                .service(login)
+        ).await;
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(&rand::random());
+        let public_key = base64::engine::general_purpose::STANDARD.encode(signing_key.verifying_key().as_bytes());
+        let mut user_ids = Vec::new();
+        for _ in 0..2 {
+            let user_id = sqlx::query!(
+                "INSERT INTO users (phone_number, public_key, scope) VALUES ($1, $2, 'client') RETURNING id",
+                test_phone_number(),
+                public_key
+            )
+            .fetch_one(&pool).await.unwrap().id;
+            user_ids.push(user_id);
+        }
+        let log_in = |user_id, code: &'static str| test::call_service(&app, login_request(&signing_key, user_id, code).to_request());
+
+        // Five wrong codes in a row start the lockout; the sixth is refused outright
+        for _ in 0..4 {
+            assert_eq!(log_in(user_ids[0], "000000").await.status(), StatusCode::BAD_REQUEST);
+        }
+        for _ in 0..2 {
+            let response = log_in(user_ids[0], "000000").await;
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+            assert!(response.headers().contains_key(header::RETRY_AFTER));
+            let body: serde_json::Value = test::read_body_json(response).await;
+            assert_eq!(body["error"]["code"], "verification_code_lockout");
+            assert!((890..=900).contains(&body["error"]["retry_after"].as_u64().unwrap()));
+        }
+        // Even the right code, until the lockout ends
+        assert_eq!(log_in(user_ids[0], "123456").await.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // Other users can still try
+        assert_eq!(log_in(user_ids[1], "000000").await.status(), StatusCode::BAD_REQUEST);
+
+        sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &user_ids).execute(&pool).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn registering_a_taken_phone_number_conflicts() {
+        let pool = test_pool().await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(Config::default()))
+                .service(register)
+        ).await;
+        let phone_number = test_phone_number();
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(&rand::random());
+
+        let body: serde_json::Value = test::call_and_read_body_json(&app, register_request(&signing_key, &phone_number).to_request()).await;
+        let user_id: Uuid = body["user_id"].as_str().unwrap().parse().unwrap();
+
+        let response = test::call_service(&app, register_request(&signing_key, &phone_number).to_request()).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["error"]["code"], "phone_number_taken");
+
+        // Other failures aren't mistaken for a conflict
+        let error = sqlx::query!("INSERT INTO users (phone_number, public_key, scope) VALUES ($1, 'key', NULL)", phone_number)
+            .execute(&pool).await.unwrap_err();
+        assert_eq!(unique_violation(&error), None);
+        let error = sqlx::query!("INSERT INTO users (phone_number, public_key, scope) VALUES ($1, 'key', 'client')", phone_number)
+            .execute(&pool).await.unwrap_err();
+        assert_eq!(unique_violation(&error), Some("users_phone_number_key"));
+
+        sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn registration_normalizes_phone_numbers() {
+        let pool = test_pool().await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(Config::default()))
+                .service(register)
+        ).await;
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(&rand::random());
+
+        let response = test::call_service(&app, register_request(&signing_key, "abc").to_request()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["error"]["code"], "invalid_phone_number");
+        assert_eq!(body["error"]["message"], "Invalid phone number: phone number contains 'a'");
+
+        // Test numbers keep working however they're typed
+        let digits = format!("{:06}", rand::random::<u32>() % 1_000_000);
+        let formatted = format!("(000) 123-{}", digits);
+        let body: serde_json::Value = test::call_and_read_body_json(&app, register_request(&signing_key, &formatted).to_request()).await;
+        assert!(body["message"].as_str().unwrap().contains("123456"));
+        let user_id: Uuid = body["user_id"].as_str().unwrap().parse().unwrap();
+        let stored = sqlx::query!("SELECT phone_number FROM users WHERE id = $1", user_id)
+            .fetch_one(&pool).await.unwrap().phone_number;
+        assert_eq!(stored, format!("000123{}", digits));
+
+        // The same number written differently is still taken
+        let response = test::call_service(&app, register_request(&signing_key, &format!("000123{}", digits)).to_request()).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["error"]["message"], "Phone number already registered");
+
+        sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn registering_as_a_provider_waits_for_approval() {
+        let pool = test_pool().await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(Config::default()))
+                .service(register)
+        ).await;
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(&rand::random());
+
+        let phone_number = test_phone_number();
+        let request = register_request_as(&signing_key, &phone_number, Some("provider")).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["provider_status"], "pending");
+        let user_id: Uuid = body["user_id"].as_str().unwrap().parse().unwrap();
+        let user = sqlx::query!("SELECT scope, provider_status FROM users WHERE id = $1", user_id)
+            .fetch_one(&pool).await.unwrap();
+        assert_eq!(user.scope, "client");
+        assert_eq!(user.provider_status.as_deref(), Some("pending"));
+
+        // Nobody can register straight into another scope
+        let phone_number = test_phone_number();
+        let request = register_request_as(&signing_key, &phone_number, Some("admin")).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+    }
+
+    // Takes a while to store each upload, so concurrent uploads overlap
+    #[derive(Default)]
+    struct SlowStorage {
+        stored: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl ImageStorage for SlowStorage {
+        async fn upload(&self, object_name: &str, _content_type: &str, _data: Vec<u8>) -> anyhow::Result<String> {
+            actix_web::rt::time::sleep(std::time::Duration::from_millis(300)).await;
+            self.stored.lock().unwrap().push(object_name.to_string());
+            Ok(format!("https://storage.example.com/{}", object_name))
+        }
+
+        async fn delete(&self, _image_url: &str) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn download(&self, image_url: &str) -> anyhow::Result<Vec<u8>> {
+            anyhow::bail!("{} was never stored", image_url)
+        }
+    }
+
+    fn upload_request() -> test::TestRequest {
+        let body = "--boundary\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"millie.png\"\r\n\
+            Content-Type: image/png\r\n\r\n\
+            not really a png\r\n\
+            --boundary--\r\n";
+        test::TestRequest::post()
+            .uri("/upload-image?image_type=pet")
+            .insert_header((header::CONTENT_TYPE, "multipart/form-data; boundary=boundary"))
+            .set_payload(body)
+    }
+
+    #[actix_web::test]
+    async fn uploads_beyond_the_concurrency_limit_are_turned_away() {
+        let pool = test_pool().await;
+        let config = Config {
+            upload_concurrency: 2,
+            concurrency_wait_ms: 50,
+            ..Config::default()
+        };
+        let storage = Arc::new(SlowStorage::default());
+        let limits = web::Data::new(ConcurrencyLimits::from_config(&config));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::from(Arc::new(scanning::NoopScanner) as Arc<dyn Scanner>))
+                .app_data(web::Data::from(storage.clone() as Arc<dyn ImageStorage>))
+                .app_data(limits.clone())
+                .app_data(web::Data::new(config))
+                .service(upload_image)
+        ).await;
+        let user_id = insert_user(&pool, "client").await;
+
+        // Signed in as the client, as if by the API key middleware
+        let upload = || {
+            let request = upload_request().to_request();
+            request.extensions_mut().insert(Claims {
+                scope: "client".to_string(),
+                ..Claims::for_service_account(user_id)
+            });
+            test::call_service(&app, request)
+        };
+
+        let responses = futures::future::join_all((0..6).map(|_| upload())).await;
+        let mut uploaded = 0;
+        for response in responses {
+            match response.status() {
+                StatusCode::OK => uploaded += 1,
+                StatusCode::SERVICE_UNAVAILABLE => {
+                    assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "1");
+                    let body: serde_json::Value = test::read_body_json(response).await;
+                    assert_eq!(body["error"]["retry_after"], 1);
+                },
+                status => panic!("unexpected status {}", status),
+            }
+        }
+        assert_eq!(uploaded, 2);
+        assert_eq!(limits.uploads.rejected(), 4);
+        assert_eq!(limits.uploads.in_use(), 0);
+
+        // Every upload let through was stored once, and recorded once
+        let images = sqlx::query!("SELECT image_url FROM images WHERE user_id = $1", user_id)
+            .fetch_all(&pool).await.unwrap();
+        let stored = storage.stored.lock().unwrap().clone();
+        assert_eq!(images.len(), stored.len());
+        for object_name in stored {
+            assert!(images.iter().any(|image| image.image_url == format!("https://storage.example.com/{}", object_name)));
+        }
+
+        // Once they finish there is room again
+        let response = upload().await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+    }
+
+    // Serves the same bytes for every image
+    struct StoredImages;
+
+    #[async_trait::async_trait]
+    impl ImageStorage for StoredImages {
+        async fn upload(&self, object_name: &str, _content_type: &str, _data: Vec<u8>) -> anyhow::Result<String> {
+            Ok(format!("https://storage.example.com/{}", object_name))
+        }
+
+        async fn delete(&self, _image_url: &str) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn download(&self, _image_url: &str) -> anyhow::Result<Vec<u8>> {
+            Ok(b"not really a png".to_vec())
+        }
+    }
+
+    #[actix_web::test]
+    async fn image_content_round_trips_its_etag_to_not_modified() {
+        let pool = test_pool().await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::from(Arc::new(StoredImages) as Arc<dyn ImageStorage>))
+                .app_data(web::Data::new(Config { image_cache_max_age_secs: 600, ..Config::default() }))
+                .service(get_image_content)
+        ).await;
+        let mut user_ids = Vec::new();
+        for _ in 0..2 {
+            user_ids.push(insert_user(&pool, "client").await);
+        }
+        let image_id = Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO images (id, user_id, filename, content_type, image_type, image_url)
+             VALUES ($1, $2, 'millie.png', 'image/png', 'pet', 'https://storage.example.com/millie.png')",
+            image_id,
+            user_ids[0]
+        )
+        .execute(&pool).await.unwrap();
+
+        // Signed in as `user_id`, as if by the API key middleware
+        let get = |user_id: Uuid, uri: String, if_none_match: Option<String>| {
+            let mut request = test::TestRequest::get().uri(&uri);
+            if let Some(etag) = if_none_match {
+                request = request.insert_header((header::IF_NONE_MATCH, etag));
+            }
+            let request = request.to_request();
+            request.extensions_mut().insert(Claims {
+                scope: "client".to_string(),
+                ..Claims::for_service_account(user_id)
+            });
+            test::call_service(&app, request)
+        };
+        let uri = format!("/images/{}/content", image_id);
+
+        let response = get(user_ids[0], uri.clone(), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(headers.get(header::CONTENT_TYPE).unwrap(), "image/png");
+        assert_eq!(headers.get(header::CACHE_CONTROL).unwrap(), "private, max-age=600");
+        assert_eq!(headers.get(header::CONTENT_DISPOSITION).unwrap(), "inline; filename=\"millie.png\"");
+        let etag = headers.get(header::ETAG).unwrap().to_str().unwrap().to_string();
+        assert_eq!(test::read_body(response).await, "not really a png");
+
+        // The same tag comes back as 304 with no body
+        let response = get(user_ids[0], uri.clone(), Some(etag.clone())).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers().get(header::ETAG).unwrap(), etag.as_str());
+        assert!(test::read_body(response).await.is_empty());
+        let response = get(user_ids[0], uri.clone(), Some(format!("\"other\", W/{}", etag))).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        // A stale tag gets the image again
+        let response = get(user_ids[0], uri.clone(), Some("\"stale\"".to_string())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = get(user_ids[0], format!("{}?download=true", uri), None).await;
+        assert_eq!(response.headers().get(header::CONTENT_DISPOSITION).unwrap(), "attachment; filename=\"millie.png\"");
+
+        // Other users can't see it outside a shared conversation
+        let response = get(user_ids[1], uri.clone(), None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        for user_id in user_ids {
+            sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+        }
+    }
+
+    // A bucket clients can upload to directly, as GCS allows with signed URLs
+    #[derive(Default)]
+    struct Bucket {
+        objects: std::sync::Mutex<HashMap<String, Vec<u8>>>,
+    }
+
+    impl Bucket {
+        fn object_name(image_url: &str) -> &str {
+            image_url.strip_prefix("https://storage.example.com/").unwrap_or(image_url)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl ImageStorage for Bucket {
+        async fn upload(&self, object_name: &str, _content_type: &str, data: Vec<u8>) -> anyhow::Result<String> {
+            self.objects.lock().unwrap().insert(object_name.to_string(), data);
+            Ok(format!("https://storage.example.com/{}", object_name))
+        }
+
+        async fn delete(&self, image_url: &str) -> anyhow::Result<()> {
+            self.objects.lock().unwrap().remove(Self::object_name(image_url));
+            Ok(())
+        }
+
+        async fn download(&self, image_url: &str) -> anyhow::Result<Vec<u8>> {
+            self.objects.lock().unwrap().get(Self::object_name(image_url)).cloned()
+                .ok_or_else(|| anyhow::anyhow!("{} was never stored", image_url))
+        }
+
+        async fn signed_upload_url(&self, object_name: &str, content_type: &str, expires_in: std::time::Duration) -> anyhow::Result<String> {
+            Ok(format!("https://storage.example.com/{}?content_type={}&expires={}", object_name, content_type, expires_in.as_secs()))
+        }
+
+        async fn find(&self, object_name: &str) -> anyhow::Result<Option<storage::StoredObject>> {
+            Ok(self.objects.lock().unwrap().get(object_name).map(|data| storage::StoredObject {
+                url: format!("https://storage.example.com/{}", object_name),
+                size_bytes: data.len() as u64,
+            }))
+        }
+    }
+
+    #[actix_web::test]
+    async fn direct_uploads_are_signed_then_completed() {
+        let pool = test_pool().await;
+        let config = Config::default();
+        let bucket = Arc::new(Bucket::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::from(Arc::new(scanning::NoopScanner) as Arc<dyn Scanner>))
+                .app_data(web::Data::from(bucket.clone() as Arc<dyn ImageStorage>))
+                .app_data(web::Data::new(ConcurrencyLimits::from_config(&config)))
+                .app_data(web::Data::new(config))
+                .service(create_upload_url)
+                .service(complete_upload)
+        ).await;
+        let user_id = insert_user(&pool, "client").await;
+        let signed_in = |request: test::TestRequest| {
+            let request = request.to_request();
+            request.extensions_mut().insert(Claims {
+                scope: "client".to_string(),
+                ..Claims::for_service_account(user_id)
+            });
+            request
+        };
+        let complete = |image_id: &serde_json::Value| signed_in(
+            test::TestRequest::post().uri("/upload-complete").set_json(json!({ "image_id": image_id }))
+        );
+
+        let request = signed_in(test::TestRequest::post().uri("/upload-url")
+            .set_json(json!({ "image_type": "pet", "content_type": "image/svg+xml" })));
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::BAD_REQUEST);
+
+        let request = signed_in(test::TestRequest::post().uri("/upload-url")
+            .set_json(json!({ "image_type": "pet", "content_type": "image/png", "filename": "millie.png" })));
+        let issued: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        let object_name = issued["object_name"].as_str().unwrap().to_string();
+        assert!(object_name.starts_with("pet/") && object_name.ends_with(".png"));
+        assert_eq!(
+            issued["upload_url"],
+            format!("https://storage.example.com/{}?content_type=image/png&expires=900", object_name)
+        );
+        assert_eq!(issued["method"], "PUT");
+        assert_eq!(issued["headers"]["Content-Type"], "image/png");
+
+        // Completing before the client has uploaded anything is refused
+        let response = test::call_service(&app, complete(&issued["image_id"])).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        bucket.objects.lock().unwrap().insert(object_name.clone(), b"not really a png".to_vec());
+        let completed: serde_json::Value = test::call_and_read_body_json(&app, complete(&issued["image_id"])).await;
+        assert_eq!(completed["image_id"], issued["image_id"]);
+        assert_eq!(completed["image_url"], format!("https://storage.example.com/{}", object_name));
+        let image = sqlx::query!("SELECT filename, content_type, size_bytes FROM images WHERE user_id = $1", user_id)
+            .fetch_one(&pool).await.unwrap();
+        assert_eq!(image.filename.as_deref(), Some("millie.png"));
+        assert_eq!(image.content_type.as_deref(), Some("image/png"));
+        assert_eq!(image.size_bytes, Some(16));
+
+        // It can only be completed once
+        let response = test::call_service(&app, complete(&issued["image_id"])).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+    }
+
+    struct Unreachable;
+
+    #[async_trait::async_trait]
+    impl readiness::Dependency for Unreachable {
+        fn name(&self) -> &'static str {
+            "twilio"
+        }
+
+        async fn warm_up(&self) -> anyhow::Result<()> {
+            Err(anyhow::anyhow!("dns error"))
+        }
+    }
+
+    #[actix_web::test]
+    async fn readiness_breaks_down_each_dependency() {
+        let pool = test_pool().await;
+        let readiness = web::Data::new(Readiness::default());
+        let app = test::init_service(
+            App::new().app_data(readiness.clone()).service(get_readiness)
+        ).await;
+        let dependencies: Vec<Box<dyn readiness::Dependency>> = vec![
+            Box::new(readiness::Database(pool)),
+            Box::new(Unreachable),
+        ];
+
+        readiness::warm_up(&readiness, &dependencies, &["database".to_string()]).await;
+        let response = test::call_service(&app, test::TestRequest::get().uri("/readiness").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["dependencies"]["database"]["status"], "ready");
+        assert_eq!(body["dependencies"]["twilio"]["status"], "degraded");
+        assert_eq!(body["dependencies"]["twilio"]["required"], false);
+        assert_eq!(body["dependencies"]["twilio"]["error"], "dns error");
+
+        readiness::warm_up(&readiness, &dependencies, &["twilio".to_string()]).await;
+        let response = test::call_service(&app, test::TestRequest::get().uri("/readiness").to_request()).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["status"], "unavailable");
+    }
+
+    #[actix_web::test]
+    async fn requests_echo_their_request_id() {
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(assign_request_id))
+                .route("/id", web::get().to(|| async {
+                    HttpResponse::Ok().body(request_id::current().unwrap_or_default())
+                }))
+        ).await;
+
+        let response = test::call_service(&app, test::TestRequest::get().uri("/id")
+            .insert_header((request_id::HEADER, "client-trace-42"))
+            .to_request()).await;
+        assert_eq!(response.headers().get(request_id::HEADER).unwrap(), "client-trace-42");
+        assert_eq!(test::read_body(response).await, "client-trace-42");
+
+        // Missing and unusable ids are replaced with generated ones
+        for request in [
+            test::TestRequest::get().uri("/id"),
+            test::TestRequest::get().uri("/id").insert_header((request_id::HEADER, "not allowed")),
+        ] {
+            let response = test::call_service(&app, request.to_request()).await;
+            let id = response.headers().get(request_id::HEADER).unwrap().to_str().unwrap().to_string();
+            assert!(Uuid::parse_str(&id).is_ok());
+            assert_eq!(test::read_body(response).await, id);
+        }
+
+        // Even routes that don't exist say which request it was
+        let response = test::call_service(&app, test::TestRequest::get().uri("/missing")
+            .insert_header((request_id::HEADER, "client-trace-43"))
+            .to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers().get(request_id::HEADER).unwrap(), "client-trace-43");
+    }
+}
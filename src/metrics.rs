@@ -0,0 +1,139 @@
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+use crate::concurrency::ConcurrencyLimits;
+
+// Upper bounds in seconds; message delivery is expected to sit well under a second
+const LATENCY_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            buckets: vec![0; LATENCY_BUCKETS.len()],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, seconds: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let _ = writeln!(out, "# UNIT {} seconds", name);
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        for (bucket, bound) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, bucket);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum);
+        let _ = writeln!(out, "{}_count {}", name, self.count);
+    }
+}
+
+/// Latency of the WebSocket message pipeline, shared by all workers: from the
+/// `message` event arriving to the row being stored, and from the row being
+/// stored to each recipient's frame being written.
+pub struct DeliveryMetrics {
+    receive_to_persist: Mutex<Histogram>,
+    persist_to_deliver: Mutex<Histogram>,
+}
+
+impl Default for DeliveryMetrics {
+    fn default() -> Self {
+        DeliveryMetrics {
+            receive_to_persist: Mutex::new(Histogram::new()),
+            persist_to_deliver: Mutex::new(Histogram::new()),
+        }
+    }
+}
+
+impl DeliveryMetrics {
+    pub fn observe_receive_to_persist(&self, elapsed: Duration) {
+        self.receive_to_persist.lock().unwrap().observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_persist_to_deliver(&self, elapsed: Duration) {
+        self.persist_to_deliver.lock().unwrap().observe(elapsed.as_secs_f64());
+    }
+
+    /// Renders the histograms, and how busy each concurrency-limited route
+    /// group is, in the OpenMetrics text format.
+    pub fn render(&self, limits: &ConcurrencyLimits) -> String {
+        let mut out = String::new();
+        self.receive_to_persist.lock().unwrap().render(
+            &mut out,
+            "vettext_message_receive_to_persist_seconds",
+            "Time from receiving a message event to storing the message.",
+        );
+        self.persist_to_deliver.lock().unwrap().render(
+            &mut out,
+            "vettext_message_persist_to_deliver_seconds",
+            "Time from storing a message to writing it to a recipient session.",
+        );
+        render_concurrency(&mut out, limits);
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+fn render_concurrency(out: &mut String, limits: &ConcurrencyLimits) {
+    let _ = writeln!(out, "# TYPE vettext_concurrency_permits gauge");
+    let _ = writeln!(out, "# HELP vettext_concurrency_permits Requests each route group may run at once.");
+    for (group, limiter) in limits.groups() {
+        let _ = writeln!(out, "vettext_concurrency_permits{{group=\"{}\"}} {}", group, limiter.capacity());
+    }
+    let _ = writeln!(out, "# TYPE vettext_concurrency_permits_in_use gauge");
+    let _ = writeln!(out, "# HELP vettext_concurrency_permits_in_use Requests in each route group running now.");
+    for (group, limiter) in limits.groups() {
+        let _ = writeln!(out, "vettext_concurrency_permits_in_use{{group=\"{}\"}} {}", group, limiter.in_use());
+    }
+    let _ = writeln!(out, "# TYPE vettext_concurrency_rejected counter");
+    let _ = writeln!(out, "# HELP vettext_concurrency_rejected Requests refused because their route group stayed full.");
+    for (group, limiter) in limits.groups() {
+        let _ = writeln!(out, "vettext_concurrency_rejected_total{{group=\"{}\"}} {}", group, limiter.rejected());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[actix_web::test]
+    async fn renders_cumulative_buckets() {
+        let metrics = DeliveryMetrics::default();
+        metrics.observe_receive_to_persist(Duration::from_millis(3));
+        metrics.observe_receive_to_persist(Duration::from_millis(30));
+        metrics.observe_persist_to_deliver(Duration::from_secs(20));
+
+        let limits = ConcurrencyLimits::from_config(&Config { upload_concurrency: 4, ..Config::default() });
+        let _upload = limits.uploads.acquire(Duration::ZERO).await.unwrap();
+
+        let text = metrics.render(&limits);
+        assert!(text.contains("vettext_message_receive_to_persist_seconds_bucket{le=\"0.001\"} 0\n"));
+        assert!(text.contains("vettext_message_receive_to_persist_seconds_bucket{le=\"0.005\"} 1\n"));
+        assert!(text.contains("vettext_message_receive_to_persist_seconds_bucket{le=\"0.05\"} 2\n"));
+        assert!(text.contains("vettext_message_receive_to_persist_seconds_count 2\n"));
+        // Slower than every bound: only counted in +Inf
+        assert!(text.contains("vettext_message_persist_to_deliver_seconds_bucket{le=\"10\"} 0\n"));
+        assert!(text.contains("vettext_message_persist_to_deliver_seconds_bucket{le=\"+Inf\"} 1\n"));
+        assert!(text.contains("vettext_concurrency_permits{group=\"uploads\"} 4\n"));
+        assert!(text.contains("vettext_concurrency_permits_in_use{group=\"uploads\"} 1\n"));
+        assert!(text.contains("vettext_concurrency_permits_in_use{group=\"profile_reads\"} 0\n"));
+        assert!(text.ends_with("# EOF\n"));
+    }
+}
@@ -1,8 +1,10 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
+use std::collections::HashMap;
 use chrono::{DateTime, Utc};
 use sqlx::FromRow;
 
+#[allow(dead_code)]
 #[derive(FromRow, Debug, Serialize, Deserialize)]
 pub struct User {
     pub id: Uuid,
@@ -21,7 +23,7 @@ pub struct User {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(FromRow, Debug, Serialize, Deserialize)]
+#[derive(FromRow, Debug, Clone, Serialize, Deserialize)]
 pub struct Pet {
     pub id: Uuid,
     pub user_id: Uuid,
@@ -37,6 +39,7 @@ pub struct Pet {
     pub weight: i32               // Now non-nullable with default 0
 }
 
+#[allow(dead_code)]
 #[derive(FromRow, Debug)]
 pub struct RefreshToken {
     pub token: String,
@@ -46,6 +49,10 @@ pub struct RefreshToken {
     pub is_revoked: bool,
     pub last_used_at: Option<DateTime<Utc>>,
     pub user_agent: Option<String>,
+    pub family_id: Uuid,
+    /// Whether `token` holds the token's hash rather than the token itself.
+    pub hashed: bool,
+    pub device_name: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -59,12 +66,31 @@ pub struct RegisterData {
     pub phone_number: String,
     pub public_key: String,
     pub timestamp: String,
+    /// "provider" to ask an admin for provider access. Left out of the signed
+    /// payload when absent, so existing clients' signatures still verify.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_scope: Option<String>,
+    /// ISO 3166 country the phone number is from when it has no `+`, e.g. "GB".
+    /// Defaults to "US"; left out of the signed payload when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country_code: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CheckPhoneData {
+    pub phone_number: String,
+    /// Defaults to "US", as for registration.
+    pub country_code: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct RequestVerificationCodeData {
     pub phone_number: String,
     pub timestamp: String,
+    /// ISO 3166 country the phone number is from when it has no `+`, e.g. "GB".
+    /// Defaults to "US"; left out of the signed payload when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country_code: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -72,6 +98,9 @@ pub struct LoginData {
     pub verification_code: String,
     pub user_id: Uuid,
     pub timestamp: String,
+    // Left out of the signed JSON when the client doesn't send it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_name: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -88,6 +117,12 @@ pub struct LogoutData {
     pub timestamp: String,
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RevokeTokenData {
+    pub user_id: Uuid,
+    pub timestamp: String,
+}
+
 #[derive(Deserialize)]
 pub struct UpdateProfileData {
     pub first_name: Option<String>,
@@ -98,6 +133,18 @@ pub struct UpdateProfileData {
     pub pets: Vec<PetData>,
 }
 
+/// What happened to one entry of `UpdateProfileData::pets`, in request order.
+#[derive(Serialize, Debug)]
+pub struct PetUpdateResult {
+    pub index: usize,
+    pub id: Option<Uuid>,
+    pub status: &'static str, // "created", "updated" or "failed"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pet: Option<Pet>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'static str>,
+}
+
 #[derive(Deserialize)]
 pub struct PetData {
     pub id: Option<Uuid>,
@@ -116,6 +163,8 @@ pub struct PetData {
 #[derive(serde::Deserialize)]
 pub struct ProfilesQuery {
     pub user_ids: String,
+    // "basic" for just names and avatars; the full profile otherwise
+    pub fields: Option<String>,
 }
 
 // Define a WebSocket message structure
@@ -135,6 +184,12 @@ pub struct Conversation {
     pub last_message: Option<String>,
     #[serde(with = "chrono::serde::ts_milliseconds")]
     pub last_updated_timestamp: DateTime<Utc>,
+    pub assigned_provider: Option<Uuid>,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub created_at: DateTime<Utc>,
+    /// The clinic whose members the conversation is routed to, if any.
+    pub organization_id: Option<Uuid>,
+    pub message_count: i64,
 }
 
 #[derive(FromRow, Debug, Serialize, Deserialize)]
@@ -145,34 +200,377 @@ pub struct Message {
     pub content: String,
     #[serde(with = "chrono::serde::ts_milliseconds")]
     pub timestamp: DateTime<Utc>,
+    /// When the message last changed; its `timestamp` if it never has.
     #[serde(with = "chrono::serde::ts_milliseconds")]
     pub updated_at: DateTime<Utc>,
+    /// When its content was last edited, if ever.
+    #[serde(with = "chrono::serde::ts_milliseconds_option")]
+    pub edited_at: Option<DateTime<Utc>>,
+    pub message_type: String, // "text" or "system"
+    pub attachment_image_id: Option<Uuid>,
+    pub via_sms: bool,
+    /// Retracted by its sender; the content and attachment are blanked.
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ConversationSummary {
+    pub conversation_id: Uuid,
+    pub message_count: i64,
+    pub participant_count: i64,
+    #[serde(with = "chrono::serde::ts_milliseconds_option")]
+    pub first_message_at: Option<DateTime<Utc>>,
+    #[serde(with = "chrono::serde::ts_milliseconds_option")]
+    pub last_message_at: Option<DateTime<Utc>>,
+    pub attachment_count: i64,
+    pub attachment_bytes: i64,
+    pub estimated_export_bytes: i64,
+    pub organization: Option<OrganizationSummary>,
+    /// Read fresh on every request rather than cached with the totals, so edits
+    /// show up immediately.
+    pub note: Option<ConversationNote>,
+}
+
+/// The shared note pinned to a conversation, such as a treatment plan. A
+/// conversation without one reads as an empty note at version 0.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ConversationNote {
+    pub conversation_id: Uuid,
+    pub content: String,
+    pub updated_by: Option<Uuid>,
+    pub version: i32,
+    #[serde(with = "chrono::serde::ts_milliseconds_option")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// A user's unsent text in a conversation. Only its author sees it; a
+/// conversation without one reads as empty, with no `updated_at`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct MessageDraft {
+    pub conversation_id: Uuid,
+    pub content: String,
+    #[serde(with = "chrono::serde::ts_milliseconds_option")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct SaveDraftData {
+    pub content: String,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateNoteData {
+    pub content: String,
+    pub version: i32,
+}
+
+#[derive(Deserialize)]
+pub struct ParticipantsQuery {
+    pub conversation_ids: String, // comma-separated
+}
+
+#[derive(Serialize, Debug, Clone, FromRow)]
+pub struct ParticipantSummary {
+    pub id: Uuid,
+    pub scope: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub profile_image_url: Option<String>,
+}
+
+impl ParticipantSummary {
+    /// First and last name as far as they're known, or "Unknown User".
+    pub fn display_name(&self) -> String {
+        match (self.first_name.as_ref(), self.last_name.as_ref()) {
+            (Some(first), Some(last)) => format!("{} {}", first, last),
+            (Some(first), None) => first.clone(),
+            (None, Some(last)) => last.clone(),
+            (None, None) => "Unknown User".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DisplayNamesData {
+    pub ids: Vec<Uuid>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DisplayProfile {
+    pub display_name: String,
+    pub profile_image_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, FromRow)]
+pub struct OrganizationSummary {
+    pub id: Uuid,
+    pub name: String,
+}
+
+/// An organization with how its new conversations are routed.
+#[derive(Serialize, Debug, Clone, FromRow)]
+pub struct Organization {
+    pub id: Uuid,
+    pub name: String,
+    pub routing_mode: String, // "broadcast" or "round_robin"
+}
+
+#[derive(Serialize, Debug, Clone, FromRow)]
+pub struct PetSummary {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub species: String,
+    pub breed: String,
+    pub pet_image_url: Option<String>,
+}
+
+/// Who is in a conversation. Users are referenced by id into
+/// `ConversationParticipants::users`.
+#[derive(Serialize, Debug)]
+pub struct ConversationMembers {
+    pub conversation_id: Uuid,
+    pub client: Uuid,
+    pub providers: Vec<Uuid>,
+    pub assigned_provider: Option<Uuid>,
+    pub pet: Option<PetSummary>,
+    pub organization: Option<OrganizationSummary>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ConversationParticipants {
+    pub conversations: Vec<ConversationMembers>,
+    pub users: HashMap<Uuid, ParticipantSummary>,
+}
+
+/// A conversation with what the inbox shows for it.
+#[derive(Serialize, Debug)]
+pub struct InboxConversation {
+    #[serde(flatten)]
+    pub conversation: Conversation,
+    /// Messages from others since the user's last `mark_read`.
+    pub unread_count: i64,
+    pub pet_summary: Option<PetSummary>,
+}
+
+/// One entry of the `conversation_previews` event: just enough for an inbox row.
+#[derive(Serialize, Debug)]
+pub struct ConversationPreview {
+    pub conversation_id: Uuid,
+    pub last_message: Option<String>,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub last_updated_timestamp: DateTime<Utc>,
+    pub unread_count: i64,
+}
+
+/// How many messages one participant has sent in a conversation, and when.
+#[derive(Serialize, Debug, FromRow)]
+pub struct SenderMessageStats {
+    pub sender_id: Uuid,
+    pub message_count: i64,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub first_message_at: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub last_message_at: DateTime<Utc>,
+}
+
+/// The `conversations` event with `include_details`. Like
+/// `ConversationParticipants`, each user appears once in `users`.
+#[derive(Serialize, Debug)]
+pub struct Inbox {
+    pub conversations: Vec<InboxConversation>,
+    pub users: HashMap<Uuid, ParticipantSummary>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "event", content = "data", rename_all = "snake_case")]
 pub enum WsEvent {
-    Conversations,
+    Conversations {
+        // Send the enriched inbox instead of bare conversations
+        #[serde(default)]
+        include_details: bool,
+    },
     Message {
         conversation_id: Uuid,
-        content: String,
+        // Either `content` or a `canned_response_id` to expand server-side
+        #[serde(default)]
+        content: Option<String>,
+        #[serde(default)]
+        canned_response_id: Option<Uuid>,
+        #[serde(default)]
+        attachment_image_id: Option<Uuid>,
+        // Ask for a `delivery_trace` with pipeline timings on the resulting `message_sent`
+        #[serde(default)]
+        trace: bool,
     },
     NewConversation {
         pet_id: Uuid,
         providers: Option<Vec<Uuid>>,
+        organization_id: Option<Uuid>,
     },
     ConversationHistory {
         conversation_id: Uuid,
         page: i32,
         limit: i32,
-    }
+        // Id of the oldest message the client has; replaces `page`
+        #[serde(default)]
+        before: Option<Uuid>,
+        // Also return the participants' read state and presence
+        #[serde(default)]
+        include_state: bool,
+    },
+    MarkRead {
+        conversation_id: Uuid,
+        message_id: Uuid,
+    },
+    ConversationTimestamps {
+        conversation_id: Uuid,
+    },
+    GetNote {
+        conversation_id: Uuid,
+    },
+    UpdateNote {
+        conversation_id: Uuid,
+        content: String,
+        // The version being replaced; 0 if the conversation has no note yet
+        version: i32,
+    },
+    SetProviders {
+        conversation_id: Uuid,
+        // The complete new set, replacing the current providers
+        providers: Vec<Uuid>,
+    },
+    GetMessageStats {
+        conversation_id: Uuid,
+    },
+    SaveDraft {
+        conversation_id: Uuid,
+        // Empty to discard the draft
+        content: String,
+    },
+    GetConversationPet {
+        conversation_id: Uuid,
+    },
+    OpenConversation {
+        conversation_id: Uuid,
+        // Size of the first page of history
+        #[serde(default)]
+        limit: Option<i32>,
+    },
+    Typing {
+        conversation_id: Uuid,
+        is_typing: bool,
+    },
+    Read {
+        conversation_id: Uuid,
+        #[serde(with = "chrono::serde::ts_milliseconds")]
+        up_to_timestamp: DateTime<Utc>,
+    },
+    DeleteMessage {
+        message_id: Uuid,
+    },
+    React {
+        message_id: Uuid,
+        emoji: String,
+    },
+    Unreact {
+        message_id: Uuid,
+        emoji: String,
+    },
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Debug)]
 pub struct ConversationHistoryResponse {
     pub messages: Vec<Message>,
     pub total_count: i32,
     pub has_more: bool,
+    /// The last message the caller has read, for placing the unread divider.
+    /// Null if they haven't read any, or the message has since been deleted.
+    pub last_read_message_id: Option<Uuid>,
+    /// When the caller last marked the conversation read; messages after it are
+    /// unread when `last_read_message_id` is gone.
+    #[serde(with = "chrono::serde::ts_milliseconds_option")]
+    pub last_read_at: Option<DateTime<Utc>>,
+    /// Only when requested with `include_state`, and left out if presence
+    /// couldn't be gathered in time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<ConversationState>,
+}
+
+/// Everything needed to show a conversation, sent in answer to `open_conversation`.
+#[derive(Serialize, Debug)]
+pub struct OpenedConversation {
+    /// The newest page of history.
+    pub messages: Vec<Message>,
+    pub total_count: i32,
+    pub has_more: bool,
+    pub members: ConversationMembers,
+    /// Profiles of everyone in `members`, by id.
+    pub users: HashMap<Uuid, ParticipantSummary>,
+    pub pet: Pet,
+    /// The caller's read position, as in `ConversationHistoryResponse`.
+    pub last_read_message_id: Option<Uuid>,
+    #[serde(with = "chrono::serde::ts_milliseconds_option")]
+    pub last_read_at: Option<DateTime<Utc>>,
+}
+
+/// Read state and presence of everyone in a conversation, as seen by the caller.
+#[derive(Serialize, Debug)]
+pub struct ConversationState {
+    pub participants: Vec<ParticipantState>,
+    /// The last message the caller has read.
+    pub last_read_message_id: Option<Uuid>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ParticipantState {
+    pub user_id: Uuid,
+    #[serde(with = "chrono::serde::ts_milliseconds_option")]
+    pub last_read_at: Option<DateTime<Utc>>,
+    pub online: bool,
+}
+
+/// One user's position in a conversation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadState {
+    pub user_id: Uuid,
+    pub last_read_message_id: Option<Uuid>,
+    pub last_read_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct ConversationHistoryQuery {
+    pub page: Option<i32>,
+    pub limit: Option<i32>,
+    pub before: Option<Uuid>,
+    #[serde(default)]
+    pub include_state: bool,
+}
+
+/// Body of a permanent deletion, repeating the id so a mistyped URL can't
+/// delete the wrong conversation.
+#[derive(Deserialize)]
+pub struct ConfirmDeletionData {
+    pub confirm_conversation_id: Uuid,
+}
+
+#[derive(Deserialize)]
+pub struct ConversationAttachmentsQuery {
+    pub limit: Option<i64>,
+    // A message id: only attachments sent before that message are returned
+    pub before: Option<Uuid>,
+}
+
+/// An image attached to a message, for a conversation's gallery.
+#[derive(Serialize, Debug, FromRow)]
+pub struct ConversationAttachment {
+    pub image_id: Uuid,
+    pub image_url: String,
+    pub sender_id: Uuid,
+    pub message_id: Uuid,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub timestamp: DateTime<Utc>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -181,6 +579,7 @@ pub struct DeleteUserData {
     pub timestamp: String,
 }
 
+#[allow(dead_code)]
 #[derive(Serialize, Deserialize, Clone)]
 pub struct UploadImageData {
     pub user_id: Uuid,
@@ -207,11 +606,31 @@ pub struct GetImagesQuery {
     pub image_type: Option<String>,
 }
 
+#[derive(Deserialize)]
+pub struct ImageContentQuery {
+    /// Serve as an attachment to save rather than inline.
+    #[serde(default)]
+    pub download: bool,
+}
+
 #[derive(Deserialize)]
 pub struct UploadImageQuery {
     pub image_type: Option<String>,
 }
 
+#[derive(Deserialize)]
+pub struct UploadUrlData {
+    pub image_type: String,
+    /// The Content-Type the client will upload with.
+    pub content_type: String,
+    pub filename: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UploadCompleteData {
+    pub image_id: Uuid,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct UpdatePetData {
     pub id: Option<Uuid>,
@@ -249,4 +668,238 @@ pub struct UserProfile {
     #[serde(with = "chrono::serde::ts_milliseconds")]
     pub updated_at: DateTime<Utc>,
     pub pets: Vec<Pet>,
+    pub organizations: Vec<OrganizationSummary>,
+}
+
+#[derive(FromRow, Debug, Serialize, Deserialize, Clone)]
+pub struct Appointment {
+    pub id: Uuid,
+    pub conversation_id: Uuid,
+    pub pet_id: Uuid,
+    pub provider_id: Uuid,
+    pub client_id: Uuid,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub starts_at: DateTime<Utc>,
+    pub duration_minutes: i32,
+    pub status: String, // "proposed", "confirmed", "declined" or "cancelled"
+    pub notes: Option<String>,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct ProposeAppointmentData {
+    pub conversation_id: Uuid,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub starts_at: DateTime<Utc>,
+    pub duration_minutes: i32,
+    pub notes: Option<String>,
+}
+
+#[derive(FromRow, Debug, Serialize, Deserialize, Clone)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    pub action: String,
+    pub actor_id: Option<Uuid>,
+    pub user_id: Option<Uuid>,
+    pub details: serde_json::Value,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct AuditLogQuery {
+    pub action: Option<String>,
+    pub user_id: Option<Uuid>,
+    #[serde(default, with = "chrono::serde::ts_milliseconds_option")]
+    pub from: Option<DateTime<Utc>>,
+    #[serde(default, with = "chrono::serde::ts_milliseconds_option")]
+    pub to: Option<DateTime<Utc>>,
+    pub sort: Option<String>, // "asc" or "desc" (default)
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(FromRow, Debug, Serialize)]
+pub struct AccessLogEntry {
+    pub id: Uuid,
+    pub conversation_id: Uuid,
+    pub accessor_id: Uuid,
+    pub source: String, // "rest" or "websocket"
+    pub message_count: i32,
+    #[serde(with = "chrono::serde::ts_milliseconds_option")]
+    pub oldest_message_at: Option<DateTime<Utc>>,
+    #[serde(with = "chrono::serde::ts_milliseconds_option")]
+    pub newest_message_at: Option<DateTime<Utc>>,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub accessed_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct ProviderPetsQuery {
+    pub species: Option<String>,
+    pub search: Option<String>, // part of the pet's name
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct AccessLogQuery {
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(FromRow, Debug, Serialize, Clone, PartialEq)]
+pub struct NotificationPreference {
+    pub conversation_id: Option<Uuid>, // None for the user's default
+    pub muted: bool,
+    pub level: String, // "all" or "push_only"
+    /// Snoozed until then; unmuted again once it has passed.
+    #[serde(with = "chrono::serde::ts_milliseconds_option")]
+    pub muted_until: Option<DateTime<Utc>>,
+}
+
+impl NotificationPreference {
+    /// Whether notifications are held back at `now`, by muting or a snooze
+    /// that hasn't run out.
+    pub fn is_muted_at(&self, now: DateTime<Utc>) -> bool {
+        self.muted || self.muted_until.is_some_and(|until| now < until)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NotificationPreferenceData {
+    pub muted: bool,
+    pub level: Option<String>,
+    #[serde(default, with = "chrono::serde::ts_milliseconds_option")]
+    pub muted_until: Option<DateTime<Utc>>,
+}
+
+#[derive(FromRow, Debug, Serialize, Deserialize, Clone)]
+pub struct PetShare {
+    pub id: Uuid,
+    pub pet_id: Uuid,
+    pub owner_id: Uuid,
+    pub shared_with_user_id: Uuid,
+    pub permissions: String, // "read" or "read_write"
+    pub status: String,      // "pending" or "accepted"
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_milliseconds_option")]
+    pub accepted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct SharePetData {
+    pub phone_number: String,
+    pub permissions: String,
+}
+
+#[derive(FromRow, Debug, Serialize, Deserialize, Clone)]
+pub struct CannedResponse {
+    pub id: Uuid,
+    pub provider_id: Uuid,
+    pub title: String,
+    pub body: String,
+    pub category: Option<String>,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct AssignProviderData {
+    pub provider_id: Option<Uuid>, // null clears the assignment
+}
+
+#[derive(Deserialize)]
+pub struct DecodeTokenData {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct CreateOrganizationData {
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+pub struct OrganizationRoutingData {
+    pub routing_mode: String,
+}
+
+#[derive(Deserialize)]
+pub struct CreateServiceAccountData {
+    pub provider_id: Uuid,
+    pub name: String,
+    pub rate_limit_per_minute: Option<i32>, // defaults to SERVICE_ACCOUNT_RATE_LIMIT_PER_MINUTE
+}
+
+#[derive(Deserialize)]
+pub struct AdminUsersQuery {
+    pub scope: Option<String>,
+    pub verified: Option<bool>,
+    pub provider_status: Option<String>,
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct SetUserScopeData {
+    pub scope: String,
+}
+
+#[derive(Deserialize)]
+pub struct ReviewProviderRequestData {
+    pub approve: bool,
+}
+
+#[derive(Deserialize)]
+pub struct FeatureFlagOverrideData {
+    pub flag: String,
+    pub enabled: Option<bool>, // null removes the override
+}
+
+#[derive(Deserialize)]
+pub struct CannedResponseData {
+    pub title: String,
+    pub body: String,
+    pub category: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CannedResponsesQuery {
+    pub category: Option<String>,
+}
+
+#[derive(FromRow, Debug, Serialize, Clone)]
+pub struct PetDocument {
+    pub id: Uuid,
+    pub pet_id: Uuid,
+    pub image_id: Uuid,
+    pub image_url: String,
+    pub uploaded_by: Uuid,
+    pub category: String, // "insurance", "adoption", "vaccination", "medical" or "other"
+    pub title: String,
+    #[serde(with = "chrono::serde::ts_milliseconds_option")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct PetDocumentData {
+    pub image_id: Uuid,
+    pub category: String,
+    pub title: String,
+    #[serde(default, with = "chrono::serde::ts_milliseconds_option")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct PetDocumentsQuery {
+    pub category: Option<String>,
+    pub expiring_within_days: Option<i32>,
 }
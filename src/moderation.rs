@@ -0,0 +1,206 @@
+use regex::Regex;
+use std::ops::Range;
+use std::str::FromStr;
+use crate::config::Config;
+
+const REDACTED: &str = "[redacted]";
+
+/// What to do with a message that trips a moderation rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModerationAction {
+    /// Refuse to store the message.
+    Reject,
+    /// Store the message with the matched text replaced.
+    Redact,
+}
+
+impl FromStr for ModerationAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(ModerationAction::Reject),
+            "redact" => Ok(ModerationAction::Redact),
+            _ => Err(format!("Unknown moderation action: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ModerationResult {
+    Allowed,
+    /// Store `content` instead of what was sent.
+    Redacted { content: String, rules: Vec<&'static str> },
+    Blocked { rules: Vec<&'static str> },
+}
+
+/// Checks message text before it's stored.
+pub trait MessageModerator: Send + Sync {
+    fn moderate(&self, content: &str) -> ModerationResult;
+}
+
+/// Default moderator: a configurable blocklist regex plus payment card detection.
+pub struct RegexModerator {
+    blocklist: Option<Regex>,
+    detect_card_numbers: bool,
+    action: ModerationAction,
+    card_candidate: Regex,
+}
+
+impl RegexModerator {
+    pub fn new(blocklist: Option<Regex>, detect_card_numbers: bool, action: ModerationAction) -> Self {
+        RegexModerator {
+            blocklist,
+            detect_card_numbers,
+            action,
+            // 13 to 19 digits, optionally grouped with spaces or dashes
+            card_candidate: Regex::new(r"\b\d(?:[ -]?\d){12,18}\b").unwrap(),
+        }
+    }
+
+    /// Builds the moderator from config. An invalid blocklist is logged and ignored
+    /// rather than stopping the server.
+    pub fn from_config(config: &Config) -> Self {
+        let blocklist = config.moderation_blocklist.as_deref().and_then(|pattern| match Regex::new(pattern) {
+            Ok(regex) => Some(regex),
+            Err(e) => {
+                logln!("Ignoring invalid MODERATION_BLOCKLIST: {}", e);
+                None
+            }
+        });
+        RegexModerator::new(blocklist, config.moderation_detect_card_numbers, config.moderation_action)
+    }
+
+    fn card_numbers(&self, content: &str) -> Vec<Range<usize>> {
+        self.card_candidate
+            .find_iter(content)
+            .filter(|m| {
+                let digits: Vec<u32> = m.as_str().chars().filter_map(|c| c.to_digit(10)).collect();
+                (13..=19).contains(&digits.len()) && luhn_valid(&digits)
+            })
+            .map(|m| m.range())
+            .collect()
+    }
+}
+
+impl MessageModerator for RegexModerator {
+    fn moderate(&self, content: &str) -> ModerationResult {
+        let mut rules = Vec::new();
+        let mut spans = Vec::new();
+
+        if let Some(blocklist) = &self.blocklist {
+            let matches: Vec<_> = blocklist.find_iter(content).map(|m| m.range()).collect();
+            if !matches.is_empty() {
+                rules.push("blocklist");
+                spans.extend(matches);
+            }
+        }
+        if self.detect_card_numbers {
+            let matches = self.card_numbers(content);
+            if !matches.is_empty() {
+                rules.push("card_number");
+                spans.extend(matches);
+            }
+        }
+
+        if rules.is_empty() {
+            return ModerationResult::Allowed;
+        }
+        match self.action {
+            ModerationAction::Reject => ModerationResult::Blocked { rules },
+            ModerationAction::Redact => ModerationResult::Redacted { content: redact(content, spans), rules },
+        }
+    }
+}
+
+/// Luhn checksum over a card number's digits, most significant first.
+pub fn luhn_valid(digits: &[u32]) -> bool {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| if i % 2 == 1 { if d * 2 > 9 { d * 2 - 9 } else { d * 2 } } else { d })
+        .sum();
+    !digits.is_empty() && sum.is_multiple_of(10)
+}
+
+// Replaces each span (merging overlaps) with a placeholder
+fn redact(content: &str, mut spans: Vec<Range<usize>>) -> String {
+    spans.sort_by_key(|span| span.start);
+    let mut merged: Vec<Range<usize>> = Vec::new();
+    for span in spans {
+        match merged.last_mut() {
+            Some(last) if span.start <= last.end => last.end = last.end.max(span.end),
+            _ => merged.push(span),
+        }
+    }
+
+    let mut result = String::with_capacity(content.len());
+    let mut position = 0;
+    for span in merged {
+        result.push_str(&content[position..span.start]);
+        result.push_str(REDACTED);
+        position = span.end;
+    }
+    result.push_str(&content[position..]);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digits(number: &str) -> Vec<u32> {
+        number.chars().filter_map(|c| c.to_digit(10)).collect()
+    }
+
+    #[test]
+    fn luhn_check() {
+        assert!(luhn_valid(&digits("4111111111111111")));
+        assert!(luhn_valid(&digits("378282246310005")));
+        assert!(!luhn_valid(&digits("4111111111111112")));
+        assert!(!luhn_valid(&[]));
+    }
+
+    #[test]
+    fn detects_card_numbers_but_not_other_digit_runs() {
+        let moderator = RegexModerator::new(None, true, ModerationAction::Reject);
+
+        for message in ["my card is 4111 1111 1111 1111", "4111-1111-1111-1111 exp 12/29", "amex 378282246310005"] {
+            assert_eq!(moderator.moderate(message), ModerationResult::Blocked { rules: vec!["card_number"] }, "{}", message);
+        }
+        // Fails Luhn, or too short to be a card
+        for message in ["ref 4111 1111 1111 1112", "call 555-123-4567", "weight 12.5 kg"] {
+            assert_eq!(moderator.moderate(message), ModerationResult::Allowed, "{}", message);
+        }
+    }
+
+    #[test]
+    fn blocklist_matches() {
+        let blocklist = Regex::new(r"(?i)\b(idiot|scam)\b").unwrap();
+        let moderator = RegexModerator::new(Some(blocklist), false, ModerationAction::Reject);
+
+        assert_eq!(moderator.moderate("You IDIOT"), ModerationResult::Blocked { rules: vec!["blocklist"] });
+        assert_eq!(moderator.moderate("Scampi for dinner"), ModerationResult::Allowed);
+        // Card detection is off
+        assert_eq!(moderator.moderate("4111 1111 1111 1111"), ModerationResult::Allowed);
+    }
+
+    #[test]
+    fn redacts_matched_spans() {
+        let blocklist = Regex::new(r"(?i)\bscam\b").unwrap();
+        let moderator = RegexModerator::new(Some(blocklist), true, ModerationAction::Redact);
+
+        assert_eq!(
+            moderator.moderate("Not a scam, card 4111 1111 1111 1111 thanks"),
+            ModerationResult::Redacted {
+                content: "Not a [redacted], card [redacted] thanks".to_string(),
+                rules: vec!["blocklist", "card_number"],
+            }
+        );
+        assert_eq!(
+            moderator.moderate("scam"),
+            ModerationResult::Redacted { content: "[redacted]".to_string(), rules: vec!["blocklist"] }
+        );
+    }
+}
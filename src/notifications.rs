@@ -0,0 +1,220 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::services::notification_preferences::{NotificationPreferenceService, LEVEL_PUSH_ONLY};
+use crate::config::Config;
+use crate::utils::{send_sms, SmsSender};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationChannel {
+    Push,
+    Sms,
+}
+
+impl NotificationChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationChannel::Push => "push",
+            NotificationChannel::Sms => "sms",
+        }
+    }
+}
+
+/// Delivery backends for out-of-app notifications.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Returns `Ok(false)` when the user has no push channel to deliver to.
+    async fn send_push(&self, user_id: Uuid, title: &str, body: &str) -> anyhow::Result<bool>;
+    async fn send_sms(&self, phone_number: &str, body: &str) -> anyhow::Result<()>;
+}
+
+/// Production notifier. No push gateway is wired up yet, so every notification
+/// falls through to SMS via Twilio.
+pub struct TwilioNotifier {
+    sender: SmsSender,
+}
+
+impl TwilioNotifier {
+    pub fn from_config(config: &Config) -> Self {
+        TwilioNotifier { sender: SmsSender::from_config(config) }
+    }
+}
+
+#[async_trait]
+impl Notifier for TwilioNotifier {
+    async fn send_push(&self, _user_id: Uuid, _title: &str, _body: &str) -> anyhow::Result<bool> {
+        Ok(false)
+    }
+
+    async fn send_sms(&self, phone_number: &str, body: &str) -> anyhow::Result<()> {
+        // Test phone numbers never reach Twilio
+        if crate::utils::is_test_phone_number(phone_number) {
+            logln!("Skipping SMS to test phone number {}: {}", phone_number, body);
+            return Ok(());
+        }
+        send_sms(&self.sender, phone_number, body).await.map_err(|e| anyhow::anyhow!("{}", e))
+    }
+}
+
+pub struct NotificationService;
+
+impl NotificationService {
+    /// Notifies a user through their preferred channel: push first, then SMS to
+    /// the phone number on their account. Returns `None` without sending when
+    /// the user has muted or snoozed `conversation_id` (or everything), or when
+    /// they have no push channel and don't want texts.
+    pub async fn notify_user(
+        pool: &PgPool,
+        notifier: &dyn Notifier,
+        user_id: Uuid,
+        conversation_id: Option<Uuid>,
+        title: &str,
+        body: &str,
+    ) -> anyhow::Result<Option<NotificationChannel>> {
+        let preference = NotificationPreferenceService::effective(pool, user_id, conversation_id).await?;
+        if preference.is_muted_at(Utc::now()) {
+            return Ok(None);
+        }
+
+        if notifier.send_push(user_id, title, body).await? {
+            return Ok(Some(NotificationChannel::Push));
+        }
+        if preference.level == LEVEL_PUSH_ONLY {
+            return Ok(None);
+        }
+
+        let phone_number = sqlx::query!("SELECT phone_number FROM users WHERE id = $1", user_id)
+            .fetch_optional(pool)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("User {} not found", user_id))?
+            .phone_number;
+
+        notifier.send_sms(&phone_number, body).await?;
+        Ok(Some(NotificationChannel::Sms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{insert_user, test_pool};
+    use crate::models::NotificationPreferenceData;
+    use crate::services::notification_preferences::NotificationPreferenceError;
+    use crate::services::conversations::ConversationService;
+    use std::sync::Mutex;
+
+    // Delivers every push, recording who it went to
+    #[derive(Default)]
+    struct RecordingNotifier {
+        pushes: Mutex<Vec<Uuid>>,
+        sms: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Notifier for RecordingNotifier {
+        async fn send_push(&self, user_id: Uuid, _title: &str, _body: &str) -> anyhow::Result<bool> {
+            self.pushes.lock().unwrap().push(user_id);
+            Ok(true)
+        }
+
+        async fn send_sms(&self, phone_number: &str, _body: &str) -> anyhow::Result<()> {
+            self.sms.lock().unwrap().push(phone_number.to_string());
+            Ok(())
+        }
+    }
+
+    // A client and vet with two conversations between them
+    async fn setup() -> (PgPool, Uuid, Uuid, Vec<Uuid>) {
+        let pool = test_pool().await;
+        let mut user_ids = Vec::new();
+        for scope in ["client", "provider"] {
+            let id = insert_user(&pool, scope).await;
+            user_ids.push(id);
+        }
+        let (client, vet) = (user_ids[0], user_ids[1]);
+        let mut conversation_ids = Vec::new();
+        for name in ["Millie", "Biscuit"] {
+            let pet_id = sqlx::query!(
+                "INSERT INTO pets (user_id, name, breed, sex, birthday) VALUES ($1, $2, 'Mutt', 'F', NOW()) RETURNING id",
+                client,
+                name
+            )
+            .fetch_one(&pool).await.unwrap().id;
+            let (conversation, _) = ConversationService::create_conversation(
+                &pool, vec![vet], None, client, pet_id, false
+            ).await.unwrap();
+            conversation_ids.push(conversation.id);
+        }
+
+        (pool, client, vet, conversation_ids)
+    }
+
+    #[tokio::test]
+    async fn muted_conversations_are_not_notified() {
+        let (pool, client, vet, conversation_ids) = setup().await;
+        let (muted, other) = (conversation_ids[0], conversation_ids[1]);
+
+        let mute = NotificationPreferenceData { muted: true, level: None, muted_until: None };
+        NotificationPreferenceService::set(&pool, client, Some(muted), &mute).await.unwrap();
+
+        let notifier = RecordingNotifier::default();
+        let channel = NotificationService::notify_user(&pool, &notifier, client, Some(muted), "Reminder", "Hi").await.unwrap();
+        assert_eq!(channel, None);
+        assert!(notifier.pushes.lock().unwrap().is_empty());
+
+        let channel = NotificationService::notify_user(&pool, &notifier, client, Some(other), "Reminder", "Hi").await.unwrap();
+        assert_eq!(channel, Some(NotificationChannel::Push));
+        assert_eq!(*notifier.pushes.lock().unwrap(), vec![client]);
+
+        // Muting everything covers the other conversation, but not the one unmuted explicitly
+        NotificationPreferenceService::set(&pool, client, None, &mute).await.unwrap();
+        let unmute = NotificationPreferenceData { muted: false, level: None, muted_until: None };
+        NotificationPreferenceService::set(&pool, client, Some(muted), &unmute).await.unwrap();
+        assert_eq!(NotificationService::notify_user(&pool, &notifier, client, Some(other), "Reminder", "Hi").await.unwrap(), None);
+        assert_eq!(
+            NotificationService::notify_user(&pool, &notifier, client, Some(muted), "Reminder", "Hi").await.unwrap(),
+            Some(NotificationChannel::Push)
+        );
+
+        sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &[client, vet]).execute(&pool).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn snoozed_conversations_are_notified_again_once_the_snooze_ends() {
+        let (pool, client, vet, conversation_ids) = setup().await;
+        let (snoozed, other) = (conversation_ids[0], conversation_ids[1]);
+        let notifier = RecordingNotifier::default();
+
+        let past = NotificationPreferenceData { muted: false, level: None, muted_until: Some(Utc::now() - chrono::Duration::minutes(1)) };
+        assert!(matches!(
+            NotificationPreferenceService::set(&pool, client, Some(snoozed), &past).await,
+            Err(NotificationPreferenceError::Invalid(_))
+        ));
+
+        let until = Utc::now() + chrono::Duration::hours(8);
+        let snooze = NotificationPreferenceData { muted: false, level: None, muted_until: Some(until) };
+        let preference = NotificationPreferenceService::set(&pool, client, Some(snoozed), &snooze).await.unwrap();
+        assert_eq!(preference.muted_until.map(|until| until.timestamp_millis()), Some(until.timestamp_millis()));
+        assert_eq!(NotificationService::notify_user(&pool, &notifier, client, Some(snoozed), "Reminder", "Hi").await.unwrap(), None);
+        assert_eq!(
+            NotificationService::notify_user(&pool, &notifier, client, Some(other), "Reminder", "Hi").await.unwrap(),
+            Some(NotificationChannel::Push)
+        );
+
+        // As if the time had come
+        sqlx::query!(
+            "UPDATE notification_preferences SET muted_until = NOW() - INTERVAL '1 second' WHERE user_id = $1 AND conversation_id = $2",
+            client,
+            snoozed
+        )
+        .execute(&pool).await.unwrap();
+        assert_eq!(
+            NotificationService::notify_user(&pool, &notifier, client, Some(snoozed), "Reminder", "Hi").await.unwrap(),
+            Some(NotificationChannel::Push)
+        );
+        assert_eq!(*notifier.pushes.lock().unwrap(), vec![client, client]);
+
+        sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &[client, vet]).execute(&pool).await.unwrap();
+    }
+}
@@ -0,0 +1,86 @@
+use actix_web::error::QueryPayloadError;
+use actix_web::http::StatusCode;
+use actix_web::HttpRequest;
+use serde_json::json;
+
+use crate::errors::ApiError;
+
+/// The most items a paged endpoint returns at once.
+pub const MAX_LIMIT: i64 = 100;
+
+// Query parameters that must be integers wherever they're accepted
+const INTEGER_FIELDS: &[&str] = &["page", "limit"];
+
+/// A 400 naming the query parameter the client got wrong.
+pub fn invalid_query(field: &str, message: impl Into<String>) -> ApiError {
+    ApiError::Rejected {
+        status: StatusCode::BAD_REQUEST,
+        code: "invalid_query",
+        message: message.into(),
+        details: Some(json!({ "field": field })),
+    }
+}
+
+/// Refuses a `page` below 1 or a `limit` outside 1 to `MAX_LIMIT`. Parameters
+/// that were left out get the endpoint's defaults.
+pub fn check(page: Option<i64>, limit: Option<i64>) -> Result<(), ApiError> {
+    if page.is_some_and(|page| page < 1) {
+        return Err(invalid_query("page", "page must be at least 1"));
+    }
+    if limit.is_some_and(|limit| !(1..=MAX_LIMIT).contains(&limit)) {
+        return Err(invalid_query("limit", format!("limit must be between 1 and {}", MAX_LIMIT)));
+    }
+    Ok(())
+}
+
+/// Error handler for `web::Query`, registered with `QueryConfig`. Serde doesn't
+/// say which parameter failed to parse, so a `page` or `limit` that isn't a
+/// number is looked for in the query string.
+pub fn query_error(error: QueryPayloadError, req: &HttpRequest) -> actix_web::Error {
+    let field = url::form_urlencoded::parse(req.query_string().as_bytes())
+        .find(|(key, value)| INTEGER_FIELDS.contains(&key.as_ref()) && value.parse::<i32>().is_err())
+        .map(|(key, _)| key.into_owned());
+    match field {
+        Some(field) => invalid_query(&field, format!("{} must be a whole number", field)).into(),
+        None => ApiError::rejected(StatusCode::BAD_REQUEST, "invalid_query", error.to_string()).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::AccessLogQuery;
+    use actix_web::{test, web, App, HttpResponse};
+    use serde_json::Value;
+
+    async fn paged(query: web::Query<AccessLogQuery>) -> Result<HttpResponse, ApiError> {
+        check(query.page, query.limit)?;
+        Ok(HttpResponse::Ok().finish())
+    }
+
+    #[actix_web::test]
+    async fn bad_page_and_limit_values_name_their_field() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::QueryConfig::default().error_handler(query_error))
+                .route("/paged", web::get().to(paged))
+        ).await;
+        let get = |uri: &'static str| test::call_service(&app, test::TestRequest::get().uri(uri).to_request());
+
+        for (uri, field, message) in [
+            ("/paged?limit=abc", "limit", "limit must be a whole number"),
+            ("/paged?page=1&limit=2.5", "limit", "limit must be a whole number"),
+            ("/paged?page=0", "page", "page must be at least 1"),
+            ("/paged?limit=0", "limit", "limit must be between 1 and 100"),
+            ("/paged?limit=101", "limit", "limit must be between 1 and 100"),
+        ] {
+            let response = get(uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+            let body: Value = test::read_body_json(response).await;
+            assert_eq!(body["error"], json!({ "code": "invalid_query", "field": field, "message": message }), "{}", uri);
+        }
+
+        assert_eq!(get("/paged?page=3&limit=100").await.status(), StatusCode::OK);
+        assert_eq!(get("/paged").await.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+// Past this many tracked keys, expired windows are dropped on the next check
+const PRUNE_THRESHOLD: usize = 10_000;
+
+struct Window {
+    started_at: Instant,
+    requests: u32,
+}
+
+/// Counts requests per key in fixed windows, shared by all workers.
+pub struct RateLimiter<K> {
+    window: Duration,
+    keys: Mutex<HashMap<K, Window>>,
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    pub fn new(window: Duration) -> Self {
+        RateLimiter {
+            window,
+            keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a request at `now`. Returns how long until the key may make
+    /// another request if it has used up `limit` for the current window.
+    pub fn check(&self, key: K, limit: u32, now: Instant) -> Result<(), Duration> {
+        let mut keys = self.keys.lock().unwrap();
+        if keys.len() > PRUNE_THRESHOLD {
+            keys.retain(|_, window| now.duration_since(window.started_at) < self.window);
+        }
+        let window = keys.entry(key).or_insert(Window { started_at: now, requests: 0 });
+        if now.duration_since(window.started_at) >= self.window {
+            window.started_at = now;
+            window.requests = 0;
+        }
+        if window.requests >= limit {
+            return Err(self.window - now.duration_since(window.started_at));
+        }
+        window.requests += 1;
+        Ok(())
+    }
+}
+
+/// Requests per service account, in one-minute windows.
+pub type ApiKeyRateLimiter = RateLimiter<Uuid>;
+
+impl Default for ApiKeyRateLimiter {
+    fn default() -> Self {
+        RateLimiter::new(Duration::from_secs(60))
+    }
+}
+
+/// Hourly allowances for `POST /check-phone`, per client IP and per phone
+/// number, so neither one source nor many can enumerate registered numbers.
+pub struct PhoneCheckRateLimiter {
+    by_ip: RateLimiter<IpAddr>,
+    by_number: RateLimiter<String>,
+}
+
+impl Default for PhoneCheckRateLimiter {
+    fn default() -> Self {
+        PhoneCheckRateLimiter {
+            by_ip: RateLimiter::new(Duration::from_secs(60 * 60)),
+            by_number: RateLimiter::new(Duration::from_secs(60 * 60)),
+        }
+    }
+}
+
+impl PhoneCheckRateLimiter {
+    /// Counts a check of `phone_number` from `ip`. Requests refused for the IP
+    /// don't count against the number.
+    pub fn check(
+        &self,
+        ip: IpAddr,
+        phone_number: &str,
+        ip_limit: u32,
+        number_limit: u32,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        self.by_ip.check(ip, ip_limit, now)?;
+        self.by_number.check(phone_number.to_string(), number_limit, now)
+    }
+}
+
+/// A client refused by `ConversationRateLimiter`.
+#[derive(Debug, PartialEq)]
+pub struct Throttled {
+    pub retry_after: Duration,
+    /// First refusal in the current window, i.e. worth flagging as possible abuse.
+    pub first_in_window: bool,
+}
+
+/// Conversations each client may start per hour, so one account can't flood
+/// providers with invitations.
+pub struct ConversationRateLimiter {
+    created: RateLimiter<Uuid>,
+    reported: RateLimiter<Uuid>,
+}
+
+impl Default for ConversationRateLimiter {
+    fn default() -> Self {
+        ConversationRateLimiter {
+            created: RateLimiter::new(Duration::from_secs(60 * 60)),
+            reported: RateLimiter::new(Duration::from_secs(60 * 60)),
+        }
+    }
+}
+
+impl ConversationRateLimiter {
+    pub fn check(&self, client_id: Uuid, limit: u32, now: Instant) -> Result<(), Throttled> {
+        self.created.check(client_id, limit, now).map_err(|retry_after| Throttled {
+            retry_after,
+            first_in_window: self.reported.check(client_id, 1, now).is_ok(),
+        })
+    }
+}
+
+/// Messages each WebSocket session may send per minute. Sessions over budget
+/// are told to back off rather than having their messages silently dropped.
+pub struct SessionMessageLimiter {
+    received: RateLimiter<Uuid>,
+    reported: RateLimiter<Uuid>,
+}
+
+impl Default for SessionMessageLimiter {
+    fn default() -> Self {
+        SessionMessageLimiter {
+            received: RateLimiter::new(Duration::from_secs(60)),
+            reported: RateLimiter::new(Duration::from_secs(60)),
+        }
+    }
+}
+
+impl SessionMessageLimiter {
+    pub fn check(&self, session_id: Uuid, limit: u32, now: Instant) -> Result<(), Throttled> {
+        self.received.check(session_id, limit, now).map_err(|retry_after| Throttled {
+            retry_after,
+            first_in_window: self.reported.check(session_id, 1, now).is_ok(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_each_account_per_window() {
+        let limiter = ApiKeyRateLimiter::default();
+        let account_id = Uuid::new_v4();
+        let now = Instant::now();
+
+        assert!(limiter.check(account_id, 2, now).is_ok());
+        assert!(limiter.check(account_id, 2, now).is_ok());
+        assert_eq!(limiter.check(account_id, 2, now + Duration::from_secs(20)), Err(Duration::from_secs(40)));
+
+        // Other accounts have their own allowance
+        assert!(limiter.check(Uuid::new_v4(), 2, now).is_ok());
+
+        // The next window starts over
+        assert!(limiter.check(account_id, 2, now + Duration::from_secs(60)).is_ok());
+    }
+
+    #[test]
+    fn phone_checks_are_limited_by_ip_and_by_number() {
+        let limiter = PhoneCheckRateLimiter::default();
+        let now = Instant::now();
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let other_ip: IpAddr = "198.51.100.2".parse().unwrap();
+
+        // One IP trying many numbers
+        assert!(limiter.check(ip, "5550000001", 2, 5, now).is_ok());
+        assert!(limiter.check(ip, "5550000002", 2, 5, now).is_ok());
+        assert!(limiter.check(ip, "5550000003", 2, 5, now).is_err());
+
+        // Many IPs trying one number
+        for i in 0..3 {
+            let ip = IpAddr::from([10, 0, 0, i]);
+            assert!(limiter.check(ip, "5559999999", 5, 3, now).is_ok());
+        }
+        assert!(limiter.check(other_ip, "5559999999", 5, 3, now).is_err());
+
+        // Both recover after the hour
+        let later = now + Duration::from_secs(60 * 60);
+        assert!(limiter.check(ip, "5550000003", 2, 5, later).is_ok());
+        assert!(limiter.check(other_ip, "5559999999", 5, 3, later).is_ok());
+    }
+
+    #[test]
+    fn conversation_creation_is_throttled_per_client() {
+        let limiter = ConversationRateLimiter::default();
+        let client_id = Uuid::new_v4();
+        let now = Instant::now();
+
+        assert!(limiter.check(client_id, 2, now).is_ok());
+        assert!(limiter.check(client_id, 2, now).is_ok());
+        let throttled = limiter.check(client_id, 2, now).unwrap_err();
+        assert!(throttled.first_in_window);
+        assert_eq!(throttled.retry_after, Duration::from_secs(60 * 60));
+        assert!(!limiter.check(client_id, 2, now).unwrap_err().first_in_window);
+
+        assert!(limiter.check(Uuid::new_v4(), 2, now).is_ok());
+    }
+
+    #[test]
+    fn bursting_sessions_are_told_when_to_retry() {
+        let limiter = SessionMessageLimiter::default();
+        let session_id = Uuid::new_v4();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check(session_id, 3, now).is_ok());
+        }
+        let throttled = limiter.check(session_id, 3, now + Duration::from_secs(15)).unwrap_err();
+        assert_eq!(throttled.retry_after, Duration::from_secs(45));
+        assert!(throttled.first_in_window);
+        // Only the first refusal in a window is worth telling the client about
+        assert!(!limiter.check(session_id, 3, now + Duration::from_secs(16)).unwrap_err().first_in_window);
+
+        let next_window = now + Duration::from_secs(60);
+        assert!(limiter.check(session_id, 3, next_window).is_ok());
+    }
+}
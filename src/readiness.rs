@@ -0,0 +1,216 @@
+use actix_web::web;
+use async_trait::async_trait;
+use futures::future::join_all;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Instant;
+use uuid::Uuid;
+use crate::config::Config;
+use crate::storage::GcsStorage;
+use crate::utils::{fetch_verify_service, generate_signed_encrypted_token, verify_and_decode_token, KeyRing};
+
+/// An external service the API relies on, set up ahead of the first request
+/// that needs it.
+#[async_trait]
+pub trait Dependency: Send + Sync {
+    /// How the dependency is listed by `/readiness` and in `WARM_UP_REQUIRED`.
+    fn name(&self) -> &'static str;
+    async fn warm_up(&self) -> anyhow::Result<()>;
+}
+
+pub struct Database(pub PgPool);
+
+#[async_trait]
+impl Dependency for Database {
+    fn name(&self) -> &'static str {
+        "database"
+    }
+
+    async fn warm_up(&self) -> anyhow::Result<()> {
+        sqlx::query("SELECT 1").execute(&self.0).await?;
+        Ok(())
+    }
+}
+
+/// Authenticates the shared Cloud Storage client.
+pub struct CloudStorage;
+
+#[async_trait]
+impl Dependency for CloudStorage {
+    fn name(&self) -> &'static str {
+        "gcs"
+    }
+
+    async fn warm_up(&self) -> anyhow::Result<()> {
+        GcsStorage::client().await?;
+        Ok(())
+    }
+}
+
+/// Opens the shared Twilio connection by fetching the Verify service's details.
+pub struct Twilio(pub Config);
+
+#[async_trait]
+impl Dependency for Twilio {
+    fn name(&self) -> &'static str {
+        "twilio"
+    }
+
+    async fn warm_up(&self) -> anyhow::Result<()> {
+        fetch_verify_service(&self.0).await.map_err(|e| anyhow::anyhow!("{}", e))
+    }
+}
+
+/// Issues and decodes a throwaway token, so bad key configuration shows up
+/// before the first login does.
+pub struct JwtKeys(pub web::Data<KeyRing>);
+
+#[async_trait]
+impl Dependency for JwtKeys {
+    fn name(&self) -> &'static str {
+        "jwt_keys"
+    }
+
+    async fn warm_up(&self) -> anyhow::Result<()> {
+        let (token, _) = generate_signed_encrypted_token(&self.0, Uuid::nil(), "client", None)
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        verify_and_decode_token(&self.0, &token).map_err(|e| anyhow::anyhow!("{}", e))?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DependencyState {
+    Pending,
+    Ready,
+    Degraded,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DependencyStatus {
+    pub status: DependencyState,
+    pub required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// How each dependency's warm-up went, shared with `GET /readiness`. Empty
+/// when warm-up is off.
+#[derive(Default)]
+pub struct Readiness {
+    dependencies: Mutex<BTreeMap<&'static str, DependencyStatus>>,
+}
+
+impl Readiness {
+    pub fn dependencies(&self) -> BTreeMap<&'static str, DependencyStatus> {
+        self.dependencies.lock().unwrap().clone()
+    }
+
+    /// "ready", "degraded" when only optional dependencies failed, "starting"
+    /// while any are still warming up, or "unavailable" when a required one failed.
+    pub fn status(&self) -> &'static str {
+        let dependencies = self.dependencies.lock().unwrap();
+        let states = || dependencies.values();
+        if states().any(|d| d.required && d.status == DependencyState::Degraded) {
+            "unavailable"
+        } else if states().any(|d| d.status == DependencyState::Pending) {
+            "starting"
+        } else if states().any(|d| d.status == DependencyState::Degraded) {
+            "degraded"
+        } else {
+            "ready"
+        }
+    }
+
+    fn set(&self, name: &'static str, status: DependencyStatus) {
+        self.dependencies.lock().unwrap().insert(name, status);
+    }
+}
+
+/// Warms up the dependencies concurrently, logging and recording each result.
+/// Returns the names of required dependencies that failed.
+pub async fn warm_up(readiness: &Readiness, dependencies: &[Box<dyn Dependency>], required: &[String]) -> Vec<&'static str> {
+    let is_required = |name: &str| required.iter().any(|r| r == name);
+    for dependency in dependencies {
+        readiness.set(dependency.name(), DependencyStatus {
+            status: DependencyState::Pending,
+            required: is_required(dependency.name()),
+            duration_ms: None,
+            error: None,
+        });
+    }
+
+    let results = join_all(dependencies.iter().map(|dependency| async move {
+        let started = Instant::now();
+        let result = dependency.warm_up().await;
+        (dependency.name(), result, started.elapsed().as_millis() as u64)
+    }))
+    .await;
+
+    let mut failed = Vec::new();
+    for (name, result, duration_ms) in results {
+        let required = is_required(name);
+        let status = match result {
+            Ok(()) => {
+                println!("Warm-up: {} ready in {} ms", name, duration_ms);
+                DependencyStatus { status: DependencyState::Ready, required, duration_ms: Some(duration_ms), error: None }
+            },
+            Err(e) => {
+                eprintln!("Warm-up: {} degraded after {} ms: {}", name, duration_ms, e);
+                if required {
+                    failed.push(name);
+                }
+                DependencyStatus { status: DependencyState::Degraded, required, duration_ms: Some(duration_ms), error: Some(e.to_string()) }
+            },
+        };
+        readiness.set(name, status);
+    }
+    failed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fake(&'static str, bool);
+
+    #[async_trait]
+    impl Dependency for Fake {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        async fn warm_up(&self) -> anyhow::Result<()> {
+            match self.1 {
+                true => Ok(()),
+                false => Err(anyhow::anyhow!("connection refused")),
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn failing_dependencies_are_degraded() {
+        let readiness = Readiness::default();
+        assert_eq!(readiness.status(), "ready");
+
+        let dependencies: Vec<Box<dyn Dependency>> = vec![Box::new(Fake("database", true)), Box::new(Fake("twilio", false))];
+        let failed = warm_up(&readiness, &dependencies, &["database".to_string()]).await;
+        assert!(failed.is_empty());
+        assert_eq!(readiness.status(), "degraded");
+        let breakdown = readiness.dependencies();
+        assert_eq!(breakdown["database"].status, DependencyState::Ready);
+        assert!(breakdown["database"].required);
+        assert_eq!(breakdown["twilio"].status, DependencyState::Degraded);
+        assert_eq!(breakdown["twilio"].error.as_deref(), Some("connection refused"));
+
+        // The same failure in a required dependency makes the server unavailable
+        let failed = warm_up(&readiness, &dependencies, &["twilio".to_string()]).await;
+        assert_eq!(failed, vec!["twilio"]);
+        assert_eq!(readiness.status(), "unavailable");
+    }
+}
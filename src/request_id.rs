@@ -0,0 +1,104 @@
+use std::future::Future;
+use uuid::Uuid;
+
+/// Carries a request's correlation id in and out over HTTP.
+pub const HEADER: &str = "x-request-id";
+
+// Longer ids, or ones with other characters, are replaced rather than logged
+const MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The caller's id if it is usable in logs and headers, otherwise a new one.
+pub fn accept_or_generate(provided: Option<&str>) -> String {
+    match provided.map(str::trim) {
+        Some(id) if is_valid(id) => id.to_string(),
+        _ => Uuid::new_v4().to_string(),
+    }
+}
+
+fn is_valid(id: &str) -> bool {
+    (1..=MAX_LENGTH).contains(&id.len())
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+/// Runs `future` with `id` as the current request id, so everything it logs
+/// through `logln!` and `elogln!` can be matched up.
+pub fn scope<F: Future>(id: String, future: F) -> impl Future<Output = F::Output> {
+    REQUEST_ID.scope(id, future)
+}
+
+/// Runs `f` with `id` as the current request id, for synchronous work such as
+/// handling a WebSocket frame.
+pub fn sync_scope<R>(id: String, f: impl FnOnce() -> R) -> R {
+    REQUEST_ID.sync_scope(id, f)
+}
+
+/// Carries the current request id, if any, into `future`, which may be polled
+/// after the current scope has ended.
+pub fn inherit<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    // Read now; by the time the future is first polled the scope may be gone
+    let id = current();
+    async move {
+        match id {
+            Some(id) => scope(id, future).await,
+            None => future.await,
+        }
+    }
+}
+
+/// The id of the request being handled, if any.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// `println!`, prefixed with the current request id when there is one.
+macro_rules! logln {
+    ($($arg:tt)*) => {
+        match $crate::request_id::current() {
+            Some(id) => println!("[{}] {}", id, format_args!($($arg)*)),
+            None => println!($($arg)*),
+        }
+    };
+}
+
+/// `eprintln!`, prefixed with the current request id when there is one.
+macro_rules! elogln {
+    ($($arg:tt)*) => {
+        match $crate::request_id::current() {
+            Some(id) => eprintln!("[{}] {}", id, format_args!($($arg)*)),
+            None => eprintln!($($arg)*),
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usable_ids_are_kept() {
+        assert_eq!(accept_or_generate(Some("abc-123")), "abc-123");
+        assert_eq!(accept_or_generate(Some(" trace.42:7 ")), "trace.42:7");
+        for unusable in [None, Some(""), Some("has space"), Some("new\nline")] {
+            assert!(Uuid::parse_str(&accept_or_generate(unusable)).is_ok());
+        }
+        assert!(Uuid::parse_str(&accept_or_generate(Some(&"a".repeat(MAX_LENGTH + 1)))).is_ok());
+    }
+
+    #[tokio::test]
+    async fn the_id_is_current_only_within_its_scope() {
+        assert_eq!(current(), None);
+        let seen = scope("abc-123".to_string(), async { current() }).await;
+        assert_eq!(seen.as_deref(), Some("abc-123"));
+        assert_eq!(current(), None);
+    }
+
+    #[tokio::test]
+    async fn inherited_futures_keep_the_id_after_the_scope_ends() {
+        let later = sync_scope("abc-123".to_string(), || inherit(async { current() }));
+        assert_eq!(later.await.as_deref(), Some("abc-123"));
+    }
+}
@@ -0,0 +1,157 @@
+use async_trait::async_trait;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::config::Config;
+
+// clamd's default StreamMaxLength is 25 MiB; chunks just need to be smaller
+const CLAMAV_CHUNK_BYTES: usize = 64 * 1024;
+const CLAMAV_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, PartialEq)]
+pub enum ScanVerdict {
+    Clean,
+    /// Holds the name of the signature that matched.
+    Infected(String),
+}
+
+/// Checks uploaded files for malware before they are stored.
+#[async_trait]
+pub trait Scanner: Send + Sync {
+    async fn scan(&self, data: &[u8]) -> anyhow::Result<ScanVerdict>;
+}
+
+/// Accepts everything; used when no scanner is configured.
+pub struct NoopScanner;
+
+#[async_trait]
+impl Scanner for NoopScanner {
+    async fn scan(&self, _data: &[u8]) -> anyhow::Result<ScanVerdict> {
+        Ok(ScanVerdict::Clean)
+    }
+}
+
+/// A clamd daemon reached over TCP, e.g. `127.0.0.1:3310`.
+pub struct ClamAvScanner {
+    address: String,
+}
+
+impl ClamAvScanner {
+    pub fn new(address: &str) -> Self {
+        ClamAvScanner { address: address.to_string() }
+    }
+}
+
+#[async_trait]
+impl Scanner for ClamAvScanner {
+    async fn scan(&self, data: &[u8]) -> anyhow::Result<ScanVerdict> {
+        let address = self.address.clone();
+        let data = data.to_vec();
+        // Runs on the blocking pool so a slow scan doesn't hold up the worker
+        actix_web::web::block(move || scan_instream(&address, &data)).await?
+    }
+}
+
+// Streams `data` to clamd with the INSTREAM command: length-prefixed chunks
+// ending with a zero-length chunk.
+fn scan_instream(address: &str, data: &[u8]) -> anyhow::Result<ScanVerdict> {
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(CLAMAV_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLAMAV_TIMEOUT))?;
+
+    stream.write_all(b"zINSTREAM\0")?;
+    for chunk in data.chunks(CLAMAV_CHUNK_BYTES) {
+        stream.write_all(&(chunk.len() as u32).to_be_bytes())?;
+        stream.write_all(chunk)?;
+    }
+    stream.write_all(&0u32.to_be_bytes())?;
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    parse_reply(reply.trim_end_matches(['\0', '\n']))
+}
+
+// Replies look like `stream: OK` or `stream: Eicar-Test-Signature FOUND`
+fn parse_reply(reply: &str) -> anyhow::Result<ScanVerdict> {
+    let result = reply.strip_prefix("stream: ").unwrap_or(reply);
+    if result == "OK" {
+        Ok(ScanVerdict::Clean)
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Ok(ScanVerdict::Infected(signature.to_string()))
+    } else {
+        Err(anyhow::anyhow!("Unexpected clamd reply: {}", reply))
+    }
+}
+
+/// ClamAV when `CLAMAV_ADDRESS` is set, otherwise no scanning.
+pub fn scanner_from_config(config: &Config) -> Arc<dyn Scanner> {
+    match &config.clamav_address {
+        Some(address) => Arc::new(ClamAvScanner::new(address)),
+        None => Arc::new(NoopScanner),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    const EICAR: &[u8] = br"X5O!P%@AP[4\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+
+    // A stand-in clamd that flags the EICAR test string
+    fn mock_clamd() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut command = [0u8; 10];
+                stream.read_exact(&mut command).unwrap();
+                assert_eq!(&command, b"zINSTREAM\0");
+
+                let mut data = Vec::new();
+                loop {
+                    let mut length = [0u8; 4];
+                    stream.read_exact(&mut length).unwrap();
+                    let length = u32::from_be_bytes(length) as usize;
+                    if length == 0 {
+                        break;
+                    }
+                    let mut chunk = vec![0u8; length];
+                    stream.read_exact(&mut chunk).unwrap();
+                    data.extend(chunk);
+                }
+
+                let infected = data.windows(EICAR.len()).any(|window| window == EICAR);
+                let reply: &[u8] = if infected { b"stream: Eicar-Test-Signature FOUND\0" } else { b"stream: OK\0" };
+                stream.write_all(reply).unwrap();
+            }
+        });
+        address
+    }
+
+    #[actix_web::test]
+    async fn flags_the_test_signature() {
+        let scanner = ClamAvScanner::new(&mock_clamd());
+
+        assert_eq!(scanner.scan(b"\x89PNG harmless image").await.unwrap(), ScanVerdict::Clean);
+
+        // Split across chunks, still found
+        let mut infected = vec![0u8; CLAMAV_CHUNK_BYTES - 10];
+        infected.extend_from_slice(EICAR);
+        assert_eq!(
+            scanner.scan(&infected).await.unwrap(),
+            ScanVerdict::Infected("Eicar-Test-Signature".to_string())
+        );
+    }
+
+    #[actix_web::test]
+    async fn unreachable_scanner_is_an_error() {
+        let address = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+        assert!(ClamAvScanner::new(&address).scan(b"data").await.is_err());
+    }
+}
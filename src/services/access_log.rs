@@ -0,0 +1,224 @@
+use actix_web::rt;
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+use sqlx::PgPool;
+use std::fmt;
+use crate::models::{AccessLogEntry, AccessLogQuery, Message};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+pub const SOURCE_REST: &str = "rest";
+pub const SOURCE_WEBSOCKET: &str = "websocket";
+
+#[derive(Debug)]
+pub enum AccessLogError {
+    NotFound,
+    Invalid(&'static str),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for AccessLogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessLogError::NotFound => write!(f, "Conversation not found"),
+            AccessLogError::Invalid(msg) => write!(f, "{}", msg),
+            AccessLogError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for AccessLogError {
+    fn from(e: sqlx::Error) -> Self {
+        AccessLogError::Database(e)
+    }
+}
+
+/// The part of a conversation's history one fetch returned.
+#[derive(Debug, PartialEq)]
+pub struct FetchedRange {
+    pub message_count: i32,
+    pub oldest: Option<DateTime<Utc>>,
+    pub newest: Option<DateTime<Utc>>,
+}
+
+impl FetchedRange {
+    pub fn of(messages: &[Message]) -> Self {
+        FetchedRange {
+            message_count: messages.len() as i32,
+            oldest: messages.iter().map(|message| message.timestamp).min(),
+            newest: messages.iter().map(|message| message.timestamp).max(),
+        }
+    }
+}
+
+pub struct AccessLogService;
+
+impl AccessLogService {
+    /// Records a history fetch. Like auditing, failures are only logged.
+    pub async fn record(pool: &PgPool, conversation_id: Uuid, accessor_id: Uuid, source: &str, range: &FetchedRange) {
+        if let Err(e) = sqlx::query!(
+            "
+            INSERT INTO conversation_access_log
+                (conversation_id, accessor_id, source, message_count, oldest_message_at, newest_message_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ",
+            conversation_id,
+            accessor_id,
+            source,
+            range.message_count,
+            range.oldest,
+            range.newest
+        )
+        .execute(pool)
+        .await {
+            elogln!("Failed to record access to conversation {}: {}", conversation_id, e);
+        }
+    }
+
+    /// Records a history fetch without making the caller wait for the write.
+    pub fn record_in_background(pool: &PgPool, conversation_id: Uuid, accessor_id: Uuid, source: &'static str, messages: &[Message]) {
+        let pool = pool.clone();
+        let range = FetchedRange::of(messages);
+        rt::spawn(async move {
+            Self::record(&pool, conversation_id, accessor_id, source, &range).await;
+        });
+    }
+
+    /// One page of the conversation's log, newest first, with the total number
+    /// of entries and whether there are more pages. Only the client, providers
+    /// and members of the clinic the conversation is routed to may read it;
+    /// everyone else, including users a pet is shared with, gets `NotFound`.
+    pub async fn list_for_user(
+        pool: &PgPool,
+        conversation_id: Uuid,
+        user_id: Uuid,
+        query: &AccessLogQuery,
+    ) -> Result<(Vec<AccessLogEntry>, i64, bool), AccessLogError> {
+        let page = query.page.unwrap_or(1);
+        if page < 1 {
+            return Err(AccessLogError::Invalid("Invalid page number: must be >= 1"));
+        }
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+            return Err(AccessLogError::Invalid("Invalid limit: must be between 1 and 100"));
+        }
+
+        let is_participant = sqlx::query!(
+            r#"
+            SELECT 1 AS "found!"
+            FROM conversations c
+            WHERE c.id = $1 AND (c.client = $2 OR $2 = ANY(c.providers) OR EXISTS (
+                SELECT 1 FROM organization_members om
+                WHERE om.organization_id = c.organization_id AND om.user_id = $2
+            ))
+            "#,
+            conversation_id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .is_some();
+        if !is_participant {
+            return Err(AccessLogError::NotFound);
+        }
+
+        let total_count = sqlx::query!(
+            r#"SELECT COUNT(*) AS "count!" FROM conversation_access_log WHERE conversation_id = $1"#,
+            conversation_id
+        )
+        .fetch_one(pool)
+        .await?
+        .count;
+
+        let offset = (page - 1) * limit;
+        let entries = sqlx::query_as!(
+            AccessLogEntry,
+            "
+            SELECT id, conversation_id, accessor_id, source, message_count,
+                   oldest_message_at, newest_message_at, accessed_at
+            FROM conversation_access_log
+            WHERE conversation_id = $1
+            ORDER BY accessed_at DESC, id
+            LIMIT $2 OFFSET $3
+            ",
+            conversation_id,
+            limit,
+            offset
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok((entries, total_count, offset + limit < total_count))
+    }
+
+    /// Deletes entries older than `retention`, returning how many.
+    pub async fn delete_expired(pool: &PgPool, retention: Duration) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM conversation_access_log WHERE accessed_at < $1",
+            Utc::now() - retention
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{insert_pet, insert_user, test_pool};
+    use crate::services::conversations::ConversationService;
+
+    #[tokio::test]
+    async fn only_participants_read_the_log() {
+        let pool = test_pool().await;
+        let mut user_ids = Vec::new();
+        for scope in ["client", "provider", "client"] {
+            let id = insert_user(&pool, scope).await;
+            user_ids.push(id);
+        }
+        let (client, vet, outsider) = (user_ids[0], user_ids[1], user_ids[2]);
+        let pet_id = insert_pet(&pool, client).await;
+        let (conversation, _) = ConversationService::create_conversation(
+            &pool, vec![vet], None, client, pet_id, false
+        ).await.unwrap();
+
+        let now = Utc::now();
+        let range = FetchedRange { message_count: 2, oldest: Some(now - Duration::hours(1)), newest: Some(now) };
+        AccessLogService::record(&pool, conversation.id, vet, SOURCE_REST, &range).await;
+        AccessLogService::record(&pool, conversation.id, client, SOURCE_WEBSOCKET, &FetchedRange::of(&[])).await;
+
+        let query = AccessLogQuery { page: Some(1), limit: Some(1) };
+        let (entries, total_count, has_more) = AccessLogService::list_for_user(&pool, conversation.id, client, &query).await.unwrap();
+        assert_eq!((entries.len(), total_count, has_more), (1, 2, true));
+        assert_eq!(entries[0].accessor_id, client);
+        assert_eq!(entries[0].source, SOURCE_WEBSOCKET);
+        assert_eq!(entries[0].message_count, 0);
+
+        let query = AccessLogQuery { page: Some(2), limit: Some(1) };
+        let (entries, _, has_more) = AccessLogService::list_for_user(&pool, conversation.id, vet, &query).await.unwrap();
+        assert!(!has_more);
+        assert_eq!(entries[0].accessor_id, vet);
+        assert_eq!(entries[0].message_count, 2);
+        assert_eq!(entries[0].newest_message_at.map(|at| at.timestamp_millis()), Some(now.timestamp_millis()));
+
+        assert!(matches!(
+            AccessLogService::list_for_user(&pool, conversation.id, outsider, &AccessLogQuery { page: None, limit: None }).await,
+            Err(AccessLogError::NotFound)
+        ));
+
+        // Entries outlive the retention period only until the next cleanup
+        sqlx::query!(
+            "UPDATE conversation_access_log SET accessed_at = NOW() - INTERVAL '2 days' WHERE accessor_id = $1",
+            vet
+        )
+        .execute(&pool).await.unwrap();
+        assert!(AccessLogService::delete_expired(&pool, Duration::days(1)).await.unwrap() >= 1);
+        let (_, total_count, _) = AccessLogService::list_for_user(&pool, conversation.id, client, &query).await.unwrap();
+        assert_eq!(total_count, 1);
+
+        sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &user_ids).execute(&pool).await.unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use chrono::{DateTime, Utc};
+use std::fmt;
+use crate::models::{Appointment, Message};
+use crate::services::conversations::ConversationService;
+
+const MAX_DURATION_MINUTES: i32 = 8 * 60;
+
+#[derive(Debug)]
+pub enum AppointmentError {
+    NotFound,
+    Forbidden(&'static str),
+    Invalid(&'static str),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for AppointmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppointmentError::NotFound => write!(f, "Appointment not found"),
+            AppointmentError::Forbidden(msg) | AppointmentError::Invalid(msg) => write!(f, "{}", msg),
+            AppointmentError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for AppointmentError {
+    fn from(e: sqlx::Error) -> Self {
+        AppointmentError::Database(e)
+    }
+}
+
+pub struct AppointmentService;
+
+impl AppointmentService {
+    pub async fn get_appointment(pool: &PgPool, appointment_id: Uuid) -> Result<Option<Appointment>, sqlx::Error> {
+        sqlx::query_as!(
+            Appointment,
+            "
+            SELECT id, conversation_id, pet_id, provider_id, client_id, starts_at,
+                   duration_minutes, status, notes, created_at, updated_at
+            FROM appointments
+            WHERE id = $1
+            ",
+            appointment_id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// A provider proposes a slot in one of their conversations. Returns the new
+    /// appointment together with the system message recorded in the conversation.
+    pub async fn propose(
+        pool: &PgPool,
+        provider_id: Uuid,
+        conversation_id: Uuid,
+        starts_at: DateTime<Utc>,
+        duration_minutes: i32,
+        notes: Option<String>,
+    ) -> Result<(Appointment, Message), AppointmentError> {
+        if starts_at <= Utc::now() {
+            return Err(AppointmentError::Invalid("Appointment time must be in the future"));
+        }
+        if !(1..=MAX_DURATION_MINUTES).contains(&duration_minutes) {
+            return Err(AppointmentError::Invalid("Duration must be between 1 and 480 minutes"));
+        }
+
+        let conversation = ConversationService::get_conversation_by_id(pool, conversation_id)
+            .await?
+            .ok_or(AppointmentError::Forbidden("Only providers in this conversation can propose appointments"))?;
+        if !conversation.providers.contains(&provider_id) {
+            return Err(AppointmentError::Forbidden("Only providers in this conversation can propose appointments"));
+        }
+
+        // The appointment and its announcement go in together, so a retry after
+        // a failure doesn't propose twice
+        let mut tx = pool.begin().await?;
+        let appointment = sqlx::query_as!(
+            Appointment,
+            "
+            INSERT INTO appointments (conversation_id, pet_id, provider_id, client_id, starts_at, duration_minutes, notes)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, conversation_id, pet_id, provider_id, client_id, starts_at,
+                      duration_minutes, status, notes, created_at, updated_at
+            ",
+            conversation_id,
+            conversation.pet,
+            provider_id,
+            conversation.client,
+            starts_at,
+            duration_minutes,
+            notes
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let message = ConversationService::send_system_message_in(
+            &mut tx,
+            provider_id,
+            conversation_id,
+            format!("Appointment proposed for {} ({} minutes)", appointment.starts_at.to_rfc3339(), duration_minutes),
+        )
+        .await?;
+        tx.commit().await?;
+
+        Ok((appointment, message))
+    }
+
+    /// Moves an appointment to a new status on behalf of `user_id`, enforcing who may
+    /// make which transition:
+    /// - the client may confirm or decline a proposed appointment
+    /// - either party may cancel a proposed or confirmed appointment
+    pub async fn transition(
+        pool: &PgPool,
+        user_id: Uuid,
+        appointment_id: Uuid,
+        new_status: &str,
+    ) -> Result<(Appointment, Message), AppointmentError> {
+        let appointment = Self::get_appointment(pool, appointment_id)
+            .await?
+            .ok_or(AppointmentError::NotFound)?;
+
+        let is_client = appointment.client_id == user_id;
+        let is_provider = appointment.provider_id == user_id;
+        if !is_client && !is_provider {
+            return Err(AppointmentError::NotFound);
+        }
+
+        let allowed_from: &[&str] = match new_status {
+            "confirmed" | "declined" => {
+                if !is_client {
+                    return Err(AppointmentError::Forbidden("Only the client can respond to an appointment"));
+                }
+                &["proposed"]
+            }
+            "cancelled" => &["proposed", "confirmed"],
+            _ => return Err(AppointmentError::Invalid("Unknown appointment status")),
+        };
+
+        if !allowed_from.contains(&appointment.status.as_str()) {
+            return Err(AppointmentError::Invalid("Appointment can no longer be changed"));
+        }
+        if new_status == "confirmed" && appointment.starts_at <= Utc::now() {
+            return Err(AppointmentError::Invalid("Appointment time has already passed"));
+        }
+
+        // Guard on the previous status so concurrent transitions can't both succeed,
+        // and announce it in the same transaction
+        let mut tx = pool.begin().await?;
+        let updated = sqlx::query_as!(
+            Appointment,
+            "
+            UPDATE appointments
+            SET status = $1
+            WHERE id = $2 AND status = $3
+            RETURNING id, conversation_id, pet_id, provider_id, client_id, starts_at,
+                      duration_minutes, status, notes, created_at, updated_at
+            ",
+            new_status,
+            appointment_id,
+            appointment.status
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(AppointmentError::Invalid("Appointment can no longer be changed"))?;
+
+        let message = ConversationService::send_system_message_in(
+            &mut tx,
+            user_id,
+            updated.conversation_id,
+            format!("Appointment for {} {}", updated.starts_at.to_rfc3339(), new_status),
+        )
+        .await?;
+        tx.commit().await?;
+
+        Ok((updated, message))
+    }
+
+    pub async fn get_upcoming_for_client(pool: &PgPool, client_id: Uuid) -> Result<Vec<Appointment>, sqlx::Error> {
+        sqlx::query_as!(
+            Appointment,
+            "
+            SELECT id, conversation_id, pet_id, provider_id, client_id, starts_at,
+                   duration_minutes, status, notes, created_at, updated_at
+            FROM appointments
+            WHERE client_id = $1
+              AND status IN ('proposed', 'confirmed')
+              AND starts_at > CURRENT_TIMESTAMP
+            ORDER BY starts_at ASC
+            ",
+            client_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn get_upcoming_for_provider(pool: &PgPool, provider_id: Uuid) -> Result<Vec<Appointment>, sqlx::Error> {
+        sqlx::query_as!(
+            Appointment,
+            "
+            SELECT id, conversation_id, pet_id, provider_id, client_id, starts_at,
+                   duration_minutes, status, notes, created_at, updated_at
+            FROM appointments
+            WHERE provider_id = $1
+              AND status IN ('proposed', 'confirmed')
+              AND starts_at > CURRENT_TIMESTAMP
+            ORDER BY starts_at ASC
+            ",
+            provider_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+}
@@ -0,0 +1,190 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use crate::models::{AuditEntry, AuditLogQuery};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+pub struct AuditService;
+
+impl AuditService {
+    /// Records an event. `actor_id` is who did it and `user_id` whose account it
+    /// concerns; they differ for admin actions. Failures are logged rather than
+    /// returned so auditing never breaks the request being audited.
+    pub async fn record(
+        pool: &PgPool,
+        action: &str,
+        actor_id: Option<Uuid>,
+        user_id: Option<Uuid>,
+        details: serde_json::Value,
+    ) {
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO audit_log (action, actor_id, user_id, details) VALUES ($1, $2, $3, $4)",
+            action,
+            actor_id,
+            user_id,
+            details
+        )
+        .execute(pool)
+        .await {
+            elogln!("Failed to record audit event {}: {}", action, e);
+        }
+    }
+
+    /// Returns one page of matching entries, the total number of matches and
+    /// whether there are more pages.
+    /// `from` is inclusive and `to` exclusive; results are newest first unless
+    /// `sort` is "asc".
+    pub async fn search(pool: &PgPool, query: &AuditLogQuery) -> Result<(Vec<AuditEntry>, i64, bool), sqlx::Error> {
+        let page = query.page.unwrap_or(1);
+        if page < 1 {
+            return Err(sqlx::Error::Protocol("Invalid page number: must be >= 1".to_string()));
+        }
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+            return Err(sqlx::Error::Protocol(format!("Invalid limit: must be between 1 and {}", MAX_PAGE_SIZE)));
+        }
+        let ascending = match query.sort.as_deref() {
+            None | Some("desc") => false,
+            Some("asc") => true,
+            Some(_) => return Err(sqlx::Error::Protocol("Invalid sort: must be 'asc' or 'desc'".to_string())),
+        };
+
+        let total_count = sqlx::query!(
+            r#"
+            SELECT COUNT(*) AS "count!"
+            FROM audit_log
+            WHERE ($1::text IS NULL OR action = $1)
+              AND ($2::uuid IS NULL OR user_id = $2 OR actor_id = $2)
+              AND ($3::timestamptz IS NULL OR created_at >= $3)
+              AND ($4::timestamptz IS NULL OR created_at < $4)
+            "#,
+            query.action,
+            query.user_id,
+            query.from,
+            query.to
+        )
+        .fetch_one(pool)
+        .await?
+        .count;
+
+        let offset = (page - 1) * limit;
+        let entries = sqlx::query_as!(
+            AuditEntry,
+            r#"
+            SELECT id, action, actor_id, user_id, details, created_at
+            FROM audit_log
+            WHERE ($1::text IS NULL OR action = $1)
+              AND ($2::uuid IS NULL OR user_id = $2 OR actor_id = $2)
+              AND ($3::timestamptz IS NULL OR created_at >= $3)
+              AND ($4::timestamptz IS NULL OR created_at < $4)
+            ORDER BY
+              CASE WHEN $5 THEN created_at END ASC,
+              CASE WHEN NOT $5 THEN created_at END DESC,
+              id
+            LIMIT $6 OFFSET $7
+            "#,
+            query.action,
+            query.user_id,
+            query.from,
+            query.to,
+            ascending,
+            limit,
+            offset
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let has_more = offset + limit < total_count;
+
+        Ok((entries, total_count, has_more))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_pool;
+    use chrono::{DateTime, Duration, Utc};
+
+    async fn insert_entry(pool: &PgPool, action: &str, user_id: Uuid, created_at: DateTime<Utc>) {
+        sqlx::query!(
+            "INSERT INTO audit_log (action, actor_id, user_id, created_at) VALUES ($1, $2, $2, $3)",
+            action,
+            user_id,
+            created_at
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    async fn cleanup(pool: &PgPool, user_id: Uuid) {
+        sqlx::query!("DELETE FROM audit_log WHERE user_id = $1", user_id).execute(pool).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn filters_by_action_and_user() {
+        let pool = test_pool().await;
+        let user_id = Uuid::new_v4();
+        let now = Utc::now();
+        insert_entry(&pool, "login", user_id, now - Duration::minutes(3)).await;
+        insert_entry(&pool, "logout", user_id, now - Duration::minutes(2)).await;
+        insert_entry(&pool, "login", user_id, now - Duration::minutes(1)).await;
+        insert_entry(&pool, "login", Uuid::new_v4(), now).await;
+
+        let query = AuditLogQuery {
+            action: Some("login".to_string()),
+            user_id: Some(user_id),
+            ..Default::default()
+        };
+        let (entries, total_count, has_more) = AuditService::search(&pool, &query).await.unwrap();
+        assert_eq!(total_count, 2);
+        assert!(!has_more);
+        assert!(entries.iter().all(|e| e.action == "login" && e.user_id == Some(user_id)));
+        // Newest first by default
+        assert!(entries[0].created_at > entries[1].created_at);
+
+        cleanup(&pool, user_id).await;
+    }
+
+    #[tokio::test]
+    async fn filters_by_date_range_and_paginates() {
+        let pool = test_pool().await;
+        let user_id = Uuid::new_v4();
+        let start = Utc::now() - Duration::days(10);
+        for day in 0..5 {
+            insert_entry(&pool, "login", user_id, start + Duration::days(day)).await;
+        }
+
+        // Days 1, 2 and 3: `from` is inclusive, `to` exclusive
+        let query = AuditLogQuery {
+            user_id: Some(user_id),
+            from: Some(start + Duration::days(1)),
+            to: Some(start + Duration::days(4)),
+            sort: Some("asc".to_string()),
+            limit: Some(2),
+            ..Default::default()
+        };
+        let (entries, total_count, has_more) = AuditService::search(&pool, &query).await.unwrap();
+        assert_eq!(total_count, 3);
+        assert!(has_more);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].created_at.timestamp_millis(), (start + Duration::days(1)).timestamp_millis());
+        assert_eq!(entries[1].created_at.timestamp_millis(), (start + Duration::days(2)).timestamp_millis());
+
+        let (entries, _, has_more) = AuditService::search(&pool, &AuditLogQuery { page: Some(2), ..query }).await.unwrap();
+        assert!(!has_more);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].created_at.timestamp_millis(), (start + Duration::days(3)).timestamp_millis());
+
+        cleanup(&pool, user_id).await;
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_pages() {
+        let pool = test_pool().await;
+        let query = AuditLogQuery { limit: Some(MAX_PAGE_SIZE + 1), ..Default::default() };
+        assert!(AuditService::search(&pool, &query).await.is_err());
+    }
+}
@@ -0,0 +1,232 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use std::fmt;
+use crate::models::{CannedResponse, CannedResponseData};
+use crate::services::conversations::ConversationService;
+
+const MAX_TITLE_LENGTH: usize = 100;
+const MAX_CATEGORY_LENGTH: usize = 50;
+
+#[derive(Debug)]
+pub enum CannedResponseError {
+    NotFound,
+    Forbidden,
+    Invalid(&'static str),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for CannedResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CannedResponseError::NotFound => write!(f, "Canned response not found"),
+            CannedResponseError::Forbidden => write!(f, "Canned response belongs to another provider"),
+            CannedResponseError::Invalid(msg) => write!(f, "{}", msg),
+            CannedResponseError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for CannedResponseError {
+    fn from(e: sqlx::Error) -> Self {
+        CannedResponseError::Database(e)
+    }
+}
+
+pub struct CannedResponseService;
+
+impl CannedResponseService {
+    fn validate(data: &CannedResponseData) -> Result<(), CannedResponseError> {
+        let title = data.title.trim();
+        if title.is_empty() || title.chars().count() > MAX_TITLE_LENGTH {
+            return Err(CannedResponseError::Invalid("Title must be between 1 and 100 characters"));
+        }
+        if data.category.as_ref().is_some_and(|c| c.chars().count() > MAX_CATEGORY_LENGTH) {
+            return Err(CannedResponseError::Invalid("Category must be at most 50 characters"));
+        }
+        // The body is sent as a message, so it has to pass the same checks
+        ConversationService::validate_message_content(&data.body).map_err(CannedResponseError::Invalid)
+    }
+
+    pub async fn list(pool: &PgPool, provider_id: Uuid, category: Option<&str>) -> Result<Vec<CannedResponse>, sqlx::Error> {
+        sqlx::query_as!(
+            CannedResponse,
+            "
+            SELECT id, provider_id, title, body, category, created_at, updated_at
+            FROM canned_responses
+            WHERE provider_id = $1 AND ($2::TEXT IS NULL OR category = $2)
+            ORDER BY category NULLS FIRST, title
+            ",
+            provider_id,
+            category
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn create(pool: &PgPool, provider_id: Uuid, data: &CannedResponseData) -> Result<CannedResponse, CannedResponseError> {
+        Self::validate(data)?;
+
+        let canned_response = sqlx::query_as!(
+            CannedResponse,
+            "
+            INSERT INTO canned_responses (provider_id, title, body, category)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, provider_id, title, body, category, created_at, updated_at
+            ",
+            provider_id,
+            data.title.trim(),
+            data.body,
+            data.category
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(canned_response)
+    }
+
+    /// Loads a canned response, making sure it belongs to `provider_id`.
+    pub async fn get_owned(pool: &PgPool, provider_id: Uuid, id: Uuid) -> Result<CannedResponse, CannedResponseError> {
+        let canned_response = sqlx::query_as!(
+            CannedResponse,
+            "
+            SELECT id, provider_id, title, body, category, created_at, updated_at
+            FROM canned_responses
+            WHERE id = $1
+            ",
+            id
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or(CannedResponseError::NotFound)?;
+
+        if canned_response.provider_id != provider_id {
+            return Err(CannedResponseError::Forbidden);
+        }
+        Ok(canned_response)
+    }
+
+    pub async fn update(
+        pool: &PgPool,
+        provider_id: Uuid,
+        id: Uuid,
+        data: &CannedResponseData,
+    ) -> Result<CannedResponse, CannedResponseError> {
+        Self::validate(data)?;
+        Self::get_owned(pool, provider_id, id).await?;
+
+        sqlx::query_as!(
+            CannedResponse,
+            "
+            UPDATE canned_responses
+            SET title = $1, body = $2, category = $3
+            WHERE id = $4 AND provider_id = $5
+            RETURNING id, provider_id, title, body, category, created_at, updated_at
+            ",
+            data.title.trim(),
+            data.body,
+            data.category,
+            id,
+            provider_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or(CannedResponseError::NotFound)
+    }
+
+    pub async fn delete(pool: &PgPool, provider_id: Uuid, id: Uuid) -> Result<(), CannedResponseError> {
+        Self::get_owned(pool, provider_id, id).await?;
+
+        sqlx::query!(
+            "DELETE FROM canned_responses WHERE id = $1 AND provider_id = $2",
+            id,
+            provider_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The message text for a canned response the provider is sending.
+    pub async fn expand(pool: &PgPool, provider_id: Uuid, id: Uuid) -> Result<String, CannedResponseError> {
+        Ok(Self::get_owned(pool, provider_id, id).await?.body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{cleanup_users, insert_user, test_pool};
+
+    async fn setup() -> (PgPool, Uuid, Uuid) {
+        let pool = test_pool().await;
+
+        let mut provider_ids = Vec::new();
+        for _ in 0..2 {
+            let id = insert_user(&pool, "provider").await;
+            provider_ids.push(id);
+        }
+
+        (pool, provider_ids[0], provider_ids[1])
+    }
+
+
+    fn data(title: &str, body: &str, category: Option<&str>) -> CannedResponseData {
+        CannedResponseData {
+            title: title.to_string(),
+            body: body.to_string(),
+            category: category.map(str::to_string),
+        }
+    }
+
+    #[tokio::test]
+    async fn crud_and_expansion() {
+        let (pool, vet, other) = setup().await;
+
+        let post_op = CannedResponseService::create(&pool, vet, &data("Post-op", "Keep the cone on for 10 days.", Some("surgery"))).await.unwrap();
+        CannedResponseService::create(&pool, vet, &data("After hours", "We're closed, call the ER line.", None)).await.unwrap();
+        assert_eq!(CannedResponseService::list(&pool, vet, None).await.unwrap().len(), 2);
+        assert_eq!(CannedResponseService::list(&pool, vet, Some("surgery")).await.unwrap().len(), 1);
+
+        let updated = CannedResponseService::update(&pool, vet, post_op.id, &data("Post-op", "Keep the cone on for 14 days.", Some("surgery"))).await.unwrap();
+        assert_eq!(updated.body, "Keep the cone on for 14 days.");
+        assert_eq!(CannedResponseService::expand(&pool, vet, post_op.id).await.unwrap(), "Keep the cone on for 14 days.");
+
+        CannedResponseService::delete(&pool, vet, post_op.id).await.unwrap();
+        assert!(matches!(CannedResponseService::expand(&pool, vet, post_op.id).await, Err(CannedResponseError::NotFound)));
+
+        cleanup_users(&pool, &[vet, other]).await;
+    }
+
+    #[tokio::test]
+    async fn other_providers_templates_are_forbidden() {
+        let (pool, vet, other) = setup().await;
+        let template = CannedResponseService::create(&pool, vet, &data("Post-op", "Rest for a week.", None)).await.unwrap();
+
+        assert!(matches!(CannedResponseService::expand(&pool, other, template.id).await, Err(CannedResponseError::Forbidden)));
+        assert!(matches!(
+            CannedResponseService::update(&pool, other, template.id, &data("Mine now", "Hi", None)).await,
+            Err(CannedResponseError::Forbidden)
+        ));
+        assert!(matches!(CannedResponseService::delete(&pool, other, template.id).await, Err(CannedResponseError::Forbidden)));
+
+        cleanup_users(&pool, &[vet, other]).await;
+    }
+
+    #[tokio::test]
+    async fn body_uses_message_validation() {
+        let (pool, vet, other) = setup().await;
+
+        assert!(matches!(
+            CannedResponseService::create(&pool, vet, &data("Empty", "   ", None)).await,
+            Err(CannedResponseError::Invalid(_))
+        ));
+        let too_long = "a".repeat(crate::services::conversations::MAX_MESSAGE_LENGTH + 1);
+        assert!(matches!(
+            CannedResponseService::create(&pool, vet, &data("Long", &too_long, None)).await,
+            Err(CannedResponseError::Invalid(_))
+        ));
+
+        cleanup_users(&pool, &[vet, other]).await;
+    }
+}
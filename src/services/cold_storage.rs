@@ -0,0 +1,93 @@
+use chrono::{DateTime, Utc};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use sqlx::PgPool;
+use std::io::{Read, Write};
+
+pub fn compress(content: &str) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(content.as_bytes()).expect("writing to a Vec can't fail");
+    encoder.finish().expect("writing to a Vec can't fail")
+}
+
+pub fn decompress(compressed: &[u8]) -> std::io::Result<String> {
+    let mut content = String::new();
+    DeflateDecoder::new(compressed).read_to_string(&mut content)?;
+    Ok(content)
+}
+
+/// A message's content from whichever of the hot column and cold storage holds it.
+pub fn warm(content: Option<String>, compressed: Option<Vec<u8>>) -> Result<String, sqlx::Error> {
+    match (content, compressed) {
+        (Some(content), _) => Ok(content),
+        (None, Some(compressed)) => decompress(&compressed).map_err(|e| sqlx::Error::Decode(Box::new(e))),
+        (None, None) => Err(sqlx::Error::Decode("message has no content".into())),
+    }
+}
+
+pub struct ColdStorageService;
+
+impl ColdStorageService {
+    /// Compresses the content of up to `batch_size` messages sent before
+    /// `cutoff` into `messages_cold`, clearing it from `messages`. Returns how
+    /// many were moved.
+    pub async fn archive_batch(pool: &PgPool, cutoff: DateTime<Utc>, batch_size: i64) -> Result<u64, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let messages = sqlx::query!(
+            r#"
+            SELECT id, content AS "content!"
+            FROM messages
+            WHERE timestamp < $1 AND content IS NOT NULL
+            ORDER BY timestamp
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED
+            "#,
+            cutoff,
+            batch_size
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for message in &messages {
+            sqlx::query!(
+                "
+                INSERT INTO messages_cold (message_id, content_compressed, content_bytes)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (message_id)
+                DO UPDATE SET content_compressed = EXCLUDED.content_compressed,
+                              content_bytes = EXCLUDED.content_bytes,
+                              compressed_at = CURRENT_TIMESTAMP
+                ",
+                message.id,
+                compress(&message.content),
+                message.content.len() as i32
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!("UPDATE messages SET content = NULL WHERE id = $1", message.id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(messages.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_round_trips_through_compression() {
+        for content in ["", "Millie ate breakfast 🐶", &"Vomiting twice since last night. ".repeat(200)] {
+            let compressed = compress(content);
+            assert_eq!(decompress(&compressed).unwrap(), content);
+            assert_eq!(warm(None, Some(compressed)).unwrap(), content);
+        }
+        assert!(compress(&"a".repeat(4000)).len() < 100);
+        assert_eq!(warm(Some("hot".to_string()), None).unwrap(), "hot");
+        assert!(warm(None, Some(b"not deflate".to_vec())).is_err());
+    }
+}
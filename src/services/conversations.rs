@@ -1,158 +1,2419 @@
 use uuid::Uuid;
-use sqlx::PgPool;
-use crate::models::Conversation;
+use sqlx::{PgConnection, PgPool};
+use crate::models::{
+    Conversation, ConversationSummary, ConversationMembers, ConversationParticipants, DisplayProfile,
+    OrganizationSummary, ParticipantSummary, PetSummary, ReadState, Inbox, InboxConversation,
+    ConversationPreview, SenderMessageStats, ConversationAttachment, Pet, OpenedConversation
+};
 use chrono::{DateTime, Utc};
 use crate::models::Message;
+use crate::services::pet_shares::AccessLevel;
+use crate::services::audit::AuditService;
+use crate::services::cold_storage;
+use crate::services::organizations::OrganizationService;
+use crate::moderation::{MessageModerator, ModerationResult};
+use crate::storage::ImageStorage;
 use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::fmt;
+use std::future::Future;
+use futures::TryFutureExt;
+
+// How long a read waits before its one retry after losing the connection
+const READ_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+// Rough per-message overhead (ids, timestamps, JSON framing) in an export
+const EXPORT_BYTES_PER_MESSAGE: i64 = 256;
+
+pub const MAX_MESSAGE_LENGTH: usize = 4000;
+
+// A message row as stored, whose content may have been moved to cold storage
+struct StoredMessage {
+    id: Uuid,
+    conversation_id: Uuid,
+    sender_id: Uuid,
+    content: Option<String>,
+    content_compressed: Option<Vec<u8>>,
+    timestamp: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    edited_at: Option<DateTime<Utc>>,
+    message_type: String,
+    attachment_image_id: Option<Uuid>,
+    via_sms: bool,
+    deleted: bool,
+}
+
+// Every query that returns messages goes through here, so they all select the
+// same columns and the compiler checks them against `StoredMessage`. The
+// message is aliased `m`; the SQL after the columns supplies
+// `content_compressed`.
+macro_rules! query_stored_messages {
+    ($before:literal, $after:literal $(, $args:expr)* $(,)?) => {
+        sqlx::query_as!(
+            StoredMessage,
+            $before
+                + "m.id, m.conversation_id, m.sender_id, m.content, m.timestamp, m.updated_at, m.edited_at, "
+                + "m.message_type, m.attachment_image_id, m.via_sms, m.deleted_at IS NOT NULL AS \"deleted!\", "
+                + $after
+            $(, $args)*
+        )
+    };
+}
+
+impl StoredMessage {
+    fn into_message(self) -> Result<Message, sqlx::Error> {
+        // Deleted messages keep their place in the history but nothing of what they said
+        let (content, attachment_image_id) = match self.deleted {
+            true => (String::new(), None),
+            false => (cold_storage::warm(self.content, self.content_compressed)?, self.attachment_image_id),
+        };
+        Ok(Message {
+            id: self.id,
+            conversation_id: self.conversation_id,
+            sender_id: self.sender_id,
+            content,
+            timestamp: self.timestamp,
+            updated_at: self.updated_at,
+            edited_at: self.edited_at,
+            message_type: self.message_type,
+            attachment_image_id,
+            via_sms: self.via_sms,
+            deleted: self.deleted,
+        })
+    }
+}
+
+/// Most conversations `get_participants` will look up in one call.
+pub const MAX_PARTICIPANT_CONVERSATIONS: usize = 100;
+
+/// Most users `get_display_profiles` will look up in one call.
+pub const MAX_DISPLAY_PROFILES: usize = 200;
+
+/// Most providers a conversation can name.
+pub const MAX_CONVERSATION_PROVIDERS: usize = 10;
+
+#[derive(Debug)]
+pub enum AssignmentError {
+    NotFound,
+    Invalid(&'static str),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for AssignmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssignmentError::NotFound => write!(f, "Conversation not found"),
+            AssignmentError::Invalid(msg) => write!(f, "{}", msg),
+            AssignmentError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for AssignmentError {
+    fn from(e: sqlx::Error) -> Self {
+        AssignmentError::Database(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum SetProvidersError {
+    NotFound,
+    Forbidden,
+    Invalid(&'static str),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for SetProvidersError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SetProvidersError::NotFound => write!(f, "Conversation not found"),
+            SetProvidersError::Forbidden => write!(f, "Only the client can change the providers"),
+            SetProvidersError::Invalid(msg) => write!(f, "{}", msg),
+            SetProvidersError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for SetProvidersError {
+    fn from(e: sqlx::Error) -> Self {
+        SetProvidersError::Database(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum HistoryError {
+    Invalid(&'static str),
+    /// The page starts deeper than offset pagination allows; the client has to
+    /// continue with a `before` cursor.
+    CursorRequired,
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistoryError::Invalid(msg) => write!(f, "{}", msg),
+            HistoryError::CursorRequired => write!(f, "This page is too far back; continue with the `before` cursor instead"),
+            HistoryError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for HistoryError {
+    fn from(e: sqlx::Error) -> Self {
+        HistoryError::Database(e)
+    }
+}
+
+/// The most characters a reaction may have. Emoji built from several code
+/// points (skin tones, ZWJ families, flags) stay well under this.
+pub const MAX_EMOJI_CHARS: usize = 16;
+
+#[derive(Debug)]
+pub enum ReactionError {
+    /// No such message, or it was deleted.
+    NotFound,
+    /// The user can't post in the message's conversation.
+    Forbidden,
+    Invalid(&'static str),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for ReactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReactionError::NotFound => write!(f, "Message not found"),
+            ReactionError::Forbidden => write!(f, "You are not authorized to send messages in this conversation"),
+            ReactionError::Invalid(msg) => write!(f, "{}", msg),
+            ReactionError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for ReactionError {
+    fn from(e: sqlx::Error) -> Self {
+        ReactionError::Database(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum SendMessageError {
+    /// Moderation refused the content; holds the rules it matched.
+    Blocked(Vec<&'static str>),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for SendMessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendMessageError::Blocked(_) => write!(f, "Message blocked by content moderation"),
+            SendMessageError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for SendMessageError {
+    fn from(e: sqlx::Error) -> Self {
+        SendMessageError::Database(e)
+    }
+}
+
+/// Whether `e` means the database connection was lost or couldn't be had, rather
+/// than the query itself failing.
+pub fn is_connection_error(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::WorkerCrashed => true,
+        // Class 08 is connection exceptions; 57P01-57P03 are shutdowns and startup
+        sqlx::Error::Database(db) => db.code().is_some_and(|code| {
+            code.starts_with("08") || matches!(code.as_ref(), "57P01" | "57P02" | "57P03")
+        }),
+        _ => false,
+    }
+}
+
+/// The unique constraint a statement violated, if that's why it failed.
+/// Matched on the SQLSTATE rather than the message, so it holds up to
+/// constraints being renamed.
+pub fn unique_violation(e: &sqlx::Error) -> Option<&str> {
+    match e {
+        sqlx::Error::Database(db) if db.is_unique_violation() => Some(db.constraint().unwrap_or_default()),
+        _ => None,
+    }
+}
+
+// Runs a read, retrying it once if the connection dropped (e.g. during a
+// failover). Only for idempotent queries.
+async fn retry_read<T, F, Fut>(read: F) -> Result<T, sqlx::Error>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    match read().await {
+        Err(e) if is_connection_error(&e) => {
+            logln!("Retrying read after connection error: {}", e);
+            actix_web::rt::time::sleep(READ_RETRY_DELAY).await;
+            read().await
+        },
+        result => result,
+    }
+}
 
 pub struct ConversationService;
 
 impl ConversationService {
     pub async fn get_conversations_by_client_id(pool: &PgPool, client_id: Uuid) -> Result<Vec<Conversation>> {
-        let result = sqlx::query_as!(
+        let result = retry_read(|| sqlx::query_as!(
             Conversation,
             "
-            SELECT id, providers, client, pet, last_message, last_updated_timestamp
+            SELECT id, providers, client, pet, last_message, last_updated_timestamp, assigned_provider, created_at, organization_id, message_count
             FROM conversations
             WHERE client = $1
             ORDER BY last_updated_timestamp DESC
             ",
             client_id
         )
-        .fetch_all(pool)
+        .fetch_all(pool))
         .await;
 
         match result {
             Ok(conversations) => Ok(conversations),
             Err(e) => {
-                eprintln!("Database error: {:?}", e);
+                elogln!("Database error: {:?}", e);
                 Err(anyhow::anyhow!("Failed to fetch conversations: {}", e))
             }
         }
     }
 
     pub async fn get_conversations_by_provider_id(pool: &PgPool, provider_id: Uuid) -> Result<Vec<Conversation>, sqlx::Error> {
-        sqlx::query_as!(
+        retry_read(|| sqlx::query_as!(
             Conversation,
             "
-            SELECT id, providers, client, pet, last_message, last_updated_timestamp
+            SELECT id, providers, client, pet, last_message, last_updated_timestamp, assigned_provider, created_at, organization_id, message_count
             FROM conversations
             WHERE $1 = ANY(providers)
+               OR organization_id IN (SELECT organization_id FROM organization_members WHERE user_id = $1)
             ORDER BY last_updated_timestamp DESC
             ",
             provider_id
         )
-        .fetch_all(pool)
+        .fetch_all(pool))
         .await
     }
 
-    pub async fn create_conversation(pool: &PgPool, providers: Vec<Uuid>, client: Uuid, pet: Uuid) -> Result<Conversation, sqlx::Error> {
-        sqlx::query_as!(
+    pub async fn get_conversation_by_id(pool: &PgPool, conversation_id: Uuid) -> Result<Option<Conversation>, sqlx::Error> {
+        retry_read(|| sqlx::query_as!(
+            Conversation,
+            "
+            SELECT id, providers, client, pet, last_message, last_updated_timestamp, assigned_provider, created_at, organization_id, message_count
+            FROM conversations
+            WHERE id = $1
+            ",
+            conversation_id
+        )
+        .fetch_optional(pool))
+        .await
+    }
+
+    /// Sets (or with `None`, clears) the provider who owns the case. The client and
+    /// any of the conversation's providers may change it, but only to one of the
+    /// conversation's providers. Current members of the conversation's
+    /// organization count as its providers.
+    pub async fn assign_provider(
+        pool: &PgPool,
+        user_id: Uuid,
+        conversation_id: Uuid,
+        provider_id: Option<Uuid>,
+    ) -> Result<Conversation, AssignmentError> {
+        let conversation = Self::get_conversation_by_id(pool, conversation_id)
+            .await?
+            .ok_or(AssignmentError::NotFound)?;
+        if conversation.client != user_id && !Self::is_provider_of(pool, &conversation, user_id).await? {
+            return Err(AssignmentError::NotFound);
+        }
+        if let Some(provider_id) = provider_id {
+            if !Self::is_provider_of(pool, &conversation, provider_id).await? {
+                return Err(AssignmentError::Invalid("Assigned provider must be one of the conversation's providers"));
+            }
+        }
+
+        let conversation = sqlx::query_as!(
+            Conversation,
+            "
+            UPDATE conversations
+            SET assigned_provider = $1
+            WHERE id = $2
+            RETURNING id, providers, client, pet, last_message, last_updated_timestamp, assigned_provider, created_at, organization_id, message_count
+            ",
+            provider_id,
+            conversation_id
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(conversation)
+    }
+
+    /// Makes a member of the conversation's organization its assigned provider,
+    /// taking it over from whoever it was routed or assigned to. The previous
+    /// assignee is replaced among its providers, so it no longer counts towards
+    /// their load. Returns the updated conversation and
+    /// the previous assignee.
+    pub async fn take_over(
+        pool: &PgPool,
+        provider_id: Uuid,
+        conversation_id: Uuid,
+    ) -> Result<(Conversation, Option<Uuid>), AssignmentError> {
+        let conversation = Self::get_conversation_by_id(pool, conversation_id)
+            .await?
+            .ok_or(AssignmentError::NotFound)?;
+        let is_member = match conversation.organization_id {
+            Some(organization_id) => OrganizationService::is_member(pool, organization_id, provider_id).await?,
+            None => false,
+        };
+        if !is_member {
+            return match Self::get_access(pool, conversation_id, provider_id).await? {
+                Some(_) => Err(AssignmentError::Invalid("Only members of the conversation's organization can take it over")),
+                None => Err(AssignmentError::NotFound),
+            };
+        }
+        if conversation.assigned_provider == Some(provider_id) {
+            return Err(AssignmentError::Invalid("You're already assigned to this conversation"));
+        }
+
+        let row = sqlx::query!(
+            "
+            UPDATE conversations c
+            SET providers = array_append(array_remove(array_remove(c.providers, previous.assigned_provider), $1), $1),
+                assigned_provider = $1
+            FROM (SELECT id, assigned_provider FROM conversations WHERE id = $2 FOR UPDATE) previous
+            WHERE c.id = previous.id
+            RETURNING c.id, c.providers, c.client, c.pet, c.last_message, c.last_updated_timestamp,
+                      c.assigned_provider, c.created_at, c.organization_id, c.message_count,
+                      previous.assigned_provider AS previous_assignee
+            ",
+            provider_id,
+            conversation_id
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok((
+            Conversation {
+                id: row.id,
+                providers: row.providers,
+                client: row.client,
+                pet: row.pet,
+                last_message: row.last_message,
+                last_updated_timestamp: row.last_updated_timestamp,
+                assigned_provider: row.assigned_provider,
+                created_at: row.created_at,
+                organization_id: row.organization_id,
+                message_count: row.message_count,
+            },
+            row.previous_assignee,
+        ))
+    }
+
+    /// Replaces the conversation's providers in one update, so concurrent
+    /// changes can't interleave. Only the client may do this. An assigned
+    /// provider who is dropped is unassigned. Returns the updated conversation
+    /// and the providers it had before.
+    pub async fn set_providers(
+        pool: &PgPool,
+        user_id: Uuid,
+        conversation_id: Uuid,
+        mut providers: Vec<Uuid>,
+    ) -> Result<(Conversation, Vec<Uuid>), SetProvidersError> {
+        let mut seen = std::collections::HashSet::new();
+        providers.retain(|id| seen.insert(*id));
+        if providers.len() > MAX_CONVERSATION_PROVIDERS {
+            return Err(SetProvidersError::Invalid("A conversation can have at most 10 providers"));
+        }
+        let valid = sqlx::query!(
+            r#"SELECT COUNT(*) AS "count!" FROM users WHERE id = ANY($1) AND scope = 'provider'"#,
+            &providers
+        )
+        .fetch_one(pool)
+        .await?
+        .count;
+        if valid as usize != providers.len() {
+            return Err(SetProvidersError::Invalid("Every id must belong to a provider"));
+        }
+
+        let updated = sqlx::query!(
+            "
+            UPDATE conversations c
+            SET providers = $1,
+                assigned_provider = CASE WHEN c.assigned_provider = ANY($1) THEN c.assigned_provider END
+            FROM (SELECT id, providers FROM conversations WHERE id = $2 AND client = $3 FOR UPDATE) previous
+            WHERE c.id = previous.id
+            RETURNING c.id, c.providers, c.client, c.pet, c.last_message, c.last_updated_timestamp,
+                      c.assigned_provider, c.created_at, c.organization_id, c.message_count,
+                      previous.providers AS previous_providers
+            ",
+            &providers,
+            conversation_id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        match updated {
+            Some(row) => Ok((
+                Conversation {
+                    id: row.id,
+                    providers: row.providers,
+                    client: row.client,
+                    pet: row.pet,
+                    last_message: row.last_message,
+                    last_updated_timestamp: row.last_updated_timestamp,
+                    assigned_provider: row.assigned_provider,
+                    created_at: row.created_at,
+                    organization_id: row.organization_id,
+                    message_count: row.message_count,
+                },
+                row.previous_providers,
+            )),
+            // Participants learn it's client-only; anyone else can't tell it exists
+            None => match Self::get_access(pool, conversation_id, user_id).await? {
+                Some(_) => Err(SetProvidersError::Forbidden),
+                None => Err(SetProvidersError::NotFound),
+            },
+        }
+    }
+
+    async fn is_provider_of(pool: &PgPool, conversation: &Conversation, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        if conversation.providers.contains(&user_id) {
+            return Ok(true);
+        }
+        match conversation.organization_id {
+            Some(organization_id) => OrganizationService::is_member(pool, organization_id, user_id).await,
+            None => Ok(false),
+        }
+    }
+
+    /// Creates a conversation, returning it with `true` if it is new. With `dedupe`
+    /// set, an existing conversation between the same client, pet, set of
+    /// providers and organization is returned instead (with `false`) so retries
+    /// and double-taps don't clutter inboxes.
+    /// Without providers, a conversation with an organization that routes
+    /// round-robin goes to one of its members, who becomes its assigned provider.
+    pub async fn create_conversation(
+        pool: &PgPool,
+        providers: Vec<Uuid>,
+        organization_id: Option<Uuid>,
+        client: Uuid,
+        pet: Uuid,
+        dedupe: bool,
+    ) -> Result<(Conversation, bool), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        if dedupe {
+            // Serialize creation per client and pet so concurrent requests can't both insert
+            sqlx::query!(
+                "SELECT pg_advisory_xact_lock(hashtext($1::uuid::text || $2::uuid::text))",
+                client,
+                pet
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+
+            let existing = sqlx::query_as!(
+                Conversation,
+                "
+                SELECT id, providers, client, pet, last_message, last_updated_timestamp, assigned_provider, created_at, organization_id, message_count
+                FROM conversations
+                WHERE client = $1 AND pet = $2
+                  AND ((providers @> $3 AND providers <@ $3)
+                       OR (cardinality($3::uuid[]) = 0 AND $4::uuid IS NOT NULL AND assigned_provider IS NOT NULL))
+                  AND organization_id IS NOT DISTINCT FROM $4
+                ORDER BY last_updated_timestamp DESC
+                LIMIT 1
+                ",
+                client,
+                pet,
+                &providers,
+                organization_id as Option<Uuid>
+            )
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            if let Some(conversation) = existing {
+                tx.commit().await?;
+                return Ok((conversation, false));
+            }
+        }
+
+        // Organizations that route round-robin hand an unaddressed inquiry to one member
+        let routed_to = match organization_id {
+            Some(organization_id) if providers.is_empty() => OrganizationService::pick_provider(&mut tx, organization_id).await?,
+            _ => None,
+        };
+        let providers = routed_to.map_or(providers, |provider| vec![provider]);
+
+        let conversation = sqlx::query_as!(
             Conversation,
             "
-            INSERT INTO conversations (providers, client, pet, last_message, last_updated_timestamp)
-            VALUES ($1, $2, $3, '', CURRENT_TIMESTAMP)
-            RETURNING id, providers, client, pet, last_message, last_updated_timestamp
+            INSERT INTO conversations (providers, client, pet, organization_id, assigned_provider, last_message, last_updated_timestamp)
+            VALUES ($1, $2, $3, $4, $5, '', CURRENT_TIMESTAMP)
+            RETURNING id, providers, client, pet, last_message, last_updated_timestamp, assigned_provider, created_at, organization_id, message_count
             ",
             &providers,
             client,
-            pet
+            pet,
+            organization_id as Option<Uuid>,
+            routed_to as Option<Uuid>
         )
-        .fetch_one(pool)
-        .await
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok((conversation, true))
+    }
+
+    /// Where an SMS reply from `user_id` belongs: the conversation named by
+    /// `short_code` if it's one of theirs, otherwise the one with the most recent
+    /// activity. A short code is the first six hex digits of the conversation id,
+    /// which only needs to be unique among one user's conversations.
+    pub async fn find_sms_reply_conversation(
+        pool: &PgPool,
+        user_id: Uuid,
+        short_code: Option<&str>,
+    ) -> Result<Option<Uuid>, sqlx::Error> {
+        let row = sqlx::query!(
+            "
+            SELECT id FROM conversations
+            WHERE client = $1 OR $1 = ANY(providers)
+               OR organization_id IN (SELECT organization_id FROM organization_members WHERE user_id = $1)
+            ORDER BY (left(id::text, 6) = lower($2)) DESC NULLS LAST, last_updated_timestamp DESC
+            LIMIT 1
+            ",
+            user_id,
+            short_code
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(row.map(|row| row.id))
+    }
+
+    /// Checks message text before it's stored. Anything that ends up as a message
+    /// body (including canned responses) goes through this.
+    pub fn validate_message_content(content: &str) -> Result<(), &'static str> {
+        if content.trim().is_empty() {
+            return Err("Message content can't be empty");
+        }
+        if content.chars().count() > MAX_MESSAGE_LENGTH {
+            return Err("Message content is too long (max 4000 characters)");
+        }
+        Ok(())
     }
 
+    /// Stores a user's message after running it past `moderator`, which may refuse
+    /// it or redact parts of it. Moderation actions are audited. `via_sms` marks
+    /// replies that arrived by text message rather than through the app.
+    #[allow(clippy::too_many_arguments)]
     pub async fn send_message(
         pool: &PgPool,
+        moderator: &dyn MessageModerator,
+        sender_id: Uuid,
+        conversation_id: Uuid,
+        content: String,
+        timestamp: DateTime<Utc>,
+        attachment_image_id: Option<Uuid>,
+        via_sms: bool,
+    ) -> Result<Message, SendMessageError> {
+        let content = match moderator.moderate(&content) {
+            ModerationResult::Allowed => content,
+            ModerationResult::Redacted { content, rules } => {
+                AuditService::record(pool, "message_redacted", Some(sender_id), None, serde_json::json!({
+                    "conversation_id": conversation_id,
+                    "rules": rules
+                })).await;
+                content
+            },
+            ModerationResult::Blocked { rules } => {
+                AuditService::record(pool, "message_blocked", Some(sender_id), None, serde_json::json!({
+                    "conversation_id": conversation_id,
+                    "rules": rules
+                })).await;
+                return Err(SendMessageError::Blocked(rules));
+            },
+        };
+
+        Ok(Self::insert_message(pool, sender_id, conversation_id, content, timestamp, "text", attachment_image_id, via_sms).await?)
+    }
+
+    // System messages record server-side events (e.g. appointment changes) in the
+    // conversation history. They are attributed to the user who triggered them.
+    pub async fn send_system_message(
+        pool: &PgPool,
+        actor_id: Uuid,
+        conversation_id: Uuid,
+        content: String,
+    ) -> Result<Message, sqlx::Error> {
+        Self::insert_message(pool, actor_id, conversation_id, content, Utc::now(), "system", None, false).await
+    }
+
+    /// `send_system_message` within the caller's transaction, so the message is
+    /// only recorded if the change it announces is.
+    pub async fn send_system_message_in(
+        conn: &mut PgConnection,
+        actor_id: Uuid,
+        conversation_id: Uuid,
+        content: String,
+    ) -> Result<Message, sqlx::Error> {
+        Self::write_message(conn, actor_id, conversation_id, content, Utc::now(), "system", None, false).await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn insert_message(
+        pool: &PgPool,
+        sender_id: Uuid,
+        conversation_id: Uuid,
+        content: String,
+        timestamp: DateTime<Utc>,
+        message_type: &str,
+        attachment_image_id: Option<Uuid>,
+        via_sms: bool,
+    ) -> Result<Message, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let message = Self::write_message(&mut tx, sender_id, conversation_id, content, timestamp, message_type, attachment_image_id, via_sms).await?;
+        tx.commit().await?;
+        Ok(message)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn write_message(
+        conn: &mut PgConnection,
         sender_id: Uuid,
         conversation_id: Uuid,
         content: String,
-        timestamp: DateTime<Utc>
+        timestamp: DateTime<Utc>,
+        message_type: &str,
+        attachment_image_id: Option<Uuid>,
+        via_sms: bool,
     ) -> Result<Message, sqlx::Error> {
         // First insert the message
-        let message = sqlx::query_as!(
-            Message,
-            r#"
-            INSERT INTO messages (conversation_id, sender_id, content, timestamp, updated_at)
-            VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP)
-            RETURNING id, conversation_id, sender_id, content, timestamp, updated_at
-            "#,
+        let message = query_stored_messages!(
+            "
+            INSERT INTO messages AS m (conversation_id, sender_id, content, timestamp, message_type, attachment_image_id, via_sms)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING ",
+            r#"NULL::BYTEA AS "content_compressed?""#,
             conversation_id,
             sender_id,
             content,
-            timestamp
+            timestamp,
+            message_type,
+            attachment_image_id,
+            via_sms
         )
-        .fetch_one(pool)
-        .await?;
+        .fetch_one(&mut *conn)
+        .await?
+        .into_message()?;
 
-        // Update the conversation's last_message and last_updated_timestamp
+        // Update the conversation's last_message, last_updated_timestamp and message_count
         sqlx::query!(
             r#"
             UPDATE conversations
             SET last_message = $1,
-                last_updated_timestamp = $2
+                last_updated_timestamp = $2,
+                message_count = message_count + 1
             WHERE id = $3
             "#,
             content,
             timestamp,
             conversation_id
         )
-        .execute(pool)
+        .execute(&mut *conn)
         .await?;
 
         Ok(message)
     }
 
+    /// A page of messages, newest first. With `before` (a message id) the page
+    /// holds the messages older than that one and `page` is ignored; otherwise
+    /// pages are counted from the newest message, down to at most `max_offset`
+    /// messages deep.
     pub async fn get_conversation_messages(
         pool: &PgPool, 
         conversation_id: Uuid, 
         page: i32, 
-        limit: i32
-    ) -> Result<(Vec<Message>, i32, bool), sqlx::Error> {
+        limit: i32,
+        before: Option<Uuid>,
+        max_offset: i32,
+    ) -> Result<(Vec<Message>, i32, bool), HistoryError> {
         // Validate input parameters
-        if page < 1 {
-            return Err(sqlx::Error::Protocol("Invalid page number: must be >= 1".to_string()));
+        if !(1..=100).contains(&limit) {
+            return Err(HistoryError::Invalid("Invalid limit: must be between 1 and 100"));
         }
-        if limit < 1 || limit > 100 {
-            return Err(sqlx::Error::Protocol("Invalid limit: must be between 1 and 100".to_string()));
+        if let Some(before) = before {
+            return retry_read(|| Self::fetch_messages_before(pool, conversation_id, before, limit))
+                .await?
+                .ok_or(HistoryError::Invalid("Unknown cursor: `before` must be a message in this conversation"));
+        }
+        if page < 1 {
+            return Err(HistoryError::Invalid("Invalid page number: must be >= 1"));
         }
         
         // Calculate offset - FIX: Use (page - 1) * limit for 1-based pagination
-        let offset = (page - 1) * limit;
+        let offset = (page - 1).saturating_mul(limit);
+        if offset > max_offset {
+            return Err(HistoryError::CursorRequired);
+        }
         
         // Debug logging
-        println!("Fetching conversation history: conversation_id={}, page={}, limit={}, offset={}", 
+        logln!("Fetching conversation history: conversation_id={}, page={}, limit={}, offset={}", 
                  conversation_id, page, limit, offset);
         
-        // Get total count
-        let total_count = sqlx::query!(
-            "SELECT COUNT(*) as count FROM messages WHERE conversation_id = $1",
+        Ok(retry_read(|| Self::fetch_messages_page(pool, conversation_id, limit, offset)).await?)
+    }
+
+    // The maintained `message_count`, which saves counting the messages on
+    // every history request
+    async fn cached_message_count(pool: &PgPool, conversation_id: Uuid) -> Result<i32, sqlx::Error> {
+        let row = sqlx::query!("SELECT message_count FROM conversations WHERE id = $1", conversation_id)
+            .fetch_optional(pool)
+            .await?;
+        Ok(row.map_or(0, |row| row.message_count as i32))
+    }
+
+    /// Takes a user's messages out of their conversations' counts, ahead of
+    /// deleting the user (which deletes the messages with them).
+    pub async fn discount_messages_from(conn: &mut PgConnection, sender_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "
+            UPDATE conversations c
+            SET message_count = GREATEST(c.message_count - sent.message_count, 0)
+            FROM (SELECT conversation_id, COUNT(*) AS message_count FROM messages WHERE sender_id = $1 GROUP BY conversation_id) sent
+            WHERE sent.conversation_id = c.id
+            ",
+            sender_id
+        )
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Recounts every conversation's messages and corrects any `message_count`
+    /// that has drifted. Returns how many were corrected.
+    pub async fn reconcile_message_counts(pool: &PgPool) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE conversations c
+            SET message_count = actual.message_count
+            FROM (
+                SELECT c2.id, COUNT(m.id) AS message_count
+                FROM conversations c2
+                LEFT JOIN messages m ON m.conversation_id = c2.id
+                GROUP BY c2.id
+            ) actual
+            WHERE actual.id = c.id AND c.message_count <> actual.message_count
+            "#
+        )
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    // Keyset pagination: walks the history index from the cursor, so it
+    // costs the same however far back the cursor is. None if the cursor isn't a
+    // message in the conversation.
+    async fn fetch_messages_before(
+        pool: &PgPool,
+        conversation_id: Uuid,
+        before: Uuid,
+        limit: i32,
+    ) -> Result<Option<(Vec<Message>, i32, bool)>, sqlx::Error> {
+        let cursor = sqlx::query!(
+            "SELECT timestamp FROM messages WHERE id = $1 AND conversation_id = $2",
+            before,
             conversation_id
         )
-        .fetch_one(pool)
+        .fetch_optional(pool)
+        .await?;
+        let Some(cursor) = cursor else {
+            return Ok(None);
+        };
+
+        let total_count = Self::cached_message_count(pool, conversation_id).await?;
+
+        // One extra row tells us whether there is another page
+        let mut messages = query_stored_messages!(
+            "SELECT ",
+            r#"mc.content_compressed AS "content_compressed?"
+             FROM messages m
+             LEFT JOIN messages_cold mc ON mc.message_id = m.id
+             WHERE m.conversation_id = $1 AND (m.timestamp, m.id) < ($2, $3)
+             ORDER BY m.timestamp DESC, m.id DESC
+             LIMIT $4"#,
+            conversation_id,
+            cursor.timestamp,
+            before,
+            limit as i64 + 1
+        )
+        .fetch_all(pool)
         .await?
-        .count
-        .unwrap_or(0) as i32;
+        .into_iter()
+        .map(StoredMessage::into_message)
+        .collect::<Result<Vec<_>, _>>()?;
+
+        let has_more = messages.len() > limit as usize;
+        messages.truncate(limit as usize);
+        Ok(Some((messages, total_count, has_more)))
+    }
+
+    async fn fetch_messages_page(
+        pool: &PgPool,
+        conversation_id: Uuid,
+        limit: i32,
+        offset: i32,
+    ) -> Result<(Vec<Message>, i32, bool), sqlx::Error> {
+        // Get total count
+        let total_count = Self::cached_message_count(pool, conversation_id).await?;
         
         // Get messages with pagination
-        let messages = sqlx::query_as!(
-            Message,
-            "SELECT id, conversation_id, sender_id, content, timestamp, updated_at
-             FROM messages 
-             WHERE conversation_id = $1 
-             ORDER BY timestamp DESC 
-             LIMIT $2 OFFSET $3",
+        let messages = query_stored_messages!(
+            "SELECT ",
+            r#"mc.content_compressed AS "content_compressed?"
+             FROM messages m
+             LEFT JOIN messages_cold mc ON mc.message_id = m.id
+             WHERE m.conversation_id = $1
+             ORDER BY m.timestamp DESC, m.id DESC
+             LIMIT $2 OFFSET $3"#,
             conversation_id,
             limit as i64,
             offset as i64
         )
         .fetch_all(pool)
-        .await?;
+        .await?
+        .into_iter()
+        .map(StoredMessage::into_message)
+        .collect::<Result<Vec<_>, _>>()?;
         
         // Calculate if there are more messages
         let has_more = (offset + limit) < total_count;
         
         Ok((messages, total_count, has_more))
     }
-}
 
+    /// The user's access to a conversation. The client, providers and current
+    /// members of its organization have full access; users the pet is shared
+    /// with get the share's permissions.
+    pub async fn get_access(pool: &PgPool, conversation_id: Uuid, user_id: Uuid) -> Result<Option<AccessLevel>, sqlx::Error> {
+        let record = retry_read(|| sqlx::query!(
+            r#"
+            SELECT (c.client = $2 OR $2 = ANY(c.providers) OR EXISTS (
+                       SELECT 1 FROM organization_members om
+                       WHERE om.organization_id = c.organization_id AND om.user_id = $2
+                   )) AS "is_participant!",
+                   s.permissions AS "permissions?"
+            FROM conversations c
+            LEFT JOIN pet_shares s
+              ON s.pet_id = c.pet AND s.shared_with_user_id = $2 AND s.status = 'accepted'
+            WHERE c.id = $1
+            "#,
+            conversation_id,
+            user_id
+        )
+        .fetch_optional(pool))
+        .await?;
+
+        Ok(match record {
+            Some(record) if record.is_participant => Some(AccessLevel::ReadWrite),
+            Some(record) => record.permissions.as_deref().and_then(AccessLevel::from_permissions),
+            None => None,
+        })
+    }
+
+    /// Participants and pets for the requested conversations the user can access,
+    /// in the order requested. Others are silently left out. Each user appears once
+    /// in `users` however many of the conversations they're in. Runs three queries
+    /// regardless of how many conversations are requested.
+    pub async fn get_participants(
+        pool: &PgPool,
+        user_id: Uuid,
+        conversation_ids: &[Uuid],
+    ) -> Result<ConversationParticipants, sqlx::Error> {
+        retry_read(|| Self::fetch_participants(pool, user_id, conversation_ids)).await
+    }
+
+    async fn fetch_participants(
+        pool: &PgPool,
+        user_id: Uuid,
+        conversation_ids: &[Uuid],
+    ) -> Result<ConversationParticipants, sqlx::Error> {
+        let conversations = sqlx::query!(
+            r#"
+            SELECT c.id AS "id!", c.client AS "client!", c.providers AS "providers!", c.assigned_provider, c.pet AS "pet!",
+                   o.id AS "organization_id?", o.name AS "organization_name?"
+            FROM conversations c
+            LEFT JOIN organizations o ON o.id = c.organization_id
+            WHERE c.id = ANY($1)
+              AND (c.client = $2 OR $2 = ANY(c.providers) OR EXISTS (
+                  SELECT 1 FROM organization_members om
+                  WHERE om.organization_id = c.organization_id AND om.user_id = $2
+              ) OR EXISTS (
+                  SELECT 1 FROM pet_shares s
+                  WHERE s.pet_id = c.pet AND s.shared_with_user_id = $2 AND s.status = 'accepted'
+              ))
+            "#,
+            conversation_ids,
+            user_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut user_ids: Vec<Uuid> = conversations
+            .iter()
+            .flat_map(|c| std::iter::once(c.client).chain(c.providers.iter().copied()))
+            .collect();
+        user_ids.sort_unstable();
+        user_ids.dedup();
+        let pet_ids: Vec<Uuid> = conversations.iter().map(|c| c.pet).collect();
+
+        let users = sqlx::query_as!(
+            ParticipantSummary,
+            "
+            SELECT id, scope, first_name, last_name, profile_image_url
+            FROM users
+            WHERE id = ANY($1)
+            ",
+            &user_ids
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let pets: HashMap<Uuid, PetSummary> = sqlx::query_as!(
+            PetSummary,
+            "
+            SELECT id, user_id, name, species, breed, pet_image_url
+            FROM pets
+            WHERE id = ANY($1)
+            ",
+            &pet_ids
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|pet| (pet.id, pet))
+        .collect();
+
+        let mut by_id: HashMap<Uuid, ConversationMembers> = conversations
+            .into_iter()
+            .map(|c| (c.id, ConversationMembers {
+                conversation_id: c.id,
+                client: c.client,
+                providers: c.providers,
+                assigned_provider: c.assigned_provider,
+                pet: pets.get(&c.pet).cloned(),
+                organization: c.organization_id
+                    .zip(c.organization_name)
+                    .map(|(id, name)| OrganizationSummary { id, name }),
+            }))
+            .collect();
+
+        Ok(ConversationParticipants {
+            conversations: conversation_ids.iter().filter_map(|id| by_id.remove(id)).collect(),
+            users: users.into_iter().map(|user| (user.id, user)).collect(),
+        })
+    }
+
+    /// Names and avatars for the requested users the viewer shares a conversation
+    /// with, plus the viewer themselves. Others are silently left out.
+    pub async fn get_display_profiles(pool: &PgPool, viewer_id: Uuid, user_ids: &[Uuid]) -> Result<HashMap<Uuid, DisplayProfile>, sqlx::Error> {
+        let users = retry_read(|| sqlx::query_as!(
+            ParticipantSummary,
+            "
+            SELECT u.id, u.scope, u.first_name, u.last_name, u.profile_image_url
+            FROM users u
+            WHERE u.id = ANY($1) AND (u.id = $2 OR EXISTS (
+                SELECT 1 FROM conversations c
+                WHERE (c.client = $2 OR $2 = ANY(c.providers)
+                       OR EXISTS (
+                           SELECT 1 FROM organization_members om
+                           WHERE om.organization_id = c.organization_id AND om.user_id = $2
+                       )
+                       OR EXISTS (
+                           SELECT 1 FROM pet_shares s
+                           WHERE s.pet_id = c.pet AND s.shared_with_user_id = $2 AND s.status = 'accepted'
+                       ))
+                  AND (c.client = u.id OR u.id = ANY(c.providers)
+                       OR EXISTS (
+                           SELECT 1 FROM organization_members om
+                           WHERE om.organization_id = c.organization_id AND om.user_id = u.id
+                       ))
+            ))
+            ",
+            user_ids,
+            viewer_id
+        )
+        .fetch_all(pool))
+        .await?;
+
+        Ok(users
+            .into_iter()
+            .map(|user| (user.id, DisplayProfile {
+                display_name: user.display_name(),
+                profile_image_url: user.profile_image_url,
+            }))
+            .collect())
+    }
+
+    /// When the conversation was started and when its first message was sent, if any.
+    pub async fn get_timestamps(pool: &PgPool, conversation_id: Uuid) -> Result<Option<(DateTime<Utc>, Option<DateTime<Utc>>)>, sqlx::Error> {
+        let row = retry_read(|| sqlx::query!(
+            "
+            SELECT c.created_at,
+                   (SELECT MIN(m.timestamp) FROM messages m WHERE m.conversation_id = c.id) AS first_message_at
+            FROM conversations c
+            WHERE c.id = $1
+            ",
+            conversation_id
+        )
+        .fetch_optional(pool))
+        .await?;
+
+        Ok(row.map(|row| (row.created_at, row.first_message_at)))
+    }
+
+    /// A page of the images attached to the conversation's messages, newest
+    /// first, and whether there are more. With `before` (a message id) the page
+    /// holds the attachments sent before that message.
+    pub async fn get_attachments(
+        pool: &PgPool,
+        conversation_id: Uuid,
+        limit: i64,
+        before: Option<Uuid>,
+    ) -> Result<(Vec<ConversationAttachment>, bool), HistoryError> {
+        if !(1..=100).contains(&limit) {
+            return Err(HistoryError::Invalid("Invalid limit: must be between 1 and 100"));
+        }
+        let cursor = match before {
+            Some(before) => {
+                let cursor = sqlx::query!(
+                    "SELECT timestamp FROM messages WHERE id = $1 AND conversation_id = $2",
+                    before,
+                    conversation_id
+                )
+                .fetch_optional(pool)
+                .await?
+                .ok_or(HistoryError::Invalid("Unknown cursor: `before` must be a message in this conversation"))?;
+                Some((cursor.timestamp, before))
+            },
+            None => None,
+        };
+        let (before_timestamp, before_id) = cursor.unzip();
+
+        // One extra row tells us whether there is another page
+        let mut attachments = retry_read(|| sqlx::query_as!(
+            ConversationAttachment,
+            r#"
+            SELECT i.id AS image_id, i.image_url, m.sender_id, m.id AS message_id, m.timestamp
+            FROM messages m
+            JOIN images i ON i.id = m.attachment_image_id
+            WHERE m.conversation_id = $1
+              AND m.attachment_image_id IS NOT NULL
+              AND ($2::TIMESTAMPTZ IS NULL OR (m.timestamp, m.id) < ($2, $3))
+            ORDER BY m.timestamp DESC, m.id DESC
+            LIMIT $4
+            "#,
+            conversation_id,
+            before_timestamp,
+            before_id,
+            limit + 1
+        )
+        .fetch_all(pool))
+        .await?;
+
+        let has_more = attachments.len() > limit as usize;
+        attachments.truncate(limit as usize);
+        Ok((attachments, has_more))
+    }
+
+    /// Permanently deletes the conversation with its messages, and the images
+    /// attached to them unless a pet document or another conversation uses
+    /// them too. The database rows go in one transaction; the stored files are
+    /// removed once it commits. None if there is no such conversation.
+    pub async fn delete_permanently(
+        pool: &PgPool,
+        storage: &dyn ImageStorage,
+        conversation_id: Uuid,
+    ) -> Result<Option<DeletedConversation>, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let conversation = sqlx::query!(
+            r#"
+            SELECT (SELECT COUNT(*) FROM messages m WHERE m.conversation_id = c.id) AS "message_count!"
+            FROM conversations c
+            WHERE c.id = $1
+            FOR UPDATE
+            "#,
+            conversation_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(conversation) = conversation else {
+            return Ok(None);
+        };
+
+        let attachment_urls: Vec<String> = sqlx::query!(
+            r#"
+            DELETE FROM images i
+            WHERE i.id IN (SELECT attachment_image_id FROM messages WHERE conversation_id = $1)
+              AND NOT EXISTS (SELECT 1 FROM pet_documents d WHERE d.image_id = i.id)
+              AND NOT EXISTS (
+                  SELECT 1 FROM messages m WHERE m.attachment_image_id = i.id AND m.conversation_id <> $1
+              )
+            RETURNING i.image_url
+            "#,
+            conversation_id
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|image| image.image_url)
+        .collect();
+
+        // Messages, reads, notes and the rest cascade with it
+        sqlx::query!("DELETE FROM conversations WHERE id = $1", conversation_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        let mut storage_failures = Vec::new();
+        for url in &attachment_urls {
+            if let Err(e) = storage.delete(url).await {
+                elogln!("Failed to delete attachment of conversation {}: {}", conversation_id, e);
+                storage_failures.push(url.clone());
+            }
+        }
+        Ok(Some(DeletedConversation {
+            message_count: conversation.message_count,
+            attachment_count: attachment_urls.len(),
+            storage_failures,
+        }))
+    }
+
+    /// Each sender's message count and first and last message times in the
+    /// conversation, most active first.
+    pub async fn get_message_stats(pool: &PgPool, conversation_id: Uuid) -> Result<Vec<SenderMessageStats>, sqlx::Error> {
+        retry_read(|| sqlx::query_as!(
+            SenderMessageStats,
+            r#"
+            SELECT sender_id, COUNT(*) AS "message_count!",
+                   MIN(timestamp) AS "first_message_at!", MAX(timestamp) AS "last_message_at!"
+            FROM messages
+            WHERE conversation_id = $1
+            GROUP BY sender_id
+            ORDER BY COUNT(*) DESC, MAX(timestamp) DESC
+            "#,
+            conversation_id
+        )
+        .fetch_all(pool))
+        .await
+    }
+
+    /// The full record of the conversation's pet. Callers check access to the
+    /// conversation first.
+    /// The newest page of a conversation's history with its members, pet and the
+    /// user's read position, fetched concurrently. None if the user can't access
+    /// the conversation or its pet is gone.
+    pub async fn open(
+        pool: &PgPool,
+        conversation_id: Uuid,
+        user_id: Uuid,
+        limit: i32,
+        max_offset: i32,
+    ) -> Result<Option<OpenedConversation>, HistoryError> {
+        let conversation_ids = [conversation_id];
+        let ((messages, total_count, has_more), read_state, participants, pet) = futures::try_join!(
+            Self::get_conversation_messages(pool, conversation_id, 1, limit, None, max_offset),
+            Self::get_read_state(pool, conversation_id, user_id).err_into(),
+            Self::get_participants(pool, user_id, &conversation_ids).err_into(),
+            Self::get_pet(pool, conversation_id).err_into(),
+        )?;
+        let (Some(members), Some(pet)) = (participants.conversations.into_iter().next(), pet) else {
+            return Ok(None);
+        };
+
+        Ok(Some(OpenedConversation {
+            messages,
+            total_count,
+            has_more,
+            members,
+            users: participants.users,
+            pet,
+            last_read_message_id: read_state.as_ref().and_then(|read| read.last_read_message_id),
+            last_read_at: read_state.map(|read| read.last_read_at),
+        }))
+    }
+
+    pub async fn get_pet(pool: &PgPool, conversation_id: Uuid) -> Result<Option<Pet>, sqlx::Error> {
+        retry_read(|| sqlx::query_as!(
+            Pet,
+            "
+            SELECT p.id, p.user_id, p.name, p.breed, p.sex, p.birthday, p.pet_image_url, p.color,
+                   p.species, p.spayed_neutered, p.weight
+            FROM conversations c
+            JOIN pets p ON p.id = c.pet
+            WHERE c.id = $1
+            ",
+            conversation_id
+        )
+        .fetch_optional(pool))
+        .await
+    }
+
+    /// Records that the user has read the conversation up to `message_id`. Returns
+    /// false if the message isn't in the conversation.
+    pub async fn mark_read(pool: &PgPool, conversation_id: Uuid, user_id: Uuid, message_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "
+            INSERT INTO conversation_reads (conversation_id, user_id, last_read_message_id, last_read_at)
+            SELECT $1, $2, id, CURRENT_TIMESTAMP FROM messages WHERE id = $3 AND conversation_id = $1
+            ON CONFLICT (conversation_id, user_id)
+            DO UPDATE SET last_read_message_id = EXCLUDED.last_read_message_id, last_read_at = EXCLUDED.last_read_at
+            ",
+            conversation_id,
+            user_id,
+            message_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Records that the user has read every message from others in the
+    /// conversation sent at or before `up_to`. Returns how many were newly read.
+    pub async fn mark_messages_read(pool: &PgPool, conversation_id: Uuid, user_id: Uuid, up_to: DateTime<Utc>) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            "
+            INSERT INTO message_reads (message_id, user_id)
+            SELECT id, $2 FROM messages
+            WHERE conversation_id = $1 AND sender_id <> $2 AND timestamp <= $3
+            ON CONFLICT (message_id, user_id) DO NOTHING
+            ",
+            conversation_id,
+            user_id,
+            up_to
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Soft-deletes a message, if `sender_id` sent it and it isn't deleted
+    /// already. Returns the conversation it was in.
+    pub async fn delete_message(pool: &PgPool, message_id: Uuid, sender_id: Uuid) -> Result<Option<Uuid>, sqlx::Error> {
+        let deleted = sqlx::query_scalar!(
+            "
+            UPDATE messages SET deleted_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND sender_id = $2 AND deleted_at IS NULL
+            RETURNING conversation_id
+            ",
+            message_id,
+            sender_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(deleted)
+    }
+
+    /// Adds the user's reaction to a message, returning the message's
+    /// conversation and whether the reaction is new. Reacting again with the
+    /// same emoji changes nothing.
+    pub async fn add_reaction(pool: &PgPool, message_id: Uuid, user_id: Uuid, emoji: &str) -> Result<(Uuid, bool), ReactionError> {
+        let emoji = emoji.trim();
+        // Not a full emoji check, just enough to keep reactions from being text
+        if emoji.is_empty()
+            || emoji.chars().count() > MAX_EMOJI_CHARS
+            || emoji.chars().any(|c| c.is_ascii_alphanumeric() || c.is_whitespace() || c.is_control())
+        {
+            return Err(ReactionError::Invalid("emoji must be a single emoji"));
+        }
+        let conversation_id = Self::reactable_message_conversation(pool, message_id, user_id).await?;
+
+        let added = sqlx::query!(
+            "
+            INSERT INTO message_reactions (message_id, user_id, emoji)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (message_id, user_id, emoji) DO NOTHING
+            ",
+            message_id,
+            user_id,
+            emoji
+        )
+        .execute(pool)
+        .await?;
+
+        Ok((conversation_id, added.rows_affected() > 0))
+    }
+
+    /// Takes back the user's reaction, returning the message's conversation and
+    /// whether there was a reaction to remove.
+    pub async fn remove_reaction(pool: &PgPool, message_id: Uuid, user_id: Uuid, emoji: &str) -> Result<(Uuid, bool), ReactionError> {
+        let conversation_id = Self::reactable_message_conversation(pool, message_id, user_id).await?;
+
+        let removed = sqlx::query!(
+            "DELETE FROM message_reactions WHERE message_id = $1 AND user_id = $2 AND emoji = $3",
+            message_id,
+            user_id,
+            emoji.trim()
+        )
+        .execute(pool)
+        .await?;
+
+        Ok((conversation_id, removed.rows_affected() > 0))
+    }
+
+    // The conversation of a message the user may react to: one that hasn't been
+    // deleted, in a conversation they can post in.
+    async fn reactable_message_conversation(pool: &PgPool, message_id: Uuid, user_id: Uuid) -> Result<Uuid, ReactionError> {
+        let conversation_id = sqlx::query_scalar!(
+            "SELECT conversation_id FROM messages WHERE id = $1 AND deleted_at IS NULL",
+            message_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or(ReactionError::NotFound)?;
+
+        match Self::get_access(pool, conversation_id, user_id).await? {
+            Some(AccessLevel::ReadWrite) => Ok(conversation_id),
+            _ => Err(ReactionError::Forbidden),
+        }
+    }
+
+    /// Read positions of everyone who has read any of the conversation.
+    pub async fn get_read_states(pool: &PgPool, conversation_id: Uuid) -> Result<Vec<ReadState>, sqlx::Error> {
+        retry_read(|| sqlx::query_as!(
+            ReadState,
+            "
+            SELECT user_id, last_read_message_id, last_read_at
+            FROM conversation_reads
+            WHERE conversation_id = $1
+            ",
+            conversation_id
+        )
+        .fetch_all(pool))
+        .await
+    }
+
+    /// The user's own read position in the conversation, if they've read any of it.
+    pub async fn get_read_state(pool: &PgPool, conversation_id: Uuid, user_id: Uuid) -> Result<Option<ReadState>, sqlx::Error> {
+        retry_read(|| sqlx::query_as!(
+            ReadState,
+            "
+            SELECT user_id, last_read_message_id, last_read_at
+            FROM conversation_reads
+            WHERE conversation_id = $1 AND user_id = $2
+            ",
+            conversation_id,
+            user_id
+        )
+        .fetch_optional(pool))
+        .await
+    }
+
+    /// Messages from others in each conversation since the user last marked it
+    /// read. Conversations with none are left out.
+    pub async fn get_unread_counts(pool: &PgPool, user_id: Uuid, conversation_ids: &[Uuid]) -> Result<HashMap<Uuid, i64>, sqlx::Error> {
+        let rows = retry_read(|| sqlx::query!(
+            r#"
+            SELECT m.conversation_id, COUNT(*) AS "unread!"
+            FROM messages m
+            LEFT JOIN conversation_reads r ON r.conversation_id = m.conversation_id AND r.user_id = $1
+            LEFT JOIN messages last_read ON last_read.id = r.last_read_message_id
+            WHERE m.conversation_id = ANY($2)
+              AND m.sender_id <> $1
+              AND (r.user_id IS NULL OR m.timestamp > COALESCE(last_read.timestamp, r.last_read_at))
+            GROUP BY m.conversation_id
+            "#,
+            user_id,
+            conversation_ids
+        )
+        .fetch_all(pool))
+        .await?;
+
+        Ok(rows.into_iter().map(|row| (row.conversation_id, row.unread)).collect())
+    }
+
+    /// Adds unread counts, pets and participant profiles to the user's
+    /// conversations, keeping their order.
+    pub async fn get_inbox(pool: &PgPool, user_id: Uuid, conversations: Vec<Conversation>) -> Result<Inbox, sqlx::Error> {
+        let conversation_ids: Vec<Uuid> = conversations.iter().map(|c| c.id).collect();
+        let (participants, unread_counts) = futures::try_join!(
+            Self::get_participants(pool, user_id, &conversation_ids),
+            Self::get_unread_counts(pool, user_id, &conversation_ids),
+        )?;
+        let mut pets: HashMap<Uuid, PetSummary> = participants.conversations
+            .into_iter()
+            .filter_map(|members| Some((members.conversation_id, members.pet?)))
+            .collect();
+
+        Ok(Inbox {
+            conversations: conversations
+                .into_iter()
+                .map(|conversation| InboxConversation {
+                    unread_count: unread_counts.get(&conversation.id).copied().unwrap_or(0),
+                    pet_summary: pets.remove(&conversation.id),
+                    conversation,
+                })
+                .collect(),
+            users: participants.users,
+        })
+    }
+
+    /// The user's conversations reduced to their last message and unread
+    /// count, keeping their order.
+    pub async fn get_previews(pool: &PgPool, user_id: Uuid, conversations: Vec<Conversation>) -> Result<Vec<ConversationPreview>, sqlx::Error> {
+        let conversation_ids: Vec<Uuid> = conversations.iter().map(|c| c.id).collect();
+        let unread_counts = Self::get_unread_counts(pool, user_id, &conversation_ids).await?;
+
+        Ok(conversations
+            .into_iter()
+            .map(|conversation| ConversationPreview {
+                conversation_id: conversation.id,
+                unread_count: unread_counts.get(&conversation.id).copied().unwrap_or(0),
+                last_message: conversation.last_message,
+                last_updated_timestamp: conversation.last_updated_timestamp,
+            })
+            .collect())
+    }
+
+    /// Conversations about pets that have been shared with the user.
+    pub async fn get_conversations_shared_with(pool: &PgPool, user_id: Uuid) -> Result<Vec<Conversation>, sqlx::Error> {
+        retry_read(|| sqlx::query_as!(
+            Conversation,
+            "
+            SELECT c.id, c.providers, c.client, c.pet, c.last_message, c.last_updated_timestamp, c.assigned_provider, c.created_at, c.organization_id, c.message_count
+            FROM conversations c
+            JOIN pet_shares s ON s.pet_id = c.pet
+            WHERE s.shared_with_user_id = $1 AND s.status = 'accepted'
+            ORDER BY c.last_updated_timestamp DESC
+            ",
+            user_id
+        )
+        .fetch_all(pool))
+        .await
+    }
+
+    /// Message, participant and attachment totals for a conversation, with an
+    /// estimate of how large an export of it would be.
+    pub async fn get_conversation_summary(pool: &PgPool, conversation: &Conversation) -> Result<ConversationSummary, sqlx::Error> {
+        let totals = retry_read(|| sqlx::query!(
+            r#"
+            SELECT COUNT(*) AS "message_count!",
+                   MIN(m.timestamp) AS first_message_at,
+                   MAX(m.timestamp) AS last_message_at,
+                   COALESCE(SUM(COALESCE(octet_length(m.content), mc.content_bytes)), 0)::BIGINT AS "content_bytes!",
+                   COUNT(i.id) AS "attachment_count!",
+                   COALESCE(SUM(i.size_bytes), 0)::BIGINT AS "attachment_bytes!",
+                   (SELECT o.name FROM organizations o WHERE o.id = $2) AS organization_name
+            FROM messages m
+            LEFT JOIN messages_cold mc ON mc.message_id = m.id
+            LEFT JOIN images i ON i.id = m.attachment_image_id
+            WHERE m.conversation_id = $1
+            "#,
+            conversation.id,
+            conversation.organization_id as Option<Uuid>
+        )
+        .fetch_one(pool))
+        .await?;
+
+        let mut participants = conversation.providers.clone();
+        participants.push(conversation.client);
+        participants.sort_unstable();
+        participants.dedup();
+
+        Ok(ConversationSummary {
+            conversation_id: conversation.id,
+            message_count: totals.message_count,
+            participant_count: participants.len() as i64,
+            first_message_at: totals.first_message_at,
+            last_message_at: totals.last_message_at,
+            attachment_count: totals.attachment_count,
+            attachment_bytes: totals.attachment_bytes,
+            estimated_export_bytes: totals.content_bytes
+                + totals.message_count * EXPORT_BYTES_PER_MESSAGE
+                + totals.attachment_bytes,
+            organization: conversation.organization_id
+                .zip(totals.organization_name)
+                .map(|(id, name)| OrganizationSummary { id, name }),
+            note: None,
+        })
+    }
+}
+
+/// What a permanent deletion removed.
+#[derive(Debug)]
+pub struct DeletedConversation {
+    pub message_count: i64,
+    pub attachment_count: usize,
+    /// Attachments whose stored files couldn't be removed and need cleaning up by hand.
+    pub storage_failures: Vec<String>,
+}
+
+/// Briefly caches conversation summaries, which aggregate over every message and
+/// are requested each time a provider opens a case.
+pub struct SummaryCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<Uuid, (Instant, ConversationSummary)>>,
+}
+
+impl SummaryCache {
+    pub fn new(ttl: Duration) -> Self {
+        SummaryCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, conversation_id: Uuid) -> Option<ConversationSummary> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(&conversation_id)
+            .filter(|(cached_at, _)| cached_at.elapsed() < self.ttl)
+            .map(|(_, summary)| summary.clone())
+    }
+
+    pub fn insert(&self, summary: ConversationSummary) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (cached_at, _)| cached_at.elapsed() < self.ttl);
+        entries.insert(summary.conversation_id, (Instant::now(), summary));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{cleanup_users, insert_user, test_pool};
+    use crate::moderation::{ModerationAction, RegexModerator};
+
+    async fn setup() -> (PgPool, Uuid, Uuid, Uuid, Uuid) {
+        let pool = test_pool().await;
+
+        let mut user_ids = Vec::new();
+        for scope in ["client", "provider", "provider"] {
+            let id = insert_user(&pool, scope).await;
+            user_ids.push(id);
+        }
+        let pet_id = sqlx::query!(
+            "INSERT INTO pets (user_id, name, breed, sex, birthday) VALUES ($1, 'Millie', 'Mutt', 'F', NOW()) RETURNING id",
+            user_ids[0]
+        )
+        .fetch_one(&pool).await.unwrap().id;
+
+        (pool, user_ids[0], user_ids[1], user_ids[2], pet_id)
+    }
+
+
+    #[tokio::test]
+    async fn round_robin_routing_spreads_conversations_and_takeovers_move_them() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        let organization = OrganizationService::create(&pool, "Eastside Vets").await.unwrap();
+        OrganizationService::add_member(&pool, organization.id, vet).await.unwrap();
+        OrganizationService::add_member(&pool, organization.id, tech).await.unwrap();
+        let route = || ConversationService::create_conversation(&pool, vec![], Some(organization.id), client, pet, false);
+
+        // Broadcast organizations don't assign anyone
+        let (broadcast, _) = route().await.unwrap();
+        assert_eq!(broadcast.assigned_provider, None);
+        assert!(broadcast.providers.is_empty());
+
+        OrganizationService::set_routing_mode(&pool, organization.id, "round_robin").await.unwrap();
+        assert!(matches!(
+            OrganizationService::set_routing_mode(&pool, organization.id, "random").await,
+            Err(crate::services::organizations::OrganizationError::Invalid(_))
+        ));
+        let mut assignees = Vec::new();
+        for _ in 0..4 {
+            let (conversation, created) = route().await.unwrap();
+            assert!(created);
+            assert_eq!(conversation.providers, vec![conversation.assigned_provider.unwrap()]);
+            assignees.push(conversation.assigned_provider.unwrap());
+        }
+        assert_eq!(assignees, vec![vet, tech, vet, tech]);
+
+        // A retried inquiry finds the routed conversation rather than routing again
+        let (retried, created) = ConversationService::create_conversation(&pool, vec![], Some(organization.id), client, pet, true).await.unwrap();
+        assert!(!created);
+        assert!(retried.assigned_provider.is_some());
+
+        // The tech takes over one of the vet's, so the vet is next in line twice
+        let vets = ConversationService::get_conversations_by_provider_id(&pool, vet).await.unwrap();
+        let taken = vets.iter().find(|c| c.assigned_provider == Some(vet)).unwrap().id;
+        let (conversation, previous) = ConversationService::take_over(&pool, tech, taken).await.unwrap();
+        assert_eq!(previous, Some(vet));
+        assert_eq!(conversation.assigned_provider, Some(tech));
+        assert_eq!(conversation.providers, vec![tech]);
+        assert_eq!(route().await.unwrap().0.assigned_provider, Some(vet));
+        assert_eq!(route().await.unwrap().0.assigned_provider, Some(vet));
+
+        assert!(matches!(ConversationService::take_over(&pool, tech, taken).await, Err(AssignmentError::Invalid(_))));
+        assert!(matches!(ConversationService::take_over(&pool, client, taken).await, Err(AssignmentError::Invalid(_))));
+        OrganizationService::remove_member(&pool, organization.id, vet).await.unwrap();
+        assert!(matches!(ConversationService::take_over(&pool, vet, taken).await, Err(AssignmentError::NotFound)));
+
+        sqlx::query!("DELETE FROM organizations WHERE id = $1", organization.id).execute(&pool).await.unwrap();
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn organization_conversations_follow_current_members() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        let organization = OrganizationService::create(&pool, "Northside Vets").await.unwrap();
+        assert!(matches!(
+            OrganizationService::add_member(&pool, organization.id, client).await,
+            Err(crate::services::organizations::OrganizationError::Invalid(_))
+        ));
+        OrganizationService::add_member(&pool, organization.id, vet).await.unwrap();
+        OrganizationService::add_member(&pool, organization.id, tech).await.unwrap();
+
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![], Some(organization.id), client, pet, true).await.unwrap();
+        assert_eq!(conversation.organization_id, Some(organization.id));
+        for member in [vet, tech] {
+            assert_eq!(ConversationService::get_access(&pool, conversation.id, member).await.unwrap(), Some(AccessLevel::ReadWrite));
+            let inbox = ConversationService::get_conversations_by_provider_id(&pool, member).await.unwrap();
+            assert!(inbox.iter().any(|c| c.id == conversation.id));
+        }
+        let reply = ConversationService::send_system_message(&pool, vet, conversation.id, "On it".to_string()).await.unwrap();
+
+        // Leaving mid-conversation ends access but keeps what they sent attributed to them
+        assert!(OrganizationService::remove_member(&pool, organization.id, vet).await.unwrap());
+        assert_eq!(ConversationService::get_access(&pool, conversation.id, vet).await.unwrap(), None);
+        assert!(ConversationService::get_conversations_by_provider_id(&pool, vet).await.unwrap().is_empty());
+        let (messages, _, _) = ConversationService::get_conversation_messages(&pool, conversation.id, 1, 10, None, 1000).await.unwrap();
+        assert_eq!(messages[0].id, reply.id);
+        assert_eq!(messages[0].sender_id, vet);
+
+        let summary = ConversationService::get_conversation_summary(&pool, &conversation).await.unwrap();
+        assert_eq!(summary.organization, Some(organization.clone()));
+
+        sqlx::query!("DELETE FROM organizations WHERE id = $1", organization.id).execute(&pool).await.unwrap();
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn display_profiles_only_cover_shared_conversations() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        sqlx::query!("UPDATE users SET first_name = 'Dana', last_name = 'Reyes' WHERE id = $1", vet)
+            .execute(&pool).await.unwrap();
+
+        let profiles = ConversationService::get_display_profiles(&pool, client, &[client, vet, tech]).await.unwrap();
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles[&vet].display_name, "Dana Reyes");
+        assert_eq!(profiles[&client].display_name, "Unknown User");
+        assert!(!profiles.contains_key(&tech));
+
+        // The vet sees the client back; the tech only sees themselves
+        assert!(ConversationService::get_display_profiles(&pool, vet, &[client]).await.unwrap().contains_key(&client));
+        let own = ConversationService::get_display_profiles(&pool, tech, &[client, vet, tech]).await.unwrap();
+        assert_eq!(own.keys().collect::<Vec<_>>(), vec![&tech]);
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn message_times_ignore_backfills_and_archiving_but_track_edits() {
+        use crate::services::cold_storage::ColdStorageService;
+
+        let (pool, client, vet, tech, pet) = setup().await;
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        let sent_at = DateTime::parse_from_rfc3339("2002-02-02T10:00:00.250Z").unwrap().with_timezone(&Utc);
+        let message = ConversationService::insert_message(
+            &pool, client, conversation.id, "Is she eating?".to_string(), sent_at, "text", None, false
+        ).await.unwrap();
+        assert_eq!((message.updated_at, message.edited_at), (sent_at, None));
+
+        // Moving it to cold storage, or touching it without changing anything, isn't an update
+        ColdStorageService::archive_batch(&pool, sent_at + chrono::Duration::days(1), 100).await.unwrap();
+        sqlx::query!("UPDATE messages SET via_sms = via_sms WHERE id = $1", message.id).execute(&pool).await.unwrap();
+        let (messages, _, _) = ConversationService::get_conversation_messages(&pool, conversation.id, 1, 10, None, 1000).await.unwrap();
+        assert_eq!((messages[0].updated_at, messages[0].edited_at), (sent_at, None));
+
+        sqlx::query!("UPDATE messages SET content = 'Is she eating today?' WHERE id = $1", message.id).execute(&pool).await.unwrap();
+        let (messages, _, _) = ConversationService::get_conversation_messages(&pool, conversation.id, 1, 10, None, 1000).await.unwrap();
+        let edited_at = messages[0].edited_at.expect("an edit sets edited_at");
+        assert_eq!(messages[0].updated_at, edited_at);
+        assert!(edited_at > sent_at);
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[test]
+    fn message_times_serialize_as_milliseconds() {
+        let sent_at = DateTime::parse_from_rfc3339("2023-01-01T12:00:00.500Z").unwrap().with_timezone(&Utc);
+        let mut message = Message {
+            id: Uuid::nil(),
+            conversation_id: Uuid::nil(),
+            sender_id: Uuid::nil(),
+            content: "Hello".to_string(),
+            timestamp: sent_at,
+            updated_at: sent_at,
+            edited_at: None,
+            message_type: "text".to_string(),
+            attachment_image_id: None,
+            via_sms: false,
+            deleted: false,
+        };
+        let json = serde_json::to_value(&message).unwrap();
+        assert_eq!(json["timestamp"], 1672574400500i64);
+        assert_eq!(json["updated_at"], 1672574400500i64);
+        assert!(json["edited_at"].is_null());
+
+        message.edited_at = Some(sent_at + chrono::Duration::milliseconds(1500));
+        let json = serde_json::to_value(&message).unwrap();
+        assert_eq!(json["edited_at"], 1672574402000i64);
+        let parsed: Message = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.edited_at, message.edited_at);
+    }
+
+    #[tokio::test]
+    async fn cold_messages_read_back_unchanged() {
+        use crate::services::cold_storage::ColdStorageService;
+
+        let (pool, client, vet, tech, pet) = setup().await;
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        let long_ago = DateTime::parse_from_rfc3339("2001-01-01T00:00:00Z").unwrap().with_timezone(&Utc);
+        for (i, content) in ["Is she eating?", "Yes, \"all\" of it 🐶", &"Still limping. ".repeat(100)].iter().enumerate() {
+            ConversationService::insert_message(
+                &pool, client, conversation.id, content.to_string(), long_ago + chrono::Duration::seconds(i as i64), "text", None, false
+            ).await.unwrap();
+        }
+        let history = |before: Option<Uuid>| {
+            let pool = pool.clone();
+            async move {
+                let (messages, _, _) = ConversationService::get_conversation_messages(&pool, conversation.id, 1, 10, before, 1000).await.unwrap();
+                serde_json::to_string(&messages).unwrap()
+            }
+        };
+        let hot_history = history(None).await;
+        let newest: Uuid = serde_json::from_str::<serde_json::Value>(&hot_history).unwrap()[0]["id"]
+            .as_str().unwrap().parse().unwrap();
+        let hot_cursor_page = history(Some(newest)).await;
+        let hot_summary = ConversationService::get_conversation_summary(&pool, &conversation).await.unwrap();
+
+        let cutoff = long_ago + chrono::Duration::days(1);
+        assert!(ColdStorageService::archive_batch(&pool, cutoff, 100).await.unwrap() >= 3);
+        let hot_left = sqlx::query!("SELECT COUNT(content) AS \"count!\" FROM messages WHERE conversation_id = $1", conversation.id)
+            .fetch_one(&pool).await.unwrap().count;
+        assert_eq!(hot_left, 0);
+
+        // Both kinds of history page and the summary read the same as before
+        assert_eq!(history(None).await, hot_history);
+        assert_eq!(history(Some(newest)).await, hot_cursor_page);
+        let cold_summary = ConversationService::get_conversation_summary(&pool, &conversation).await.unwrap();
+        assert_eq!(cold_summary.estimated_export_bytes, hot_summary.estimated_export_bytes);
+
+        // Editing a cold message brings it back
+        sqlx::query!("UPDATE messages SET content = 'Edited' WHERE id = $1", newest).execute(&pool).await.unwrap();
+        let cold_rows = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM messages_cold WHERE message_id = $1", newest)
+            .fetch_one(&pool).await.unwrap().count;
+        assert_eq!(cold_rows, 0);
+        assert!(history(None).await.contains("\"content\":\"Edited\""));
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn providers_are_replaced_by_the_client() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        ConversationService::assign_provider(&pool, client, conversation.id, Some(vet)).await.unwrap();
+
+        let (updated, previous) = ConversationService::set_providers(&pool, client, conversation.id, vec![tech, tech]).await.unwrap();
+        assert_eq!(previous, vec![vet]);
+        assert_eq!(updated.providers, vec![tech]);
+        assert_eq!(updated.assigned_provider, None);
+
+        // Only providers, only up to the cap, and only the client
+        assert!(matches!(
+            ConversationService::set_providers(&pool, client, conversation.id, vec![vet, client]).await,
+            Err(SetProvidersError::Invalid(_))
+        ));
+        let too_many: Vec<Uuid> = (0..=MAX_CONVERSATION_PROVIDERS).map(|_| Uuid::new_v4()).collect();
+        assert!(matches!(
+            ConversationService::set_providers(&pool, client, conversation.id, too_many).await,
+            Err(SetProvidersError::Invalid(_))
+        ));
+        assert!(matches!(
+            ConversationService::set_providers(&pool, tech, conversation.id, vec![vet]).await,
+            Err(SetProvidersError::Forbidden)
+        ));
+        assert!(matches!(
+            ConversationService::set_providers(&pool, vet, conversation.id, vec![vet]).await,
+            Err(SetProvidersError::NotFound)
+        ));
+        let unchanged = ConversationService::get_conversation_by_id(&pool, conversation.id).await.unwrap().unwrap();
+        assert_eq!(unchanged.providers, vec![tech]);
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn deep_history_pages_require_a_cursor() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        let start = Utc::now();
+        let mut sent = Vec::new();
+        for i in 0..5 {
+            let message = ConversationService::insert_message(
+                &pool, client, conversation.id, format!("Message {}", i), start + chrono::Duration::seconds(i), "text", None, false
+            ).await.unwrap();
+            sent.push(message.id);
+        }
+
+        // Offsets up to the limit still work
+        let (page, _, has_more) = ConversationService::get_conversation_messages(&pool, conversation.id, 2, 2, None, 2).await.unwrap();
+        assert_eq!(page.iter().map(|m| m.id).collect::<Vec<_>>(), vec![sent[2], sent[1]]);
+        assert!(has_more);
+        assert!(matches!(
+            ConversationService::get_conversation_messages(&pool, conversation.id, 3, 2, None, 2).await,
+            Err(HistoryError::CursorRequired)
+        ));
+
+        // Past it, the cursor carries on from the oldest message seen
+        let (page, total_count, has_more) = ConversationService::get_conversation_messages(&pool, conversation.id, 3, 2, Some(sent[1]), 2).await.unwrap();
+        assert_eq!(page.iter().map(|m| m.id).collect::<Vec<_>>(), vec![sent[0]]);
+        assert_eq!(total_count, 5);
+        assert!(!has_more);
+        assert!(matches!(
+            ConversationService::get_conversation_messages(&pool, conversation.id, 1, 2, Some(Uuid::new_v4()), 2).await,
+            Err(HistoryError::Invalid(_))
+        ));
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn message_count_is_maintained_and_reconciled() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        assert_eq!(conversation.message_count, 0);
+
+        for content in ["Hi", "Hello"] {
+            ConversationService::send_system_message(&pool, vet, conversation.id, content.to_string()).await.unwrap();
+        }
+        let conversation = ConversationService::get_conversation_by_id(&pool, conversation.id).await.unwrap().unwrap();
+        assert_eq!(conversation.message_count, 2);
+
+        // History totals come from the counter, not a count of the messages
+        sqlx::query!("UPDATE conversations SET message_count = 40 WHERE id = $1", conversation.id)
+            .execute(&pool).await.unwrap();
+        let (messages, total_count, has_more) = ConversationService::get_conversation_messages(&pool, conversation.id, 1, 20, None, 1000).await.unwrap();
+        assert_eq!((messages.len(), total_count, has_more), (2, 40, true));
+
+        assert!(ConversationService::reconcile_message_counts(&pool).await.unwrap() >= 1);
+        let (_, total_count, has_more) = ConversationService::get_conversation_messages(&pool, conversation.id, 1, 20, None, 1000).await.unwrap();
+        assert_eq!((total_count, has_more), (2, false));
+
+        // Deleting an account takes its messages out of the count
+        let mut conn = pool.acquire().await.unwrap();
+        ConversationService::discount_messages_from(&mut conn, vet).await.unwrap();
+        let conversation = ConversationService::get_conversation_by_id(&pool, conversation.id).await.unwrap().unwrap();
+        assert_eq!(conversation.message_count, 0);
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn dedupe_returns_existing_conversation() {
+        let (pool, client, vet, tech, pet) = setup().await;
+
+        let (first, created) = ConversationService::create_conversation(&pool, vec![vet, tech], None, client, pet, true).await.unwrap();
+        assert!(created);
+
+        // Same provider set in a different order is the same conversation
+        let (second, created) = ConversationService::create_conversation(&pool, vec![tech, vet], None, client, pet, true).await.unwrap();
+        assert!(!created);
+        assert_eq!(first.id, second.id);
+
+        // A different provider set is a different conversation
+        let (third, created) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, true).await.unwrap();
+        assert!(created);
+        assert_ne!(first.id, third.id);
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn assigned_provider_must_be_a_member() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+
+        let updated = ConversationService::assign_provider(&pool, client, conversation.id, Some(vet)).await.unwrap();
+        assert_eq!(updated.assigned_provider, Some(vet));
+
+        // tech isn't one of this conversation's providers
+        assert!(matches!(
+            ConversationService::assign_provider(&pool, vet, conversation.id, Some(tech)).await,
+            Err(AssignmentError::Invalid(_))
+        ));
+        // ...nor can they change the assignment
+        assert!(matches!(
+            ConversationService::assign_provider(&pool, tech, conversation.id, None).await,
+            Err(AssignmentError::NotFound)
+        ));
+
+        let cleared = ConversationService::assign_provider(&pool, vet, conversation.id, None).await.unwrap();
+        assert_eq!(cleared.assigned_provider, None);
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn participants_are_deduplicated_and_filtered_by_membership() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        let (first, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        let (second, _) = ConversationService::create_conversation(&pool, vec![vet, tech], None, client, pet, false).await.unwrap();
+        let (third, _) = ConversationService::create_conversation(&pool, vec![tech], None, client, pet, false).await.unwrap();
+
+        // vet belongs to the first two only
+        let participants = ConversationService::get_participants(&pool, vet, &[third.id, second.id, first.id, Uuid::new_v4()]).await.unwrap();
+        let ids: Vec<Uuid> = participants.conversations.iter().map(|c| c.conversation_id).collect();
+        assert_eq!(ids, vec![second.id, first.id]);
+        // client and vet appear in both, but only once in `users`
+        assert_eq!(participants.users.len(), 3);
+        assert!(participants.users.contains_key(&client) && participants.users.contains_key(&tech));
+        assert_eq!(participants.conversations[0].pet.as_ref().unwrap().id, pet);
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn summary_aggregates_messages_and_attachments() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![vet, tech], None, client, pet, false).await.unwrap();
+
+        let mut image_ids = Vec::new();
+        for size_bytes in [1000i64, 2500] {
+            let id = sqlx::query!(
+                "INSERT INTO images (id, user_id, image_type, image_url, size_bytes) VALUES ($1, $2, 'attachment', 'https://example.com/x.jpg', $3) RETURNING id",
+                Uuid::new_v4(),
+                client,
+                size_bytes
+            )
+            .fetch_one(&pool).await.unwrap().id;
+            image_ids.push(id);
+        }
+
+        let start = Utc::now() - chrono::Duration::hours(1);
+        let moderator = RegexModerator::new(None, true, ModerationAction::Reject);
+        ConversationService::send_message(&pool, &moderator, client, conversation.id, "hello".to_string(), start, Some(image_ids[0]), false).await.unwrap();
+        ConversationService::send_message(&pool, &moderator, vet, conversation.id, "hi there".to_string(), start + chrono::Duration::minutes(5), None, false).await.unwrap();
+        ConversationService::send_message(&pool, &moderator, client, conversation.id, "x-ray".to_string(), start + chrono::Duration::minutes(10), Some(image_ids[1]), false).await.unwrap();
+
+        let summary = ConversationService::get_conversation_summary(&pool, &conversation).await.unwrap();
+        assert_eq!(summary.message_count, 3);
+        assert_eq!(summary.participant_count, 3);
+        assert_eq!(summary.attachment_count, 2);
+        assert_eq!(summary.attachment_bytes, 3500);
+        assert_eq!(summary.first_message_at.unwrap().timestamp_millis(), start.timestamp_millis());
+        assert_eq!(summary.last_message_at.unwrap().timestamp_millis(), (start + chrono::Duration::minutes(10)).timestamp_millis());
+        let content_bytes = ("hello".len() + "hi there".len() + "x-ray".len()) as i64;
+        assert_eq!(summary.estimated_export_bytes, content_bytes + 3 * EXPORT_BYTES_PER_MESSAGE + 3500);
+
+        // An empty conversation has no timestamps
+        let (empty, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        let summary = ConversationService::get_conversation_summary(&pool, &empty).await.unwrap();
+        assert_eq!(summary.message_count, 0);
+        assert!(summary.first_message_at.is_none());
+        assert_eq!(summary.estimated_export_bytes, 0);
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn duplicates_allowed_without_dedupe() {
+        let (pool, client, vet, tech, pet) = setup().await;
+
+        let (first, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        let (second, created) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        assert!(created);
+        assert_ne!(first.id, second.id);
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn reports_creation_and_first_message_times() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        let before = Utc::now() - chrono::Duration::seconds(5);
+
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        assert!(conversation.created_at >= before && conversation.created_at <= Utc::now());
+
+        let (created_at, first_message_at) = ConversationService::get_timestamps(&pool, conversation.id).await.unwrap().unwrap();
+        assert_eq!(created_at, conversation.created_at);
+        assert_eq!(first_message_at, None);
+
+        let sent_at = Utc::now();
+        let moderator = RegexModerator::new(None, false, ModerationAction::Reject);
+        ConversationService::send_message(&pool, &moderator, client, conversation.id, "Hello".to_string(), sent_at, None, false).await.unwrap();
+        let (_, first_message_at) = ConversationService::get_timestamps(&pool, conversation.id).await.unwrap().unwrap();
+        assert_eq!(first_message_at.map(|t| t.timestamp_micros()), Some(sent_at.timestamp_micros()));
+
+        assert!(ConversationService::get_timestamps(&pool, Uuid::new_v4()).await.unwrap().is_none());
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn retries_a_read_once_after_a_connection_error() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let attempts = AtomicUsize::new(0);
+        let result = retry_read(|| async {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(sqlx::Error::Io(std::io::Error::from(std::io::ErrorKind::ConnectionReset))),
+                _ => Ok(42),
+            }
+        }).await;
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+        // Query errors aren't retried, and a connection that stays down surfaces
+        attempts.store(0, Ordering::SeqCst);
+        let result: Result<i32, _> = retry_read(|| async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(sqlx::Error::RowNotFound)
+        }).await;
+        assert!(matches!(result, Err(sqlx::Error::RowNotFound)));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        attempts.store(0, Ordering::SeqCst);
+        let result: Result<i32, _> = retry_read(|| async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(sqlx::Error::PoolTimedOut)
+        }).await;
+        assert!(result.as_ref().is_err_and(is_connection_error));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn tracks_how_far_each_participant_has_read() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        let (other, _) = ConversationService::create_conversation(&pool, vec![tech], None, client, pet, false).await.unwrap();
+        let moderator = RegexModerator::new(None, false, ModerationAction::Reject);
+        let first = ConversationService::send_message(&pool, &moderator, vet, conversation.id, "First".to_string(), Utc::now(), None, false).await.unwrap();
+        let second = ConversationService::send_message(&pool, &moderator, vet, conversation.id, "Second".to_string(), Utc::now(), None, false).await.unwrap();
+
+        assert!(ConversationService::get_read_states(&pool, conversation.id).await.unwrap().is_empty());
+
+        assert!(ConversationService::mark_read(&pool, conversation.id, client, first.id).await.unwrap());
+        assert!(ConversationService::mark_read(&pool, conversation.id, client, second.id).await.unwrap());
+        // A message from another conversation doesn't count
+        assert!(!ConversationService::mark_read(&pool, other.id, client, second.id).await.unwrap());
+
+        let states = ConversationService::get_read_states(&pool, conversation.id).await.unwrap();
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].user_id, client);
+        assert_eq!(states[0].last_read_message_id, Some(second.id));
+        assert!(ConversationService::get_read_states(&pool, other.id).await.unwrap().is_empty());
+
+        let own = ConversationService::get_read_state(&pool, conversation.id, client).await.unwrap().unwrap();
+        assert_eq!(own, states[0]);
+        assert!(ConversationService::get_read_state(&pool, conversation.id, vet).await.unwrap().is_none());
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn marks_messages_from_others_read_up_to_a_time() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        let moderator = RegexModerator::new(None, false, ModerationAction::Reject);
+        let sent_at = Utc::now() - chrono::Duration::minutes(5);
+        ConversationService::send_message(&pool, &moderator, vet, conversation.id, "First".to_string(), sent_at, None, false).await.unwrap();
+        ConversationService::send_message(&pool, &moderator, client, conversation.id, "Reply".to_string(), sent_at, None, false).await.unwrap();
+        ConversationService::send_message(&pool, &moderator, vet, conversation.id, "Later".to_string(), Utc::now(), None, false).await.unwrap();
+
+        // The client's own reply isn't theirs to read, and the later message is past the mark
+        assert_eq!(ConversationService::mark_messages_read(&pool, conversation.id, client, sent_at).await.unwrap(), 1);
+        assert_eq!(ConversationService::mark_messages_read(&pool, conversation.id, client, sent_at).await.unwrap(), 0);
+        assert_eq!(ConversationService::mark_messages_read(&pool, conversation.id, client, Utc::now()).await.unwrap(), 1);
+        assert_eq!(ConversationService::mark_messages_read(&pool, conversation.id, vet, Utc::now()).await.unwrap(), 1);
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn only_the_sender_deletes_a_message_and_it_stays_blanked_in_history() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        let moderator = RegexModerator::new(None, false, ModerationAction::Reject);
+        let message = ConversationService::send_message(&pool, &moderator, client, conversation.id, "Wrong pet, sorry".to_string(), Utc::now(), None, false).await.unwrap();
+        ConversationService::send_message(&pool, &moderator, vet, conversation.id, "No problem".to_string(), Utc::now(), None, false).await.unwrap();
+
+        assert_eq!(ConversationService::delete_message(&pool, message.id, vet).await.unwrap(), None);
+        assert_eq!(ConversationService::delete_message(&pool, message.id, client).await.unwrap(), Some(conversation.id));
+        assert_eq!(ConversationService::delete_message(&pool, message.id, client).await.unwrap(), None);
+
+        let (messages, total, _) = ConversationService::get_conversation_messages(&pool, conversation.id, 1, 10, None, 1000).await.unwrap();
+        assert_eq!(total, 2);
+        let deleted = messages.iter().find(|m| m.id == message.id).unwrap();
+        assert!(deleted.deleted);
+        assert_eq!(deleted.content, "");
+        assert!(messages.iter().any(|m| !m.deleted && m.content == "No problem"));
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn opening_a_conversation_returns_history_members_pet_and_read_position() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        let moderator = RegexModerator::new(None, false, ModerationAction::Reject);
+        let first = ConversationService::send_message(&pool, &moderator, vet, conversation.id, "How is her appetite?".to_string(), Utc::now(), None, false).await.unwrap();
+        ConversationService::send_message(&pool, &moderator, vet, conversation.id, "Any vomiting?".to_string(), Utc::now(), None, false).await.unwrap();
+        assert!(ConversationService::mark_read(&pool, conversation.id, client, first.id).await.unwrap());
+
+        let opened = ConversationService::open(&pool, conversation.id, client, 1, 1000).await.unwrap().unwrap();
+        assert_eq!((opened.messages.len(), opened.total_count, opened.has_more), (1, 2, true));
+        assert_eq!(opened.messages[0].content, "Any vomiting?");
+        assert_eq!((opened.members.conversation_id, opened.members.providers.clone()), (conversation.id, vec![vet]));
+        assert!(opened.users.contains_key(&client) && opened.users.contains_key(&vet));
+        assert_eq!(opened.pet.id, pet);
+        assert_eq!(opened.last_read_message_id, Some(first.id));
+        assert!(opened.last_read_at.is_some());
+
+        assert!(ConversationService::open(&pool, conversation.id, Uuid::new_v4(), 20, 1000).await.unwrap().is_none());
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn reactions_are_one_per_emoji_and_limited_to_participants() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        let moderator = RegexModerator::new(None, false, ModerationAction::Reject);
+        let message = ConversationService::send_message(&pool, &moderator, client, conversation.id, "She ate the whole sock".to_string(), Utc::now(), None, false).await.unwrap();
+
+        assert_eq!(ConversationService::add_reaction(&pool, message.id, vet, "😮").await.unwrap(), (conversation.id, true));
+        assert_eq!(ConversationService::add_reaction(&pool, message.id, vet, "😮").await.unwrap(), (conversation.id, false));
+        assert_eq!(ConversationService::add_reaction(&pool, message.id, client, "😮").await.unwrap(), (conversation.id, true));
+        for emoji in ["", "wow", "👍 👍"] {
+            assert!(matches!(ConversationService::add_reaction(&pool, message.id, vet, emoji).await, Err(ReactionError::Invalid(_))), "{:?}", emoji);
+        }
+        assert!(matches!(ConversationService::add_reaction(&pool, message.id, Uuid::new_v4(), "😮").await, Err(ReactionError::Forbidden)));
+        assert!(matches!(ConversationService::add_reaction(&pool, Uuid::new_v4(), vet, "😮").await, Err(ReactionError::NotFound)));
+
+        assert_eq!(ConversationService::remove_reaction(&pool, message.id, vet, "😮").await.unwrap(), (conversation.id, true));
+        assert_eq!(ConversationService::remove_reaction(&pool, message.id, vet, "😮").await.unwrap(), (conversation.id, false));
+        let remaining = sqlx::query_scalar!("SELECT user_id FROM message_reactions WHERE message_id = $1", message.id)
+            .fetch_all(&pool).await.unwrap();
+        assert_eq!(remaining, vec![client]);
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn inbox_counts_unread_messages_from_others() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        sqlx::query!("UPDATE users SET first_name = 'Dana' WHERE id = $1", vet).execute(&pool).await.unwrap();
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        let (quiet, _) = ConversationService::create_conversation(&pool, vec![tech], None, client, pet, false).await.unwrap();
+        let moderator = RegexModerator::new(None, false, ModerationAction::Reject);
+        let send = |sender, content: &str| ConversationService::send_message(
+            &pool, &moderator, sender, conversation.id, content.to_string(), Utc::now(), None, false
+        );
+
+        let first = send(vet, "First").await.unwrap();
+        send(vet, "Second").await.unwrap();
+        // The user's own messages are never unread
+        send(client, "Reply").await.unwrap();
+
+        let inbox = ConversationService::get_inbox(&pool, client, vec![conversation.clone(), quiet.clone()]).await.unwrap();
+        let unread: Vec<_> = inbox.conversations.iter().map(|c| (c.conversation.id, c.unread_count)).collect();
+        assert_eq!(unread, vec![(conversation.id, 2), (quiet.id, 0)]);
+        assert_eq!(inbox.conversations[0].pet_summary.as_ref().unwrap().name, "Millie");
+        assert_eq!(inbox.users[&vet].first_name.as_deref(), Some("Dana"));
+        assert!(inbox.users.contains_key(&tech));
+
+        ConversationService::mark_read(&pool, conversation.id, client, first.id).await.unwrap();
+        let unread = ConversationService::get_unread_counts(&pool, client, &[conversation.id]).await.unwrap();
+        assert_eq!(unread.get(&conversation.id), Some(&1));
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn previews_carry_only_the_last_message_and_unread_count() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        let (quiet, _) = ConversationService::create_conversation(&pool, vec![tech], None, client, pet, false).await.unwrap();
+        let moderator = RegexModerator::new(None, false, ModerationAction::Reject);
+        for (sender, content) in [(vet, "First"), (vet, "Second"), (client, "Reply")] {
+            ConversationService::send_message(
+                &pool, &moderator, sender, conversation.id, content.to_string(), Utc::now(), None, false
+            ).await.unwrap();
+        }
+        let conversation = ConversationService::get_conversation_by_id(&pool, conversation.id).await.unwrap().unwrap();
+
+        let previews = ConversationService::get_previews(&pool, client, vec![conversation.clone(), quiet.clone()]).await.unwrap();
+        let previews = serde_json::to_value(&previews).unwrap();
+        assert_eq!(previews, serde_json::json!([
+            {
+                "conversation_id": conversation.id,
+                "last_message": "Reply",
+                "last_updated_timestamp": conversation.last_updated_timestamp.timestamp_millis(),
+                "unread_count": 2
+            },
+            {
+                "conversation_id": quiet.id,
+                "last_message": quiet.last_message,
+                "last_updated_timestamp": quiet.last_updated_timestamp.timestamp_millis(),
+                "unread_count": 0
+            }
+        ]));
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn message_stats_count_each_senders_messages() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        let moderator = RegexModerator::new(None, false, ModerationAction::Reject);
+        let start = Utc::now() - chrono::Duration::hours(1);
+        let minutes = |n| start + chrono::Duration::minutes(n);
+        for (sender, content, sent_at) in [(client, "Millie is limping", minutes(0)), (vet, "Since when?", minutes(5)), (client, "This morning", minutes(9))] {
+            ConversationService::send_message(
+                &pool, &moderator, sender, conversation.id, content.to_string(), sent_at, None, false
+            ).await.unwrap();
+        }
+
+        let stats = ConversationService::get_message_stats(&pool, conversation.id).await.unwrap();
+        let counts: Vec<_> = stats.iter().map(|s| (s.sender_id, s.message_count)).collect();
+        assert_eq!(counts, vec![(client, 2), (vet, 1)]);
+        assert_eq!(stats[0].first_message_at.timestamp_millis(), minutes(0).timestamp_millis());
+        assert_eq!(stats[0].last_message_at.timestamp_millis(), minutes(9).timestamp_millis());
+        assert_eq!(stats[1].first_message_at, stats[1].last_message_at);
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn attachments_are_paged_newest_first() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        let start = Utc::now() - chrono::Duration::hours(1);
+        let mut attached = Vec::new();
+        for (minute, sender, attach) in [(0, client, true), (1, vet, false), (2, vet, true), (3, client, false), (4, client, true)] {
+            let image_id = match attach {
+                true => Some(sqlx::query!(
+                    "INSERT INTO images (id, user_id, image_type, image_url) VALUES ($1, $2, 'attachment', $3) RETURNING id",
+                    Uuid::new_v4(),
+                    sender,
+                    format!("https://example.com/{}.jpg", minute)
+                )
+                .fetch_one(&pool).await.unwrap().id),
+                false => None,
+            };
+            let timestamp = start + chrono::Duration::minutes(minute);
+            let message = ConversationService::insert_message(&pool, sender, conversation.id, "Photo".to_string(), timestamp, "text", image_id, false).await.unwrap();
+            if let Some(image_id) = image_id {
+                attached.push((message.id, image_id, sender));
+            }
+        }
+        attached.reverse();
+
+        let (page, has_more) = ConversationService::get_attachments(&pool, conversation.id, 2, None).await.unwrap();
+        let found: Vec<_> = page.iter().map(|a| (a.message_id, a.image_id, a.sender_id)).collect();
+        assert_eq!(found, attached[..2]);
+        assert!(has_more);
+        assert_eq!(page[0].image_url, "https://example.com/4.jpg");
+
+        let (page, has_more) = ConversationService::get_attachments(&pool, conversation.id, 2, Some(page[1].message_id)).await.unwrap();
+        let found: Vec<_> = page.iter().map(|a| (a.message_id, a.image_id, a.sender_id)).collect();
+        assert_eq!(found, attached[2..]);
+        assert!(!has_more);
+
+        assert!(matches!(
+            ConversationService::get_attachments(&pool, conversation.id, 2, Some(Uuid::new_v4())).await,
+            Err(HistoryError::Invalid(_))
+        ));
+        assert!(matches!(
+            ConversationService::get_attachments(&pool, conversation.id, 0, None).await,
+            Err(HistoryError::Invalid(_))
+        ));
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    // Records what would have been removed from GCS
+    #[derive(Default)]
+    struct RecordingStorage {
+        deleted: Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl ImageStorage for RecordingStorage {
+        async fn upload(&self, object_name: &str, _content_type: &str, _data: Vec<u8>) -> anyhow::Result<String> {
+            Ok(format!("https://storage.example.com/{}", object_name))
+        }
+
+        async fn delete(&self, image_url: &str) -> anyhow::Result<()> {
+            self.deleted.lock().unwrap().push(image_url.to_string());
+            Ok(())
+        }
+
+        async fn download(&self, image_url: &str) -> anyhow::Result<Vec<u8>> {
+            anyhow::bail!("{} was never stored", image_url)
+        }
+    }
+
+    #[tokio::test]
+    async fn permanent_deletion_removes_messages_and_their_attachments() {
+        let (pool, client, vet, tech, pet) = setup().await;
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet, false).await.unwrap();
+        let (other, _) = ConversationService::create_conversation(&pool, vec![tech], None, client, pet, false).await.unwrap();
+        let upload = |name: &'static str| {
+            let pool = pool.clone();
+            async move {
+                sqlx::query!(
+                    "INSERT INTO images (id, user_id, image_type, image_url) VALUES ($1, $2, 'attachment', $3) RETURNING id",
+                    Uuid::new_v4(),
+                    client,
+                    format!("https://storage.example.com/{}.jpg", name)
+                )
+                .fetch_one(&pool).await.unwrap().id
+            }
+        };
+        let xray = upload("xray").await;
+        let shared = upload("shared").await;
+        for (conversation_id, image_id) in [(conversation.id, Some(xray)), (conversation.id, Some(shared)), (conversation.id, None), (other.id, Some(shared))] {
+            ConversationService::insert_message(&pool, client, conversation_id, "Photo".to_string(), Utc::now(), "text", image_id, false).await.unwrap();
+        }
+
+        let storage = RecordingStorage::default();
+        let deleted = ConversationService::delete_permanently(&pool, &storage, conversation.id).await.unwrap().unwrap();
+        assert_eq!(deleted.message_count, 3);
+        assert_eq!(deleted.attachment_count, 1);
+        assert!(deleted.storage_failures.is_empty());
+        assert_eq!(*storage.deleted.lock().unwrap(), vec!["https://storage.example.com/xray.jpg".to_string()]);
+
+        let remaining = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM messages WHERE conversation_id = $1"#, conversation.id)
+            .fetch_one(&pool).await.unwrap().count;
+        assert_eq!(remaining, 0);
+        assert!(ConversationService::get_conversation_by_id(&pool, conversation.id).await.unwrap().is_none());
+        let images = sqlx::query!("SELECT id FROM images WHERE id = ANY($1)", &[xray, shared])
+            .fetch_all(&pool).await.unwrap();
+        assert_eq!(images.iter().map(|i| i.id).collect::<Vec<_>>(), vec![shared]);
+
+        // The other conversation keeps its copy of the shared photo
+        let (attachments, _) = ConversationService::get_attachments(&pool, other.id, 10, None).await.unwrap();
+        assert_eq!(attachments.iter().map(|a| a.image_id).collect::<Vec<_>>(), vec![shared]);
+        assert!(ConversationService::delete_permanently(&pool, &storage, conversation.id).await.unwrap().is_none());
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+}
@@ -0,0 +1,211 @@
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+use sqlx::PgPool;
+use std::fmt;
+
+/// Image types an upload may be filed under.
+pub const IMAGE_TYPES: &[&str] = &["profile", "pet", "attachment", "document"];
+
+// The content types a signed upload URL is issued for, with the extension the
+// object is stored under. PDFs are only accepted as documents.
+const CONTENT_TYPES: &[(&str, &str)] = &[
+    ("image/jpeg", "jpg"),
+    ("image/png", "png"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+    ("image/heic", "heic"),
+    ("application/pdf", "pdf"),
+];
+
+/// An image the client was given a signed URL to upload, not yet confirmed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectUpload {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub filename: Option<String>,
+    pub content_type: String,
+    pub image_type: String,
+    pub object_name: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum DirectUploadError {
+    NotFound,
+    Invalid(&'static str),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for DirectUploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DirectUploadError::NotFound => write!(f, "Upload not found or expired"),
+            DirectUploadError::Invalid(msg) => write!(f, "{}", msg),
+            DirectUploadError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for DirectUploadError {
+    fn from(e: sqlx::Error) -> Self {
+        DirectUploadError::Database(e)
+    }
+}
+
+pub struct DirectUploadService;
+
+impl DirectUploadService {
+    /// Records an upload the client may make until `expires_in` from now, and
+    /// picks the object it goes to. The content type must be on the allowlist.
+    pub async fn create(
+        pool: &PgPool,
+        user_id: Uuid,
+        image_type: &str,
+        content_type: &str,
+        filename: Option<String>,
+        expires_in: Duration,
+    ) -> Result<DirectUpload, DirectUploadError> {
+        let image_type = image_type.to_lowercase();
+        if !IMAGE_TYPES.contains(&image_type.as_str()) {
+            return Err(DirectUploadError::Invalid("Invalid image_type. Must be 'profile', 'pet', 'attachment' or 'document'"));
+        }
+        let content_type = content_type.trim().to_lowercase();
+        let extension = match CONTENT_TYPES.iter().find(|(allowed, _)| *allowed == content_type) {
+            Some((_, "pdf")) if image_type != "document" => {
+                return Err(DirectUploadError::Invalid("Only documents may be PDFs"));
+            },
+            Some((_, extension)) => extension,
+            None => return Err(DirectUploadError::Invalid("Unsupported content_type")),
+        };
+
+        let upload = DirectUpload {
+            id: Uuid::new_v4(),
+            user_id,
+            filename,
+            object_name: format!("{}/{}.{}", image_type, Uuid::new_v4(), extension),
+            content_type,
+            image_type,
+            expires_at: Utc::now() + expires_in,
+        };
+        sqlx::query!(
+            "
+            INSERT INTO direct_uploads (id, user_id, filename, content_type, image_type, object_name, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ",
+            upload.id,
+            upload.user_id,
+            upload.filename,
+            upload.content_type,
+            upload.image_type,
+            upload.object_name,
+            upload.expires_at
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(upload)
+    }
+
+    /// One of the user's uploads that hasn't been completed or expired.
+    pub async fn find(pool: &PgPool, user_id: Uuid, image_id: Uuid) -> Result<DirectUpload, DirectUploadError> {
+        sqlx::query_as!(
+            DirectUpload,
+            "
+            SELECT id, user_id, filename, content_type, image_type, object_name, expires_at
+            FROM direct_uploads
+            WHERE id = $1 AND user_id = $2 AND expires_at > CURRENT_TIMESTAMP
+            ",
+            image_id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or(DirectUploadError::NotFound)
+    }
+
+    /// Moves a confirmed upload into `images` under its id.
+    pub async fn complete(pool: &PgPool, upload: &DirectUpload, image_url: &str, size_bytes: i64) -> Result<(), DirectUploadError> {
+        let mut tx = pool.begin().await?;
+        let removed = sqlx::query!("DELETE FROM direct_uploads WHERE id = $1", upload.id)
+            .execute(&mut *tx)
+            .await?;
+        // Completed concurrently by another request
+        if removed.rows_affected() == 0 {
+            return Err(DirectUploadError::NotFound);
+        }
+        sqlx::query!(
+            "
+            INSERT INTO images (id, user_id, filename, content_type, image_type, image_url, size_bytes)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ",
+            upload.id,
+            upload.user_id,
+            upload.filename,
+            upload.content_type,
+            upload.image_type,
+            image_url,
+            size_bytes
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Forgets an upload that was refused, so it can't be completed later.
+    pub async fn discard(pool: &PgPool, image_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM direct_uploads WHERE id = $1", image_id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{insert_user, test_pool};
+
+    async fn setup() -> (PgPool, Uuid) {
+        let pool = test_pool().await;
+        let user_id = insert_user(&pool, "client").await;
+
+        (pool, user_id)
+    }
+
+    #[tokio::test]
+    async fn uploads_are_checked_then_moved_into_images_once() {
+        let (pool, user_id) = setup().await;
+        let ttl = Duration::minutes(15);
+
+        for (image_type, content_type) in [("pet", "image/svg+xml"), ("pet", "application/pdf"), ("banner", "image/png")] {
+            let result = DirectUploadService::create(&pool, user_id, image_type, content_type, None, ttl).await;
+            assert!(matches!(result, Err(DirectUploadError::Invalid(_))), "{} {}", image_type, content_type);
+        }
+        let document = DirectUploadService::create(&pool, user_id, "document", "application/pdf", None, ttl).await.unwrap();
+        assert!(document.object_name.starts_with("document/") && document.object_name.ends_with(".pdf"));
+
+        let upload = DirectUploadService::create(&pool, user_id, "Pet", "image/PNG", Some("millie.png".to_string()), ttl).await.unwrap();
+        assert_eq!((upload.image_type.as_str(), upload.content_type.as_str()), ("pet", "image/png"));
+        assert_eq!(DirectUploadService::find(&pool, user_id, upload.id).await.unwrap().object_name, upload.object_name);
+        assert!(matches!(DirectUploadService::find(&pool, Uuid::new_v4(), upload.id).await, Err(DirectUploadError::NotFound)));
+
+        DirectUploadService::complete(&pool, &upload, "https://storage.example/pet/millie.png", 2048).await.unwrap();
+        let image = sqlx::query!("SELECT user_id, image_url, size_bytes FROM images WHERE id = $1", upload.id)
+            .fetch_one(&pool).await.unwrap();
+        assert_eq!((image.user_id, image.size_bytes), (user_id, Some(2048)));
+        assert_eq!(image.image_url, "https://storage.example/pet/millie.png");
+        assert!(matches!(DirectUploadService::find(&pool, user_id, upload.id).await, Err(DirectUploadError::NotFound)));
+        assert!(matches!(
+            DirectUploadService::complete(&pool, &upload, "https://storage.example/pet/millie.png", 2048).await,
+            Err(DirectUploadError::NotFound)
+        ));
+
+        let expired = DirectUploadService::create(&pool, user_id, "pet", "image/jpeg", None, Duration::seconds(-1)).await.unwrap();
+        assert!(matches!(DirectUploadService::find(&pool, user_id, expired.id).await, Err(DirectUploadError::NotFound)));
+
+        sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+    }
+}
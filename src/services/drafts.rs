@@ -0,0 +1,135 @@
+use chrono::Utc;
+use uuid::Uuid;
+use sqlx::PgPool;
+use std::fmt;
+use crate::models::MessageDraft;
+use crate::services::conversations::{ConversationService, MAX_MESSAGE_LENGTH};
+use crate::services::pet_shares::AccessLevel;
+
+#[derive(Debug)]
+pub enum DraftError {
+    NotFound,
+    Forbidden,
+    Invalid(&'static str),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for DraftError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DraftError::NotFound => write!(f, "Conversation not found"),
+            DraftError::Forbidden => write!(f, "You can't send messages in this conversation"),
+            DraftError::Invalid(msg) => write!(f, "{}", msg),
+            DraftError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for DraftError {
+    fn from(e: sqlx::Error) -> Self {
+        DraftError::Database(e)
+    }
+}
+
+pub struct DraftService;
+
+impl DraftService {
+    /// The user's draft in the conversation, or an empty one if they have none.
+    pub async fn get_for_user(pool: &PgPool, conversation_id: Uuid, user_id: Uuid) -> Result<MessageDraft, DraftError> {
+        if ConversationService::get_access(pool, conversation_id, user_id).await?.is_none() {
+            return Err(DraftError::NotFound);
+        }
+        let draft = sqlx::query_as!(
+            MessageDraft,
+            r#"
+            SELECT conversation_id, content, updated_at AS "updated_at?"
+            FROM message_drafts
+            WHERE conversation_id = $1 AND user_id = $2
+            "#,
+            conversation_id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(draft.unwrap_or(MessageDraft { conversation_id, content: String::new(), updated_at: None }))
+    }
+
+    /// Replaces the user's draft; empty `content` discards it. Only users who
+    /// can send messages in the conversation keep drafts there.
+    pub async fn save(pool: &PgPool, conversation_id: Uuid, user_id: Uuid, content: &str) -> Result<MessageDraft, DraftError> {
+        match ConversationService::get_access(pool, conversation_id, user_id).await? {
+            Some(AccessLevel::ReadWrite) => {},
+            Some(AccessLevel::Read) => return Err(DraftError::Forbidden),
+            None => return Err(DraftError::NotFound),
+        }
+        if content.chars().count() > MAX_MESSAGE_LENGTH {
+            return Err(DraftError::Invalid("Draft is too long (max 4000 characters)"));
+        }
+
+        if content.is_empty() {
+            sqlx::query!(
+                "DELETE FROM message_drafts WHERE conversation_id = $1 AND user_id = $2",
+                conversation_id,
+                user_id
+            )
+            .execute(pool)
+            .await?;
+            return Ok(MessageDraft { conversation_id, content: String::new(), updated_at: Some(Utc::now()) });
+        }
+
+        Ok(sqlx::query_as!(
+            MessageDraft,
+            r#"
+            INSERT INTO message_drafts (user_id, conversation_id, content)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, conversation_id)
+            DO UPDATE SET content = EXCLUDED.content, updated_at = CURRENT_TIMESTAMP
+            RETURNING conversation_id, content, updated_at AS "updated_at?"
+            "#,
+            user_id,
+            conversation_id,
+            content
+        )
+        .fetch_one(pool)
+        .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{insert_pet, insert_user, test_pool};
+
+    #[tokio::test]
+    async fn drafts_are_private_and_discarded_when_emptied() {
+        let pool = test_pool().await;
+
+        let mut user_ids = Vec::new();
+        for scope in ["client", "provider", "provider"] {
+            let id = insert_user(&pool, scope).await;
+            user_ids.push(id);
+        }
+        let (client, vet, stranger) = (user_ids[0], user_ids[1], user_ids[2]);
+        let pet_id = insert_pet(&pool, client).await;
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![vet], None, client, pet_id, false).await.unwrap();
+
+        let saved = DraftService::save(&pool, conversation.id, client, "She ate half").await.unwrap();
+        assert!(saved.updated_at.is_some());
+        let saved = DraftService::save(&pool, conversation.id, client, "She ate half her breakfast").await.unwrap();
+        assert_eq!(DraftService::get_for_user(&pool, conversation.id, client).await.unwrap(), saved);
+
+        // The other participant has their own, empty draft; outsiders have none
+        assert_eq!(DraftService::get_for_user(&pool, conversation.id, vet).await.unwrap().content, "");
+        assert!(matches!(DraftService::save(&pool, conversation.id, stranger, "Hi").await, Err(DraftError::NotFound)));
+        assert!(matches!(
+            DraftService::save(&pool, conversation.id, client, &"a".repeat(MAX_MESSAGE_LENGTH + 1)).await,
+            Err(DraftError::Invalid(_))
+        ));
+
+        DraftService::save(&pool, conversation.id, client, "").await.unwrap();
+        assert_eq!(DraftService::get_for_user(&pool, conversation.id, client).await.unwrap().updated_at, None);
+
+        sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &user_ids).execute(&pool).await.unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use crate::config::Config;
+
+pub const CANNED_RESPONSES: &str = "canned_responses";
+
+/// Flags the server checks, and whether each is on when `FEATURE_FLAGS` doesn't say.
+pub const DEFAULT_FLAGS: &[(&str, bool)] = &[
+    (CANNED_RESPONSES, true),
+];
+
+pub struct FeatureFlagService;
+
+impl FeatureFlagService {
+    /// Every flag's state for this user: the environment's setting, unless the user
+    /// has an override.
+    pub async fn flags_for_user(pool: &PgPool, config: &Config, user_id: Uuid) -> Result<BTreeMap<String, bool>, sqlx::Error> {
+        let mut flags = config.feature_flags.clone();
+        let overrides = sqlx::query!(
+            "SELECT flag, enabled FROM feature_flag_overrides WHERE user_id = $1",
+            user_id
+        )
+        .fetch_all(pool)
+        .await?;
+        for record in overrides {
+            flags.insert(record.flag, record.enabled);
+        }
+        Ok(flags)
+    }
+
+    /// Names of the flags turned on for this user, for clients to adapt their UI.
+    pub async fn enabled_for_user(pool: &PgPool, config: &Config, user_id: Uuid) -> Result<Vec<String>, sqlx::Error> {
+        Ok(Self::flags_for_user(pool, config, user_id)
+            .await?
+            .into_iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(flag, _)| flag)
+            .collect())
+    }
+
+    /// Whether `flag` is on for this user. If the override can't be read, the
+    /// environment's setting applies.
+    pub async fn is_enabled(pool: &PgPool, config: &Config, user_id: Uuid, flag: &str) -> bool {
+        let default = config.feature_flags.get(flag).copied().unwrap_or(false);
+        match sqlx::query!(
+            "SELECT enabled FROM feature_flag_overrides WHERE user_id = $1 AND flag = $2",
+            user_id,
+            flag
+        )
+        .fetch_optional(pool)
+        .await {
+            Ok(record) => record.map(|record| record.enabled).unwrap_or(default),
+            Err(e) => {
+                logln!("Failed to read feature flag override {} for user {}: {}", flag, user_id, e);
+                default
+            }
+        }
+    }
+
+    /// Turns a flag on or off for one user; `None` removes the override.
+    pub async fn set_override(pool: &PgPool, user_id: Uuid, flag: &str, enabled: Option<bool>) -> Result<(), sqlx::Error> {
+        match enabled {
+            Some(enabled) => {
+                sqlx::query!(
+                    "
+                    INSERT INTO feature_flag_overrides (user_id, flag, enabled)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT (user_id, flag) DO UPDATE SET enabled = EXCLUDED.enabled
+                    ",
+                    user_id,
+                    flag,
+                    enabled
+                )
+                .execute(pool)
+                .await?;
+            },
+            None => {
+                sqlx::query!(
+                    "DELETE FROM feature_flag_overrides WHERE user_id = $1 AND flag = $2",
+                    user_id,
+                    flag
+                )
+                .execute(pool)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{cleanup_users, insert_user, test_pool};
+
+    async fn setup() -> (PgPool, Uuid) {
+        let pool = test_pool().await;
+
+        let user_id = insert_user(&pool, "provider").await;
+
+        (pool, user_id)
+    }
+
+
+    #[tokio::test]
+    async fn overrides_beat_the_environment() {
+        let (pool, user_id) = setup().await;
+        let mut config = Config::default();
+        config.feature_flags.insert("new_inbox".to_string(), false);
+
+        assert!(FeatureFlagService::is_enabled(&pool, &config, user_id, CANNED_RESPONSES).await);
+        assert!(!FeatureFlagService::is_enabled(&pool, &config, user_id, "new_inbox").await);
+        // Flags nobody configured are off
+        assert!(!FeatureFlagService::is_enabled(&pool, &config, user_id, "unknown").await);
+
+        FeatureFlagService::set_override(&pool, user_id, CANNED_RESPONSES, Some(false)).await.unwrap();
+        FeatureFlagService::set_override(&pool, user_id, "new_inbox", Some(true)).await.unwrap();
+        assert!(!FeatureFlagService::is_enabled(&pool, &config, user_id, CANNED_RESPONSES).await);
+        assert_eq!(FeatureFlagService::enabled_for_user(&pool, &config, user_id).await.unwrap(), vec!["new_inbox"]);
+
+        // Removing the override falls back to the environment
+        FeatureFlagService::set_override(&pool, user_id, CANNED_RESPONSES, None).await.unwrap();
+        assert!(FeatureFlagService::is_enabled(&pool, &config, user_id, CANNED_RESPONSES).await);
+
+        cleanup_users(&pool, &[user_id]).await;
+    }
+}
@@ -0,0 +1,168 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use chrono::{Duration, Utc};
+
+/// A response recorded for an idempotency key.
+pub struct StoredResponse {
+    pub status_code: u16,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+pub enum Reservation {
+    /// The caller owns the key and should execute the request.
+    Acquired,
+    /// The request already completed; replay this response.
+    Completed(StoredResponse),
+    /// Another request with the same key is still running.
+    InProgress,
+    /// The key was already used for a different endpoint.
+    Mismatch,
+}
+
+pub struct IdempotencyService;
+
+impl IdempotencyService {
+    /// Claims `key` for this request for `lease`, until `complete` records the
+    /// response. Expired keys, and reservations whose lease ran out without a
+    /// response, are taken over as if they were new.
+    pub async fn reserve(
+        pool: &PgPool,
+        user_id: Uuid,
+        key: &str,
+        endpoint: &str,
+        lease: Duration,
+    ) -> Result<Reservation, sqlx::Error> {
+        let acquired = sqlx::query!(
+            "
+            INSERT INTO idempotency_keys (user_id, idempotency_key, endpoint, expires_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, idempotency_key) DO UPDATE
+            SET endpoint = EXCLUDED.endpoint,
+                status_code = NULL,
+                content_type = NULL,
+                response_body = NULL,
+                created_at = CURRENT_TIMESTAMP,
+                expires_at = EXCLUDED.expires_at
+            WHERE idempotency_keys.expires_at < CURRENT_TIMESTAMP
+            RETURNING user_id
+            ",
+            user_id,
+            key,
+            endpoint,
+            Utc::now() + lease
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        if acquired.is_some() {
+            return Ok(Reservation::Acquired);
+        }
+
+        let existing = sqlx::query!(
+            "
+            SELECT endpoint, status_code, content_type, response_body
+            FROM idempotency_keys
+            WHERE user_id = $1 AND idempotency_key = $2
+            ",
+            user_id,
+            key
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(match existing {
+            // Released between the two queries; let the client retry
+            None => Reservation::InProgress,
+            Some(row) if row.endpoint != endpoint => Reservation::Mismatch,
+            Some(row) => match row.status_code {
+                Some(status_code) => Reservation::Completed(StoredResponse {
+                    status_code: status_code as u16,
+                    content_type: row.content_type,
+                    body: row.response_body.unwrap_or_default(),
+                }),
+                None => Reservation::InProgress,
+            },
+        })
+    }
+
+    /// Records the response, to be replayed for `ttl`.
+    pub async fn complete(
+        pool: &PgPool,
+        user_id: Uuid,
+        key: &str,
+        response: &StoredResponse,
+        ttl: Duration,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "
+            UPDATE idempotency_keys
+            SET status_code = $1, content_type = $2, response_body = $3, expires_at = $4
+            WHERE user_id = $5 AND idempotency_key = $6
+            ",
+            response.status_code as i16,
+            response.content_type,
+            response.body,
+            Utc::now() + ttl,
+            user_id,
+            key
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Drops a reservation so the request can be retried with the same key.
+    pub async fn release(pool: &PgPool, user_id: Uuid, key: &str) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "DELETE FROM idempotency_keys WHERE user_id = $1 AND idempotency_key = $2",
+            user_id,
+            key
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_expired(pool: &PgPool) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!("DELETE FROM idempotency_keys WHERE expires_at < CURRENT_TIMESTAMP")
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{cleanup_users, insert_user, test_pool};
+
+    #[tokio::test]
+    async fn abandoned_reservations_free_up_after_the_lease() {
+        let pool = test_pool().await;
+        let user_id = insert_user(&pool, "client").await;
+        let lease = Duration::milliseconds(300);
+        let reserve = || IdempotencyService::reserve(&pool, user_id, "retry-me", "POST /profile", lease);
+
+        // Reserved and never completed, as when the client hangs up mid-request
+        assert!(matches!(reserve().await.unwrap(), Reservation::Acquired));
+        assert!(matches!(reserve().await.unwrap(), Reservation::InProgress));
+
+        tokio::time::sleep(std::time::Duration::from_millis(400)).await;
+        assert!(matches!(reserve().await.unwrap(), Reservation::Acquired));
+
+        // Once completed the response outlives the lease
+        let response = StoredResponse { status_code: 200, content_type: None, body: b"ok".to_vec() };
+        IdempotencyService::complete(&pool, user_id, "retry-me", &response, Duration::hours(1)).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(400)).await;
+        match reserve().await.unwrap() {
+            Reservation::Completed(stored) => assert_eq!(stored.body, b"ok"),
+            _ => panic!("expected the recorded response"),
+        }
+
+        cleanup_users(&pool, &[user_id]).await;
+    }
+}
@@ -0,0 +1,42 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use crate::models::Image;
+use crate::services::conversations::ConversationService;
+
+pub struct ImageService;
+
+impl ImageService {
+    /// An image the user may see: one of their own, or one attached to a message
+    /// in a conversation they can access.
+    pub async fn find_viewable(pool: &PgPool, user_id: Uuid, image_id: Uuid) -> Result<Option<Image>, sqlx::Error> {
+        let image = sqlx::query_as!(
+            Image,
+            "SELECT id, user_id, filename, content_type, image_type, image_url, created_at, updated_at
+             FROM images
+             WHERE id = $1",
+            image_id
+        )
+        .fetch_optional(pool)
+        .await?;
+        let Some(image) = image else {
+            return Ok(None);
+        };
+        if image.user_id == user_id {
+            return Ok(Some(image));
+        }
+
+        let conversation_ids = sqlx::query_scalar!(
+            "SELECT DISTINCT conversation_id FROM messages WHERE attachment_image_id = $1",
+            image_id
+        )
+        .fetch_all(pool)
+        .await?;
+        for conversation_id in conversation_ids {
+            if ConversationService::get_access(pool, conversation_id, user_id).await?.is_some() {
+                return Ok(Some(image));
+            }
+        }
+
+        Ok(None)
+    }
+}
@@ -1 +1,26 @@
-pub mod conversations;
\ No newline at end of file
+pub mod access_log;
+pub mod appointments;
+pub mod audit;
+pub mod canned_responses;
+pub mod cold_storage;
+pub mod conversations;
+pub mod direct_uploads;
+pub mod drafts;
+pub mod feature_flags;
+pub mod idempotency;
+pub mod images;
+pub mod notes;
+pub mod notification_preferences;
+pub mod organizations;
+pub mod pending_events;
+pub mod pending_uploads;
+pub mod pet_documents;
+pub mod pet_shares;
+pub mod provider_pets;
+pub mod reminders;
+pub mod request_nonces;
+pub mod revoked_tokens;
+pub mod service_accounts;
+pub mod sessions;
+pub mod storage_quota;
+pub mod user_admin;
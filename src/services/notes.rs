@@ -0,0 +1,216 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use std::fmt;
+use crate::models::ConversationNote;
+use crate::services::conversations::ConversationService;
+use crate::services::pet_shares::AccessLevel;
+
+const MAX_NOTE_LENGTH: usize = 10_000;
+
+#[derive(Debug)]
+pub enum NoteError {
+    NotFound,
+    Forbidden,
+    Invalid(&'static str),
+    /// The note changed since the version the editor started from; holds the current one.
+    Conflict(ConversationNote),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for NoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoteError::NotFound => write!(f, "Conversation not found"),
+            NoteError::Forbidden => write!(f, "Only providers can edit the note"),
+            NoteError::Invalid(msg) => write!(f, "{}", msg),
+            NoteError::Conflict(_) => write!(f, "The note was changed by someone else"),
+            NoteError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for NoteError {
+    fn from(e: sqlx::Error) -> Self {
+        NoteError::Database(e)
+    }
+}
+
+pub struct NoteService;
+
+impl NoteService {
+    /// The conversation's note, or an empty one at version 0 if none was written.
+    /// Doesn't check access.
+    pub async fn get(pool: &PgPool, conversation_id: Uuid) -> Result<ConversationNote, sqlx::Error> {
+        let note = sqlx::query_as!(
+            ConversationNote,
+            r#"
+            SELECT conversation_id, content, updated_by, version, updated_at AS "updated_at?"
+            FROM conversation_notes
+            WHERE conversation_id = $1
+            "#,
+            conversation_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(note.unwrap_or(ConversationNote {
+            conversation_id,
+            content: String::new(),
+            updated_by: None,
+            version: 0,
+            updated_at: None,
+        }))
+    }
+
+    /// The note if `user_id` can see the conversation.
+    pub async fn get_for_user(pool: &PgPool, conversation_id: Uuid, user_id: Uuid) -> Result<ConversationNote, NoteError> {
+        if ConversationService::get_access(pool, conversation_id, user_id).await?.is_none() {
+            return Err(NoteError::NotFound);
+        }
+        Ok(Self::get(pool, conversation_id).await?)
+    }
+
+    /// Replaces the note, provided it is still at `version` (0 to write the first
+    /// one). Only providers taking part in the conversation may edit it.
+    pub async fn update(
+        pool: &PgPool,
+        conversation_id: Uuid,
+        user_id: Uuid,
+        content: &str,
+        version: i32,
+    ) -> Result<ConversationNote, NoteError> {
+        match ConversationService::get_access(pool, conversation_id, user_id).await? {
+            Some(AccessLevel::ReadWrite) => {},
+            Some(AccessLevel::Read) => return Err(NoteError::Forbidden),
+            None => return Err(NoteError::NotFound),
+        }
+        let is_provider = sqlx::query!("SELECT scope FROM users WHERE id = $1", user_id)
+            .fetch_optional(pool)
+            .await?
+            .is_some_and(|user| user.scope == "provider");
+        if !is_provider {
+            return Err(NoteError::Forbidden);
+        }
+        if content.chars().count() > MAX_NOTE_LENGTH {
+            return Err(NoteError::Invalid("Note is too long (max 10000 characters)"));
+        }
+
+        let updated = if version == 0 {
+            sqlx::query_as!(
+                ConversationNote,
+                r#"
+                INSERT INTO conversation_notes (conversation_id, content, updated_by)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (conversation_id) DO NOTHING
+                RETURNING conversation_id, content, updated_by, version, updated_at AS "updated_at?"
+                "#,
+                conversation_id,
+                content,
+                user_id
+            )
+            .fetch_optional(pool)
+            .await?
+        } else {
+            sqlx::query_as!(
+                ConversationNote,
+                r#"
+                UPDATE conversation_notes
+                SET content = $2, updated_by = $3, version = version + 1, updated_at = CURRENT_TIMESTAMP
+                WHERE conversation_id = $1 AND version = $4
+                RETURNING conversation_id, content, updated_by, version, updated_at AS "updated_at?"
+                "#,
+                conversation_id,
+                content,
+                user_id,
+                version
+            )
+            .fetch_optional(pool)
+            .await?
+        };
+
+        match updated {
+            Some(note) => Ok(note),
+            None => Err(NoteError::Conflict(Self::get(pool, conversation_id).await?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{cleanup_users, insert_user, test_pool};
+
+    async fn setup() -> (PgPool, Uuid, Uuid, Uuid, Uuid) {
+        let pool = test_pool().await;
+
+        let mut user_ids = Vec::new();
+        for scope in ["client", "provider", "provider"] {
+            let id = insert_user(&pool, scope).await;
+            user_ids.push(id);
+        }
+        let pet_id = sqlx::query!(
+            "INSERT INTO pets (user_id, name, breed, sex, birthday) VALUES ($1, 'Millie', 'Mutt', 'F', NOW()) RETURNING id",
+            user_ids[0]
+        )
+        .fetch_one(&pool).await.unwrap().id;
+        let (conversation, _) = ConversationService::create_conversation(
+            &pool, vec![user_ids[1], user_ids[2]], None, user_ids[0], pet_id, false
+        ).await.unwrap();
+
+        (pool, user_ids[0], user_ids[1], user_ids[2], conversation.id)
+    }
+
+
+    #[tokio::test]
+    async fn concurrent_edits_from_the_same_version_conflict() {
+        let (pool, client, vet, tech, conversation_id) = setup().await;
+        assert_eq!(NoteService::get(&pool, conversation_id).await.unwrap().version, 0);
+
+        let first = NoteService::update(&pool, conversation_id, vet, "Treatment plan: rest", 0).await.unwrap();
+        assert_eq!(first.version, 1);
+
+        // Both start from version 1; the second save loses and gets the current note back
+        let (vet_edit, tech_edit) = tokio::join!(
+            NoteService::update(&pool, conversation_id, vet, "Rest and fluids", 1),
+            NoteService::update(&pool, conversation_id, tech, "Rest and antibiotics", 1),
+        );
+        let (saved, conflict) = match (vet_edit, tech_edit) {
+            (Ok(saved), Err(conflict)) | (Err(conflict), Ok(saved)) => (saved, conflict),
+            other => panic!("expected exactly one edit to win, got {:?}", other),
+        };
+        assert_eq!(saved.version, 2);
+        match conflict {
+            NoteError::Conflict(current) => assert_eq!(current, saved),
+            e => panic!("expected a conflict, got {:?}", e),
+        }
+
+        // Writing a first note over an existing one conflicts too
+        assert!(matches!(
+            NoteService::update(&pool, conversation_id, vet, "Start over", 0).await,
+            Err(NoteError::Conflict(_))
+        ));
+        assert_eq!(NoteService::get(&pool, conversation_id).await.unwrap(), saved);
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+
+    #[tokio::test]
+    async fn clients_can_read_but_not_edit() {
+        let (pool, client, vet, tech, conversation_id) = setup().await;
+        NoteService::update(&pool, conversation_id, vet, "Recheck in 2 weeks", 0).await.unwrap();
+
+        assert!(matches!(
+            NoteService::update(&pool, conversation_id, client, "Actually 3 weeks", 1).await,
+            Err(NoteError::Forbidden)
+        ));
+        let note = NoteService::get_for_user(&pool, conversation_id, client).await.unwrap();
+        assert_eq!(note.content, "Recheck in 2 weeks");
+        assert_eq!(note.updated_by, Some(vet));
+
+        // Outsiders can't tell the conversation exists
+        let outsider = Uuid::new_v4();
+        assert!(matches!(NoteService::get_for_user(&pool, conversation_id, outsider).await, Err(NoteError::NotFound)));
+
+        cleanup_users(&pool, &[client, vet, tech]).await;
+    }
+}
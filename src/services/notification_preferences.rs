@@ -0,0 +1,143 @@
+use chrono::Utc;
+use uuid::Uuid;
+use sqlx::PgPool;
+use std::fmt;
+use crate::models::{NotificationPreference, NotificationPreferenceData};
+use crate::services::conversations::ConversationService;
+
+/// Notify through push, falling back to SMS when the user has no push channel.
+pub const LEVEL_ALL: &str = "all";
+/// Notify through push only; never text the user.
+pub const LEVEL_PUSH_ONLY: &str = "push_only";
+
+#[derive(Debug)]
+pub enum NotificationPreferenceError {
+    NotFound,
+    Invalid(&'static str),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for NotificationPreferenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotificationPreferenceError::NotFound => write!(f, "Conversation not found"),
+            NotificationPreferenceError::Invalid(msg) => write!(f, "{}", msg),
+            NotificationPreferenceError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for NotificationPreferenceError {
+    fn from(e: sqlx::Error) -> Self {
+        NotificationPreferenceError::Database(e)
+    }
+}
+
+pub struct NotificationPreferenceService;
+
+impl NotificationPreferenceService {
+    /// The preference that applies to a notification about `conversation_id`
+    /// (or about no conversation): the conversation's own, else the user's
+    /// default, else unmuted at level "all".
+    pub async fn effective(pool: &PgPool, user_id: Uuid, conversation_id: Option<Uuid>) -> Result<NotificationPreference, sqlx::Error> {
+        let preference = sqlx::query_as!(
+            NotificationPreference,
+            "
+            SELECT conversation_id, muted, level, muted_until
+            FROM notification_preferences
+            WHERE user_id = $1 AND (conversation_id IS NULL OR conversation_id = $2)
+            ORDER BY conversation_id NULLS LAST
+            LIMIT 1
+            ",
+            user_id,
+            conversation_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(preference.unwrap_or(NotificationPreference {
+            conversation_id: None,
+            muted: false,
+            level: LEVEL_ALL.to_string(),
+            muted_until: None,
+        }))
+    }
+
+    /// The user's default and every conversation they've set a preference for.
+    pub async fn list(pool: &PgPool, user_id: Uuid) -> Result<(NotificationPreference, Vec<NotificationPreference>), sqlx::Error> {
+        let mut conversations = sqlx::query_as!(
+            NotificationPreference,
+            "
+            SELECT conversation_id, muted, level, muted_until
+            FROM notification_preferences
+            WHERE user_id = $1
+            ORDER BY conversation_id NULLS FIRST
+            ",
+            user_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let default = match conversations.first() {
+            Some(preference) if preference.conversation_id.is_none() => conversations.remove(0),
+            _ => NotificationPreference { conversation_id: None, muted: false, level: LEVEL_ALL.to_string(), muted_until: None },
+        };
+        Ok((default, conversations))
+    }
+
+    /// Sets the user's default, or with `conversation_id` their preference for
+    /// a conversation they can access. A `muted_until` snoozes notifications
+    /// until then; leaving it out ends any snooze.
+    pub async fn set(
+        pool: &PgPool,
+        user_id: Uuid,
+        conversation_id: Option<Uuid>,
+        data: &NotificationPreferenceData,
+    ) -> Result<NotificationPreference, NotificationPreferenceError> {
+        let level = data.level.as_deref().unwrap_or(LEVEL_ALL);
+        if level != LEVEL_ALL && level != LEVEL_PUSH_ONLY {
+            return Err(NotificationPreferenceError::Invalid("level must be \"all\" or \"push_only\""));
+        }
+        if data.muted_until.is_some_and(|until| until <= Utc::now()) {
+            return Err(NotificationPreferenceError::Invalid("muted_until must be in the future"));
+        }
+        if let Some(conversation_id) = conversation_id {
+            if ConversationService::get_access(pool, conversation_id, user_id).await?.is_none() {
+                return Err(NotificationPreferenceError::NotFound);
+            }
+        }
+
+        Ok(sqlx::query_as!(
+            NotificationPreference,
+            "
+            INSERT INTO notification_preferences (user_id, conversation_id, muted, level, muted_until)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (user_id, conversation_id)
+            DO UPDATE SET muted = EXCLUDED.muted, level = EXCLUDED.level, muted_until = EXCLUDED.muted_until,
+                updated_at = CURRENT_TIMESTAMP
+            RETURNING conversation_id, muted, level, muted_until
+            ",
+            user_id,
+            conversation_id,
+            data.muted,
+            level,
+            data.muted_until
+        )
+        .fetch_one(pool)
+        .await?)
+    }
+
+    /// Drops the user's preference for a conversation so their default applies
+    /// again. Returns false if they hadn't set one.
+    pub async fn clear(pool: &PgPool, user_id: Uuid, conversation_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM notification_preferences WHERE user_id = $1 AND conversation_id = $2",
+            user_id,
+            conversation_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
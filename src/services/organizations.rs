@@ -0,0 +1,210 @@
+use uuid::Uuid;
+use sqlx::{PgConnection, PgPool};
+use std::collections::HashMap;
+use std::fmt;
+use crate::models::{Organization, OrganizationSummary};
+
+#[derive(Debug)]
+pub enum OrganizationError {
+    NotFound(&'static str),
+    Invalid(&'static str),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for OrganizationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrganizationError::NotFound(msg) | OrganizationError::Invalid(msg) => write!(f, "{}", msg),
+            OrganizationError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for OrganizationError {
+    fn from(e: sqlx::Error) -> Self {
+        OrganizationError::Database(e)
+    }
+}
+
+const ROUTING_MODES: [&str; 2] = ["broadcast", "round_robin"];
+
+pub struct OrganizationService;
+
+impl OrganizationService {
+    pub async fn create(pool: &PgPool, name: &str) -> Result<OrganizationSummary, OrganizationError> {
+        let name = name.trim();
+        if name.is_empty() || name.len() > 100 {
+            return Err(OrganizationError::Invalid("Name must be between 1 and 100 characters"));
+        }
+
+        Ok(sqlx::query_as!(
+            OrganizationSummary,
+            "INSERT INTO organizations (name) VALUES ($1) RETURNING id, name",
+            name
+        )
+        .fetch_one(pool)
+        .await?)
+    }
+
+    pub async fn set_routing_mode(pool: &PgPool, organization_id: Uuid, routing_mode: &str) -> Result<Organization, OrganizationError> {
+        if !ROUTING_MODES.contains(&routing_mode) {
+            return Err(OrganizationError::Invalid("routing_mode must be \"broadcast\" or \"round_robin\""));
+        }
+
+        sqlx::query_as!(
+            Organization,
+            "UPDATE organizations SET routing_mode = $1 WHERE id = $2 RETURNING id, name, routing_mode",
+            routing_mode,
+            organization_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or(OrganizationError::NotFound("Organization not found"))
+    }
+
+    /// The member a new conversation is routed to, for organizations that route
+    /// round-robin: whoever is a provider on the fewest conversations, and of
+    /// those whoever was picked longest ago. `None` for organizations that
+    /// route to every member, or have none. Run it in the transaction that
+    /// creates the conversation, so concurrent picks see each other.
+    pub async fn pick_provider(conn: &mut PgConnection, organization_id: Uuid) -> Result<Option<Uuid>, sqlx::Error> {
+        let organization = sqlx::query!(
+            "SELECT routing_mode FROM organizations WHERE id = $1 FOR UPDATE",
+            organization_id
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+        match organization {
+            Some(organization) if organization.routing_mode == "round_robin" => {},
+            _ => return Ok(None),
+        }
+
+        let picked = sqlx::query!(
+            "
+            UPDATE organization_members
+            SET last_routed_at = CURRENT_TIMESTAMP
+            WHERE organization_id = $1 AND user_id = (
+                SELECT m.user_id
+                FROM organization_members m
+                WHERE m.organization_id = $1
+                ORDER BY (SELECT COUNT(*) FROM conversations c WHERE m.user_id = ANY(c.providers)),
+                         m.last_routed_at NULLS FIRST,
+                         m.joined_at
+                LIMIT 1
+            )
+            RETURNING user_id
+            ",
+            organization_id
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        Ok(picked.map(|row| row.user_id))
+    }
+
+    /// Adds a provider to the organization. Returns false if they were already a member.
+    pub async fn add_member(pool: &PgPool, organization_id: Uuid, user_id: Uuid) -> Result<bool, OrganizationError> {
+        let organization = sqlx::query!("SELECT id FROM organizations WHERE id = $1", organization_id)
+            .fetch_optional(pool)
+            .await?;
+        if organization.is_none() {
+            return Err(OrganizationError::NotFound("Organization not found"));
+        }
+        let is_provider = sqlx::query!("SELECT scope FROM users WHERE id = $1", user_id)
+            .fetch_optional(pool)
+            .await?
+            .is_some_and(|user| user.scope == "provider");
+        if !is_provider {
+            return Err(OrganizationError::Invalid("Only providers can join an organization"));
+        }
+
+        let result = sqlx::query!(
+            "
+            INSERT INTO organization_members (organization_id, user_id) VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            ",
+            organization_id,
+            user_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Removes a member. Returns false if they weren't one.
+    pub async fn remove_member(pool: &PgPool, organization_id: Uuid, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM organization_members WHERE organization_id = $1 AND user_id = $2",
+            organization_id,
+            user_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn member_ids(pool: &PgPool, organization_id: Uuid) -> Result<Vec<Uuid>, sqlx::Error> {
+        let rows = sqlx::query!(
+            "SELECT user_id FROM organization_members WHERE organization_id = $1 ORDER BY joined_at",
+            organization_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| row.user_id).collect())
+    }
+
+    pub async fn is_member(pool: &PgPool, organization_id: Uuid, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        let row = sqlx::query!(
+            "SELECT 1 AS found FROM organization_members WHERE organization_id = $1 AND user_id = $2",
+            organization_id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(row.is_some())
+    }
+
+    /// Conversations the user reaches only through the organization, i.e. those
+    /// routed to it where they aren't also the client or a named provider. Their
+    /// subscriptions to these follow their membership.
+    pub async fn routed_conversation_ids(pool: &PgPool, organization_id: Uuid, user_id: Uuid) -> Result<Vec<Uuid>, sqlx::Error> {
+        let rows = sqlx::query!(
+            "
+            SELECT id FROM conversations
+            WHERE organization_id = $1 AND client <> $2 AND NOT ($2 = ANY(providers))
+            ",
+            organization_id,
+            user_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| row.id).collect())
+    }
+
+    /// The organizations each of the users belongs to.
+    pub async fn memberships(pool: &PgPool, user_ids: &[Uuid]) -> Result<HashMap<Uuid, Vec<OrganizationSummary>>, sqlx::Error> {
+        let rows = sqlx::query!(
+            "
+            SELECT m.user_id, o.id, o.name
+            FROM organization_members m
+            JOIN organizations o ON o.id = m.organization_id
+            WHERE m.user_id = ANY($1)
+            ORDER BY o.name
+            ",
+            user_ids
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut memberships: HashMap<Uuid, Vec<OrganizationSummary>> = HashMap::new();
+        for row in rows {
+            memberships.entry(row.user_id).or_default().push(OrganizationSummary { id: row.id, name: row.name });
+        }
+        Ok(memberships)
+    }
+}
@@ -0,0 +1,124 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use chrono::Duration;
+use crate::models::WsMessage;
+
+pub struct PendingEventService;
+
+impl PendingEventService {
+    /// Stores an event for a user with no open session. Only the newest
+    /// `max_per_user` undelivered events are kept.
+    pub async fn enqueue(
+        pool: &PgPool,
+        user_id: Uuid,
+        message: &WsMessage,
+        max_per_user: usize,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "INSERT INTO pending_events (user_id, event, params) VALUES ($1, $2, $3)",
+            user_id,
+            message.event,
+            message.params
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query!(
+            "
+            DELETE FROM pending_events
+            WHERE user_id = $1 AND delivered_at IS NULL AND id NOT IN (
+                SELECT id FROM pending_events
+                WHERE user_id = $1 AND delivered_at IS NULL
+                ORDER BY created_at DESC
+                LIMIT $2
+            )
+            ",
+            user_id,
+            max_per_user as i64
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Marks the user's undelivered events as delivered and returns them, oldest first.
+    pub async fn take_undelivered(pool: &PgPool, user_id: Uuid) -> Result<Vec<WsMessage>, sqlx::Error> {
+        let mut rows = sqlx::query!(
+            "
+            UPDATE pending_events
+            SET delivered_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 AND delivered_at IS NULL
+            RETURNING event, params, created_at
+            ",
+            user_id
+        )
+        .fetch_all(pool)
+        .await?;
+        rows.sort_by_key(|row| row.created_at);
+
+        Ok(rows
+            .into_iter()
+            .map(|row| WsMessage {
+                sender_id: Uuid::nil(),
+                event: row.event,
+                params: row.params,
+            })
+            .collect())
+    }
+
+    /// Removes delivered events, and undelivered ones older than `max_age`.
+    pub async fn delete_expired(pool: &PgPool, max_age: Duration) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            "
+            DELETE FROM pending_events
+            WHERE delivered_at IS NOT NULL
+               OR created_at < CURRENT_TIMESTAMP - make_interval(secs => $1)
+            ",
+            max_age.num_seconds() as f64
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{insert_user, test_pool};
+    use serde_json::json;
+
+    async fn setup() -> (PgPool, Uuid) {
+        let pool = test_pool().await;
+
+        let user_id = insert_user(&pool, "provider").await;
+
+        (pool, user_id)
+    }
+
+    fn event(n: u32) -> WsMessage {
+        WsMessage {
+            sender_id: Uuid::nil(),
+            event: "new_conversation_invitation".to_string(),
+            params: json!({ "n": n }),
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_the_newest_events_in_order_and_delivers_them_once() {
+        let (pool, user_id) = setup().await;
+
+        for n in 1..=4 {
+            PendingEventService::enqueue(&pool, user_id, &event(n), 3).await.unwrap();
+        }
+
+        let delivered = PendingEventService::take_undelivered(&pool, user_id).await.unwrap();
+        let numbers: Vec<_> = delivered.iter().map(|message| message.params["n"].clone()).collect();
+        assert_eq!(numbers, vec![json!(2), json!(3), json!(4)]);
+        assert!(PendingEventService::take_undelivered(&pool, user_id).await.unwrap().is_empty());
+
+        sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use crate::storage::ImageStorage;
+
+// Retries back off exponentially up to this cap
+const MAX_RETRY_DELAY_MINUTES: i64 = 60;
+const RETRY_BATCH_SIZE: i64 = 20;
+
+/// An image that couldn't be stored when it was uploaded.
+pub struct NewPendingUpload {
+    pub image_id: Uuid,
+    pub user_id: Uuid,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub image_type: String,
+    pub object_name: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum UploadStatus {
+    Pending { attempts: i32 },
+    Uploaded { image_url: String },
+}
+
+pub struct PendingUploadService;
+
+impl PendingUploadService {
+    pub async fn enqueue(pool: &PgPool, upload: NewPendingUpload) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "
+            INSERT INTO pending_uploads (id, user_id, filename, content_type, image_type, object_name, data)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ",
+            upload.image_id,
+            upload.user_id,
+            upload.filename,
+            upload.content_type,
+            upload.image_type,
+            upload.object_name,
+            upload.data
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Where one of the user's images is: still waiting for storage, or uploaded.
+    pub async fn status(pool: &PgPool, user_id: Uuid, image_id: Uuid) -> Result<Option<UploadStatus>, sqlx::Error> {
+        let image = sqlx::query!(
+            "SELECT image_url FROM images WHERE id = $1 AND user_id = $2",
+            image_id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?;
+        if let Some(image) = image {
+            return Ok(Some(UploadStatus::Uploaded { image_url: image.image_url }));
+        }
+
+        let pending = sqlx::query!(
+            "SELECT attempts FROM pending_uploads WHERE id = $1 AND user_id = $2",
+            image_id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(pending.map(|row| UploadStatus::Pending { attempts: row.attempts }))
+    }
+
+    /// Retries the uploads that are due at `now`. A successful upload is moved into
+    /// `images` under its original id; a failed one is rescheduled with backoff.
+    /// Returns the number of images uploaded.
+    pub async fn retry_due(pool: &PgPool, storage: &dyn ImageStorage, now: DateTime<Utc>) -> anyhow::Result<usize> {
+        let due = sqlx::query!(
+            "
+            SELECT id, user_id, filename, content_type, image_type, object_name, data, attempts
+            FROM pending_uploads
+            WHERE next_attempt_at <= $1
+            ORDER BY next_attempt_at
+            LIMIT $2
+            ",
+            now,
+            RETRY_BATCH_SIZE
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut uploaded = 0;
+        for upload in due {
+            let content_type = upload.content_type.clone().unwrap_or_else(|| "application/octet-stream".to_string());
+            let size_bytes = upload.data.len() as i64;
+            match storage.upload(&upload.object_name, &content_type, upload.data).await {
+                Ok(image_url) => {
+                    let mut tx = pool.begin().await?;
+                    sqlx::query!(
+                        "
+                        INSERT INTO images (id, user_id, filename, content_type, image_type, image_url, size_bytes)
+                        VALUES ($1, $2, $3, $4, $5, $6, $7)
+                        ",
+                        upload.id,
+                        upload.user_id,
+                        upload.filename,
+                        upload.content_type,
+                        upload.image_type,
+                        image_url,
+                        size_bytes
+                    )
+                    .execute(&mut *tx)
+                    .await?;
+                    sqlx::query!("DELETE FROM pending_uploads WHERE id = $1", upload.id)
+                        .execute(&mut *tx)
+                        .await?;
+                    tx.commit().await?;
+                    uploaded += 1;
+                },
+                Err(e) => {
+                    let attempts = upload.attempts + 1;
+                    let delay = Duration::minutes((1i64 << attempts.min(6)).min(MAX_RETRY_DELAY_MINUTES));
+                    logln!("Retry {} of pending upload {} failed: {}", attempts, upload.id, e);
+                    sqlx::query!(
+                        "
+                        UPDATE pending_uploads
+                        SET attempts = $1, last_error = $2, next_attempt_at = $3
+                        WHERE id = $4
+                        ",
+                        attempts,
+                        e.to_string(),
+                        now + delay,
+                        upload.id
+                    )
+                    .execute(pool)
+                    .await?;
+                }
+            }
+        }
+
+        Ok(uploaded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{insert_user, test_pool};
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // Stands in for GCS; fails until switched on
+    struct ToggleStorage {
+        available: AtomicBool,
+    }
+
+    #[async_trait]
+    impl ImageStorage for ToggleStorage {
+        async fn upload(&self, object_name: &str, _content_type: &str, _data: Vec<u8>) -> anyhow::Result<String> {
+            if !self.available.load(Ordering::SeqCst) {
+                anyhow::bail!("storage unavailable");
+            }
+            Ok(format!("https://storage.example.com/{}", object_name))
+        }
+
+        async fn delete(&self, _image_url: &str) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn download(&self, image_url: &str) -> anyhow::Result<Vec<u8>> {
+            anyhow::bail!("{} was never stored", image_url)
+        }
+    }
+
+    async fn setup() -> (PgPool, Uuid) {
+        let pool = test_pool().await;
+
+        let user_id = insert_user(&pool, "client").await;
+
+        (pool, user_id)
+    }
+
+    #[tokio::test]
+    async fn pending_upload_resolves_once_storage_recovers() {
+        let (pool, user_id) = setup().await;
+        let image_id = Uuid::new_v4();
+        let object_name = format!("pet/{}.png", Uuid::new_v4());
+        PendingUploadService::enqueue(&pool, NewPendingUpload {
+            image_id,
+            user_id,
+            filename: Some("millie.png".to_string()),
+            content_type: Some("image/png".to_string()),
+            image_type: "pet".to_string(),
+            object_name: object_name.clone(),
+            data: vec![1, 2, 3],
+        }).await.unwrap();
+
+        assert_eq!(
+            PendingUploadService::status(&pool, user_id, image_id).await.unwrap(),
+            Some(UploadStatus::Pending { attempts: 0 })
+        );
+
+        // Storage is still down: the upload stays pending and is pushed back
+        let storage = ToggleStorage { available: AtomicBool::new(false) };
+        let now = Utc::now();
+        PendingUploadService::retry_due(&pool, &storage, now).await.unwrap();
+        assert_eq!(
+            PendingUploadService::status(&pool, user_id, image_id).await.unwrap(),
+            Some(UploadStatus::Pending { attempts: 1 })
+        );
+
+        // Not retried again before the backoff elapses, then resolved to a URL
+        storage.available.store(true, Ordering::SeqCst);
+        PendingUploadService::retry_due(&pool, &storage, now).await.unwrap();
+        assert!(matches!(
+            PendingUploadService::status(&pool, user_id, image_id).await.unwrap(),
+            Some(UploadStatus::Pending { .. })
+        ));
+        PendingUploadService::retry_due(&pool, &storage, now + Duration::minutes(5)).await.unwrap();
+        assert_eq!(
+            PendingUploadService::status(&pool, user_id, image_id).await.unwrap(),
+            Some(UploadStatus::Uploaded { image_url: format!("https://storage.example.com/{}", object_name) })
+        );
+
+        sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+    }
+}
@@ -0,0 +1,267 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use chrono::{Duration, Utc};
+use std::fmt;
+use crate::models::{PetDocument, PetDocumentData, PetDocumentsQuery};
+use crate::services::pet_shares::{AccessLevel, PetShareService};
+
+const CATEGORIES: [&str; 5] = ["insurance", "adoption", "vaccination", "medical", "other"];
+const MAX_TITLE_LENGTH: usize = 200;
+const MAX_EXPIRING_WITHIN_DAYS: i32 = 3650;
+
+#[derive(Debug)]
+pub enum PetDocumentError {
+    NotFound(&'static str),
+    Forbidden,
+    Invalid(&'static str),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for PetDocumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PetDocumentError::NotFound(msg) | PetDocumentError::Invalid(msg) => write!(f, "{}", msg),
+            PetDocumentError::Forbidden => write!(f, "You don't have permission to change this pet's documents"),
+            PetDocumentError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for PetDocumentError {
+    fn from(e: sqlx::Error) -> Self {
+        PetDocumentError::Database(e)
+    }
+}
+
+pub struct PetDocumentService;
+
+impl PetDocumentService {
+    /// The user's access to a pet's documents: whatever they have to the pet
+    /// itself, or full access for providers on a conversation about it.
+    pub async fn get_access(pool: &PgPool, user_id: Uuid, pet_id: Uuid) -> Result<Option<AccessLevel>, sqlx::Error> {
+        if let Some(access) = PetShareService::get_pet_access(pool, user_id, pet_id).await? {
+            return Ok(Some(access));
+        }
+
+        let treating = sqlx::query!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM conversations c
+                WHERE c.pet = $1 AND ($2 = ANY(c.providers) OR EXISTS (
+                    SELECT 1 FROM organization_members om
+                    WHERE om.organization_id = c.organization_id AND om.user_id = $2
+                ))
+            ) AS "treating!"
+            "#,
+            pet_id,
+            user_id
+        )
+        .fetch_one(pool)
+        .await?
+        .treating;
+
+        Ok(treating.then_some(AccessLevel::ReadWrite))
+    }
+
+    /// Files one of the user's uploads under the pet.
+    pub async fn create(pool: &PgPool, user_id: Uuid, pet_id: Uuid, data: &PetDocumentData) -> Result<PetDocument, PetDocumentError> {
+        match Self::get_access(pool, user_id, pet_id).await? {
+            Some(AccessLevel::ReadWrite) => {},
+            Some(AccessLevel::Read) => return Err(PetDocumentError::Forbidden),
+            None => return Err(PetDocumentError::NotFound("Pet not found")),
+        }
+        if !CATEGORIES.contains(&data.category.as_str()) {
+            return Err(PetDocumentError::Invalid(
+                "category must be one of \"insurance\", \"adoption\", \"vaccination\", \"medical\" or \"other\"",
+            ));
+        }
+        let title = data.title.trim();
+        if title.is_empty() || title.chars().count() > MAX_TITLE_LENGTH {
+            return Err(PetDocumentError::Invalid("title must be between 1 and 200 characters"));
+        }
+
+        let document = sqlx::query_as!(
+            PetDocument,
+            r#"
+            WITH inserted AS (
+                INSERT INTO pet_documents (pet_id, image_id, uploaded_by, category, title, expires_at)
+                SELECT $1, i.id, $3, $4, $5, $6
+                FROM images i
+                WHERE i.id = $2 AND i.user_id = $3
+                RETURNING *
+            )
+            SELECT d.id AS "id!", d.pet_id AS "pet_id!", d.image_id AS "image_id!", i.image_url,
+                   d.uploaded_by AS "uploaded_by!", d.category AS "category!", d.title AS "title!",
+                   d.expires_at, d.created_at AS "created_at!"
+            FROM inserted d
+            JOIN images i ON i.id = d.image_id
+            "#,
+            pet_id,
+            data.image_id,
+            user_id,
+            data.category,
+            title,
+            data.expires_at
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        document.ok_or(PetDocumentError::NotFound("Upload not found"))
+    }
+
+    /// The pet's documents, newest first, or with `expiring_within_days` those
+    /// expiring between now and then, soonest first.
+    pub async fn list(pool: &PgPool, user_id: Uuid, pet_id: Uuid, query: &PetDocumentsQuery) -> Result<Vec<PetDocument>, PetDocumentError> {
+        if Self::get_access(pool, user_id, pet_id).await?.is_none() {
+            return Err(PetDocumentError::NotFound("Pet not found"));
+        }
+        let expiring_before = match query.expiring_within_days {
+            Some(days) if !(0..=MAX_EXPIRING_WITHIN_DAYS).contains(&days) => {
+                return Err(PetDocumentError::Invalid("expiring_within_days must be between 0 and 3650"));
+            },
+            Some(days) => Some(Utc::now() + Duration::days(days as i64)),
+            None => None,
+        };
+
+        Ok(sqlx::query_as!(
+            PetDocument,
+            r#"
+            SELECT d.id, d.pet_id, d.image_id, i.image_url, d.uploaded_by, d.category, d.title, d.expires_at, d.created_at
+            FROM pet_documents d
+            JOIN images i ON i.id = d.image_id
+            WHERE d.pet_id = $1
+              AND ($2::TEXT IS NULL OR d.category = $2)
+              AND ($3::TIMESTAMPTZ IS NULL OR d.expires_at BETWEEN CURRENT_TIMESTAMP AND $3)
+            ORDER BY CASE WHEN $3::TIMESTAMPTZ IS NULL THEN NULL ELSE d.expires_at END, d.created_at DESC
+            "#,
+            pet_id,
+            query.category,
+            expiring_before
+        )
+        .fetch_all(pool)
+        .await?)
+    }
+
+    /// Removes a document; its upload stays with the user who made it. Only
+    /// the pet's owner and whoever filed the document may remove it.
+    pub async fn delete(pool: &PgPool, user_id: Uuid, pet_id: Uuid, document_id: Uuid) -> Result<(), PetDocumentError> {
+        if Self::get_access(pool, user_id, pet_id).await?.is_none() {
+            return Err(PetDocumentError::NotFound("Pet not found"));
+        }
+        let document = sqlx::query!(
+            r#"
+            SELECT d.uploaded_by, p.user_id AS owner_id
+            FROM pet_documents d
+            JOIN pets p ON p.id = d.pet_id
+            WHERE d.id = $1 AND d.pet_id = $2
+            "#,
+            document_id,
+            pet_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or(PetDocumentError::NotFound("Document not found"))?;
+        if document.uploaded_by != user_id && document.owner_id != user_id {
+            return Err(PetDocumentError::Forbidden);
+        }
+
+        sqlx::query!("DELETE FROM pet_documents WHERE id = $1", document_id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{insert_pet, test_phone_number, test_pool};
+    use crate::services::conversations::ConversationService;
+
+    async fn insert_user(pool: &PgPool, scope: &str) -> Uuid {
+        let phone_number = test_phone_number();
+        sqlx::query!(
+            "INSERT INTO users (phone_number, public_key, scope) VALUES ($1, 'key', $2) RETURNING id",
+            phone_number,
+            scope
+        )
+        .fetch_one(pool).await.unwrap().id
+    }
+
+    async fn insert_upload(pool: &PgPool, user_id: Uuid) -> Uuid {
+        sqlx::query!(
+            "
+            INSERT INTO images (id, user_id, filename, content_type, image_type, image_url, size_bytes)
+            VALUES ($1, $2, 'policy.pdf', 'application/pdf', 'document', 'https://example.com/policy.pdf', 10)
+            RETURNING id
+            ",
+            Uuid::new_v4(),
+            user_id
+        )
+        .fetch_one(pool).await.unwrap().id
+    }
+
+    fn document(image_id: Uuid, category: &str, expires_in_days: Option<i64>) -> PetDocumentData {
+        PetDocumentData {
+            image_id,
+            category: category.to_string(),
+            title: format!("{} papers", category),
+            expires_at: expires_in_days.map(|days| Utc::now() + Duration::days(days)),
+        }
+    }
+
+    #[tokio::test]
+    async fn documents_follow_pet_access() {
+        let pool = test_pool().await;
+
+        let owner = insert_user(&pool, "client").await;
+        let provider = insert_user(&pool, "provider").await;
+        let stranger = insert_user(&pool, "provider").await;
+        let pet_id = insert_pet(&pool, owner).await;
+        ConversationService::create_conversation(&pool, vec![provider], None, owner, pet_id, false).await.unwrap();
+
+        // The owner files an expiring policy and an adoption record
+        let policy = PetDocumentService::create(&pool, owner, pet_id, &document(insert_upload(&pool, owner).await, "insurance", Some(20))).await.unwrap();
+        PetDocumentService::create(&pool, owner, pet_id, &document(insert_upload(&pool, owner).await, "adoption", None)).await.unwrap();
+        assert!(matches!(
+            PetDocumentService::create(&pool, owner, pet_id, &document(insert_upload(&pool, owner).await, "receipts", None)).await,
+            Err(PetDocumentError::Invalid(_))
+        ));
+
+        // Only the uploader's own files can be filed
+        let not_theirs = document(insert_upload(&pool, owner).await, "medical", None);
+        assert!(matches!(
+            PetDocumentService::create(&pool, provider, pet_id, &not_theirs).await,
+            Err(PetDocumentError::NotFound("Upload not found"))
+        ));
+
+        // The treating provider sees them; other providers don't see the pet at all
+        let all = PetDocumentService::list(&pool, provider, pet_id, &PetDocumentsQuery::default()).await.unwrap();
+        assert_eq!(all.len(), 2);
+        assert!(matches!(
+            PetDocumentService::list(&pool, stranger, pet_id, &PetDocumentsQuery::default()).await,
+            Err(PetDocumentError::NotFound(_))
+        ));
+
+        let expiring = |days| PetDocumentsQuery { category: None, expiring_within_days: Some(days) };
+        let soon = PetDocumentService::list(&pool, owner, pet_id, &expiring(30)).await.unwrap();
+        assert_eq!(soon.iter().map(|d| d.id).collect::<Vec<_>>(), vec![policy.id]);
+        assert!(PetDocumentService::list(&pool, owner, pet_id, &expiring(7)).await.unwrap().is_empty());
+        let adoption = PetDocumentsQuery { category: Some("adoption".to_string()), expiring_within_days: None };
+        assert_eq!(PetDocumentService::list(&pool, owner, pet_id, &adoption).await.unwrap().len(), 1);
+
+        // The provider can't remove the owner's papers, but the owner can
+        assert!(matches!(
+            PetDocumentService::delete(&pool, provider, pet_id, policy.id).await,
+            Err(PetDocumentError::Forbidden)
+        ));
+        PetDocumentService::delete(&pool, owner, pet_id, policy.id).await.unwrap();
+        assert!(matches!(
+            PetDocumentService::delete(&pool, owner, pet_id, policy.id).await,
+            Err(PetDocumentError::NotFound("Document not found"))
+        ));
+
+        sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &[owner, provider, stranger])
+            .execute(&pool).await.unwrap();
+    }
+}
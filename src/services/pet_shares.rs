@@ -0,0 +1,270 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use std::fmt;
+use crate::models::PetShare;
+
+#[derive(Debug)]
+pub enum PetShareError {
+    NotFound(&'static str),
+    Invalid(&'static str),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for PetShareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PetShareError::NotFound(msg) | PetShareError::Invalid(msg) => write!(f, "{}", msg),
+            PetShareError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for PetShareError {
+    fn from(e: sqlx::Error) -> Self {
+        PetShareError::Database(e)
+    }
+}
+
+/// What a user may do with a pet and the conversations about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLevel {
+    Read,
+    ReadWrite,
+}
+
+impl AccessLevel {
+    pub fn from_permissions(permissions: &str) -> Option<AccessLevel> {
+        match permissions {
+            "read" => Some(AccessLevel::Read),
+            "read_write" => Some(AccessLevel::ReadWrite),
+            _ => None,
+        }
+    }
+}
+
+pub struct PetShareService;
+
+impl PetShareService {
+    /// The owner shares a pet with the account registered to `phone_number`. Inviting
+    /// someone who already has a share updates its permissions.
+    pub async fn invite(
+        pool: &PgPool,
+        owner_id: Uuid,
+        pet_id: Uuid,
+        phone_number: &str,
+        permissions: &str,
+    ) -> Result<PetShare, PetShareError> {
+        if AccessLevel::from_permissions(permissions).is_none() {
+            return Err(PetShareError::Invalid("Permissions must be 'read' or 'read_write'"));
+        }
+
+        let owns_pet = sqlx::query!(
+            "SELECT id FROM pets WHERE id = $1 AND user_id = $2",
+            pet_id,
+            owner_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .is_some();
+        if !owns_pet {
+            return Err(PetShareError::NotFound("Pet not found or does not belong to you"));
+        }
+
+        let invitee_id = sqlx::query!("SELECT id FROM users WHERE phone_number = $1", phone_number)
+            .fetch_optional(pool)
+            .await?
+            .ok_or(PetShareError::NotFound("No user is registered with that phone number"))?
+            .id;
+        if invitee_id == owner_id {
+            return Err(PetShareError::Invalid("You can't share a pet with yourself"));
+        }
+
+        let share = sqlx::query_as!(
+            PetShare,
+            "
+            INSERT INTO pet_shares (pet_id, owner_id, shared_with_user_id, permissions)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (pet_id, shared_with_user_id) DO UPDATE
+            SET permissions = EXCLUDED.permissions
+            RETURNING id, pet_id, owner_id, shared_with_user_id, permissions, status, created_at, accepted_at
+            ",
+            pet_id,
+            owner_id,
+            invitee_id,
+            permissions
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(share)
+    }
+
+    pub async fn accept(pool: &PgPool, user_id: Uuid, share_id: Uuid) -> Result<PetShare, PetShareError> {
+        sqlx::query_as!(
+            PetShare,
+            "
+            UPDATE pet_shares
+            SET status = 'accepted', accepted_at = COALESCE(accepted_at, CURRENT_TIMESTAMP)
+            WHERE id = $1 AND shared_with_user_id = $2
+            RETURNING id, pet_id, owner_id, shared_with_user_id, permissions, status, created_at, accepted_at
+            ",
+            share_id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or(PetShareError::NotFound("Share not found"))
+    }
+
+    /// Either the owner or the invitee can revoke a share, pending or accepted.
+    pub async fn revoke(pool: &PgPool, user_id: Uuid, share_id: Uuid) -> Result<PetShare, PetShareError> {
+        sqlx::query_as!(
+            PetShare,
+            "
+            DELETE FROM pet_shares
+            WHERE id = $1 AND (owner_id = $2 OR shared_with_user_id = $2)
+            RETURNING id, pet_id, owner_id, shared_with_user_id, permissions, status, created_at, accepted_at
+            ",
+            share_id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or(PetShareError::NotFound("Share not found"))
+    }
+
+    /// Shares the user has made or been offered, newest first.
+    pub async fn get_shares_for_user(pool: &PgPool, user_id: Uuid) -> Result<Vec<PetShare>, sqlx::Error> {
+        sqlx::query_as!(
+            PetShare,
+            "
+            SELECT id, pet_id, owner_id, shared_with_user_id, permissions, status, created_at, accepted_at
+            FROM pet_shares
+            WHERE owner_id = $1 OR shared_with_user_id = $1
+            ORDER BY created_at DESC
+            ",
+            user_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// The user's access to a pet: full access for the owner, otherwise whatever an
+    /// accepted share grants.
+    pub async fn get_pet_access(pool: &PgPool, user_id: Uuid, pet_id: Uuid) -> Result<Option<AccessLevel>, sqlx::Error> {
+        let record = sqlx::query!(
+            r#"
+            SELECT p.user_id = $2 AS "is_owner!", s.permissions AS "permissions?"
+            FROM pets p
+            LEFT JOIN pet_shares s
+              ON s.pet_id = p.id AND s.shared_with_user_id = $2 AND s.status = 'accepted'
+            WHERE p.id = $1
+            "#,
+            pet_id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(match record {
+            Some(record) if record.is_owner => Some(AccessLevel::ReadWrite),
+            Some(record) => record.permissions.as_deref().and_then(AccessLevel::from_permissions),
+            None => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{cleanup_users, test_phone_number, test_pool};
+    use crate::services::conversations::ConversationService;
+
+    // Returns (pool, owner, co-owner, provider, pet, conversation, co-owner's phone number)
+    async fn setup() -> (PgPool, Uuid, Uuid, Uuid, Uuid, Uuid, String) {
+        let pool = test_pool().await;
+
+        let mut users = Vec::new();
+        for scope in ["client", "client", "provider"] {
+            let phone_number = test_phone_number();
+            let id = sqlx::query!(
+                "INSERT INTO users (phone_number, public_key, scope) VALUES ($1, 'key', $2) RETURNING id",
+                phone_number,
+                scope
+            )
+            .fetch_one(&pool).await.unwrap().id;
+            users.push((id, phone_number));
+        }
+        let pet_id = sqlx::query!(
+            "INSERT INTO pets (user_id, name, breed, sex, birthday) VALUES ($1, 'Millie', 'Mutt', 'F', NOW()) RETURNING id",
+            users[0].0
+        )
+        .fetch_one(&pool).await.unwrap().id;
+        let (conversation, _) = ConversationService::create_conversation(&pool, vec![users[2].0], None, users[0].0, pet_id, false)
+            .await
+            .unwrap();
+
+        (pool, users[0].0, users[1].0, users[2].0, pet_id, conversation.id, users[1].1.clone())
+    }
+
+
+    #[tokio::test]
+    async fn permission_levels_apply_once_accepted() {
+        for (permissions, expected) in [("read", AccessLevel::Read), ("read_write", AccessLevel::ReadWrite)] {
+            let (pool, owner, co_owner, provider, pet, conversation, phone_number) = setup().await;
+
+            let share = PetShareService::invite(&pool, owner, pet, &phone_number, permissions).await.unwrap();
+
+            // A pending invitation grants nothing
+            assert_eq!(PetShareService::get_pet_access(&pool, co_owner, pet).await.unwrap(), None);
+            assert_eq!(ConversationService::get_access(&pool, conversation, co_owner).await.unwrap(), None);
+
+            PetShareService::accept(&pool, co_owner, share.id).await.unwrap();
+            assert_eq!(PetShareService::get_pet_access(&pool, co_owner, pet).await.unwrap(), Some(expected));
+            assert_eq!(ConversationService::get_access(&pool, conversation, co_owner).await.unwrap(), Some(expected));
+
+            // Participants keep full access regardless of shares
+            assert_eq!(ConversationService::get_access(&pool, conversation, owner).await.unwrap(), Some(AccessLevel::ReadWrite));
+            assert_eq!(ConversationService::get_access(&pool, conversation, provider).await.unwrap(), Some(AccessLevel::ReadWrite));
+
+            cleanup_users(&pool, &[owner, co_owner, provider]).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn revocation_takes_effect_immediately() {
+        let (pool, owner, co_owner, provider, pet, conversation, phone_number) = setup().await;
+        let share = PetShareService::invite(&pool, owner, pet, &phone_number, "read_write").await.unwrap();
+        PetShareService::accept(&pool, co_owner, share.id).await.unwrap();
+        assert!(PetShareService::get_pet_access(&pool, co_owner, pet).await.unwrap().is_some());
+
+        // Someone outside the share can't revoke it
+        assert!(matches!(
+            PetShareService::revoke(&pool, provider, share.id).await,
+            Err(PetShareError::NotFound(_))
+        ));
+
+        PetShareService::revoke(&pool, owner, share.id).await.unwrap();
+        assert_eq!(PetShareService::get_pet_access(&pool, co_owner, pet).await.unwrap(), None);
+        assert_eq!(ConversationService::get_access(&pool, conversation, co_owner).await.unwrap(), None);
+        assert!(ConversationService::get_conversations_shared_with(&pool, co_owner).await.unwrap().is_empty());
+
+        cleanup_users(&pool, &[owner, co_owner, provider]).await;
+    }
+
+    #[tokio::test]
+    async fn only_the_owner_can_share() {
+        let (pool, owner, co_owner, provider, pet, _, phone_number) = setup().await;
+
+        assert!(matches!(
+            PetShareService::invite(&pool, provider, pet, &phone_number, "read").await,
+            Err(PetShareError::NotFound(_))
+        ));
+        assert!(matches!(
+            PetShareService::invite(&pool, owner, pet, &phone_number, "admin").await,
+            Err(PetShareError::Invalid(_))
+        ));
+
+        cleanup_users(&pool, &[owner, co_owner, provider]).await;
+    }
+}
@@ -0,0 +1,165 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use std::fmt;
+use crate::models::{Pet, ProviderPetsQuery};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug)]
+pub enum ProviderPetError {
+    Invalid(&'static str),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for ProviderPetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProviderPetError::Invalid(msg) => write!(f, "{}", msg),
+            ProviderPetError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for ProviderPetError {
+    fn from(e: sqlx::Error) -> Self {
+        ProviderPetError::Database(e)
+    }
+}
+
+pub struct ProviderPetService;
+
+impl ProviderPetService {
+    /// One page of the pets of every client the provider has a conversation
+    /// with, directly or through their clinic, by name, with the total number
+    /// of matches and whether there are more pages. `search` matches anywhere
+    /// in the name; both filters ignore case.
+    pub async fn list(
+        pool: &PgPool,
+        provider_id: Uuid,
+        query: &ProviderPetsQuery,
+    ) -> Result<(Vec<Pet>, i64, bool), ProviderPetError> {
+        let page = query.page.unwrap_or(1);
+        if page < 1 {
+            return Err(ProviderPetError::Invalid("Invalid page number: must be >= 1"));
+        }
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+            return Err(ProviderPetError::Invalid("Invalid limit: must be between 1 and 100"));
+        }
+        let species = query.species.as_deref().map(str::trim).filter(|species| !species.is_empty());
+        // The search is literal text, not a LIKE pattern
+        let search = query.search.as_deref().map(str::trim).filter(|search| !search.is_empty()).map(|search| {
+            search.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+        });
+
+        let total_count = sqlx::query!(
+            r#"
+            SELECT COUNT(*) AS "count!"
+            FROM pets p
+            WHERE p.user_id IN (
+                SELECT c.client FROM conversations c
+                WHERE $1 = ANY(c.providers) OR EXISTS (
+                    SELECT 1 FROM organization_members om
+                    WHERE om.organization_id = c.organization_id AND om.user_id = $1
+                )
+            )
+              AND ($2::TEXT IS NULL OR LOWER(p.species) = LOWER($2))
+              AND ($3::TEXT IS NULL OR p.name ILIKE '%' || $3 || '%')
+            "#,
+            provider_id,
+            species,
+            search
+        )
+        .fetch_one(pool)
+        .await?
+        .count;
+
+        let offset = (page - 1) * limit;
+        let pets = sqlx::query_as!(
+            Pet,
+            r#"
+            SELECT p.id, p.user_id, p.name, p.breed, p.sex, p.birthday AS "birthday?", p.pet_image_url,
+                   p.color, p.species, p.spayed_neutered, p.weight
+            FROM pets p
+            WHERE p.user_id IN (
+                SELECT c.client FROM conversations c
+                WHERE $1 = ANY(c.providers) OR EXISTS (
+                    SELECT 1 FROM organization_members om
+                    WHERE om.organization_id = c.organization_id AND om.user_id = $1
+                )
+            )
+              AND ($2::TEXT IS NULL OR LOWER(p.species) = LOWER($2))
+              AND ($3::TEXT IS NULL OR p.name ILIKE '%' || $3 || '%')
+            ORDER BY p.name, p.id
+            LIMIT $4 OFFSET $5
+            "#,
+            provider_id,
+            species,
+            search,
+            limit,
+            offset
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let has_more = offset + (pets.len() as i64) < total_count;
+        Ok((pets, total_count, has_more))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{insert_user, test_pool};
+    use crate::services::conversations::ConversationService;
+
+    async fn insert_pet(pool: &PgPool, owner: Uuid, name: &str, species: &str) -> Uuid {
+        sqlx::query!(
+            "INSERT INTO pets (user_id, name, breed, sex, birthday, species) VALUES ($1, $2, 'Mutt', 'F', NOW(), $3) RETURNING id",
+            owner,
+            name,
+            species
+        )
+        .fetch_one(pool).await.unwrap().id
+    }
+
+    fn query(species: Option<&str>, search: Option<&str>) -> ProviderPetsQuery {
+        ProviderPetsQuery { species: species.map(str::to_string), search: search.map(str::to_string), page: None, limit: None }
+    }
+
+    #[tokio::test]
+    async fn lists_the_pets_of_the_providers_clients() {
+        let pool = test_pool().await;
+
+        let vet = insert_user(&pool, "provider").await;
+        let client = insert_user(&pool, "client").await;
+        let stranger = insert_user(&pool, "client").await;
+        let millie = insert_pet(&pool, client, "Millie", "dog").await;
+        // The client's other pet has no conversation of its own but is still theirs
+        let biscuit = insert_pet(&pool, client, "Biscuit_2", "cat").await;
+        insert_pet(&pool, stranger, "Rex", "dog").await;
+        // Two conversations with the same client don't list their pets twice
+        ConversationService::create_conversation(&pool, vec![vet], None, client, millie, false).await.unwrap();
+        ConversationService::create_conversation(&pool, vec![vet], None, client, millie, false).await.unwrap();
+
+        let (pets, total_count, has_more) = ProviderPetService::list(&pool, vet, &query(None, None)).await.unwrap();
+        assert_eq!(pets.iter().map(|p| p.id).collect::<Vec<_>>(), vec![biscuit, millie]);
+        assert_eq!((total_count, has_more), (2, false));
+
+        let (pets, _, _) = ProviderPetService::list(&pool, vet, &query(Some("Dog"), None)).await.unwrap();
+        assert_eq!(pets.iter().map(|p| p.id).collect::<Vec<_>>(), vec![millie]);
+        let (pets, _, _) = ProviderPetService::list(&pool, vet, &query(None, Some("mil"))).await.unwrap();
+        assert_eq!(pets.iter().map(|p| p.id).collect::<Vec<_>>(), vec![millie]);
+        let (pets, _, _) = ProviderPetService::list(&pool, vet, &query(None, Some("t_"))).await.unwrap();
+        assert_eq!(pets.iter().map(|p| p.id).collect::<Vec<_>>(), vec![biscuit]);
+
+        let first_page = ProviderPetsQuery { page: Some(1), limit: Some(1), ..query(None, None) };
+        let (pets, total_count, has_more) = ProviderPetService::list(&pool, vet, &first_page).await.unwrap();
+        assert_eq!((pets.len(), total_count, has_more), (1, 2, true));
+        let too_many = ProviderPetsQuery { limit: Some(101), ..query(None, None) };
+        assert!(matches!(ProviderPetService::list(&pool, vet, &too_many).await, Err(ProviderPetError::Invalid(_))));
+
+        sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &[vet, client, stranger]).execute(&pool).await.unwrap();
+    }
+}
@@ -0,0 +1,210 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use chrono::{DateTime, Duration, Utc};
+use crate::notifications::{NotificationService, Notifier};
+
+pub struct ReminderService;
+
+impl ReminderService {
+    /// Sends reminders for confirmed appointments that start within one of the
+    /// configured lead times of `now`. Each (appointment, lead time) pair is claimed
+    /// in `appointment_reminders` before sending so it fires exactly once, even if
+    /// several scans overlap or the worker restarts. Returns the number of
+    /// reminders sent.
+    pub async fn send_due_reminders(
+        pool: &PgPool,
+        notifier: &dyn Notifier,
+        now: DateTime<Utc>,
+        lead_times: &[Duration],
+    ) -> anyhow::Result<usize> {
+        // Shortest lead time first: an appointment booked at short notice only
+        // gets the most urgent reminder, and the longer ones are marked as covered.
+        let mut lead_minutes: Vec<i32> = lead_times.iter().map(|d| d.num_minutes() as i32).collect();
+        lead_minutes.sort_unstable();
+        lead_minutes.dedup();
+
+        let mut sent = 0;
+        for (index, &lead) in lead_minutes.iter().enumerate() {
+            let due = sqlx::query!(
+                r#"
+                SELECT a.id, a.client_id, a.conversation_id, a.starts_at
+                FROM appointments a
+                WHERE a.status = 'confirmed'
+                  AND a.starts_at > $1
+                  AND a.starts_at <= $1 + make_interval(mins => $2)
+                  AND NOT EXISTS (
+                      SELECT 1 FROM appointment_reminders r
+                      WHERE r.appointment_id = a.id AND r.lead_time_minutes = $2
+                  )
+                "#,
+                now,
+                lead
+            )
+            .fetch_all(pool)
+            .await?;
+
+            for appointment in due {
+                if !Self::claim(pool, appointment.id, lead).await? {
+                    continue;
+                }
+
+                let body = format!(
+                    "Reminder: your VetText appointment starts at {}",
+                    appointment.starts_at.format("%Y-%m-%d %H:%M UTC")
+                );
+                let notified = NotificationService::notify_user(
+                    pool, notifier, appointment.client_id, Some(appointment.conversation_id), "Appointment reminder", &body
+                ).await;
+                match notified {
+                    Ok(channel) => {
+                        // No channel when the client's preferences suppressed it
+                        sqlx::query!(
+                            "UPDATE appointment_reminders SET channel = $1 WHERE appointment_id = $2 AND lead_time_minutes = $3",
+                            channel.map(|channel| channel.as_str()),
+                            appointment.id,
+                            lead
+                        )
+                        .execute(pool)
+                        .await?;
+
+                        for &longer in &lead_minutes[index + 1..] {
+                            Self::claim(pool, appointment.id, longer).await?;
+                        }
+                        if channel.is_some() {
+                            sent += 1;
+                        }
+                    },
+                    Err(e) => {
+                        // Release the claim so the next scan retries
+                        logln!("Failed to send reminder for appointment {}: {}", appointment.id, e);
+                        sqlx::query!(
+                            "DELETE FROM appointment_reminders WHERE appointment_id = $1 AND lead_time_minutes = $2",
+                            appointment.id,
+                            lead
+                        )
+                        .execute(pool)
+                        .await?;
+                    }
+                }
+            }
+        }
+
+        Ok(sent)
+    }
+
+    // Returns true if this call recorded the reminder, false if it already existed
+    async fn claim(pool: &PgPool, appointment_id: Uuid, lead_time_minutes: i32) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "
+            INSERT INTO appointment_reminders (appointment_id, lead_time_minutes)
+            VALUES ($1, $2)
+            ON CONFLICT (appointment_id, lead_time_minutes) DO NOTHING
+            ",
+            appointment_id,
+            lead_time_minutes
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{cleanup_users, insert_conversation, insert_pet, insert_user, test_phone_number, test_pool};
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingNotifier {
+        sms: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Notifier for RecordingNotifier {
+        async fn send_push(&self, _user_id: Uuid, _title: &str, _body: &str) -> anyhow::Result<bool> {
+            Ok(false)
+        }
+
+        async fn send_sms(&self, phone_number: &str, _body: &str) -> anyhow::Result<()> {
+            self.sms.lock().unwrap().push(phone_number.to_string());
+            Ok(())
+        }
+    }
+
+    async fn setup() -> (PgPool, Uuid, Uuid, Uuid, String) {
+        let pool = test_pool().await;
+
+        let phone_number = test_phone_number();
+        let client_id = sqlx::query!(
+            "INSERT INTO users (phone_number, public_key, scope) VALUES ($1, 'key', 'client') RETURNING id",
+            phone_number
+        )
+        .fetch_one(&pool).await.unwrap().id;
+        let provider_id = insert_user(&pool, "provider").await;
+        let pet_id = insert_pet(&pool, client_id).await;
+        let conversation_id = insert_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+        (pool, client_id, provider_id, conversation_id, phone_number)
+    }
+
+    async fn insert_appointment(pool: &PgPool, conversation_id: Uuid, starts_at: DateTime<Utc>, status: &str) -> Uuid {
+        sqlx::query!(
+            "
+            INSERT INTO appointments (conversation_id, pet_id, provider_id, client_id, starts_at, duration_minutes, status)
+            SELECT id, pet, providers[1], client, $2, 30, $3 FROM conversations WHERE id = $1
+            RETURNING id
+            ",
+            conversation_id,
+            starts_at,
+            status
+        )
+        .fetch_one(pool).await.unwrap().id
+    }
+
+
+    #[tokio::test]
+    async fn reminders_fire_once_per_lead_time() {
+        let (pool, client_id, provider_id, conversation_id, phone_number) = setup().await;
+        let lead_times = [Duration::hours(24), Duration::hours(1)];
+        // Far enough in the future that other appointments in the database don't interfere
+        let starts_at = Utc::now() + Duration::days(365 * 50);
+        insert_appointment(&pool, conversation_id, starts_at, "confirmed").await;
+
+        let notifier = RecordingNotifier::default();
+        let sent_to_client = |n: &RecordingNotifier| n.sms.lock().unwrap().iter().filter(|p| **p == phone_number).count();
+
+        // Two days out: nothing is due yet
+        ReminderService::send_due_reminders(&pool, &notifier, starts_at - Duration::hours(48), &lead_times).await.unwrap();
+        assert_eq!(sent_to_client(&notifier), 0);
+
+        // Crossing the 24h boundary sends the first reminder, exactly once
+        ReminderService::send_due_reminders(&pool, &notifier, starts_at - Duration::hours(23), &lead_times).await.unwrap();
+        ReminderService::send_due_reminders(&pool, &notifier, starts_at - Duration::hours(22), &lead_times).await.unwrap();
+        assert_eq!(sent_to_client(&notifier), 1);
+
+        // Crossing the 1h boundary sends the second reminder, exactly once
+        ReminderService::send_due_reminders(&pool, &notifier, starts_at - Duration::minutes(59), &lead_times).await.unwrap();
+        ReminderService::send_due_reminders(&pool, &notifier, starts_at - Duration::minutes(30), &lead_times).await.unwrap();
+        assert_eq!(sent_to_client(&notifier), 2);
+
+        cleanup_users(&pool, &[client_id, provider_id]).await;
+    }
+
+    #[tokio::test]
+    async fn cancelled_appointments_do_not_trigger_reminders() {
+        let (pool, client_id, provider_id, conversation_id, phone_number) = setup().await;
+        let lead_times = [Duration::hours(24), Duration::hours(1)];
+        let starts_at = Utc::now() + Duration::days(365 * 51);
+        insert_appointment(&pool, conversation_id, starts_at, "cancelled").await;
+
+        let notifier = RecordingNotifier::default();
+        ReminderService::send_due_reminders(&pool, &notifier, starts_at - Duration::hours(23), &lead_times).await.unwrap();
+        ReminderService::send_due_reminders(&pool, &notifier, starts_at - Duration::minutes(59), &lead_times).await.unwrap();
+        assert!(!notifier.sms.lock().unwrap().contains(&phone_number));
+
+        cleanup_users(&pool, &[client_id, provider_id]).await;
+    }
+}
@@ -0,0 +1,75 @@
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use sqlx::PgPool;
+use sha2::{Digest, Sha256};
+
+/// How long a signed request is remembered. Outlasts the window in which
+/// `is_timestamp_valid` accepts its timestamp, after which replays fail anyway.
+const REPLAY_WINDOW: Duration = Duration::minutes(2);
+
+pub struct RequestNonceService;
+
+impl RequestNonceService {
+    /// Records a signed request of the user's by its signature. Returns false if
+    /// it has already been seen, i.e. this is a replay.
+    pub async fn claim(pool: &PgPool, user_id: Uuid, signature: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "
+            INSERT INTO signed_request_nonces (user_id, nonce, expires_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, nonce) DO NOTHING
+            ",
+            user_id,
+            hex::encode(Sha256::digest(signature.as_bytes())),
+            Utc::now() + REPLAY_WINDOW
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Forgets requests old enough that their timestamps are refused anyway.
+    pub async fn delete_expired(pool: &PgPool) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!("DELETE FROM signed_request_nonces WHERE expires_at < CURRENT_TIMESTAMP")
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{insert_user, test_pool};
+
+    #[tokio::test]
+    async fn each_signature_is_accepted_once_per_user() {
+        let pool = test_pool().await;
+
+        let mut user_ids = Vec::new();
+        for _ in 0..2 {
+            user_ids.push(insert_user(&pool, "client").await);
+        }
+        let (user_id, other_id) = (user_ids[0], user_ids[1]);
+
+        assert!(RequestNonceService::claim(&pool, user_id, "c2lnbmF0dXJl").await.unwrap());
+        assert!(!RequestNonceService::claim(&pool, user_id, "c2lnbmF0dXJl").await.unwrap());
+        assert!(RequestNonceService::claim(&pool, user_id, "b3RoZXI=").await.unwrap());
+        assert!(RequestNonceService::claim(&pool, other_id, "c2lnbmF0dXJl").await.unwrap());
+
+        // Once the window has passed the row can go
+        sqlx::query!(
+            "UPDATE signed_request_nonces SET expires_at = CURRENT_TIMESTAMP - INTERVAL '1 minute' WHERE user_id = $1",
+            user_id
+        )
+        .execute(&pool).await.unwrap();
+        assert!(RequestNonceService::delete_expired(&pool).await.unwrap() >= 2);
+        let left = sqlx::query!("SELECT user_id FROM signed_request_nonces WHERE user_id = ANY($1)", &user_ids)
+            .fetch_all(&pool).await.unwrap();
+        assert_eq!(left.iter().map(|row| row.user_id).collect::<Vec<_>>(), vec![other_id]);
+
+        sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &user_ids).execute(&pool).await.unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use sqlx::PgPool;
+
+pub struct RevokedTokenService;
+
+impl RevokedTokenService {
+    /// Blocks the token with this `jti` until it expires. Revoking it again is a no-op.
+    pub async fn revoke(pool: &PgPool, jti: Uuid, user_id: Uuid, expires_at: DateTime<Utc>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "
+            INSERT INTO revoked_tokens (jti, user_id, expires_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (jti) DO NOTHING
+            ",
+            jti,
+            user_id,
+            expires_at
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn is_token_revoked(pool: &PgPool, jti: Uuid) -> Result<bool, sqlx::Error> {
+        let revoked = sqlx::query!("SELECT 1 AS one FROM revoked_tokens WHERE jti = $1", jti)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(revoked.is_some())
+    }
+
+    /// Forgets revocations of tokens that have expired since, which are refused anyway.
+    pub async fn delete_expired(pool: &PgPool) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!("DELETE FROM revoked_tokens WHERE expires_at < CURRENT_TIMESTAMP")
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{insert_user, test_pool};
+    use chrono::Duration;
+
+    #[tokio::test]
+    async fn revoked_tokens_are_blocked_until_they_expire() {
+        let pool = test_pool().await;
+
+        let user_id = insert_user(&pool, "client").await;
+        let (live, expired) = (Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(!RevokedTokenService::is_token_revoked(&pool, live).await.unwrap());
+        RevokedTokenService::revoke(&pool, live, user_id, Utc::now() + Duration::hours(1)).await.unwrap();
+        RevokedTokenService::revoke(&pool, live, user_id, Utc::now() + Duration::hours(1)).await.unwrap();
+        RevokedTokenService::revoke(&pool, expired, user_id, Utc::now() - Duration::hours(1)).await.unwrap();
+        assert!(RevokedTokenService::is_token_revoked(&pool, live).await.unwrap());
+
+        assert!(RevokedTokenService::delete_expired(&pool).await.unwrap() >= 1);
+        assert!(RevokedTokenService::is_token_revoked(&pool, live).await.unwrap());
+        assert!(!RevokedTokenService::is_token_revoked(&pool, expired).await.unwrap());
+
+        sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+    }
+}
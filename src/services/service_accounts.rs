@@ -0,0 +1,205 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use crate::utils::generate_refresh_token;
+
+// Lets keys be recognised in logs and secret scanners
+const API_KEY_PREFIX: &str = "vtsk_";
+
+#[derive(Debug)]
+pub enum ServiceAccountError {
+    NotFound(&'static str),
+    Invalid(&'static str),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for ServiceAccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceAccountError::NotFound(msg) | ServiceAccountError::Invalid(msg) => write!(f, "{}", msg),
+            ServiceAccountError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for ServiceAccountError {
+    fn from(e: sqlx::Error) -> Self {
+        ServiceAccountError::Database(e)
+    }
+}
+
+/// An integration acting for a provider with read-only access.
+#[derive(Serialize, Debug, Clone)]
+pub struct ServiceAccount {
+    pub id: Uuid,
+    pub provider_id: Uuid,
+    pub name: String,
+    pub rate_limit_per_minute: i32,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_milliseconds_option")]
+    pub rotated_at: Option<DateTime<Utc>>,
+    #[serde(with = "chrono::serde::ts_milliseconds_option")]
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+pub struct ServiceAccountService;
+
+impl ServiceAccountService {
+    /// Creates an account for a provider. Returns it with its API key, which is
+    /// only ever available here and from `rotate`.
+    pub async fn create(
+        pool: &PgPool,
+        provider_id: Uuid,
+        name: &str,
+        rate_limit_per_minute: i32,
+    ) -> Result<(ServiceAccount, String), ServiceAccountError> {
+        let name = name.trim();
+        if name.is_empty() || name.len() > 100 {
+            return Err(ServiceAccountError::Invalid("Name must be between 1 and 100 characters"));
+        }
+        if rate_limit_per_minute < 1 {
+            return Err(ServiceAccountError::Invalid("Rate limit must be at least 1 request per minute"));
+        }
+        let is_provider = sqlx::query!("SELECT scope FROM users WHERE id = $1", provider_id)
+            .fetch_optional(pool)
+            .await?
+            .is_some_and(|user| user.scope == "provider");
+        if !is_provider {
+            return Err(ServiceAccountError::NotFound("Provider not found"));
+        }
+
+        let api_key = generate_api_key();
+        let account = sqlx::query_as!(
+            ServiceAccount,
+            "
+            INSERT INTO service_accounts (provider_id, name, key_hash, rate_limit_per_minute)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, provider_id, name, rate_limit_per_minute, created_at, rotated_at, revoked_at
+            ",
+            provider_id,
+            name,
+            hash_api_key(&api_key),
+            rate_limit_per_minute
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok((account, api_key))
+    }
+
+    /// Replaces an active account's key. The old key stops working immediately.
+    pub async fn rotate(pool: &PgPool, id: Uuid) -> Result<(ServiceAccount, String), ServiceAccountError> {
+        let api_key = generate_api_key();
+        let account = sqlx::query_as!(
+            ServiceAccount,
+            "
+            UPDATE service_accounts
+            SET key_hash = $1, rotated_at = CURRENT_TIMESTAMP
+            WHERE id = $2 AND revoked_at IS NULL
+            RETURNING id, provider_id, name, rate_limit_per_minute, created_at, rotated_at, revoked_at
+            ",
+            hash_api_key(&api_key),
+            id
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or(ServiceAccountError::NotFound("Service account not found"))?;
+
+        Ok((account, api_key))
+    }
+
+    pub async fn revoke(pool: &PgPool, id: Uuid) -> Result<ServiceAccount, ServiceAccountError> {
+        sqlx::query_as!(
+            ServiceAccount,
+            "
+            UPDATE service_accounts
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND revoked_at IS NULL
+            RETURNING id, provider_id, name, rate_limit_per_minute, created_at, rotated_at, revoked_at
+            ",
+            id
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or(ServiceAccountError::NotFound("Service account not found"))
+    }
+
+    /// The active account an API key belongs to, if any.
+    pub async fn authenticate(pool: &PgPool, api_key: &str) -> Result<Option<ServiceAccount>, sqlx::Error> {
+        sqlx::query_as!(
+            ServiceAccount,
+            "
+            UPDATE service_accounts
+            SET last_used_at = CURRENT_TIMESTAMP
+            WHERE key_hash = $1 AND revoked_at IS NULL
+            RETURNING id, provider_id, name, rate_limit_per_minute, created_at, rotated_at, revoked_at
+            ",
+            hash_api_key(api_key)
+        )
+        .fetch_optional(pool)
+        .await
+    }
+}
+
+fn generate_api_key() -> String {
+    format!("{}{}", API_KEY_PREFIX, generate_refresh_token())
+}
+
+fn hash_api_key(api_key: &str) -> String {
+    hex::encode(Sha256::digest(api_key.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{insert_user, test_pool};
+
+    async fn setup() -> (PgPool, Uuid, Uuid) {
+        let pool = test_pool().await;
+
+        let mut ids = Vec::new();
+        for scope in ["provider", "client"] {
+            ids.push(insert_user(&pool, scope).await);
+        }
+
+        (pool, ids[0], ids[1])
+    }
+
+    #[tokio::test]
+    async fn keys_authenticate_until_rotated_or_revoked() {
+        let (pool, provider_id, client_id) = setup().await;
+
+        assert!(matches!(
+            ServiceAccountService::create(&pool, client_id, "PMS sync", 60).await,
+            Err(ServiceAccountError::NotFound(_))
+        ));
+
+        let (account, key) = ServiceAccountService::create(&pool, provider_id, "PMS sync", 60).await.unwrap();
+        assert!(key.starts_with(API_KEY_PREFIX));
+        let stored = sqlx::query!("SELECT key_hash FROM service_accounts WHERE id = $1", account.id)
+            .fetch_one(&pool).await.unwrap();
+        assert_ne!(stored.key_hash, key);
+
+        let authenticated = ServiceAccountService::authenticate(&pool, &key).await.unwrap().unwrap();
+        assert_eq!(authenticated.provider_id, provider_id);
+        assert!(ServiceAccountService::authenticate(&pool, "vtsk_wrong").await.unwrap().is_none());
+
+        let (_, rotated) = ServiceAccountService::rotate(&pool, account.id).await.unwrap();
+        assert!(ServiceAccountService::authenticate(&pool, &key).await.unwrap().is_none());
+        assert!(ServiceAccountService::authenticate(&pool, &rotated).await.unwrap().is_some());
+
+        ServiceAccountService::revoke(&pool, account.id).await.unwrap();
+        assert!(ServiceAccountService::authenticate(&pool, &rotated).await.unwrap().is_none());
+        assert!(matches!(
+            ServiceAccountService::rotate(&pool, account.id).await,
+            Err(ServiceAccountError::NotFound(_))
+        ));
+
+        sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &[provider_id, client_id][..])
+            .execute(&pool).await.unwrap();
+    }
+}
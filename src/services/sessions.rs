@@ -0,0 +1,342 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use crate::models::RefreshToken;
+use crate::utils::generate_refresh_token;
+
+/// One signed-in device: the refresh tokens sharing a family.
+#[derive(Serialize, Debug)]
+pub struct DeviceSession {
+    pub family_id: Uuid,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub signed_in_at: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_milliseconds_option")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub user_agent: Option<String>,
+    pub device_name: Option<String>,
+    /// Whether this is the device making the request.
+    pub current: bool,
+}
+
+/// Longest `User-Agent` kept for a session; the rest is cut off.
+const MAX_USER_AGENT_LENGTH: usize = 512;
+pub const MAX_DEVICE_NAME_LENGTH: usize = 100;
+
+pub struct SessionService;
+
+impl SessionService {
+    /// Issues the first refresh token of a new family for a fresh login from
+    /// the described device, valid until `expires_at`. Only its hash is stored;
+    /// the token itself is returned to give to the client.
+    pub async fn start_family(
+        pool: &PgPool,
+        pepper: Option<&str>,
+        user_id: Uuid,
+        user_agent: Option<&str>,
+        device_name: Option<&str>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(String, Uuid), sqlx::Error> {
+        let token = generate_refresh_token();
+        let family_id = Uuid::new_v4();
+        let user_agent = user_agent.map(|agent| agent.chars().take(MAX_USER_AGENT_LENGTH).collect::<String>());
+        sqlx::query!(
+            "
+            INSERT INTO refresh_tokens (token, user_id, family_id, hashed, user_agent, device_name, expires_at)
+            VALUES ($1, $2, $3, true, $4, $5, $6)
+            ",
+            hash_refresh_token(pepper, &token),
+            user_id,
+            family_id,
+            user_agent,
+            device_name,
+            expires_at
+        )
+        .execute(pool)
+        .await?;
+
+        Ok((token, family_id))
+    }
+
+    /// The user's stored refresh token matching `token`. A token still stored
+    /// in plaintext is hashed as it's found.
+    pub async fn find_token(pool: &PgPool, pepper: Option<&str>, user_id: Uuid, token: &str) -> Result<Option<RefreshToken>, sqlx::Error> {
+        let hash = hash_refresh_token(pepper, token);
+        let record = sqlx::query_as!(
+            RefreshToken,
+            "
+            SELECT * FROM refresh_tokens
+            WHERE user_id = $1 AND ((hashed AND token = $2) OR (NOT hashed AND token = $3))
+            ",
+            user_id,
+            hash,
+            token
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        match record {
+            Some(record) if !record.hashed => {
+                sqlx::query!(
+                    "UPDATE refresh_tokens SET token = $1, hashed = true WHERE token = $2 AND NOT hashed",
+                    hash,
+                    token
+                )
+                .execute(pool)
+                .await?;
+                Ok(Some(RefreshToken { token: hash, hashed: true, ..record }))
+            },
+            record => Ok(record),
+        }
+    }
+
+    /// Records that a stored token, as returned by `find_token`, was just used,
+    /// and extends it to `expires_at`.
+    pub async fn mark_used(pool: &PgPool, stored_token: &str, used_at: DateTime<Utc>, expires_at: DateTime<Utc>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE refresh_tokens SET last_used_at = $1, expires_at = $2 WHERE token = $3",
+            used_at,
+            expires_at,
+            stored_token
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Deletes the user's refresh token matching `token`. Returns false if they have none.
+    pub async fn delete_token(pool: &PgPool, pepper: Option<&str>, user_id: Uuid, token: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "
+            DELETE FROM refresh_tokens
+            WHERE user_id = $1 AND ((hashed AND token = $2) OR (NOT hashed AND token = $3))
+            ",
+            user_id,
+            hash_refresh_token(pepper, token),
+            token
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Deletes refresh tokens that expired more than `grace` ago. Returns how many.
+    pub async fn delete_expired(pool: &PgPool, grace: Duration) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM refresh_tokens WHERE expires_at < $1",
+            Utc::now() - grace
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Hashes the refresh tokens still stored in plaintext. Returns how many there were.
+    pub async fn hash_legacy_tokens(pool: &PgPool, pepper: Option<&str>) -> Result<u64, sqlx::Error> {
+        let tokens = sqlx::query!("SELECT token FROM refresh_tokens WHERE NOT hashed")
+            .fetch_all(pool)
+            .await?;
+
+        let mut hashed = 0;
+        for record in tokens {
+            hashed += sqlx::query!(
+                "UPDATE refresh_tokens SET token = $1, hashed = true WHERE token = $2 AND NOT hashed",
+                hash_refresh_token(pepper, &record.token),
+                record.token
+            )
+            .execute(pool)
+            .await?
+            .rows_affected();
+        }
+        Ok(hashed)
+    }
+
+    /// The user's signed-in devices, oldest login first. `current_family` marks the caller's own.
+    pub async fn list(pool: &PgPool, user_id: Uuid, current_family: Option<Uuid>) -> Result<Vec<DeviceSession>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT family_id,
+                   MIN(issued_at) AS "signed_in_at!",
+                   MAX(last_used_at) AS last_used_at,
+                   (ARRAY_AGG(user_agent ORDER BY issued_at DESC))[1] AS user_agent,
+                   (ARRAY_AGG(device_name ORDER BY issued_at DESC))[1] AS device_name
+            FROM refresh_tokens
+            WHERE user_id = $1
+              AND is_revoked = false
+              AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+            GROUP BY family_id
+            ORDER BY 2
+            "#,
+            user_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| DeviceSession {
+                family_id: row.family_id,
+                signed_in_at: row.signed_in_at,
+                last_used_at: row.last_used_at,
+                user_agent: row.user_agent,
+                device_name: row.device_name,
+                current: Some(row.family_id) == current_family,
+            })
+            .collect())
+    }
+
+    /// Revokes every token in one of the user's families. Returns false if the user
+    /// has no active tokens in it.
+    pub async fn revoke_family(pool: &PgPool, user_id: Uuid, family_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "
+            UPDATE refresh_tokens
+            SET is_revoked = true
+            WHERE user_id = $1 AND family_id = $2 AND is_revoked = false
+            ",
+            user_id,
+            family_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn is_family_revoked(pool: &PgPool, family_id: Uuid) -> Result<bool, sqlx::Error> {
+        let active = sqlx::query!(
+            "SELECT 1 AS one FROM refresh_tokens WHERE family_id = $1 AND is_revoked = false LIMIT 1",
+            family_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(active.is_none())
+    }
+}
+
+/// What's stored for a refresh token: the hex SHA-256 of the pepper and token.
+fn hash_refresh_token(pepper: Option<&str>, token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(pepper.unwrap_or_default().as_bytes());
+    hasher.update(token.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{insert_user, test_pool};
+
+    async fn setup() -> (PgPool, Uuid) {
+        let pool = test_pool().await;
+
+        let user_id = insert_user(&pool, "client").await;
+
+        (pool, user_id)
+    }
+
+    fn in_a_month() -> DateTime<Utc> {
+        Utc::now() + Duration::days(30)
+    }
+
+    #[tokio::test]
+    async fn revoking_one_family_leaves_the_others() {
+        let (pool, user_id) = setup().await;
+        let (_, phone) = SessionService::start_family(&pool, None, user_id, Some("VetText/2.3 (iPhone; iOS 17.4)"), Some("Sam's iPhone"), in_a_month()).await.unwrap();
+        let (_, tablet) = SessionService::start_family(&pool, None, user_id, None, None, in_a_month()).await.unwrap();
+
+        let sessions = SessionService::list(&pool, user_id, Some(phone)).await.unwrap();
+        assert_eq!(sessions.iter().map(|s| s.family_id).collect::<Vec<_>>(), vec![phone, tablet]);
+        assert!(sessions[0].current && !sessions[1].current);
+        assert_eq!(sessions[0].user_agent.as_deref(), Some("VetText/2.3 (iPhone; iOS 17.4)"));
+        assert_eq!(sessions[0].device_name.as_deref(), Some("Sam's iPhone"));
+        assert_eq!((sessions[1].user_agent.as_deref(), sessions[1].device_name.as_deref()), (None, None));
+
+        // Someone else can't revoke the family
+        assert!(!SessionService::revoke_family(&pool, Uuid::new_v4(), tablet).await.unwrap());
+
+        assert!(SessionService::revoke_family(&pool, user_id, tablet).await.unwrap());
+        assert!(SessionService::is_family_revoked(&pool, tablet).await.unwrap());
+        assert!(!SessionService::is_family_revoked(&pool, phone).await.unwrap());
+        let sessions = SessionService::list(&pool, user_id, None).await.unwrap();
+        assert_eq!(sessions.iter().map(|s| s.family_id).collect::<Vec<_>>(), vec![phone]);
+
+        // Already revoked
+        assert!(!SessionService::revoke_family(&pool, user_id, tablet).await.unwrap());
+
+        sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn refresh_tokens_are_stored_hashed() {
+        let (pool, user_id) = setup().await;
+        let pepper = Some("pepper");
+        let (token, family_id) = SessionService::start_family(&pool, pepper, user_id, None, None, in_a_month()).await.unwrap();
+
+        let stored = sqlx::query!("SELECT token FROM refresh_tokens WHERE family_id = $1", family_id)
+            .fetch_one(&pool).await.unwrap().token;
+        assert_ne!(stored, token);
+        let found = SessionService::find_token(&pool, pepper, user_id, &token).await.unwrap().unwrap();
+        assert_eq!((found.token.as_str(), found.family_id), (stored.as_str(), family_id));
+
+        // The hash is only useful with the pepper, and only as the token
+        assert!(SessionService::find_token(&pool, None, user_id, &token).await.unwrap().is_none());
+        assert!(SessionService::find_token(&pool, pepper, user_id, &stored).await.unwrap().is_none());
+
+        // Tokens issued before hashing still work, and are hashed on first use
+        let legacy = generate_refresh_token();
+        sqlx::query!("INSERT INTO refresh_tokens (token, user_id) VALUES ($1, $2)", legacy, user_id)
+            .execute(&pool).await.unwrap();
+        let found = SessionService::find_token(&pool, pepper, user_id, &legacy).await.unwrap().unwrap();
+        assert!(found.hashed);
+        assert_eq!(found.token, hash_refresh_token(pepper, &legacy));
+        assert!(SessionService::find_token(&pool, pepper, user_id, &legacy).await.unwrap().is_some());
+
+        // ...or in bulk
+        let untouched = generate_refresh_token();
+        sqlx::query!("INSERT INTO refresh_tokens (token, user_id) VALUES ($1, $2)", untouched, user_id)
+            .execute(&pool).await.unwrap();
+        assert!(SessionService::hash_legacy_tokens(&pool, pepper).await.unwrap() >= 1);
+        let stored = sqlx::query!("SELECT hashed FROM refresh_tokens WHERE token = $1", hash_refresh_token(pepper, &untouched))
+            .fetch_one(&pool).await.unwrap();
+        assert!(stored.hashed);
+
+        assert!(SessionService::delete_token(&pool, pepper, user_id, &untouched).await.unwrap());
+        assert!(!SessionService::delete_token(&pool, pepper, user_id, &untouched).await.unwrap());
+
+        sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn refreshing_extends_expiry_and_stale_tokens_are_swept() {
+        let (pool, user_id) = setup().await;
+        let now = Utc::now();
+        let (token, _) = SessionService::start_family(&pool, None, user_id, None, None, now + Duration::minutes(1)).await.unwrap();
+        let stored = SessionService::find_token(&pool, None, user_id, &token).await.unwrap().unwrap();
+        SessionService::mark_used(&pool, &stored.token, now, now + Duration::days(30)).await.unwrap();
+        let extended = SessionService::find_token(&pool, None, user_id, &token).await.unwrap().unwrap();
+        assert!(extended.expires_at.unwrap() > now + Duration::days(29));
+
+        let (_, recent) = SessionService::start_family(&pool, None, user_id, None, None, now - Duration::days(1)).await.unwrap();
+        let (_, stale) = SessionService::start_family(&pool, None, user_id, None, None, now - Duration::days(10)).await.unwrap();
+        assert!(SessionService::delete_expired(&pool, Duration::days(7)).await.unwrap() >= 1);
+
+        let left: Vec<Uuid> = sqlx::query!("SELECT family_id FROM refresh_tokens WHERE user_id = $1", user_id)
+            .fetch_all(&pool).await.unwrap()
+            .into_iter().map(|row| row.family_id).collect();
+        assert!(left.contains(&stored.family_id));
+        assert!(left.contains(&recent), "expired within the grace period");
+        assert!(!left.contains(&stale));
+
+        // Expired tokens are left out of the user's devices even before they're swept
+        let sessions = SessionService::list(&pool, user_id, None).await.unwrap();
+        assert_eq!(sessions.iter().map(|s| s.family_id).collect::<Vec<_>>(), vec![stored.family_id]);
+
+        sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+    }
+}
@@ -0,0 +1,75 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use serde::Serialize;
+
+#[derive(Serialize, Debug)]
+pub struct StorageQuota {
+    pub used_bytes: i64,
+    pub quota_bytes: i64,
+    pub remaining_bytes: i64,
+}
+
+pub struct StorageQuotaService;
+
+impl StorageQuotaService {
+    /// Bytes the user is storing, counting uploads still waiting to be retried.
+    pub async fn used_bytes(pool: &PgPool, user_id: Uuid) -> Result<i64, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT
+                (SELECT COALESCE(SUM(size_bytes), 0) FROM images WHERE user_id = $1)::BIGINT AS "images!",
+                (SELECT COALESCE(SUM(LENGTH(data)), 0) FROM pending_uploads WHERE user_id = $1)::BIGINT AS "pending!"
+            "#,
+            user_id
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(row.images + row.pending)
+    }
+
+    pub async fn quota_for_user(pool: &PgPool, user_id: Uuid, quota_bytes: u64) -> Result<StorageQuota, sqlx::Error> {
+        let used_bytes = Self::used_bytes(pool, user_id).await?;
+        let quota_bytes = quota_bytes as i64;
+        Ok(StorageQuota {
+            used_bytes,
+            quota_bytes,
+            remaining_bytes: (quota_bytes - used_bytes).max(0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{insert_user, test_pool};
+
+    #[tokio::test]
+    async fn counts_stored_and_pending_images() {
+        let pool = test_pool().await;
+        let user_id = insert_user(&pool, "client").await;
+
+        assert_eq!(StorageQuotaService::used_bytes(&pool, user_id).await.unwrap(), 0);
+
+        sqlx::query!(
+            "INSERT INTO images (id, user_id, image_type, image_url, size_bytes) VALUES ($1, $2, 'pet', 'https://example.com/a.png', 700)",
+            Uuid::new_v4(),
+            user_id
+        )
+        .execute(&pool).await.unwrap();
+        sqlx::query!(
+            "INSERT INTO pending_uploads (id, user_id, image_type, object_name, data) VALUES ($1, $2, 'pet', 'pet/b.png', $3)",
+            Uuid::new_v4(),
+            user_id,
+            vec![0u8; 200]
+        )
+        .execute(&pool).await.unwrap();
+
+        let quota = StorageQuotaService::quota_for_user(&pool, user_id, 1000).await.unwrap();
+        assert_eq!((quota.used_bytes, quota.remaining_bytes), (900, 100));
+        let quota = StorageQuotaService::quota_for_user(&pool, user_id, 500).await.unwrap();
+        assert_eq!(quota.remaining_bytes, 0);
+
+        sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+    }
+}
@@ -0,0 +1,273 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fmt;
+use crate::models::AdminUsersQuery;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+/// Scopes an admin can give a user. Admins are only made by hand.
+pub const ASSIGNABLE_SCOPES: &[&str] = &["client", "provider"];
+
+/// A user as admins see them.
+#[derive(Serialize, Debug)]
+pub struct AdminUser {
+    pub id: Uuid,
+    pub phone_number: String,
+    pub scope: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub email: Option<String>,
+    pub verified: bool,
+    pub provider_status: Option<String>,
+    #[serde(with = "chrono::serde::ts_milliseconds_option")]
+    pub suspended_at: Option<DateTime<Utc>>,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum UserAdminError {
+    NotFound,
+    Invalid(&'static str),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for UserAdminError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UserAdminError::NotFound => write!(f, "User not found"),
+            UserAdminError::Invalid(msg) => write!(f, "{}", msg),
+            UserAdminError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for UserAdminError {
+    fn from(e: sqlx::Error) -> Self {
+        UserAdminError::Database(e)
+    }
+}
+
+pub struct UserAdminService;
+
+impl UserAdminService {
+    /// One page of users, newest first, with the total number of matches and
+    /// whether there are more pages.
+    pub async fn list(pool: &PgPool, query: &AdminUsersQuery) -> Result<(Vec<AdminUser>, i64, bool), UserAdminError> {
+        let page = query.page.unwrap_or(1);
+        if page < 1 {
+            return Err(UserAdminError::Invalid("Invalid page number: must be >= 1"));
+        }
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+            return Err(UserAdminError::Invalid("Invalid limit: must be between 1 and 100"));
+        }
+
+        let total_count = sqlx::query!(
+            r#"
+            SELECT COUNT(*) AS "count!"
+            FROM users
+            WHERE ($1::TEXT IS NULL OR scope = $1)
+              AND ($2::BOOL IS NULL OR verified = $2)
+              AND ($3::TEXT IS NULL OR provider_status = $3)
+            "#,
+            query.scope,
+            query.verified,
+            query.provider_status
+        )
+        .fetch_one(pool)
+        .await?
+        .count;
+
+        let offset = (page - 1) * limit;
+        let users = sqlx::query_as!(
+            AdminUser,
+            r#"
+            SELECT id, phone_number, scope, first_name, last_name, email, verified, provider_status,
+                   suspended_at, created_at
+            FROM users
+            WHERE ($1::TEXT IS NULL OR scope = $1)
+              AND ($2::BOOL IS NULL OR verified = $2)
+              AND ($3::TEXT IS NULL OR provider_status = $3)
+            ORDER BY created_at DESC, id
+            LIMIT $4 OFFSET $5
+            "#,
+            query.scope,
+            query.verified,
+            query.provider_status,
+            limit,
+            offset
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let has_more = offset + (users.len() as i64) < total_count;
+        Ok((users, total_count, has_more))
+    }
+
+    /// Moves the user to `scope`, returning the scope they had. Tokens already
+    /// issued keep the old scope until they're refreshed.
+    pub async fn set_scope(pool: &PgPool, user_id: Uuid, scope: &str) -> Result<String, UserAdminError> {
+        if !ASSIGNABLE_SCOPES.contains(&scope) {
+            return Err(UserAdminError::Invalid("scope must be \"client\" or \"provider\""));
+        }
+        let previous = sqlx::query!(
+            r#"
+            UPDATE users u SET scope = $2, updated_at = CURRENT_TIMESTAMP
+            FROM users old
+            WHERE u.id = $1 AND old.id = u.id
+            RETURNING old.scope AS "scope!"
+            "#,
+            user_id,
+            scope
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or(UserAdminError::NotFound)?;
+
+        Ok(previous.scope)
+    }
+
+    /// Approves or rejects the user's request to be a provider. Approving makes
+    /// them a provider; their next token carries the new scope. Returns the
+    /// request's new status.
+    pub async fn review_provider_request(pool: &PgPool, user_id: Uuid, approve: bool) -> Result<&'static str, UserAdminError> {
+        let status = if approve { "approved" } else { "rejected" };
+        let reviewed = sqlx::query!(
+            "
+            UPDATE users
+            SET provider_status = $2,
+                scope = CASE WHEN $3 THEN 'provider' ELSE scope END,
+                updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND provider_status = 'pending'
+            RETURNING id
+            ",
+            user_id,
+            status,
+            approve
+        )
+        .fetch_optional(pool)
+        .await?;
+        if reviewed.is_some() {
+            return Ok(status);
+        }
+
+        let exists = sqlx::query!("SELECT id FROM users WHERE id = $1", user_id)
+            .fetch_optional(pool)
+            .await?
+            .is_some();
+        if exists {
+            Err(UserAdminError::Invalid("User has no pending provider request"))
+        } else {
+            Err(UserAdminError::NotFound)
+        }
+    }
+
+    /// Suspends the user and revokes all their refresh tokens, so every device
+    /// is signed out and they can't log in again. Returns the refresh token
+    /// families that were revoked.
+    pub async fn suspend(pool: &PgPool, user_id: Uuid) -> Result<Vec<Uuid>, UserAdminError> {
+        let mut tx = pool.begin().await?;
+        sqlx::query!(
+            "UPDATE users SET suspended_at = COALESCE(suspended_at, CURRENT_TIMESTAMP) WHERE id = $1 RETURNING id",
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(UserAdminError::NotFound)?;
+
+        let families = sqlx::query!(
+            "
+            UPDATE refresh_tokens SET is_revoked = true
+            WHERE user_id = $1 AND is_revoked = false
+            RETURNING family_id
+            ",
+            user_id
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        let mut family_ids: Vec<Uuid> = families.into_iter().map(|row| row.family_id).collect();
+        family_ids.sort();
+        family_ids.dedup();
+        Ok(family_ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{insert_user, test_pool};
+    use crate::services::sessions::SessionService;
+
+    #[tokio::test]
+    async fn admins_promote_and_suspend_users() {
+        let pool = test_pool().await;
+        let user_id = insert_user(&pool, "client").await;
+
+        assert_eq!(UserAdminService::set_scope(&pool, user_id, "provider").await.unwrap(), "client");
+        assert!(matches!(UserAdminService::set_scope(&pool, user_id, "admin").await, Err(UserAdminError::Invalid(_))));
+        assert!(matches!(UserAdminService::set_scope(&pool, Uuid::new_v4(), "provider").await, Err(UserAdminError::NotFound)));
+
+        let query = AdminUsersQuery { scope: Some("provider".to_string()), verified: Some(false), provider_status: None, page: None, limit: Some(100) };
+        let (users, total_count, _) = UserAdminService::list(&pool, &query).await.unwrap();
+        assert!(total_count >= 1);
+        assert!(users.iter().all(|user| user.scope == "provider" && !user.verified));
+        let query = AdminUsersQuery { scope: Some("client".to_string()), verified: None, provider_status: None, page: None, limit: Some(100) };
+        let (users, _, _) = UserAdminService::list(&pool, &query).await.unwrap();
+        assert!(users.iter().all(|user| user.id != user_id));
+
+        let expires_at = Utc::now() + chrono::Duration::days(30);
+        let (_, phone) = SessionService::start_family(&pool, None, user_id, None, None, expires_at).await.unwrap();
+        let (_, tablet) = SessionService::start_family(&pool, None, user_id, None, None, expires_at).await.unwrap();
+        let mut expected = vec![phone, tablet];
+        expected.sort();
+        assert_eq!(UserAdminService::suspend(&pool, user_id).await.unwrap(), expected);
+        assert!(SessionService::list(&pool, user_id, None).await.unwrap().is_empty());
+        let suspended_at = sqlx::query!("SELECT suspended_at FROM users WHERE id = $1", user_id)
+            .fetch_one(&pool).await.unwrap().suspended_at;
+        assert!(suspended_at.is_some());
+
+        // Suspending again keeps the original time
+        assert!(UserAdminService::suspend(&pool, user_id).await.unwrap().is_empty());
+        let again = sqlx::query!("SELECT suspended_at FROM users WHERE id = $1", user_id)
+            .fetch_one(&pool).await.unwrap().suspended_at;
+        assert_eq!(again, suspended_at);
+
+        sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn admins_review_provider_requests() {
+        let pool = test_pool().await;
+        let approved_id = insert_user(&pool, "client").await;
+        let rejected_id = insert_user(&pool, "client").await;
+        let client_id = insert_user(&pool, "client").await;
+        sqlx::query!("UPDATE users SET provider_status = 'pending' WHERE id = ANY($1)", &[approved_id, rejected_id])
+            .execute(&pool).await.unwrap();
+
+        let query = AdminUsersQuery { scope: None, verified: None, provider_status: Some("pending".to_string()), page: None, limit: Some(100) };
+        let (users, _, _) = UserAdminService::list(&pool, &query).await.unwrap();
+        assert!(users.iter().any(|user| user.id == approved_id));
+        assert!(users.iter().all(|user| user.provider_status.as_deref() == Some("pending") && user.scope == "client"));
+
+        assert_eq!(UserAdminService::review_provider_request(&pool, approved_id, true).await.unwrap(), "approved");
+        assert_eq!(UserAdminService::review_provider_request(&pool, rejected_id, false).await.unwrap(), "rejected");
+        let scopes = sqlx::query!("SELECT id, scope FROM users WHERE id = ANY($1)", &[approved_id, rejected_id])
+            .fetch_all(&pool).await.unwrap();
+        for user in scopes {
+            assert_eq!(user.scope, if user.id == approved_id { "provider" } else { "client" });
+        }
+
+        // Only a pending request can be reviewed
+        assert!(matches!(UserAdminService::review_provider_request(&pool, rejected_id, true).await, Err(UserAdminError::Invalid(_))));
+        assert!(matches!(UserAdminService::review_provider_request(&pool, client_id, true).await, Err(UserAdminError::Invalid(_))));
+        assert!(matches!(UserAdminService::review_provider_request(&pool, Uuid::new_v4(), true).await, Err(UserAdminError::NotFound)));
+
+        sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &[approved_id, rejected_id, client_id]).execute(&pool).await.unwrap();
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+#[derive(Default)]
+struct FailureState {
+    consecutive_failures: u32,
+    locked_until: Option<Instant>,
+}
+
+#[derive(Default)]
+struct AddressState {
+    failures: u32,
+    window_started: Option<Instant>,
+    locked_until: Option<Instant>,
+}
+
+/// Counts consecutive signature verification failures per user, and failures
+/// per client address, shared by all workers. Once either reaches its threshold
+/// the user's, or the address's, signed requests are refused until the lockout
+/// expires.
+pub struct SignatureFailureTracker {
+    threshold: u32,
+    address_threshold: u32,
+    lockout: Duration,
+    users: Mutex<HashMap<Uuid, FailureState>>,
+    addresses: Mutex<HashMap<IpAddr, AddressState>>,
+}
+
+impl SignatureFailureTracker {
+    pub fn new(threshold: u32, address_threshold: u32, lockout: Duration) -> Self {
+        SignatureFailureTracker {
+            threshold,
+            address_threshold,
+            lockout,
+            users: Mutex::new(HashMap::new()),
+            addresses: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Time left on the user's lockout, if they are locked out at `now`.
+    pub fn lockout_remaining(&self, user_id: Uuid, now: Instant) -> Option<Duration> {
+        let mut users = self.users.lock().unwrap();
+        let locked_until = users.get(&user_id)?.locked_until?;
+        if locked_until > now {
+            return Some(locked_until - now);
+        }
+        // Expired: the user starts over with a clean count
+        users.remove(&user_id);
+        None
+    }
+
+    /// Records a failed verification. Returns the failure count if this failure
+    /// started a lockout.
+    pub fn record_failure(&self, user_id: Uuid, now: Instant) -> Option<u32> {
+        let mut users = self.users.lock().unwrap();
+        let state = users.entry(user_id).or_default();
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= self.threshold && state.locked_until.is_none() {
+            state.locked_until = Some(now + self.lockout);
+            return Some(state.consecutive_failures);
+        }
+        None
+    }
+
+    pub fn record_success(&self, user_id: Uuid) {
+        self.users.lock().unwrap().remove(&user_id);
+    }
+
+    /// Time left on the address's lockout, if it is locked out at `now`.
+    pub fn address_lockout_remaining(&self, ip: IpAddr, now: Instant) -> Option<Duration> {
+        let mut addresses = self.addresses.lock().unwrap();
+        let locked_until = addresses.get(&ip)?.locked_until?;
+        if locked_until > now {
+            return Some(locked_until - now);
+        }
+        addresses.remove(&ip);
+        None
+    }
+
+    /// Records a failed verification from the address, whichever user it was
+    /// for. Failures count within a window as long as the lockout, and valid
+    /// signatures don't reset them, so one good account can't cover for
+    /// guesses at others. Returns the failure count if this failure started a
+    /// lockout.
+    pub fn record_address_failure(&self, ip: IpAddr, now: Instant) -> Option<u32> {
+        let mut addresses = self.addresses.lock().unwrap();
+        let state = addresses.entry(ip).or_default();
+        if state.window_started.is_none_or(|started| now.duration_since(started) >= self.lockout) {
+            state.failures = 0;
+            state.window_started = Some(now);
+        }
+        state.failures += 1;
+        if state.failures >= self.address_threshold && state.locked_until.is_none() {
+            state.locked_until = Some(now + self.lockout);
+            return Some(state.failures);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locks_out_after_threshold_consecutive_failures() {
+        let tracker = SignatureFailureTracker::new(3, 10, Duration::from_secs(60));
+        let user_id = Uuid::new_v4();
+        let now = Instant::now();
+
+        assert_eq!(tracker.record_failure(user_id, now), None);
+        assert_eq!(tracker.record_failure(user_id, now), None);
+        assert!(tracker.lockout_remaining(user_id, now).is_none());
+
+        assert_eq!(tracker.record_failure(user_id, now), Some(3));
+        assert_eq!(tracker.lockout_remaining(user_id, now), Some(Duration::from_secs(60)));
+
+        // Other users are unaffected
+        assert!(tracker.lockout_remaining(Uuid::new_v4(), now).is_none());
+    }
+
+    #[test]
+    fn success_resets_the_count() {
+        let tracker = SignatureFailureTracker::new(3, 10, Duration::from_secs(60));
+        let user_id = Uuid::new_v4();
+        let now = Instant::now();
+
+        tracker.record_failure(user_id, now);
+        tracker.record_failure(user_id, now);
+        tracker.record_success(user_id);
+        tracker.record_failure(user_id, now);
+        tracker.record_failure(user_id, now);
+        assert!(tracker.lockout_remaining(user_id, now).is_none());
+    }
+
+    #[test]
+    fn lockout_expires() {
+        let tracker = SignatureFailureTracker::new(2, 10, Duration::from_secs(60));
+        let user_id = Uuid::new_v4();
+        let now = Instant::now();
+
+        tracker.record_failure(user_id, now);
+        assert!(tracker.record_failure(user_id, now).is_some());
+        assert!(tracker.lockout_remaining(user_id, now + Duration::from_secs(59)).is_some());
+        assert!(tracker.lockout_remaining(user_id, now + Duration::from_secs(60)).is_none());
+
+        // The count starts over after the lockout
+        assert_eq!(tracker.record_failure(user_id, now + Duration::from_secs(61)), None);
+    }
+
+    #[test]
+    fn locks_out_an_address_failing_for_many_users() {
+        let tracker = SignatureFailureTracker::new(3, 4, Duration::from_secs(60));
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let now = Instant::now();
+
+        // One failure each for several users: no user is locked out, but the address is
+        for i in 0..3u64 {
+            assert_eq!(tracker.record_address_failure(ip, now + Duration::from_secs(i)), None);
+            tracker.record_success(Uuid::new_v4());
+        }
+        assert!(tracker.address_lockout_remaining(ip, now).is_none());
+        assert_eq!(tracker.record_address_failure(ip, now + Duration::from_secs(3)), Some(4));
+        assert_eq!(tracker.address_lockout_remaining(ip, now + Duration::from_secs(3)), Some(Duration::from_secs(60)));
+        assert!(tracker.address_lockout_remaining("203.0.113.8".parse().unwrap(), now).is_none());
+
+        assert!(tracker.address_lockout_remaining(ip, now + Duration::from_secs(63)).is_none());
+    }
+
+    #[test]
+    fn address_failures_outside_the_window_are_forgotten() {
+        let tracker = SignatureFailureTracker::new(3, 3, Duration::from_secs(60));
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        let now = Instant::now();
+
+        tracker.record_address_failure(ip, now);
+        tracker.record_address_failure(ip, now + Duration::from_secs(30));
+        assert_eq!(tracker.record_address_failure(ip, now + Duration::from_secs(61)), None);
+        assert!(tracker.address_lockout_remaining(ip, now + Duration::from_secs(61)).is_none());
+    }
+}
@@ -0,0 +1,171 @@
+use async_trait::async_trait;
+use google_cloud_storage::client::{Client as GcsClient, ClientConfig};
+use google_cloud_storage::http::objects::delete::DeleteObjectRequest;
+use google_cloud_storage::http::objects::download::Range;
+use google_cloud_storage::http::objects::get::GetObjectRequest;
+use google_cloud_storage::http::objects::upload::{UploadObjectRequest, UploadType, Media};
+use google_cloud_storage::http::Error as GcsError;
+use google_cloud_storage::sign::{SignedURLMethod, SignedURLOptions};
+use std::borrow::Cow;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+// Authenticated once and shared, so only the first upload (or the warm-up) pays for it
+static GCS_CLIENT: OnceLock<GcsClient> = OnceLock::new();
+
+/// Where uploaded images are stored.
+#[async_trait]
+pub trait ImageStorage: Send + Sync {
+    /// Stores `data` under `object_name` and returns its public URL.
+    async fn upload(&self, object_name: &str, content_type: &str, data: Vec<u8>) -> anyhow::Result<String>;
+    /// Removes the object at a URL `upload` returned.
+    async fn delete(&self, image_url: &str) -> anyhow::Result<()>;
+    /// Reads back the object at a URL `upload` returned.
+    async fn download(&self, image_url: &str) -> anyhow::Result<Vec<u8>>;
+    /// A URL the client can PUT `object_name` to directly, valid for `expires_in`.
+    /// The client must send `content_type` as its Content-Type.
+    async fn signed_upload_url(&self, _object_name: &str, _content_type: &str, _expires_in: Duration) -> anyhow::Result<String> {
+        anyhow::bail!("Direct uploads are not supported by this storage")
+    }
+    /// The object a client uploaded through a signed URL, or None if it hasn't been.
+    async fn find(&self, _object_name: &str) -> anyhow::Result<Option<StoredObject>> {
+        anyhow::bail!("Direct uploads are not supported by this storage")
+    }
+}
+
+/// An object in storage, as `find` sees it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredObject {
+    /// The URL `upload` would have returned for it.
+    pub url: String,
+    pub size_bytes: u64,
+}
+
+/// Google Cloud Storage, in the bucket named by `GCS_BUCKET_NAME`.
+pub struct GcsStorage;
+
+impl GcsStorage {
+    pub async fn client() -> anyhow::Result<GcsClient> {
+        if let Some(client) = GCS_CLIENT.get() {
+            return Ok(client.clone());
+        }
+        let client_config = ClientConfig::default()
+            .with_auth()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to initialize GCS client: {}", e))?;
+        Ok(GCS_CLIENT.get_or_init(|| GcsClient::new(client_config)).clone())
+    }
+
+    // The bucket and the object's name in it, for a URL `upload` returned
+    fn locate(image_url: &str) -> anyhow::Result<(String, String)> {
+        let bucket_name = std::env::var("GCS_BUCKET_NAME")
+            .map_err(|_| anyhow::anyhow!("GCS_BUCKET_NAME not set in environment"))?;
+        let object_name = image_url
+            .strip_prefix(&format!("https://storage.googleapis.com/{}/", bucket_name))
+            .ok_or_else(|| anyhow::anyhow!("{} is not in bucket {}", image_url, bucket_name))?
+            .to_string();
+        Ok((bucket_name, object_name))
+    }
+}
+
+#[async_trait]
+impl ImageStorage for GcsStorage {
+    async fn upload(&self, object_name: &str, content_type: &str, data: Vec<u8>) -> anyhow::Result<String> {
+        let client = Self::client().await?;
+
+        let bucket_name = std::env::var("GCS_BUCKET_NAME")
+            .map_err(|_| anyhow::anyhow!("GCS_BUCKET_NAME not set in environment"))?;
+
+        let upload_request = UploadObjectRequest {
+            bucket: bucket_name.clone(),
+            ..Default::default()
+        };
+        // Media with object name and content type
+        let media = Media {
+            name: Cow::Owned(object_name.to_string()),
+            content_type: Cow::Owned(content_type.to_string()),
+            content_length: Some(data.len() as u64),
+        };
+
+        if let Err(e) = client.upload_object(&upload_request, data, &UploadType::Simple(media)).await {
+            logln!("❌ Upload failed: {:?}", e);
+
+            let error_string = format!("{:?}", e);
+            if error_string.contains("status code: 403") {
+                logln!("❌ This is a permissions error (403 Forbidden)");
+            } else if error_string.contains("status code: 404") {
+                logln!("❌ This is a not found error (404 Not Found) - check bucket name");
+            }
+
+            // Check bucket name case sensitivity
+            logln!("❌ Using bucket name: '{}' (check case sensitivity)", bucket_name);
+            logln!("❌ Object path: '{}'", object_name);
+            return Err(anyhow::anyhow!("Failed to upload image to GCS: {}", e));
+        }
+
+        Ok(format!("https://storage.googleapis.com/{}/{}", bucket_name, object_name))
+    }
+
+    async fn delete(&self, image_url: &str) -> anyhow::Result<()> {
+        let (bucket_name, object_name) = Self::locate(image_url)?;
+
+        let request = DeleteObjectRequest {
+            bucket: bucket_name,
+            object: object_name.clone(),
+            ..Default::default()
+        };
+        Self::client().await?
+            .delete_object(&request)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to delete {} from GCS: {}", object_name, e))
+    }
+
+    async fn download(&self, image_url: &str) -> anyhow::Result<Vec<u8>> {
+        let (bucket_name, object_name) = Self::locate(image_url)?;
+
+        let request = GetObjectRequest {
+            bucket: bucket_name,
+            object: object_name.clone(),
+            ..Default::default()
+        };
+        Self::client().await?
+            .download_object(&request, &Range::default())
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to download {} from GCS: {}", object_name, e))
+    }
+
+    async fn signed_upload_url(&self, object_name: &str, content_type: &str, expires_in: Duration) -> anyhow::Result<String> {
+        let bucket_name = std::env::var("GCS_BUCKET_NAME")
+            .map_err(|_| anyhow::anyhow!("GCS_BUCKET_NAME not set in environment"))?;
+
+        let options = SignedURLOptions {
+            method: SignedURLMethod::PUT,
+            expires: expires_in,
+            content_type: Some(content_type.to_string()),
+            ..Default::default()
+        };
+        Self::client().await?
+            .signed_url(&bucket_name, object_name, None, None, options)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to sign an upload URL for {}: {}", object_name, e))
+    }
+
+    async fn find(&self, object_name: &str) -> anyhow::Result<Option<StoredObject>> {
+        let bucket_name = std::env::var("GCS_BUCKET_NAME")
+            .map_err(|_| anyhow::anyhow!("GCS_BUCKET_NAME not set in environment"))?;
+
+        let request = GetObjectRequest {
+            bucket: bucket_name.clone(),
+            object: object_name.to_string(),
+            ..Default::default()
+        };
+        match Self::client().await?.get_object(&request).await {
+            Ok(object) => Ok(Some(StoredObject {
+                url: format!("https://storage.googleapis.com/{}/{}", bucket_name, object_name),
+                size_bytes: object.size.max(0) as u64,
+            })),
+            Err(GcsError::Response(e)) if e.code == 404 => Ok(None),
+            Err(e) => Err(anyhow::anyhow!("Failed to look up {} in GCS: {}", object_name, e)),
+        }
+    }
+}
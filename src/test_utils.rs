@@ -0,0 +1,52 @@
+use sqlx::postgres::PgPoolOptions;
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::utils::TEST_PHONE_PREFIX;
+
+/// A small pool on `DATABASE_URL` for tests that need the database.
+pub async fn test_pool() -> PgPool {
+    dotenv::dotenv().ok();
+    PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&std::env::var("DATABASE_URL").expect("DATABASE_URL must be set"))
+        .await
+        .expect("Failed to create test database pool")
+}
+
+/// A random test number, using all nine digits the prefix leaves free so
+/// tests running in parallel don't collide.
+pub fn test_phone_number() -> String {
+    format!("{}{:09}", TEST_PHONE_PREFIX, rand::random::<u32>() % 1_000_000_000)
+}
+
+/// A user on a fresh test number, for tests to hang their data off.
+pub async fn insert_user(pool: &PgPool, scope: &str) -> Uuid {
+    sqlx::query!(
+        "INSERT INTO users (phone_number, public_key, scope) VALUES ($1, 'key', $2) RETURNING id",
+        test_phone_number(),
+        scope
+    )
+    .fetch_one(pool).await.unwrap().id
+}
+
+pub async fn insert_pet(pool: &PgPool, user_id: Uuid) -> Uuid {
+    sqlx::query!(
+        "INSERT INTO pets (user_id, name, breed, sex, birthday) VALUES ($1, 'Millie', 'Mutt', 'F', NOW()) RETURNING id",
+        user_id
+    )
+    .fetch_one(pool).await.unwrap().id
+}
+
+pub async fn insert_conversation(pool: &PgPool, client_id: Uuid, pet_id: Uuid, providers: &[Uuid]) -> Uuid {
+    sqlx::query!(
+        "INSERT INTO conversations (providers, client, pet) VALUES ($1, $2, $3) RETURNING id",
+        providers,
+        client_id,
+        pet_id
+    )
+    .fetch_one(pool).await.unwrap().id
+}
+
+pub async fn cleanup_users(pool: &PgPool, user_ids: &[Uuid]) {
+    sqlx::query!("DELETE FROM users WHERE id = ANY($1)", user_ids).execute(pool).await.unwrap();
+}
@@ -11,22 +11,63 @@ use rand::{thread_rng, Rng};
 use uuid::Uuid;
 use ed25519_dalek::{VerifyingKey, Signature};
 use serde_json::Value;
-use anyhow;
-use actix_web::HttpRequest;
 use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::OnceLock;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use sha2::{Digest, Sha256};
+use crate::config::Config;
+
+// Shared so Twilio requests reuse one connection pool instead of each paying for DNS and TLS
+fn twilio_client() -> &'static ReqwestClient {
+    static CLIENT: OnceLock<ReqwestClient> = OnceLock::new();
+    CLIENT.get_or_init(ReqwestClient::new)
+}
+
+const TWILIO_VERIFY_URL: &str = "https://verify.twilio.com/v2";
+
+// The URL of `resource` under the configured Verify service
+fn verify_service_url(base_url: &str, config: &Config, resource: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let service_sid = config.twilio_verify_service_sid.as_deref().ok_or("TWILIO_VERIFY_SERVICE_SID is not set")?;
+    Ok(format!("{}/Services/{}{}", base_url, service_sid, resource))
+}
+
+/// Fetches the Verify service's details. Does nothing useful beyond opening
+/// the connection and checking the credentials.
+pub async fn fetch_verify_service(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let account_sid = std::env::var("TWILIO_ACCOUNT_SID")?;
+    let auth_token = std::env::var("TWILIO_AUTH_TOKEN")?;
+
+    let url = verify_service_url(TWILIO_VERIFY_URL, config, "")?;
+    let response = twilio_client().get(&url)
+        .basic_auth(&account_sid, Some(&auth_token))
+        .send()
+        .await?;
 
-pub async fn send_verification_request(phone_number: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("Failed to fetch Verify service: {:?}", response.text().await?).into())
+    }
+}
+
+pub async fn send_verification_request(config: &Config, phone_number: &str) -> Result<(), Box<dyn std::error::Error>> {
+    post_verification(TWILIO_VERIFY_URL, config, phone_number).await
+}
+
+async fn post_verification(base_url: &str, config: &Config, phone_number: &str) -> Result<(), Box<dyn std::error::Error>> {
     let account_sid = std::env::var("TWILIO_ACCOUNT_SID")?;
     let auth_token = std::env::var("TWILIO_AUTH_TOKEN")?;
-    let service_sid = std::env::var("TWILIO_SERVICE_SID")?;
 
-    let client = ReqwestClient::new();
-    let url = format!("https://verify.twilio.com/v2/Services/{}/Verifications", service_sid);
+    let client = twilio_client();
+    let url = verify_service_url(base_url, config, "/Verifications")?;
 
     let response = client.post(&url)
         .basic_auth(&account_sid, Some(&auth_token))
         .form(&[
-            ("To", format!("+1{}", phone_number)),
+            ("To", phone_number.to_string()),
             ("Channel", "sms".to_string())
         ])
         .send()
@@ -39,18 +80,17 @@ pub async fn send_verification_request(phone_number: &str) -> Result<(), Box<dyn
     }
 }
 
-pub async fn check_verification_code(phone_number: &str, code: &str) -> Result<bool, Box<dyn std::error::Error>> {
+pub async fn check_verification_code(config: &Config, phone_number: &str, code: &str) -> Result<bool, Box<dyn std::error::Error>> {
     let account_sid = std::env::var("TWILIO_ACCOUNT_SID")?;
     let auth_token = std::env::var("TWILIO_AUTH_TOKEN")?;
-    let service_sid = std::env::var("TWILIO_SERVICE_SID")?;
 
-    let client = ReqwestClient::new();
-    let url = format!("https://verify.twilio.com/v2/Services/{}/VerificationCheck", service_sid);
+    let client = twilio_client();
+    let url = verify_service_url(TWILIO_VERIFY_URL, config, "/VerificationCheck")?;
 
     let response = client.post(&url)
         .basic_auth(&account_sid, Some(&auth_token))
         .form(&[
-            ("To", format!("+1{}", phone_number)),
+            ("To", phone_number.to_string()),
             ("Code", code.to_string())
         ])
         .send()
@@ -64,6 +104,198 @@ pub async fn check_verification_code(phone_number: &str, code: &str) -> Result<b
     }
 }
 
+/// Who outbound texts come from: a Messaging Service, a number or sender ID,
+/// or a sender within a Messaging Service.
+#[derive(Clone, Debug, Default)]
+pub struct SmsSender {
+    pub messaging_service_sid: Option<String>,
+    pub from_number: Option<String>,
+}
+
+impl SmsSender {
+    pub fn from_config(config: &Config) -> Self {
+        SmsSender {
+            messaging_service_sid: config.twilio_messaging_service_sid.clone(),
+            from_number: config.twilio_from_number.clone(),
+        }
+    }
+}
+
+pub async fn send_sms(sender: &SmsSender, phone_number: &str, body: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let account_sid = std::env::var("TWILIO_ACCOUNT_SID")?;
+    let auth_token = std::env::var("TWILIO_AUTH_TOKEN")?;
+
+    let client = twilio_client();
+    let url = format!("https://api.twilio.com/2010-04-01/Accounts/{}/Messages.json", account_sid);
+
+    let mut form = vec![
+        ("To", phone_number.to_string()),
+        ("Body", body.to_string()),
+    ];
+    if sender.messaging_service_sid.is_none() && sender.from_number.is_none() {
+        return Err("Set TWILIO_MESSAGING_SERVICE_SID or TWILIO_FROM_NUMBER to send SMS".into());
+    }
+    if let Some(sid) = &sender.messaging_service_sid {
+        form.push(("MessagingServiceSid", sid.clone()));
+    }
+    if let Some(from) = &sender.from_number {
+        form.push(("From", from.clone()));
+    }
+
+    let response = client.post(&url)
+        .basic_auth(&account_sid, Some(&auth_token))
+        .form(&form)
+        .send()
+        .await?;
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("Failed to send SMS: {:?}", response.text().await?).into())
+    }
+}
+
+/// The `X-Twilio-Signature` Twilio sends with a webhook: HMAC-SHA1 of the full
+/// URL followed by each POST parameter's name and value, sorted by name.
+pub fn twilio_signature(auth_token: &str, url: &str, params: &BTreeMap<String, String>) -> Result<String, openssl::error::ErrorStack> {
+    let mut payload = url.to_string();
+    for (name, value) in params {
+        payload.push_str(name);
+        payload.push_str(value);
+    }
+    let key = PKey::hmac(auth_token.as_bytes())?;
+    let mut signer = Signer::new(MessageDigest::sha1(), &key)?;
+    signer.update(payload.as_bytes())?;
+    Ok(general_purpose::STANDARD.encode(signer.sign_to_vec()?))
+}
+
+pub fn verify_twilio_signature(auth_token: &str, url: &str, params: &BTreeMap<String, String>, signature: &str) -> bool {
+    match twilio_signature(auth_token, url, params) {
+        Ok(expected) => expected.len() == signature.len() && openssl::memcmp::eq(expected.as_bytes(), signature.as_bytes()),
+        Err(_) => false,
+    }
+}
+
+/// National numbers starting with this are for testing: they're never texted,
+/// and accept the verification code 123456.
+pub const TEST_PHONE_PREFIX: &str = "000123";
+
+/// Which country a number without a `+` is from, when the client doesn't say.
+pub const DEFAULT_COUNTRY_CODE: &str = "US";
+
+// How a country's numbers are written without the `+`
+struct NationalFormat {
+    country_code: &'static str,
+    dialing_code: &'static str,
+    // Dialled before the national number at home, and dropped from E.164
+    trunk_prefix: &'static str,
+    lengths: std::ops::RangeInclusive<usize>,
+}
+
+const NATIONAL_FORMATS: &[NationalFormat] = &[
+    NationalFormat { country_code: "US", dialing_code: "1", trunk_prefix: "1", lengths: 10..=10 },
+    NationalFormat { country_code: "CA", dialing_code: "1", trunk_prefix: "1", lengths: 10..=10 },
+    NationalFormat { country_code: "GB", dialing_code: "44", trunk_prefix: "0", lengths: 9..=10 },
+    NationalFormat { country_code: "IE", dialing_code: "353", trunk_prefix: "0", lengths: 7..=9 },
+    NationalFormat { country_code: "AU", dialing_code: "61", trunk_prefix: "0", lengths: 9..=9 },
+];
+
+/// Why a phone number was refused.
+#[derive(Debug, PartialEq)]
+pub enum PhoneNumberError {
+    Empty,
+    InvalidCharacter(char),
+    InvalidCountryCode,
+    MissingCountryCode,
+    UnsupportedCountry,
+    WrongLength,
+}
+
+impl fmt::Display for PhoneNumberError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PhoneNumberError::Empty => write!(f, "phone number is empty"),
+            PhoneNumberError::InvalidCharacter(c) => write!(f, "phone number contains '{}'", c),
+            PhoneNumberError::InvalidCountryCode => write!(f, "country codes don't start with 0"),
+            PhoneNumberError::MissingCountryCode => write!(f, "phone number is too long for its country; include + and the country code"),
+            PhoneNumberError::UnsupportedCountry => write!(f, "unsupported country_code; include + and the country code instead"),
+            PhoneNumberError::WrongLength => write!(f, "phone number has the wrong number of digits"),
+        }
+    }
+}
+
+/// Canonicalizes a phone number to the E.164 form it is stored and texted in,
+/// e.g. `+15551234567`. Spaces, dashes, dots and parentheses are ignored.
+/// Without a leading `+` the number is read as a national number in
+/// `country_code` (ISO 3166, e.g. "GB"), with or without its trunk prefix.
+/// US and Canadian test numbers are kept as bare digits.
+pub fn normalize_phone_number(phone_number: &str, country_code: &str) -> Result<String, PhoneNumberError> {
+    let trimmed = phone_number.trim();
+    let (international, rest) = match trimmed.strip_prefix('+') {
+        Some(rest) => (true, rest),
+        None => (false, trimmed),
+    };
+    let mut digits = String::new();
+    for c in rest.chars() {
+        match c {
+            '0'..='9' => digits.push(c),
+            ' ' | '-' | '.' | '(' | ')' => {},
+            _ => return Err(PhoneNumberError::InvalidCharacter(c)),
+        }
+    }
+    if digits.is_empty() {
+        return Err(PhoneNumberError::Empty);
+    }
+
+    if international {
+        if digits.starts_with('0') {
+            return Err(PhoneNumberError::InvalidCountryCode);
+        }
+        // E.164 allows at most 15 digits; North American numbers have exactly 11
+        let valid_length = match digits.starts_with('1') {
+            true => digits.len() == 11,
+            false => (8..=15).contains(&digits.len()),
+        };
+        return match valid_length {
+            true => Ok(format!("+{}", digits)),
+            false => Err(PhoneNumberError::WrongLength),
+        };
+    }
+
+    let format = NATIONAL_FORMATS.iter()
+        .find(|format| format.country_code.eq_ignore_ascii_case(country_code))
+        .ok_or(PhoneNumberError::UnsupportedCountry)?;
+    if digits.starts_with(TEST_PHONE_PREFIX) {
+        return match format.dialing_code {
+            "1" if (10..=15).contains(&digits.len()) => Ok(digits),
+            "1" => Err(PhoneNumberError::WrongLength),
+            dialing_code if digits.len() >= 10 && dialing_code.len() + digits.len() <= 15 => Ok(format!("+{}{}", dialing_code, digits)),
+            _ => Err(PhoneNumberError::WrongLength),
+        };
+    }
+    let national = match digits.strip_prefix(format.trunk_prefix) {
+        Some(national) if format.lengths.contains(&national.len()) => national,
+        _ => digits.as_str(),
+    };
+    if format.lengths.contains(&national.len()) {
+        Ok(format!("+{}{}", format.dialing_code, national))
+    } else if national.len() < *format.lengths.start() {
+        Err(PhoneNumberError::WrongLength)
+    } else {
+        Err(PhoneNumberError::MissingCountryCode)
+    }
+}
+
+/// Whether a stored number is a test number, with or without its country code.
+pub fn is_test_phone_number(phone_number: &str) -> bool {
+    match phone_number.strip_prefix('+') {
+        Some(international) => NATIONAL_FORMATS.iter().any(|format| {
+            international.strip_prefix(format.dialing_code).is_some_and(|national| national.starts_with(TEST_PHONE_PREFIX))
+        }),
+        None => phone_number.starts_with(TEST_PHONE_PREFIX),
+    }
+}
+
 pub fn is_timestamp_valid(timestamp: &str) -> bool {
     let now = Utc::now();
     match DateTime::parse_from_rfc3339(timestamp) {
@@ -75,14 +307,20 @@ pub fn is_timestamp_valid(timestamp: &str) -> bool {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,  // user id
     pub iss: String,  // issuer
     pub aud: String,  // audience
     pub exp: usize,   // expiration time
     pub iat: usize,   // issued at
-    pub scope: String, // user scope (client or provider)
+    pub scope: String, // user scope (client, provider, admin, or service)
+    // Refresh token family (device login) the token was issued for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fam: Option<Uuid>,
+    // Identifies the token so it can be revoked; missing from older tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<Uuid>,
 }
 
 impl Claims {
@@ -93,28 +331,149 @@ impl Claims {
     pub fn get_sub(&self) -> &str {
         &self.sub
     }
+
+    pub fn get_family(&self) -> Option<Uuid> {
+        self.fam
+    }
+
+    pub fn get_jti(&self) -> Option<Uuid> {
+        self.jti
+    }
+
+    /// Claims for a request authenticated with a service account's API key. They
+    /// are never signed into a token.
+    pub fn for_service_account(provider_id: Uuid) -> Claims {
+        let now = Utc::now();
+        Claims {
+            sub: provider_id.to_string(),
+            iss: "VeterinaryText".to_string(),
+            aud: "VeterinaryText".to_string(),
+            exp: (now + Duration::minutes(1)).timestamp() as usize,
+            iat: now.timestamp() as usize,
+            scope: "service".to_string(),
+            fam: None,
+            jti: None,
+        }
+    }
 }
 
-pub fn generate_signed_encrypted_token(user_id: Uuid, user_scope: &str) -> Result<(String, usize), Box<dyn std::error::Error>> {
-    // Load keys from environment variables
-    let jwt_private_key_pem_base64 = env::var("JWT_PRIVATE_KEY")
-        .map_err(|e| format!("Failed to get JWT_PRIVATE_KEY from env: {}", e))?;
-    let encryption_key_base64 = env::var("ENCRYPTION_KEY")
-        .map_err(|e| format!("Failed to get ENCRYPTION_KEY from env: {}", e))?;
+/// The keys access tokens are signed and encrypted with, read once at startup.
+/// New tokens always use the primary keys. Keys that have been rotated out are
+/// kept to verify and decrypt the tokens issued under them until those expire.
+pub struct KeyRing {
+    signing_key: EncodingKey,
+    signing_kid: String,
+    // Primary first
+    verifying_keys: Vec<(String, DecodingKey)>,
+    encryption_keys: Vec<Vec<u8>>,
+}
+
+impl KeyRing {
+    /// Keys from the environment. `JWT_PRIVATE_KEY` is the primary signing key,
+    /// and its public key is always accepted. `JWT_PUBLIC_KEYS` lists the public
+    /// keys of signing keys rotated out (`JWT_PUBLIC_KEY` is read if it's unset).
+    /// `ENCRYPTION_KEYS` lists the encryption keys, primary first, falling back
+    /// to `ENCRYPTION_KEY`. All are base64 and the lists are comma-separated.
+    pub fn from_env() -> Result<KeyRing, TokenError> {
+        let private_key = env::var("JWT_PRIVATE_KEY")
+            .map_err(|e| TokenError::Keys(format!("Failed to get JWT_PRIVATE_KEY from env: {}", e)))?;
+        let public_keys = env::var("JWT_PUBLIC_KEYS").or_else(|_| env::var("JWT_PUBLIC_KEY")).unwrap_or_default();
+        let encryption_keys = env::var("ENCRYPTION_KEYS").or_else(|_| env::var("ENCRYPTION_KEY"))
+            .map_err(|e| TokenError::Keys(format!("Failed to get ENCRYPTION_KEYS or ENCRYPTION_KEY from env: {}", e)))?;
+
+        let decode_list = |name: &str, value: &str| -> Result<Vec<Vec<u8>>, TokenError> {
+            value.split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(|key| general_purpose::STANDARD.decode(key)
+                    .map_err(|e| TokenError::Keys(format!("Failed to base64 decode {}: {}", name, e))))
+                .collect()
+        };
+        let private_key = decode_list("JWT_PRIVATE_KEY", &private_key)?.pop()
+            .ok_or_else(|| TokenError::Keys("JWT_PRIVATE_KEY is empty".to_string()))?;
+        KeyRing::new(&private_key, &decode_list("JWT_PUBLIC_KEYS", &public_keys)?, decode_list("ENCRYPTION_KEYS", &encryption_keys)?)
+    }
+
+    /// A key ring signing with `private_key_pem`, also accepting the older
+    /// `public_key_pems`, and encrypting with the first of `encryption_keys`.
+    pub fn new(private_key_pem: &[u8], public_key_pems: &[Vec<u8>], encryption_keys: Vec<Vec<u8>>) -> Result<KeyRing, TokenError> {
+        let signing_key = EncodingKey::from_ec_pem(private_key_pem)
+            .map_err(|e| TokenError::Keys(format!("Failed to read JWT_PRIVATE_KEY: {}", e)))?;
+        let primary_public_key = PKey::private_key_from_pem(private_key_pem)
+            .and_then(|key| key.public_key_to_pem())
+            .map_err(|e| TokenError::Keys(format!("Failed to read JWT_PRIVATE_KEY: {}", e)))?;
+
+        let mut verifying_keys: Vec<(String, DecodingKey)> = Vec::new();
+        for pem in std::iter::once(&primary_public_key).chain(public_key_pems) {
+            let kid = key_id(pem)?;
+            if verifying_keys.iter().any(|(id, _)| *id == kid) {
+                continue;
+            }
+            let key = DecodingKey::from_ec_pem(pem)
+                .map_err(|e| TokenError::Keys(format!("Failed to read JWT public key: {}", e)))?;
+            verifying_keys.push((kid, key));
+        }
+
+        if encryption_keys.is_empty() {
+            return Err(TokenError::Keys("No encryption keys are configured".to_string()));
+        }
+        if encryption_keys.iter().any(|key| key.len() != 32) {
+            return Err(TokenError::Keys("Encryption keys must be 32 bytes".to_string()));
+        }
+
+        Ok(KeyRing {
+            signing_key,
+            signing_kid: verifying_keys[0].0.clone(),
+            verifying_keys,
+            encryption_keys,
+        })
+    }
 
-    // Base64 decode the PEM key
-    let jwt_private_key_pem_bytes = general_purpose::STANDARD.decode(&jwt_private_key_pem_base64)
-        .map_err(|e| format!("Failed to base64 decode JWT_PRIVATE_KEY: {}", e))?;
+    /// Signs `claims` with the primary key, naming it in the `kid` header, and
+    /// encrypts the result with the primary encryption key.
+    fn seal(&self, claims: &Claims) -> Result<String, Box<dyn std::error::Error>> {
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(self.signing_kid.clone());
+        let token = encode(&header, claims, &self.signing_key)
+            .map_err(|e| format!("Failed to encode JWT: {}", e))?;
+        encrypt_token(&self.encryption_keys[0], &token)
+    }
 
-    let jwt_private_key_pem = String::from_utf8(jwt_private_key_pem_bytes)
-        .map_err(|e| format!("Failed to convert JWT_PRIVATE_KEY to string: {}", e))?;
+    fn open(&self, encrypted_token: &str, validate_exp: bool) -> Result<Claims, TokenError> {
+        let token = self.encryption_keys.iter()
+            .find_map(|key| decrypt_token(key, encrypted_token).ok())
+            .ok_or(TokenError::Undecryptable)?;
+
+        // Tokens from before keys had ids don't name one, so any of ours may have signed them
+        let kid = jsonwebtoken::decode_header(&token).map_err(TokenError::Invalid)?.kid;
+        let mut validation = Validation::new(Algorithm::ES256);
+        validation.validate_exp = validate_exp;
+        let mut refusal = TokenError::Invalid(jsonwebtoken::errors::ErrorKind::InvalidSignature.into());
+        for (_, key) in self.verifying_keys.iter().filter(|(id, _)| kid.as_ref().is_none_or(|kid| kid == id)) {
+            match decode::<Claims>(&token, key, &validation) {
+                Ok(token_data) => return Ok(token_data.claims),
+                // Only reported once the signature checks out
+                Err(e) if matches!(e.kind(), jsonwebtoken::errors::ErrorKind::ExpiredSignature) => {
+                    return Err(TokenError::Expired);
+                },
+                Err(e) => refusal = TokenError::Invalid(e),
+            }
+        }
+        Err(refusal)
+    }
+}
 
-    // Base64 decode the encryption key
-    let encryption_key_bytes = general_purpose::STANDARD.decode(&encryption_key_base64)
-        .map_err(|e| format!("Failed to base64 decode ENCRYPTION_KEY: {}", e))?;
+// Names a public key by its fingerprint, so key ids never need configuring
+fn key_id(public_key_pem: &[u8]) -> Result<String, TokenError> {
+    let der = PKey::public_key_from_pem(public_key_pem)
+        .and_then(|key| key.public_key_to_der())
+        .map_err(|e| TokenError::Keys(format!("Failed to read JWT public key: {}", e)))?;
+    Ok(hex::encode(&Sha256::digest(der)[..8]))
+}
 
+pub fn generate_signed_encrypted_token(keys: &KeyRing, user_id: Uuid, user_scope: &str, family_id: Option<Uuid>) -> Result<(String, usize), Box<dyn std::error::Error>> {
     // Define expiration time
-    let expiration = (Utc::now() + Duration::days(1)).timestamp() as usize;
+    let expiration = (Utc::now() + access_token_ttl(user_scope)).timestamp() as usize;
 
     // Create the claims
     let claims = Claims {
@@ -124,23 +483,66 @@ pub fn generate_signed_encrypted_token(user_id: Uuid, user_scope: &str) -> Resul
         exp: expiration,
         iat: Utc::now().timestamp() as usize,
         scope: user_scope.to_string(),
+        fam: family_id,
+        jti: Some(Uuid::new_v4()),
     };
 
-    // Sign the JWT
-    let header = Header::new(Algorithm::ES256);
-    let encoding_key = EncodingKey::from_ec_pem(jwt_private_key_pem.as_bytes())
-        .map_err(|e| format!("Failed to create encoding key from JWT_PRIVATE_KEY: {}", e))?;
-    let token = encode(&header, &claims, &encoding_key)
-        .map_err(|e| format!("Failed to encode JWT: {}", e))?;
-
-    // Encrypt the signed token
-    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&encryption_key_bytes));
-    let nonce = Nonce::from_slice(&[0u8; 12]); // For testing, fixed nonce is acceptable
-    let ciphertext = cipher.encrypt(nonce, token.as_bytes())
+    // Sign and encrypt the token and return it with its expiration
+    Ok((keys.seal(&claims)?, expiration))
+}
+
+const DEFAULT_ACCESS_TOKEN_TTL_SECONDS: i64 = 24 * 60 * 60;
+
+/// How long an access token for `scope` lasts: `<SCOPE>_ACCESS_TOKEN_TTL_SECONDS`
+/// (e.g. `PROVIDER_ACCESS_TOKEN_TTL_SECONDS`), else `ACCESS_TOKEN_TTL_SECONDS`,
+/// else a day. Unset, unparseable and non-positive values are skipped.
+fn access_token_ttl(scope: &str) -> Duration {
+    let seconds = |key: &str| env::var(key).ok()
+        .and_then(|value| value.trim().parse::<i64>().ok())
+        .filter(|seconds| *seconds > 0);
+    let ttl = seconds(&format!("{}_ACCESS_TOKEN_TTL_SECONDS", scope.to_uppercase()))
+        .or_else(|| seconds("ACCESS_TOKEN_TTL_SECONDS"))
+        .unwrap_or(DEFAULT_ACCESS_TOKEN_TTL_SECONDS);
+    Duration::seconds(ttl)
+}
+
+// AES-GCM nonces are 96 bits
+const NONCE_LENGTH: usize = 12;
+
+/// Encrypts a signed token under a fresh random nonce, which is prepended to
+/// the ciphertext before the whole is base64 encoded.
+fn encrypt_token(encryption_key: &[u8], token: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(encryption_key));
+    let mut nonce = [0u8; NONCE_LENGTH];
+    thread_rng().fill(&mut nonce);
+    let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce), token.as_bytes())
         .map_err(|e| format!("Encryption error: {:?}", e))?;
 
-    // Base64 encode the encrypted token and return with expiration
-    Ok((general_purpose::URL_SAFE_NO_PAD.encode(ciphertext), expiration))
+    let mut encrypted = nonce.to_vec();
+    encrypted.extend_from_slice(&ciphertext);
+    Ok(general_purpose::URL_SAFE_NO_PAD.encode(encrypted))
+}
+
+/// Reverses `encrypt_token`. Tokens issued before nonces were random were
+/// encrypted under an all-zero nonce with none prepended; those still decrypt
+/// until they expire.
+fn decrypt_token(encryption_key: &[u8], encrypted_token: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let encrypted = general_purpose::URL_SAFE_NO_PAD.decode(encrypted_token)?;
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(encryption_key));
+
+    // GCM authenticates the nonce too, so a token only decrypts under the one it was made with
+    let current = (encrypted.len() > NONCE_LENGTH)
+        .then(|| {
+            let (nonce, ciphertext) = encrypted.split_at(NONCE_LENGTH);
+            cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()
+        })
+        .flatten();
+    let token = match current {
+        Some(token) => token,
+        None => cipher.decrypt(Nonce::from_slice(&[0u8; NONCE_LENGTH]), encrypted.as_ref())
+            .map_err(|_| "Token could not be decrypted; sign in again")?,
+    };
+    Ok(String::from_utf8(token)?)
 }
 
 pub fn generate_refresh_token() -> String {
@@ -169,11 +571,6 @@ pub fn verify_signature<T: Serialize>(
 
     // Serialize the data with sorted keys
     let stringified_data = to_canonical_json(&data_value);
-    
-    // Debug logging
-    println!("DEBUG: Canonical JSON: {}", stringified_data);
-    println!("DEBUG: Signature: {}", signature);
-    println!("DEBUG: Public key: {}", public_key);
 
     // Decode the base64 signature
     let signature_bytes = base64::engine::general_purpose::STANDARD.decode(signature)?;
@@ -195,84 +592,340 @@ pub fn verify_signature<T: Serialize>(
     Ok(())
 }
 
+/// Serializes a JSON value with object keys sorted at every depth and no
+/// insignificant whitespace, matching what clients sign.
 pub fn to_canonical_json(value: &Value) -> String {
     match value {
         Value::Object(map) => {
-            let mut btree_map = BTreeMap::new();
-            for (k, v) in map {
-                btree_map.insert(k.clone(), v.clone());
-            }
-            serde_json::to_string(&btree_map).unwrap()
+            let sorted: BTreeMap<&String, &Value> = map.iter().collect();
+            let fields: Vec<String> = sorted
+                .into_iter()
+                .map(|(k, v)| format!("{}:{}", serde_json::to_string(k).unwrap(), to_canonical_json(v)))
+                .collect();
+            format!("{{{}}}", fields.join(","))
         }
         Value::Array(arr) => {
-            let serialized_arr: Vec<Value> = arr.iter().cloned().collect();
-            serde_json::to_string(&serialized_arr).unwrap()
+            let items: Vec<String> = arr.iter().map(to_canonical_json).collect();
+            format!("[{}]", items.join(","))
         }
         _ => serde_json::to_string(value).unwrap(),
     }
 }
 
+/// Why a token was refused.
+#[derive(Debug)]
+pub enum TokenError {
+    /// Not a token we encrypted, or one that was tampered with.
+    Undecryptable,
+    Expired,
+    /// Decrypted, but its signature or claims don't check out.
+    Invalid(jsonwebtoken::errors::Error),
+    /// The server's keys are missing or unreadable; the token may be fine.
+    Keys(String),
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::Undecryptable => write!(f, "Token could not be decrypted; sign in again"),
+            TokenError::Expired => write!(f, "Token has expired"),
+            TokenError::Invalid(e) => write!(f, "Invalid token: {}", e),
+            TokenError::Keys(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
 pub fn verify_and_decode_token(
+    keys: &KeyRing,
     encrypted_token: &str,
-) -> Result<Claims, Box<dyn std::error::Error>> {
-    // Load keys from environment variables
-    let jwt_public_key_pem_base64 = env::var("JWT_PUBLIC_KEY")
-        .map_err(|e| format!("Failed to get JWT_PUBLIC_KEY from env: {}", e))?;
-    let encryption_key_base64 = env::var("ENCRYPTION_KEY")
-        .map_err(|e| format!("Failed to get ENCRYPTION_KEY from env: {}", e))?;
-
-    // Base64 decode the PEM key
-    let jwt_public_key_pem_bytes = general_purpose::STANDARD.decode(&jwt_public_key_pem_base64)
-        .map_err(|e| format!("Failed to base64 decode JWT_PUBLIC_KEY: {}", e))?;
-
-    let jwt_public_key_pem = String::from_utf8(jwt_public_key_pem_bytes)
-        .map_err(|e| format!("Failed to convert JWT_PUBLIC_KEY to string: {}", e))?;
-
-    // Base64 decode the encryption key
-    let encryption_key_bytes = general_purpose::STANDARD.decode(&encryption_key_base64)
-        .map_err(|e| format!("Failed to base64 decode ENCRYPTION_KEY: {}", e))?;
-
-    // Base64 decode the encrypted token
-    let ciphertext = general_purpose::URL_SAFE_NO_PAD.decode(encrypted_token)?;
-
-    // Decrypt the token
-    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&encryption_key_bytes));
-    let nonce = Nonce::from_slice(&[0u8; 12]); // Use the same fixed nonce as in encryption
-    let token = cipher.decrypt(nonce, ciphertext.as_ref())
-        .map_err(|e| format!("Decryption error: {:?}", e))?;
-    let token = String::from_utf8(token)?;
-
-    // Decode and verify the JWT
-    let decoding_key = DecodingKey::from_ec_pem(jwt_public_key_pem.as_bytes())?;
-    let validation = Validation::new(Algorithm::ES256);
-    let token_data = decode::<Claims>(&token, &decoding_key, &validation)?;
-
-    Ok(token_data.claims)
-}
-
-pub fn extract_user_id_from_token(req: &HttpRequest) -> Result<Uuid, anyhow::Error> {
-    // Extract the token from the Authorization header
-    let token = match req.headers().get("Authorization") {
-        Some(value) => {
-            let parts: Vec<&str> = value.to_str().unwrap_or("").split_whitespace().collect();
-            if parts.len() == 2 && parts[0] == "Bearer" {
-                parts[1]
-            } else {
-                return Err(anyhow::anyhow!("Invalid Authorization header"));
-            }
+) -> Result<Claims, TokenError> {
+    keys.open(encrypted_token, true)
+}
+
+/// Decrypts a token and checks its signature but not its expiry, so support can
+/// see why an expired token was rejected. Never use this for authentication.
+pub fn inspect_token(
+    keys: &KeyRing,
+    encrypted_token: &str,
+) -> Result<Claims, TokenError> {
+    keys.open(encrypted_token, false)
+}
+
+/// Throwaway token keys for tests, since the real ones aren't set there.
+#[cfg(test)]
+pub mod test_keys {
+    use super::*;
+
+    /// A new signing key pair, as PEM private and public keys.
+    pub fn signing_key_pair() -> (Vec<u8>, Vec<u8>) {
+        let group = openssl::ec::EcGroup::from_curve_name(openssl::nid::Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(openssl::ec::EcKey::generate(&group).unwrap()).unwrap();
+        (key.private_key_to_pem_pkcs8().unwrap(), key.public_key_to_pem().unwrap())
+    }
+
+    /// The key ring tests share, so they can run in parallel, as the app holds it.
+    pub fn key_ring() -> actix_web::web::Data<KeyRing> {
+        static KEY_RING: OnceLock<actix_web::web::Data<KeyRing>> = OnceLock::new();
+        KEY_RING.get_or_init(|| {
+            actix_web::web::Data::new(KeyRing::new(&signing_key_pair().0, &[], vec![vec![7u8; 32]]).unwrap())
+        }).clone()
+    }
+
+    /// A token carrying `claims` as they are, e.g. one that has already expired.
+    pub fn token_for(claims: &Claims) -> String {
+        key_ring().seal(claims).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    // A stand-in Twilio that accepts one request and reports its request line
+    fn mock_twilio() -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/v2", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request_line = String::new();
+            BufReader::new(&stream).read_line(&mut request_line).unwrap();
+            sender.send(request_line.trim_end().to_string()).unwrap();
+            stream.write_all(b"HTTP/1.1 201 Created\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}").unwrap();
+        });
+        (base_url, receiver)
+    }
+
+    const TEST_KEY: [u8; 32] = [7; 32];
+
+    #[test]
+    fn tokens_round_trip_under_random_nonces() {
+        let first = encrypt_token(&TEST_KEY, "header.claims.signature").unwrap();
+        let second = encrypt_token(&TEST_KEY, "header.claims.signature").unwrap();
+        assert_ne!(first, second);
+        assert_eq!(decrypt_token(&TEST_KEY, &first).unwrap(), "header.claims.signature");
+        assert_eq!(decrypt_token(&TEST_KEY, &second).unwrap(), "header.claims.signature");
+        assert!(decrypt_token(&[8; 32], &first).is_err());
+    }
+
+    #[test]
+    fn tampered_nonces_are_rejected() {
+        let token = encrypt_token(&TEST_KEY, "header.claims.signature").unwrap();
+        let mut bytes = general_purpose::URL_SAFE_NO_PAD.decode(&token).unwrap();
+        bytes[0] ^= 1;
+        let tampered = general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+        let error = decrypt_token(&TEST_KEY, &tampered).unwrap_err();
+        assert_eq!(error.to_string(), "Token could not be decrypted; sign in again");
+    }
+
+    #[test]
+    fn legacy_zero_nonce_tokens_still_decrypt() {
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&TEST_KEY));
+        let ciphertext = cipher.encrypt(Nonce::from_slice(&[0u8; 12]), b"header.claims.signature".as_ref()).unwrap();
+        let legacy = general_purpose::URL_SAFE_NO_PAD.encode(ciphertext);
+        assert_eq!(decrypt_token(&TEST_KEY, &legacy).unwrap(), "header.claims.signature");
+    }
+
+    #[test]
+    fn access_token_lifetime_follows_the_environment() {
+        let keys = test_keys::key_ring();
+
+        let expires_in = |scope: &str| {
+            let (token, expiration) = generate_signed_encrypted_token(&keys, Uuid::new_v4(), scope, None).unwrap();
+            assert_eq!(verify_and_decode_token(&keys, &token).unwrap().exp, expiration);
+            expiration as i64 - Utc::now().timestamp()
+        };
+
+        std::env::set_var("ACCESS_TOKEN_TTL_SECONDS", "7200");
+        assert!((7198..=7200).contains(&expires_in("client")));
+
+        // A scope's own setting wins, unless it can't be used
+        std::env::set_var("INTEGRATION_ACCESS_TOKEN_TTL_SECONDS", "900");
+        assert!((898..=900).contains(&expires_in("integration")));
+        std::env::set_var("INTEGRATION_ACCESS_TOKEN_TTL_SECONDS", "a while");
+        assert!((7198..=7200).contains(&expires_in("integration")));
+
+        std::env::set_var("ACCESS_TOKEN_TTL_SECONDS", "0");
+        assert!((86398..=86400).contains(&expires_in("client")));
+        std::env::remove_var("ACCESS_TOKEN_TTL_SECONDS");
+    }
+
+    #[test]
+    fn tokens_issued_before_a_rotation_still_validate() {
+        let ((private_a, public_a), (private_b, _)) = (test_keys::signing_key_pair(), test_keys::signing_key_pair());
+        let (encryption_a, encryption_b) = (vec![1u8; 32], vec![2u8; 32]);
+        let user_id = Uuid::new_v4();
+
+        let before = KeyRing::new(&private_a, &[], vec![encryption_a.clone()]).unwrap();
+        let (old_token, _) = generate_signed_encrypted_token(&before, user_id, "client", None).unwrap();
+
+        // B is now primary, with A kept for the tokens already out there
+        let after = KeyRing::new(&private_b, &[public_a], vec![encryption_b.clone(), encryption_a.clone()]).unwrap();
+        assert_eq!(verify_and_decode_token(&after, &old_token).unwrap().sub, user_id.to_string());
+        let (new_token, _) = generate_signed_encrypted_token(&after, user_id, "client", None).unwrap();
+        assert_eq!(verify_and_decode_token(&after, &new_token).unwrap().sub, user_id.to_string());
+        assert!(matches!(verify_and_decode_token(&before, &new_token), Err(TokenError::Undecryptable)));
+
+        // Once A is dropped its tokens stop working
+        let without_encryption_a = KeyRing::new(&private_b, &[], vec![encryption_b]).unwrap();
+        assert!(matches!(verify_and_decode_token(&without_encryption_a, &old_token), Err(TokenError::Undecryptable)));
+        let without_signing_a = KeyRing::new(&private_b, &[], vec![vec![2u8; 32], encryption_a]).unwrap();
+        assert!(matches!(verify_and_decode_token(&without_signing_a, &old_token), Err(TokenError::Invalid(_))));
+    }
+
+    #[test]
+    fn key_rings_refuse_unusable_encryption_keys() {
+        let (private_key, _) = test_keys::signing_key_pair();
+        for encryption_keys in [vec![], vec![vec![1u8; 16]]] {
+            assert!(matches!(KeyRing::new(&private_key, &[], encryption_keys), Err(TokenError::Keys(_))));
         }
-        None => return Err(anyhow::anyhow!("Missing Authorization header")),
-    };
+    }
 
-    // Verify and decode the token
-    let claims = match verify_and_decode_token(token) {
-        Ok(claims) => claims,
-        Err(e) => return Err(anyhow::anyhow!("Token verification failed: {}", e)),
-    };
-    
-    // Extract the user_id from the token
-    let user_id = Uuid::parse_str(claims.get_sub())
-        .map_err(|_| anyhow::anyhow!("Invalid user ID in token"))?;
-    
-    Ok(user_id)
+    #[actix_web::test]
+    async fn verification_goes_to_the_configured_service() {
+        std::env::set_var("TWILIO_ACCOUNT_SID", "AC00000000000000000000000000000000");
+        std::env::set_var("TWILIO_AUTH_TOKEN", "test-auth-token");
+        let config = Config {
+            twilio_verify_service_sid: Some("VA0123456789abcdef0123456789abcdef".to_string()),
+            ..Config::default()
+        };
+
+        let (base_url, request_line) = mock_twilio();
+        post_verification(&base_url, &config, "5551234567").await.unwrap();
+        assert_eq!(
+            request_line.recv().unwrap(),
+            "POST /v2/Services/VA0123456789abcdef0123456789abcdef/Verifications HTTP/1.1"
+        );
+
+        // Without a service there's nothing to send through
+        assert!(post_verification(&base_url, &Config::default(), "5551234567").await.is_err());
+    }
+
+    #[test]
+    fn sender_settings_are_checked_at_startup() {
+        assert!(Config::default().validate_twilio_sender().is_ok());
+        let with = |verify: Option<&str>, messaging: Option<&str>, from: Option<&str>| Config {
+            twilio_verify_service_sid: verify.map(str::to_string),
+            twilio_messaging_service_sid: messaging.map(str::to_string),
+            twilio_from_number: from.map(str::to_string),
+            ..Config::default()
+        };
+
+        assert!(with(Some("VA0123456789abcdef0123456789abcdef"), Some("MG0123456789abcdef0123456789abcdef"), Some("+15551234567"))
+            .validate_twilio_sender()
+            .is_ok());
+        assert!(with(None, None, Some("VetText")).validate_twilio_sender().is_ok());
+        assert!(with(Some("MG0123456789abcdef0123456789abcdef"), None, None).validate_twilio_sender().is_err());
+        assert!(with(None, Some("VA0123456789abcdef0123456789abcdef"), None).validate_twilio_sender().is_err());
+        assert!(with(None, None, Some("5551234567")).validate_twilio_sender().is_err());
+        assert!(with(None, None, Some("VetText Clinic")).validate_twilio_sender().is_err());
+    }
+
+    #[test]
+    fn nested_payloads_canonicalize_with_sorted_keys_at_every_depth() {
+        let payload = serde_json::json!({
+            "user_id": "b3c1",
+            "details": {"weight": 12.5, "breed": "Beagle", "owner": {"phone": "5551234567", "name": "Sam"}},
+            "tags": [{"z": 1, "a": 2}, "senior", null],
+            "timestamp": 1700000000000i64
+        });
+        assert_eq!(
+            to_canonical_json(&payload),
+            r#"{"details":{"breed":"Beagle","owner":{"name":"Sam","phone":"5551234567"},"weight":12.5},"tags":[{"a":2,"z":1},"senior",null],"timestamp":1700000000000,"user_id":"b3c1"}"#
+        );
+    }
+
+    #[test]
+    fn signatures_over_nested_payloads_verify() {
+        use ed25519_dalek::Signer;
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(&thread_rng().gen());
+        let public_key = general_purpose::STANDARD.encode(signing_key.verifying_key().as_bytes());
+
+        // The client signs its own key order; the server re-serializes after parsing
+        let signed = r#"{"tags":["senior",{"z":1,"a":2}],"details":{"owner":{"name":"Sam","phone":"5551234567"},"breed":"Beagle"},"timestamp":1700000000000}"#;
+        let client_value: Value = serde_json::from_str(signed).unwrap();
+        let signature = general_purpose::STANDARD.encode(
+            signing_key.sign(to_canonical_json(&client_value).as_bytes()).to_bytes(),
+        );
+
+        let received: Value = serde_json::from_str(
+            r#"{"timestamp":1700000000000,"details":{"breed":"Beagle","owner":{"phone":"5551234567","name":"Sam"}},"tags":["senior",{"a":2,"z":1}]}"#,
+        ).unwrap();
+        assert!(verify_signature(&received, &signature, &public_key).is_ok());
+
+        let tampered = serde_json::json!({
+            "timestamp": 1700000000000i64,
+            "details": {"breed": "Beagle", "owner": {"phone": "5559999999", "name": "Sam"}},
+            "tags": ["senior", {"a": 2, "z": 1}]
+        });
+        assert!(verify_signature(&tampered, &signature, &public_key).is_err());
+    }
+
+    #[test]
+    fn phone_numbers_are_normalized_to_e164() {
+        let cases = [
+            ("5551234567", "+15551234567"),
+            ("(555) 123-4567", "+15551234567"),
+            ("555-123-4567", "+15551234567"),
+            ("555.123.4567", "+15551234567"),
+            ("  555 123 4567 ", "+15551234567"),
+            ("15551234567", "+15551234567"),
+            ("1 (555) 123-4567", "+15551234567"),
+            ("+1 555 123 4567", "+15551234567"),
+            ("+15551234567", "+15551234567"),
+            ("+44 20 7946 0958", "+442079460958"),
+            ("+61 (2) 9876-5432", "+61298765432"),
+            ("+353 1 234 5678", "+35312345678"),
+            ("000123456789", "000123456789"),
+            ("0001-2345-6789", "000123456789"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(normalize_phone_number(input, DEFAULT_COUNTRY_CODE).as_deref(), Ok(expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn national_numbers_take_the_country_code() {
+        let cases = [
+            ("020 7946 0958", "GB", "+442079460958"),
+            ("20 7946 0958", "gb", "+442079460958"),
+            ("+44 20 7946 0958", "CA", "+442079460958"),
+            ("(416) 555-0123", "CA", "+14165550123"),
+            ("01 234 5678", "IE", "+35312345678"),
+            ("02 9876 5432", "AU", "+61298765432"),
+            ("000123 4567", "GB", "+440001234567"),
+            ("000123456789", "CA", "000123456789"),
+        ];
+        for (input, country_code, expected) in cases {
+            assert_eq!(normalize_phone_number(input, country_code).as_deref(), Ok(expected), "{} in {}", input, country_code);
+        }
+        assert_eq!(normalize_phone_number("020 7946", "GB"), Err(PhoneNumberError::WrongLength));
+        assert_eq!(normalize_phone_number("5551234567", "XX"), Err(PhoneNumberError::UnsupportedCountry));
+
+        assert!(is_test_phone_number("000123456789"));
+        assert!(is_test_phone_number("+440001234567"));
+        assert!(!is_test_phone_number("+442079460958"));
+        assert!(!is_test_phone_number("+15551234567"));
+    }
+
+    #[test]
+    fn invalid_phone_numbers_say_why() {
+        assert_eq!(normalize_phone_number("", DEFAULT_COUNTRY_CODE), Err(PhoneNumberError::Empty));
+        assert_eq!(normalize_phone_number("+", DEFAULT_COUNTRY_CODE), Err(PhoneNumberError::Empty));
+        assert_eq!(normalize_phone_number("abc", DEFAULT_COUNTRY_CODE), Err(PhoneNumberError::InvalidCharacter('a')));
+        assert_eq!(normalize_phone_number("555-123-4567 ext 2", DEFAULT_COUNTRY_CODE), Err(PhoneNumberError::InvalidCharacter('e')));
+        assert_eq!(normalize_phone_number("1+5551234567", DEFAULT_COUNTRY_CODE), Err(PhoneNumberError::InvalidCharacter('+')));
+        assert_eq!(normalize_phone_number("555-1234", DEFAULT_COUNTRY_CODE), Err(PhoneNumberError::WrongLength));
+        assert_eq!(normalize_phone_number("+1 555 123 456", DEFAULT_COUNTRY_CODE), Err(PhoneNumberError::WrongLength));
+        assert_eq!(normalize_phone_number("+1234567", DEFAULT_COUNTRY_CODE), Err(PhoneNumberError::WrongLength));
+        assert_eq!(normalize_phone_number("+4412345678901234", DEFAULT_COUNTRY_CODE), Err(PhoneNumberError::WrongLength));
+        assert_eq!(normalize_phone_number("+0 555 123 4567", DEFAULT_COUNTRY_CODE), Err(PhoneNumberError::InvalidCountryCode));
+        assert_eq!(normalize_phone_number("442079460958", DEFAULT_COUNTRY_CODE), Err(PhoneNumberError::MissingCountryCode));
+        assert_eq!(normalize_phone_number("000123", DEFAULT_COUNTRY_CODE), Err(PhoneNumberError::WrongLength));
+    }
 }
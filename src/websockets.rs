@@ -1,14 +1,35 @@
-use actix::{Actor, Context, Handler, Recipient, StreamHandler, WrapFuture, Message, AsyncContext, ActorContext, Addr, Running, ActorFutureExt, ContextFutureSpawner};
+use actix::{Actor, Context, Handler, Recipient, StreamHandler, WrapFuture, Message, MessageResult, AsyncContext, ActorContext, Addr, Running, ActorFutureExt, ContextFutureSpawner};
 use actix::fut::wrap_future;
-use actix_web::{web, HttpRequest, HttpResponse, get};
+use actix_web::{web, HttpRequest, HttpResponse, get, http::header};
 use actix_web_actors::ws;
+use serde::{Deserialize, Serialize};
 use serde_json::{self, json};
 use sqlx::PgPool;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
 use uuid::Uuid;
-use chrono::Utc;
-use crate::models::{WsMessage, WsEvent};
-use crate::services::conversations::ConversationService;
+use chrono::{DateTime, Utc};
+use crate::config::Config;
+use crate::auth::AuthenticatedUser;
+use crate::errors::ApiError;
+use crate::models::{WsMessage, WsEvent, Conversation, ConversationState, ParticipantState, ConversationHistoryResponse};
+use crate::services::access_log::{self, AccessLogService};
+use crate::services::conversations::{ConversationService, HistoryError, ReactionError, SendMessageError, SetProvidersError};
+use crate::moderation::MessageModerator;
+use crate::metrics::DeliveryMetrics;
+use crate::rate_limits::{ConversationRateLimiter, SessionMessageLimiter};
+use crate::services::audit::AuditService;
+use crate::services::pet_shares::AccessLevel;
+use crate::services::canned_responses::{CannedResponseService, CannedResponseError};
+use crate::services::feature_flags::{self, FeatureFlagService};
+use crate::services::drafts::{DraftService, DraftError};
+use crate::services::notes::{NoteService, NoteError};
+use crate::services::organizations::OrganizationService;
+use crate::services::pending_events::PendingEventService;
+use crate::services::revoked_tokens::RevokedTokenService;
+use crate::services::sessions::SessionService;
+use crate::request_id;
+use crate::ws_schema::{self, PayloadError};
 
 // -----------------------
 // Define Message Types
@@ -23,6 +44,23 @@ pub struct BroadcastMessage(pub WsMessage);
 pub struct BroadcastToConversation {
     pub message: WsMessage,
     pub conversation_id: Uuid,
+    // Set for new chat messages so each recipient can time its delivery
+    pub timing: Option<DeliveryTiming>,
+}
+
+#[derive(Clone, Copy)]
+pub struct DeliveryTiming {
+    pub persisted_at: Instant,
+    // Add the recipient's persist -> deliver time to the frame's `delivery_trace`
+    pub trace: bool,
+}
+
+/// A conversation event for a session to write and time against `timing`.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct DeliverMessage {
+    pub message: WsMessage,
+    pub timing: DeliveryTiming,
 }
 
 #[derive(Message)]
@@ -32,6 +70,19 @@ pub struct SubscribeToConversation {
     pub conversation_id: Uuid,
 }
 
+/// Sends a reconnecting session the conversation events broadcast after
+/// `last_event_seq`, or `resync_required` if they are no longer buffered. More
+/// than `ws_replay_max_events` are cut down to the newest, after a
+/// `resync_required` flagged `truncated`.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ReplayConversation {
+    pub user_id: Uuid,
+    pub session_id: Uuid,
+    pub conversation_id: Uuid,
+    pub last_event_seq: u64,
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct UnsubscribeFromConversation {
@@ -39,17 +90,246 @@ pub struct UnsubscribeFromConversation {
     pub conversation_id: Uuid,
 }
 
+/// Applies a change of a conversation's providers in one step: subscribes
+/// those added, broadcasts `message` (so those removed hear about it too),
+/// then unsubscribes those removed.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ReconcileProviders {
+    pub conversation_id: Uuid,
+    pub added: Vec<Uuid>,
+    pub removed: Vec<Uuid>,
+    pub message: WsMessage,
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct Connect {
     pub addr: Recipient<BroadcastMessage>,
+    pub deliver: Recipient<DeliverMessage>,
+    pub close: Recipient<CloseSession>,
     pub id: Uuid,
+    pub session_id: Uuid,
+    // Refresh token family of the access token the session connected with
+    pub family_id: Option<Uuid>,
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct Disconnect {
     pub id: Uuid,
+    pub session_id: Uuid,
+}
+
+/// A session went quiet past `ws_away_after_secs`, or became active again.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetAway {
+    pub id: Uuid,
+    pub session_id: Uuid,
+    pub away: bool,
+}
+
+/// A session saw a ping or pong from its client.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Heartbeat {
+    pub id: Uuid,
+    pub session_id: Uuid,
+}
+
+/// Snapshot of every connected user, for support tooling.
+#[derive(Message)]
+#[rtype(result = "Vec<ConnectionSummary>")]
+pub struct ListConnections;
+
+/// Snapshot of one user's sessions and subscriptions, or `None` if they aren't connected.
+#[derive(Message)]
+#[rtype(result = "Option<ConnectionDetails>")]
+pub struct GetConnection {
+    pub user_id: Uuid,
+}
+
+/// The conversations a user is subscribed to, sorted.
+#[derive(Message)]
+#[rtype(result = "Vec<Uuid>")]
+pub struct GetSubscriptions {
+    pub user_id: Uuid,
+}
+
+/// Which of `user_ids` have at least one open session.
+#[derive(Message)]
+#[rtype(result = "HashSet<Uuid>")]
+pub struct GetOnlineUsers {
+    pub user_ids: Vec<Uuid>,
+}
+
+/// Closes all of the user's sessions, resolving to how many were closed.
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct DisconnectUser {
+    pub user_id: Uuid,
+    pub reason: &'static str,
+}
+
+/// Closes the user's sessions that connected with a token from `family_id`,
+/// resolving to how many were closed.
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct DisconnectFamily {
+    pub user_id: Uuid,
+    pub family_id: Uuid,
+}
+
+/// Tells a session to close its socket.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CloseSession {
+    pub reason: &'static str,
+}
+
+/// Delivers a message to every open session of one user. Resolves to `false`
+/// if the user has none.
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct SendToUser {
+    pub user_id: Uuid,
+    pub message: WsMessage,
+}
+
+/// Delivers a message to every open session of one user except `session_id`,
+/// e.g. to sync what one of their devices did to the others. Resolves to how
+/// many sessions it reached.
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct SendToOtherSessions {
+    pub user_id: Uuid,
+    pub session_id: Uuid,
+    pub message: WsMessage,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ConnectionSummary {
+    pub user_id: Uuid,
+    pub session_count: usize,
+    #[serde(serialize_with = "serialize_millis_vec")]
+    pub connected_at: Vec<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ConnectionDetails {
+    pub user_id: Uuid,
+    pub sessions: Vec<SessionInfo>,
+    pub subscriptions: Vec<Uuid>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SessionInfo {
+    pub session_id: Uuid,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub connected_at: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub last_heartbeat: DateTime<Utc>,
+    pub encoding: &'static str,
+    pub away: bool,
+}
+
+fn serialize_millis_vec<S: serde::Serializer>(times: &[DateTime<Utc>], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(times.iter().map(|t| t.timestamp_millis()))
+}
+
+// Messages are always sent as JSON text frames; no other encoding is negotiated yet
+const SESSION_ENCODING: &str = "json";
+
+struct SessionEntry {
+    addr: Recipient<BroadcastMessage>,
+    deliver: Recipient<DeliverMessage>,
+    close: Recipient<CloseSession>,
+    family_id: Option<Uuid>,
+    connected_at: DateTime<Utc>,
+    last_heartbeat: DateTime<Utc>,
+    away: bool,
+}
+
+// -----------------------
+// Define Conversation Replay Buffer
+// -----------------------
+
+struct BufferedEvent {
+    seq: u64,
+    broadcast_at: DateTime<Utc>,
+    message: WsMessage,
+}
+
+/// Recent events broadcast to one conversation, kept so a client that briefly
+/// lost its socket can catch up without refetching the whole history.
+struct ReplayBuffer {
+    events: VecDeque<BufferedEvent>,
+    // Every event for this conversation with a seq above this is still buffered
+    complete_after: u64,
+}
+
+impl ReplayBuffer {
+    fn new(complete_after: u64) -> Self {
+        ReplayBuffer {
+            events: VecDeque::new(),
+            complete_after,
+        }
+    }
+
+    fn push(&mut self, seq: u64, broadcast_at: DateTime<Utc>, message: WsMessage, max_events: usize) {
+        self.events.push_back(BufferedEvent { seq, broadcast_at, message });
+        while self.events.len() > max_events {
+            self.evict_oldest();
+        }
+    }
+
+    fn prune(&mut self, cutoff: DateTime<Utc>) {
+        while self.events.front().is_some_and(|event| event.broadcast_at < cutoff) {
+            self.evict_oldest();
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some(event) = self.events.pop_front() {
+            self.complete_after = event.seq;
+        }
+    }
+
+    /// Events after `last_event_seq` in broadcast order, or `None` if some of
+    /// them have already been evicted.
+    fn events_after(&self, last_event_seq: u64) -> Option<Vec<WsMessage>> {
+        if last_event_seq < self.complete_after {
+            return None;
+        }
+        Some(
+            self.events
+                .iter()
+                .filter(|event| event.seq > last_event_seq)
+                .map(|event| event.message.clone())
+                .collect(),
+        )
+    }
+}
+
+// -----------------------
+// Define Disconnect Buffer
+// -----------------------
+
+/// Conversation events for a user whose last session just closed. They stay
+/// subscribed for a short while, and a quick reconnect gets these straight away.
+struct DisconnectBuffer {
+    disconnected_at: DateTime<Utc>,
+    events: VecDeque<WsMessage>,
+}
+
+impl DisconnectBuffer {
+    fn push(&mut self, message: WsMessage, max_events: usize) {
+        self.events.push_back(message);
+        while self.events.len() > max_events {
+            self.events.pop_front();
+        }
+    }
 }
 
 // -----------------------
@@ -57,30 +337,54 @@ pub struct Disconnect {
 // -----------------------
 
 pub struct WsServer {
-    sessions: HashMap<Uuid, Recipient<BroadcastMessage>>,
+    sessions: HashMap<Uuid, HashMap<Uuid, SessionEntry>>, // user_id -> session_id -> session
     conversation_subscriptions: HashMap<Uuid, HashSet<Uuid>>, // conversation_id -> set of user_ids
+    replay_buffers: HashMap<Uuid, ReplayBuffer>, // conversation_id -> recent events
+    // Seeded from the clock so sequence numbers from a previous process are
+    // always older than anything this one hands out
+    first_event_seq: u64,
+    last_event_seq: u64,
+    // Highest seq that may have been dropped along with an idle conversation's buffer
+    dropped_through: u64,
+    replay_buffer_events: usize,
+    replay_buffer_ttl: chrono::Duration,
+    replay_max_events: usize,
+    disconnect_buffers: HashMap<Uuid, DisconnectBuffer>, // user_id -> events held since their last session closed
+    disconnect_buffer_events: usize,
+    disconnect_buffer_ttl: chrono::Duration,
 }
 
 impl WsServer {
-    pub fn new() -> Self {
+    pub fn new(config: &Config) -> Self {
+        let first_event_seq = Utc::now().timestamp_micros() as u64;
         WsServer {
             sessions: HashMap::new(),
             conversation_subscriptions: HashMap::new(),
+            replay_buffers: HashMap::new(),
+            first_event_seq,
+            last_event_seq: first_event_seq,
+            dropped_through: first_event_seq,
+            replay_buffer_events: config.ws_replay_buffer_events,
+            replay_buffer_ttl: config.ws_replay_buffer_ttl,
+            replay_max_events: config.ws_replay_max_events,
+            disconnect_buffers: HashMap::new(),
+            disconnect_buffer_events: config.ws_disconnect_buffer_events,
+            disconnect_buffer_ttl: config.ws_disconnect_buffer_ttl,
         }
     }
 
     // Subscribe a user to a conversation
     pub fn subscribe_to_conversation(&mut self, user_id: Uuid, conversation_id: Uuid) {
-        println!("User {} subscribed to conversation {}", user_id, conversation_id);
+        logln!("User {} subscribed to conversation {}", user_id, conversation_id);
         self.conversation_subscriptions
             .entry(conversation_id)
-            .or_insert_with(HashSet::new)
+            .or_default()
             .insert(user_id);
     }
 
     // Unsubscribe a user from a conversation
     pub fn unsubscribe_from_conversation(&mut self, user_id: Uuid, conversation_id: Uuid) {
-        println!("User {} unsubscribed from conversation {}", user_id, conversation_id);
+        logln!("User {} unsubscribed from conversation {}", user_id, conversation_id);
         if let Some(subscribers) = self.conversation_subscriptions.get_mut(&conversation_id) {
             subscribers.remove(&user_id);
             if subscribers.is_empty() {
@@ -89,37 +393,316 @@ impl WsServer {
         }
     }
 
-    // Broadcast to specific conversation
-    pub fn broadcast_to_conversation(&self, message: &WsMessage, conversation_id: Uuid) {
-        println!("Broadcasting to conversation {}: {:?}", conversation_id, message.event);
+    // Broadcast to specific conversation, recording the event for replay
+    pub fn broadcast_to_conversation(&mut self, message: &WsMessage, conversation_id: Uuid, timing: Option<DeliveryTiming>) {
+        logln!("Broadcasting to conversation {}: {:?}", conversation_id, message.event);
+        let message = &self.record_event(message, conversation_id);
         if let Some(subscribers) = self.conversation_subscriptions.get(&conversation_id) {
             for user_id in subscribers {
-                if let Some(recipient) = self.sessions.get(user_id) {
-                    let _ = recipient.do_send(BroadcastMessage(message.clone()));
+                let Some(sessions) = self.sessions.get(user_id) else {
+                    // Between sessions; hold it for when they reconnect
+                    if let Some(buffer) = self.disconnect_buffers.get_mut(user_id) {
+                        buffer.push(message.clone(), self.disconnect_buffer_events);
+                    }
+                    continue;
+                };
+                for session in sessions.values() {
+                    match timing {
+                        Some(timing) => session.deliver.do_send(DeliverMessage { message: message.clone(), timing }),
+                        None => session.addr.do_send(BroadcastMessage(message.clone())),
+                    }
+                }
+            }
+        }
+    }
+
+    // Stamps the message with the next event_seq and appends it to the conversation's buffer
+    fn record_event(&mut self, message: &WsMessage, conversation_id: Uuid) -> WsMessage {
+        self.last_event_seq += 1;
+        let seq = self.last_event_seq;
+
+        let mut message = message.clone();
+        if let Some(params) = message.params.as_object_mut() {
+            params.insert("event_seq".to_string(), json!(seq));
+        }
+
+        if self.replay_buffer_events > 0 {
+            let complete_after = seq - 1;
+            self.replay_buffers
+                .entry(conversation_id)
+                .or_insert_with(|| ReplayBuffer::new(complete_after))
+                .push(seq, Utc::now(), message.clone(), self.replay_buffer_events);
+        }
+
+        message
+    }
+
+    // Sends a reconnecting session what it missed, or tells it to refetch. The
+    // user's other sessions stayed connected and have these already.
+    pub fn replay_conversation(&mut self, user_id: Uuid, session_id: Uuid, conversation_id: Uuid, last_event_seq: u64) {
+        let Some(recipient) = self.sessions.get(&user_id).and_then(|sessions| sessions.get(&session_id)) else {
+            return;
+        };
+        let recipient = recipient.addr.clone();
+
+        let cutoff = Utc::now() - self.replay_buffer_ttl;
+        let missed = if last_event_seq > self.last_event_seq || last_event_seq < self.first_event_seq {
+            // Issued by a previous server process
+            None
+        } else {
+            match self.replay_buffers.get_mut(&conversation_id) {
+                Some(buffer) => {
+                    buffer.prune(cutoff);
+                    buffer.events_after(last_event_seq)
+                },
+                None if self.replay_buffer_events > 0 && last_event_seq >= self.dropped_through => Some(Vec::new()),
+                None => None,
+            }
+        };
+
+        let resync = |truncated: bool| WsMessage {
+            sender_id: Uuid::nil(),
+            event: "resync_required".to_string(),
+            params: json!({
+                "conversation_id": conversation_id,
+                "event_seq": self.last_event_seq,
+                "truncated": truncated
+            }),
+        };
+        match missed {
+            Some(mut events) => {
+                // Too many to replay: say so first, then send just the newest
+                if events.len() > self.replay_max_events {
+                    logln!("Truncating replay of {} events in conversation {} to user {}", events.len(), conversation_id, user_id);
+                    events.drain(..events.len() - self.replay_max_events);
+                    recipient.do_send(BroadcastMessage(resync(true)));
                 }
+                logln!("Replaying {} events in conversation {} to user {}", events.len(), conversation_id, user_id);
+                for event in events {
+                    recipient.do_send(BroadcastMessage(event));
+                }
+            },
+            None => {
+                logln!("User {} must resync conversation {}", user_id, conversation_id);
+                recipient.do_send(BroadcastMessage(resync(false)));
+            }
+        }
+    }
+
+    // Drops expired events, and the buffers of conversations that have gone quiet
+    fn prune_replay_buffers(&mut self) {
+        let cutoff = Utc::now() - self.replay_buffer_ttl;
+        let mut dropped_through = self.dropped_through;
+        self.replay_buffers.retain(|_, buffer| {
+            buffer.prune(cutoff);
+            if buffer.events.is_empty() {
+                dropped_through = dropped_through.max(buffer.complete_after);
+                false
+            } else {
+                true
+            }
+        });
+        self.dropped_through = dropped_through;
+    }
+
+    // Removes a user who has no sessions left from every conversation
+    fn drop_subscriptions(&mut self, user_id: Uuid) {
+        let mut empty_conversations = Vec::new();
+        for (conversation_id, subscribers) in &mut self.conversation_subscriptions {
+            subscribers.remove(&user_id);
+            if subscribers.is_empty() {
+                empty_conversations.push(*conversation_id);
             }
         }
+        
+        // Clean up empty conversation subscriptions
+        for conversation_id in &empty_conversations {
+            self.conversation_subscriptions.remove(conversation_id);
+            logln!("Removed empty conversation subscription: {}", conversation_id);
+        }
+        
+        logln!("User {} disconnected and cleaned up from {} conversations", user_id, empty_conversations.len());
+    }
+
+    // Gives up on users who didn't reconnect in time
+    fn prune_disconnect_buffers(&mut self) {
+        let cutoff = Utc::now() - self.disconnect_buffer_ttl;
+        let expired: Vec<Uuid> = self.disconnect_buffers
+            .iter()
+            .filter(|(_, buffer)| buffer.disconnected_at <= cutoff)
+            .map(|(user_id, _)| *user_id)
+            .collect();
+        for user_id in expired {
+            self.disconnect_buffers.remove(&user_id);
+            self.drop_subscriptions(user_id);
+        }
     }
 
     // Keep the general broadcast for system messages
     pub fn broadcast_message(&self, message: &WsMessage) {
-        println!("Broadcasting to all users: {:?}", message.event);
-        for recipient in self.sessions.values() {
-            let _ = recipient.do_send(BroadcastMessage(message.clone()));
+        logln!("Broadcasting to all users: {:?}", message.event);
+        for session in self.sessions.values().flat_map(HashMap::values) {
+            session.addr.do_send(BroadcastMessage(message.clone()));
+        }
+    }
+
+    pub fn connection_summaries(&self) -> Vec<ConnectionSummary> {
+        let mut summaries: Vec<ConnectionSummary> = self.sessions
+            .iter()
+            .map(|(user_id, sessions)| {
+                let mut connected_at: Vec<_> = sessions.values().map(|session| session.connected_at).collect();
+                connected_at.sort();
+                ConnectionSummary {
+                    user_id: *user_id,
+                    session_count: sessions.len(),
+                    connected_at,
+                }
+            })
+            .collect();
+        summaries.sort_by_key(|summary| summary.connected_at.first().copied());
+        summaries
+    }
+
+    pub fn connection_details(&self, user_id: Uuid) -> Option<ConnectionDetails> {
+        let sessions = self.sessions.get(&user_id)?;
+        let mut sessions: Vec<SessionInfo> = sessions
+            .iter()
+            .map(|(session_id, session)| SessionInfo {
+                session_id: *session_id,
+                connected_at: session.connected_at,
+                last_heartbeat: session.last_heartbeat,
+                encoding: SESSION_ENCODING,
+                away: session.away,
+            })
+            .collect();
+        sessions.sort_by_key(|session| session.connected_at);
+
+        Some(ConnectionDetails {
+            user_id,
+            sessions,
+            subscriptions: self.subscriptions_of(user_id),
+        })
+    }
+
+    // Returns false if the user has no open sessions
+    pub fn send_to_user(&self, user_id: Uuid, message: &WsMessage) -> bool {
+        let Some(sessions) = self.sessions.get(&user_id) else {
+            return false;
+        };
+        for session in sessions.values() {
+            session.addr.do_send(BroadcastMessage(message.clone()));
         }
+        !sessions.is_empty()
+    }
+
+    pub fn send_to_other_sessions(&self, user_id: Uuid, session_id: Uuid, message: &WsMessage) -> usize {
+        let Some(sessions) = self.sessions.get(&user_id) else {
+            return 0;
+        };
+        let mut sent = 0;
+        for (id, session) in sessions {
+            if *id != session_id {
+                session.addr.do_send(BroadcastMessage(message.clone()));
+                sent += 1;
+            }
+        }
+        sent
+    }
+
+    pub fn online_users(&self, user_ids: &[Uuid]) -> HashSet<Uuid> {
+        user_ids
+            .iter()
+            .filter(|user_id| self.sessions.contains_key(user_id))
+            .copied()
+            .collect()
+    }
+
+    /// "online" while any of the user's sessions is active, "away" once all of
+    /// them have gone quiet, and `None` when they have none open.
+    pub fn presence(&self, user_id: Uuid) -> Option<&'static str> {
+        let sessions = self.sessions.get(&user_id).filter(|sessions| !sessions.is_empty())?;
+        match sessions.values().all(|session| session.away) {
+            true => Some("away"),
+            false => Some("online"),
+        }
+    }
+
+    // Tells the user's conversations when they go away or come back. Connecting
+    // and disconnecting are announced through user_joined and user_left instead.
+    fn announce_presence(&mut self, user_id: Uuid, before: Option<&'static str>) {
+        let after = self.presence(user_id);
+        let (Some(_), Some(status)) = (before, after) else {
+            return;
+        };
+        if before == after {
+            return;
+        }
+        for conversation_id in self.subscriptions_of(user_id) {
+            let message = WsMessage {
+                sender_id: Uuid::nil(),
+                event: "presence_changed".to_string(),
+                params: json!({
+                    "user_id": user_id,
+                    "conversation_id": conversation_id,
+                    "status": status,
+                    "timestamp": Utc::now().timestamp_millis()
+                }),
+            };
+            self.broadcast_to_conversation(&message, conversation_id, None);
+        }
+    }
+
+    pub fn subscriptions_of(&self, user_id: Uuid) -> Vec<Uuid> {
+        let mut subscriptions: Vec<Uuid> = self.conversation_subscriptions
+            .iter()
+            .filter(|(_, subscribers)| subscribers.contains(&user_id))
+            .map(|(conversation_id, _)| *conversation_id)
+            .collect();
+        subscriptions.sort();
+        subscriptions
     }
 }
 
 impl Actor for WsServer {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(Duration::from_secs(30), |act, _ctx| {
+            act.prune_replay_buffers();
+            act.prune_disconnect_buffers();
+        });
+    }
 }
 
 impl Handler<Connect> for WsServer {
     type Result = ();
 
     fn handle(&mut self, msg: Connect, _: &mut Context<Self>) {
-        self.sessions.insert(msg.id, msg.addr);
-        println!("User {} connected", msg.id);
+        let now = Utc::now();
+        let presence = self.presence(msg.id);
+        self.sessions.entry(msg.id).or_default().insert(msg.session_id, SessionEntry {
+            addr: msg.addr.clone(),
+            deliver: msg.deliver,
+            close: msg.close,
+            family_id: msg.family_id,
+            connected_at: now,
+            last_heartbeat: now,
+            away: false,
+        });
+        logln!("User {} connected (session {})", msg.id, msg.session_id);
+        self.announce_presence(msg.id, presence);
+
+        if let Some(buffer) = self.disconnect_buffers.remove(&msg.id) {
+            if buffer.disconnected_at > now - self.disconnect_buffer_ttl {
+                logln!("Delivering {} events buffered while user {} was disconnected", buffer.events.len(), msg.id);
+                for event in buffer.events {
+                    msg.addr.do_send(BroadcastMessage(event));
+                }
+            } else {
+                // Too late; the new session subscribes afresh
+                self.drop_subscriptions(msg.id);
+            }
+        }
     }
 }
 
@@ -128,26 +711,130 @@ impl Handler<Disconnect> for WsServer {
 
     fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
         let user_id = msg.id;
+        let presence = self.presence(user_id);
         
-        // Remove user from sessions
+        // Remove the session; the user stays subscribed while they have others open
+        if let Some(sessions) = self.sessions.get_mut(&user_id) {
+            sessions.remove(&msg.session_id);
+            if !sessions.is_empty() {
+                logln!("User {} closed session {}", user_id, msg.session_id);
+                // The sessions left may all be away
+                self.announce_presence(user_id, presence);
+                return;
+            }
+        }
         self.sessions.remove(&user_id);
-        
-        // Remove user from all conversation subscriptions
-        let mut empty_conversations = Vec::new();
-        for (conversation_id, subscribers) in &mut self.conversation_subscriptions {
-            subscribers.remove(&user_id);
-            if subscribers.is_empty() {
-                empty_conversations.push(*conversation_id);
+
+        // Keep them subscribed for a moment in case this is a brief drop
+        if self.disconnect_buffer_events > 0 && self.disconnect_buffer_ttl > chrono::Duration::zero() {
+            logln!("User {} disconnected; holding their events for {}s", user_id, self.disconnect_buffer_ttl.num_seconds());
+            self.disconnect_buffers.insert(user_id, DisconnectBuffer {
+                disconnected_at: Utc::now(),
+                events: VecDeque::new(),
+            });
+            return;
+        }
+        self.drop_subscriptions(user_id);
+    }
+}
+
+impl Handler<SetAway> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetAway, _: &mut Context<Self>) {
+        let presence = self.presence(msg.id);
+        if let Some(session) = self.sessions.get_mut(&msg.id).and_then(|sessions| sessions.get_mut(&msg.session_id)) {
+            session.away = msg.away;
+        }
+        self.announce_presence(msg.id, presence);
+    }
+}
+
+impl Handler<Heartbeat> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Heartbeat, _: &mut Context<Self>) {
+        if let Some(session) = self.sessions.get_mut(&msg.id).and_then(|sessions| sessions.get_mut(&msg.session_id)) {
+            session.last_heartbeat = Utc::now();
+        }
+    }
+}
+
+impl Handler<ListConnections> for WsServer {
+    type Result = MessageResult<ListConnections>;
+
+    fn handle(&mut self, _: ListConnections, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.connection_summaries())
+    }
+}
+
+impl Handler<GetConnection> for WsServer {
+    type Result = MessageResult<GetConnection>;
+
+    fn handle(&mut self, msg: GetConnection, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.connection_details(msg.user_id))
+    }
+}
+
+impl Handler<GetSubscriptions> for WsServer {
+    type Result = MessageResult<GetSubscriptions>;
+
+    fn handle(&mut self, msg: GetSubscriptions, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.subscriptions_of(msg.user_id))
+    }
+}
+
+impl Handler<GetOnlineUsers> for WsServer {
+    type Result = MessageResult<GetOnlineUsers>;
+
+    fn handle(&mut self, msg: GetOnlineUsers, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.online_users(&msg.user_ids))
+    }
+}
+
+impl Handler<DisconnectFamily> for WsServer {
+    type Result = usize;
+
+    // Sessions unregister themselves through Disconnect once their socket closes
+    fn handle(&mut self, msg: DisconnectFamily, _: &mut Context<Self>) -> usize {
+        let mut closed = 0;
+        for session in self.sessions.get(&msg.user_id).into_iter().flat_map(HashMap::values) {
+            if session.family_id == Some(msg.family_id) {
+                session.close.do_send(CloseSession { reason: "session revoked" });
+                closed += 1;
             }
         }
-        
-        // Clean up empty conversation subscriptions
-        for conversation_id in &empty_conversations {
-            self.conversation_subscriptions.remove(conversation_id);
-            println!("Removed empty conversation subscription: {}", conversation_id);
+        logln!("Closed {} sessions of user {} for revoked family {}", closed, msg.user_id, msg.family_id);
+        closed
+    }
+}
+
+impl Handler<DisconnectUser> for WsServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: DisconnectUser, _: &mut Context<Self>) -> usize {
+        let sessions: Vec<_> = self.sessions.get(&msg.user_id).into_iter().flat_map(HashMap::values).collect();
+        for session in &sessions {
+            session.close.do_send(CloseSession { reason: msg.reason });
         }
-        
-        println!("User {} disconnected and cleaned up from {} conversations", user_id, empty_conversations.len());
+        logln!("Closed {} sessions of user {}: {}", sessions.len(), msg.user_id, msg.reason);
+        sessions.len()
+    }
+}
+
+impl Handler<SendToUser> for WsServer {
+    type Result = bool;
+
+    fn handle(&mut self, msg: SendToUser, _: &mut Context<Self>) -> bool {
+        self.send_to_user(msg.user_id, &msg.message)
+    }
+}
+
+impl Handler<SendToOtherSessions> for WsServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: SendToOtherSessions, _: &mut Context<Self>) -> usize {
+        self.send_to_other_sessions(msg.user_id, msg.session_id, &msg.message)
     }
 }
 
@@ -163,7 +850,7 @@ impl Handler<BroadcastToConversation> for WsServer {
     type Result = ();
 
     fn handle(&mut self, msg: BroadcastToConversation, _: &mut Context<Self>) {
-        self.broadcast_to_conversation(&msg.message, msg.conversation_id);
+        self.broadcast_to_conversation(&msg.message, msg.conversation_id, msg.timing);
     }
 }
 
@@ -175,6 +862,14 @@ impl Handler<SubscribeToConversation> for WsServer {
     }
 }
 
+impl Handler<ReplayConversation> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: ReplayConversation, _: &mut Context<Self>) {
+        self.replay_conversation(msg.user_id, msg.session_id, msg.conversation_id, msg.last_event_seq);
+    }
+}
+
 impl Handler<UnsubscribeFromConversation> for WsServer {
     type Result = ();
 
@@ -183,636 +878,1880 @@ impl Handler<UnsubscribeFromConversation> for WsServer {
     }
 }
 
+impl Handler<ReconcileProviders> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: ReconcileProviders, _: &mut Context<Self>) {
+        for user_id in &msg.added {
+            self.subscribe_to_conversation(*user_id, msg.conversation_id);
+        }
+        self.broadcast_to_conversation(&msg.message, msg.conversation_id, None);
+        for user_id in &msg.removed {
+            self.unsubscribe_from_conversation(*user_id, msg.conversation_id);
+        }
+    }
+}
+
 // -----------------------
-// Define WebSocket Session Actor
+// Direct Notifications
+// -----------------------
+
+/// Sends `message` to `user_id` now if they're connected, otherwise stores it
+/// to be delivered when they next connect.
+pub async fn send_or_queue(server: &Addr<WsServer>, pool: &PgPool, config: &Config, user_id: Uuid, message: WsMessage) {
+    let delivered = server
+        .send(SendToUser { user_id, message: message.clone() })
+        .await
+        .unwrap_or(false);
+    if !delivered {
+        queue(pool, config, user_id, &message).await;
+    }
+}
+
+/// Stores `message` for `user_id` if they aren't connected. For events that
+/// connected users already receive through a conversation broadcast.
+pub async fn queue_if_offline(server: &Addr<WsServer>, pool: &PgPool, config: &Config, user_id: Uuid, message: WsMessage) {
+    if let Ok(None) = server.send(GetConnection { user_id }).await {
+        queue(pool, config, user_id, &message).await;
+    }
+}
+
+async fn queue(pool: &PgPool, config: &Config, user_id: Uuid, message: &WsMessage) {
+    if let Err(e) = PendingEventService::enqueue(pool, user_id, message, config.pending_events_max_per_user).await {
+        logln!("Failed to queue {} for user {}: {}", message.event, user_id, e);
+    }
+}
+
+/// Records a system message in the conversation on behalf of `actor_id` and
+/// sends it to everyone subscribed as `message_sent`.
+pub async fn post_system_message(server: &Addr<WsServer>, pool: &PgPool, actor_id: Uuid, conversation_id: Uuid, content: String) {
+    match ConversationService::send_system_message(pool, actor_id, conversation_id, content).await {
+        Ok(message) => server.do_send(BroadcastToConversation {
+            conversation_id,
+            message: WsMessage {
+                sender_id: Uuid::nil(),
+                event: "message_sent".to_string(),
+                params: json!(message),
+            },
+            timing: None,
+        }),
+        Err(e) => logln!("Failed to record system message in conversation {}: {}", conversation_id, e),
+    }
+}
+
+/// The user's name as other participants see it.
+pub async fn display_name(pool: &PgPool, user_id: Uuid) -> String {
+    match ConversationService::get_display_profiles(pool, user_id, &[user_id]).await {
+        Ok(mut profiles) => profiles.remove(&user_id).map_or_else(|| "Unknown User".to_string(), |p| p.display_name),
+        Err(_) => "Unknown User".to_string(),
+    }
+}
+
+// The error event for a failed `save_draft`.
+fn draft_error_event(e: DraftError) -> WsMessage {
+    let message = match e {
+        DraftError::Database(e) => {
+            logln!("Error saving draft: {:?}", e);
+            "Error saving draft".to_string()
+        },
+        e => e.to_string(),
+    };
+    WsMessage {
+        sender_id: Uuid::nil(),
+        event: "error".to_string(),
+        params: json!({ "message": message }),
+    }
+}
+
+// The error event for a failed `get_note` or `update_note`. A version conflict
+// carries the current note so the editor can merge their changes into it.
+fn note_error_event(e: NoteError) -> WsMessage {
+    let params = match e {
+        NoteError::Conflict(ref note) => json!({
+            "code": "note_version_conflict",
+            "message": e.to_string(),
+            "note": note
+        }),
+        NoteError::Database(ref db_error) => {
+            logln!("Error accessing conversation note: {:?}", db_error);
+            json!({ "message": "Error accessing conversation note" })
+        },
+        _ => json!({ "message": e.to_string() }),
+    };
+    WsMessage {
+        sender_id: Uuid::nil(),
+        event: "error".to_string(),
+        params,
+    }
+}
+
+/// The conversations in the user's inbox, newest first: for clients their own
+/// and those about pets shared with them, for providers those they're on.
+async fn visible_conversations(db_pool: &PgPool, user_id: Uuid, scope: &str) -> Vec<Conversation> {
+    let mut conversations = match scope {
+        "client" => {
+            // Fetch client conversations
+            let mut convs = match ConversationService::get_conversations_by_client_id(db_pool, user_id).await {
+                Ok(convs) => convs,
+                Err(e) => {
+                    logln!("Error fetching client conversations: {:?}", e);
+                    Vec::new()
+                }
+            };
+            // Plus conversations about pets shared with them
+            match ConversationService::get_conversations_shared_with(db_pool, user_id).await {
+                Ok(shared) => convs.extend(shared),
+                Err(e) => logln!("Error fetching shared conversations: {:?}", e),
+            }
+            convs
+        },
+        "provider" => {
+            // Fetch provider conversations
+            match ConversationService::get_conversations_by_provider_id(db_pool, user_id).await {
+                Ok(convs) => convs,
+                Err(e) => {
+                    logln!("Error fetching provider conversations: {:?}", e);
+                    Vec::new()
+                }
+            }
+        },
+        _ => {
+            logln!("Unknown user role: {}", scope);
+            Vec::new()
+        },
+    };
+
+    // Sort by last_updated_timestamp (newest first)
+    conversations.sort_by_key(|c| std::cmp::Reverse(c.last_updated_timestamp));
+    conversations
+}
+
+/// Subscribes the user to every conversation their scope gives them, as on
+/// connecting. Returns everything they're subscribed to afterwards.
+async fn subscribe_visible_conversations(server: &Addr<WsServer>, db_pool: &PgPool, user_id: Uuid, scope: &str) -> Vec<Uuid> {
+    for conversation in visible_conversations(db_pool, user_id, scope).await {
+        server.do_send(SubscribeToConversation {
+            user_id,
+            conversation_id: conversation.id,
+        });
+    }
+    // Queued behind the subscriptions, so it sees them
+    server.send(GetSubscriptions { user_id }).await.unwrap_or_default()
+}
+
+// -----------------------
+// Conversation State
+// -----------------------
+
+// History is still returned if the server is too busy to report presence in time
+const PRESENCE_TIMEOUT: Duration = Duration::from_millis(500);
+
+// Messages `open_conversation` returns when the client doesn't ask for a number
+const OPEN_CONVERSATION_LIMIT: i32 = 20;
+
+/// Read state and presence of the conversation's client and providers, for
+/// `user_id` opening it. `None` if either couldn't be gathered.
+pub async fn conversation_state(
+    server: &Addr<WsServer>,
+    pool: &PgPool,
+    conversation_id: Uuid,
+    user_id: Uuid,
+) -> Option<ConversationState> {
+    let (conversation, read_states) = match futures::try_join!(
+        ConversationService::get_conversation_by_id(pool, conversation_id),
+        ConversationService::get_read_states(pool, conversation_id),
+    ) {
+        Ok((Some(conversation), read_states)) => (conversation, read_states),
+        Ok((None, _)) => return None,
+        Err(e) => {
+            logln!("Failed to load read state for conversation {}: {}", conversation_id, e);
+            return None;
+        }
+    };
+
+    let mut participants = vec![conversation.client];
+    participants.extend(conversation.providers.iter().filter(|id| **id != conversation.client));
+    let online = match actix_web::rt::time::timeout(
+        PRESENCE_TIMEOUT,
+        server.send(GetOnlineUsers { user_ids: participants.clone() }),
+    ).await {
+        Ok(Ok(online)) => online,
+        _ => {
+            logln!("Presence for conversation {} unavailable; omitting state", conversation_id);
+            return None;
+        }
+    };
+
+    Some(ConversationState {
+        participants: participants
+            .into_iter()
+            .map(|participant| ParticipantState {
+                user_id: participant,
+                last_read_at: read_states.iter().find(|r| r.user_id == participant).map(|r| r.last_read_at),
+                online: online.contains(&participant),
+            })
+            .collect(),
+        last_read_message_id: read_states
+            .iter()
+            .find(|r| r.user_id == user_id)
+            .and_then(|r| r.last_read_message_id),
+    })
+}
+
+// -----------------------
+// Define Protocol Versions
+// -----------------------
+
+/// Close code for a client that only offered protocols this server doesn't speak.
+pub const UNSUPPORTED_PROTOCOL_CLOSE_CODE: u16 = 4406;
+
+/// The event schema a session speaks, negotiated with `Sec-WebSocket-Protocol`
+/// so existing app builds keep working as the schema evolves.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProtocolVersion {
+    /// Today's shapes; also what clients that don't ask for a protocol get.
+    V1,
+    /// Numbers every frame with `seq` and gives every error a `code`.
+    V2,
+}
+
+impl ProtocolVersion {
+    // Oldest first, so the last one a client offers here is the newest it speaks
+    const SUPPORTED: [ProtocolVersion; 2] = [ProtocolVersion::V1, ProtocolVersion::V2];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ProtocolVersion::V1 => "vt.v1",
+            ProtocolVersion::V2 => "vt.v2",
+        }
+    }
+
+    /// The newest supported protocol in a `Sec-WebSocket-Protocol` header, or v1
+    /// without one. Otherwise the first protocol offered, to echo back before
+    /// closing the socket.
+    pub fn negotiate(header: Option<&str>) -> Result<ProtocolVersion, String> {
+        let offered: Vec<&str> = header
+            .into_iter()
+            .flat_map(|header| header.split(','))
+            .map(str::trim)
+            .filter(|protocol| !protocol.is_empty())
+            .collect();
+        if offered.is_empty() {
+            return Ok(ProtocolVersion::V1);
+        }
+        Self::SUPPORTED
+            .into_iter()
+            .rev()
+            .find(|version| offered.contains(&version.name()))
+            .ok_or_else(|| offered[0].to_string())
+    }
+
+    /// `message` as this version frames it. `seq` counts the frames written to
+    /// the session, starting from 1.
+    pub fn frame(self, message: &WsMessage, seq: u64) -> String {
+        match self {
+            ProtocolVersion::V1 => serde_json::to_string(message).unwrap(),
+            ProtocolVersion::V2 => {
+                let mut frame = json!(message);
+                frame["seq"] = json!(seq);
+                if message.event == "error" && frame["params"].get("code").is_none() {
+                    frame["params"]["code"] = json!(error_code(&message.params));
+                }
+                frame.to_string()
+            },
+        }
+    }
+}
+
+// A code for error events that predate codes, from their HTTP-style status
+fn error_code(params: &serde_json::Value) -> &'static str {
+    match params.get("status").and_then(|status| status.as_u64()) {
+        Some(400) => "invalid_request",
+        Some(403) => "forbidden",
+        Some(404) => "not_found",
+        Some(409) => "conflict",
+        Some(429) => "rate_limited",
+        Some(500) => "internal_error",
+        _ => "error",
+    }
+}
+
+// The optional `request_id` any inbound message may carry, so its logs can be
+// matched up with the client's
+#[derive(Deserialize)]
+struct InboundRequestId {
+    request_id: Option<String>,
+}
+
+/// Accepts the upgrade only to close it with `UNSUPPORTED_PROTOCOL_CLOSE_CODE`,
+/// since a refused handshake doesn't tell the client why.
+struct UnsupportedProtocolSession;
+
+impl Actor for UnsupportedProtocolSession {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let supported: Vec<&str> = ProtocolVersion::SUPPORTED.iter().map(|version| version.name()).collect();
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Other(UNSUPPORTED_PROTOCOL_CLOSE_CODE),
+            description: Some(format!("unsupported protocol; supported: {}", supported.join(", "))),
+        }));
+        ctx.stop();
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UnsupportedProtocolSession {
+    fn handle(&mut self, _msg: Result<ws::Message, ws::ProtocolError>, _ctx: &mut Self::Context) {}
+}
+
+// -----------------------
+// Define WebSocket Session Actor
 // -----------------------
 
 pub struct WsSession {
     pub id: Uuid,
+    // Distinguishes this socket from the user's other open sessions
+    session_id: Uuid,
+    family_id: Option<Uuid>,
+    // The scope the access token was issued for
+    scope: String,
     pub addr: Addr<WsServer>,
     pub db_pool: web::Data<PgPool>,
+    pub config: web::Data<Config>,
+    pub moderator: web::Data<dyn MessageModerator>,
+    pub metrics: web::Data<DeliveryMetrics>,
+    pub conversation_limiter: web::Data<ConversationRateLimiter>,
+    pub message_limiter: web::Data<SessionMessageLimiter>,
+    // Last time the client sent an application message
+    last_activity: Instant,
+    // Quiet for longer than ws_away_after_secs
+    away: bool,
+    protocol: ProtocolVersion,
+    frames_sent: u64,
+    // Conversations this session has announced itself in with user_joined, so
+    // subscribing again doesn't repeat it
+    announced_conversations: HashSet<Uuid>,
 }
 
-impl Actor for WsSession {
-    type Context = ws::WebsocketContext<Self>;
+impl WsSession {
+    fn write(&mut self, ctx: &mut ws::WebsocketContext<Self>, message: &WsMessage) {
+        self.frames_sent += 1;
+        ctx.text(self.protocol.frame(message, self.frames_sent));
+    }
 
-    // Called when the actor starts
-    fn started(&mut self, ctx: &mut Self::Context) {
-        // Register self in the server
-        self.addr
-            .send(Connect {
-                addr: ctx.address().recipient(),
-                id: self.id,
-            })
-            .into_actor(self)
-            .then(|_res, act, _ctx| {
-                // Auto-subscribe to all conversations the user is part of
-                let db_pool = act.db_pool.clone();
-                let user_id = act.id;
-                let addr = act.addr.clone();
-                
-                async move {
-                    // First, determine the user's role
-                    let user_role = match sqlx::query!(
-                        "SELECT scope FROM users WHERE id = $1",
-                        user_id
-                    )
-                    .fetch_optional(&**db_pool)
-                    .await {
-                        Ok(Some(record)) => record.scope,
-                        _ => "unknown".to_string(),
-                    };
-                    
-                    // Subscribe to conversations based on role
-                    match user_role.as_str() {
-                        "client" => {
-                            // Subscribe to client conversations
-                            if let Ok(conversations) = ConversationService::get_conversations_by_client_id(&db_pool, user_id).await {
-                                for conversation in conversations {
-                                    addr.do_send(SubscribeToConversation {
-                                        user_id,
-                                        conversation_id: conversation.id,
-                                    });
-                                }
-                            }
-                        },
-                        "provider" => {
-                            // Subscribe to provider conversations
-                            if let Ok(conversations) = ConversationService::get_conversations_by_provider_id(&db_pool, user_id).await {
-                                for conversation in conversations {
-                                    addr.do_send(SubscribeToConversation {
-                                        user_id,
-                                        conversation_id: conversation.id,
-                                    });
-                                }
-                            }
-                        },
-                        _ => {
-                            println!("Unknown user role: {}", user_role);
-                        }
-                    }
-                }
-                .into_actor(act)
-            })
-            .wait(ctx);
+    // v1 clients get the bare text they always have; v2 an `error` event with `code`
+    fn write_error(&mut self, ctx: &mut ws::WebsocketContext<Self>, code: &str, message: &str) {
+        match self.protocol {
+            ProtocolVersion::V1 => ctx.text(message.to_string()),
+            ProtocolVersion::V2 => self.write(ctx, &WsMessage {
+                sender_id: Uuid::nil(),
+                event: "error".to_string(),
+                params: json!({ "code": code, "message": message }),
+            }),
+        }
     }
 
-    // Called when the actor stops
-    fn stopping(&mut self, _ctx: &mut Self::Context) -> Running {
-        // Unregister self from the server
-        self.addr.do_send(Disconnect { id: self.id });
-        Running::Stop
+    // v1 clients get the problems spelled out in text; v2 the structured error
+    fn write_payload_error(&mut self, ctx: &mut ws::WebsocketContext<Self>, e: &PayloadError) {
+        match self.protocol {
+            ProtocolVersion::V1 => ctx.text(e.to_string()),
+            ProtocolVersion::V2 => self.write(ctx, &WsMessage {
+                sender_id: Uuid::nil(),
+                event: "error".to_string(),
+                params: e.to_params(),
+            }),
+        }
     }
-}
 
-impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsSession {
-    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut ws::WebsocketContext<Self>) {
-        match msg {
-            Ok(ws::Message::Ping(msg)) => {
-                ctx.pong(&msg);
+    // Closes the session once the client has been idle for the configured timeout
+    fn start_idle_check(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.config.ws_idle_timeout_secs == 0 {
+            return;
+        }
+        let idle_timeout = Duration::from_secs(self.config.ws_idle_timeout_secs);
+        let check_interval = idle_timeout.min(Duration::from_secs(10));
+
+        ctx.run_interval(check_interval, move |act, ctx| {
+            if act.last_activity.elapsed() >= idle_timeout {
+                logln!("Closing idle session for user {}", act.id);
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Normal,
+                    description: Some("idle timeout".to_string()),
+                }));
+                ctx.stop();
             }
-            Ok(ws::Message::Pong(_)) => {}
-            Ok(ws::Message::Text(text)) => {
-                println!("Received message from user {}: {}", self.id, text);
-                
-                // Log the raw incoming message for debugging
-                println!("Raw WebSocket message: {}", text);
-                
-                match serde_json::from_str::<WsMessage>(&text) {
-                    Ok(ws_message) => {
-                        println!("Successfully parsed WebSocket message: {:?}", ws_message);
-                        // Process based on event type
-                        match ws_message.event.as_str() {
-                            "conversations" => {
-                                let db_pool = self.db_pool.clone();
-                                let user_id = self.id;
-                                let addr = ctx.address();
-                                let future = async move {
-                                    // First, determine the user's role
-                                    let user_role = match sqlx::query!(
-                                        "SELECT scope FROM users WHERE id = $1",
-                                        user_id
-                                    )
-                                    .fetch_optional(&**db_pool)
-                                    .await {
-                                        Ok(Some(record)) => record.scope,
-                                        _ => "unknown".to_string(),
-                                    };
+        });
+    }
 
-                                    let conversations = match user_role.as_str() {
-                                        "client" => {
-                                            // Fetch client conversations
-                                            match ConversationService::get_conversations_by_client_id(&db_pool, user_id).await {
-                                                Ok(convs) => convs,
-                                                Err(e) => {
-                                                    println!("Error fetching client conversations: {:?}", e);
-                                                    Vec::new()
-                                                }
-                                            }
-                                        },
-                                        "provider" => {
-                                            // Fetch provider conversations
-                                            match ConversationService::get_conversations_by_provider_id(&db_pool, user_id).await {
-                                                Ok(convs) => convs,
-                                                Err(e) => {
-                                                    println!("Error fetching provider conversations: {:?}", e);
-                                                    Vec::new()
-                                                }
-                                            }
-                                        },
-                                        _ => {
-                                            println!("Unknown user role: {}", user_role);
-                                            Vec::new()
-                                        },
-                                    };
+    // Marks the session away once the client has been quiet for a while; its
+    // next message marks it active again
+    fn start_away_check(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.config.ws_away_after_secs == 0 {
+            return;
+        }
+        let away_after = Duration::from_secs(self.config.ws_away_after_secs);
+        let check_interval = away_after.min(Duration::from_secs(10));
 
-                                    // Sort by last_updated_timestamp (newest first)
-                                    let mut sorted_conversations = conversations;
-                                    sorted_conversations.sort_by(|a, b| b.last_updated_timestamp.cmp(&a.last_updated_timestamp));
+        ctx.run_interval(check_interval, move |act, _ctx| {
+            if !act.away && act.last_activity.elapsed() >= away_after {
+                act.away = true;
+                act.addr.do_send(SetAway { id: act.id, session_id: act.session_id, away: true });
+            }
+        });
+    }
 
-                                    addr.do_send(BroadcastMessage(WsMessage {
+    // Tells the client which feature flags are on for them
+    fn send_features(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        let db_pool = self.db_pool.clone();
+        let config = self.config.clone();
+        let user_id = self.id;
+        let addr = ctx.address();
+        ctx.spawn(wrap_future(async move {
+            match FeatureFlagService::enabled_for_user(&db_pool, &config, user_id).await {
+                Ok(features) => addr.do_send(BroadcastMessage(WsMessage {
+                    sender_id: Uuid::nil(),
+                    event: "features".to_string(),
+                    params: json!({ "features": features }),
+                })),
+                Err(e) => logln!("Failed to load feature flags for user {}: {}", user_id, e),
+            }
+        }));
+    }
+
+    // Handles one application message; runs with the message's request id current
+    fn handle_text(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        self.last_activity = Instant::now();
+        if self.away {
+            self.away = false;
+            self.addr.do_send(SetAway { id: self.id, session_id: self.session_id, away: false });
+        }
+
+        let limit = self.config.ws_message_limit_per_minute;
+        if limit > 0 {
+            if let Err(throttled) = self.message_limiter.check(self.session_id, limit, Instant::now()) {
+                if throttled.first_in_window {
+                    self.write(ctx, &WsMessage {
+                        sender_id: Uuid::nil(),
+                        event: "backpressure".to_string(),
+                        params: json!({
+                            "retry_after_ms": throttled.retry_after.as_millis() as u64,
+                            "limit_per_minute": limit
+                        }),
+                    });
+                }
+                return;
+            }
+        }
+
+        match serde_json::from_str::<WsMessage>(text) {
+            Ok(ws_message) => {
+                if let Err(e) = ws_schema::validate(&ws_message.event, &ws_message.params) {
+                    self.write_payload_error(ctx, &e);
+                    return;
+                }
+                // Process based on event type
+                match ws_message.event.as_str() {
+                    "ping" => {
+                        // Application-level keepalive; receiving it already reset the idle timer
+                        self.addr.do_send(Heartbeat { id: self.id, session_id: self.session_id });
+                        self.write(ctx, &WsMessage {
+                            sender_id: Uuid::nil(),
+                            event: "pong".to_string(),
+                            params: json!({
+                                "timestamp": Utc::now().timestamp_millis()
+                            }),
+                        });
+                    },
+                    "get_subscriptions" => {
+                        // What the server will deliver to this user, for debugging sync issues
+                        let server_addr = self.addr.clone();
+                        let addr = ctx.address();
+                        let user_id = self.id;
+                        ctx.spawn(wrap_future(request_id::inherit(async move {
+                            match server_addr.send(GetSubscriptions { user_id }).await {
+                                Ok(conversation_ids) => addr.do_send(BroadcastMessage(WsMessage {
+                                    sender_id: Uuid::nil(),
+                                    event: "subscriptions".to_string(),
+                                    params: json!({ "conversation_ids": conversation_ids }),
+                                })),
+                                Err(e) => logln!("Failed to fetch subscriptions for user {}: {}", user_id, e),
+                            }
+                        })));
+                    },
+                    "resubscribe" => {
+                        // Picks up conversations joined since connecting, e.g. over REST
+                        let server_addr = self.addr.clone();
+                        let db_pool = self.db_pool.clone();
+                        let user_id = self.id;
+                        let scope = self.scope.clone();
+                        let addr = ctx.address();
+                        ctx.spawn(wrap_future(request_id::inherit(async move {
+                            let conversation_ids = subscribe_visible_conversations(&server_addr, &db_pool, user_id, &scope).await;
+                            addr.do_send(BroadcastMessage(WsMessage {
+                                sender_id: Uuid::nil(),
+                                event: "subscriptions".to_string(),
+                                params: json!({ "conversation_ids": conversation_ids }),
+                            }));
+                        })));
+                    },
+                    "conversations" => {
+                        let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
+                        let include_details = matches!(
+                            serde_json::from_value(wrapped),
+                            Ok(WsEvent::Conversations { include_details: true })
+                        );
+                        let db_pool = self.db_pool.clone();
+                        let user_id = self.id;
+                        let scope = self.scope.clone();
+                        let addr = ctx.address();
+                        let future = async move {
+                            let sorted_conversations = visible_conversations(&db_pool, user_id, &scope).await;
+
+                            let params = if include_details {
+                                match ConversationService::get_inbox(&db_pool, user_id, sorted_conversations).await {
+                                    Ok(inbox) => json!(inbox),
+                                    Err(e) => {
+                                        logln!("Error fetching inbox details: {:?}", e);
+                                        addr.do_send(BroadcastMessage(WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "error".to_string(),
+                                            params: json!({
+                                                "message": "Error fetching conversations"
+                                            }),
+                                        }));
+                                        return;
+                                    }
+                                }
+                            } else {
+                                json!(sorted_conversations)
+                            };
+
+                            addr.do_send(BroadcastMessage(WsMessage {
+                                sender_id: Uuid::nil(),
+                                event: "conversations".to_string(),
+                                params,
+                            }));
+                        };
+                        ctx.spawn(wrap_future(request_id::inherit(future)));
+                    },
+                    "conversation_previews" => {
+                        // A compact inbox for low-bandwidth clients: no pets or profiles
+                        let db_pool = self.db_pool.clone();
+                        let user_id = self.id;
+                        let scope = self.scope.clone();
+                        let addr = ctx.address();
+                        ctx.spawn(wrap_future(request_id::inherit(async move {
+                            let conversations = visible_conversations(&db_pool, user_id, &scope).await;
+                            let message = match ConversationService::get_previews(&db_pool, user_id, conversations).await {
+                                Ok(previews) => WsMessage {
+                                    sender_id: Uuid::nil(),
+                                    event: "conversation_previews".to_string(),
+                                    params: json!({ "conversations": previews }),
+                                },
+                                Err(e) => {
+                                    logln!("Error fetching conversation previews: {:?}", e);
+                                    WsMessage {
                                         sender_id: Uuid::nil(),
-                                        event: "conversations".to_string(),
-                                        params: json!(sorted_conversations),
+                                        event: "error".to_string(),
+                                        params: json!({ "message": "Error fetching conversation previews" }),
+                                    }
+                                },
+                            };
+                            addr.do_send(BroadcastMessage(message));
+                        })));
+                    },
+                    "message" => {
+                        let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
+                        if let Ok(WsEvent::Message { conversation_id, content, canned_response_id, attachment_image_id, trace }) = serde_json::from_value(wrapped) {
+                            let received_at = Instant::now();
+                            let db_pool = self.db_pool.clone();
+                            let config = self.config.clone();
+                            let moderator = self.moderator.clone();
+                            let metrics = self.metrics.clone();
+                            let addr = self.addr.clone();
+                            // Errors go back to this session only, not through the server
+                            let session = ctx.address();
+                            let user_id = self.id;
+                            let timestamp = Utc::now();
+                            let future = async move {
+                                // Participants and users with a read_write share on the pet can post
+                                let can_send = matches!(
+                                    ConversationService::get_access(&db_pool, conversation_id, user_id).await,
+                                    Ok(Some(AccessLevel::ReadWrite))
+                                );
+
+                                if !can_send {
+                                    session.do_send(BroadcastMessage(WsMessage {
+                                        sender_id: Uuid::nil(),
+                                        event: "error".to_string(),
+                                        params: json!({
+                                            "message": "You are not authorized to send messages in this conversation"
+                                        }),
                                     }));
-                                };
-                                ctx.spawn(wrap_future(future));
-                            },
-                            "message" => {
-                                let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
-                                if let Ok(WsEvent::Message { conversation_id, content }) = serde_json::from_value(wrapped) {
-                                    let db_pool = self.db_pool.clone();
-                                    let sender_id = ws_message.sender_id;
-                                    let addr = self.addr.clone();
-                                    let user_id = self.id;
-                                    let timestamp = Utc::now();
-                                    let future = async move {
-                                        // Check if the user is part of this conversation
-                                        let user_role = match sqlx::query!(
-                                            "SELECT scope FROM users WHERE id = $1",
-                                            user_id
-                                        )
-                                        .fetch_optional(&**db_pool)
-                                        .await {
-                                            Ok(Some(record)) => record.scope,
-                                            _ => "unknown".to_string(),
-                                        };
-                                        
-                                        let can_send = match user_role.as_str() {
-                                            "client" => {
-                                                // Check if client is part of this conversation
-                                                match sqlx::query!(
-                                                    "SELECT id FROM conversations WHERE id = $1 AND client = $2",
-                                                    conversation_id,
-                                                    user_id
-                                                )
-                                                .fetch_optional(&**db_pool)
-                                                .await {
-                                                    Ok(Some(_)) => true,
-                                                    _ => false,
-                                                }
-                                            },
-                                            "provider" => {
-                                                // Check if provider is part of this conversation
-                                                match sqlx::query!(
-                                                    "SELECT id FROM conversations WHERE id = $1 AND $2 = ANY(providers)",
-                                                    conversation_id,
-                                                    user_id
-                                                )
-                                                .fetch_optional(&**db_pool)
-                                                .await {
-                                                    Ok(Some(_)) => true,
-                                                    _ => false,
-                                                }
-                                            },
-                                            _ => false,
-                                        };
-                                        
-                                        if !can_send {
-                                            addr.do_send(BroadcastMessage(WsMessage {
-                                                sender_id: Uuid::nil(),
-                                                event: "error".to_string(),
-                                                params: json!({
-                                                    "message": "You are not authorized to send messages in this conversation"
-                                                }),
-                                            }));
-                                            return;
-                                        }
-                                        
-                                        // First, ensure the user is subscribed to this conversation
-                                        addr.do_send(SubscribeToConversation {
-                                            user_id,
-                                            conversation_id,
-                                        });
-                                        
-                                        let result = ConversationService::send_message(
-                                            &db_pool,
-                                            sender_id,
-                                            conversation_id,
-                                            content,
-                                            timestamp,
-                                        ).await;
-
-                                        match result {
-                                            Ok(message) => {
-                                                let message_payload = json!({
-                                                    "id": message.id,
-                                                    "conversation_id": message.conversation_id,
-                                                    "sender_id": message.sender_id,
-                                                    "content": message.content,
-                                                    "timestamp": message.timestamp.timestamp_millis()
-                                                });
-                                                addr.do_send(BroadcastMessage(WsMessage {
-                                                    sender_id: Uuid::nil(),
-                                                    event: "message_sent".to_string(),
-                                                    params: message_payload,
-                                                }));
-                                            },
+                                    return;
+                                }
+
+                                // A canned response is expanded here, so providers can only use their own
+                                let content = match (content, canned_response_id) {
+                                    (_, Some(_)) if !FeatureFlagService::is_enabled(&db_pool, &config, user_id, feature_flags::CANNED_RESPONSES).await => {
+                                        session.do_send(BroadcastMessage(WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "error".to_string(),
+                                            params: json!({
+                                                "message": "Canned responses are not enabled"
+                                            }),
+                                        }));
+                                        return;
+                                    },
+                                    (_, Some(canned_response_id)) => {
+                                        match CannedResponseService::expand(&db_pool, user_id, canned_response_id).await {
+                                            Ok(body) => body,
                                             Err(e) => {
-                                                println!("Error sending message: {:?}", e);
-                                                addr.do_send(BroadcastMessage(WsMessage {
+                                                let (status, message) = match e {
+                                                    CannedResponseError::NotFound => (404, e.to_string()),
+                                                    CannedResponseError::Forbidden => (403, e.to_string()),
+                                                    CannedResponseError::Invalid(_) => (400, e.to_string()),
+                                                    CannedResponseError::Database(_) => {
+                                                        logln!("Error expanding canned response: {:?}", e);
+                                                        (500, "Error expanding canned response".to_string())
+                                                    },
+                                                };
+                                                session.do_send(BroadcastMessage(WsMessage {
                                                     sender_id: Uuid::nil(),
                                                     event: "error".to_string(),
-                                                                                                    params: json!({
-                                                    "message": format!("Error sending message: {:?}", e)
-                                                }),
+                                                    params: json!({
+                                                        "message": message,
+                                                        "status": status
+                                                    }),
                                                 }));
+                                                return;
                                             }
                                         }
-                                    };
-                                    ctx.spawn(wrap_future(future));
-                                } else {
-                                    ctx.text("Invalid message data format");
+                                    },
+                                    (Some(content), None) => content,
+                                    (None, None) => String::new(),
+                                };
+                                if let Err(message) = ConversationService::validate_message_content(&content) {
+                                    session.do_send(BroadcastMessage(WsMessage {
+                                        sender_id: Uuid::nil(),
+                                        event: "error".to_string(),
+                                        params: json!({
+                                            "message": message
+                                        }),
+                                    }));
+                                    return;
                                 }
-                            },
-                            "new_conversation" => {
-                                let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
-                                if let Ok(WsEvent::NewConversation { pet_id, providers }) = serde_json::from_value(wrapped) {
-                                    let db_pool = self.db_pool.clone();
-                                    let user_id = self.id;
-                                    let addr = self.addr.clone();
-                                    let future = async move {
-                                        // Check if the user is a client (only clients can create conversations)
-                                        let user_role = match sqlx::query!(
-                                            "SELECT scope FROM users WHERE id = $1",
+
+                                // Users can only attach images they uploaded themselves
+                                if let Some(image_id) = attachment_image_id {
+                                    let owns_image = matches!(
+                                        sqlx::query!(
+                                            "SELECT id FROM images WHERE id = $1 AND user_id = $2",
+                                            image_id,
                                             user_id
                                         )
                                         .fetch_optional(&**db_pool)
-                                        .await {
-                                            Ok(Some(record)) => record.scope,
-                                            _ => "unknown".to_string(),
-                                        };
-                                        
-                                        if user_role != "client" {
-                                            addr.do_send(BroadcastMessage(WsMessage {
+                                        .await,
+                                        Ok(Some(_))
+                                    );
+                                    if !owns_image {
+                                        session.do_send(BroadcastMessage(WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "error".to_string(),
+                                            params: json!({
+                                                "message": "Attachment not found"
+                                            }),
+                                        }));
+                                        return;
+                                    }
+                                }
+
+                                // First, ensure the user is subscribed to this conversation
+                                addr.do_send(SubscribeToConversation {
+                                    user_id,
+                                    conversation_id,
+                                });
+
+                                let result = ConversationService::send_message(
+                                    &db_pool,
+                                    &**moderator,
+                                    // The authenticated user, whatever sender_id the client put in the event
+                                    user_id,
+                                    conversation_id,
+                                    content,
+                                    timestamp,
+                                    attachment_image_id,
+                                    false,
+                                ).await;
+
+                                match result {
+                                    Ok(message) => {
+                                        let persisted_at = Instant::now();
+                                        let receive_to_persist = persisted_at - received_at;
+                                        metrics.observe_receive_to_persist(receive_to_persist);
+
+                                        // Same shape as messages in the history, timestamps included
+                                        let mut message_payload = json!(message);
+                                        if trace {
+                                            message_payload["delivery_trace"] = json!({
+                                                "receive_to_persist_us": receive_to_persist.as_micros() as u64
+                                            });
+                                        }
+                                        addr.do_send(BroadcastToConversation {
+                                            message: WsMessage {
                                                 sender_id: Uuid::nil(),
-                                                event: "error".to_string(),
-                                                                                            params: json!({
-                                                "message": "Only clients can create conversations"
+                                                event: "message_sent".to_string(),
+                                                params: message_payload,
+                                            },
+                                            conversation_id,
+                                            timing: Some(DeliveryTiming { persisted_at, trace }),
+                                        });
+                                    },
+                                    Err(SendMessageError::Blocked(rules)) => {
+                                        session.do_send(BroadcastMessage(WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "error".to_string(),
+                                            params: json!({
+                                                "code": "content_blocked",
+                                                "message": "Message blocked by content moderation",
+                                                "conversation_id": conversation_id,
+                                                "rules": rules
                                             }),
-                                            }));
-                                            return;
+                                        }));
+                                    },
+                                    Err(e) => {
+                                        logln!("Error sending message: {:?}", e);
+                                        session.do_send(BroadcastMessage(WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "error".to_string(),
+                                                                                            params: json!({
+                                            "message": "Error sending message"
+                                        }),
+                                        }));
+                                    }
+                                }
+                            };
+                            ctx.spawn(wrap_future(request_id::inherit(future)));
+                        } else {
+                            self.write_error(ctx, "invalid_params", "Invalid message data format");
+                        }
+                    },
+                    "new_conversation" => {
+                        let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
+                        if let Ok(WsEvent::NewConversation { pet_id, providers, organization_id }) = serde_json::from_value(wrapped) {
+                            let db_pool = self.db_pool.clone();
+                            let user_id = self.id;
+                            let is_client = self.scope == "client";
+                            let addr = self.addr.clone();
+                            // Replies go back to this session only, not through the server
+                            let session = ctx.address();
+                            let config = self.config.clone();
+                            let dedupe = self.config.dedupe_conversations;
+                            let limiter = self.conversation_limiter.clone();
+                            let future = async move {
+                                // Only clients can create conversations
+                                if !is_client {
+                                    session.do_send(BroadcastMessage(WsMessage {
+                                        sender_id: Uuid::nil(),
+                                        event: "error".to_string(),
+                                                                                    params: json!({
+                                        "message": "Only clients can create conversations"
+                                    }),
+                                    }));
+                                    return;
+                                }
+
+                                let limit = config.conversation_creation_limit_per_hour;
+                                if limit > 0 {
+                                    if let Err(throttled) = limiter.check(user_id, limit, Instant::now()) {
+                                        // Flag the account once per window rather than on every retry
+                                        if throttled.first_in_window {
+                                            logln!("Client {} exceeded {} new conversations per hour", user_id, limit);
+                                            AuditService::record(&db_pool, "conversation_rate_limited", None, Some(user_id), json!({
+                                                "limit_per_hour": limit
+                                            })).await;
                                         }
-                                        
-                                        let result = ConversationService::create_conversation(
-                                            &db_pool,
-                                            providers.clone().unwrap_or_default(),
+                                        session.do_send(BroadcastMessage(WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "error".to_string(),
+                                            params: json!({
+                                                "code": "conversation_rate_limited",
+                                                "message": "Too many new conversations, try again later",
+                                                "retry_after": throttled.retry_after.as_secs()
+                                            }),
+                                        }));
+                                        return;
+                                    }
+                                }
+
+                                let result = ConversationService::create_conversation(
+                                    &db_pool,
+                                    providers.clone().unwrap_or_default(),
+                                    organization_id,
+                                    user_id,
+                                    pet_id,
+                                    dedupe
+                                ).await;
+
+                                match result {
+                                    Ok((conversation, created)) => {
+                                        // Subscribe the client to the new conversation
+                                        addr.do_send(SubscribeToConversation {
                                             user_id,
-                                            pet_id
-                                        ).await;
-
-                                        match result {
-                                            Ok(conversation) => {
-                                                // Subscribe the client to the new conversation
-                                                addr.do_send(SubscribeToConversation {
-                                                    user_id,
-                                                    conversation_id: conversation.id,
-                                                });
-                                                
-                                                // Subscribe all providers to the conversation
-                                                if let Some(ref provider_ids) = providers {
-                                                    for _provider_id in provider_ids {
-                                                        addr.do_send(SubscribeToConversation {
-                                                            user_id: *_provider_id,
-                                                            conversation_id: conversation.id,
-                                                        });
-                                                    }
-                                                }
-                                                
-                                                // Notify the client about the new conversation
-                                                addr.do_send(BroadcastMessage(WsMessage {
+                                            conversation_id: conversation.id,
+                                        });
+
+                                        // The named providers plus whoever is currently in the organization
+                                        let mut provider_ids = providers.unwrap_or_default();
+                                        if let Some(organization_id) = organization_id {
+                                            match OrganizationService::member_ids(&db_pool, organization_id).await {
+                                                Ok(members) => provider_ids.extend(members.into_iter().filter(|id| !provider_ids.contains(id)).collect::<Vec<_>>()),
+                                                Err(e) => logln!("Error fetching members of organization {}: {:?}", organization_id, e),
+                                            }
+                                        }
+
+                                        // Subscribe all providers to the conversation
+                                        for provider_id in &provider_ids {
+                                            addr.do_send(SubscribeToConversation {
+                                                user_id: *provider_id,
+                                                conversation_id: conversation.id,
+                                            });
+                                        }
+
+                                        // Notify the client about the new conversation
+                                        session.do_send(BroadcastMessage(WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "conversation_created".to_string(),
+                                            params: json!(conversation),
+                                        }));
+
+                                        // Notify all providers about the new conversation, holding it for
+                                        // those who are offline. An existing conversation returned by
+                                        // dedupe was already announced. A conversation routed to one
+                                        // member goes to them alone; the rest see it in their inbox.
+                                        if created {
+                                            if let Some(assignee) = conversation.assigned_provider {
+                                                provider_ids = vec![assignee];
+                                                let content = format!("Routed to {}", display_name(&db_pool, assignee).await);
+                                                post_system_message(&addr, &db_pool, user_id, conversation.id, content).await;
+                                            }
+                                            for provider_id in &provider_ids {
+                                                send_or_queue(&addr, &db_pool, &config, *provider_id, WsMessage {
                                                     sender_id: Uuid::nil(),
-                                                    event: "conversation_created".to_string(),
-                                                    params: json!(conversation),
-                                                }));
-                                                
-                                                // Notify all providers about the new conversation
-                                                if let Some(ref provider_ids) = providers {
-                                                    for _provider_id in provider_ids {
-                                                        addr.do_send(BroadcastToConversation {
-                                                            message: WsMessage {
-                                                                sender_id: Uuid::nil(),
-                                                                event: "new_conversation_invitation".to_string(),
-                                                                params: json!(conversation.clone()),
-                                                            },
-                                                            conversation_id: conversation.id,
-                                                        });
-                                                    }
-                                                }
-                                            },
+                                                    event: "new_conversation_invitation".to_string(),
+                                                    params: json!(conversation.clone()),
+                                                }).await;
+                                            }
+                                        }
+                                    },
+                                    Err(e) => {
+                                        logln!("Error creating conversation: {:?}", e);
+                                        session.do_send(BroadcastMessage(WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "error".to_string(),
+                                                                                            params: json!({
+                                            "message": "Error creating conversation"
+                                        }),
+                                        }));
+                                    }
+                                }
+                            };
+                            ctx.spawn(wrap_future(request_id::inherit(future)));
+                        } else {
+                            self.write_error(ctx, "invalid_params", "Invalid new conversation data format");
+                        }
+                    },
+                    "conversation_history" => {
+                        let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
+                        if let Ok(WsEvent::ConversationHistory { conversation_id, page, limit, before, include_state }) = serde_json::from_value(wrapped) {
+                            let addr = ctx.address();
+                            let user_id = self.id;
+                            let server_addr = self.addr.clone();
+                            let db_pool = self.db_pool.clone();
+                            let max_history_offset = self.config.max_history_offset;
+
+                            let future = async move {
+                                // Participants and users the pet is shared with can read the history
+                                let can_access = matches!(
+                                    ConversationService::get_access(&db_pool, conversation_id, user_id).await,
+                                    Ok(Some(_))
+                                );
+
+                                if !can_access {
+                                    addr.do_send(BroadcastMessage(WsMessage {
+                                        sender_id: Uuid::nil(),
+                                        event: "error".to_string(),
+                                                                                    params: json!({
+                                        "message": "You are not authorized to access this conversation history"
+                                    }),
+                                    }));
+                                    return;
+                                }
+
+                                // Subscribe to the conversation when requesting history
+                                server_addr.do_send(SubscribeToConversation {
+                                    user_id,
+                                    conversation_id,
+                                });
+
+                                // Fetch real messages from database
+                                match ConversationService::get_conversation_messages(
+                                    &db_pool, conversation_id, page, limit, before, max_history_offset
+                                ).await {
+                                    Ok((messages, total_count, has_more)) => {
+                                        let read_state = match ConversationService::get_read_state(&db_pool, conversation_id, user_id).await {
+                                            Ok(read_state) => read_state,
                                             Err(e) => {
-                                                println!("Error creating conversation: {:?}", e);
+                                                logln!("Error fetching read position: {:?}", e);
                                                 addr.do_send(BroadcastMessage(WsMessage {
                                                     sender_id: Uuid::nil(),
                                                     event: "error".to_string(),
-                                                                                                    params: json!({
-                                                    "message": format!("Error creating conversation: {:?}", e)
-                                                }),
+                                                    params: json!({
+                                                        "message": "Error fetching conversation history"
+                                                    }),
                                                 }));
+                                                return;
                                             }
+                                        };
+                                        AccessLogService::record_in_background(
+                                            &db_pool, conversation_id, user_id, access_log::SOURCE_WEBSOCKET, &messages
+                                        );
+                                        let state = match include_state {
+                                            true => conversation_state(&server_addr, &db_pool, conversation_id, user_id).await,
+                                            false => None,
+                                        };
+                                        let response = ConversationHistoryResponse {
+                                            messages,
+                                            total_count,
+                                            has_more,
+                                            last_read_message_id: read_state.as_ref().and_then(|read| read.last_read_message_id),
+                                            last_read_at: read_state.map(|read| read.last_read_at),
+                                            state,
+                                        };
+                                        addr.do_send(BroadcastMessage(WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "conversation_history_response".to_string(),
+                                            params: json!(response),
+                                        }));
+                                    },
+                                    Err(e @ HistoryError::CursorRequired) => {
+                                        addr.do_send(BroadcastMessage(WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "error".to_string(),
+                                            params: json!({
+                                                "code": "cursor_required",
+                                                "message": e.to_string()
+                                            }),
+                                        }));
+                                    },
+                                    Err(HistoryError::Invalid(message)) => {
+                                        addr.do_send(BroadcastMessage(WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "error".to_string(),
+                                            params: json!({
+                                                "message": message
+                                            }),
+                                        }));
+                                    },
+                                    Err(e) => {
+                                        logln!("Error fetching conversation history: {:?}", e);
+                                        addr.do_send(BroadcastMessage(WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "error".to_string(),
+                                            params: json!({
+                                                "message": "Error fetching conversation history"
+                                            }),
+                                        }));
+                                    }
+                                }
+                            };
+                            ctx.spawn(wrap_future(request_id::inherit(future)));
+                        } else {
+                            self.write_error(ctx, "invalid_params", "Invalid conversation history data format");
+                        }
+                    },
+                    "conversation_timestamps" => {
+                        let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
+                        if let Ok(WsEvent::ConversationTimestamps { conversation_id }) = serde_json::from_value(wrapped) {
+                            let addr = ctx.address();
+                            let user_id = self.id;
+                            let db_pool = self.db_pool.clone();
+                            ctx.spawn(wrap_future(request_id::inherit(async move {
+                                let can_access = matches!(
+                                    ConversationService::get_access(&db_pool, conversation_id, user_id).await,
+                                    Ok(Some(_))
+                                );
+                                let timestamps = match can_access {
+                                    true => ConversationService::get_timestamps(&db_pool, conversation_id).await,
+                                    false => Ok(None),
+                                };
+
+                                let response = match timestamps {
+                                    Ok(Some((created_at, first_message_at))) => WsMessage {
+                                        sender_id: Uuid::nil(),
+                                        event: "conversation_timestamps".to_string(),
+                                        params: json!({
+                                            "conversation_id": conversation_id,
+                                            "created_at": created_at.timestamp_millis(),
+                                            "first_message_at": first_message_at.map(|t| t.timestamp_millis())
+                                        }),
+                                    },
+                                    Ok(None) => WsMessage {
+                                        sender_id: Uuid::nil(),
+                                        event: "error".to_string(),
+                                        params: json!({
+                                            "message": "You are not authorized to access this conversation"
+                                        }),
+                                    },
+                                    Err(e) => {
+                                        logln!("Error fetching conversation timestamps: {:?}", e);
+                                        WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "error".to_string(),
+                                            params: json!({
+                                                "message": "Error fetching conversation timestamps"
+                                            }),
                                         }
-                                    };
-                                    ctx.spawn(wrap_future(future));
-                                } else {
-                                    ctx.text("Invalid new conversation data format");
+                                    },
+                                };
+                                addr.do_send(BroadcastMessage(response));
+                            })));
+                        } else {
+                            self.write_error(ctx, "invalid_params", "Invalid conversation timestamps data format");
+                        }
+                    },
+                    "save_draft" => {
+                        let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
+                        if let Ok(WsEvent::SaveDraft { conversation_id, content }) = serde_json::from_value(wrapped) {
+                            let addr = ctx.address();
+                            let server = self.addr.clone();
+                            let user_id = self.id;
+                            let session_id = self.session_id;
+                            let db_pool = self.db_pool.clone();
+                            ctx.spawn(wrap_future(request_id::inherit(async move {
+                                match DraftService::save(&db_pool, conversation_id, user_id, &content).await {
+                                    // This device already has it; only the user's others need it
+                                    Ok(draft) => server.do_send(SendToOtherSessions {
+                                        user_id,
+                                        session_id,
+                                        message: WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "draft_updated".to_string(),
+                                            params: json!(draft),
+                                        },
+                                    }),
+                                    Err(e) => addr.do_send(BroadcastMessage(draft_error_event(e))),
                                 }
-                            },
-                            "conversation_history" => {
-                                let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
-                                if let Ok(WsEvent::ConversationHistory { conversation_id, page, limit }) = serde_json::from_value(wrapped) {
-                                    let addr = ctx.address();
-                                    let user_id = self.id;
-                                    let server_addr = self.addr.clone();
-                                    let db_pool = self.db_pool.clone();
-                                    
-                                    let future = async move {
-                                        // Check if the user is part of this conversation
-                                        let user_role = match sqlx::query!(
-                                            "SELECT scope FROM users WHERE id = $1",
-                                            user_id
-                                        )
-                                        .fetch_optional(&**db_pool)
-                                        .await {
-                                            Ok(Some(record)) => record.scope,
-                                            _ => "unknown".to_string(),
-                                        };
-                                        
-                                        let can_access = match user_role.as_str() {
-                                            "client" => {
-                                                // Check if client is part of this conversation
-                                                match sqlx::query!(
-                                                    "SELECT id FROM conversations WHERE id = $1 AND client = $2",
-                                                    conversation_id,
-                                                    user_id
-                                                )
-                                                .fetch_optional(&**db_pool)
-                                                .await {
-                                                    Ok(Some(_)) => true,
-                                                    _ => false,
-                                                }
+                            })));
+                        } else {
+                            self.write_error(ctx, "invalid_params", "Invalid draft data format");
+                        }
+                    },
+                    "get_message_stats" => {
+                        let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
+                        if let Ok(WsEvent::GetMessageStats { conversation_id }) = serde_json::from_value(wrapped) {
+                            let addr = ctx.address();
+                            let user_id = self.id;
+                            let db_pool = self.db_pool.clone();
+                            ctx.spawn(wrap_future(request_id::inherit(async move {
+                                let can_access = matches!(
+                                    ConversationService::get_access(&db_pool, conversation_id, user_id).await,
+                                    Ok(Some(_))
+                                );
+                                let response = match can_access {
+                                    true => match ConversationService::get_message_stats(&db_pool, conversation_id).await {
+                                        Ok(senders) => WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "message_stats".to_string(),
+                                            params: json!({
+                                                "conversation_id": conversation_id,
+                                                "senders": senders
+                                            }),
+                                        },
+                                        Err(e) => {
+                                            logln!("Error fetching message stats: {:?}", e);
+                                            WsMessage {
+                                                sender_id: Uuid::nil(),
+                                                event: "error".to_string(),
+                                                params: json!({
+                                                    "message": "Error fetching message stats"
+                                                }),
+                                            }
+                                        },
+                                    },
+                                    false => WsMessage {
+                                        sender_id: Uuid::nil(),
+                                        event: "error".to_string(),
+                                        params: json!({
+                                            "message": "You are not authorized to access this conversation"
+                                        }),
+                                    },
+                                };
+                                addr.do_send(BroadcastMessage(response));
+                            })));
+                        } else {
+                            self.write_error(ctx, "invalid_params", "Invalid message stats data format");
+                        }
+                    },
+                    "typing" => {
+                        let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
+                        if let Ok(WsEvent::Typing { conversation_id, is_typing }) = serde_json::from_value(wrapped) {
+                            let addr = ctx.address();
+                            let server = self.addr.clone();
+                            let user_id = self.id;
+                            let db_pool = self.db_pool.clone();
+                            ctx.spawn(wrap_future(request_id::inherit(async move {
+                                // Only those who can post can be typing; nothing is stored
+                                let can_send = matches!(
+                                    ConversationService::get_access(&db_pool, conversation_id, user_id).await,
+                                    Ok(Some(AccessLevel::ReadWrite))
+                                );
+                                if !can_send {
+                                    addr.do_send(BroadcastMessage(WsMessage {
+                                        sender_id: Uuid::nil(),
+                                        event: "error".to_string(),
+                                        params: json!({
+                                            "message": "You are not authorized to send messages in this conversation"
+                                        }),
+                                    }));
+                                    return;
+                                }
+                                server.do_send(BroadcastToConversation {
+                                    conversation_id,
+                                    message: WsMessage {
+                                        sender_id: Uuid::nil(),
+                                        event: "typing".to_string(),
+                                        params: json!({
+                                            "conversation_id": conversation_id,
+                                            "user_id": user_id,
+                                            "is_typing": is_typing
+                                        }),
+                                    },
+                                    timing: None,
+                                });
+                            })));
+                        } else {
+                            self.write_error(ctx, "invalid_params", "Invalid typing data format");
+                        }
+                    },
+                    "read" => {
+                        let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
+                        if let Ok(WsEvent::Read { conversation_id, up_to_timestamp }) = serde_json::from_value(wrapped) {
+                            let addr = ctx.address();
+                            let server = self.addr.clone();
+                            let user_id = self.id;
+                            let db_pool = self.db_pool.clone();
+                            ctx.spawn(wrap_future(request_id::inherit(async move {
+                                let can_access = matches!(
+                                    ConversationService::get_access(&db_pool, conversation_id, user_id).await,
+                                    Ok(Some(_))
+                                );
+                                if !can_access {
+                                    addr.do_send(BroadcastMessage(WsMessage {
+                                        sender_id: Uuid::nil(),
+                                        event: "error".to_string(),
+                                        params: json!({
+                                            "message": "You are not authorized to access this conversation"
+                                        }),
+                                    }));
+                                    return;
+                                }
+
+                                match ConversationService::mark_messages_read(&db_pool, conversation_id, user_id, up_to_timestamp).await {
+                                    // Nothing new to tell the others about
+                                    Ok(0) => {},
+                                    Ok(_) => server.do_send(BroadcastToConversation {
+                                        conversation_id,
+                                        message: WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "messages_read".to_string(),
+                                            params: json!({
+                                                "conversation_id": conversation_id,
+                                                "user_id": user_id,
+                                                "up_to_timestamp": up_to_timestamp.timestamp_millis()
+                                            }),
+                                        },
+                                        timing: None,
+                                    }),
+                                    Err(e) => {
+                                        logln!("Error marking messages read: {:?}", e);
+                                        addr.do_send(BroadcastMessage(WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "error".to_string(),
+                                            params: json!({
+                                                "message": "Error marking messages read"
+                                            }),
+                                        }));
+                                    },
+                                }
+                            })));
+                        } else {
+                            self.write_error(ctx, "invalid_params", "Invalid read data format");
+                        }
+                    },
+                    "delete_message" => {
+                        let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
+                        if let Ok(WsEvent::DeleteMessage { message_id }) = serde_json::from_value(wrapped) {
+                            let addr = ctx.address();
+                            let server = self.addr.clone();
+                            let user_id = self.id;
+                            let db_pool = self.db_pool.clone();
+                            ctx.spawn(wrap_future(request_id::inherit(async move {
+                                match ConversationService::delete_message(&db_pool, message_id, user_id).await {
+                                    Ok(Some(conversation_id)) => server.do_send(BroadcastToConversation {
+                                        conversation_id,
+                                        message: WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "message_deleted".to_string(),
+                                            params: json!({
+                                                "conversation_id": conversation_id,
+                                                "message_id": message_id
+                                            }),
+                                        },
+                                        timing: None,
+                                    }),
+                                    // Someone else's message, or already deleted
+                                    Ok(None) => addr.do_send(BroadcastMessage(WsMessage {
+                                        sender_id: Uuid::nil(),
+                                        event: "error".to_string(),
+                                        params: json!({
+                                            "message": "Message not found or not yours to delete"
+                                        }),
+                                    })),
+                                    Err(e) => {
+                                        logln!("Error deleting message: {:?}", e);
+                                        addr.do_send(BroadcastMessage(WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "error".to_string(),
+                                            params: json!({
+                                                "message": "Error deleting message"
+                                            }),
+                                        }));
+                                    },
+                                }
+                            })));
+                        } else {
+                            self.write_error(ctx, "invalid_params", "Invalid delete_message data format");
+                        }
+                    },
+                    "react" => {
+                        let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
+                        if let Ok(WsEvent::React { message_id, emoji }) = serde_json::from_value(wrapped) {
+                            let addr = ctx.address();
+                            let server = self.addr.clone();
+                            let user_id = self.id;
+                            let db_pool = self.db_pool.clone();
+                            ctx.spawn(wrap_future(request_id::inherit(async move {
+                                match ConversationService::add_reaction(&db_pool, message_id, user_id, &emoji).await {
+                                    Ok((conversation_id, true)) => server.do_send(BroadcastToConversation {
+                                        conversation_id,
+                                        message: WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "reaction_added".to_string(),
+                                            params: json!({
+                                                "conversation_id": conversation_id,
+                                                "message_id": message_id,
+                                                "user_id": user_id,
+                                                "emoji": emoji.trim()
+                                            }),
+                                        },
+                                        timing: None,
+                                    }),
+                                    // Already reacted with this emoji
+                                    Ok((_, false)) => {},
+                                    Err(e) => {
+                                        let message = match e {
+                                            ReactionError::Database(e) => {
+                                                logln!("Error updating reaction: {:?}", e);
+                                                "Error updating reaction".to_string()
                                             },
-                                            "provider" => {
-                                                // Check if provider is part of this conversation
-                                                match sqlx::query!(
-                                                    "SELECT id FROM conversations WHERE id = $1 AND $2 = ANY(providers)",
-                                                    conversation_id,
-                                                    user_id
-                                                )
-                                                .fetch_optional(&**db_pool)
-                                                .await {
-                                                    Ok(Some(_)) => true,
-                                                    _ => false,
-                                                }
+                                            e => e.to_string(),
+                                        };
+                                        addr.do_send(BroadcastMessage(WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "error".to_string(),
+                                            params: json!({ "message": message }),
+                                        }));
+                                    },
+                                }
+                            })));
+                        } else {
+                            self.write_error(ctx, "invalid_params", "Invalid react data format");
+                        }
+                    },
+                    "unreact" => {
+                        let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
+                        if let Ok(WsEvent::Unreact { message_id, emoji }) = serde_json::from_value(wrapped) {
+                            let addr = ctx.address();
+                            let server = self.addr.clone();
+                            let user_id = self.id;
+                            let db_pool = self.db_pool.clone();
+                            ctx.spawn(wrap_future(request_id::inherit(async move {
+                                match ConversationService::remove_reaction(&db_pool, message_id, user_id, &emoji).await {
+                                    Ok((conversation_id, true)) => server.do_send(BroadcastToConversation {
+                                        conversation_id,
+                                        message: WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "reaction_removed".to_string(),
+                                            params: json!({
+                                                "conversation_id": conversation_id,
+                                                "message_id": message_id,
+                                                "user_id": user_id,
+                                                "emoji": emoji.trim()
+                                            }),
+                                        },
+                                        timing: None,
+                                    }),
+                                    // No such reaction to take back
+                                    Ok((_, false)) => {},
+                                    Err(e) => {
+                                        let message = match e {
+                                            ReactionError::Database(e) => {
+                                                logln!("Error updating reaction: {:?}", e);
+                                                "Error updating reaction".to_string()
                                             },
-                                            _ => false,
+                                            e => e.to_string(),
                                         };
-                                        
-                                        if !can_access {
-                                            addr.do_send(BroadcastMessage(WsMessage {
+                                        addr.do_send(BroadcastMessage(WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "error".to_string(),
+                                            params: json!({ "message": message }),
+                                        }));
+                                    },
+                                }
+                            })));
+                        } else {
+                            self.write_error(ctx, "invalid_params", "Invalid unreact data format");
+                        }
+                    },
+                    "get_conversation_pet" => {
+                        let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
+                        if let Ok(WsEvent::GetConversationPet { conversation_id }) = serde_json::from_value(wrapped) {
+                            let addr = ctx.address();
+                            let user_id = self.id;
+                            let db_pool = self.db_pool.clone();
+                            ctx.spawn(wrap_future(request_id::inherit(async move {
+                                let can_access = matches!(
+                                    ConversationService::get_access(&db_pool, conversation_id, user_id).await,
+                                    Ok(Some(_))
+                                );
+                                let response = match can_access {
+                                    true => match ConversationService::get_pet(&db_pool, conversation_id).await {
+                                        Ok(Some(pet)) => WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "conversation_pet".to_string(),
+                                            params: json!({
+                                                "conversation_id": conversation_id,
+                                                "pet": pet
+                                            }),
+                                        },
+                                        Ok(None) => WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "error".to_string(),
+                                            params: json!({
+                                                "message": "Pet not found",
+                                                "status": 404
+                                            }),
+                                        },
+                                        Err(e) => {
+                                            logln!("Error fetching pet: {:?}", e);
+                                            WsMessage {
                                                 sender_id: Uuid::nil(),
                                                 event: "error".to_string(),
-                                                                                            params: json!({
-                                                "message": "You are not authorized to access this conversation history"
-                                            }),
-                                            }));
-                                            return;
-                                        }
-                                        
-                                        // Subscribe to the conversation when requesting history
-                                        server_addr.do_send(SubscribeToConversation {
-                                            user_id,
-                                            conversation_id,
-                                        });
-                                        
-                                        // Fetch real messages from database
-                                        match ConversationService::get_conversation_messages(
-                                            &db_pool, conversation_id, page, limit
-                                        ).await {
-                                            Ok((messages, total_count, has_more)) => {
-                                                addr.do_send(BroadcastMessage(WsMessage {
-                                                    sender_id: Uuid::nil(),
-                                                    event: "conversation_history_response".to_string(),
-                                                    params: json!({
-                                                        "messages": messages,
-                                                        "total_count": total_count,
-                                                        "has_more": has_more
-                                                    }),
-                                                }));
+                                                params: json!({
+                                                    "message": "Error fetching pet"
+                                                }),
+                                            }
+                                        },
+                                    },
+                                    false => WsMessage {
+                                        sender_id: Uuid::nil(),
+                                        event: "error".to_string(),
+                                        params: json!({
+                                            "message": "You are not authorized to access this conversation"
+                                        }),
+                                    },
+                                };
+                                addr.do_send(BroadcastMessage(response));
+                            })));
+                        } else {
+                            self.write_error(ctx, "invalid_params", "Invalid conversation pet data format");
+                        }
+                    },
+                    "open_conversation" => {
+                        let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
+                        if let Ok(WsEvent::OpenConversation { conversation_id, limit }) = serde_json::from_value(wrapped) {
+                            let addr = ctx.address();
+                            let server = self.addr.clone();
+                            let user_id = self.id;
+                            let db_pool = self.db_pool.clone();
+                            let max_history_offset = self.config.max_history_offset;
+                            ctx.spawn(wrap_future(request_id::inherit(async move {
+                                let error = |params: serde_json::Value| addr.do_send(BroadcastMessage(WsMessage {
+                                    sender_id: Uuid::nil(),
+                                    event: "error".to_string(),
+                                    params,
+                                }));
+
+                                // Same access as conversation_history
+                                let can_access = matches!(
+                                    ConversationService::get_access(&db_pool, conversation_id, user_id).await,
+                                    Ok(Some(_))
+                                );
+                                if !can_access {
+                                    error(json!({ "message": "You are not authorized to access this conversation" }));
+                                    return;
+                                }
+
+                                // Before loading, so nothing sent meanwhile is missed
+                                server.do_send(SubscribeToConversation { user_id, conversation_id });
+
+                                let limit = limit.unwrap_or(OPEN_CONVERSATION_LIMIT);
+                                match ConversationService::open(&db_pool, conversation_id, user_id, limit, max_history_offset).await {
+                                    Ok(Some(opened)) => {
+                                        AccessLogService::record_in_background(
+                                            &db_pool, conversation_id, user_id, access_log::SOURCE_WEBSOCKET, &opened.messages
+                                        );
+                                        addr.do_send(BroadcastMessage(WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "conversation_opened".to_string(),
+                                            params: json!(opened),
+                                        }));
+                                    },
+                                    Ok(None) => error(json!({ "message": "Pet not found", "status": 404 })),
+                                    Err(HistoryError::Invalid(message)) => error(json!({ "message": message })),
+                                    Err(e) => {
+                                        logln!("Error opening conversation: {:?}", e);
+                                        error(json!({ "message": "Error opening conversation" }));
+                                    },
+                                }
+                            })));
+                        } else {
+                            self.write_error(ctx, "invalid_params", "Invalid open_conversation data format");
+                        }
+                    },
+                    "get_note" => {
+                        let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
+                        if let Ok(WsEvent::GetNote { conversation_id }) = serde_json::from_value(wrapped) {
+                            let addr = ctx.address();
+                            let user_id = self.id;
+                            let db_pool = self.db_pool.clone();
+                            ctx.spawn(wrap_future(request_id::inherit(async move {
+                                let response = match NoteService::get_for_user(&db_pool, conversation_id, user_id).await {
+                                    Ok(note) => WsMessage {
+                                        sender_id: Uuid::nil(),
+                                        event: "note".to_string(),
+                                        params: json!(note),
+                                    },
+                                    Err(e) => note_error_event(e),
+                                };
+                                addr.do_send(BroadcastMessage(response));
+                            })));
+                        } else {
+                            self.write_error(ctx, "invalid_params", "Invalid get note data format");
+                        }
+                    },
+                    "update_note" => {
+                        let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
+                        if let Ok(WsEvent::UpdateNote { conversation_id, content, version }) = serde_json::from_value(wrapped) {
+                            let addr = ctx.address();
+                            let server = self.addr.clone();
+                            let user_id = self.id;
+                            let db_pool = self.db_pool.clone();
+                            ctx.spawn(wrap_future(request_id::inherit(async move {
+                                match NoteService::update(&db_pool, conversation_id, user_id, &content, version).await {
+                                    // The editor is subscribed, so they get their own copy too
+                                    Ok(note) => server.do_send(BroadcastToConversation {
+                                        message: WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "note_updated".to_string(),
+                                            params: json!(note),
+                                        },
+                                        conversation_id,
+                                        timing: None,
+                                    }),
+                                    Err(e) => addr.do_send(BroadcastMessage(note_error_event(e))),
+                                }
+                            })));
+                        } else {
+                            self.write_error(ctx, "invalid_params", "Invalid update note data format");
+                        }
+                    },
+                    "set_providers" => {
+                        let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
+                        if let Ok(WsEvent::SetProviders { conversation_id, providers }) = serde_json::from_value(wrapped) {
+                            let addr = ctx.address();
+                            let server = self.addr.clone();
+                            let user_id = self.id;
+                            let db_pool = self.db_pool.clone();
+                            let config = self.config.clone();
+                            ctx.spawn(wrap_future(request_id::inherit(async move {
+                                let (conversation, previous) = match ConversationService::set_providers(&db_pool, user_id, conversation_id, providers).await {
+                                    Ok(result) => result,
+                                    Err(e) => {
+                                        let message = match e {
+                                            SetProvidersError::Database(e) => {
+                                                logln!("Error setting providers of conversation {}: {:?}", conversation_id, e);
+                                                "Error setting providers".to_string()
                                             },
-                                            Err(e) => {
-                                                println!("Error fetching conversation history: {:?}", e);
-                                                addr.do_send(BroadcastMessage(WsMessage {
+                                            e => e.to_string(),
+                                        };
+                                        addr.do_send(BroadcastMessage(WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "error".to_string(),
+                                            params: json!({ "message": message }),
+                                        }));
+                                        return;
+                                    },
+                                };
+
+                                let added: Vec<Uuid> = conversation.providers.iter().filter(|id| !previous.contains(id)).copied().collect();
+                                let mut removed: Vec<Uuid> = previous.into_iter().filter(|id| !conversation.providers.contains(id)).collect();
+                                // Organization members keep following it through their membership
+                                if let Some(organization_id) = conversation.organization_id {
+                                    match OrganizationService::member_ids(&db_pool, organization_id).await {
+                                        Ok(members) => removed.retain(|id| !members.contains(id)),
+                                        Err(e) => logln!("Error fetching members of organization {}: {:?}", organization_id, e),
+                                    }
+                                }
+
+                                server.do_send(ReconcileProviders {
+                                    conversation_id,
+                                    added: added.clone(),
+                                    removed: removed.clone(),
+                                    message: WsMessage {
+                                        sender_id: Uuid::nil(),
+                                        event: "providers_updated".to_string(),
+                                        params: json!({
+                                            "conversation_id": conversation_id,
+                                            "providers": conversation.providers,
+                                            "added": added,
+                                            "removed": removed,
+                                            "assigned_provider": conversation.assigned_provider,
+                                            "updated_by": user_id
+                                        }),
+                                    },
+                                });
+                                for provider_id in added {
+                                    queue_if_offline(&server, &db_pool, &config, provider_id, WsMessage {
+                                        sender_id: Uuid::nil(),
+                                        event: "new_conversation_invitation".to_string(),
+                                        params: json!(conversation.clone()),
+                                    }).await;
+                                }
+                            })));
+                        } else {
+                            self.write_error(ctx, "invalid_params", "Invalid set providers data format");
+                        }
+                    },
+                    "mark_read" => {
+                        let wrapped = json!({"event": ws_message.event, "data": ws_message.params});
+                        if let Ok(WsEvent::MarkRead { conversation_id, message_id }) = serde_json::from_value(wrapped) {
+                            let addr = ctx.address();
+                            let user_id = self.id;
+                            let db_pool = self.db_pool.clone();
+                            ctx.spawn(wrap_future(request_id::inherit(async move {
+                                let can_access = matches!(
+                                    ConversationService::get_access(&db_pool, conversation_id, user_id).await,
+                                    Ok(Some(_))
+                                );
+                                let marked = match can_access {
+                                    true => ConversationService::mark_read(&db_pool, conversation_id, user_id, message_id).await,
+                                    false => Ok(false),
+                                };
+
+                                let response = match marked {
+                                    Ok(true) => WsMessage {
+                                        sender_id: Uuid::nil(),
+                                        event: "read_marked".to_string(),
+                                        params: json!({
+                                            "conversation_id": conversation_id,
+                                            "message_id": message_id
+                                        }),
+                                    },
+                                    Ok(false) => WsMessage {
+                                        sender_id: Uuid::nil(),
+                                        event: "error".to_string(),
+                                        params: json!({
+                                            "message": "Message not found in this conversation"
+                                        }),
+                                    },
+                                    Err(e) => {
+                                        logln!("Error marking conversation read: {:?}", e);
+                                        WsMessage {
+                                            sender_id: Uuid::nil(),
+                                            event: "error".to_string(),
+                                            params: json!({
+                                                "message": "Error marking conversation read"
+                                            }),
+                                        }
+                                    },
+                                };
+                                addr.do_send(BroadcastMessage(response));
+                            })));
+                        } else {
+                            self.write_error(ctx, "invalid_params", "Invalid mark read data format");
+                        }
+                    },
+                    "subscribe_conversation" => {
+                        if let Some(conversation_id) = ws_message.params.get("conversation_id") {
+                            if let Ok(conversation_id) = serde_json::from_value::<Uuid>(conversation_id.clone()) {
+                                // A reconnecting client passes the last event_seq it saw to catch up.
+                                // Only members may see what they missed, so that waits on the access check;
+                                // subscribing alongside it keeps live events from arriving twice.
+                                match ws_message.params.get("last_event_seq").and_then(|seq| seq.as_u64()) {
+                                    Some(last_event_seq) => {
+                                        let session = ctx.address();
+                                        let server = self.addr.clone();
+                                        let (user_id, session_id) = (self.id, self.session_id);
+                                        let db_pool = self.db_pool.clone();
+                                        ctx.spawn(wrap_future(request_id::inherit(async move {
+                                            let can_access = matches!(
+                                                ConversationService::get_access(&db_pool, conversation_id, user_id).await,
+                                                Ok(Some(_))
+                                            );
+                                            if !can_access {
+                                                session.do_send(BroadcastMessage(WsMessage {
                                                     sender_id: Uuid::nil(),
                                                     event: "error".to_string(),
-                                                    params: json!({
-                                                        "message": format!("Error fetching conversation history: {:?}", e)
-                                                    }),
+                                                    params: json!({ "message": "You are not authorized to access this conversation" }),
                                                 }));
+                                                return;
                                             }
+                                            server.do_send(SubscribeToConversation { user_id, conversation_id });
+                                            server.do_send(ReplayConversation { user_id, session_id, conversation_id, last_event_seq });
+                                        })));
+                                    },
+                                    None => self.addr.do_send(SubscribeToConversation {
+                                        user_id: self.id,
+                                        conversation_id,
+                                    }),
+                                }
+
+                                // Fetch user profile data and notify others, once until they unsubscribe
+                                let user_id = self.id;
+                                let db_pool = self.db_pool.clone();
+                                let addr = self.addr.clone();
+                                let announce = self.announced_conversations.insert(conversation_id);
+
+                                let future = async move {
+                                    // Get user profile data
+                                    let user_profile = match sqlx::query!(
+                                        "SELECT first_name, last_name, profile_image_url FROM users WHERE id = $1",
+                                        user_id
+                                    )
+                                    .fetch_one(&**db_pool)
+                                    .await {
+                                        Ok(profile) => profile,
+                                        Err(e) => {
+                                            logln!("Error fetching user profile: {:?}", e);
+                                            return;
                                         }
                                     };
-                                    ctx.spawn(wrap_future(future));
-                                } else {
-                                    ctx.text("Invalid conversation history data format");
-                                }
-                            },
-                            "subscribe_conversation" => {
-                                if let Some(conversation_id) = ws_message.params.get("conversation_id") {
-                                    if let Ok(conversation_id) = serde_json::from_value::<Uuid>(conversation_id.clone()) {
-                                        self.addr.do_send(SubscribeToConversation {
-                                            user_id: self.id,
-                                            conversation_id,
-                                        });
-                                        
-                                        // Fetch user profile data and notify others
-                                        let user_id = self.id;
-                                        let db_pool = self.db_pool.clone();
-                                        let addr = self.addr.clone();
-                                        
-                                        let future = async move {
-                                            // Get user profile data
-                                            let user_profile = match sqlx::query!(
-                                                "SELECT first_name, last_name, profile_image_url FROM users WHERE id = $1",
-                                                user_id
-                                            )
-                                            .fetch_one(&**db_pool)
-                                            .await {
-                                                Ok(profile) => profile,
-                                                Err(e) => {
-                                                    println!("Error fetching user profile: {:?}", e);
-                                                    return;
-                                                }
-                                            };
-                                            
-                                            // Create a display name from first and last name
-                                            let display_name = match (user_profile.first_name.as_ref(), user_profile.last_name.as_ref()) {
-                                                (Some(first), Some(last)) => format!("{} {}", first, last),
-                                                (Some(first), None) => first.clone(),
-                                                (None, Some(last)) => last.clone(),
-                                                (None, None) => "Unknown User".to_string(),
-                                            };
-                                            
-                                            // Send a system message to the conversation about the user joining
-                                            addr.do_send(BroadcastToConversation {
-                                                conversation_id,
-                                                message: WsMessage {
-                                                    sender_id: Uuid::nil(), // System message
-                                                    event: "user_joined".to_string(),
-                                                    params: json!({
-                                                        "user_id": user_id,
-                                                        "display_name": display_name,
-                                                        "profile_image_url": user_profile.profile_image_url,
-                                                        "conversation_id": conversation_id,
-                                                        "timestamp": Utc::now().timestamp_millis()
-                                                    }),
-                                                },
-                                            });
-                                        };
-                                        
-                                        ctx.spawn(wrap_future(future));
-                                        
-                                        ctx.text(serde_json::to_string(&WsMessage {
-                                            sender_id: Uuid::nil(),
-                                            event: "subscribed".to_string(),
-                                            params: json!({
-                                                "conversation_id": conversation_id,
-                                                "status": "success"
-                                            }),
-                                        }).unwrap());
-                                    } else {
-                                        ctx.text("Invalid conversation ID format");
-                                    }
-                                } else {
-                                    ctx.text("Missing conversation_id parameter");
-                                }
-                            },
-                            "unsubscribe_conversation" => {
-                                if let Some(conversation_id) = ws_message.params.get("conversation_id") {
-                                    if let Ok(conversation_id) = serde_json::from_value::<Uuid>(conversation_id.clone()) {
-                                        self.addr.do_send(UnsubscribeFromConversation {
-                                            user_id: self.id,
-                                            conversation_id,
-                                        });
-                                        
-                                        // Fetch user profile data and notify others about the user leaving
-                                        let user_id = self.id;
-                                        let db_pool = self.db_pool.clone();
-                                        let addr = self.addr.clone();
-                                        
-                                        let future = async move {
-                                            // Get user profile data
-                                            let user_profile = match sqlx::query!(
-                                                "SELECT first_name, last_name, profile_image_url FROM users WHERE id = $1",
-                                                user_id
-                                            )
-                                            .fetch_one(&**db_pool)
-                                            .await {
-                                                Ok(profile) => profile,
-                                                Err(e) => {
-                                                    println!("Error fetching user profile: {:?}", e);
-                                                    return;
-                                                }
-                                            };
-                                            
-                                            // Create a display name from first and last name
-                                            let display_name = match (user_profile.first_name.as_ref(), user_profile.last_name.as_ref()) {
-                                                (Some(first), Some(last)) => format!("{} {}", first, last),
-                                                (Some(first), None) => first.clone(),
-                                                (None, Some(last)) => last.clone(),
-                                                (None, None) => "Unknown User".to_string(),
-                                            };
-                                            
-                                            // Send a system message to the conversation about the user leaving
-                                            addr.do_send(BroadcastToConversation {
-                                                conversation_id,
-                                                message: WsMessage {
-                                                    sender_id: Uuid::nil(), // System message
-                                                    event: "user_left".to_string(),
-                                                    params: json!({
-                                                        "user_id": user_id,
-                                                        "display_name": display_name,
-                                                        "profile_image_url": user_profile.profile_image_url,
-                                                        "conversation_id": conversation_id,
-                                                        "timestamp": Utc::now().timestamp_millis(),
-                                                        "reason": "unsubscribed"
-                                                    }),
-                                                },
-                                            });
-                                        };
-                                        
-                                        ctx.spawn(wrap_future(future));
-                                        
-                                        ctx.text(serde_json::to_string(&WsMessage {
-                                            sender_id: Uuid::nil(),
-                                            event: "unsubscribed".to_string(),
+
+                                    // Create a display name from first and last name
+                                    let display_name = match (user_profile.first_name.as_ref(), user_profile.last_name.as_ref()) {
+                                        (Some(first), Some(last)) => format!("{} {}", first, last),
+                                        (Some(first), None) => first.clone(),
+                                        (None, Some(last)) => last.clone(),
+                                        (None, None) => "Unknown User".to_string(),
+                                    };
+
+                                    // Send a system message to the conversation about the user joining
+                                    addr.do_send(BroadcastToConversation {
+                                        conversation_id,
+                                        message: WsMessage {
+                                            sender_id: Uuid::nil(), // System message
+                                            event: "user_joined".to_string(),
                                             params: json!({
+                                                "user_id": user_id,
+                                                "display_name": display_name,
+                                                "profile_image_url": user_profile.profile_image_url,
                                                 "conversation_id": conversation_id,
-                                                "status": "success"
+                                                "timestamp": Utc::now().timestamp_millis()
                                             }),
-                                        }).unwrap());
-                                    } else {
-                                        ctx.text("Invalid conversation ID format");
-                                    }
-                                } else {
-                                    ctx.text("Missing conversation_id parameter");
+                                        },
+                                        timing: None,
+                                    });
+                                };
+
+                                if announce {
+                                    ctx.spawn(wrap_future(request_id::inherit(future)));
                                 }
-                            },
-                            _ => {
-                                ctx.text("Unknown event type");
+
+                                self.write(ctx, &WsMessage {
+                                    sender_id: Uuid::nil(),
+                                    event: "subscribed".to_string(),
+                                    params: json!({
+                                        "conversation_id": conversation_id,
+                                        "status": "success"
+                                    }),
+                                });
+                            } else {
+                                self.write_error(ctx, "invalid_params", "Invalid conversation ID format");
                             }
+                        } else {
+                            self.write_error(ctx, "invalid_params", "Missing conversation_id parameter");
                         }
                     },
-                    Err(e) => {
-                        // Detailed error logging
-                        println!("Failed to parse WebSocket message: {}", e);
-                        println!("Message causing error: {}", text);
-                        
-                        // Try to determine what part is failing
-                        if let Ok(raw_json) = serde_json::from_str::<serde_json::Value>(&text) {
-                            println!("JSON is valid, but doesn't match WsMessage structure");
-                            println!("Expected structure: sender_id (UUID), event (String), params (Object)");
-                            println!("Received structure: {:?}", raw_json);
-                            
-                            // Check for specific missing fields
-                            if !raw_json.get("sender_id").is_some() {
-                                println!("Missing 'sender_id' field");
-                            }
-                            if !raw_json.get("event").is_some() {
-                                println!("Missing 'event' field");
-                            }
-                            if !raw_json.get("params").is_some() {
-                                println!("Missing 'params' field");
+                    "unsubscribe_conversation" => {
+                        if let Some(conversation_id) = ws_message.params.get("conversation_id") {
+                            if let Ok(conversation_id) = serde_json::from_value::<Uuid>(conversation_id.clone()) {
+                                self.addr.do_send(UnsubscribeFromConversation {
+                                    user_id: self.id,
+                                    conversation_id,
+                                });
+                                self.announced_conversations.remove(&conversation_id);
+
+                                // Fetch user profile data and notify others about the user leaving
+                                let user_id = self.id;
+                                let db_pool = self.db_pool.clone();
+                                let addr = self.addr.clone();
+
+                                let future = async move {
+                                    // Get user profile data
+                                    let user_profile = match sqlx::query!(
+                                        "SELECT first_name, last_name, profile_image_url FROM users WHERE id = $1",
+                                        user_id
+                                    )
+                                    .fetch_one(&**db_pool)
+                                    .await {
+                                        Ok(profile) => profile,
+                                        Err(e) => {
+                                            logln!("Error fetching user profile: {:?}", e);
+                                            return;
+                                        }
+                                    };
+
+                                    // Create a display name from first and last name
+                                    let display_name = match (user_profile.first_name.as_ref(), user_profile.last_name.as_ref()) {
+                                        (Some(first), Some(last)) => format!("{} {}", first, last),
+                                        (Some(first), None) => first.clone(),
+                                        (None, Some(last)) => last.clone(),
+                                        (None, None) => "Unknown User".to_string(),
+                                    };
+
+                                    // Send a system message to the conversation about the user leaving
+                                    addr.do_send(BroadcastToConversation {
+                                        conversation_id,
+                                        message: WsMessage {
+                                            sender_id: Uuid::nil(), // System message
+                                            event: "user_left".to_string(),
+                                            params: json!({
+                                                "user_id": user_id,
+                                                "display_name": display_name,
+                                                "profile_image_url": user_profile.profile_image_url,
+                                                "conversation_id": conversation_id,
+                                                "timestamp": Utc::now().timestamp_millis(),
+                                                "reason": "unsubscribed"
+                                            }),
+                                        },
+                                        timing: None,
+                                    });
+                                };
+
+                                ctx.spawn(wrap_future(request_id::inherit(future)));
+
+                                self.write(ctx, &WsMessage {
+                                    sender_id: Uuid::nil(),
+                                    event: "unsubscribed".to_string(),
+                                    params: json!({
+                                        "conversation_id": conversation_id,
+                                        "status": "success"
+                                    }),
+                                });
+                            } else {
+                                self.write_error(ctx, "invalid_params", "Invalid conversation ID format");
                             }
                         } else {
-                            println!("JSON is invalid or malformed");
+                            self.write_error(ctx, "invalid_params", "Missing conversation_id parameter");
                         }
-                        
-                        ctx.text(format!("Invalid message format: {}", e));
+                    },
+                    _ => {
+                        self.write_error(ctx, "unknown_event", "Unknown event type");
+                    }
+                }
+            },
+            Err(e) => {
+                self.write_error(ctx, "invalid_message", &format!("Invalid message format: {}", e));
+            }
+        }
+    }
+}
+
+impl Actor for WsSession {
+    type Context = ws::WebsocketContext<Self>;
+
+    // Called when the actor starts
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.start_idle_check(ctx);
+        self.start_away_check(ctx);
+        self.send_features(ctx);
+
+        // Register self in the server
+        self.addr
+            .send(Connect {
+                addr: ctx.address().recipient(),
+                deliver: ctx.address().recipient(),
+                close: ctx.address().recipient(),
+                id: self.id,
+                session_id: self.session_id,
+                family_id: self.family_id,
+            })
+            .into_actor(self)
+            .then(|_res, act, ctx| {
+                // Auto-subscribe to all conversations the user is part of
+                let db_pool = act.db_pool.clone();
+                let user_id = act.id;
+                let scope = act.scope.clone();
+                let addr = act.addr.clone();
+                let session = ctx.address();
+                
+                async move {
+                    subscribe_visible_conversations(&addr, &db_pool, user_id, &scope).await;
+
+                    // Then deliver what was sent directly to the user while they were offline
+                    match PendingEventService::take_undelivered(&db_pool, user_id).await {
+                        Ok(events) if events.is_empty() => {},
+                        Ok(events) => session.do_send(BroadcastMessage(WsMessage {
+                            sender_id: Uuid::nil(),
+                            event: "pending_events".to_string(),
+                            params: json!({ "events": events }),
+                        })),
+                        Err(e) => logln!("Failed to load pending events for user {}: {}", user_id, e),
                     }
                 }
+                .into_actor(act)
+            })
+            .wait(ctx);
+    }
+
+    // Called when the actor stops
+    fn stopping(&mut self, _ctx: &mut Self::Context) -> Running {
+        // Unregister self from the server
+        self.addr.do_send(Disconnect { id: self.id, session_id: self.session_id });
+        Running::Stop
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsSession {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut ws::WebsocketContext<Self>) {
+        match msg {
+            Ok(ws::Message::Ping(msg)) => {
+                self.addr.do_send(Heartbeat { id: self.id, session_id: self.session_id });
+                ctx.pong(&msg);
+            }
+            Ok(ws::Message::Pong(_)) => {
+                self.addr.do_send(Heartbeat { id: self.id, session_id: self.session_id });
+            }
+            Ok(ws::Message::Text(text)) => {
+                let id = request_id::accept_or_generate(
+                    serde_json::from_str::<InboundRequestId>(&text).ok().and_then(|inbound| inbound.request_id).as_deref(),
+                );
+                request_id::sync_scope(id, || self.handle_text(&text, ctx));
             }
             Ok(ws::Message::Binary(_)) => {
-                ctx.text("Binary messages are not supported");
+                self.write_error(ctx, "unsupported_frame", "Binary messages are not supported");
             }
             Ok(ws::Message::Close(reason)) => {
                 ctx.close(reason);
@@ -827,7 +2766,36 @@ impl Handler<BroadcastMessage> for WsSession {
     type Result = ();
 
     fn handle(&mut self, msg: BroadcastMessage, ctx: &mut Self::Context) {
-        ctx.text(serde_json::to_string(&msg.0).unwrap());
+        self.write(ctx, &msg.0);
+    }
+}
+
+impl Handler<DeliverMessage> for WsSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: DeliverMessage, ctx: &mut Self::Context) {
+        let mut message = msg.message;
+        let elapsed = msg.timing.persisted_at.elapsed();
+        if msg.timing.trace {
+            if let Some(trace) = message.params.get_mut("delivery_trace").and_then(|trace| trace.as_object_mut()) {
+                trace.insert("persist_to_deliver_us".to_string(), json!(elapsed.as_micros() as u64));
+            }
+        }
+        self.write(ctx, &message);
+        self.metrics.observe_persist_to_deliver(elapsed);
+    }
+}
+
+impl Handler<CloseSession> for WsSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: CloseSession, ctx: &mut Self::Context) {
+        logln!("Closing session {} of user {}: {}", self.session_id, self.id, msg.reason);
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Policy,
+            description: Some(msg.reason.to_string()),
+        }));
+        ctx.stop();
     }
 }
 
@@ -836,13 +2804,20 @@ impl Handler<BroadcastMessage> for WsSession {
 // -----------------------
 
 #[get("/ws/")]
+#[allow(clippy::too_many_arguments)]
 pub async fn websocket_route(
     req: HttpRequest,
     stream: actix_web::web::Payload,
     srv: actix_web::web::Data<Addr<WsServer>>,
     pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    moderator: web::Data<dyn MessageModerator>,
+    metrics: web::Data<DeliveryMetrics>,
+    conversation_limiter: web::Data<ConversationRateLimiter>,
+    message_limiter: web::Data<SessionMessageLimiter>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    // Extract token from query parameters
+    // Browsers can't set headers on a WebSocket, so the token may come in the
+    // query string; other clients can send the usual Authorization header
     let token = req.uri().query()
         .and_then(|query| {
             url::form_urlencoded::parse(query.as_bytes())
@@ -850,35 +2825,525 @@ pub async fn websocket_route(
                 .map(|(_, value)| value.to_string())
         });
 
-    let user_id = match token {
-        Some(token) => {
-            // Verify and decode the token
-            match crate::utils::verify_and_decode_token(&token) {
-                Ok(claims) => {
-                    match Uuid::parse_str(claims.get_sub()) {
-                        Ok(user_id) => user_id,
-                        Err(_) => {
-                            return Ok(HttpResponse::Unauthorized().body("Invalid user ID in token"));
-                        }
-                    }
-                }
-                Err(_) => {
-                    return Ok(HttpResponse::Unauthorized().body("Invalid token"));
-                }
-            }
-        }
+    let user = match token {
+        Some(token) => AuthenticatedUser::from_token(&req, &token),
+        None if req.headers().contains_key(header::AUTHORIZATION) => AuthenticatedUser::authenticate(&req),
         None => {
-            return Ok(HttpResponse::Unauthorized().body("Missing token parameter"));
+            return Err(ApiError::Unauthorized("Missing token parameter or Authorization header".to_string()).into());
+        }
+    };
+    let AuthenticatedUser { user_id, scope, claims } = user.map_err(ApiError::from)?;
+    let (family_id, jti) = (claims.get_family(), claims.get_jti());
+
+    if let Some(jti) = jti {
+        if RevokedTokenService::is_token_revoked(&pool, jti).await.map_err(ApiError::from)? {
+            return Err(ApiError::Unauthorized("Token has been revoked".to_string()).into());
         }
+    }
+
+    // Access tokens stay valid until they expire, but a revoked device can't open new sockets
+    if let Some(family_id) = family_id {
+        if SessionService::is_family_revoked(&pool, family_id).await.map_err(ApiError::from)? {
+            return Err(ApiError::Unauthorized("Session has been revoked".to_string()).into());
+        }
+    }
+
+    let protocol_header = req.headers().get(header::SEC_WEBSOCKET_PROTOCOL).and_then(|value| value.to_str().ok());
+    let protocol = match ProtocolVersion::negotiate(protocol_header) {
+        Ok(protocol) => protocol,
+        Err(offered) => {
+            logln!("Closing socket of user {}: unsupported protocol {:?}", user_id, protocol_header);
+            return ws::WsResponseBuilder::new(UnsupportedProtocolSession, &req, stream)
+                .protocols(&[offered.as_str()])
+                .start();
+        },
+    };
+    // Clients that didn't ask for a protocol mustn't be sent one
+    let protocols = match protocol_header {
+        Some(_) => vec![protocol.name()],
+        None => vec![],
     };
 
-    ws::start(
+    ws::WsResponseBuilder::new(
         WsSession {
             id: user_id,
+            session_id: Uuid::new_v4(),
+            family_id,
+            scope,
             addr: srv.get_ref().clone(),
             db_pool: pool,
+            config,
+            moderator,
+            metrics,
+            conversation_limiter,
+            message_limiter,
+            last_activity: Instant::now(),
+            away: false,
+            protocol,
+            frames_sent: 0,
+            announced_conversations: HashSet::new(),
         },
         &req,
         stream,
     )
+    .protocols(&protocols)
+    .start()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn event(seq: u64) -> WsMessage {
+        WsMessage {
+            sender_id: Uuid::nil(),
+            event: "message_sent".to_string(),
+            params: json!({ "event_seq": seq }),
+        }
+    }
+
+    fn seqs(events: Vec<WsMessage>) -> Vec<u64> {
+        events.iter().map(|e| e.params["event_seq"].as_u64().unwrap()).collect()
+    }
+
+    #[test]
+    fn replays_events_after_last_seen_seq() {
+        let now = Utc::now();
+        let mut buffer = ReplayBuffer::new(10);
+        for seq in 11..=15 {
+            buffer.push(seq, now, event(seq), 100);
+        }
+
+        assert_eq!(seqs(buffer.events_after(12).unwrap()), vec![13, 14, 15]);
+        assert_eq!(seqs(buffer.events_after(10).unwrap()), vec![11, 12, 13, 14, 15]);
+        assert!(buffer.events_after(15).unwrap().is_empty());
+        // Events before the buffer was created are unknown
+        assert!(buffer.events_after(9).is_none());
+    }
+
+    #[test]
+    fn requires_resync_once_missed_events_are_evicted() {
+        let now = Utc::now();
+        let mut buffer = ReplayBuffer::new(0);
+        for seq in 1..=5 {
+            buffer.push(seq, now, event(seq), 3);
+        }
+
+        // Only 3..=5 are kept, so a client that last saw 1 missed seq 2
+        assert_eq!(seqs(buffer.events_after(2).unwrap()), vec![3, 4, 5]);
+        assert!(buffer.events_after(1).is_none());
+    }
+
+    #[test]
+    fn prunes_events_older_than_cutoff() {
+        let now = Utc::now();
+        let mut buffer = ReplayBuffer::new(0);
+        buffer.push(1, now - chrono::Duration::minutes(10), event(1), 100);
+        buffer.push(2, now - chrono::Duration::minutes(10), event(2), 100);
+        buffer.push(3, now, event(3), 100);
+
+        buffer.prune(now - chrono::Duration::minutes(5));
+        assert_eq!(seqs(buffer.events_after(2).unwrap()), vec![3]);
+        assert!(buffer.events_after(1).is_none());
+    }
+
+    #[test]
+    fn negotiates_the_newest_offered_protocol() {
+        assert_eq!(ProtocolVersion::negotiate(None), Ok(ProtocolVersion::V1));
+        assert_eq!(ProtocolVersion::negotiate(Some("vt.v1")), Ok(ProtocolVersion::V1));
+        assert_eq!(ProtocolVersion::negotiate(Some("vt.v1, vt.v2")), Ok(ProtocolVersion::V2));
+        assert_eq!(ProtocolVersion::negotiate(Some("vt.v2,vt.v1")), Ok(ProtocolVersion::V2));
+        assert_eq!(ProtocolVersion::negotiate(Some("vt.v9, vt.v1")), Ok(ProtocolVersion::V1));
+        assert_eq!(ProtocolVersion::negotiate(Some("vt.v9, chat")), Err("vt.v9".to_string()));
+    }
+
+    #[test]
+    fn versions_frame_the_same_broadcast_differently() {
+        let broadcast = WsMessage {
+            sender_id: Uuid::nil(),
+            event: "message_sent".to_string(),
+            params: json!({ "content": "Hello", "event_seq": 7 }),
+        };
+        let v1: serde_json::Value = serde_json::from_str(&ProtocolVersion::V1.frame(&broadcast, 3)).unwrap();
+        let v2: serde_json::Value = serde_json::from_str(&ProtocolVersion::V2.frame(&broadcast, 3)).unwrap();
+        assert_eq!(v1, json!(broadcast));
+        assert_eq!(v2["seq"], 3);
+        assert_eq!(v2["params"], v1["params"]);
+
+        // Errors keep their own code, or get one from their status
+        let error = |params| WsMessage { sender_id: Uuid::nil(), event: "error".to_string(), params };
+        let frame = |message| serde_json::from_str::<serde_json::Value>(&ProtocolVersion::V2.frame(&message, 1)).unwrap();
+        assert_eq!(frame(error(json!({ "message": "Not found", "status": 404 })))["params"]["code"], "not_found");
+        assert_eq!(frame(error(json!({ "code": "content_blocked", "message": "Blocked" })))["params"]["code"], "content_blocked");
+        assert_eq!(frame(error(json!({ "message": "Oops" })))["params"]["code"], "error");
+        let v1_error: serde_json::Value = serde_json::from_str(&ProtocolVersion::V1.frame(&error(json!({ "message": "Oops" })), 1)).unwrap();
+        assert!(v1_error["params"].get("code").is_none());
+    }
+
+    // Stands in for a WsSession so the server has somewhere to send messages
+    struct NullSession;
+
+    impl Actor for NullSession {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<BroadcastMessage> for NullSession {
+        type Result = ();
+
+        fn handle(&mut self, _: BroadcastMessage, _: &mut Context<Self>) {}
+    }
+
+    impl Handler<DeliverMessage> for NullSession {
+        type Result = ();
+
+        fn handle(&mut self, _: DeliverMessage, _: &mut Context<Self>) {}
+    }
+
+    impl Handler<CloseSession> for NullSession {
+        type Result = ();
+
+        fn handle(&mut self, _: CloseSession, _: &mut Context<Self>) {}
+    }
+
+    #[actix_web::test]
+    async fn tracks_each_session_of_a_user() {
+        let server = WsServer::new(&Config::default()).start();
+        let user_id = Uuid::new_v4();
+        let conversation_id = Uuid::new_v4();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+
+        for session_id in [first, second] {
+            let session = NullSession.start();
+            server.send(Connect {
+                addr: session.clone().recipient(),
+                deliver: session.clone().recipient(),
+                close: session.recipient(),
+                id: user_id,
+                session_id,
+                family_id: None,
+            }).await.unwrap();
+        }
+        server.send(SubscribeToConversation { user_id, conversation_id }).await.unwrap();
+
+        let connections = server.send(ListConnections).await.unwrap();
+        let summary = connections.iter().find(|c| c.user_id == user_id).unwrap();
+        assert_eq!(summary.session_count, 2);
+
+        // Closing one socket leaves the other connected and subscribed
+        server.send(Disconnect { id: user_id, session_id: first }).await.unwrap();
+        let details = server.send(GetConnection { user_id }).await.unwrap().unwrap();
+        assert_eq!(details.sessions.len(), 1);
+        assert_eq!(details.sessions[0].session_id, second);
+        assert_eq!(details.subscriptions, vec![conversation_id]);
+        assert_eq!(server.send(GetSubscriptions { user_id }).await.unwrap(), vec![conversation_id]);
+        assert!(server.send(SendToUser { user_id, message: event(1) }).await.unwrap());
+
+        server.send(Disconnect { id: user_id, session_id: second }).await.unwrap();
+        assert!(server.send(GetConnection { user_id }).await.unwrap().is_none());
+        // With no sessions left, direct notifications have to be queued
+        assert!(!server.send(SendToUser { user_id, message: event(2) }).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn connection_snapshots_use_millisecond_timestamps() {
+        let server = WsServer::new(&Config::default()).start();
+        let user_id = Uuid::new_v4();
+        let session = NullSession.start();
+        let before = Utc::now().timestamp_millis();
+        server.send(Connect {
+            addr: session.clone().recipient(),
+            deliver: session.clone().recipient(),
+            close: session.recipient(),
+            id: user_id,
+            session_id: Uuid::new_v4(),
+            family_id: None,
+        }).await.unwrap();
+
+        let connections = server.send(ListConnections).await.unwrap();
+        let summary = json!(connections.iter().find(|c| c.user_id == user_id).unwrap());
+        let connected_at = summary["connected_at"][0].as_i64().unwrap();
+        assert!(connected_at >= before && connected_at <= Utc::now().timestamp_millis());
+
+        let details = json!(server.send(GetConnection { user_id }).await.unwrap().unwrap());
+        assert_eq!(details["sessions"][0]["connected_at"].as_i64(), Some(connected_at));
+        assert!(details["sessions"][0]["last_heartbeat"].is_i64());
+    }
+
+    // Keeps what the server sends it
+    struct RecordingSession(Arc<Mutex<Vec<WsMessage>>>);
+
+    impl Actor for RecordingSession {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<BroadcastMessage> for RecordingSession {
+        type Result = ();
+
+        fn handle(&mut self, msg: BroadcastMessage, _: &mut Context<Self>) {
+            self.0.lock().unwrap().push(msg.0);
+        }
+    }
+
+    impl Handler<DeliverMessage> for RecordingSession {
+        type Result = ();
+
+        fn handle(&mut self, msg: DeliverMessage, _: &mut Context<Self>) {
+            self.0.lock().unwrap().push(msg.message);
+        }
+    }
+
+    impl Handler<CloseSession> for RecordingSession {
+        type Result = ();
+
+        fn handle(&mut self, _: CloseSession, _: &mut Context<Self>) {}
+    }
+
+    async fn connect_recording(server: &Addr<WsServer>, user_id: Uuid, session_id: Uuid) -> Arc<Mutex<Vec<WsMessage>>> {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let session = RecordingSession(received.clone()).start();
+        server.send(Connect {
+            addr: session.clone().recipient(),
+            deliver: session.clone().recipient(),
+            close: session.recipient(),
+            id: user_id,
+            session_id,
+            family_id: None,
+        }).await.unwrap();
+        received
+    }
+
+    fn contents(received: &Arc<Mutex<Vec<WsMessage>>>) -> Vec<String> {
+        received.lock().unwrap().iter().map(|m| m.params["content"].as_str().unwrap().to_string()).collect()
+    }
+
+    #[actix_web::test]
+    async fn quick_reconnect_receives_buffered_broadcasts() {
+        let config = Config {
+            ws_disconnect_buffer_events: 2,
+            ws_disconnect_buffer_ttl: chrono::Duration::milliseconds(200),
+            ..Config::default()
+        };
+        let server = WsServer::new(&config).start();
+        let user_id = Uuid::new_v4();
+        let conversation_id = Uuid::new_v4();
+        let first_session = Uuid::new_v4();
+        connect_recording(&server, user_id, first_session).await;
+        server.send(SubscribeToConversation { user_id, conversation_id }).await.unwrap();
+        let broadcast = |content: &str| BroadcastToConversation {
+            message: WsMessage {
+                sender_id: Uuid::nil(),
+                event: "message_sent".to_string(),
+                params: json!({ "content": content }),
+            },
+            conversation_id,
+            timing: None,
+        };
+
+        // Sent while the socket is down; only the newest two are kept
+        server.send(Disconnect { id: user_id, session_id: first_session }).await.unwrap();
+        for content in ["one", "two", "three"] {
+            server.send(broadcast(content)).await.unwrap();
+        }
+        let received = connect_recording(&server, user_id, Uuid::new_v4()).await;
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(contents(&received), vec!["two", "three"]);
+        assert!(received.lock().unwrap()[0].params["event_seq"].is_u64());
+
+        // Live delivery carries on without duplicates
+        server.send(broadcast("four")).await.unwrap();
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(contents(&received), vec!["two", "three", "four"]);
+
+        // Past the window, nothing is held
+        let session_id = server.send(GetConnection { user_id }).await.unwrap().unwrap().sessions[0].session_id;
+        server.send(Disconnect { id: user_id, session_id }).await.unwrap();
+        server.send(broadcast("five")).await.unwrap();
+        actix_web::rt::time::sleep(Duration::from_millis(250)).await;
+        let received = connect_recording(&server, user_id, Uuid::new_v4()).await;
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+        assert!(contents(&received).is_empty());
+        assert!(server.send(GetSubscriptions { user_id }).await.unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn long_replays_are_truncated_to_the_newest_events() {
+        let config = Config { ws_replay_max_events: 3, ..Config::default() };
+        let server = WsServer::new(&config).start();
+        let (user_id, sender) = (Uuid::new_v4(), Uuid::new_v4());
+        let conversation_id = Uuid::new_v4();
+        let sent = connect_recording(&server, sender, Uuid::new_v4()).await;
+        server.send(SubscribeToConversation { user_id: sender, conversation_id }).await.unwrap();
+        let broadcast = |content: String| BroadcastToConversation {
+            message: WsMessage {
+                sender_id: Uuid::nil(),
+                event: "message_sent".to_string(),
+                params: json!({ "content": content }),
+            },
+            conversation_id,
+            timing: None,
+        };
+        server.send(broadcast("seen".to_string())).await.unwrap();
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+        let last_event_seq = sent.lock().unwrap()[0].params["event_seq"].as_u64().unwrap();
+
+        // Five events since the last one the user saw, more than the cap
+        for n in 1..=5 {
+            server.send(broadcast(format!("missed {}", n))).await.unwrap();
+        }
+        let session_id = Uuid::new_v4();
+        let received = connect_recording(&server, user_id, session_id).await;
+        let other_session = connect_recording(&server, user_id, Uuid::new_v4()).await;
+        server.send(ReplayConversation { user_id, session_id, conversation_id, last_event_seq }).await.unwrap();
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+
+        // Only the session that asked gets the replay
+        assert!(other_session.lock().unwrap().is_empty());
+
+        let received = received.lock().unwrap();
+        assert_eq!(received[0].event, "resync_required");
+        assert_eq!(received[0].params["truncated"], true);
+        let replayed: Vec<_> = received[1..].iter().map(|m| m.params["content"].as_str().unwrap()).collect();
+        assert_eq!(replayed, vec!["missed 3", "missed 4", "missed 5"]);
+    }
+
+    #[actix_web::test]
+    async fn idle_sessions_go_away_and_come_back() {
+        let server = WsServer::new(&Config::default()).start();
+        let (idle_user, watcher) = (Uuid::new_v4(), Uuid::new_v4());
+        let conversation_id = Uuid::new_v4();
+        let idle_session = Uuid::new_v4();
+        connect_recording(&server, idle_user, idle_session).await;
+        let received = connect_recording(&server, watcher, Uuid::new_v4()).await;
+        for user_id in [idle_user, watcher] {
+            server.send(SubscribeToConversation { user_id, conversation_id }).await.unwrap();
+        }
+        let statuses = || -> Vec<String> {
+            received.lock().unwrap().iter()
+                .filter(|m| m.event == "presence_changed")
+                .map(|m| m.params["status"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        server.send(SetAway { id: idle_user, session_id: idle_session, away: true }).await.unwrap();
+        assert!(server.send(GetConnection { user_id: idle_user }).await.unwrap().unwrap().sessions[0].away);
+        server.send(SetAway { id: idle_user, session_id: idle_session, away: false }).await.unwrap();
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(statuses(), vec!["away", "online"]);
+        assert_eq!(received.lock().unwrap().last().unwrap().params["user_id"], json!(idle_user));
+
+        // A second, active session keeps the user online
+        connect_recording(&server, idle_user, Uuid::new_v4()).await;
+        server.send(SetAway { id: idle_user, session_id: idle_session, away: true }).await.unwrap();
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(statuses(), vec!["away", "online"]);
+    }
+
+    #[actix_web::test]
+    async fn provider_changes_reconcile_subscriptions() {
+        let server = WsServer::new(&Config::default()).start();
+        let (client, kept, dropped, added) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let conversation_id = Uuid::new_v4();
+        let mut received = HashMap::new();
+        for user_id in [client, kept, dropped, added] {
+            received.insert(user_id, connect_recording(&server, user_id, Uuid::new_v4()).await);
+        }
+        for user_id in [client, kept, dropped] {
+            server.send(SubscribeToConversation { user_id, conversation_id }).await.unwrap();
+        }
+
+        server.send(ReconcileProviders {
+            conversation_id,
+            added: vec![added],
+            removed: vec![dropped],
+            message: WsMessage {
+                sender_id: Uuid::nil(),
+                event: "providers_updated".to_string(),
+                params: json!({ "providers": [kept, added] }),
+            },
+        }).await.unwrap();
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+
+        for user_id in [client, kept, added] {
+            assert_eq!(server.send(GetSubscriptions { user_id }).await.unwrap(), vec![conversation_id]);
+        }
+        assert!(server.send(GetSubscriptions { user_id: dropped }).await.unwrap().is_empty());
+
+        // Everyone involved hears about it, including the provider who was dropped
+        for user_id in [client, kept, dropped, added] {
+            let events = received[&user_id].lock().unwrap();
+            assert!(events.iter().any(|m| m.event == "providers_updated"), "{} missed the update", user_id);
+        }
+    }
+
+    #[actix_web::test]
+    async fn drafts_sync_to_the_users_other_sessions_only() {
+        let server = WsServer::new(&Config::default()).start();
+        let (user_id, other_participant) = (Uuid::new_v4(), Uuid::new_v4());
+        let conversation_id = Uuid::new_v4();
+        let (phone, laptop) = (Uuid::new_v4(), Uuid::new_v4());
+        let on_phone = connect_recording(&server, user_id, phone).await;
+        let on_laptop = connect_recording(&server, user_id, laptop).await;
+        let elsewhere = connect_recording(&server, other_participant, Uuid::new_v4()).await;
+        for user_id in [user_id, other_participant] {
+            server.send(SubscribeToConversation { user_id, conversation_id }).await.unwrap();
+        }
+
+        // Typed on the phone, the draft shows up on the laptop
+        let reached = server.send(SendToOtherSessions {
+            user_id,
+            session_id: phone,
+            message: WsMessage {
+                sender_id: Uuid::nil(),
+                event: "draft_updated".to_string(),
+                params: json!({ "conversation_id": conversation_id, "content": "Millie is eating again" }),
+            },
+        }).await.unwrap();
+        assert_eq!(reached, 1);
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(contents(&on_laptop), vec!["Millie is eating again"]);
+        assert!(on_phone.lock().unwrap().is_empty());
+        assert!(elsewhere.lock().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn reports_which_users_are_online() {
+        let server = WsServer::new(&Config::default()).start();
+        let (online, offline) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let session = NullSession.start();
+        server.send(Connect {
+            addr: session.clone().recipient(),
+            deliver: session.clone().recipient(),
+            close: session.recipient(),
+            id: online,
+            session_id: Uuid::new_v4(),
+            family_id: None,
+        }).await.unwrap();
+
+        let users = server.send(GetOnlineUsers { user_ids: vec![online, offline] }).await.unwrap();
+        assert_eq!(users, HashSet::from([online]));
+    }
+
+    #[actix_web::test]
+    async fn disconnects_only_the_revoked_family() {
+        let server = WsServer::new(&Config::default()).start();
+        let user_id = Uuid::new_v4();
+        let (phone, tablet) = (Uuid::new_v4(), Uuid::new_v4());
+
+        for family_id in [phone, tablet] {
+            let session = NullSession.start();
+            server.send(Connect {
+                addr: session.clone().recipient(),
+                deliver: session.clone().recipient(),
+                close: session.recipient(),
+                id: user_id,
+                session_id: Uuid::new_v4(),
+                family_id: Some(family_id),
+            }).await.unwrap();
+        }
+
+        assert_eq!(server.send(DisconnectFamily { user_id: Uuid::new_v4(), family_id: tablet }).await.unwrap(), 0);
+        assert_eq!(server.send(DisconnectFamily { user_id, family_id: tablet }).await.unwrap(), 1);
+    }
 }
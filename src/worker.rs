@@ -0,0 +1,172 @@
+use actix_web::rt::time;
+use chrono::Utc;
+use sqlx::PgPool;
+use std::time::Duration;
+use crate::config::Config;
+use crate::notifications::TwilioNotifier;
+use crate::services::access_log::AccessLogService;
+use crate::services::cold_storage::ColdStorageService;
+use crate::services::conversations::ConversationService;
+use crate::services::idempotency::IdempotencyService;
+use crate::services::pending_events::PendingEventService;
+use crate::services::pending_uploads::PendingUploadService;
+use crate::storage::GcsStorage;
+use crate::services::reminders::ReminderService;
+use crate::services::request_nonces::RequestNonceService;
+use crate::services::revoked_tokens::RevokedTokenService;
+use crate::services::sessions::SessionService;
+
+/// Runs the background jobs. Started with `vt-rust worker` instead of the HTTP
+/// server so jobs run in a single process regardless of how many API instances
+/// are deployed.
+pub async fn run(pool: PgPool, config: Config) {
+    println!("Starting worker...");
+
+    futures::join!(
+        appointment_reminders(&pool, &config),
+        expired_idempotency_keys(&pool),
+        expired_token_revocations(&pool),
+        expired_request_nonces(&pool),
+        expired_refresh_tokens(&pool, &config),
+        expired_pending_events(&pool, &config),
+        pending_uploads(&pool),
+        message_count_drift(&pool),
+        expired_access_log(&pool, &config),
+        cold_storage(&pool, &config),
+    );
+}
+
+async fn appointment_reminders(pool: &PgPool, config: &Config) {
+    let notifier = TwilioNotifier::from_config(config);
+    let mut interval = time::interval(Duration::from_secs(config.reminder_scan_interval_secs));
+
+    loop {
+        interval.tick().await;
+        match ReminderService::send_due_reminders(pool, &notifier, Utc::now(), &config.reminder_lead_times).await {
+            Ok(0) => {},
+            Ok(sent) => println!("Sent {} appointment reminders", sent),
+            Err(e) => eprintln!("Appointment reminder scan failed: {}", e),
+        }
+    }
+}
+
+async fn expired_idempotency_keys(pool: &PgPool) {
+    let mut interval = time::interval(Duration::from_secs(60 * 60));
+
+    loop {
+        interval.tick().await;
+        match IdempotencyService::delete_expired(pool).await {
+            Ok(0) => {},
+            Ok(deleted) => println!("Deleted {} expired idempotency keys", deleted),
+            Err(e) => eprintln!("Idempotency key cleanup failed: {}", e),
+        }
+    }
+}
+
+async fn expired_token_revocations(pool: &PgPool) {
+    let mut interval = time::interval(Duration::from_secs(60 * 60));
+
+    loop {
+        interval.tick().await;
+        match RevokedTokenService::delete_expired(pool).await {
+            Ok(0) => {},
+            Ok(deleted) => println!("Deleted {} expired token revocations", deleted),
+            Err(e) => eprintln!("Token revocation cleanup failed: {}", e),
+        }
+    }
+}
+
+async fn expired_request_nonces(pool: &PgPool) {
+    let mut interval = time::interval(Duration::from_secs(10 * 60));
+
+    loop {
+        interval.tick().await;
+        match RequestNonceService::delete_expired(pool).await {
+            Ok(0) => {},
+            Ok(deleted) => println!("Deleted {} expired signed request nonces", deleted),
+            Err(e) => eprintln!("Signed request nonce cleanup failed: {}", e),
+        }
+    }
+}
+
+async fn expired_refresh_tokens(pool: &PgPool, config: &Config) {
+    let mut interval = time::interval(Duration::from_secs(60 * 60));
+
+    loop {
+        interval.tick().await;
+        match SessionService::delete_expired(pool, config.refresh_token_cleanup_grace).await {
+            Ok(0) => {},
+            Ok(deleted) => println!("Deleted {} expired refresh tokens", deleted),
+            Err(e) => eprintln!("Refresh token cleanup failed: {}", e),
+        }
+    }
+}
+
+async fn expired_pending_events(pool: &PgPool, config: &Config) {
+    let mut interval = time::interval(Duration::from_secs(60 * 60));
+
+    loop {
+        interval.tick().await;
+        match PendingEventService::delete_expired(pool, config.pending_events_ttl).await {
+            Ok(0) => {},
+            Ok(deleted) => println!("Deleted {} pending events", deleted),
+            Err(e) => eprintln!("Pending event cleanup failed: {}", e),
+        }
+    }
+}
+
+async fn pending_uploads(pool: &PgPool) {
+    let mut interval = time::interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+        match PendingUploadService::retry_due(pool, &GcsStorage, Utc::now()).await {
+            Ok(0) => {},
+            Ok(uploaded) => println!("Uploaded {} pending images", uploaded),
+            Err(e) => eprintln!("Pending upload retry failed: {}", e),
+        }
+    }
+}
+
+async fn message_count_drift(pool: &PgPool) {
+    let mut interval = time::interval(Duration::from_secs(24 * 60 * 60));
+
+    loop {
+        interval.tick().await;
+        match ConversationService::reconcile_message_counts(pool).await {
+            Ok(0) => {},
+            Ok(repaired) => println!("Repaired message counts of {} conversations", repaired),
+            Err(e) => eprintln!("Message count reconciliation failed: {}", e),
+        }
+    }
+}
+
+async fn expired_access_log(pool: &PgPool, config: &Config) {
+    let mut interval = time::interval(Duration::from_secs(24 * 60 * 60));
+
+    loop {
+        interval.tick().await;
+        match AccessLogService::delete_expired(pool, config.access_log_retention).await {
+            Ok(0) => {},
+            Ok(deleted) => println!("Deleted {} access log entries", deleted),
+            Err(e) => eprintln!("Access log cleanup failed: {}", e),
+        }
+    }
+}
+
+async fn cold_storage(pool: &PgPool, config: &Config) {
+    if !config.cold_storage_enabled {
+        return;
+    }
+    let mut interval = time::interval(Duration::from_secs(10 * 60));
+
+    loop {
+        interval.tick().await;
+        let cutoff = Utc::now() - config.cold_storage_after;
+        match ColdStorageService::archive_batch(pool, cutoff, config.cold_storage_batch_size).await {
+            Ok(0) => {},
+            Ok(moved) => println!("Moved {} messages to cold storage", moved),
+            Err(e) => eprintln!("Cold storage job failed: {}", e),
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fmt;
+use uuid::Uuid;
+use crate::models::WsEvent;
+
+/// The JSON a field of an event's params must hold.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldType {
+    Uuid,
+    String,
+    Integer,
+    Sequence,
+    Boolean,
+    UuidArray,
+    Timestamp,
+}
+
+impl FieldType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FieldType::Uuid => "uuid",
+            FieldType::String => "string",
+            FieldType::Integer => "integer",
+            FieldType::Sequence => "non-negative integer",
+            FieldType::Boolean => "boolean",
+            FieldType::UuidArray => "array of uuid",
+            FieldType::Timestamp => "unix milliseconds",
+        }
+    }
+
+    // Adds a problem for `value` at `path`, or for each bad element of an array
+    fn check(&self, value: &Value, path: &str, problems: &mut Vec<FieldProblem>) {
+        let is_uuid = |value: &Value| value.as_str().is_some_and(|s| Uuid::parse_str(s).is_ok());
+        let matches = match self {
+            FieldType::Uuid => is_uuid(value),
+            FieldType::String => value.is_string(),
+            FieldType::Integer => value.as_i64().is_some_and(|n| i32::try_from(n).is_ok()),
+            FieldType::Sequence => value.is_u64(),
+            FieldType::Boolean => value.is_boolean(),
+            FieldType::UuidArray => match value.as_array() {
+                Some(ids) => {
+                    for (i, id) in ids.iter().enumerate() {
+                        FieldType::Uuid.check(id, &format!("{}[{}]", path, i), problems);
+                    }
+                    true
+                },
+                None => false,
+            },
+            FieldType::Timestamp => value.is_i64(),
+        };
+        if !matches {
+            problems.push(FieldProblem::new(path, "wrong_type", self.name()));
+        }
+    }
+}
+
+/// One field of an event's params.
+pub struct Field {
+    pub name: &'static str,
+    pub field_type: FieldType,
+    pub required: bool,
+    /// Whether `null` is accepted in place of a value.
+    pub nullable: bool,
+}
+
+const fn required(name: &'static str, field_type: FieldType) -> Field {
+    Field { name, field_type, required: true, nullable: false }
+}
+
+// An `Option` field: may be left out or null
+const fn optional(name: &'static str, field_type: FieldType) -> Field {
+    Field { name, field_type, required: false, nullable: true }
+}
+
+// A `#[serde(default)]` field: may be left out, but not null
+const fn defaulted(name: &'static str, field_type: FieldType) -> Field {
+    Field { name, field_type, required: false, nullable: false }
+}
+
+/// The params each client event takes. Mirrors `WsEvent` for the typed
+/// events; the tests check the two agree.
+pub const EVENTS: &[(&str, &[Field])] = &[
+    ("ping", &[]),
+    ("get_subscriptions", &[]),
+    ("resubscribe", &[]),
+    ("conversations", &[defaulted("include_details", FieldType::Boolean)]),
+    ("conversation_previews", &[]),
+    ("message", &[
+        required("conversation_id", FieldType::Uuid),
+        optional("content", FieldType::String),
+        optional("canned_response_id", FieldType::Uuid),
+        optional("attachment_image_id", FieldType::Uuid),
+        defaulted("trace", FieldType::Boolean),
+    ]),
+    ("new_conversation", &[
+        required("pet_id", FieldType::Uuid),
+        optional("providers", FieldType::UuidArray),
+        optional("organization_id", FieldType::Uuid),
+    ]),
+    ("conversation_history", &[
+        required("conversation_id", FieldType::Uuid),
+        required("page", FieldType::Integer),
+        required("limit", FieldType::Integer),
+        optional("before", FieldType::Uuid),
+        defaulted("include_state", FieldType::Boolean),
+    ]),
+    ("mark_read", &[required("conversation_id", FieldType::Uuid), required("message_id", FieldType::Uuid)]),
+    ("conversation_timestamps", &[required("conversation_id", FieldType::Uuid)]),
+    ("get_note", &[required("conversation_id", FieldType::Uuid)]),
+    ("update_note", &[
+        required("conversation_id", FieldType::Uuid),
+        required("content", FieldType::String),
+        required("version", FieldType::Integer),
+    ]),
+    ("set_providers", &[required("conversation_id", FieldType::Uuid), required("providers", FieldType::UuidArray)]),
+    ("get_message_stats", &[required("conversation_id", FieldType::Uuid)]),
+    ("save_draft", &[required("conversation_id", FieldType::Uuid), required("content", FieldType::String)]),
+    ("get_conversation_pet", &[required("conversation_id", FieldType::Uuid)]),
+    ("open_conversation", &[required("conversation_id", FieldType::Uuid), optional("limit", FieldType::Integer)]),
+    ("typing", &[required("conversation_id", FieldType::Uuid), required("is_typing", FieldType::Boolean)]),
+    ("read", &[required("conversation_id", FieldType::Uuid), required("up_to_timestamp", FieldType::Timestamp)]),
+    ("delete_message", &[required("message_id", FieldType::Uuid)]),
+    ("react", &[required("message_id", FieldType::Uuid), required("emoji", FieldType::String)]),
+    ("unreact", &[required("message_id", FieldType::Uuid), required("emoji", FieldType::String)]),
+    ("subscribe_conversation", &[
+        required("conversation_id", FieldType::Uuid),
+        optional("last_event_seq", FieldType::Sequence),
+    ]),
+    ("unsubscribe_conversation", &[required("conversation_id", FieldType::Uuid)]),
+];
+
+/// What's wrong with one field of a payload.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldProblem {
+    /// Where the field is within `params`, e.g. `providers[1]`.
+    pub path: String,
+    /// "missing", "wrong_type" or "unknown".
+    pub problem: &'static str,
+    pub expected: String,
+}
+
+impl FieldProblem {
+    fn new(path: &str, problem: &'static str, expected: &str) -> Self {
+        FieldProblem { path: path.to_string(), problem, expected: expected.to_string() }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PayloadError {
+    UnknownEvent(String),
+    Invalid { event: String, fields: Vec<FieldProblem> },
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayloadError::UnknownEvent(_) => write!(f, "Unknown event type"),
+            PayloadError::Invalid { event, fields } => {
+                let problems: Vec<String> = fields
+                    .iter()
+                    .map(|field| match field.problem {
+                        "missing" => format!("{} is missing (expected {})", field.path, field.expected),
+                        _ => format!("{} should be {}", field.path, field.expected),
+                    })
+                    .collect();
+                write!(f, "Invalid {} params: {}", event, problems.join("; "))
+            },
+        }
+    }
+}
+
+impl PayloadError {
+    /// The params of the `error` event describing this to the client.
+    pub fn to_params(&self) -> Value {
+        let (code, event, fields) = match self {
+            PayloadError::UnknownEvent(event) => {
+                let known: Vec<&str> = EVENTS.iter().map(|(name, _)| *name).collect();
+                let expected = format!("one of {}", known.join(", "));
+                ("unknown_event", event, vec![FieldProblem::new("event", "unknown", &expected)])
+            },
+            PayloadError::Invalid { event, fields } => ("invalid_payload", event, fields.clone()),
+        };
+        json!({
+            "code": code,
+            "message": self.to_string(),
+            "event": event,
+            "fields": fields
+        })
+    }
+}
+
+/// Checks `params` against what `event` takes, reporting every missing or
+/// mistyped field. Fields the event doesn't use are ignored.
+pub fn validate(event: &str, params: &Value) -> Result<(), PayloadError> {
+    let Some((_, fields)) = EVENTS.iter().find(|(name, _)| *name == event) else {
+        return Err(PayloadError::UnknownEvent(event.to_string()));
+    };
+    let invalid = |fields| Err(PayloadError::Invalid { event: event.to_string(), fields });
+
+    let empty = json!({});
+    let params = if params.is_null() { &empty } else { params };
+    let Some(object) = params.as_object() else {
+        return invalid(vec![FieldProblem::new("params", "wrong_type", "object")]);
+    };
+
+    let mut problems = Vec::new();
+    for field in fields.iter() {
+        match object.get(field.name) {
+            None if field.required => problems.push(FieldProblem::new(field.name, "missing", field.field_type.name())),
+            None => {},
+            Some(Value::Null) if field.nullable => {},
+            Some(value) => field.field_type.check(value, field.name, &mut problems),
+        }
+    }
+    if !problems.is_empty() {
+        return invalid(problems);
+    }
+
+    // The table should catch everything serde would; if it has drifted from
+    // `WsEvent`, still refuse the payload with serde's own account of it
+    if let Err(e) = serde_json::from_value::<WsEvent>(json!({ "event": event, "data": params })) {
+        let message = e.to_string();
+        if !message.starts_with("unknown variant") {
+            let path = message
+                .strip_prefix("missing field `")
+                .and_then(|rest| rest.split('`').next())
+                .unwrap_or("params");
+            return invalid(vec![FieldProblem::new(path, "wrong_type", &message)]);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(result: Result<(), PayloadError>) -> Vec<(String, &'static str, String)> {
+        match result {
+            Err(PayloadError::Invalid { fields, .. }) => fields
+                .into_iter()
+                .map(|field| (field.path, field.problem, field.expected))
+                .collect(),
+            other => panic!("expected an invalid payload, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_fields_are_reported_by_path() {
+        let problems = fields(validate("update_note", &json!({ "conversation_id": Uuid::new_v4(), "content": "Fed at 6" })));
+        assert_eq!(problems, vec![("version".to_string(), "missing", "integer".to_string())]);
+
+        let problems = fields(validate("mark_read", &Value::Null));
+        assert_eq!(problems.iter().map(|p| p.0.as_str()).collect::<Vec<_>>(), vec!["conversation_id", "message_id"]);
+    }
+
+    #[test]
+    fn mistyped_fields_are_reported_by_path() {
+        let params = json!({
+            "pet_id": "millie",
+            "providers": [Uuid::new_v4(), 42],
+            "organization_id": null
+        });
+        let error = validate("new_conversation", &params).unwrap_err();
+        assert_eq!(error.to_params()["code"], "invalid_payload");
+        assert_eq!(error.to_params()["event"], "new_conversation");
+        assert_eq!(fields(Err(error)), vec![
+            ("pet_id".to_string(), "wrong_type", "uuid".to_string()),
+            ("providers[1]".to_string(), "wrong_type", "uuid".to_string()),
+        ]);
+
+        // Defaulted flags may be left out but not nulled
+        let params = json!({ "conversation_id": Uuid::new_v4(), "page": 1, "limit": 20, "include_state": null });
+        assert_eq!(fields(validate("conversation_history", &params)), vec![
+            ("include_state".to_string(), "wrong_type", "boolean".to_string()),
+        ]);
+        assert_eq!(fields(validate("ping", &json!([1]))), vec![("params".to_string(), "wrong_type", "object".to_string())]);
+    }
+
+    #[test]
+    fn unknown_events_are_reported() {
+        let error = validate("send_fax", &json!({})).unwrap_err();
+        assert_eq!(error, PayloadError::UnknownEvent("send_fax".to_string()));
+        let params = error.to_params();
+        assert_eq!(params["code"], "unknown_event");
+        assert_eq!(params["fields"][0]["path"], "event");
+        assert!(params["fields"][0]["expected"].as_str().unwrap().contains("new_conversation"));
+    }
+
+    #[test]
+    fn the_table_agrees_with_the_typed_events() {
+        let sample = |field_type: FieldType| match field_type {
+            FieldType::Uuid => json!(Uuid::nil()),
+            FieldType::String => json!("text"),
+            FieldType::Integer | FieldType::Sequence => json!(1),
+            FieldType::Boolean => json!(true),
+            FieldType::UuidArray => json!([Uuid::nil()]),
+            FieldType::Timestamp => json!(1_700_000_000_000i64),
+        };
+        for (event, fields) in EVENTS {
+            let complete: serde_json::Map<String, Value> = fields.iter().map(|f| (f.name.to_string(), sample(f.field_type))).collect();
+            let typed = serde_json::from_value::<WsEvent>(json!({ "event": event, "data": complete }));
+            if typed.as_ref().is_err_and(|e| e.to_string().starts_with("unknown variant")) {
+                continue;
+            }
+            assert!(typed.is_ok(), "{} rejects a complete payload: {:?}", event, typed.err());
+            assert!(validate(event, &Value::Object(complete.clone())).is_ok());
+
+            for field in fields.iter() {
+                let mut without = complete.clone();
+                without.remove(field.name);
+                let typed = serde_json::from_value::<WsEvent>(json!({ "event": event, "data": without }));
+                assert_eq!(typed.is_err(), field.required, "{}.{} is required by one but not the other", event, field.name);
+            }
+        }
+    }
+}
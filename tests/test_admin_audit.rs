@@ -0,0 +1,77 @@
+use reqwest::Client;
+use serde_json::Value;
+use chrono::{Duration, Utc};
+
+mod testing_utils;
+use testing_utils::{generate_test_token, setup_test_db, insert_test_user, cleanup_test_users, test_phone_number};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+#[tokio::test]
+async fn test_admin_audit_filters() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let admin_id = insert_test_user(&pool, &test_phone_number(), "admin").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let user_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+
+    let now = Utc::now();
+    for (action, age) in [("login", Duration::days(3)), ("logout", Duration::days(2)), ("login", Duration::hours(1))] {
+        sqlx::query!(
+            "INSERT INTO audit_log (action, actor_id, user_id, created_at) VALUES ($1, $2, $2, $3)",
+            action,
+            user_id,
+            now - age
+        )
+        .execute(&pool)
+        .await?;
+    }
+
+    let http = Client::new();
+    let (admin_token, _) = generate_test_token(admin_id, "admin")?;
+
+    // Filter by action
+    let body: Value = http
+        .get(format!("{}/admin/audit?action=login&user_id={}", SERVER_URL, user_id))
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["total_count"], 2);
+    let entries = body["entries"].as_array().unwrap();
+    assert!(entries.iter().all(|e| e["action"] == "login"));
+    assert!(entries[0]["created_at"].as_i64() > entries[1]["created_at"].as_i64());
+
+    // Filter by date range
+    let from = (now - Duration::days(4)).timestamp_millis();
+    let to = (now - Duration::days(1)).timestamp_millis();
+    let body: Value = http
+        .get(format!("{}/admin/audit?user_id={}&from={}&to={}", SERVER_URL, user_id, from, to))
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["total_count"], 2);
+
+    // Page size is capped
+    let response = http
+        .get(format!("{}/admin/audit?limit=1000", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    // Non-admins are refused
+    let (provider_token, _) = generate_test_token(provider_id, "provider")?;
+    let response = http
+        .get(format!("{}/admin/audit", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", provider_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    sqlx::query!("DELETE FROM audit_log WHERE user_id = $1", user_id).execute(&pool).await?;
+    cleanup_test_users(&pool, &[admin_id, provider_id, user_id]).await;
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use reqwest::{Client, StatusCode};
+use tokio::time::{sleep, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use serde_json::{json, Value};
+use futures::SinkExt;
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+#[tokio::test]
+async fn test_admin_connection_state() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let admin_id = insert_test_user(&pool, &test_phone_number(), "admin").await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+    let http = Client::new();
+    let (admin_token, _) = generate_test_token(admin_id, "admin")?;
+    let (client_token, _) = generate_test_token(client_id, "client")?;
+    let detail_url = format!("{}/admin/connections/{}", SERVER_URL, client_id);
+
+    // Not connected yet
+    let response = http
+        .get(&detail_url)
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let (mut ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", client_token)).await?;
+    let ping = json!({ "sender_id": client_id, "event": "ping", "params": {} });
+    ws_stream.send(Message::Text(ping.to_string())).await?;
+    // Give the auto-subscription a moment to land
+    sleep(Duration::from_millis(500)).await;
+
+    let connections: Value = http
+        .get(format!("{}/admin/connections", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .send()
+        .await?
+        .json()
+        .await?;
+    let entry = connections
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|c| c["user_id"] == client_id.to_string())
+        .expect("Connected user missing from list");
+    assert_eq!(entry["session_count"], 1);
+    assert_eq!(entry["connected_at"].as_array().unwrap().len(), 1);
+
+    let details: Value = http
+        .get(&detail_url)
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(details["sessions"][0]["encoding"], "json");
+    assert!(details["sessions"][0]["last_heartbeat"].as_i64() >= details["sessions"][0]["connected_at"].as_i64());
+    assert!(details["subscriptions"].as_array().unwrap().contains(&json!(conversation_id)));
+
+    // Non-admins are refused
+    let response = http
+        .get(format!("{}/admin/connections", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", client_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    ws_stream.close(None).await?;
+    cleanup_test_users(&pool, &[admin_id, client_id, provider_id]).await;
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+
+mod testing_utils;
+use testing_utils::{generate_test_token, setup_test_db, insert_test_user, cleanup_test_users, test_phone_number};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+#[tokio::test]
+async fn test_admin_decode_token() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let admin_id = insert_test_user(&pool, &test_phone_number(), "admin").await;
+    let user_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+
+    let http = Client::new();
+    let (admin_token, _) = generate_test_token(admin_id, "admin")?;
+    let (user_token, _) = generate_test_token(user_id, "client")?;
+
+    let response = http
+        .post(format!("{}/admin/decode-token", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .json(&json!({ "token": user_token }))
+        .send()
+        .await?;
+    assert!(response.status().is_success(), "Decode failed: {}", response.status());
+    let body: Value = response.json().await?;
+    assert_eq!(body["claims"]["sub"], user_id.to_string());
+    assert_eq!(body["claims"]["scope"], "client");
+    assert_eq!(body["expired"], false);
+
+    // The lookup is audited against the token's owner
+    let logged = sqlx::query!(
+        "SELECT COUNT(*) AS count FROM audit_log WHERE action = 'token_decoded' AND actor_id = $1 AND user_id = $2",
+        admin_id,
+        user_id
+    )
+    .fetch_one(&pool)
+    .await?;
+    assert_eq!(logged.count, Some(1));
+
+    // Non-admins can't decode tokens, not even their own
+    let response = http
+        .post(format!("{}/admin/decode-token", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", user_token))
+        .json(&json!({ "token": user_token }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    cleanup_test_users(&pool, &[admin_id, user_id]).await;
+    Ok(())
+}
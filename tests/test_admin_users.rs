@@ -0,0 +1,110 @@
+use ed25519_dalek::Signer;
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use base64::{Engine as _, engine::general_purpose};
+use chrono::Utc;
+use uuid::Uuid;
+
+mod testing_utils;
+use testing_utils::{
+    TEST_SIGNING_KEY, TEST_VERIFYING_KEY, to_canonical_json, generate_test_token, setup_test_db,
+    insert_test_user, cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+async fn login(client: &Client, user_id: Uuid) -> Result<reqwest::Response, reqwest::Error> {
+    let data = json!({
+        "user_id": user_id,
+        "timestamp": Utc::now().to_rfc3339(),
+        "verification_code": "123456"
+    });
+    let signature = TEST_SIGNING_KEY.sign(to_canonical_json(&data).as_bytes());
+    client.post(format!("{}/login", SERVER_URL))
+        .json(&json!({
+            "data": data,
+            "signature": general_purpose::STANDARD.encode(signature.to_bytes())
+        }))
+        .send()
+        .await
+}
+
+#[tokio::test]
+async fn test_only_admins_manage_users() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let admin_id = insert_test_user(&pool, &test_phone_number(), "admin").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO users (id, phone_number, public_key, scope, verified) VALUES ($1, $2, $3, 'client', true)",
+        user_id,
+        test_phone_number(),
+        general_purpose::STANDARD.encode(TEST_VERIFYING_KEY.as_bytes())
+    )
+    .execute(&pool)
+    .await?;
+
+    let http = Client::new();
+    let (admin_token, _) = generate_test_token(admin_id, "admin")?;
+    let (provider_token, _) = generate_test_token(provider_id, "provider")?;
+
+    // Providers are turned away from every admin route
+    let listed = http.get(format!("{}/admin/users", SERVER_URL)).bearer_auth(&provider_token).send().await?;
+    assert_eq!(listed.status(), StatusCode::FORBIDDEN);
+    let promoted = http.post(format!("{}/admin/users/{}/scope", SERVER_URL, user_id))
+        .bearer_auth(&provider_token)
+        .json(&json!({ "scope": "provider" }))
+        .send()
+        .await?;
+    assert_eq!(promoted.status(), StatusCode::FORBIDDEN);
+    let suspended = http.post(format!("{}/admin/users/{}/suspend", SERVER_URL, user_id)).bearer_auth(&provider_token).send().await?;
+    assert_eq!(suspended.status(), StatusCode::FORBIDDEN);
+
+    // An admin can list, filter and promote
+    let body: Value = http.get(format!("{}/admin/users?scope=client&verified=true&limit=100", SERVER_URL))
+        .bearer_auth(&admin_token)
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert!(body["users"].as_array().unwrap().iter().all(|user| user["scope"] == "client" && user["verified"] == true));
+
+    let promoted = http.post(format!("{}/admin/users/{}/scope", SERVER_URL, user_id))
+        .bearer_auth(&admin_token)
+        .json(&json!({ "scope": "provider" }))
+        .send()
+        .await?;
+    assert_eq!(promoted.status(), StatusCode::OK);
+    let scope = sqlx::query!("SELECT scope FROM users WHERE id = $1", user_id).fetch_one(&pool).await?.scope;
+    assert_eq!(scope, "provider");
+
+    let to_admin = http.post(format!("{}/admin/users/{}/scope", SERVER_URL, user_id))
+        .bearer_auth(&admin_token)
+        .json(&json!({ "scope": "admin" }))
+        .send()
+        .await?;
+    assert_eq!(to_admin.status(), StatusCode::BAD_REQUEST);
+
+    // Suspension signs the user out and keeps them out
+    assert_eq!(login(&http, user_id).await?.status(), StatusCode::OK);
+    let suspended: Value = http.post(format!("{}/admin/users/{}/suspend", SERVER_URL, user_id))
+        .bearer_auth(&admin_token)
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(suspended["revoked_sessions"], 1);
+    let active = sqlx::query!("SELECT COUNT(*) AS count FROM refresh_tokens WHERE user_id = $1 AND NOT is_revoked", user_id)
+        .fetch_one(&pool)
+        .await?
+        .count;
+    assert_eq!(active, Some(0));
+
+    let refused = login(&http, user_id).await?;
+    assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+    let body: Value = refused.json().await?;
+    assert_eq!(body["error"]["code"], "account_suspended");
+
+    cleanup_test_users(&pool, &[admin_id, provider_id, user_id]).await;
+    Ok(())
+}
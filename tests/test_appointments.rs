@@ -0,0 +1,138 @@
+use reqwest::Client;
+use serde_json::{json, Value};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+async fn propose(client: &Client, token: &str, conversation_id: Uuid) -> Result<(reqwest::StatusCode, Value), Box<dyn std::error::Error>> {
+    let response = client
+        .post(format!("{}/appointments", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&json!({
+            "conversation_id": conversation_id,
+            "starts_at": (Utc::now() + Duration::days(2)).timestamp_millis(),
+            "duration_minutes": 30,
+            "notes": "Annual checkup"
+        }))
+        .send()
+        .await?;
+    let status = response.status();
+    Ok((status, response.json().await?))
+}
+
+async fn respond(client: &Client, token: &str, appointment_id: &str, action: &str) -> Result<(reqwest::StatusCode, Value), Box<dyn std::error::Error>> {
+    let response = client
+        .post(format!("{}/appointments/{}/{}", SERVER_URL, appointment_id, action))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?;
+    let status = response.status();
+    Ok((status, response.json().await?))
+}
+
+#[tokio::test]
+async fn test_propose_and_confirm_appointment() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+    let (client_token, _) = generate_test_token(client_id, "client")?;
+    let (provider_token, _) = generate_test_token(provider_id, "provider")?;
+    let http = Client::new();
+
+    // Clients cannot propose appointments
+    let (status, _) = propose(&http, &client_token, conversation_id).await?;
+    assert_eq!(status, reqwest::StatusCode::FORBIDDEN);
+
+    let (status, body) = propose(&http, &provider_token, conversation_id).await?;
+    assert_eq!(status, reqwest::StatusCode::CREATED, "Propose failed: {}", body);
+    assert_eq!(body["appointment"]["status"], "proposed");
+    let appointment_id = body["appointment"]["id"].as_str().unwrap().to_string();
+
+    // Only the client can confirm
+    let (status, _) = respond(&http, &provider_token, &appointment_id, "confirm").await?;
+    assert_eq!(status, reqwest::StatusCode::FORBIDDEN);
+
+    let (status, body) = respond(&http, &client_token, &appointment_id, "confirm").await?;
+    assert!(status.is_success(), "Confirm failed: {}", body);
+    assert_eq!(body["appointment"]["status"], "confirmed");
+
+    // A confirmed appointment can't be declined afterwards
+    let (status, _) = respond(&http, &client_token, &appointment_id, "decline").await?;
+    assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+
+    // It shows up in both parties' upcoming lists
+    for token in [&client_token, &provider_token] {
+        let upcoming: Vec<Value> = http
+            .get(format!("{}/appointments/upcoming", SERVER_URL))
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert!(upcoming.iter().any(|a| a["id"] == appointment_id.as_str()));
+    }
+
+    // Each transition is recorded as a system message
+    let system_messages = sqlx::query!(
+        "SELECT COUNT(*) as count FROM messages WHERE conversation_id = $1 AND message_type = 'system'",
+        conversation_id
+    )
+    .fetch_one(&pool)
+    .await?
+    .count
+    .unwrap_or(0);
+    assert_eq!(system_messages, 2);
+
+    cleanup_test_users(&pool, &[client_id, provider_id]).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_propose_and_decline_appointment() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let outsider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+    let (client_token, _) = generate_test_token(client_id, "client")?;
+    let (provider_token, _) = generate_test_token(provider_id, "provider")?;
+    let (outsider_token, _) = generate_test_token(outsider_id, "provider")?;
+    let http = Client::new();
+
+    // Providers outside the conversation are rejected
+    let (status, _) = propose(&http, &outsider_token, conversation_id).await?;
+    assert_eq!(status, reqwest::StatusCode::FORBIDDEN);
+
+    let (status, body) = propose(&http, &provider_token, conversation_id).await?;
+    assert_eq!(status, reqwest::StatusCode::CREATED, "Propose failed: {}", body);
+    let appointment_id = body["appointment"]["id"].as_str().unwrap().to_string();
+
+    let (status, body) = respond(&http, &client_token, &appointment_id, "decline").await?;
+    assert!(status.is_success(), "Decline failed: {}", body);
+    assert_eq!(body["appointment"]["status"], "declined");
+
+    // Declined appointments are not upcoming
+    let upcoming: Vec<Value> = http
+        .get(format!("{}/appointments/upcoming", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", client_token))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert!(!upcoming.iter().any(|a| a["id"] == appointment_id.as_str()));
+
+    cleanup_test_users(&pool, &[client_id, provider_id, outsider_id]).await;
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+#[tokio::test]
+async fn test_assign_provider() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let vet_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let tech_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let outsider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[vet_id, tech_id]).await;
+
+    let http = Client::new();
+    let (client_token, _) = generate_test_token(client_id, "client")?;
+    let url = format!("{}/conversations/{}/assigned-provider", SERVER_URL, conversation_id);
+
+    let response = http
+        .put(&url)
+        .header("Authorization", format!("Bearer {}", client_token))
+        .json(&json!({ "provider_id": vet_id }))
+        .send()
+        .await?;
+    assert!(response.status().is_success(), "Assign failed: {}", response.status());
+    let conversation: Value = response.json().await?;
+    assert_eq!(conversation["assigned_provider"], vet_id.to_string());
+
+    // Only the conversation's providers can be assigned
+    let response = http
+        .put(&url)
+        .header("Authorization", format!("Bearer {}", client_token))
+        .json(&json!({ "provider_id": outsider_id }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    cleanup_test_users(&pool, &[client_id, vet_id, tech_id, outsider_id]).await;
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use reqwest::{Client, StatusCode};
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+async fn send_canned(ws_stream: &mut WsStream, sender_id: Uuid, conversation_id: Uuid, canned_response_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let message = json!({
+        "sender_id": sender_id,
+        "event": "message",
+        "params": { "conversation_id": conversation_id, "canned_response_id": canned_response_id }
+    });
+    ws_stream.send(Message::Text(message.to_string())).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_canned_responses() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let vet_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let other_vet_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[vet_id, other_vet_id]).await;
+
+    let http = Client::new();
+    let (vet_token, _) = generate_test_token(vet_id, "provider")?;
+    let (other_vet_token, _) = generate_test_token(other_vet_id, "provider")?;
+    let (client_token, _) = generate_test_token(client_id, "client")?;
+
+    // Clients can't manage canned responses
+    let response = http
+        .get(format!("{}/canned-responses", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", client_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = http
+        .post(format!("{}/canned-responses", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", vet_token))
+        .json(&json!({ "title": "Post-op", "body": "Keep the cone on for 10 days.", "category": "surgery" }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created: Value = response.json().await?;
+    let template_id = created["id"].as_str().unwrap().to_string();
+
+    let response = http
+        .put(format!("{}/canned-responses/{}", SERVER_URL, template_id))
+        .header("Authorization", format!("Bearer {}", vet_token))
+        .json(&json!({ "title": "Post-op", "body": "Keep the cone on for 14 days.", "category": "surgery" }))
+        .send()
+        .await?;
+    assert!(response.status().is_success(), "Update failed: {}", response.status());
+
+    let response = http
+        .get(format!("{}/canned-responses?category=surgery", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", vet_token))
+        .send()
+        .await?;
+    let listed: Value = response.json().await?;
+    assert_eq!(listed.as_array().unwrap().len(), 1);
+
+    // Another provider can't touch it
+    let response = http
+        .delete(format!("{}/canned-responses/{}", SERVER_URL, template_id))
+        .header("Authorization", format!("Bearer {}", other_vet_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Sending by id expands the template server-side
+    let (mut vet_ws, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", vet_token)).await?;
+    send_canned(&mut vet_ws, vet_id, conversation_id, &template_id).await?;
+    let sent = next_event(&mut vet_ws, "message_sent").await?;
+    assert_eq!(sent["params"]["content"], "Keep the cone on for 14 days.");
+
+    // ...but only for the provider who owns it
+    let (mut other_ws, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", other_vet_token)).await?;
+    send_canned(&mut other_ws, other_vet_id, conversation_id, &template_id).await?;
+    let error = next_event(&mut other_ws, "error").await?;
+    assert_eq!(error["params"]["status"], 403);
+
+    let response = http
+        .delete(format!("{}/canned-responses/{}", SERVER_URL, template_id))
+        .header("Authorization", format!("Bearer {}", vet_token))
+        .send()
+        .await?;
+    assert!(response.status().is_success(), "Delete failed: {}", response.status());
+
+    cleanup_test_users(&pool, &[client_id, vet_id, other_vet_id]).await;
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use uuid::Uuid;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet, insert_test_conversation,
+    insert_test_message, cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn connect(user_id: Uuid, scope: &str) -> Result<WsStream, Box<dyn std::error::Error>> {
+    let (token, _) = generate_test_token(user_id, scope)?;
+    let (ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", token)).await?;
+    Ok(ws_stream)
+}
+
+async fn send_event(ws_stream: &mut WsStream, user_id: Uuid, event: &str, params: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let message = json!({ "sender_id": user_id, "event": event, "params": params });
+    ws_stream.send(Message::Text(message.to_string())).await?;
+    Ok(())
+}
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+async fn get_access_log(http: &Client, token: &str, conversation_id: Uuid) -> Result<reqwest::Response, reqwest::Error> {
+    http.get(format!("{}/conversations/{}/access-log", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await
+}
+
+#[tokio::test]
+async fn test_history_reads_are_logged() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let vet_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let outsider_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[vet_id]).await;
+    insert_test_message(&pool, conversation_id, client_id, "Millie is limping").await;
+    insert_test_message(&pool, conversation_id, vet_id, "Since when?").await;
+
+    // The vet reads the history over REST, the client over the websocket
+    let http = Client::new();
+    let (vet_token, _) = generate_test_token(vet_id, "provider")?;
+    let response = http
+        .get(format!("{}/conversations/{}/messages", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", vet_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mut client_ws = connect(client_id, "client").await?;
+    send_event(&mut client_ws, client_id, "conversation_history", json!({
+        "conversation_id": conversation_id,
+        "page": 1,
+        "limit": 20
+    })).await?;
+    next_event(&mut client_ws, "conversation_history_response").await?;
+
+    // Entries are written in the background
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let (client_token, _) = generate_test_token(client_id, "client")?;
+    let log = get_access_log(&http, &client_token, conversation_id).await?.json::<Value>().await?;
+    assert_eq!(log["total_count"], 2);
+    let entries = log["entries"].as_array().unwrap();
+    let websocket = entries.iter().find(|entry| entry["source"] == "websocket").unwrap();
+    assert_eq!(websocket["accessor_id"], client_id.to_string());
+    assert_eq!(websocket["message_count"], 2);
+    let rest = entries.iter().find(|entry| entry["source"] == "rest").unwrap();
+    assert_eq!(rest["accessor_id"], vet_id.to_string());
+
+    // Pages follow `page` and `limit`
+    let page = http
+        .get(format!("{}/conversations/{}/access-log?page=2&limit=1", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", vet_token))
+        .send()
+        .await?
+        .json::<Value>()
+        .await?;
+    assert_eq!(page["entries"].as_array().unwrap().len(), 1);
+    assert_eq!(page["has_more"], false);
+
+    // Someone outside the conversation can't read the log
+    let (outsider_token, _) = generate_test_token(outsider_id, "client")?;
+    let response = get_access_log(&http, &outsider_token, conversation_id).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    cleanup_test_users(&pool, &[client_id, vet_id, outsider_id]).await;
+    Ok(())
+}
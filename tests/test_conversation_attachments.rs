@@ -0,0 +1,84 @@
+use reqwest::{Client, StatusCode};
+use serde_json::Value;
+use uuid::Uuid;
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, insert_test_message, cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+#[tokio::test]
+async fn test_conversation_attachments() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let outsider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+    // Two photos a minute apart, with a plain message between them
+    let mut message_ids = Vec::new();
+    for (minutes_ago, name) in [(2.0, "paw"), (1.0, "ear")] {
+        let image_id = sqlx::query!(
+            "INSERT INTO images (id, user_id, image_type, image_url) VALUES ($1, $2, 'attachment', $3) RETURNING id",
+            Uuid::new_v4(),
+            client_id,
+            format!("https://example.com/{}.jpg", name)
+        )
+        .fetch_one(&pool)
+        .await?
+        .id;
+        message_ids.push(sqlx::query!(
+            "INSERT INTO messages (conversation_id, sender_id, content, timestamp, attachment_image_id)
+             VALUES ($1, $2, 'Photo', CURRENT_TIMESTAMP - make_interval(mins => $3), $4) RETURNING id",
+            conversation_id,
+            client_id,
+            minutes_ago as i32,
+            image_id
+        )
+        .fetch_one(&pool)
+        .await?
+        .id);
+    }
+    insert_test_message(&pool, conversation_id, provider_id, "Thanks, looking now").await;
+
+    let http = Client::new();
+    let (provider_token, _) = generate_test_token(provider_id, "provider")?;
+    let response = http
+        .get(format!("{}/conversations/{}/attachments?limit=1", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", provider_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await?;
+    assert_eq!(body["attachments"][0]["message_id"], message_ids[1].to_string());
+    assert_eq!(body["attachments"][0]["image_url"], "https://example.com/ear.jpg");
+    assert_eq!(body["attachments"][0]["sender_id"], client_id.to_string());
+    assert!(body["attachments"][0]["timestamp"].is_i64());
+    assert_eq!(body["has_more"], true);
+
+    // The next page picks up from the last attachment seen
+    let response = http
+        .get(format!("{}/conversations/{}/attachments?limit=1&before={}", SERVER_URL, conversation_id, message_ids[1]))
+        .header("Authorization", format!("Bearer {}", provider_token))
+        .send()
+        .await?;
+    let body: Value = response.json().await?;
+    assert_eq!(body["attachments"][0]["image_url"], "https://example.com/paw.jpg");
+    assert_eq!(body["has_more"], false);
+
+    // Non-participants can't see them
+    let (outsider_token, _) = generate_test_token(outsider_id, "provider")?;
+    let response = http
+        .get(format!("{}/conversations/{}/attachments", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", outsider_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    cleanup_test_users(&pool, &[client_id, provider_id, outsider_id]).await;
+    Ok(())
+}
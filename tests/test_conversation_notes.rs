@@ -0,0 +1,108 @@
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use uuid::Uuid;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet, insert_test_conversation,
+    cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn connect(user_id: Uuid, scope: &str) -> Result<WsStream, Box<dyn std::error::Error>> {
+    let (token, _) = generate_test_token(user_id, scope)?;
+    let (ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", token)).await?;
+    Ok(ws_stream)
+}
+
+async fn send_event(ws_stream: &mut WsStream, user_id: Uuid, event: &str, params: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let message = json!({ "sender_id": user_id, "event": event, "params": params });
+    ws_stream.send(Message::Text(message.to_string())).await?;
+    Ok(())
+}
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_note_edits_are_broadcast_and_versioned() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let vet_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[vet_id]).await;
+
+    let mut vet_ws = connect(vet_id, "provider").await?;
+    let mut client_ws = connect(client_id, "client").await?;
+
+    // The vet writes the first version and the client sees it arrive
+    send_event(&mut vet_ws, vet_id, "update_note", json!({
+        "conversation_id": conversation_id,
+        "content": "Treatment plan: amoxicillin twice daily",
+        "version": 0
+    })).await?;
+    let updated = next_event(&mut client_ws, "note_updated").await?;
+    assert_eq!(updated["params"]["version"], 1);
+    assert_eq!(updated["params"]["updated_by"], vet_id.to_string());
+    next_event(&mut vet_ws, "note_updated").await?;
+
+    // The client can read it but not change it
+    send_event(&mut client_ws, client_id, "get_note", json!({ "conversation_id": conversation_id })).await?;
+    let note = next_event(&mut client_ws, "note").await?;
+    assert_eq!(note["params"]["content"], "Treatment plan: amoxicillin twice daily");
+    send_event(&mut client_ws, client_id, "update_note", json!({
+        "conversation_id": conversation_id,
+        "content": "No more pills please",
+        "version": 1
+    })).await?;
+    let error = next_event(&mut client_ws, "error").await?;
+    assert_eq!(error["params"]["message"], "Only providers can edit the note");
+
+    // A save from a stale version is refused with the current note
+    let http = Client::new();
+    let (vet_token, _) = generate_test_token(vet_id, "provider")?;
+    let response = http
+        .put(format!("{}/conversations/{}/note", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", vet_token))
+        .json(&json!({ "content": "Recheck Friday", "version": 0 }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let conflict = response.json::<Value>().await?;
+    assert_eq!(conflict["error"]["code"], "note_version_conflict");
+    assert_eq!(conflict["error"]["note"]["version"], 1);
+
+    // The conversation summary includes the note
+    let (client_token, _) = generate_test_token(client_id, "client")?;
+    let summary = http
+        .get(format!("{}/conversations/{}/summary", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", client_token))
+        .send()
+        .await?
+        .json::<Value>()
+        .await?;
+    assert_eq!(summary["note"]["content"], "Treatment plan: amoxicillin twice daily");
+
+    cleanup_test_users(&pool, &[client_id, vet_id]).await;
+    Ok(())
+}
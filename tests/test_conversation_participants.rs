@@ -0,0 +1,55 @@
+use reqwest::{Client, StatusCode};
+use serde_json::Value;
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+#[tokio::test]
+async fn test_participants_prefetch() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let vet_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let tech_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let with_vet = insert_test_conversation(&pool, client_id, pet_id, &[vet_id]).await;
+    let with_both = insert_test_conversation(&pool, client_id, pet_id, &[vet_id, tech_id]).await;
+    let tech_only = insert_test_conversation(&pool, client_id, pet_id, &[tech_id]).await;
+
+    let http = Client::new();
+    let (vet_token, _) = generate_test_token(vet_id, "provider")?;
+    let response = http
+        .get(format!("{}/conversations/participants?conversation_ids={},{},{}", SERVER_URL, with_vet, with_both, tech_only))
+        .header("Authorization", format!("Bearer {}", vet_token))
+        .send()
+        .await?;
+    assert!(response.status().is_success(), "Prefetch failed: {}", response.status());
+    let body: Value = response.json().await?;
+
+    // The vet isn't in tech_only, so it's left out
+    let conversations = body["conversations"].as_array().unwrap();
+    assert_eq!(conversations.len(), 2);
+    assert_eq!(conversations[0]["conversation_id"], with_vet.to_string());
+    assert_eq!(conversations[1]["pet"]["id"], pet_id.to_string());
+
+    // client and vet are in both conversations but listed once
+    let users = body["users"].as_object().unwrap();
+    assert_eq!(users.len(), 3);
+    assert_eq!(users[&client_id.to_string()]["scope"], "client");
+
+    // Too many ids
+    let ids: Vec<String> = (0..101).map(|_| uuid::Uuid::new_v4().to_string()).collect();
+    let response = http
+        .get(format!("{}/conversations/participants?conversation_ids={}", SERVER_URL, ids.join(",")))
+        .header("Authorization", format!("Bearer {}", vet_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    cleanup_test_users(&pool, &[client_id, vet_id, tech_id]).await;
+    Ok(())
+}
@@ -0,0 +1,93 @@
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    cleanup_test_users, test_phone_number
+};
+
+// Default CONVERSATION_CREATION_LIMIT_PER_HOUR
+const LIMIT_PER_HOUR: usize = 10;
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn send_event(ws_stream: &mut WsStream, user_id: Uuid, event: &str, params: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let message = json!({ "sender_id": user_id, "event": event, "params": params });
+    ws_stream.send(Message::Text(message.to_string())).await?;
+    Ok(())
+}
+
+/// Whether an event named `event` arrives within a second.
+async fn receives_event(ws_stream: &mut WsStream, event: &str) -> bool {
+    matches!(timeout(Duration::from_secs(1), next_event(ws_stream, event)).await, Ok(Ok(_)))
+}
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_new_conversation_is_throttled() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let bystander_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+
+    let (token, _) = generate_test_token(client_id, "client")?;
+    let (mut ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", token)).await?;
+    let (bystander_token, _) = generate_test_token(bystander_id, "client")?;
+    let (mut bystander_ws, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", bystander_token)).await?;
+
+    for _ in 0..LIMIT_PER_HOUR {
+        let pet_id = insert_test_pet(&pool, client_id).await;
+        send_event(&mut ws_stream, client_id, "new_conversation", json!({
+            "pet_id": pet_id,
+            "providers": [provider_id]
+        })).await?;
+        next_event(&mut ws_stream, "conversation_created").await?;
+    }
+
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    send_event(&mut ws_stream, client_id, "new_conversation", json!({
+        "pet_id": pet_id,
+        "providers": [provider_id]
+    })).await?;
+    let error = next_event(&mut ws_stream, "error").await?;
+    assert_eq!(error["params"]["code"], "conversation_rate_limited");
+    assert!(error["params"]["retry_after"].as_u64().unwrap() > 0);
+    // Other users don't see the client being throttled
+    assert!(!receives_event(&mut bystander_ws, "error").await);
+
+    let created = sqlx::query!("SELECT COUNT(*) AS count FROM conversations WHERE client = $1", client_id)
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(created.count, Some(LIMIT_PER_HOUR as i64));
+
+    let flagged = sqlx::query!(
+        "SELECT COUNT(*) AS count FROM audit_log WHERE action = 'conversation_rate_limited' AND user_id = $1",
+        client_id
+    )
+    .fetch_one(&pool)
+    .await?;
+    assert_eq!(flagged.count, Some(1));
+
+    cleanup_test_users(&pool, &[client_id, provider_id, bystander_id]).await;
+    Ok(())
+}
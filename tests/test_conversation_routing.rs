@@ -0,0 +1,146 @@
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use uuid::Uuid;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn connect(user_id: Uuid, scope: &str) -> Result<WsStream, Box<dyn std::error::Error>> {
+    let (token, _) = generate_test_token(user_id, scope)?;
+    let (ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", token)).await?;
+    Ok(ws_stream)
+}
+
+async fn send_event(ws_stream: &mut WsStream, user_id: Uuid, event: &str, params: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let message = json!({ "sender_id": user_id, "event": event, "params": params });
+    ws_stream.send(Message::Text(message.to_string())).await?;
+    Ok(())
+}
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_round_robin_routing_and_take_over() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let admin_id = insert_test_user(&pool, &test_phone_number(), "admin").await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let vet_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let tech_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+
+    let http = Client::new();
+    let (admin_token, _) = generate_test_token(admin_id, "admin")?;
+    let response = http
+        .post(format!("{}/admin/organizations", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .json(&json!({ "name": "Riverside Vets" }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let organization_id: Uuid = response.json::<Value>().await?["id"].as_str().unwrap().parse()?;
+
+    for member_id in [vet_id, tech_id] {
+        let response = http
+            .put(format!("{}/admin/organizations/{}/members/{}", SERVER_URL, organization_id, member_id))
+            .header("Authorization", format!("Bearer {}", admin_token))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let response = http
+        .put(format!("{}/admin/organizations/{}/routing", SERVER_URL, organization_id))
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .json(&json!({ "routing_mode": "lottery" }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = http
+        .put(format!("{}/admin/organizations/{}/routing", SERVER_URL, organization_id))
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .json(&json!({ "routing_mode": "round_robin" }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.json::<Value>().await?["routing_mode"], "round_robin");
+
+    let mut vet_ws = connect(vet_id, "provider").await?;
+    let mut tech_ws = connect(tech_id, "provider").await?;
+    let mut client_ws = connect(client_id, "client").await?;
+
+    // The first inquiry goes to the vet alone, with a note in the history
+    send_event(&mut client_ws, client_id, "new_conversation", json!({
+        "pet_id": pet_id,
+        "organization_id": organization_id
+    })).await?;
+    let created = next_event(&mut client_ws, "conversation_created").await?;
+    assert_eq!(created["params"]["assigned_provider"], vet_id.to_string());
+    let conversation_id = created["params"]["id"].as_str().unwrap().to_string();
+    let invitation = next_event(&mut vet_ws, "new_conversation_invitation").await?;
+    assert_eq!(invitation["params"]["id"], conversation_id);
+    let routed = next_event(&mut client_ws, "message_sent").await?;
+    assert!(routed["params"]["content"].as_str().unwrap().starts_with("Routed to"));
+    assert!(next_event(&mut tech_ws, "new_conversation_invitation").await.is_err());
+
+    // The tech takes it over; everyone following the conversation hears about it
+    let (tech_token, _) = generate_test_token(tech_id, "provider")?;
+    let response = http
+        .post(format!("{}/conversations/{}/take-over", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", tech_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.json::<Value>().await?["assigned_provider"], tech_id.to_string());
+    for ws in [&mut client_ws, &mut vet_ws] {
+        let changed = next_event(ws, "assignment_changed").await?;
+        assert_eq!(changed["params"]["assigned_provider"], tech_id.to_string());
+        let note = next_event(ws, "message_sent").await?;
+        assert!(note["params"]["content"].as_str().unwrap().contains("took over from"));
+    }
+
+    // Taking over twice is refused, as is a client trying it
+    let response = http
+        .post(format!("{}/conversations/{}/take-over", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", tech_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let (client_token, _) = generate_test_token(client_id, "client")?;
+    let response = http
+        .post(format!("{}/conversations/{}/take-over", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", client_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    sqlx::query!("DELETE FROM organizations WHERE id = $1", organization_id)
+        .execute(&pool)
+        .await?;
+    cleanup_test_users(&pool, &[admin_id, client_id, vet_id, tech_id]).await;
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use reqwest::Client;
+use serde_json::Value;
+use tokio_tungstenite::connect_async;
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, insert_test_message, cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+#[tokio::test]
+async fn test_history_includes_state_on_request() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+    let message_id = insert_test_message(&pool, conversation_id, provider_id, "Hi").await;
+    sqlx::query!(
+        "INSERT INTO conversation_reads (conversation_id, user_id, last_read_message_id) VALUES ($1, $2, $3)",
+        conversation_id,
+        client_id,
+        message_id
+    )
+    .execute(&pool)
+    .await?;
+
+    // The provider is online; the client only uses REST
+    let (provider_token, _) = generate_test_token(provider_id, "provider")?;
+    let (_provider_ws, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", provider_token)).await?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+
+    let http = Client::new();
+    let (client_token, _) = generate_test_token(client_id, "client")?;
+
+    let body: Value = http
+        .get(format!("{}/conversations/{}/messages", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", client_token))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["total_count"], 1);
+    assert!(body.get("state").is_none());
+
+    let body: Value = http
+        .get(format!("{}/conversations/{}/messages?include_state=true", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", client_token))
+        .send()
+        .await?
+        .json()
+        .await?;
+    let state = &body["state"];
+    assert_eq!(state["last_read_message_id"], message_id.to_string());
+    let participants = state["participants"].as_array().unwrap();
+    let participant = |id: uuid::Uuid| participants.iter().find(|p| p["user_id"] == id.to_string()).unwrap().clone();
+    assert_eq!(participant(client_id)["online"], false);
+    assert!(participant(client_id)["last_read_at"].is_i64());
+    assert_eq!(participant(provider_id)["online"], true);
+    assert!(participant(provider_id)["last_read_at"].is_null());
+
+    cleanup_test_users(&pool, &[client_id, provider_id]).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_history_includes_the_callers_read_position() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+    let read_id = insert_test_message(&pool, conversation_id, provider_id, "Read").await;
+    insert_test_message(&pool, conversation_id, provider_id, "Unread").await;
+    sqlx::query!(
+        "INSERT INTO conversation_reads (conversation_id, user_id, last_read_message_id) VALUES ($1, $2, $3)",
+        conversation_id,
+        client_id,
+        read_id
+    )
+    .execute(&pool)
+    .await?;
+
+    let http = Client::new();
+    let history = |user_id, scope: &'static str| {
+        let http = http.clone();
+        async move {
+            let (token, _) = generate_test_token(user_id, scope)?;
+            let body: Value = http
+                .get(format!("{}/conversations/{}/messages", SERVER_URL, conversation_id))
+                .header("Authorization", format!("Bearer {}", token))
+                .send()
+                .await?
+                .json()
+                .await?;
+            Ok::<_, Box<dyn std::error::Error>>(body)
+        }
+    };
+
+    // No include_state needed; the client has read up to the first message
+    let body = history(client_id, "client").await?;
+    assert_eq!(body["last_read_message_id"], read_id.to_string());
+    assert!(body["last_read_at"].is_i64());
+
+    // The provider hasn't read anything, so everything is unread
+    let body = history(provider_id, "provider").await?;
+    assert!(body["last_read_message_id"].is_null());
+    assert!(body["last_read_at"].is_null());
+
+    cleanup_test_users(&pool, &[client_id, provider_id]).await;
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use reqwest::Client;
+use serde_json::Value;
+use uuid::Uuid;
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+#[tokio::test]
+async fn test_conversation_summary() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let outsider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+    // Two messages, one carrying a 2 KB attachment
+    let image_id = sqlx::query!(
+        "INSERT INTO images (id, user_id, image_type, image_url, size_bytes) VALUES ($1, $2, 'attachment', 'https://example.com/xray.jpg', 2048) RETURNING id",
+        Uuid::new_v4(),
+        client_id
+    )
+    .fetch_one(&pool)
+    .await?
+    .id;
+    sqlx::query!(
+        "INSERT INTO messages (conversation_id, sender_id, content, attachment_image_id) VALUES ($1, $2, 'Here is the x-ray', $3)",
+        conversation_id,
+        client_id,
+        image_id
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO messages (conversation_id, sender_id, content) VALUES ($1, $2, 'Thanks, looks good')",
+        conversation_id,
+        provider_id
+    )
+    .execute(&pool)
+    .await?;
+
+    let http = Client::new();
+    let (provider_token, _) = generate_test_token(provider_id, "provider")?;
+    let response = http
+        .get(format!("{}/conversations/{}/summary", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", provider_token))
+        .send()
+        .await?;
+    assert!(response.status().is_success(), "Summary failed: {}", response.status());
+    let summary: Value = response.json().await?;
+    assert_eq!(summary["message_count"], 2);
+    assert_eq!(summary["participant_count"], 2);
+    assert_eq!(summary["attachment_count"], 1);
+    assert_eq!(summary["attachment_bytes"], 2048);
+    assert!(summary["estimated_export_bytes"].as_i64().unwrap() > 2048);
+    assert!(summary["first_message_at"].is_i64());
+
+    // Non-participants can't see it
+    let (outsider_token, _) = generate_test_token(outsider_id, "provider")?;
+    let response = http
+        .get(format!("{}/conversations/{}/summary", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", outsider_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    cleanup_test_users(&pool, &[client_id, provider_id, outsider_id]).await;
+    Ok(())
+}
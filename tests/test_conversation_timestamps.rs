@@ -0,0 +1,65 @@
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    cleanup_test_users, test_phone_number
+};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn send_event(ws_stream: &mut WsStream, user_id: Uuid, event: &str, params: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let message = json!({ "sender_id": user_id, "event": event, "params": params });
+    ws_stream.send(Message::Text(message.to_string())).await?;
+    Ok(())
+}
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_new_conversation_reports_created_at() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+
+    let (token, _) = generate_test_token(client_id, "client")?;
+    let (mut ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", token)).await?;
+
+    let before = chrono::Utc::now().timestamp_millis() - 5_000;
+    send_event(&mut ws_stream, client_id, "new_conversation", json!({
+        "providers": [provider_id],
+        "pet_id": pet_id
+    })).await?;
+    let created = next_event(&mut ws_stream, "conversation_created").await?;
+    let conversation_id = created["params"]["id"].clone();
+    let created_at = created["params"]["created_at"].as_i64().unwrap();
+    assert!(created_at >= before && created_at <= chrono::Utc::now().timestamp_millis());
+
+    send_event(&mut ws_stream, client_id, "conversation_timestamps", json!({ "conversation_id": conversation_id })).await?;
+    let timestamps = next_event(&mut ws_stream, "conversation_timestamps").await?;
+    assert_eq!(timestamps["params"]["created_at"], json!(created_at));
+    assert!(timestamps["params"]["first_message_at"].is_null());
+
+    cleanup_test_users(&pool, &[client_id, provider_id]).await;
+    Ok(())
+}
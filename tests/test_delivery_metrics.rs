@@ -0,0 +1,87 @@
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, cleanup_test_users, test_phone_number
+};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn send_message(ws_stream: &mut WsStream, user_id: Uuid, params: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let message = json!({ "sender_id": user_id, "event": "message", "params": params });
+    ws_stream.send(Message::Text(message.to_string())).await?;
+    Ok(())
+}
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+fn histogram_count(metrics: &str, name: &str) -> u64 {
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(&format!("{}_count ", name)))
+        .and_then(|count| count.parse().ok())
+        .unwrap_or_else(|| panic!("{} missing from metrics", name))
+}
+
+#[tokio::test]
+async fn test_message_delivery_is_traced_and_measured() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+    let http = reqwest::Client::new();
+    let before = http.get("http://localhost:8080/metrics").send().await?.text().await?;
+
+    let (token, _) = generate_test_token(client_id, "client")?;
+    let (mut ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", token)).await?;
+
+    // Without `trace` the frame carries no timings
+    send_message(&mut ws_stream, client_id, json!({
+        "conversation_id": conversation_id,
+        "content": "Untraced"
+    })).await?;
+    let untraced = next_event(&mut ws_stream, "message_sent").await?;
+    assert!(untraced["params"].get("delivery_trace").is_none());
+
+    // With it, both stages are reported
+    send_message(&mut ws_stream, client_id, json!({
+        "conversation_id": conversation_id,
+        "content": "Traced",
+        "trace": true
+    })).await?;
+    let traced = next_event(&mut ws_stream, "message_sent").await?;
+    let delivery_trace = &traced["params"]["delivery_trace"];
+    assert!(delivery_trace["receive_to_persist_us"].is_u64());
+    assert!(delivery_trace["persist_to_deliver_us"].is_u64());
+
+    let after = http.get("http://localhost:8080/metrics").send().await?.text().await?;
+    for name in ["vettext_message_receive_to_persist_seconds", "vettext_message_persist_to_deliver_seconds"] {
+        assert!(histogram_count(&after, name) >= histogram_count(&before, name) + 2);
+    }
+    assert!(after.ends_with("# EOF\n"));
+
+    cleanup_test_users(&pool, &[client_id, provider_id]).await;
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+#[tokio::test]
+async fn test_display_names_for_visible_users() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let vet_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let stranger_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    insert_test_conversation(&pool, client_id, pet_id, &[vet_id]).await;
+    sqlx::query!("UPDATE users SET first_name = 'Dana', last_name = 'Reyes' WHERE id = $1", vet_id)
+        .execute(&pool)
+        .await?;
+
+    let http = Client::new();
+    let (client_token, _) = generate_test_token(client_id, "client")?;
+    let response = http
+        .post(format!("{}/users/display", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", client_token))
+        .json(&json!({ "ids": [vet_id, stranger_id, Uuid::new_v4()] }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await?;
+
+    // Only the vet shares a conversation with the client
+    let users = body.as_object().unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[&vet_id.to_string()]["display_name"], "Dana Reyes");
+    assert!(users[&vet_id.to_string()].get("profile_image_url").is_some());
+
+    // Batches are capped
+    let too_many: Vec<Uuid> = (0..201).map(|_| Uuid::new_v4()).collect();
+    let response = http
+        .post(format!("{}/users/display", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", client_token))
+        .json(&json!({ "ids": too_many }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    cleanup_test_users(&pool, &[client_id, vet_id, stranger_id]).await;
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+
+mod testing_utils;
+use testing_utils::{generate_test_token, setup_test_db, insert_test_user, cleanup_test_users, test_phone_number};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+#[tokio::test]
+async fn test_feature_flag_gates_endpoint() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let admin_id = insert_test_user(&pool, &test_phone_number(), "admin").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+
+    let http = Client::new();
+    let (admin_token, _) = generate_test_token(admin_id, "admin")?;
+    let (provider_token, _) = generate_test_token(provider_id, "provider")?;
+
+    let set_flag = |enabled: bool| {
+        http.put(format!("{}/admin/feature-flags/{}", SERVER_URL, provider_id))
+            .header("Authorization", format!("Bearer {}", admin_token))
+            .json(&json!({ "flag": "canned_responses", "enabled": enabled }))
+            .send()
+    };
+    let list_canned = || {
+        http.get(format!("{}/canned-responses", SERVER_URL))
+            .header("Authorization", format!("Bearer {}", provider_token))
+            .send()
+    };
+    let features = || async {
+        let body: Value = http
+            .get(format!("{}/config", SERVER_URL))
+            .header("Authorization", format!("Bearer {}", provider_token))
+            .send()
+            .await?
+            .json()
+            .await?;
+        Ok::<Vec<Value>, reqwest::Error>(body["features"].as_array().cloned().unwrap_or_default())
+    };
+
+    // Off: the endpoint disappears and the flag isn't advertised
+    assert!(set_flag(false).await?.status().is_success());
+    assert_eq!(list_canned().await?.status(), StatusCode::NOT_FOUND);
+    assert!(!features().await?.contains(&json!("canned_responses")));
+
+    // On: back to normal
+    assert!(set_flag(true).await?.status().is_success());
+    assert!(list_canned().await?.status().is_success());
+    assert!(features().await?.contains(&json!("canned_responses")));
+
+    cleanup_test_users(&pool, &[admin_id, provider_id]).await;
+    Ok(())
+}
@@ -1,11 +1,9 @@
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
 use reqwest::Client;
-use tokio;
 use sqlx::{PgPool, postgres::PgPoolOptions};
 use std::env;
 use serde::{Deserialize, Serialize};
-use dotenv;
 mod testing_utils;
 use testing_utils::generate_test_token;
 
@@ -0,0 +1,60 @@
+use reqwest::{Client, StatusCode};
+use serde_json::Value;
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+#[tokio::test]
+async fn test_deep_pages_require_cursor() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+    let mut message_ids = Vec::new();
+    for seconds_ago in [30.0, 20.0, 10.0] {
+        message_ids.push(sqlx::query!(
+            "INSERT INTO messages (conversation_id, sender_id, content, timestamp)
+             VALUES ($1, $2, 'Hi', CURRENT_TIMESTAMP - make_interval(secs => $3)) RETURNING id",
+            conversation_id,
+            provider_id,
+            seconds_ago
+        )
+        .fetch_one(&pool)
+        .await?
+        .id);
+    }
+
+    let http = Client::new();
+    let (token, _) = generate_test_token(client_id, "client")?;
+
+    // Page 100 of 20 starts past MAX_HISTORY_OFFSET (1000 by default)
+    let response = http
+        .get(format!("{}/conversations/{}/messages?page=100&limit=20", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = response.json().await?;
+    assert_eq!(body["error"]["code"], "cursor_required");
+
+    // The cursor returns messages older than the one given
+    let response = http
+        .get(format!("{}/conversations/{}/messages?limit=20&before={}", SERVER_URL, conversation_id, message_ids[2]))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await?;
+    let ids: Vec<&str> = body["messages"].as_array().unwrap().iter().map(|m| m["id"].as_str().unwrap()).collect();
+    assert_eq!(ids, vec![message_ids[1].to_string(), message_ids[0].to_string()]);
+    assert_eq!(body["has_more"], false);
+
+    cleanup_test_users(&pool, &[client_id, provider_id]).await;
+    Ok(())
+}
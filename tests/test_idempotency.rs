@@ -0,0 +1,67 @@
+use reqwest::Client;
+use serde_json::{json, Value};
+
+mod testing_utils;
+use testing_utils::{generate_test_token, setup_test_db, insert_test_user, cleanup_test_users, test_phone_number};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+#[tokio::test]
+async fn test_pet_creation_is_idempotent() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let user_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let (token, _) = generate_test_token(user_id, "client")?;
+    let http = Client::new();
+
+    let key = uuid::Uuid::new_v4().to_string();
+    let body = json!({
+        "name": "Max",
+        "breed": "Golden Retriever",
+        "sex": "M",
+        "birthday": 1579046400000i64
+    });
+
+    let first = http
+        .post(format!("{}/pet", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", token))
+        .header("Idempotency-Key", &key)
+        .json(&body)
+        .send()
+        .await?;
+    assert!(first.status().is_success(), "Create failed: {}", first.status());
+    assert!(first.headers().get("Idempotency-Replayed").is_none());
+    let first: Value = first.json().await?;
+
+    // Retrying with the same key replays the first response instead of creating another pet
+    let retry = http
+        .post(format!("{}/pet", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", token))
+        .header("Idempotency-Key", &key)
+        .json(&body)
+        .send()
+        .await?;
+    assert!(retry.status().is_success());
+    assert_eq!(retry.headers().get("Idempotency-Replayed").unwrap(), "true");
+    let retry: Value = retry.json().await?;
+    assert_eq!(first["pet"]["id"], retry["pet"]["id"]);
+
+    let count = sqlx::query!("SELECT COUNT(*) as count FROM pets WHERE user_id = $1", user_id)
+        .fetch_one(&pool)
+        .await?
+        .count
+        .unwrap_or(0);
+    assert_eq!(count, 1);
+
+    // The same key on a different endpoint is rejected
+    let mismatch = http
+        .post(format!("{}/profile", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", token))
+        .header("Idempotency-Key", &key)
+        .json(&json!({ "first_name": "Test", "pets": [] }))
+        .send()
+        .await?;
+    assert_eq!(mismatch.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+
+    cleanup_test_users(&pool, &[user_id]).await;
+    Ok(())
+}
@@ -1,6 +1,4 @@
 use reqwest::Client;
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
 use uuid::Uuid;
 use serde_json::Value;
 use std::error::Error as StdError;
@@ -52,14 +50,14 @@ async fn test_image_upload() -> Result<(), Box<dyn StdError>> {
     // Use a real image file for testing
     let image_path = "me_and_millie_at_manzanita.jpeg";
     let file_bytes = tokio::fs::read(image_path).await
-        .map_err(|e| Box::<dyn StdError>::from(e))?;
+        .map_err(Box::<dyn StdError>::from)?;
 
     println!("Read test image '{}' with size: {} bytes", image_path, file_bytes.len());
 
     let file_part = reqwest::multipart::Part::bytes(file_bytes)
         .file_name("me_and_millie_at_manzanita.jpeg")
         .mime_str("image/jpeg")
-        .map_err(|e| Box::<dyn StdError>::from(e))?;
+        .map_err(Box::<dyn StdError>::from)?;
 
     let form = reqwest::multipart::Form::new()
         .part("file", file_part);
@@ -68,7 +66,7 @@ async fn test_image_upload() -> Result<(), Box<dyn StdError>> {
     let client = Client::builder()
         .timeout(std::time::Duration::from_secs(30))
         .build()
-        .map_err(|e| Box::<dyn StdError>::from(e))?;
+        .map_err(Box::<dyn StdError>::from)?;
 
     // Modified: Use a different endpoint for local testing
     let base_url = get_server_url();
@@ -145,7 +143,7 @@ async fn test_image_upload() -> Result<(), Box<dyn StdError>> {
     
     // Parse the response body as JSON
     let response_json: Value = serde_json::from_str(&body)
-        .map_err(|e| Box::<dyn StdError>::from(e))?;
+        .map_err(Box::<dyn StdError>::from)?;
     
     // Assert that the response contains the expected fields
     assert!(response_json["message"].is_string(), "Response missing 'message' field");
@@ -203,12 +201,12 @@ async fn test_get_images() -> Result<(), Box<dyn StdError>> {
         .header("Authorization", format!("Bearer {}", access_token))
         .send()
         .await
-        .map_err(|e| Box::<dyn StdError>::from(e))?;
+        .map_err(Box::<dyn StdError>::from)?;
     
     // Check the response status
     let status = response.status();
     let body = response.text().await
-        .map_err(|e| Box::<dyn StdError>::from(e))?;
+        .map_err(Box::<dyn StdError>::from)?;
     
     println!("Get images response status: {}", status);
     println!("Get images response body: {}", body);
@@ -218,7 +216,7 @@ async fn test_get_images() -> Result<(), Box<dyn StdError>> {
     
     // Parse the response body as JSON
     let response_json: Value = serde_json::from_str(&body)
-        .map_err(|e| Box::<dyn StdError>::from(e))?;
+        .map_err(Box::<dyn StdError>::from)?;
     
     // Assert that the response is an array (even if empty for a new user)
     assert!(response_json.is_array(), "Response is not an array");
@@ -0,0 +1,78 @@
+use ed25519_dalek::Signer;
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use base64::{Engine as _, engine::general_purpose};
+use chrono::Utc;
+use uuid::Uuid;
+
+mod testing_utils;
+use testing_utils::{TEST_SIGNING_KEY, TEST_VERIFYING_KEY, to_canonical_json, setup_test_db, cleanup_test_users};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+async fn signed_post(client: &Client, path: &str, data: Value) -> Result<reqwest::Response, reqwest::Error> {
+    let signature = TEST_SIGNING_KEY.sign(to_canonical_json(&data).as_bytes());
+    client.post(format!("{}{}", SERVER_URL, path))
+        .json(&json!({
+            "data": data,
+            "signature": general_purpose::STANDARD.encode(signature.to_bytes())
+        }))
+        .send()
+        .await
+}
+
+#[tokio::test]
+async fn test_uk_number_registers_and_logs_in() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let http = Client::new();
+    // A UK test number, written nationally
+    let national = format!("000123 {:06}", rand::random::<u32>() % 1_000_000);
+
+    let response = signed_post(&http, "/register", json!({
+        "phone_number": national,
+        "country_code": "GB",
+        "public_key": general_purpose::STANDARD.encode(TEST_VERIFYING_KEY.as_bytes()),
+        "timestamp": Utc::now().to_rfc3339()
+    }))
+    .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let registered: Value = response.json().await?;
+    let user_id: Uuid = registered["user_id"].as_str().unwrap().parse()?;
+    let stored = sqlx::query!("SELECT phone_number FROM users WHERE id = $1", user_id)
+        .fetch_one(&pool)
+        .await?
+        .phone_number;
+    assert_eq!(stored, format!("+44{}", national.replace(' ', "")));
+
+    // The same number in international form is already registered
+    let duplicate = signed_post(&http, "/register", json!({
+        "phone_number": stored,
+        "public_key": general_purpose::STANDARD.encode(TEST_VERIFYING_KEY.as_bytes()),
+        "timestamp": Utc::now().to_rfc3339()
+    }))
+    .await?;
+    assert_eq!(duplicate.status(), StatusCode::CONFLICT);
+
+    let requested = signed_post(&http, "/request-verification-code", json!({
+        "phone_number": national,
+        "country_code": "GB",
+        "timestamp": Utc::now().to_rfc3339()
+    }))
+    .await?;
+    assert_eq!(requested.status(), StatusCode::OK);
+    let requested: Value = requested.json().await?;
+    assert!(requested["message"].as_str().unwrap().contains("123456"));
+
+    let logged_in = signed_post(&http, "/login", json!({
+        "user_id": user_id,
+        "timestamp": Utc::now().to_rfc3339(),
+        "verification_code": "123456"
+    }))
+    .await?;
+    assert_eq!(logged_in.status(), StatusCode::OK);
+    let logged_in: Value = logged_in.json().await?;
+    assert!(logged_in["access_token"].is_string());
+
+    cleanup_test_users(&pool, &[user_id]).await;
+    Ok(())
+}
@@ -1,11 +1,13 @@
 use ed25519_dalek::Signer;
-use serde_json::{json, Value};
+use serde_json::json;
 use base64::{Engine as _, engine::general_purpose};
 use chrono::Utc;
 use uuid::Uuid;
 
 mod testing_utils;
-use testing_utils::{TEST_SIGNING_KEY, to_canonical_json};
+use testing_utils::{
+    TEST_SIGNING_KEY, TEST_VERIFYING_KEY, to_canonical_json, setup_test_db, cleanup_test_users, test_phone_number
+};
 
 #[tokio::test]
 async fn test_login_endpoint() -> Result<(), Box<dyn std::error::Error>> {
@@ -64,3 +66,98 @@ async fn test_login_endpoint() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_replayed_login_is_refused() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO users (id, phone_number, public_key, scope, verified) VALUES ($1, $2, $3, 'client', true)",
+        user_id,
+        test_phone_number(),
+        general_purpose::STANDARD.encode(TEST_VERIFYING_KEY.as_bytes())
+    )
+    .execute(&pool)
+    .await?;
+
+    let data = json!({
+        "user_id": user_id,
+        "timestamp": Utc::now().to_rfc3339(),
+        "verification_code": "123456"
+    });
+    let signature = TEST_SIGNING_KEY.sign(to_canonical_json(&data).as_bytes());
+    let payload = json!({
+        "data": data,
+        "signature": general_purpose::STANDARD.encode(signature.to_bytes())
+    });
+
+    // The same signed payload only works once, even within the timestamp window
+    let client = reqwest::Client::new();
+    let first = client.post("http://localhost:8080/login").json(&payload).send().await?;
+    assert!(first.status().is_success());
+    let replay = client.post("http://localhost:8080/login").json(&payload).send().await?;
+    assert_eq!(replay.status(), reqwest::StatusCode::CONFLICT);
+    let body: serde_json::Value = replay.json().await?;
+    assert_eq!(body["error"]["code"], "request_replayed");
+
+    // Only the first login started a session
+    let sessions = sqlx::query!("SELECT COUNT(*) AS count FROM refresh_tokens WHERE user_id = $1", user_id)
+        .fetch_one(&pool)
+        .await?
+        .count;
+    assert_eq!(sessions, Some(1));
+
+    cleanup_test_users(&pool, &[user_id]).await;
+    Ok(())
+}
+
+async fn log_in(client: &reqwest::Client, user_id: Uuid, verification_code: &str) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+    let data = json!({
+        "user_id": user_id,
+        "timestamp": Utc::now().to_rfc3339(),
+        "verification_code": verification_code
+    });
+    let signature = TEST_SIGNING_KEY.sign(to_canonical_json(&data).as_bytes());
+    let payload = json!({
+        "data": data,
+        "signature": general_purpose::STANDARD.encode(signature.to_bytes())
+    });
+    Ok(client.post("http://localhost:8080/login").json(&payload).send().await?)
+}
+
+#[tokio::test]
+async fn test_wrong_codes_lock_out_login() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO users (id, phone_number, public_key, scope, verified) VALUES ($1, $2, $3, 'client', true)",
+        user_id,
+        test_phone_number(),
+        general_purpose::STANDARD.encode(TEST_VERIFYING_KEY.as_bytes())
+    )
+    .execute(&pool)
+    .await?;
+    let client = reqwest::Client::new();
+
+    // A successful login starts the count over
+    for _ in 0..4 {
+        assert_eq!(log_in(&client, user_id, "000000").await?.status(), reqwest::StatusCode::BAD_REQUEST);
+    }
+    assert!(log_in(&client, user_id, "123456").await?.status().is_success());
+
+    for _ in 0..4 {
+        assert_eq!(log_in(&client, user_id, "000000").await?.status(), reqwest::StatusCode::BAD_REQUEST);
+    }
+    let locked = log_in(&client, user_id, "000000").await?;
+    assert_eq!(locked.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+    assert!(locked.headers().contains_key("retry-after"));
+    let body: serde_json::Value = locked.json().await?;
+    assert_eq!(body["error"]["code"], "verification_code_lockout");
+
+    // The right code doesn't help until the lockout ends
+    let refused = log_in(&client, user_id, "123456").await?;
+    assert_eq!(refused.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+
+    cleanup_test_users(&pool, &[user_id]).await;
+    Ok(())
+}
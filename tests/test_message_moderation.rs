@@ -0,0 +1,79 @@
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use serde_json::{json, Value};
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, cleanup_test_users, test_phone_number
+};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+/// Whether an event named `event` arrives within a second.
+async fn receives_event(ws_stream: &mut WsStream, event: &str) -> bool {
+    matches!(timeout(Duration::from_secs(1), next_event(ws_stream, event)).await, Ok(Ok(_)))
+}
+
+// Assumes the server runs with the default MODERATION_ACTION=reject and card detection on
+#[tokio::test]
+async fn test_card_number_is_rejected() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+    let outsider_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+
+    let (token, _) = generate_test_token(client_id, "client")?;
+    let (mut ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", token)).await?;
+    let (outsider_token, _) = generate_test_token(outsider_id, "client")?;
+    let (mut outsider_ws, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", outsider_token)).await?;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let message = json!({
+        "sender_id": client_id,
+        "event": "message",
+        "params": {
+            "conversation_id": conversation_id,
+            "content": "Sure, my card is 4111 1111 1111 1111"
+        }
+    });
+    ws_stream.send(Message::Text(message.to_string())).await?;
+
+    let error = next_event(&mut ws_stream, "error").await?;
+    assert_eq!(error["params"]["code"], "content_blocked");
+    assert_eq!(error["params"]["rules"], json!(["card_number"]));
+    // Only the sender hears about it
+    assert!(!receives_event(&mut outsider_ws, "error").await);
+
+    // Nothing was stored, but the attempt was audited
+    let stored = sqlx::query!("SELECT COUNT(*) AS count FROM messages WHERE conversation_id = $1", conversation_id)
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(stored.count, Some(0));
+    let audited = sqlx::query!("SELECT COUNT(*) AS count FROM audit_log WHERE action = 'message_blocked' AND actor_id = $1", client_id)
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(audited.count, Some(1));
+
+    cleanup_test_users(&pool, &[client_id, provider_id, outsider_id]).await;
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet, insert_test_conversation,
+    cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+#[tokio::test]
+async fn test_notification_preferences() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let vet_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let outsider_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[vet_id]).await;
+
+    let http = Client::new();
+    let (token, _) = generate_test_token(client_id, "client")?;
+
+    // Nothing set yet: notified about everything
+    let preferences = http
+        .get(format!("{}/notification-preferences", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?
+        .json::<Value>()
+        .await?;
+    assert_eq!(preferences["default"], json!({ "conversation_id": null, "muted": false, "level": "all" }));
+    assert_eq!(preferences["conversations"], json!([]));
+
+    let response = http
+        .put(format!("{}/notification-preferences", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&json!({ "muted": false, "level": "push_only" }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = http
+        .put(format!("{}/conversations/{}/notification-preferences", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&json!({ "muted": true }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let preferences = http
+        .get(format!("{}/notification-preferences", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?
+        .json::<Value>()
+        .await?;
+    assert_eq!(preferences["default"]["level"], "push_only");
+    assert_eq!(preferences["conversations"][0]["conversation_id"], conversation_id.to_string());
+    assert_eq!(preferences["conversations"][0]["muted"], true);
+
+    // Unknown levels and other people's conversations are refused
+    let response = http
+        .put(format!("{}/notification-preferences", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&json!({ "muted": false, "level": "loud" }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let (outsider_token, _) = generate_test_token(outsider_id, "client")?;
+    let response = http
+        .put(format!("{}/conversations/{}/notification-preferences", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", outsider_token))
+        .json(&json!({ "muted": true }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Clearing the conversation's preference falls back to the default
+    let response = http
+        .delete(format!("{}/conversations/{}/notification-preferences", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = http
+        .delete(format!("{}/conversations/{}/notification-preferences", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    cleanup_test_users(&pool, &[client_id, vet_id, outsider_id]).await;
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use uuid::Uuid;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn connect(user_id: Uuid, scope: &str) -> Result<WsStream, Box<dyn std::error::Error>> {
+    let (token, _) = generate_test_token(user_id, scope)?;
+    let (ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", token)).await?;
+    Ok(ws_stream)
+}
+
+async fn send_event(ws_stream: &mut WsStream, user_id: Uuid, event: &str, params: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let message = json!({ "sender_id": user_id, "event": event, "params": params });
+    ws_stream.send(Message::Text(message.to_string())).await?;
+    Ok(())
+}
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_organization_inbox_fan_out() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let admin_id = insert_test_user(&pool, &test_phone_number(), "admin").await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let vet_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let tech_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+
+    let http = Client::new();
+    let (admin_token, _) = generate_test_token(admin_id, "admin")?;
+    let response = http
+        .post(format!("{}/admin/organizations", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .json(&json!({ "name": "Northside Vets" }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let organization_id: Uuid = response.json::<Value>().await?["id"].as_str().unwrap().parse()?;
+
+    for member_id in [vet_id, tech_id] {
+        let response = http
+            .put(format!("{}/admin/organizations/{}/members/{}", SERVER_URL, organization_id, member_id))
+            .header("Authorization", format!("Bearer {}", admin_token))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let mut vet_ws = connect(vet_id, "provider").await?;
+    let mut tech_ws = connect(tech_id, "provider").await?;
+    let mut client_ws = connect(client_id, "client").await?;
+
+    // Every member is invited to a conversation started with the clinic
+    send_event(&mut client_ws, client_id, "new_conversation", json!({
+        "pet_id": pet_id,
+        "organization_id": organization_id
+    })).await?;
+    let created = next_event(&mut client_ws, "conversation_created").await?;
+    assert_eq!(created["params"]["organization_id"], organization_id.to_string());
+    let conversation_id = created["params"]["id"].as_str().unwrap().to_string();
+    for ws in [&mut vet_ws, &mut tech_ws] {
+        let invitation = next_event(ws, "new_conversation_invitation").await?;
+        assert_eq!(invitation["params"]["id"], conversation_id);
+    }
+
+    // A reply from one member reaches the client and the other member, attributed to its sender
+    send_event(&mut vet_ws, vet_id, "message", json!({
+        "conversation_id": conversation_id,
+        "content": "Dr. Reyes here, how can I help?"
+    })).await?;
+    for ws in [&mut client_ws, &mut tech_ws] {
+        let message = next_event(ws, "message_sent").await?;
+        assert_eq!(message["params"]["sender_id"], vet_id.to_string());
+    }
+
+    // Once the vet leaves, they stop receiving the conversation
+    let response = http
+        .delete(format!("{}/admin/organizations/{}/members/{}", SERVER_URL, organization_id, vet_id))
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    send_event(&mut client_ws, client_id, "message", json!({
+        "conversation_id": conversation_id,
+        "content": "Thanks!"
+    })).await?;
+    next_event(&mut tech_ws, "message_sent").await?;
+    assert!(next_event(&mut vet_ws, "message_sent").await.is_err());
+
+    // Their earlier reply keeps its sender, and the summary shows the clinic
+    let (client_token, _) = generate_test_token(client_id, "client")?;
+    let history = http
+        .get(format!("{}/conversations/{}/messages", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", client_token))
+        .send()
+        .await?
+        .json::<Value>()
+        .await?;
+    let reply = history["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|m| m["content"] == "Dr. Reyes here, how can I help?")
+        .unwrap();
+    assert_eq!(reply["sender_id"], vet_id.to_string());
+
+    let summary = http
+        .get(format!("{}/conversations/{}/summary", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", client_token))
+        .send()
+        .await?
+        .json::<Value>()
+        .await?;
+    assert_eq!(summary["organization"]["name"], "Northside Vets");
+
+    sqlx::query!("DELETE FROM organizations WHERE id = $1", organization_id)
+        .execute(&pool)
+        .await?;
+    cleanup_test_users(&pool, &[admin_id, client_id, vet_id, tech_id]).await;
+    Ok(())
+}
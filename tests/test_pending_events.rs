@@ -0,0 +1,71 @@
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    cleanup_test_users, test_phone_number
+};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn connect(user_id: Uuid, scope: &str) -> Result<WsStream, Box<dyn std::error::Error>> {
+    let (token, _) = generate_test_token(user_id, scope)?;
+    let (ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", token)).await?;
+    Ok(ws_stream)
+}
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_offline_provider_receives_invitation_on_connect() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+
+    // The client creates a conversation while the provider has no socket open
+    let mut client_ws = connect(client_id, "client").await?;
+    let message = json!({
+        "sender_id": client_id,
+        "event": "new_conversation",
+        "params": { "pet_id": pet_id, "providers": [provider_id] }
+    });
+    client_ws.send(Message::Text(message.to_string())).await?;
+    let created = next_event(&mut client_ws, "conversation_created").await?;
+    let conversation_id = created["params"]["id"].clone();
+
+    // The invitation is waiting for the provider when they connect
+    let mut provider_ws = connect(provider_id, "provider").await?;
+    let pending = next_event(&mut provider_ws, "pending_events").await?;
+    let events = pending["params"]["events"].as_array().unwrap();
+    assert!(events.iter().any(|event| {
+        event["event"] == "new_conversation_invitation" && event["params"]["id"] == conversation_id
+    }));
+    provider_ws.close(None).await?;
+
+    // It was delivered once; a second connection doesn't get it again
+    let mut provider_ws = connect(provider_id, "provider").await?;
+    assert!(next_event(&mut provider_ws, "pending_events").await.is_err());
+
+    cleanup_test_users(&pool, &[client_id, provider_id]).await;
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet, insert_test_conversation,
+    cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+#[tokio::test]
+async fn test_pet_document_access() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let owner_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let vet_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let other_vet_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, owner_id).await;
+    insert_test_conversation(&pool, owner_id, pet_id, &[vet_id]).await;
+
+    let image_id = Uuid::new_v4();
+    sqlx::query!(
+        "
+        INSERT INTO images (id, user_id, filename, content_type, image_type, image_url, size_bytes)
+        VALUES ($1, $2, 'policy.pdf', 'application/pdf', 'document', 'https://example.com/policy.pdf', 1024)
+        ",
+        image_id,
+        owner_id
+    )
+    .execute(&pool)
+    .await?;
+
+    let http = Client::new();
+    let (owner_token, _) = generate_test_token(owner_id, "client")?;
+    let (vet_token, _) = generate_test_token(vet_id, "provider")?;
+    let (other_vet_token, _) = generate_test_token(other_vet_id, "provider")?;
+    let documents_url = format!("{}/pets/{}/documents", SERVER_URL, pet_id);
+
+    // The owner files their policy, expiring in two weeks
+    let expires_at = chrono::Utc::now().timestamp_millis() + 14 * 24 * 60 * 60 * 1000;
+    let response = http
+        .post(&documents_url)
+        .header("Authorization", format!("Bearer {}", owner_token))
+        .json(&json!({ "image_id": image_id, "category": "insurance", "title": "Pet insurance", "expires_at": expires_at }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let document: Value = response.json().await?;
+    assert_eq!(document["image_url"], "https://example.com/policy.pdf");
+    assert_eq!(document["expires_at"], expires_at);
+    let document_id = document["id"].as_str().unwrap().to_string();
+
+    let response = http
+        .post(&documents_url)
+        .header("Authorization", format!("Bearer {}", owner_token))
+        .json(&json!({ "image_id": image_id, "category": "receipts", "title": "Pet insurance" }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // The treating vet sees it among the documents expiring this month
+    let response = http
+        .get(format!("{}?expiring_within_days=30", documents_url))
+        .header("Authorization", format!("Bearer {}", vet_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await?;
+    assert_eq!(body["documents"][0]["id"], document_id);
+
+    // A vet with no conversation about the pet can't see or touch it
+    let response = http
+        .get(&documents_url)
+        .header("Authorization", format!("Bearer {}", other_vet_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = http
+        .delete(format!("{}/{}", documents_url, document_id))
+        .header("Authorization", format!("Bearer {}", other_vet_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Only the owner or the uploader may delete it
+    let response = http
+        .delete(format!("{}/{}", documents_url, document_id))
+        .header("Authorization", format!("Bearer {}", vet_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = http
+        .delete(format!("{}/{}", documents_url, document_id))
+        .header("Authorization", format!("Bearer {}", owner_token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    cleanup_test_users(&pool, &[owner_id, vet_id, other_vet_id]).await;
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+#[tokio::test]
+async fn test_pet_share_lifecycle() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let owner_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let co_owner_phone = test_phone_number();
+    let co_owner_id = insert_test_user(&pool, &co_owner_phone, "client").await;
+    let pet_id = insert_test_pet(&pool, owner_id).await;
+
+    let http = Client::new();
+    let (owner_token, _) = generate_test_token(owner_id, "client")?;
+    let (co_owner_token, _) = generate_test_token(co_owner_id, "client")?;
+    let get_pet = |token: String| {
+        http.get(format!("{}/pets/{}", SERVER_URL, pet_id))
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+    };
+
+    // The owner invites the co-owner by phone number
+    let response = http
+        .post(format!("{}/pets/{}/shares", SERVER_URL, pet_id))
+        .header("Authorization", format!("Bearer {}", owner_token))
+        .json(&json!({ "phone_number": co_owner_phone, "permissions": "read" }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body: Value = response.json().await?;
+    let share_id = body["share"]["id"].as_str().unwrap().to_string();
+    assert_eq!(body["share"]["status"], "pending");
+
+    // Nothing is visible until the invitation is accepted
+    assert_eq!(get_pet(co_owner_token.clone()).await?.status(), StatusCode::NOT_FOUND);
+
+    let response = http
+        .post(format!("{}/pet-shares/{}/accept", SERVER_URL, share_id))
+        .header("Authorization", format!("Bearer {}", co_owner_token))
+        .send()
+        .await?;
+    assert!(response.status().is_success(), "Accept failed: {}", response.status());
+
+    let response = get_pet(co_owner_token.clone()).await?;
+    assert!(response.status().is_success(), "Shared pet not visible: {}", response.status());
+    let pet: Value = response.json().await?;
+    assert_eq!(pet["id"], pet_id.to_string());
+
+    // Revoking cuts access off straight away
+    let response = http
+        .delete(format!("{}/pet-shares/{}", SERVER_URL, share_id))
+        .header("Authorization", format!("Bearer {}", owner_token))
+        .send()
+        .await?;
+    assert!(response.status().is_success(), "Revoke failed: {}", response.status());
+    assert_eq!(get_pet(co_owner_token).await?.status(), StatusCode::NOT_FOUND);
+    assert!(get_pet(owner_token).await?.status().is_success());
+
+    cleanup_test_users(&pool, &[owner_id, co_owner_id]).await;
+    Ok(())
+}
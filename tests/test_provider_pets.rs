@@ -0,0 +1,41 @@
+use reqwest::{Client, StatusCode};
+use serde_json::Value;
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet, insert_test_conversation,
+    cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+#[tokio::test]
+async fn test_provider_pets() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let other_client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let vet_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let other_pet_id = insert_test_pet(&pool, other_client_id).await;
+    insert_test_conversation(&pool, client_id, pet_id, &[vet_id]).await;
+
+    let http = Client::new();
+    let (vet_token, _) = generate_test_token(vet_id, "provider")?;
+    let (client_token, _) = generate_test_token(client_id, "client")?;
+    let url = format!("{}/provider/pets", SERVER_URL);
+
+    // The vet sees their client's pet but not a pet of someone they don't treat
+    let response = http.get(&url).bearer_auth(&vet_token).send().await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await?;
+    let ids: Vec<&str> = body["pets"].as_array().unwrap().iter().map(|p| p["id"].as_str().unwrap()).collect();
+    assert_eq!(ids, vec![pet_id.to_string()]);
+    assert!(!ids.contains(&other_pet_id.to_string().as_str()));
+    assert_eq!(body["total_count"], 1);
+
+    let response = http.get(&url).bearer_auth(&client_token).send().await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    cleanup_test_users(&pool, &[client_id, other_client_id, vet_id]).await;
+    Ok(())
+}
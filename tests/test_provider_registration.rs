@@ -0,0 +1,96 @@
+use ed25519_dalek::Signer;
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use base64::{Engine as _, engine::general_purpose};
+use chrono::Utc;
+use uuid::Uuid;
+
+mod testing_utils;
+use testing_utils::{
+    TEST_SIGNING_KEY, TEST_VERIFYING_KEY, to_canonical_json, generate_test_token, setup_test_db,
+    insert_test_user, cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+async fn signed_post(client: &Client, path: &str, data: Value) -> Result<reqwest::Response, reqwest::Error> {
+    let signature = TEST_SIGNING_KEY.sign(to_canonical_json(&data).as_bytes());
+    client.post(format!("{}{}", SERVER_URL, path))
+        .json(&json!({
+            "data": data,
+            "signature": general_purpose::STANDARD.encode(signature.to_bytes())
+        }))
+        .send()
+        .await
+}
+
+async fn login_token(client: &Client, user_id: Uuid) -> Result<String, Box<dyn std::error::Error>> {
+    let body: Value = signed_post(client, "/login", json!({
+        "user_id": user_id,
+        "timestamp": Utc::now().to_rfc3339(),
+        "verification_code": "123456"
+    }))
+    .await?
+    .json()
+    .await?;
+    Ok(body["access_token"].as_str().unwrap().to_string())
+}
+
+#[tokio::test]
+async fn test_provider_registration_is_approved_by_an_admin() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let admin_id = insert_test_user(&pool, &test_phone_number(), "admin").await;
+    let http = Client::new();
+
+    let registered: Value = signed_post(&http, "/register", json!({
+        "phone_number": test_phone_number(),
+        "public_key": general_purpose::STANDARD.encode(TEST_VERIFYING_KEY.as_bytes()),
+        "timestamp": Utc::now().to_rfc3339(),
+        "requested_scope": "provider"
+    }))
+    .await?
+    .json()
+    .await?;
+    assert_eq!(registered["provider_status"], "pending");
+    let user_id: Uuid = registered["user_id"].as_str().unwrap().parse()?;
+
+    // Until approved they're a client, and provider-only routes turn them away
+    let token = login_token(&http, user_id).await?;
+    let pets = http.get(format!("{}/provider/pets", SERVER_URL)).bearer_auth(&token).send().await?;
+    assert_eq!(pets.status(), StatusCode::FORBIDDEN);
+
+    let (admin_token, _) = generate_test_token(admin_id, "admin")?;
+    let pending: Value = http.get(format!("{}/admin/users?provider_status=pending&limit=100", SERVER_URL))
+        .bearer_auth(&admin_token)
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert!(pending["users"].as_array().unwrap().iter().any(|user| user["id"] == user_id.to_string()));
+
+    let approved: Value = http.post(format!("{}/admin/users/{}/provider-request", SERVER_URL, user_id))
+        .bearer_auth(&admin_token)
+        .json(&json!({ "approve": true }))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(approved["provider_status"], "approved");
+    assert_eq!(approved["scope"], "provider");
+
+    // Their next token carries the provider scope
+    let token = login_token(&http, user_id).await?;
+    let pets = http.get(format!("{}/provider/pets", SERVER_URL)).bearer_auth(&token).send().await?;
+    assert_eq!(pets.status(), StatusCode::OK);
+
+    // A request can only be reviewed once
+    let again = http.post(format!("{}/admin/users/{}/provider-request", SERVER_URL, user_id))
+        .bearer_auth(&admin_token)
+        .json(&json!({ "approve": false }))
+        .send()
+        .await?;
+    assert_eq!(again.status(), StatusCode::BAD_REQUEST);
+
+    cleanup_test_users(&pool, &[admin_id, user_id]).await;
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+
+mod testing_utils;
+use testing_utils::{generate_test_token, setup_test_db, insert_test_user, cleanup_test_users, test_phone_number};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+#[tokio::test]
+async fn test_paging_parameters_are_validated() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let vet_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let (vet_token, _) = generate_test_token(vet_id, "provider")?;
+    let http = Client::new();
+
+    // A value that isn't a number gets the same error as one out of range
+    for (query, field, message) in [
+        ("limit=abc", "limit", "limit must be a whole number"),
+        ("page=0", "page", "page must be at least 1"),
+    ] {
+        let response = http
+            .get(format!("{}/provider/pets?{}", SERVER_URL, query))
+            .bearer_auth(&vet_token)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: Value = response.json().await?;
+        assert_eq!(body["error"], json!({ "code": "invalid_query", "field": field, "message": message }));
+    }
+
+    let response = http
+        .get(format!("{}/provider/pets?page=1&limit=10", SERVER_URL))
+        .bearer_auth(&vet_token)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    cleanup_test_users(&pool, &[vet_id]).await;
+    Ok(())
+}
@@ -55,4 +55,28 @@ async fn test_register_endpoint() -> Result<(), Box<dyn std::error::Error>> {
     assert_eq!(response["message"], "Registration data received and verified. Verification code sent.");
 
     Ok(())
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn test_register_rejects_invalid_phone_numbers() -> Result<(), Box<dyn std::error::Error>> {
+    let data = json!({
+        "phone_number": "call me maybe",
+        "public_key": general_purpose::STANDARD.encode(TEST_VERIFYING_KEY.as_bytes()),
+        "timestamp": Utc::now().to_rfc3339()
+    });
+    let signature = TEST_SIGNING_KEY.sign(to_canonical_json(&data).as_bytes());
+
+    let res = reqwest::Client::new()
+        .post("http://localhost:8080/register")
+        .json(&json!({
+            "data": data,
+            "signature": general_purpose::STANDARD.encode(signature.to_bytes())
+        }))
+        .send()
+        .await?;
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = res.json().await?;
+    assert_eq!(body["error"]["code"], "invalid_phone_number");
+    assert!(body["error"]["message"].as_str().unwrap().starts_with("Invalid phone number"));
+
+    Ok(())
+}
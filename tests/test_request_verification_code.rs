@@ -1,8 +1,7 @@
 use ed25519_dalek::Signer;
-use serde_json::{json, Value};
+use serde_json::json;
 use base64::{Engine as _, engine::general_purpose};
 use chrono::Utc;
-use uuid::Uuid;
 
 mod testing_utils;
 use testing_utils::{TEST_SIGNING_KEY, to_canonical_json};
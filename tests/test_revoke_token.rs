@@ -0,0 +1,65 @@
+use ed25519_dalek::Signer;
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use base64::{Engine as _, engine::general_purpose};
+use chrono::Utc;
+use uuid::Uuid;
+
+mod testing_utils;
+use testing_utils::{
+    TEST_SIGNING_KEY, TEST_VERIFYING_KEY, to_canonical_json, setup_test_db, generate_test_token,
+    cleanup_test_users, test_phone_number
+};
+
+const BASE_URL: &str = "http://localhost:8080";
+
+fn signed(data: Value) -> Value {
+    let signature = TEST_SIGNING_KEY.sign(to_canonical_json(&data).as_bytes());
+    json!({
+        "data": data,
+        "signature": general_purpose::STANDARD.encode(signature.to_bytes())
+    })
+}
+
+#[tokio::test]
+async fn test_revoked_token_is_refused() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO users (id, phone_number, public_key, scope, verified) VALUES ($1, $2, $3, 'client', true)",
+        user_id,
+        test_phone_number(),
+        general_purpose::STANDARD.encode(TEST_VERIFYING_KEY.as_bytes())
+    )
+    .execute(&pool)
+    .await?;
+
+    let client = Client::new();
+    let (leaked, _) = generate_test_token(user_id, "client")?;
+    let (other, _) = generate_test_token(user_id, "client")?;
+    let profiles = |token: String| client.get(format!("{}/profiles", BASE_URL))
+        .query(&[("user_ids", user_id.to_string())])
+        .bearer_auth(token)
+        .send();
+    assert_eq!(profiles(leaked.clone()).await?.status(), StatusCode::OK);
+
+    let response = client.post(format!("{}/revoke-token", BASE_URL))
+        .bearer_auth(&leaked)
+        .json(&signed(json!({
+            "user_id": user_id,
+            "timestamp": Utc::now().to_rfc3339()
+        })))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Only the revoked token stops working
+    let response = profiles(leaked.clone()).await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let body: Value = response.json().await?;
+    assert_eq!(body["error"]["message"], "Token has been revoked");
+    assert_eq!(profiles(other).await?.status(), StatusCode::OK);
+
+    cleanup_test_users(&pool, &[user_id]).await;
+    Ok(())
+}
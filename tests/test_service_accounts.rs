@@ -0,0 +1,127 @@
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+#[tokio::test]
+async fn test_service_account_keys() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let admin_id = insert_test_user(&pool, &test_phone_number(), "admin").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+    let http = Client::new();
+    let (admin_token, _) = generate_test_token(admin_id, "admin")?;
+    let response = http
+        .post(format!("{}/admin/service-accounts", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .json(&json!({ "provider_id": provider_id, "name": "PMS sync" }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body: Value = response.json().await?;
+    let account_id = body["service_account"]["id"].as_str().unwrap().to_string();
+    let api_key = body["api_key"].as_str().unwrap().to_string();
+
+    // Reads conversations as the provider
+    let response = http
+        .get(format!("{}/conversations/{}/summary", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Api-Key {}", api_key))
+        .send()
+        .await?;
+    assert!(response.status().is_success(), "Summary failed: {}", response.status());
+    let response = http
+        .get(format!("{}/profiles?user_ids={}", SERVER_URL, client_id))
+        .header("Authorization", format!("Api-Key {}", api_key))
+        .send()
+        .await?;
+    assert!(response.status().is_success(), "Profiles failed: {}", response.status());
+
+    // Can't write: no sending messages, no assigning providers
+    let response = http
+        .get(format!("{}/ws/", SERVER_URL))
+        .header("Authorization", format!("Api-Key {}", api_key))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = http
+        .put(format!("{}/conversations/{}/assigned-provider", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Api-Key {}", api_key))
+        .json(&json!({ "provider_id": provider_id }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Rotating invalidates the old key
+    let response = http
+        .post(format!("{}/admin/service-accounts/{}/rotate", SERVER_URL, account_id))
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .send()
+        .await?;
+    assert!(response.status().is_success(), "Rotate failed: {}", response.status());
+    let body: Value = response.json().await?;
+    let rotated_key = body["api_key"].as_str().unwrap().to_string();
+    let response = http
+        .get(format!("{}/conversations/{}/summary", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Api-Key {}", api_key))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Revoking stops the account entirely
+    let response = http
+        .post(format!("{}/admin/service-accounts/{}/revoke", SERVER_URL, account_id))
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .send()
+        .await?;
+    assert!(response.status().is_success(), "Revoke failed: {}", response.status());
+    let response = http
+        .get(format!("{}/conversations/{}/summary", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Api-Key {}", rotated_key))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    cleanup_test_users(&pool, &[admin_id, provider_id, client_id]).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_service_account_rate_limit() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let admin_id = insert_test_user(&pool, &test_phone_number(), "admin").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+
+    let http = Client::new();
+    let (admin_token, _) = generate_test_token(admin_id, "admin")?;
+    let response = http
+        .post(format!("{}/admin/service-accounts", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .json(&json!({ "provider_id": provider_id, "name": "Reporting", "rate_limit_per_minute": 2 }))
+        .send()
+        .await?;
+    let body: Value = response.json().await?;
+    let api_key = body["api_key"].as_str().unwrap().to_string();
+
+    let mut statuses = Vec::new();
+    for _ in 0..3 {
+        let response = http
+            .get(format!("{}/profiles?user_ids={}", SERVER_URL, provider_id))
+            .header("Authorization", format!("Api-Key {}", api_key))
+            .send()
+            .await?;
+        statuses.push(response.status());
+    }
+    assert_eq!(statuses, vec![StatusCode::OK, StatusCode::OK, StatusCode::TOO_MANY_REQUESTS]);
+
+    cleanup_test_users(&pool, &[admin_id, provider_id]).await;
+    Ok(())
+}
@@ -0,0 +1,274 @@
+use ed25519_dalek::Signer;
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use base64::{Engine as _, engine::general_purpose};
+use chrono::Utc;
+use futures::StreamExt;
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use uuid::Uuid;
+
+mod testing_utils;
+use testing_utils::{
+    TEST_SIGNING_KEY, TEST_VERIFYING_KEY, to_canonical_json, setup_test_db, generate_test_token,
+    cleanup_test_users, test_phone_number
+};
+
+const BASE_URL: &str = "http://localhost:8080";
+
+fn signed(data: Value) -> Value {
+    let signature = TEST_SIGNING_KEY.sign(to_canonical_json(&data).as_bytes());
+    json!({
+        "data": data,
+        "signature": general_purpose::STANDARD.encode(signature.to_bytes())
+    })
+}
+
+/// Logs in as a new device, returning its access and refresh tokens.
+async fn login(client: &Client, user_id: Uuid) -> Result<(String, String), Box<dyn std::error::Error>> {
+    let response: Value = client.post(format!("{}/login", BASE_URL))
+        .json(&signed(json!({
+            "user_id": user_id,
+            "timestamp": Utc::now().to_rfc3339(),
+            "verification_code": "123456"
+        })))
+        .send()
+        .await?
+        .json()
+        .await?;
+    Ok((
+        response["access_token"].as_str().unwrap().to_string(),
+        response["refresh_token"].as_str().unwrap().to_string(),
+    ))
+}
+
+async fn refresh(client: &Client, user_id: Uuid, refresh_token: &str) -> Result<StatusCode, Box<dyn std::error::Error>> {
+    let response = client.post(format!("{}/refresh", BASE_URL))
+        .json(&signed(json!({
+            "user_id": user_id,
+            "timestamp": Utc::now().to_rfc3339(),
+            "refresh_token": refresh_token
+        })))
+        .send()
+        .await?;
+    Ok(response.status())
+}
+
+#[tokio::test]
+async fn test_revoke_one_device() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO users (id, phone_number, public_key, scope, verified) VALUES ($1, $2, $3, 'client', true)",
+        user_id,
+        test_phone_number(),
+        general_purpose::STANDARD.encode(TEST_VERIFYING_KEY.as_bytes())
+    )
+    .execute(&pool)
+    .await?;
+    let other_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO users (id, phone_number, public_key, scope, verified) VALUES ($1, $2, 'key', 'client', true)",
+        other_id,
+        test_phone_number()
+    )
+    .execute(&pool)
+    .await?;
+
+    let client = Client::new();
+    let (phone_access, phone_refresh) = login(&client, user_id).await?;
+    let (tablet_access, tablet_refresh) = login(&client, user_id).await?;
+
+    // Both devices are listed, and the phone is marked as the caller
+    let sessions: Value = client.get(format!("{}/sessions", BASE_URL))
+        .bearer_auth(&phone_access)
+        .send()
+        .await?
+        .json()
+        .await?;
+    let sessions = sessions["sessions"].as_array().unwrap();
+    assert_eq!(sessions.len(), 2);
+    let tablet_family = sessions.iter().find(|s| s["current"] == false).unwrap()["family_id"].as_str().unwrap().to_string();
+
+    // The tablet has a socket open
+    let (mut tablet_ws, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", tablet_access)).await?;
+
+    // Another user can't see the tablet's family
+    let (other_token, _) = generate_test_token(other_id, "client")?;
+    let response = client.post(format!("{}/sessions/{}/revoke", BASE_URL, tablet_family))
+        .bearer_auth(&other_token)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // The phone signs the tablet out
+    let response = client.post(format!("{}/sessions/{}/revoke", BASE_URL, tablet_family))
+        .bearer_auth(&phone_access)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // The tablet's socket is closed and its refresh token no longer works
+    let closed = timeout(Duration::from_secs(5), async {
+        while let Some(msg) = tablet_ws.next().await {
+            if matches!(msg, Ok(Message::Close(_)) | Err(_)) {
+                return true;
+            }
+        }
+        true
+    }).await?;
+    assert!(closed);
+    assert_eq!(refresh(&client, user_id, &tablet_refresh).await?, StatusCode::UNAUTHORIZED);
+    assert!(connect_async(format!("ws://localhost:8080/ws/?token={}", tablet_access)).await.is_err());
+
+    // The phone keeps working
+    assert_eq!(refresh(&client, user_id, &phone_refresh).await?, StatusCode::OK);
+
+    cleanup_test_users(&pool, &[user_id, other_id]).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_refresh_tokens_are_stored_hashed() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO users (id, phone_number, public_key, scope, verified) VALUES ($1, $2, $3, 'client', true)",
+        user_id,
+        test_phone_number(),
+        general_purpose::STANDARD.encode(TEST_VERIFYING_KEY.as_bytes())
+    )
+    .execute(&pool)
+    .await?;
+
+    let client = Client::new();
+    let (_, refresh_token) = login(&client, user_id).await?;
+
+    // The database only has the hash
+    let stored = sqlx::query!("SELECT token, hashed FROM refresh_tokens WHERE user_id = $1", user_id)
+        .fetch_one(&pool)
+        .await?;
+    assert!(stored.hashed);
+    assert_ne!(stored.token, refresh_token);
+    assert_eq!(refresh(&client, user_id, &stored.token).await?, StatusCode::UNAUTHORIZED);
+
+    // The token the client holds still refreshes and logs out
+    assert_eq!(refresh(&client, user_id, &refresh_token).await?, StatusCode::OK);
+    let response = client.post(format!("{}/logout", BASE_URL))
+        .json(&signed(json!({
+            "user_id": user_id,
+            "timestamp": Utc::now().to_rfc3339(),
+            "refresh_token": refresh_token
+        })))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(refresh(&client, user_id, &refresh_token).await?, StatusCode::UNAUTHORIZED);
+
+    cleanup_test_users(&pool, &[user_id]).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_expired_refresh_token_is_rejected() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO users (id, phone_number, public_key, scope, verified) VALUES ($1, $2, $3, 'client', true)",
+        user_id,
+        test_phone_number(),
+        general_purpose::STANDARD.encode(TEST_VERIFYING_KEY.as_bytes())
+    )
+    .execute(&pool)
+    .await?;
+
+    let client = Client::new();
+    let (_, refresh_token) = login(&client, user_id).await?;
+
+    // Login sets an expiry, and using the token pushes it back
+    let issued = sqlx::query!("SELECT expires_at FROM refresh_tokens WHERE user_id = $1", user_id)
+        .fetch_one(&pool)
+        .await?
+        .expires_at
+        .expect("login should set an expiry");
+    assert!(issued > Utc::now());
+    assert_eq!(refresh(&client, user_id, &refresh_token).await?, StatusCode::OK);
+    let extended = sqlx::query!("SELECT expires_at FROM refresh_tokens WHERE user_id = $1", user_id)
+        .fetch_one(&pool)
+        .await?
+        .expires_at
+        .unwrap();
+    assert!(extended >= issued);
+
+    sqlx::query!("UPDATE refresh_tokens SET expires_at = NOW() - INTERVAL '1 minute' WHERE user_id = $1", user_id)
+        .execute(&pool)
+        .await?;
+    let response = client.post(format!("{}/refresh", BASE_URL))
+        .json(&signed(json!({
+            "user_id": user_id,
+            "timestamp": Utc::now().to_rfc3339(),
+            "refresh_token": refresh_token
+        })))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let body: Value = response.json().await?;
+    assert_eq!(body["error"]["message"], "Refresh token expired");
+
+    cleanup_test_users(&pool, &[user_id]).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sessions_list_the_device_they_were_started_on() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO users (id, phone_number, public_key, scope, verified) VALUES ($1, $2, $3, 'client', true)",
+        user_id,
+        test_phone_number(),
+        general_purpose::STANDARD.encode(TEST_VERIFYING_KEY.as_bytes())
+    )
+    .execute(&pool)
+    .await?;
+
+    let client = Client::new();
+    let response: Value = client.post(format!("{}/login", BASE_URL))
+        .header("User-Agent", "VetText/2.3 (iPhone; iOS 17.4)")
+        .json(&signed(json!({
+            "user_id": user_id,
+            "timestamp": Utc::now().to_rfc3339(),
+            "verification_code": "123456",
+            "device_name": "Sam's iPhone"
+        })))
+        .send()
+        .await?
+        .json()
+        .await?;
+    let access_token = response["access_token"].as_str().unwrap();
+
+    let sessions: Value = client.get(format!("{}/sessions", BASE_URL))
+        .bearer_auth(access_token)
+        .send()
+        .await?
+        .json()
+        .await?;
+    let session = &sessions["sessions"][0];
+    assert_eq!(session["user_agent"], "VetText/2.3 (iPhone; iOS 17.4)");
+    assert_eq!(session["device_name"], "Sam's iPhone");
+
+    // Overlong names are refused before anything is stored
+    let response = client.post(format!("{}/login", BASE_URL))
+        .json(&signed(json!({
+            "user_id": user_id,
+            "timestamp": Utc::now().to_rfc3339(),
+            "verification_code": "123456",
+            "device_name": "x".repeat(101)
+        })))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    cleanup_test_users(&pool, &[user_id]).await;
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use tokio::time::{sleep, timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, cleanup_test_users, test_phone_number
+};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn connect(user_id: Uuid, scope: &str) -> Result<WsStream, Box<dyn std::error::Error>> {
+    let (token, _) = generate_test_token(user_id, scope)?;
+    let (ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", token)).await?;
+    Ok(ws_stream)
+}
+
+async fn send_event(ws_stream: &mut WsStream, user_id: Uuid, event: &str, params: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let message = json!({ "sender_id": user_id, "event": event, "params": params });
+    ws_stream.send(Message::Text(message.to_string())).await?;
+    Ok(())
+}
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+async fn subscriptions(ws_stream: &mut WsStream, user_id: Uuid) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    send_event(ws_stream, user_id, "get_subscriptions", json!({})).await?;
+    let response = next_event(ws_stream, "subscriptions").await?;
+    Ok(response["params"]["conversation_ids"].as_array().unwrap().clone())
+}
+
+#[tokio::test]
+async fn test_set_providers_reconciles_subscriptions() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let old_vet_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let new_vet_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[old_vet_id]).await;
+
+    let mut client_ws = connect(client_id, "client").await?;
+    let mut old_vet_ws = connect(old_vet_id, "provider").await?;
+    let mut new_vet_ws = connect(new_vet_id, "provider").await?;
+    sleep(Duration::from_millis(200)).await;
+    assert!(subscriptions(&mut old_vet_ws, old_vet_id).await?.contains(&json!(conversation_id)));
+
+    // Providers can't change the set
+    send_event(&mut old_vet_ws, old_vet_id, "set_providers", json!({
+        "conversation_id": conversation_id,
+        "providers": [new_vet_id]
+    })).await?;
+    let error = next_event(&mut old_vet_ws, "error").await?;
+    assert_eq!(error["params"]["message"], "Only the client can change the providers");
+
+    send_event(&mut client_ws, client_id, "set_providers", json!({
+        "conversation_id": conversation_id,
+        "providers": [new_vet_id]
+    })).await?;
+    let updated = next_event(&mut client_ws, "providers_updated").await?;
+    assert_eq!(updated["params"]["providers"], json!([new_vet_id]));
+    assert_eq!(updated["params"]["removed"], json!([old_vet_id]));
+    let removed = next_event(&mut old_vet_ws, "providers_updated").await?;
+    assert_eq!(removed["params"]["added"], json!([new_vet_id]));
+
+    // The new vet now follows the conversation and the old one doesn't
+    assert!(subscriptions(&mut new_vet_ws, new_vet_id).await?.contains(&json!(conversation_id)));
+    assert!(!subscriptions(&mut old_vet_ws, old_vet_id).await?.contains(&json!(conversation_id)));
+
+    cleanup_test_users(&pool, &[client_id, old_vet_id, new_vet_id]).await;
+    Ok(())
+}
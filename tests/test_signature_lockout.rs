@@ -0,0 +1,57 @@
+use serde_json::{json, Value};
+use chrono::Utc;
+
+mod testing_utils;
+use testing_utils::{setup_test_db, insert_test_user, cleanup_test_users, test_phone_number};
+
+const SERVER_URL: &str = "http://localhost:8080";
+// Matches the server's default SIGNATURE_FAILURE_THRESHOLD
+const THRESHOLD: usize = 5;
+
+#[tokio::test]
+async fn test_repeated_bad_signatures_lock_out_user() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let phone_number = test_phone_number();
+    let user_id = insert_test_user(&pool, &phone_number, "client").await;
+    let client = reqwest::Client::new();
+
+    let send_corrupted = || async {
+        let payload = json!({
+            "data": {
+                "phone_number": phone_number,
+                "timestamp": Utc::now().to_rfc3339()
+            },
+            "signature": "bm90IGEgdmFsaWQgc2lnbmF0dXJl"
+        });
+        client.post(format!("{}/request-verification-code", SERVER_URL))
+            .json(&payload)
+            .send()
+            .await
+    };
+
+    for _ in 0..THRESHOLD {
+        let res = send_corrupted().await?;
+        assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+    }
+
+    // Past the threshold the user is locked out
+    let res = send_corrupted().await?;
+    assert_eq!(res.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+    assert!(res.headers().get("Retry-After").is_some());
+    let body: Value = res.json().await?;
+    assert_eq!(body["error"]["code"], "signature_failure_lockout");
+
+    let audited = sqlx::query!(
+        "SELECT COUNT(*) AS count FROM audit_log WHERE action = 'signature_failure_lockout' AND user_id = $1",
+        user_id
+    )
+    .fetch_one(&pool)
+    .await?
+    .count
+    .unwrap_or(0);
+    assert_eq!(audited, 1);
+
+    sqlx::query!("DELETE FROM audit_log WHERE user_id = $1", user_id).execute(&pool).await?;
+    cleanup_test_users(&pool, &[user_id]).await;
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use reqwest::{Client, StatusCode, multipart};
+use serde_json::Value;
+use uuid::Uuid;
+
+mod testing_utils;
+use testing_utils::{generate_test_token, setup_test_db, insert_test_user, cleanup_test_users, test_phone_number};
+
+const BASE_URL: &str = "http://localhost:8080";
+
+async fn upload(client: &Client, token: &str, bytes: Vec<u8>) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+    let part = multipart::Part::bytes(bytes).file_name("image.png").mime_str("image/png")?;
+    let response = client.post(format!("{}/upload-image?image_type=pet", BASE_URL))
+        .bearer_auth(token)
+        .multipart(multipart::Form::new().part("file", part))
+        .send()
+        .await?;
+    Ok(response)
+}
+
+#[tokio::test]
+async fn test_uploads_past_quota_are_rejected() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let user_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let (token, _) = generate_test_token(user_id, "client")?;
+    let client = Client::new();
+
+    let quota: Value = client.get(format!("{}/images/quota", BASE_URL))
+        .bearer_auth(&token)
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(quota["used_bytes"], 0);
+    let quota_bytes = quota["quota_bytes"].as_i64().unwrap();
+
+    // Fill the quota to 10 bytes short of the limit
+    sqlx::query!(
+        "INSERT INTO images (id, user_id, image_type, image_url, size_bytes) VALUES ($1, $2, 'pet', 'https://example.com/big.png', $3)",
+        Uuid::new_v4(),
+        user_id,
+        quota_bytes - 10
+    )
+    .execute(&pool)
+    .await?;
+
+    let quota: Value = client.get(format!("{}/images/quota", BASE_URL))
+        .bearer_auth(&token)
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(quota["remaining_bytes"], 10);
+
+    // One byte over what's left is refused before anything is stored
+    let response = upload(&client, &token, vec![0u8; 11]).await?;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let body: Value = response.json().await?;
+    assert_eq!(body["error"]["message"], "Storage quota exceeded");
+    assert_eq!(body["error"]["quota"]["remaining_bytes"], 10);
+
+    // Exactly what's left gets past the quota check
+    let response = upload(&client, &token, vec![0u8; 10]).await?;
+    assert_ne!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    cleanup_test_users(&pool, &[user_id]).await;
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use uuid::Uuid;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet, insert_test_conversation,
+    cleanup_test_users, test_phone_number
+};
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn connect(user_id: Uuid, scope: &str) -> Result<WsStream, Box<dyn std::error::Error>> {
+    let (token, _) = generate_test_token(user_id, scope)?;
+    let (ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", token)).await?;
+    Ok(ws_stream)
+}
+
+async fn send_event(ws_stream: &mut WsStream, user_id: Uuid, event: &str, params: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let message = json!({ "sender_id": user_id, "event": event, "params": params });
+    ws_stream.send(Message::Text(message.to_string())).await?;
+    Ok(())
+}
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_times_are_milliseconds() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let vet_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[vet_id]).await;
+
+    let http = Client::new();
+    let (token, _) = generate_test_token(client_id, "client")?;
+    let whoami = http
+        .get(format!("{}/whoami", SERVER_URL))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?
+        .json::<Value>()
+        .await?;
+    let expires_at = whoami["expires_at"].as_i64().unwrap();
+    assert!(expires_at > chrono::Utc::now().timestamp_millis());
+
+    // A message's time is the same number live and in the history
+    let mut client_ws = connect(client_id, "client").await?;
+    send_event(&mut client_ws, client_id, "subscribe_conversation", json!({ "conversation_id": conversation_id })).await?;
+    send_event(&mut client_ws, client_id, "message", json!({
+        "conversation_id": conversation_id,
+        "content": "Is 8am OK?"
+    })).await?;
+    let sent = next_event(&mut client_ws, "message_sent").await?;
+    assert!(sent["params"]["timestamp"].is_i64());
+
+    let response = http
+        .get(format!("{}/conversations/{}/messages", SERVER_URL, conversation_id))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let history = response.json::<Value>().await?;
+    assert_eq!(history["messages"][0]["timestamp"], sent["params"]["timestamp"]);
+    assert_eq!(history["messages"][0]["updated_at"], sent["params"]["updated_at"]);
+
+    cleanup_test_users(&pool, &[client_id, vet_id]).await;
+    Ok(())
+}
@@ -2,7 +2,6 @@ use reqwest::Client;
 use uuid::Uuid;
 use serde_json::{json, Value};
 use std::error::Error as StdError;
-use chrono::{DateTime, Utc};
 
 mod testing_utils;
 use testing_utils::generate_test_token;
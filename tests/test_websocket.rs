@@ -6,7 +6,6 @@ use uuid::Uuid;
 use futures::{StreamExt, SinkExt};
 use sqlx::{PgPool, postgres::PgPoolOptions};
 use std::env;
-use dotenv;
 
 mod testing_utils;
 use testing_utils::generate_test_token;
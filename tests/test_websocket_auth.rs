@@ -0,0 +1,138 @@
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::{self, client::IntoClientRequest, protocol::Message}};
+use serde_json::{json, Value};
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, cleanup_test_users, test_phone_number
+};
+
+const WS_URL: &str = "ws://localhost:8080/ws/";
+
+#[tokio::test]
+async fn test_websocket_authenticates_the_token_holder() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+    let (token, _) = generate_test_token(client_id, "client")?;
+
+    // The token works in the query string and in the Authorization header alike
+    let mut with_header = WS_URL.into_client_request()?;
+    with_header.headers_mut().insert("Authorization", format!("Bearer {}", token).parse()?);
+    let requests = [format!("{}?token={}", WS_URL, token).into_client_request()?, with_header];
+
+    for request in requests {
+        let (mut ws_stream, _) = connect_async(request).await?;
+        let request = json!({ "sender_id": client_id, "event": "conversations", "params": {} });
+        ws_stream.send(Message::Text(request.to_string())).await?;
+
+        // The socket belongs to the client, so it lists the client's conversation
+        let conversations = loop {
+            let msg = timeout(Duration::from_secs(5), ws_stream.next())
+                .await?
+                .ok_or("WebSocket closed")??;
+            if let Message::Text(text) = msg {
+                let value: Value = serde_json::from_str(&text)?;
+                if value["event"] == "conversations" {
+                    break value["params"].clone();
+                }
+            }
+        };
+        let ids: Vec<&str> = conversations.as_array().unwrap().iter().map(|c| c["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, vec![conversation_id.to_string()]);
+        ws_stream.close(None).await?;
+    }
+
+    // Without a valid token the upgrade is refused
+    for url in [WS_URL.to_string(), format!("{}?token=not-a-token", WS_URL)] {
+        match connect_async(url).await {
+            Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), 401),
+            other => panic!("expected a 401, got {:?}", other.map(|(_, response)| response.status())),
+        }
+    }
+
+    cleanup_test_users(&pool, &[client_id, provider_id]).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_websocket_subscribes_to_the_token_holders_conversations() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let other_provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let theirs = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+    let not_theirs = insert_test_conversation(&pool, client_id, pet_id, &[other_provider_id]).await;
+
+    // The provider's scope comes from the token; nothing is subscribed by hand
+    let (token, _) = generate_test_token(provider_id, "provider")?;
+    let (mut ws_stream, _) = connect_async(format!("{}?token={}", WS_URL, token)).await?;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let request = json!({ "sender_id": provider_id, "event": "get_subscriptions", "params": {} });
+    ws_stream.send(Message::Text(request.to_string())).await?;
+
+    let subscribed = loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            let value: Value = serde_json::from_str(&text)?;
+            if value["event"] == "subscriptions" {
+                break value["params"]["conversation_ids"].clone();
+            }
+        }
+    };
+    let subscribed = subscribed.as_array().unwrap();
+    assert!(subscribed.contains(&json!(theirs)));
+    assert!(!subscribed.contains(&json!(not_theirs)));
+
+    ws_stream.close(None).await?;
+    cleanup_test_users(&pool, &[client_id, provider_id, other_provider_id]).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_messages_are_sent_as_the_token_holder() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+    let (token, _) = generate_test_token(client_id, "client")?;
+    let (mut ws_stream, _) = connect_async(format!("{}?token={}", WS_URL, token)).await?;
+
+    // Claiming to be the provider changes nothing
+    let request = json!({
+        "sender_id": provider_id,
+        "event": "message",
+        "params": { "conversation_id": conversation_id, "content": "Prescription approved" }
+    });
+    ws_stream.send(Message::Text(request.to_string())).await?;
+
+    let sent = loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            let value: Value = serde_json::from_str(&text)?;
+            if value["event"] == "message_sent" {
+                break value["params"].clone();
+            }
+        }
+    };
+    assert_eq!(sent["sender_id"], json!(client_id));
+    let stored = sqlx::query!("SELECT sender_id FROM messages WHERE conversation_id = $1", conversation_id)
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(stored.sender_id, client_id);
+
+    ws_stream.close(None).await?;
+    cleanup_test_users(&pool, &[client_id, provider_id]).await;
+    Ok(())
+}
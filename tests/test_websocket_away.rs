@@ -0,0 +1,93 @@
+use tokio::time::{sleep, timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet, insert_test_conversation,
+    cleanup_test_users, test_phone_number
+};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// Must match the server's WS_AWAY_AFTER_SECS; run the server with a short
+// threshold (e.g. WS_AWAY_AFTER_SECS=2) to keep this test fast.
+fn away_after() -> Duration {
+    dotenv::dotenv().ok();
+    let secs = std::env::var("WS_AWAY_AFTER_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(300);
+    Duration::from_secs(secs)
+}
+
+async fn connect(user_id: Uuid, scope: &str) -> Result<WsStream, Box<dyn std::error::Error>> {
+    let (token, _) = generate_test_token(user_id, scope)?;
+    let (ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", token)).await?;
+    Ok(ws_stream)
+}
+
+async fn send_event(ws_stream: &mut WsStream, user_id: Uuid, event: &str, params: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let message = json!({ "sender_id": user_id, "event": event, "params": params });
+    ws_stream.send(Message::Text(message.to_string())).await?;
+    Ok(())
+}
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str, wait: Duration) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(wait, ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_idle_session_goes_away_and_comes_back() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+    let away_after = away_after();
+
+    let mut client_ws = connect(client_id, "client").await?;
+    let mut provider_ws = connect(provider_id, "provider").await?;
+    send_event(&mut client_ws, client_id, "subscribe_conversation", json!({ "conversation_id": conversation_id })).await?;
+    send_event(&mut provider_ws, provider_id, "subscribe_conversation", json!({ "conversation_id": conversation_id })).await?;
+    sleep(Duration::from_millis(200)).await;
+
+    // The client goes quiet while the provider keeps talking
+    let provider_presence = async {
+        let ping = json!({ "sender_id": provider_id, "event": "ping", "params": {} });
+        loop {
+            let event = next_event(&mut provider_ws, "presence_changed", Duration::from_secs(1)).await;
+            match event {
+                Ok(event) => return Ok::<Value, Box<dyn std::error::Error>>(event),
+                Err(_) => provider_ws.send(Message::Text(ping.to_string())).await?,
+            }
+        }
+    };
+    let away = timeout(away_after + Duration::from_secs(15), provider_presence).await??;
+    assert_eq!(away["params"]["user_id"], client_id.to_string());
+    assert_eq!(away["params"]["status"], "away");
+
+    // Any message brings them back
+    send_event(&mut client_ws, client_id, "ping", json!({})).await?;
+    let online = next_event(&mut provider_ws, "presence_changed", Duration::from_secs(5)).await?;
+    assert_eq!(online["params"]["user_id"], client_id.to_string());
+    assert_eq!(online["params"]["status"], "online");
+
+    cleanup_test_users(&pool, &[client_id, provider_id]).await;
+    Ok(())
+}
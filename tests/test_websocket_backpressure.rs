@@ -0,0 +1,54 @@
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use serde_json::{json, Value};
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{generate_test_token, setup_test_db, insert_test_user, cleanup_test_users, test_phone_number};
+
+// Must match the server's WS_MESSAGE_LIMIT_PER_MINUTE
+fn message_limit() -> u32 {
+    dotenv::dotenv().ok();
+    std::env::var("WS_MESSAGE_LIMIT_PER_MINUTE")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(120)
+}
+
+#[tokio::test]
+async fn test_bursting_session_is_told_to_back_off() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let user_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let (token, _) = generate_test_token(user_id, "client")?;
+    let limit = message_limit();
+
+    let (mut ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", token)).await?;
+    let ping = json!({ "sender_id": user_id, "event": "ping", "params": {} });
+    for _ in 0..limit + 5 {
+        ws_stream.send(Message::Text(ping.to_string())).await?;
+    }
+
+    let mut pongs = 0;
+    let mut backpressure = Vec::new();
+    while let Ok(Some(msg)) = timeout(Duration::from_secs(2), ws_stream.next()).await {
+        if let Message::Text(text) = msg? {
+            let value: Value = serde_json::from_str(&text)?;
+            match value["event"].as_str() {
+                Some("pong") => pongs += 1,
+                Some("backpressure") => backpressure.push(value["params"].clone()),
+                _ => {}
+            }
+        }
+    }
+
+    // Messages past the budget are dropped, with one hint saying when to retry
+    assert_eq!(pongs, limit);
+    assert_eq!(backpressure.len(), 1);
+    let retry_after_ms = backpressure[0]["retry_after_ms"].as_u64().unwrap();
+    assert!(retry_after_ms > 0 && retry_after_ms <= 60_000);
+    assert_eq!(backpressure[0]["limit_per_minute"].as_u64(), Some(limit as u64));
+
+    ws_stream.close(None).await?;
+    cleanup_test_users(&pool, &[user_id]).await;
+    Ok(())
+}
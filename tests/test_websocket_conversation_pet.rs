@@ -0,0 +1,69 @@
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, cleanup_test_users, test_phone_number
+};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn send_event(ws_stream: &mut WsStream, user_id: Uuid, event: &str, params: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let message = json!({ "sender_id": user_id, "event": event, "params": params });
+    ws_stream.send(Message::Text(message.to_string())).await?;
+    Ok(())
+}
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_provider_fetches_the_conversation_pet() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let outsider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+    let (token, _) = generate_test_token(provider_id, "provider")?;
+    let (mut ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", token)).await?;
+    send_event(&mut ws_stream, provider_id, "get_conversation_pet", json!({ "conversation_id": conversation_id })).await?;
+    let response = next_event(&mut ws_stream, "conversation_pet").await?;
+    assert_eq!(response["params"]["conversation_id"], json!(conversation_id));
+    let pet = &response["params"]["pet"];
+    assert_eq!(pet["id"], json!(pet_id));
+    assert_eq!(pet["species"], "Dog");
+    assert_eq!(pet["breed"], "Test Breed");
+    assert_eq!(pet["weight"], 25);
+    assert_eq!(pet["spayed_neutered"], true);
+    assert!(pet["birthday"].is_i64());
+
+    // Providers outside the conversation are refused
+    let (token, _) = generate_test_token(outsider_id, "provider")?;
+    let (mut ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", token)).await?;
+    send_event(&mut ws_stream, outsider_id, "get_conversation_pet", json!({ "conversation_id": conversation_id })).await?;
+    let response = next_event(&mut ws_stream, "error").await?;
+    assert_eq!(response["params"]["message"], "You are not authorized to access this conversation");
+
+    cleanup_test_users(&pool, &[client_id, provider_id, outsider_id]).await;
+    Ok(())
+}
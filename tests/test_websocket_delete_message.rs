@@ -0,0 +1,75 @@
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, cleanup_test_users, test_phone_number
+};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn send_event(ws_stream: &mut WsStream, user_id: Uuid, event: &str, params: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let message = json!({ "sender_id": user_id, "event": event, "params": params });
+    ws_stream.send(Message::Text(message.to_string())).await?;
+    Ok(())
+}
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_senders_delete_their_own_messages() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+    let (provider_token, _) = generate_test_token(provider_id, "provider")?;
+    let (mut provider_ws, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", provider_token)).await?;
+    let (client_token, _) = generate_test_token(client_id, "client")?;
+    let (mut client_ws, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", client_token)).await?;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    send_event(&mut client_ws, client_id, "message", json!({ "conversation_id": conversation_id, "content": "Wrong pet, sorry" })).await?;
+    let sent = next_event(&mut client_ws, "message_sent").await?;
+    let message_id = sent["params"]["id"].clone();
+
+    // The provider can't delete the client's message
+    send_event(&mut provider_ws, provider_id, "delete_message", json!({ "message_id": message_id })).await?;
+    let refused = next_event(&mut provider_ws, "error").await?;
+    assert_eq!(refused["params"]["message"], "Message not found or not yours to delete");
+
+    send_event(&mut client_ws, client_id, "delete_message", json!({ "message_id": message_id })).await?;
+    let deleted = next_event(&mut provider_ws, "message_deleted").await?;
+    assert_eq!(deleted["params"]["conversation_id"], json!(conversation_id));
+    assert_eq!(deleted["params"]["message_id"], message_id);
+
+    send_event(&mut provider_ws, provider_id, "conversation_history", json!({ "conversation_id": conversation_id })).await?;
+    let history = next_event(&mut provider_ws, "conversation_history_response").await?;
+    let message = &history["params"]["messages"][0];
+    assert_eq!(message["id"], message_id);
+    assert_eq!(message["deleted"], true);
+    assert_eq!(message["content"], "");
+
+    cleanup_test_users(&pool, &[client_id, provider_id]).await;
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use tokio::time::{sleep, timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use serde_json::json;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{generate_test_token, setup_test_db, insert_test_user, cleanup_test_users, test_phone_number};
+
+// Must match the server's WS_IDLE_TIMEOUT_SECS; run the server with a short
+// timeout (e.g. WS_IDLE_TIMEOUT_SECS=3) to keep this test fast.
+fn idle_timeout() -> Duration {
+    dotenv::dotenv().ok();
+    let secs = std::env::var("WS_IDLE_TIMEOUT_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(600);
+    Duration::from_secs(secs)
+}
+
+#[tokio::test]
+async fn test_idle_session_is_closed() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let user_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let (token, _) = generate_test_token(user_id, "client")?;
+    let idle_timeout = idle_timeout();
+
+    let (mut ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", token)).await?;
+
+    // App-level pings keep the session alive past the timeout
+    let ping = json!({ "sender_id": user_id, "event": "ping", "params": {} });
+    for _ in 0..3 {
+        sleep(idle_timeout / 2).await;
+        ws_stream.send(Message::Text(ping.to_string())).await?;
+    }
+
+    let mut got_pong = false;
+    while let Ok(Some(msg)) = timeout(Duration::from_secs(1), ws_stream.next()).await {
+        if let Message::Text(text) = msg? {
+            got_pong |= text.contains("\"pong\"");
+        }
+    }
+    assert!(got_pong, "Expected a pong reply to the keepalive ping");
+
+    // Once the client goes quiet the server closes the socket
+    let closed = timeout(idle_timeout + Duration::from_secs(15), async {
+        while let Some(msg) = ws_stream.next().await {
+            match msg {
+                Ok(Message::Close(frame)) => return frame.map(|f| f.reason.to_string()),
+                Ok(_) => continue,
+                Err(_) => return None,
+            }
+        }
+        None
+    })
+    .await?;
+    assert_eq!(closed.as_deref(), Some("idle timeout"));
+
+    cleanup_test_users(&pool, &[user_id]).await;
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, cleanup_test_users, test_phone_number
+};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn send_event(ws_stream: &mut WsStream, user_id: Uuid, event: &str, params: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let message = json!({ "sender_id": user_id, "event": event, "params": params });
+    ws_stream.send(Message::Text(message.to_string())).await?;
+    Ok(())
+}
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_conversations_with_details() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    sqlx::query!("UPDATE users SET first_name = 'Dana', last_name = 'Reyes' WHERE id = $1", provider_id)
+        .execute(&pool)
+        .await?;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+    for content in ["Hello", "Any update?"] {
+        sqlx::query!(
+            "INSERT INTO messages (conversation_id, sender_id, content, timestamp) VALUES ($1, $2, $3, CURRENT_TIMESTAMP)",
+            conversation_id,
+            provider_id,
+            content
+        )
+        .execute(&pool)
+        .await?;
+    }
+
+    let (token, _) = generate_test_token(client_id, "client")?;
+    let (mut ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", token)).await?;
+
+    // Old clients still get the bare list
+    send_event(&mut ws_stream, client_id, "conversations", json!({})).await?;
+    let response = next_event(&mut ws_stream, "conversations").await?;
+    assert!(response["params"].is_array());
+
+    send_event(&mut ws_stream, client_id, "conversations", json!({ "include_details": true })).await?;
+    let response = next_event(&mut ws_stream, "conversations").await?;
+    let conversation = response["params"]["conversations"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|c| c["id"] == conversation_id.to_string())
+        .unwrap()
+        .clone();
+    assert_eq!(conversation["unread_count"], 2);
+    let provider = &response["params"]["users"][provider_id.to_string()];
+    assert_eq!(provider["first_name"], "Dana");
+    assert_eq!(provider["last_name"], "Reyes");
+
+    cleanup_test_users(&pool, &[client_id, provider_id]).await;
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::client::IntoClientRequest, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet, insert_test_conversation,
+    cleanup_test_users, test_phone_number
+};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Connects offering `protocol`, returning the socket and the protocol the server picked.
+async fn connect(user_id: Uuid, scope: &str, protocol: Option<&str>) -> Result<(WsStream, Option<String>), Box<dyn std::error::Error>> {
+    let (token, _) = generate_test_token(user_id, scope)?;
+    let mut request = format!("ws://localhost:8080/ws/?token={}", token).into_client_request()?;
+    if let Some(protocol) = protocol {
+        request.headers_mut().insert("Sec-WebSocket-Protocol", protocol.parse()?);
+    }
+    let (ws_stream, response) = connect_async(request).await?;
+    let negotiated = response
+        .headers()
+        .get("Sec-WebSocket-Protocol")
+        .map(|value| value.to_str().unwrap().to_string());
+    Ok((ws_stream, negotiated))
+}
+
+async fn send_event(ws_stream: &mut WsStream, user_id: Uuid, event: &str, params: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let message = json!({ "sender_id": user_id, "event": event, "params": params });
+    ws_stream.send(Message::Text(message.to_string())).await?;
+    Ok(())
+}
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_each_protocol_gets_its_own_shape() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+    let (mut unversioned_ws, negotiated) = connect(provider_id, "provider", None).await?;
+    assert_eq!(negotiated, None);
+    let (mut v1_ws, negotiated) = connect(provider_id, "provider", Some("vt.v1")).await?;
+    assert_eq!(negotiated.as_deref(), Some("vt.v1"));
+    let (mut v2_ws, negotiated) = connect(client_id, "client", Some("vt.v1, vt.v2")).await?;
+    assert_eq!(negotiated.as_deref(), Some("vt.v2"));
+
+    // The same broadcast reaches each session in its version's shape
+    send_event(&mut v2_ws, client_id, "message", json!({
+        "conversation_id": conversation_id,
+        "content": "Is Millie due for her shots?"
+    })).await?;
+    let v2 = next_event(&mut v2_ws, "message_sent").await?;
+    let v1 = next_event(&mut v1_ws, "message_sent").await?;
+    let unversioned = next_event(&mut unversioned_ws, "message_sent").await?;
+    assert!(v2["seq"].as_u64().unwrap() > 0);
+    assert!(v1.get("seq").is_none());
+    assert_eq!(v1, unversioned);
+    assert_eq!(v1["params"], v2["params"]);
+
+    // Malformed requests are events with a code in v2, bare text in v1
+    send_event(&mut v2_ws, client_id, "bark", json!({})).await?;
+    let error = next_event(&mut v2_ws, "error").await?;
+    assert_eq!(error["params"]["code"], "unknown_event");
+    send_event(&mut v1_ws, provider_id, "bark", json!({})).await?;
+    loop {
+        let msg = timeout(Duration::from_secs(5), v1_ws.next()).await?.ok_or("WebSocket closed")??;
+        if msg == Message::Text("Unknown event type".to_string()) {
+            break;
+        }
+    }
+
+    cleanup_test_users(&pool, &[client_id, provider_id]).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unsupported_protocol_is_closed_with_a_code() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+
+    let (mut ws, _) = connect(client_id, "client", Some("vt.v9")).await?;
+    let msg = timeout(Duration::from_secs(5), ws.next()).await?.ok_or("WebSocket closed")??;
+    match msg {
+        Message::Close(Some(frame)) => assert_eq!(u16::from(frame.code), 4406),
+        other => panic!("expected a close frame, got {:?}", other),
+    }
+
+    cleanup_test_users(&pool, &[client_id]).await;
+    Ok(())
+}
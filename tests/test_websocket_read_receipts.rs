@@ -0,0 +1,71 @@
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, cleanup_test_users, test_phone_number
+};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn send_event(ws_stream: &mut WsStream, user_id: Uuid, event: &str, params: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let message = json!({ "sender_id": user_id, "event": event, "params": params });
+    ws_stream.send(Message::Text(message.to_string())).await?;
+    Ok(())
+}
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_senders_see_when_their_messages_are_read() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let outsider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+    let (provider_token, _) = generate_test_token(provider_id, "provider")?;
+    let (mut provider_ws, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", provider_token)).await?;
+    let (client_token, _) = generate_test_token(client_id, "client")?;
+    let (mut client_ws, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", client_token)).await?;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    send_event(&mut provider_ws, provider_id, "message", json!({ "conversation_id": conversation_id, "content": "Millie's results are in" })).await?;
+    let sent = next_event(&mut client_ws, "message_sent").await?;
+    let timestamp = sent["params"]["timestamp"].clone();
+
+    send_event(&mut client_ws, client_id, "read", json!({ "conversation_id": conversation_id, "up_to_timestamp": timestamp })).await?;
+    let read = next_event(&mut provider_ws, "messages_read").await?;
+    assert_eq!(read["params"]["conversation_id"], json!(conversation_id));
+    assert_eq!(read["params"]["user_id"], json!(client_id));
+    assert_eq!(read["params"]["up_to_timestamp"], timestamp);
+
+    let (outsider_token, _) = generate_test_token(outsider_id, "provider")?;
+    let (mut outsider_ws, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", outsider_token)).await?;
+    send_event(&mut outsider_ws, outsider_id, "read", json!({ "conversation_id": conversation_id, "up_to_timestamp": timestamp })).await?;
+    let refused = next_event(&mut outsider_ws, "error").await?;
+    assert_eq!(refused["params"]["message"], "You are not authorized to access this conversation");
+
+    cleanup_test_users(&pool, &[client_id, provider_id, outsider_id]).await;
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, cleanup_test_users, test_phone_number
+};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn connect(user_id: Uuid, scope: &str) -> Result<WsStream, Box<dyn std::error::Error>> {
+    let (token, _) = generate_test_token(user_id, scope)?;
+    let (ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", token)).await?;
+    Ok(ws_stream)
+}
+
+async fn send_event(ws_stream: &mut WsStream, user_id: Uuid, event: &str, params: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let message = json!({ "sender_id": user_id, "event": event, "params": params });
+    ws_stream.send(Message::Text(message.to_string())).await?;
+    Ok(())
+}
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_reconnect_replays_missed_events() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+    // The client sees one message, then drops its socket
+    let mut client_ws = connect(client_id, "client").await?;
+    send_event(&mut client_ws, client_id, "message", json!({
+        "conversation_id": conversation_id,
+        "content": "Before the drop"
+    })).await?;
+    let seen = next_event(&mut client_ws, "message_sent").await?;
+    let last_event_seq = seen["params"]["event_seq"].as_u64().expect("message_sent missing event_seq");
+    client_ws.close(None).await?;
+
+    // The provider keeps talking while the client is away
+    let mut provider_ws = connect(provider_id, "provider").await?;
+    for content in ["Missed one", "Missed two"] {
+        send_event(&mut provider_ws, provider_id, "message", json!({
+            "conversation_id": conversation_id,
+            "content": content
+        })).await?;
+        next_event(&mut provider_ws, "message_sent").await?;
+    }
+
+    // Reconnecting with the last seen seq replays both, in order
+    let mut client_ws = connect(client_id, "client").await?;
+    send_event(&mut client_ws, client_id, "subscribe_conversation", json!({
+        "conversation_id": conversation_id,
+        "last_event_seq": last_event_seq
+    })).await?;
+    let first = next_event(&mut client_ws, "message_sent").await?;
+    let second = next_event(&mut client_ws, "message_sent").await?;
+    assert_eq!(first["params"]["content"], "Missed one");
+    assert_eq!(second["params"]["content"], "Missed two");
+    assert!(first["params"]["event_seq"].as_u64() < second["params"]["event_seq"].as_u64());
+
+    // A seq the server can't account for (e.g. from before a restart) asks for a resync
+    send_event(&mut client_ws, client_id, "subscribe_conversation", json!({
+        "conversation_id": conversation_id,
+        "last_event_seq": 1
+    })).await?;
+    let resync = next_event(&mut client_ws, "resync_required").await?;
+    assert_eq!(resync["params"]["conversation_id"], conversation_id.to_string());
+
+    cleanup_test_users(&pool, &[client_id, provider_id]).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replay_requires_conversation_access() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let outsider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+    let mut client_ws = connect(client_id, "client").await?;
+    send_event(&mut client_ws, client_id, "message", json!({
+        "conversation_id": conversation_id,
+        "content": "Members only"
+    })).await?;
+    next_event(&mut client_ws, "message_sent").await?;
+
+    // Someone outside the conversation asks for everything since the start
+    let mut outsider_ws = connect(outsider_id, "provider").await?;
+    send_event(&mut outsider_ws, outsider_id, "subscribe_conversation", json!({
+        "conversation_id": conversation_id,
+        "last_event_seq": 0
+    })).await?;
+    let error = next_event(&mut outsider_ws, "error").await?;
+    assert_eq!(error["params"]["message"], "You are not authorized to access this conversation");
+    assert!(timeout(Duration::from_secs(1), next_event(&mut outsider_ws, "message_sent")).await.is_err());
+
+    cleanup_test_users(&pool, &[client_id, provider_id, outsider_id]).await;
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use tokio::net::TcpStream;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use futures::{StreamExt, SinkExt};
+
+mod testing_utils;
+use testing_utils::{
+    generate_test_token, setup_test_db, insert_test_user, insert_test_pet,
+    insert_test_conversation, cleanup_test_users, test_phone_number
+};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn send_event(ws_stream: &mut WsStream, user_id: Uuid, event: &str, params: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let message = json!({ "sender_id": user_id, "event": event, "params": params });
+    ws_stream.send(Message::Text(message.to_string())).await?;
+    Ok(())
+}
+
+/// Reads events until one named `event` arrives, returning it.
+async fn next_event(ws_stream: &mut WsStream, event: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws_stream.next())
+            .await?
+            .ok_or("WebSocket closed")??;
+        if let Message::Text(text) = msg {
+            if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                if value["event"] == event {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_get_subscriptions() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let first = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+    let second = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+    let (token, _) = generate_test_token(provider_id, "provider")?;
+    let (mut ws_stream, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", token)).await?;
+    for conversation_id in [first, second] {
+        send_event(&mut ws_stream, provider_id, "subscribe_conversation", json!({ "conversation_id": conversation_id })).await?;
+    }
+
+    send_event(&mut ws_stream, provider_id, "get_subscriptions", json!({})).await?;
+    let response = next_event(&mut ws_stream, "subscriptions").await?;
+    let subscribed = response["params"]["conversation_ids"].as_array().unwrap();
+    assert!(subscribed.contains(&json!(first)));
+    assert!(subscribed.contains(&json!(second)));
+
+    cleanup_test_users(&pool, &[client_id, provider_id]).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_resubscribe_picks_up_conversations_joined_since_connecting() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+
+    let (provider_token, _) = generate_test_token(provider_id, "provider")?;
+    let (mut provider_ws, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", provider_token)).await?;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    // A conversation the open socket never heard about
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+    send_event(&mut provider_ws, provider_id, "resubscribe", json!({})).await?;
+    let response = next_event(&mut provider_ws, "subscriptions").await?;
+    assert!(response["params"]["conversation_ids"].as_array().unwrap().contains(&json!(conversation_id)));
+
+    // The provider now receives the conversation's messages without reconnecting
+    let (client_token, _) = generate_test_token(client_id, "client")?;
+    let (mut client_ws, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", client_token)).await?;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    send_event(&mut client_ws, client_id, "message", json!({ "conversation_id": conversation_id, "content": "Hello" })).await?;
+    let delivered = next_event(&mut provider_ws, "message_sent").await?;
+    assert_eq!(delivered["params"]["conversation_id"], json!(conversation_id));
+
+    cleanup_test_users(&pool, &[client_id, provider_id]).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_subscribing_twice_announces_the_user_once() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = setup_test_db().await;
+    let client_id = insert_test_user(&pool, &test_phone_number(), "client").await;
+    let provider_id = insert_test_user(&pool, &test_phone_number(), "provider").await;
+    let pet_id = insert_test_pet(&pool, client_id).await;
+    let conversation_id = insert_test_conversation(&pool, client_id, pet_id, &[provider_id]).await;
+
+    let (provider_token, _) = generate_test_token(provider_id, "provider")?;
+    let (mut provider_ws, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", provider_token)).await?;
+    let (client_token, _) = generate_test_token(client_id, "client")?;
+    let (mut client_ws, _) = connect_async(format!("ws://localhost:8080/ws/?token={}", client_token)).await?;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    // Both subscribes are confirmed
+    for _ in 0..2 {
+        send_event(&mut client_ws, client_id, "subscribe_conversation", json!({ "conversation_id": conversation_id })).await?;
+        let confirmed = next_event(&mut client_ws, "subscribed").await?;
+        assert_eq!(confirmed["params"]["conversation_id"], json!(conversation_id));
+    }
+
+    // But the provider only hears about the client joining once
+    let mut joined = 0;
+    while let Ok(Some(msg)) = timeout(Duration::from_secs(1), provider_ws.next()).await {
+        if let Message::Text(text) = msg? {
+            let value: Value = serde_json::from_str(&text).unwrap_or_default();
+            if value["event"] == "user_joined" && value["params"]["user_id"] == json!(client_id) {
+                joined += 1;
+            }
+        }
+    }
+    assert_eq!(joined, 1);
+
+    cleanup_test_users(&pool, &[client_id, provider_id]).await;
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use reqwest::{Client, StatusCode};
+use serde_json::Value;
+use uuid::Uuid;
+
+mod testing_utils;
+use testing_utils::generate_test_token;
+
+const SERVER_URL: &str = "http://localhost:8080";
+
+#[tokio::test]
+async fn test_whoami_matches_token() -> Result<(), Box<dyn std::error::Error>> {
+    dotenv::dotenv().ok();
+    let http = Client::new();
+
+    // No user rows are needed; the answer comes from the token alone
+    for scope in ["client", "provider"] {
+        let user_id = Uuid::new_v4();
+        let (token, expiration) = generate_test_token(user_id, scope)?;
+
+        let response = http
+            .get(format!("{}/whoami", SERVER_URL))
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: Value = response.json().await?;
+        assert_eq!(body["user_id"], user_id.to_string());
+        assert_eq!(body["scope"], scope);
+        assert_eq!(body["expires_at"], expiration as i64 * 1000);
+    }
+
+    let response = http.get(format!("{}/whoami", SERVER_URL)).send().await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    Ok(())
+}
@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 use ed25519_dalek::{SigningKey, VerifyingKey};
 use once_cell::sync::Lazy;
 use std::collections::BTreeMap;
@@ -11,6 +13,10 @@ use jsonwebtoken::{encode, Header, Algorithm, EncodingKey};
 use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
 use aes_gcm::aead::{Aead};
 use serde::{Serialize, Deserialize};
+use sqlx::{PgPool, postgres::PgPoolOptions};
+use rand::Rng;
+use openssl::pkey::PKey;
+use sha2::{Digest, Sha256};
 
 pub static TEST_SIGNING_KEY: Lazy<SigningKey> = Lazy::new(|| {
     // This is a hard-coded private key for testing purposes only.
@@ -34,6 +40,7 @@ pub struct Claims {
     pub exp: usize,   // expiration time
     pub iat: usize,   // issued at
     pub scope: String, // user scope (client or provider)
+    pub jti: Option<Uuid>, // lets the token be revoked
 }
 
 impl Claims {
@@ -48,20 +55,17 @@ impl Claims {
 
 pub fn to_canonical_json(value: &Value) -> String {
     match value {
-        Value::String(s) => {
-            s.clone()
-        }
         Value::Object(map) => {
-            let mut btree_map = BTreeMap::new();
-            for (k, v) in map {
-                btree_map.insert(k, to_canonical_json(v));
-            }
-            let serialized = serde_json::to_string(&btree_map).unwrap();
-            serialized
+            let sorted: BTreeMap<&String, &Value> = map.iter().collect();
+            let fields: Vec<String> = sorted
+                .into_iter()
+                .map(|(k, v)| format!("{}:{}", serde_json::to_string(k).unwrap(), to_canonical_json(v)))
+                .collect();
+            format!("{{{}}}", fields.join(","))
         }
         Value::Array(arr) => {
-            let serialized_arr: Vec<String> = arr.iter().map(|v| to_canonical_json(v)).collect();
-            serde_json::to_string(&serialized_arr).unwrap()
+            let items: Vec<String> = arr.iter().map(to_canonical_json).collect();
+            format!("[{}]", items.join(","))
         }
         _ => serde_json::to_string(value).unwrap(),
     }
@@ -71,8 +75,13 @@ pub fn generate_test_token(user_id: Uuid, user_scope: &str) -> Result<(String, u
     // Load keys from environment variables
     let jwt_private_key_pem_base64 = env::var("JWT_PRIVATE_KEY")
         .map_err(|e| format!("Failed to get JWT_PRIVATE_KEY from env: {}", e))?;
-    let encryption_key_base64 = env::var("ENCRYPTION_KEY")
-        .map_err(|e| format!("Failed to get ENCRYPTION_KEY from env: {}", e))?;
+    // Like the server's key ring, encrypt with the first of ENCRYPTION_KEYS, falling back to ENCRYPTION_KEY
+    let encryption_keys = env::var("ENCRYPTION_KEYS").or_else(|_| env::var("ENCRYPTION_KEY"))
+        .map_err(|e| format!("Failed to get ENCRYPTION_KEYS or ENCRYPTION_KEY from env: {}", e))?;
+    let encryption_key_base64 = encryption_keys.split(',')
+        .map(str::trim)
+        .find(|key| !key.is_empty())
+        .ok_or("ENCRYPTION_KEYS is empty")?;
 
     // Base64 decode the PEM key
     let jwt_private_key_pem_bytes = general_purpose::STANDARD.decode(&jwt_private_key_pem_base64)
@@ -82,8 +91,8 @@ pub fn generate_test_token(user_id: Uuid, user_scope: &str) -> Result<(String, u
         .map_err(|e| format!("Failed to convert JWT_PRIVATE_KEY to string: {}", e))?;
 
     // Base64 decode the encryption key
-    let encryption_key_bytes = general_purpose::STANDARD.decode(&encryption_key_base64)
-        .map_err(|e| format!("Failed to base64 decode ENCRYPTION_KEY: {}", e))?;
+    let encryption_key_bytes = general_purpose::STANDARD.decode(encryption_key_base64)
+        .map_err(|e| format!("Failed to base64 decode ENCRYPTION_KEYS: {}", e))?;
 
     // Define expiration time
     let expiration = (Utc::now() + Duration::days(1)).timestamp() as usize;
@@ -96,21 +105,127 @@ pub fn generate_test_token(user_id: Uuid, user_scope: &str) -> Result<(String, u
         exp: expiration,
         iat: Utc::now().timestamp() as usize,
         scope: user_scope.to_string(),
+        jti: Some(Uuid::new_v4()),
     };
 
-    // Sign the JWT
-    let header = Header::new(Algorithm::ES256);
+    // Sign the JWT, naming the key the way the server does: the first 8 bytes of the public key's SHA-256, in hex
+    let public_key_der = PKey::private_key_from_pem(jwt_private_key_pem.as_bytes())
+        .and_then(|key| key.public_key_to_der())
+        .map_err(|e| format!("Failed to read JWT_PRIVATE_KEY: {}", e))?;
+    let mut header = Header::new(Algorithm::ES256);
+    header.kid = Some(hex::encode(&Sha256::digest(public_key_der)[..8]));
     let encoding_key = EncodingKey::from_ec_pem(jwt_private_key_pem.as_bytes())
         .map_err(|e| format!("Failed to create encoding key from JWT_PRIVATE_KEY: {}", e))?;
     let token = encode(&header, &claims, &encoding_key)
         .map_err(|e| format!("Failed to encode JWT: {}", e))?;
 
-    // Encrypt the signed token
+    // Encrypt the signed token under a random nonce, prepended as the server does
     let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&encryption_key_bytes));
-    let nonce = Nonce::from_slice(&[0u8; 12]); // For testing, fixed nonce is acceptable
-    let ciphertext = cipher.encrypt(nonce, token.as_bytes())
+    let mut nonce = [0u8; 12];
+    rand::thread_rng().fill(&mut nonce);
+    let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce), token.as_bytes())
         .map_err(|e| format!("Encryption error: {:?}", e))?;
+    let mut encrypted = nonce.to_vec();
+    encrypted.extend_from_slice(&ciphertext);
 
     // Base64 encode the encrypted token and return with expiration
-    Ok((general_purpose::URL_SAFE_NO_PAD.encode(ciphertext), expiration))
+    Ok((general_purpose::URL_SAFE_NO_PAD.encode(encrypted), expiration))
+}
+
+/// Helper function to initialize the test database connection.
+pub async fn setup_test_db() -> PgPool {
+    dotenv::dotenv().ok();
+
+    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    PgPoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url)
+        .await
+        .expect("Failed to create test database pool")
+}
+
+/// Inserts a verified test user into the database.
+/// Returns the user's UUID.
+pub async fn insert_test_user(pool: &PgPool, phone_number: &str, scope: &str) -> Uuid {
+    let user_id = Uuid::new_v4();
+
+    sqlx::query!(
+        "INSERT INTO users (id, phone_number, public_key, scope, verified) VALUES ($1, $2, $3, $4, true)",
+        user_id,
+        phone_number,
+        "TestPublicKeyBase64==",
+        scope
+    )
+    .execute(pool)
+    .await
+    .expect("Failed to insert test user");
+
+    user_id
+}
+
+/// Inserts a test pet for a user.
+/// Returns the pet's UUID.
+pub async fn insert_test_pet(pool: &PgPool, user_id: Uuid) -> Uuid {
+    let pet_id = Uuid::new_v4();
+
+    sqlx::query!(
+        "INSERT INTO pets (id, user_id, name, breed, sex, birthday, color, species, spayed_neutered, weight)
+         VALUES ($1, $2, 'Test Pet', 'Test Breed', 'M', $3, 'Brown', 'Dog', true, 25)",
+        pet_id,
+        user_id,
+        Utc::now()
+    )
+    .execute(pool)
+    .await
+    .expect("Failed to insert test pet");
+
+    pet_id
+}
+
+/// Inserts a conversation between a client and a set of providers about a pet.
+/// Returns the conversation's UUID.
+pub async fn insert_test_conversation(pool: &PgPool, client_id: Uuid, pet_id: Uuid, providers: &[Uuid]) -> Uuid {
+    sqlx::query!(
+        "INSERT INTO conversations (providers, client, pet, last_message) VALUES ($1, $2, $3, '') RETURNING id",
+        providers,
+        client_id,
+        pet_id
+    )
+    .fetch_one(pool)
+    .await
+    .expect("Failed to insert test conversation")
+    .id
+}
+
+/// Inserts a message the way the server stores one, bumping the conversation's
+/// `message_count`. Returns the message's UUID.
+pub async fn insert_test_message(pool: &PgPool, conversation_id: Uuid, sender_id: Uuid, content: &str) -> Uuid {
+    let message_id = sqlx::query!(
+        "INSERT INTO messages (conversation_id, sender_id, content, timestamp) VALUES ($1, $2, $3, CURRENT_TIMESTAMP) RETURNING id",
+        conversation_id,
+        sender_id,
+        content
+    )
+    .fetch_one(pool)
+    .await
+    .expect("Failed to insert test message")
+    .id;
+    sqlx::query!("UPDATE conversations SET message_count = message_count + 1 WHERE id = $1", conversation_id)
+        .execute(pool)
+        .await
+        .expect("Failed to update message count");
+    message_id
+}
+
+/// Deletes test users; pets, conversations and messages cascade with them.
+pub async fn cleanup_test_users(pool: &PgPool, user_ids: &[Uuid]) {
+    sqlx::query!("DELETE FROM users WHERE id = ANY($1)", user_ids)
+        .execute(pool)
+        .await
+        .expect("Failed to delete test users");
+}
+
+/// Random test phone number using the "000123" test prefix so no SMS is sent.
+pub fn test_phone_number() -> String {
+    format!("000123{:09}", rand::random::<u32>() % 1_000_000_000)
 }